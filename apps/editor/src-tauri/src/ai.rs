@@ -0,0 +1,160 @@
+//! Text completion through OpenAI-compatible chat APIs (OpenAI, OpenRouter,
+//! Ollama, LM Studio and so on), streamed back as it is generated. Going
+//! through the backend keeps the API keys out of the webview.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    error::BackendError,
+    flags::{self, Flag},
+    net::{self, reqwest},
+    secrets, send, BackendEvent, EventChannel,
+};
+
+/// Local models can take a while to produce the first token.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_ERROR_SIZE: u64 = 64 * 1024;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AiProvider {
+    /// E.g. `https://api.openai.com/v1` or `http://localhost:11434/v1`.
+    base_url: String,
+    model: String,
+    /// Keychain entry with the API key; local servers usually need none.
+    secret_name: Option<String>,
+}
+
+impl AiProvider {
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CompletionOptions {
+    system: Option<String>,
+    temperature: Option<f64>,
+    max_tokens: Option<u32>,
+}
+
+fn error_message(value: &Value) -> Option<&str> {
+    value["error"]["message"].as_str().or(value["error"].as_str())
+}
+
+/// Handles one line of the event stream; `Ok(true)` at the end.
+fn handle_line(line: &[u8], channel: &EventChannel) -> Result<bool, String> {
+    let line = String::from_utf8_lossy(line);
+    let Some(data) = line.trim_end_matches('\r').strip_prefix("data:") else {
+        // comments, event names and the blank lines between events
+        return Ok(false);
+    };
+    let data = data.trim();
+    if data == "[DONE]" {
+        return Ok(true);
+    }
+    let value: Value = serde_json::from_str(data).map_err(|e| format!("invalid event: {e}"))?;
+    if let Some(message) = error_message(&value) {
+        return Err(message.to_owned());
+    }
+    if let Some(text) = value["choices"][0]["delta"]["content"].as_str() {
+        if !text.is_empty() {
+            send(channel, BackendEvent::Chunk { text: text.to_owned() });
+        }
+    }
+    Ok(false)
+}
+
+/// Completes `prompt` with `provider`, sending the text in
+/// [`BackendEvent::Chunk`]s as it arrives.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn complete(
+    prompt: String, provider: AiProvider, options: CompletionOptions,
+    channel: EventChannel,
+) -> Result<(), BackendError> {
+    log::info!("complete start: {} with {}", provider.model, provider.base_url);
+    flags::require(Flag::Ai)?;
+    let mut messages = Vec::new();
+    if let Some(system) = &options.system {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    messages.push(json!({ "role": "user", "content": prompt }));
+    let mut body = json!({
+        "model": provider.model,
+        "messages": messages,
+        "stream": true,
+    });
+    if let Some(temperature) = options.temperature {
+        body["temperature"] = temperature.into();
+    }
+    if let Some(max_tokens) = options.max_tokens {
+        body["max_tokens"] = max_tokens.into();
+    }
+
+    let url = format!("{}/chat/completions", provider.base_url.trim_end_matches('/'));
+    let mut request = net::streaming_client(IDLE_TIMEOUT)?
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(reqwest::header::ACCEPT, "text/event-stream")
+        .body(body.to_string());
+    if let Some(name) = provider.secret_name {
+        let key = secrets::require_secret(name).await?;
+        request = request.bearer_auth(key);
+    }
+    let mut response = request.send().await.map_err(|e| format!("complete: request: {e}"))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = net::read_capped(response, MAX_ERROR_SIZE).await.unwrap_or_default();
+        let value: Value = serde_json::from_slice(&body).unwrap_or_default();
+        let message = error_message(&value)
+            .map_or_else(|| String::from_utf8_lossy(&body).into_owned(), str::to_owned);
+        return Err(BackendError::network("complete", format!("{url} returned {status}: {message}")));
+    }
+
+    let mut pending = Vec::new();
+    'stream: while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("complete: read: {e}"))?
+    {
+        pending.extend_from_slice(&chunk);
+        while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            if handle_line(&line[..end], &channel).map_err(|e| format!("complete: {e}"))? {
+                break 'stream;
+            }
+        }
+    }
+    send(&channel, BackendEvent::Done);
+    log::info!("complete done");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_lines_send_their_text() {
+        let (channel, events) = EventChannel::recording();
+        assert!(!handle_line(br#"data: {"choices":[{"delta":{"content":"Hel"}}]}"#, &channel).unwrap());
+        assert!(!handle_line(b"data:{\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\r", &channel).unwrap());
+        assert!(!handle_line(br#"data: {"choices":[{"delta":{"content":""}}]}"#, &channel).unwrap());
+        assert!(!handle_line(b": keep-alive", &channel).unwrap());
+        assert!(!handle_line(b"", &channel).unwrap());
+        assert!(handle_line(b"data: [DONE]", &channel).unwrap());
+        assert_eq!(*events.lock().unwrap(), [
+            r#"{"jobId":1,"event":"chunk","data":{"text":"Hel"}}"#,
+            r#"{"jobId":1,"event":"chunk","data":{"text":"lo"}}"#,
+        ]);
+
+        assert_eq!(handle_line(br#"data: {"error":{"message":"overloaded"}}"#, &channel).unwrap_err(), "overloaded");
+        assert_eq!(error_message(&json!({"error": "no model"})), Some("no model"));
+        assert!(handle_line(b"data: {", &channel).is_err());
+    }
+}
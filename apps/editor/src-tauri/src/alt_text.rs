@@ -0,0 +1,271 @@
+//! Alt text suggested for inserted images, from a small captioning model
+//! run locally with ONNX Runtime, for exports that read well to screen
+//! readers. The model is an optional download, set in the settings: a
+//! folder exported from a vision-encoder-decoder like
+//! `vit-gpt2-image-captioning`, with `encoder_model.onnx`,
+//! `decoder_model.onnx` and GPT-2's `vocab.json`. Suggestions are kept in
+//! the database's `assets` table by the image's hash, so an image, or a
+//! copy of it, is only captioned once by each model.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use image::imageops::FilterType;
+use ort::{session::Session, value::Tensor};
+use rusqlite::OptionalExtension;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tauri::State;
+
+use crate::{
+    db::Database,
+    error::BackendError,
+    metrics, onnx, settings,
+    workers::{self, Priority},
+};
+
+/// Of a caption, in tokens.
+const MAX_TOKENS: usize = 20;
+/// GPT-2's `<|endoftext|>`, which starts and ends a caption.
+const END_OF_TEXT: i64 = 50256;
+const DEFAULT_IMAGE_SIZE: u32 = 224;
+
+/// What the encoder expects, from `preprocessor_config.json`.
+#[derive(Deserialize)]
+#[serde(default)]
+struct Preprocessor {
+    size: ImageSize,
+    image_mean: [f32; 3],
+    image_std: [f32; 3],
+}
+
+impl Default for Preprocessor {
+    fn default() -> Preprocessor {
+        Preprocessor { size: ImageSize::Square(DEFAULT_IMAGE_SIZE), image_mean: [0.5; 3], image_std: [0.5; 3] }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ImageSize {
+    Square(u32),
+    Sides { height: u32, width: u32 },
+}
+
+struct Captioner {
+    dir: PathBuf,
+    encoder: Session,
+    decoder: Session,
+    preprocessor: Preprocessor,
+    /// GPT-2's byte-level tokens, by id.
+    tokens: HashMap<i64, String>,
+}
+
+/// The model last loaded, which is kept for the next image.
+static CAPTIONER: LazyLock<Mutex<Option<Captioner>>> = LazyLock::new(Mutex::default);
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    serde_json::from_str(&text).map_err(|e| format!("parse {}: {e}", path.display()))
+}
+
+impl Captioner {
+    fn load(dir: &Path) -> Result<Captioner, String> {
+        let preprocessor_path = dir.join("preprocessor_config.json");
+        let preprocessor =
+            if preprocessor_path.is_file() { read_json(&preprocessor_path)? } else { Preprocessor::default() };
+        let vocab: HashMap<String, i64> = read_json(&dir.join("vocab.json"))?;
+        Ok(Captioner {
+            dir: dir.to_owned(),
+            encoder: onnx::session(&dir.join("encoder_model.onnx"))?,
+            decoder: onnx::session(&dir.join("decoder_model.onnx"))?,
+            preprocessor,
+            tokens: vocab.into_iter().map(|(token, id)| (id, token)).collect(),
+        })
+    }
+
+    /// The image as the encoder takes it: resized, normalized, and in
+    /// planes of red, green and blue.
+    fn pixels(&self, path: &Path) -> Result<Tensor<f32>, String> {
+        let (width, height) = match self.preprocessor.size {
+            ImageSize::Square(side) => (side, side),
+            ImageSize::Sides { height, width } => (width, height),
+        };
+        let image = image::open(path)
+            .map_err(|e| format!("open {}: {e}", path.display()))?
+            .resize_exact(width, height, FilterType::Triangle)
+            .into_rgb8();
+        let plane = (width * height) as usize;
+        let mut values = vec![0.0; 3 * plane];
+        for (i, pixel) in image.pixels().enumerate() {
+            for channel in 0..3 {
+                let value = f32::from(pixel[channel]) / 255.0;
+                values[channel * plane + i] =
+                    (value - self.preprocessor.image_mean[channel]) / self.preprocessor.image_std[channel];
+            }
+        }
+        Tensor::from_array(([1, 3, height as usize, width as usize], values)).map_err(|e| format!("pixels: {e}"))
+    }
+
+    /// Greedily, the likeliest token after each.
+    fn caption(&mut self, path: &Path) -> Result<String, String> {
+        let pixels = self.pixels(path)?;
+        let outputs = self.encoder.run(ort::inputs!["pixel_values" => pixels]).map_err(|e| format!("encode: {e}"))?;
+        let (shape, hidden) = outputs[0].try_extract_tensor::<f32>().map_err(|e| format!("encode: {e}"))?;
+        let (shape, hidden) = (shape.to_vec(), hidden.to_vec());
+        drop(outputs);
+
+        let mut ids = vec![END_OF_TEXT];
+        while ids.len() <= MAX_TOKENS {
+            let input_ids = Tensor::from_array(([1, ids.len()], ids.clone())).map_err(|e| format!("decode: {e}"))?;
+            let states = Tensor::from_array((shape.clone(), hidden.clone())).map_err(|e| format!("decode: {e}"))?;
+            let outputs = self
+                .decoder
+                .run(ort::inputs!["input_ids" => input_ids, "encoder_hidden_states" => states])
+                .map_err(|e| format!("decode: {e}"))?;
+            let (logits_shape, logits) =
+                outputs["logits"].try_extract_tensor::<f32>().map_err(|e| format!("decode: {e}"))?;
+            let vocab = logits_shape.last().and_then(|&n| usize::try_from(n).ok()).ok_or("decode: bad logits")?;
+            let last = &logits[logits.len() - vocab..];
+            let next = last.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).map_or(END_OF_TEXT, |(id, _)| {
+                i64::try_from(id).unwrap_or(END_OF_TEXT)
+            });
+            if next == END_OF_TEXT {
+                break;
+            }
+            ids.push(next);
+        }
+        Ok(self.detokenize(&ids[1..]))
+    }
+
+    fn detokenize(&self, ids: &[i64]) -> String {
+        let bytes: Vec<u8> = ids
+            .iter()
+            .filter_map(|id| self.tokens.get(id))
+            .flat_map(|token| token.chars())
+            .filter_map(|c| BYTES.get(&c).copied())
+            .collect();
+        let text = String::from_utf8_lossy(&bytes);
+        let text = text.trim();
+        let mut chars = text.chars();
+        chars.next().map_or_else(String::new, |first| first.to_uppercase().chain(chars).collect())
+    }
+}
+
+/// GPT-2 spells bytes that aren't printable as characters from 256 on, in
+/// order, so every token is printable; this undoes it.
+static BYTES: LazyLock<HashMap<char, u8>> = LazyLock::new(|| {
+    let printable = |b: u8| matches!(b, b'!'..=b'~' | 0xa1..=0xac | 0xae..=0xff);
+    let mut next = 256;
+    (0..=u8::MAX)
+        .map(|b| {
+            if printable(b) {
+                (char::from(b), b)
+            } else {
+                next += 1;
+                (char::from_u32(next - 1).expect("a char"), b)
+            }
+        })
+        .collect()
+});
+
+/// What `model` suggested before for an image hashing to `sha256`.
+fn suggested(database: &Database, sha256: &str, model: &str) -> Result<Option<String>, String> {
+    database.with(|connection| {
+        connection
+            .query_row(
+                "SELECT alt_text FROM assets WHERE sha256 = ?1 AND alt_text_model = ?2 LIMIT 1",
+                [sha256, model],
+                |row| row.get(0),
+            )
+            .optional()
+    })
+}
+
+fn remember(database: &Database, path: &Path, sha256: &str, model: &str, text: &str) -> Result<(), String> {
+    database.with(|connection| {
+        connection
+            .execute(
+                "INSERT INTO assets (path, sha256, alt_text, alt_text_model) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (path) DO UPDATE
+                 SET sha256 = excluded.sha256, alt_text = excluded.alt_text, alt_text_model = excluded.alt_text_model",
+                [&path.to_string_lossy(), sha256, text, model],
+            )
+            .map(|_| ())
+    })
+}
+
+fn suggest(database: &Database, path: &Path, dir: &Path, runtime_path: Option<&str>) -> Result<String, String> {
+    let path = fs::canonicalize(path).map_err(|e| format!("open {}: {e}", path.display()))?;
+    let data = fs::read(&path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    let sha256 = hex::encode(Sha256::digest(&data));
+    let model = dir.to_string_lossy();
+    if let Some(text) = suggested(database, &sha256, &model)? {
+        return Ok(text);
+    }
+    onnx::init(runtime_path)?;
+    let mut captioner = CAPTIONER.lock().expect("captioner lock poisoned");
+    if captioner.as_ref().is_none_or(|c| c.dir != dir) {
+        *captioner = None;
+        *captioner = Some(Captioner::load(dir)?);
+    }
+    let timer = metrics::timer("suggest_alt_text");
+    let caption = captioner.as_mut().expect("a captioner").caption(&path);
+    timer.finish(caption.is_ok());
+    drop(captioner);
+    let caption = caption?;
+    remember(database, &path, &sha256, &model, &caption)?;
+    Ok(caption)
+}
+
+/// Suggests alt text for the image at `path`, like "A cat sitting on a
+/// laptop", with the captioning model in the settings. The first time,
+/// and when the model changes, it's loaded, which takes a few seconds.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn suggest_alt_text(path: String, database: State<'_, Arc<Database>>) -> Result<String, BackendError> {
+    log::info!("suggest_alt_text start: {path}");
+    let settings = settings::get();
+    let dir = PathBuf::from(&settings.caption_model_dir);
+    if settings.caption_model_dir.is_empty() || !dir.join("encoder_model.onnx").is_file() {
+        return Err(BackendError::not_found(format!(
+            "no captioning model in {:?}; set one in the settings",
+            settings.caption_model_dir
+        )));
+    }
+    let runtime_path = Some(settings.onnx_runtime_path).filter(|p| !p.is_empty());
+    let database = database.inner().clone();
+    let result = workers::run(Priority::Interactive, move || {
+        suggest(&database, Path::new(&path), &dir, runtime_path.as_deref())
+    })
+    .await;
+    match result {
+        Ok(Ok(text)) => {
+            log::info!("suggest_alt_text done: {text}");
+            Ok(text)
+        }
+        Ok(Err(e)) => Err(format!("suggest_alt_text task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggestions_are_kept_by_hash_and_model() {
+        let dir = std::env::temp_dir().join(format!("emmm-alt-text-test-{}", std::process::id()));
+        let database = Database::new(dir.join("test.sqlite"));
+        remember(&database, &dir.join("a.png"), "abc", "/models/one", "A cat").unwrap();
+        remember(&database, &dir.join("a.png"), "abc", "/models/one", "A cat on a laptop").unwrap();
+        assert_eq!(suggested(&database, "abc", "/models/one").unwrap().as_deref(), Some("A cat on a laptop"));
+        assert_eq!(suggested(&database, "abc", "/models/two").unwrap(), None);
+        assert_eq!(suggested(&database, "def", "/models/one").unwrap(), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,168 @@
+//! Removes the background of images, like product shots and portraits,
+//! before they're embedded: a U²-Net-style model, run locally with ONNX
+//! Runtime, tells the subject apart, and what isn't it is made
+//! transparent. The model is an optional download, set in the settings.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+
+use image::{imageops::FilterType, DynamicImage, GrayImage, ImageFormat, Luma};
+use ort::{session::Session, value::Tensor};
+use serde::Serialize;
+
+use crate::{
+    error::BackendError,
+    metrics, onnx, settings,
+    workers::{self, Priority},
+};
+
+/// What U²-Net takes, in pixels a side.
+const MODEL_SIZE: u32 = 320;
+/// Of ImageNet, which U²-Net was trained with.
+const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemovedBackground {
+    path: String,
+    width: u32,
+    height: u32,
+}
+
+/// The model last loaded, by path, which is kept for the next image.
+static MODEL: LazyLock<Mutex<Option<(PathBuf, Session)>>> = LazyLock::new(Mutex::default);
+
+/// `image` as the model takes it: resized, scaled to its brightest,
+/// normalized, and in planes of red, green and blue.
+fn planes(image: &DynamicImage) -> Vec<f32> {
+    let rgb = image.resize_exact(MODEL_SIZE, MODEL_SIZE, FilterType::Triangle).into_rgb8();
+    let brightest = f32::from(rgb.pixels().flat_map(|p| p.0).max().unwrap_or(0).max(1));
+    let plane = (MODEL_SIZE * MODEL_SIZE) as usize;
+    let mut values = vec![0.0; 3 * plane];
+    for (i, pixel) in rgb.pixels().enumerate() {
+        for channel in 0..3 {
+            values[channel * plane + i] = (f32::from(pixel[channel]) / brightest - MEAN[channel]) / STD[channel];
+        }
+    }
+    values
+}
+
+fn input(image: &DynamicImage) -> Result<Tensor<f32>, String> {
+    let side = MODEL_SIZE as usize;
+    Tensor::from_array(([1, 3, side, side], planes(image))).map_err(|e| format!("input: {e}"))
+}
+
+/// How much each pixel is the subject, from the model's first output
+/// stretched to 0 to 255, at the size of `image`.
+fn mask(session: &mut Session, image: &DynamicImage) -> Result<GrayImage, String> {
+    let input = input(image)?;
+    let name = session.inputs.first().map(|input| input.name.clone()).ok_or("the model takes no input")?;
+    let outputs = session.run(ort::inputs![name => input]).map_err(|e| format!("run: {e}"))?;
+    let (_, values) = outputs[0].try_extract_tensor::<f32>().map_err(|e| format!("output: {e}"))?;
+    let plane = (MODEL_SIZE * MODEL_SIZE) as usize;
+    let values = values.get(..plane).ok_or("the model's output is too small")?;
+    let (min, max) = values.iter().fold((f32::MAX, f32::MIN), |(min, max), &v| (min.min(v), max.max(v)));
+    let range = (max - min).max(f32::EPSILON);
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let pixels = values.iter().map(|&v| ((v - min) / range * 255.0).round() as u8).collect();
+    let mask = GrayImage::from_raw(MODEL_SIZE, MODEL_SIZE, pixels).ok_or("mask of the wrong size")?;
+    Ok(image::imageops::resize(&mask, image.width(), image.height(), FilterType::Triangle))
+}
+
+/// With the model at `model_path`, like `u2net.onnx` or `u2netp.onnx`, and
+/// ONNX Runtime's library at `runtime_path`; see [`onnx::init`].
+fn remove(
+    path: &Path, out: &Path, model_path: PathBuf, runtime_path: Option<&str>,
+) -> Result<RemovedBackground, String> {
+    let format = match ImageFormat::from_path(out) {
+        Ok(format @ (ImageFormat::Png | ImageFormat::WebP)) => format,
+        _ => return Err(format!("{} is neither a PNG nor a WebP", out.display())),
+    };
+    let image = image::open(path).map_err(|e| format!("open {}: {e}", path.display()))?;
+    onnx::init(runtime_path)?;
+    let mut model = MODEL.lock().expect("background model lock poisoned");
+    if model.as_ref().is_none_or(|(loaded, _)| *loaded != model_path) {
+        *model = None;
+        let session = onnx::session(&model_path)?;
+        *model = Some((model_path, session));
+    }
+    let (_, session) = model.as_mut().expect("a model");
+    let mask = mask(session, &image)?;
+    drop(model);
+
+    let mut rgba = image.into_rgba8();
+    for (pixel, &Luma([alpha])) in rgba.pixels_mut().zip(mask.pixels()) {
+        // keeps what was transparent already
+        pixel[3] = u8::try_from(u16::from(pixel[3]) * u16::from(alpha) / 255).unwrap_or(u8::MAX);
+    }
+    let (width, height) = rgba.dimensions();
+    rgba.save_with_format(out, format).map_err(|e| format!("save {}: {e}", out.display()))?;
+    Ok(RemovedBackground { path: out.to_string_lossy().into_owned(), width, height })
+}
+
+/// Saves the image at `path` to `out`, a `.png` or a lossless `.webp`,
+/// with its background made transparent, by the model in the settings.
+/// It's loaded the first time, and when it changes.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn remove_background(path: String, out: String) -> Result<RemovedBackground, BackendError> {
+    log::info!("remove_background start: {path}");
+    let settings = settings::get();
+    let model_path = PathBuf::from(&settings.background_model_path);
+    if settings.background_model_path.is_empty() || !model_path.is_file() {
+        return Err(BackendError::not_found(format!(
+            "no background removal model at {:?}; set one in the settings",
+            settings.background_model_path
+        )));
+    }
+    let runtime_path = Some(settings.onnx_runtime_path).filter(|p| !p.is_empty());
+    let timer = metrics::timer("remove_background");
+    let result = workers::run(Priority::Interactive, move || {
+        remove(Path::new(&path), Path::new(&out), model_path, runtime_path.as_deref())
+    })
+    .await;
+    timer.finish(matches!(result, Ok(Ok(_))));
+    match result {
+        Ok(Ok(removed)) => {
+            log::info!("remove_background done: {}", removed.path);
+            Ok(removed)
+        }
+        Ok(Err(e)) => Err(format!("remove_background task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn images_are_normalized_in_planes() {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([100, 50, 25])));
+        let values = planes(&image);
+        let plane = (MODEL_SIZE * MODEL_SIZE) as usize;
+        assert_eq!(values.len(), 3 * plane);
+        // scaled to the brightest, which is the red
+        for (channel, value) in [1.0, 0.5, 0.25].into_iter().enumerate() {
+            let expected = (value - MEAN[channel]) / STD[channel];
+            let (first, last) = (values[channel * plane], values[(channel + 1) * plane - 1]);
+            assert!((first - expected).abs() < 1e-5 && (last - expected).abs() < 1e-5, "{channel}: {first}");
+        }
+        // black doesn't divide by zero
+        let black = planes(&DynamicImage::ImageRgb8(RgbImage::new(2, 2)));
+        assert!((black[0] + MEAN[0] / STD[0]).abs() < 1e-5);
+    }
+
+    #[test]
+    fn only_png_and_webp_are_written() {
+        let error = remove(Path::new("photo.jpg"), Path::new("cutout.jpg"), PathBuf::from("u2net.onnx"), None)
+            .err()
+            .expect("an error for a JPEG");
+        assert!(error.contains("neither a PNG nor a WebP"), "{error}");
+    }
+}
@@ -0,0 +1,235 @@
+//! Compresses sample images like pasted images are, with several size
+//! limits, and measures what comes out, so users can pick the limit that
+//! suits what they put in their documents: photos, screenshots or scans.
+
+use std::{fs, io::Cursor, time::Instant};
+
+use fast_image_resize::Resizer;
+use image::{DynamicImage, ImageReader};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compress_data,
+    error::BackendError,
+    jobs::{self, JobKind},
+    workers::{self, Priority},
+};
+
+/// What `compress_image` can be asked for.
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CompressionProfile {
+    name: String,
+    /// In bytes.
+    max_size: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileResult {
+    profile: String,
+    /// In bytes.
+    size: u64,
+    /// Peak signal-to-noise ratio against the sample, scaled down like the
+    /// result was, in dB; `None` if nothing was lost.
+    psnr: Option<f64>,
+    millis: u64,
+    width: u32,
+    height: u32,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleResult {
+    path: String,
+    /// Of the file, in bytes.
+    size: u64,
+    width: u32,
+    height: u32,
+    /// Why the sample couldn't be read; the profiles aren't run then.
+    error: Option<String>,
+    results: Vec<ProfileResult>,
+}
+
+/// A profile's results over all samples it could encode.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileSummary {
+    profile: String,
+    /// Of the samples, in bytes.
+    original_size: u64,
+    /// Of the results, in bytes.
+    size: u64,
+    /// Over the lossy results; `None` if there were none.
+    mean_psnr: Option<f64>,
+    millis: u64,
+    failures: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchmarkReport {
+    samples: Vec<SampleResult>,
+    profiles: Vec<ProfileSummary>,
+}
+
+/// `img` at the size of `other`.
+fn resized(img: &DynamicImage, other: &DynamicImage) -> Result<DynamicImage, String> {
+    if img.width() == other.width() && img.height() == other.height() {
+        return Ok(img.clone());
+    }
+    let mut scaled = DynamicImage::new(other.width(), other.height(), img.color());
+    Resizer::new().resize(img, &mut scaled, None).map_err(|e| format!("resize: {e}"))?;
+    Ok(scaled)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn psnr(reference: &DynamicImage, decoded: &DynamicImage) -> Result<Option<f64>, String> {
+    let (a, b) = (reference.to_rgb8(), decoded.to_rgb8());
+    if a.dimensions() != b.dimensions() {
+        return Err("result has other dimensions".to_owned());
+    }
+    let squared: u64 = a.iter().zip(b.iter()).map(|(&x, &y)| u64::from(x.abs_diff(y)).pow(2)).sum();
+    if squared == 0 {
+        return Ok(None);
+    }
+    let mse = squared as f64 / a.len() as f64;
+    Ok(Some(10.0 * (255.0 * 255.0 / mse).log10()))
+}
+
+fn run_profile(data: &[u8], img: &DynamicImage, profile: &CompressionProfile) -> ProfileResult {
+    let started = Instant::now();
+    let result = compress_data(data.to_vec(), profile.max_size).and_then(|compressed| {
+        let millis = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+        let size = compressed.len() as u64;
+        // small enough images are kept as they are
+        if compressed == data {
+            return Ok((None, size, millis, img.width(), img.height()));
+        }
+        let decoded = image::load_from_memory(&compressed).map_err(|e| format!("decode result: {e}"))?;
+        Ok((psnr(&resized(img, &decoded)?, &decoded)?, size, millis, decoded.width(), decoded.height()))
+    });
+    let (psnr, size, millis, width, height, error) = match result {
+        Ok((psnr, size, millis, width, height)) => (psnr, size, millis, width, height, None),
+        Err(e) => (None, 0, 0, 0, 0, Some(e)),
+    };
+    ProfileResult { profile: profile.name.clone(), size, psnr, millis, width, height, error }
+}
+
+fn run_sample(path: &str, profiles: &[CompressionProfile]) -> Result<SampleResult, String> {
+    let mut sample =
+        SampleResult { path: path.to_owned(), size: 0, width: 0, height: 0, error: None, results: Vec::new() };
+    let decoded = fs::read(path).map_err(|e| format!("read: {e}")).and_then(|data| {
+        sample.size = data.len() as u64;
+        let reader = ImageReader::new(Cursor::new(&data))
+            .with_guessed_format()
+            .map_err(|e| format!("with_guessed_format: {e}"))?;
+        let img = reader.decode().map_err(|e| format!("decode: {e}"))?;
+        Ok((data, img))
+    });
+    let (data, img) = match decoded {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            log::warn!("benchmark_compression: {path}: {e}");
+            sample.error = Some(e);
+            return Ok(sample);
+        }
+    };
+    (sample.width, sample.height) = (img.width(), img.height());
+    for profile in profiles {
+        jobs::checkpoint()?;
+        sample.results.push(run_profile(&data, &img, profile));
+    }
+    Ok(sample)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn summarize(samples: &[SampleResult], profiles: &[CompressionProfile]) -> Vec<ProfileSummary> {
+    profiles
+        .iter()
+        .enumerate()
+        .map(|(i, profile)| {
+            let mut summary = ProfileSummary {
+                profile: profile.name.clone(),
+                original_size: 0,
+                size: 0,
+                mean_psnr: None,
+                millis: 0,
+                failures: 0,
+            };
+            let mut psnrs = Vec::new();
+            for (sample, result) in samples.iter().filter_map(|s| Some((s, s.results.get(i)?))) {
+                if result.error.is_some() {
+                    summary.failures += 1;
+                    continue;
+                }
+                summary.original_size += sample.size;
+                summary.size += result.size;
+                summary.millis += result.millis;
+                psnrs.extend(result.psnr);
+            }
+            summary.mean_psnr = (!psnrs.is_empty()).then(|| psnrs.iter().sum::<f64>() / psnrs.len() as f64);
+            summary
+        })
+        .collect()
+}
+
+fn benchmark(sample_paths: &[String], profiles: &[CompressionProfile]) -> Result<BenchmarkReport, String> {
+    let mut samples = Vec::new();
+    for (i, path) in sample_paths.iter().enumerate() {
+        jobs::progress(i, sample_paths.len(), path);
+        samples.push(run_sample(path, profiles)?);
+    }
+    let profiles = summarize(&samples, profiles);
+    Ok(BenchmarkReport { samples, profiles })
+}
+
+/// Compresses every sample with each profile and reports the size, quality
+/// and time of each result, and of each profile overall. Runs as a job that
+/// can be cancelled.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn benchmark_compression(
+    sample_paths: Vec<String>, profiles: Vec<CompressionProfile>,
+) -> Result<BenchmarkReport, BackendError> {
+    log::info!("benchmark_compression start: {} samples, {} profiles", sample_paths.len(), profiles.len());
+    if profiles.is_empty() {
+        return Err(BackendError::invalid("benchmark_compression: no profiles"));
+    }
+    let job = jobs::start(JobKind::Compression, Priority::Interactive, "Compression benchmark");
+    let result = workers::run(Priority::Interactive, move || job.run(|| benchmark(&sample_paths, &profiles))).await;
+    match result {
+        Ok(Ok(report)) => {
+            log::info!("benchmark_compression done");
+            Ok(report)
+        }
+        Ok(Err(e)) => Err(format!("benchmark_compression task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_compress_like_pasted_images() {
+        // noise, which PNG can't make much smaller
+        let mut seed = 1_u32;
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |_, _| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let [r, g, b, _] = seed.to_le_bytes();
+            image::Rgb([r, g, b])
+        }));
+        let mut data = Vec::new();
+        img.write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png).unwrap();
+        let profile = |max_size| CompressionProfile { name: String::new(), max_size };
+
+        let kept = run_profile(&data, &img, &profile(data.len() + 1));
+        assert_eq!((kept.size, kept.psnr, kept.error), (data.len() as u64, None, None));
+        let compressed = run_profile(&data, &img, &profile(data.len() / 2));
+        assert!(compressed.size < data.len() as u64 / 2, "{}", compressed.size);
+        assert!(compressed.psnr.is_some_and(|psnr| psnr > 20.0), "{:?} {:?}", compressed.psnr, compressed.error);
+    }
+}
@@ -0,0 +1,216 @@
+//! Quick capture: a global shortcut pops up a small window, whatever app is
+//! in front, and what's typed or pasted into it goes to the end of an inbox
+//! note, under the time it was captured. Images are compressed and saved
+//! in an `assets` folder next to the inbox.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager, WebviewUrl, WebviewWindowBuilder};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::{
+    compress_data,
+    error::BackendError,
+    workers::{self, Priority},
+    workspace::write_atomic,
+};
+
+/// The label of the capture window.
+pub const WINDOW: &str = "capture";
+const ASSETS_DIR: &str = "assets";
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CaptureOptions {
+    /// Like `CmdOrCtrl+Shift+Space`; none if empty.
+    shortcut: String,
+    /// The note captures are appended to.
+    inbox: String,
+    /// Images are compressed below this many bytes.
+    image_max_size: usize,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> CaptureOptions {
+        CaptureOptions {
+            shortcut: "CmdOrCtrl+Shift+Space".to_owned(),
+            inbox: String::new(),
+            image_max_size: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Captured {
+    /// The inbox note.
+    path: String,
+    /// Whether capturing created it.
+    created: bool,
+    /// The saved image, if there was one.
+    image: Option<String>,
+}
+
+static OPTIONS: Mutex<Option<CaptureOptions>> = Mutex::new(None);
+
+/// Shows the capture window, making it the first time.
+pub fn show(app: &AppHandle) -> Result<(), String> {
+    let window = match app.get_webview_window(WINDOW) {
+        Some(window) => window,
+        None => WebviewWindowBuilder::new(app, WINDOW, WebviewUrl::App("index.html?capture".into()))
+            .title("Quick capture")
+            .inner_size(520.0, 220.0)
+            .resizable(false)
+            .always_on_top(true)
+            .skip_taskbar(true)
+            .center()
+            .build()
+            .map_err(|e| format!("create capture window: {e}"))?,
+    };
+    window.show().and_then(|()| window.set_focus()).map_err(|e| format!("show capture window: {e}"))
+}
+
+/// Saves the base64 `image`, compressed, in the assets folder next to
+/// `inbox`; returns its path relative to the inbox's folder.
+fn save_image(inbox: &Path, image: &str, max_size: usize) -> Result<String, String> {
+    // data URLs too
+    let image = image.rsplit_once("base64,").map_or(image, |(_, data)| data);
+    let data = STANDARD.decode(image.trim()).map_err(|e| format!("invalid image: {e}"))?;
+    let data = compress_data(data, max_size)?;
+    let format = image::guess_format(&data).map_err(|e| format!("guess_format: {e}"))?;
+    let extension = format.extensions_str().first().copied().unwrap_or("bin");
+    let name = format!("{}.{extension}", &hex::encode(Sha256::digest(&data))[..16]);
+    let dir = inbox.parent().unwrap_or(Path::new(".")).join(ASSETS_DIR);
+    fs::create_dir_all(&dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    write_atomic(&dir.join(&name), &data)?;
+    Ok(format!("{ASSETS_DIR}/{name}"))
+}
+
+fn append(options: &CaptureOptions, text: &str, image: Option<&str>) -> Result<Captured, String> {
+    if options.inbox.is_empty() {
+        return Err("no inbox note is set up for captures".to_owned());
+    }
+    let inbox = PathBuf::from(&options.inbox);
+    let image = image.map(|image| save_image(&inbox, image, options.image_max_size)).transpose()?;
+    let created = !inbox.exists();
+    let mut note = if created {
+        if let Some(parent) = inbox.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("create {}: {e}", parent.display()))?;
+        }
+        "# Inbox\n".to_owned()
+    } else {
+        fs::read_to_string(&inbox).map_err(|e| format!("read {}: {e}", inbox.display()))?
+    };
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let heading = now.format(format_description!("[year]-[month]-[day] [hour]:[minute]")).unwrap_or_default();
+    if !note.ends_with('\n') {
+        note.push('\n');
+    }
+    note.push_str(&format!("\n## {heading}\n\n"));
+    if !text.trim().is_empty() {
+        note.push_str(text.trim_end());
+        note.push_str("\n\n");
+    }
+    if let Some(image) = &image {
+        note.push_str(&format!("![]({image})\n"));
+    }
+    write_atomic(&inbox, note.as_bytes())?;
+    Ok(Captured { path: options.inbox.clone(), created, image })
+}
+
+/// Registers the shortcut of `options` in place of the previous one and
+/// uses its inbox from now on.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn configure_capture(options: CaptureOptions, app: AppHandle) -> Result<(), BackendError> {
+    log::info!("configure_capture: {:?}", options.shortcut);
+    let shortcuts = app.global_shortcut();
+    let mut current = OPTIONS.lock().expect("capture lock poisoned");
+    if let Some(previous) = current.as_ref().filter(|previous| !previous.shortcut.is_empty()) {
+        if let Err(e) = shortcuts.unregister(previous.shortcut.as_str()) {
+            log::warn!("configure_capture: unregister {}: {e}", previous.shortcut);
+        }
+    }
+    let shortcut = options.shortcut.clone();
+    *current = Some(options);
+    if !shortcut.is_empty() {
+        shortcuts
+            .on_shortcut(shortcut.as_str(), |app, _, event| {
+                if event.state == ShortcutState::Pressed {
+                    if let Err(e) = show(app) {
+                        log::warn!("capture: {e}");
+                    }
+                }
+            })
+            .map_err(|e| format!("invalid shortcut {shortcut}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Appends `text` and the base64 `image`, if any, to the inbox note,
+/// creating it if needed, and hides the capture window.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn capture(text: String, image: Option<String>, app: AppHandle) -> Result<Captured, BackendError> {
+    log::info!("capture start");
+    let options = OPTIONS.lock().expect("capture lock poisoned").clone().unwrap_or_default();
+    let result = workers::run(Priority::Interactive, move || append(&options, &text, image.as_deref())).await;
+    match result {
+        Ok(Ok(captured)) => {
+            if let Some(window) = app.get_webview_window(WINDOW) {
+                if let Err(e) = window.hide() {
+                    log::warn!("capture: hide window: {e}");
+                }
+            }
+            log::info!("capture done: {}", captured.path);
+            Ok(captured)
+        }
+        Ok(Err(e)) => Err(format!("capture task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn captures_go_to_the_end_of_the_inbox() {
+        let dir = std::env::temp_dir().join(format!("emmm-capture-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        let inbox = dir.join("notes/inbox.md");
+        let options = CaptureOptions { inbox: inbox.to_string_lossy().into_owned(), ..CaptureOptions::default() };
+        assert!(append(&CaptureOptions::default(), "lost", None).is_err());
+
+        let captured = append(&options, "first thought  \n", None).expect("capture text");
+        assert!(captured.created && captured.image.is_none());
+        let mut png = Vec::new();
+        image::RgbImage::new(2, 2)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .expect("encode an image");
+        let data_url = format!("data:image/png;base64,{}", STANDARD.encode(&png));
+        let captured = append(&options, " ", Some(&data_url)).expect("capture an image");
+        assert!(!captured.created);
+        let image = captured.image.expect("the saved image");
+        assert!(image.starts_with("assets/") && image.ends_with(".png"), "{image}");
+        assert_eq!(fs::read(dir.join("notes").join(&image)).expect("read the image"), png);
+
+        let note = fs::read_to_string(&inbox).expect("read the inbox");
+        let sections: Vec<_> = note.split("\n## ").collect();
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[0], "# Inbox\n");
+        assert!(sections[1].ends_with("\n\nfirst thought\n\n"), "{note}");
+        assert!(sections[2].ends_with(&format!("\n\n![]({image})\n")), "{note}");
+        fs::remove_dir_all(&dir).ok();
+    }
+}
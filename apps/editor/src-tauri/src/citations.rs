@@ -0,0 +1,484 @@
+//! Citations from a BibTeX or CSL-JSON library. Keys are completed while
+//! writing, and exports render citations and the bibliography with a CSL
+//! style. Citations are written like in pandoc: `[@doe]`, or
+//! `[@doe, p. 33; @smith]` with a locator.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, RwLock},
+    time::SystemTime,
+};
+
+use hayagriva::{
+    archive::{self, ArchivedStyle},
+    citationberg::{
+        json::{self, DateValue, NameValue},
+        taxonomy::Locator,
+        IndependentStyle, Locale, Style,
+    },
+    BibliographyDriver, BibliographyRequest, BufWriteFormat, CitationItem, CitationRequest, ElemChildren,
+    LocatorPayload, Rendered, SpecificLocator,
+};
+use regex::Regex;
+use serde::Serialize;
+use tauri::State;
+
+use crate::{
+    completion::{fuzzy_score, Completion},
+    error::BackendError,
+    frontmatter::Frontmatter,
+    markdown, typography,
+};
+
+/// Like pandoc's.
+const DEFAULT_STYLE: &str = "chicago-author-date";
+const DEFAULT_LIMIT: usize = 20;
+
+/// Longer labels first, since they're matched as prefixes.
+const LOCATOR_LABELS: &[(&str, Locator)] = &[
+    ("pages", Locator::Page),
+    ("page", Locator::Page),
+    ("pp.", Locator::Page),
+    ("p.", Locator::Page),
+    ("chapter", Locator::Chapter),
+    ("chap.", Locator::Chapter),
+    ("ch.", Locator::Chapter),
+    ("section", Locator::Section),
+    ("sec.", Locator::Section),
+    ("§", Locator::Section),
+    ("figure", Locator::Figure),
+    ("fig.", Locator::Figure),
+    ("volume", Locator::Volume),
+    ("vol.", Locator::Volume),
+    ("paragraph", Locator::Paragraph),
+    ("para.", Locator::Paragraph),
+    ("line", Locator::Line),
+    ("l.", Locator::Line),
+];
+
+static CITATION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[([^\[\]]*@[^\[\]]*)\]").expect("citation regex"));
+
+/// The CSL locales hayagriva comes with; decoding them takes a while.
+static LOCALES: LazyLock<Vec<Locale>> = LazyLock::new(archive::locales);
+
+enum Entries {
+    BibTeX(hayagriva::Library),
+    /// With the index of each key.
+    CslJson(Vec<json::Item>, HashMap<String, usize>),
+}
+
+/// What completion shows of an entry.
+struct Summary {
+    key: String,
+    title: Option<String>,
+    /// Family names.
+    authors: Vec<String>,
+    year: Option<i32>,
+}
+
+pub struct Library {
+    entries: Entries,
+    summaries: Vec<Summary>,
+}
+
+struct Loaded {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    library: Arc<Library>,
+}
+
+/// The library completion and the bibliography command use.
+#[derive(Default)]
+pub struct Citations {
+    loaded: RwLock<Option<Loaded>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CitationStyle {
+    /// What to give as `style`, or as `csl` in the frontmatter.
+    name: String,
+    title: String,
+}
+
+/// One cited entry of a `[...]` citation.
+struct Cited<'a> {
+    key: &'a str,
+    locator: Option<(Locator, &'a str)>,
+}
+
+fn csl_json_summary(item: &json::Item) -> Option<Summary> {
+    let text = |field: &str| item.0.get(field).and_then(json::Value::to_str).map(|s| s.into_owned());
+    let authors = match item.0.get("author") {
+        Some(json::Value::Names(names)) => names
+            .iter()
+            .map(|name| match name {
+                NameValue::Item(name) => name.family.clone(),
+                NameValue::Literal(name) => name.literal.clone(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let year = match item.0.get("issued") {
+        Some(json::Value::Date(DateValue::Raw { raw, .. })) => Some(i32::from(raw.start.year)),
+        Some(json::Value::Date(DateValue::DateParts { date_parts, .. })) => {
+            date_parts.0.first().and_then(|date| date.0.first()).copied().map(i32::from)
+        }
+        _ => None,
+    };
+    Some(Summary { key: item.id()?.into_owned(), title: text("title"), authors, year })
+}
+
+impl Library {
+    fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+        let (entries, summaries) = if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+            let items: Vec<json::Item> =
+                serde_json::from_str(&source).map_err(|e| format!("parse {}: {e}", path.display()))?;
+            let summaries: Vec<Summary> = items.iter().filter_map(csl_json_summary).collect();
+            let keys = items
+                .iter()
+                .enumerate()
+                .filter_map(|(i, item)| Some((item.id()?.into_owned(), i)))
+                .collect();
+            (Entries::CslJson(items, keys), summaries)
+        } else {
+            let library = hayagriva::io::from_biblatex_str(&source).map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                format!("parse {}: {}", path.display(), errors.join("; "))
+            })?;
+            let summaries = library
+                .iter()
+                .map(|entry| Summary {
+                    key: entry.key().to_owned(),
+                    title: entry.title().map(|t| t.value.to_string()),
+                    authors: entry.authors().unwrap_or_default().iter().map(|p| p.name.clone()).collect(),
+                    year: entry.date_any().map(|d| d.year),
+                })
+                .collect();
+            (Entries::BibTeX(library), summaries)
+        };
+        Ok(Library { entries, summaries })
+    }
+
+    /// Renders `citations`, each of them citing one or more entries, and
+    /// the bibliography of everything cited, as HTML. Citations of keys
+    /// that aren't in the library come out as `None`.
+    fn render(&self, citations: &[Vec<Cited>], style: &IndependentStyle) -> (Vec<Option<String>>, Option<String>) {
+        // `BibliographyDriver` wants a trait hayagriva doesn't export, so
+        // this is spelled out for both kinds of entries
+        macro_rules! drive {
+            ($find:expr) => {{
+                let mut driver = BibliographyDriver::new();
+                let mut found = Vec::with_capacity(citations.len());
+                for citation in citations {
+                    let items: Vec<_> = citation
+                        .iter()
+                        .filter_map(|cited| {
+                            let locator = cited.locator.map(|(l, at)| SpecificLocator(l, LocatorPayload::Str(at)));
+                            Some(CitationItem::with_locator($find(cited.key)?, locator))
+                        })
+                        .collect();
+                    found.push(!items.is_empty());
+                    if !items.is_empty() {
+                        driver.citation(CitationRequest::from_items(items, style, &LOCALES));
+                    }
+                }
+                (found, driver.finish(BibliographyRequest::new(style, None, &LOCALES)))
+            }};
+        }
+        let (found, rendered): (Vec<bool>, Rendered) = match &self.entries {
+            Entries::BibTeX(library) => drive!(|key| library.get(key)),
+            Entries::CslJson(items, keys) => drive!(|key: &str| keys.get(key).map(|&i| &items[i])),
+        };
+
+        let mut rendered_citations = rendered.citations.iter();
+        let citations = found
+            .into_iter()
+            .map(|found| found.then(|| rendered_citations.next().map(|c| html(&c.citation))).flatten())
+            .collect();
+        let bibliography = rendered.bibliography.filter(|b| !b.items.is_empty()).map(|bibliography| {
+            let class = if bibliography.hanging_indent { "references hanging-indent" } else { "references" };
+            let mut out = format!("<div class=\"{class}\">\n");
+            for item in &bibliography.items {
+                let key = markdown::escape(&item.key);
+                out.push_str(&format!("<div class=\"csl-entry\" id=\"ref-{key}\">{}</div>\n", html(&item.content)));
+            }
+            out.push_str("</div>\n");
+            out
+        });
+        (citations, bibliography)
+    }
+}
+
+fn html(children: &ElemChildren) -> String {
+    let mut out = String::new();
+    // writing to a string doesn't fail
+    let _ = children.write_buf(&mut out, BufWriteFormat::Html);
+    out
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Citations {
+    fn load(&self, path: &Path) -> Result<Arc<Library>, String> {
+        let library = Arc::new(Library::load(path)?);
+        *self.loaded.write().expect("citations lock poisoned") = Some(Loaded {
+            path: path.to_owned(),
+            modified: modified(path),
+            library: library.clone(),
+        });
+        Ok(library)
+    }
+
+    /// The loaded library, read again if its file changed.
+    fn library(&self) -> Result<Arc<Library>, String> {
+        let path = {
+            let loaded = self.loaded.read().expect("citations lock poisoned");
+            let loaded = loaded.as_ref().ok_or("no citation library is loaded".to_owned())?;
+            if modified(&loaded.path) == loaded.modified {
+                return Ok(loaded.library.clone());
+            }
+            loaded.path.clone()
+        };
+        self.load(&path)
+    }
+}
+
+/// A style bundled with hayagriva by name, like `apa`, or a `.csl` file.
+fn style(name: &str, base_dir: Option<&Path>) -> Result<IndependentStyle, String> {
+    let parsed = if name.ends_with(".csl") {
+        let path = base_dir.map_or_else(|| PathBuf::from(name), |dir| dir.join(name));
+        let source = fs::read_to_string(&path).map_err(|e| format!("read {}: {e}", path.display()))?;
+        Style::from_xml(&source).map_err(|e| format!("parse {}: {e}", path.display()))?
+    } else {
+        ArchivedStyle::by_name(name).ok_or_else(|| format!("unknown citation style {name}"))?.get()
+    };
+    match parsed {
+        Style::Independent(style) => Ok(style),
+        // like https://www.zotero.org/styles/apa
+        Style::Dependent(dependent) => match dependent.parent_link.href.rsplit('/').next() {
+            Some(parent) if parent != name && !parent.ends_with(".csl") => style(parent, None),
+            _ => Err(format!("{name} depends on {}, which isn't bundled", dependent.parent_link.href)),
+        },
+    }
+}
+
+fn locator(text: &str) -> (Locator, &str) {
+    for &(label, locator) in LOCATOR_LABELS {
+        if let Some(rest) = text.strip_prefix(label) {
+            return (locator, rest.trim_start());
+        }
+    }
+    (Locator::Page, text)
+}
+
+/// The entries cited between the brackets of `[...]`, or `None` if it
+/// isn't a citation.
+fn parse_citation(inner: &str) -> Option<Vec<Cited<'_>>> {
+    inner
+        .split(';')
+        .map(|part| {
+            let part = part.trim();
+            let at = part.find('@')?;
+            // not an email address
+            if part[..at].chars().next_back().is_some_and(char::is_alphanumeric) {
+                return None;
+            }
+            let rest = &part[at + 1..];
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric() && !"_:.#$%&-+?<>~/".contains(c))
+                .unwrap_or(rest.len());
+            // punctuation ends a key unless more of it follows
+            let key = rest[..end].trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_');
+            let locator = rest[key.len()..].trim_start().strip_prefix(',').map(str::trim).filter(|l| !l.is_empty());
+            (!key.is_empty()).then(|| Cited { key, locator: locator.map(self::locator) })
+        })
+        .collect()
+}
+
+/// Replaces the citations in the markdown `body` for an export, with the
+/// library and style given in the frontmatter as `bibliography` and `csl`
+/// like in pandoc, and renders the bibliography of what's cited. Without a
+/// library in the frontmatter the body comes back as it is.
+pub(crate) fn for_export(
+    body: &str, meta: &Frontmatter, base_dir: Option<&Path>,
+) -> Result<(String, Option<String>), String> {
+    let Some(library) = meta.get("bibliography") else { return Ok((body.to_owned(), None)) };
+    let path = base_dir.map_or_else(|| PathBuf::from(library), |dir| dir.join(library));
+    let library = Library::load(&path)?;
+    let style = style(meta.get("csl").unwrap_or(DEFAULT_STYLE), base_dir)?;
+
+    let prose = typography::prose_ranges(body);
+    let (matches, citations): (Vec<regex::Match>, Vec<Vec<Cited>>) = CITATION
+        .captures_iter(body)
+        .filter_map(|captures| {
+            let whole = captures.get(0)?;
+            // not in code, and not a link like [@user](...)
+            let in_prose = prose.iter().any(|r| r.start <= whole.start() && whole.start() < r.end);
+            let link = body[whole.end()..].starts_with(['(', '[']);
+            if !in_prose || link {
+                return None;
+            }
+            Some((whole, parse_citation(captures.get(1)?.as_str())?))
+        })
+        .unzip();
+    let (rendered, bibliography) = library.render(&citations, &style);
+
+    let mut out = String::with_capacity(body.len());
+    let mut done = 0;
+    for (whole, rendered) in matches.iter().zip(rendered) {
+        out.push_str(&body[done..whole.start()]);
+        match rendered {
+            Some(html) => out.push_str(&format!("<span class=\"citation\">{html}</span>")),
+            None => {
+                log::warn!("export: no entry for {} in {}", whole.as_str(), path.display());
+                out.push_str(whole.as_str());
+            }
+        }
+        done = whole.end();
+    }
+    out.push_str(&body[done..]);
+    Ok((out, bibliography))
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn complete(library: &Library, query: &str, limit: usize) -> Vec<Completion> {
+    let query = query.trim_start_matches('@');
+    let mut result: Vec<Completion> = library
+        .summaries
+        .iter()
+        .filter_map(|summary| {
+            let authors = summary.authors.join(", ");
+            let score = fuzzy_score(query, &summary.key)
+                .max(summary.title.as_deref().and_then(|t| fuzzy_score(query, t)))
+                .max(fuzzy_score(query, &authors))?;
+            let detail = match (authors.is_empty(), summary.year) {
+                (false, Some(year)) => Some(format!("{authors} {year}")),
+                (false, None) => Some(authors),
+                (true, Some(year)) => Some(year.to_string()),
+                (true, None) => None,
+            };
+            Some(Completion {
+                label: summary.title.clone().unwrap_or_else(|| summary.key.clone()),
+                insert: summary.key.clone(),
+                detail,
+                score,
+            })
+        })
+        .collect();
+    result.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.insert.cmp(&b.insert)));
+    result.truncate(limit);
+    result
+}
+
+/// Loads the library at `path`, a `.bib` file or CSL-JSON, for completion
+/// and bibliographies, and returns how many entries it has.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn load_citation_library(path: String, citations: State<'_, Arc<Citations>>) -> Result<usize, BackendError> {
+    log::info!("load_citation_library start: {path}");
+    let citations = citations.inner().clone();
+    let result = tokio::task::spawn_blocking(move || citations.load(Path::new(&path))).await;
+    match result {
+        Ok(Ok(library)) => {
+            log::info!("load_citation_library done");
+            Ok(library.summaries.len())
+        }
+        Ok(Err(e)) => Err(format!("load_citation_library task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Entries of the loaded library whose key, title or authors match
+/// `query`, the best first. `insert` is the key.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn cite_complete(
+    query: String, limit: Option<usize>, citations: State<'_, Arc<Citations>>,
+) -> Result<Vec<Completion>, BackendError> {
+    let citations = citations.inner().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        citations.library().map(|library| complete(&library, &query, limit.unwrap_or(DEFAULT_LIMIT)))
+    }).await;
+    match result {
+        Ok(Ok(completions)) => Ok(completions),
+        Ok(Err(e)) => Err(format!("cite_complete task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// The bibliography of `keys` from the loaded library as HTML, in a
+/// bundled `style` or a `.csl` file; Chicago author-date by default.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn format_bibliography(
+    keys: Vec<String>, style: Option<String>, citations: State<'_, Arc<Citations>>,
+) -> Result<String, BackendError> {
+    let citations = citations.inner().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let library = citations.library()?;
+        let style = self::style(style.as_deref().unwrap_or(DEFAULT_STYLE), None)?;
+        let cited: Vec<Vec<Cited>> = keys.iter().map(|key| vec![Cited { key, locator: None }]).collect();
+        Ok::<_, String>(library.render(&cited, &style).1.unwrap_or_default())
+    }).await;
+    match result {
+        Ok(Ok(html)) => Ok(html),
+        Ok(Err(e)) => Err(format!("format_bibliography task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// The CSL styles that can be used by name.
+#[tauri::command]
+pub fn list_citation_styles() -> Vec<CitationStyle> {
+    ArchivedStyle::all()
+        .iter()
+        .filter_map(|style| {
+            Some(CitationStyle { name: (*style.names().first()?).to_owned(), title: style.display_name().to_owned() })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn citations_are_parsed_like_pandoc() {
+        let cited = parse_citation("see @doe, p. 33; @smith_2020.").expect("a citation");
+        assert_eq!(cited.iter().map(|c| c.key).collect::<Vec<_>>(), ["doe", "smith_2020"]);
+        assert!(matches!(cited[0].locator, Some((Locator::Page, "33"))));
+        assert!(cited[1].locator.is_none());
+        let chapter = parse_citation("@doe, chap. 2").expect("a citation");
+        assert!(matches!(chapter[0].locator, Some((Locator::Chapter, "2"))));
+        assert!(parse_citation("mail me at a@b.c").is_none());
+        assert!(parse_citation("@doe; no key").is_none());
+    }
+
+    #[test]
+    fn exports_render_citations_and_the_bibliography() {
+        let root = std::env::temp_dir().join(format!("emmm-citations-test-{}", std::process::id()));
+        fs::create_dir_all(&root).expect("a folder");
+        fs::write(root.join("refs.bib"), "@book{doe, title = {A Book}, author = {Doe, Jane}, year = {2020}}\n")
+            .expect("a library");
+        let (meta, body) = Frontmatter::of("---\nbibliography: refs.bib\n---\nAs [@doe, p. 3] and [@nobody] \
+                                            say, but not `[@doe]` or [@doe](link).\n");
+        let (out, bibliography) = for_export(body, &meta, Some(&root)).expect("rendered");
+        assert!(out.starts_with("As <span class=\"citation\">(Doe 2020, 3)</span> and [@nobody]"), "{out}");
+        assert!(out.ends_with("but not `[@doe]` or [@doe](link).\n"), "{out}");
+        let bibliography = bibliography.expect("a bibliography");
+        assert!(bibliography.contains("id=\"ref-doe\"") && bibliography.contains("A Book"), "{bibliography}");
+
+        let library = Library::load(&root.join("refs.bib")).expect("a library");
+        let found = complete(&library, "@doe", 5);
+        assert_eq!((found[0].insert.as_str(), found[0].detail.as_deref()), ("doe", Some("Doe 2020")));
+        assert!(style("no-such-style", None).is_err());
+        fs::remove_dir_all(&root).expect("cleaned up");
+    }
+}
@@ -0,0 +1,278 @@
+//! Compressing, exporting and searching from the command line, without a
+//! window, through the same code as the app; for CI pipelines and scripts.
+//! [`crate::run`] hands over when the first argument is one of the
+//! [`COMMANDS`]; anything else starts the app as usual.
+//!
+//! Release builds on Windows have no console, so the output only shows
+//! when it's redirected.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use regex::RegexBuilder;
+
+use crate::{
+    compress_file,
+    frontmatter::Frontmatter,
+    markdown::{self, escape, RenderOptions},
+    single_file::{self, SingleFileOptions},
+    site::{self, SiteOptions},
+    workspace,
+};
+
+const COMMANDS: &[&str] = &["compress", "export-html", "export-site", "search", "help"];
+
+const USAGE: &str = "\
+usage:
+  emmm compress <image> [-o <output>] [--max-size <bytes>]
+      Compresses like pasted images are, to JPEG unless small enough;
+      writes to stdout without -o.
+  emmm export-html <document> [-o <output>] [--max-image-size <bytes>]
+      Renders a Markdown document to one HTML file with its images inlined.
+  emmm export-site <workspace> <out-dir> [--theme <light|dark|css>] [--options <json>]
+      Exports a workspace as a static website; --options takes the site
+      options as JSON, like {\"title\": \"Notes\", \"baseUrl\": \"https://...\"}.
+  emmm search <workspace> <pattern> [--regex]
+      Prints the lines of documents containing the pattern, ignoring case;
+      exits with 1 if there are none.
+
+Failures exit with 2.
+";
+
+const DEFAULT_MAX_SIZE: usize = 1024 * 1024;
+
+/// The arguments of a command: positional ones and `--flag [value]`s.
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+/// Options that take no value.
+const SWITCHES: &[&str] = &["--regex"];
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args { positional: Vec::new(), options: Vec::new() };
+        while let Some(arg) = args.next() {
+            if arg.starts_with('-') && arg.len() > 1 {
+                let value = if SWITCHES.contains(&arg.as_str()) {
+                    None
+                } else {
+                    Some(args.next().ok_or_else(|| format!("{arg} needs a value"))?)
+                };
+                parsed.options.push((arg, value));
+            } else {
+                parsed.positional.push(arg);
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn option(&self, names: &[&str]) -> Option<&str> {
+        self.options.iter().rev().find(|(name, _)| names.contains(&name.as_str())).and_then(|(_, v)| v.as_deref())
+    }
+
+    fn switch(&self, name: &str) -> bool {
+        self.options.iter().any(|(option, _)| option == name)
+    }
+
+    fn size(&self, name: &str) -> Result<Option<usize>, String> {
+        self.option(&[name]).map(|v| v.parse().map_err(|_| format!("{name}: not a size: {v}"))).transpose()
+    }
+
+    /// The positional arguments, if there are exactly `N`.
+    fn positional<const N: usize>(&self) -> Result<[&str; N], String> {
+        let args: Vec<&str> = self.positional.iter().map(String::as_str).collect();
+        args.try_into().map_err(|_| format!("expected {N} arguments"))
+    }
+}
+
+fn write_output(output: Option<&str>, data: &[u8]) -> Result<(), String> {
+    match output {
+        Some(path) => fs::write(path, data).map_err(|e| format!("write {path}: {e}")),
+        None => io::stdout().write_all(data).map_err(|e| format!("write stdout: {e}")),
+    }
+}
+
+fn compress(args: &Args) -> Result<i32, String> {
+    let [input] = args.positional()?;
+    let max_size = args.size("--max-size")?.unwrap_or(DEFAULT_MAX_SIZE);
+    let data = compress_file(input, max_size)?;
+    write_output(args.option(&["-o", "--output"]), &data)?;
+    Ok(0)
+}
+
+fn export_html(args: &Args) -> Result<i32, String> {
+    let [input] = args.positional()?;
+    let path = Path::new(input);
+    let document = fs::read_to_string(path).map_err(|e| format!("read {input}: {e}"))?;
+    let (frontmatter, body) = Frontmatter::of(&document);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let title = frontmatter.get("title").map_or_else(|| stem.into_owned(), str::to_owned);
+    let options = RenderOptions { base_dir: path.parent(), ..RenderOptions::default() };
+    let content = markdown::render_document(body, &frontmatter, &options)?.into_html();
+    let html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n\
+         <body>\n{}\n</body>\n</html>\n",
+        escape(&title),
+        content,
+    );
+    // the same as exporting from the app
+    let options = SingleFileOptions {
+        image_max_size: Some(args.size("--max-image-size")?.unwrap_or(DEFAULT_MAX_SIZE)),
+        fetch_remote: false,
+    };
+    let html = single_file::inline_html(&html, path.parent(), &options)?;
+    write_output(args.option(&["-o", "--output"]), html.as_bytes())?;
+    Ok(0)
+}
+
+fn export_site(args: &Args) -> Result<i32, String> {
+    let [workspace, out_dir] = args.positional()?;
+    let options: SiteOptions = match args.option(&["--options"]) {
+        Some(json) => serde_json::from_str(json).map_err(|e| format!("--options: {e}"))?,
+        None => SiteOptions::default(),
+    };
+    let theme = args.option(&["--theme"]).unwrap_or("light").to_owned();
+    let summary =
+        tauri::async_runtime::block_on(site::export_site(workspace.to_owned(), out_dir.to_owned(), theme, options))
+            .map_err(|e| e.to_string())?;
+    let summary = serde_json::to_string_pretty(&summary).map_err(|e| format!("serialize: {e}"))?;
+    println!("{summary}");
+    Ok(0)
+}
+
+fn search(args: &Args) -> Result<i32, String> {
+    let [root, pattern] = args.positional()?;
+    let pattern = if args.switch("--regex") { pattern.to_owned() } else { regex::escape(pattern) };
+    let pattern = RegexBuilder::new(&pattern).case_insensitive(true).build().map_err(|e| format!("pattern: {e}"))?;
+    let root = PathBuf::from(root);
+    let mut stdout = io::stdout().lock();
+    let mut found = false;
+    for path in workspace::documents(&root)? {
+        let Ok(text) = fs::read_to_string(&path) else { continue };
+        let relative = path.strip_prefix(&root).unwrap_or(&path).display().to_string();
+        for (number, line) in text.lines().enumerate().filter(|(_, line)| pattern.is_match(line)) {
+            found = true;
+            writeln!(stdout, "{relative}:{}: {line}", number + 1).map_err(|e| format!("write stdout: {e}"))?;
+        }
+    }
+    Ok(if found { 0 } else { 1 })
+}
+
+/// Runs the command in the process's arguments and returns the exit code,
+/// or `None` if the arguments aren't for the command line.
+pub fn run() -> Option<i32> {
+    run_with(std::env::args().skip(1))
+}
+
+/// [`run`] with `args`, the arguments after the program's.
+fn run_with(mut args: impl Iterator<Item = String>) -> Option<i32> {
+    let command = args.next().filter(|command| COMMANDS.contains(&command.as_str()))?;
+    let result = Args::parse(args).and_then(|args| match command.as_str() {
+        "compress" => compress(&args),
+        "export-html" => export_html(&args),
+        "export-site" => export_site(&args),
+        "search" => search(&args),
+        _ => {
+            print!("{USAGE}");
+            Ok(0)
+        }
+    });
+    Some(match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("emmm {command}: {e}\nsee `emmm help` for usage");
+            2
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> std::vec::IntoIter<String> {
+        args.iter().map(|&a| a.to_owned()).collect::<Vec<_>>().into_iter()
+    }
+
+    fn run_args(arguments: &[&str]) -> Option<i32> {
+        run_with(args(arguments))
+    }
+
+    #[test]
+    fn options_take_values_and_switches_dont() {
+        let parsed = Args::parse(args(&["a.png", "-o", "out.jpg", "--regex", "b", "--max-size", "10", "-o", "c.jpg"]))
+            .expect("valid arguments");
+        assert_eq!(parsed.positional::<2>(), Ok(["a.png", "b"]));
+        assert_eq!(parsed.positional::<1>(), Err("expected 1 arguments".to_owned()));
+        assert_eq!(parsed.option(&["-o", "--output"]), Some("c.jpg"));
+        assert!(parsed.switch("--regex"));
+        assert_eq!(parsed.size("--max-size"), Ok(Some(10)));
+        assert_eq!(parsed.size("--max-image-size"), Ok(None));
+
+        let parsed = Args::parse(args(&["-", "--max-size", "big"])).expect("valid arguments");
+        assert_eq!(parsed.positional::<1>(), Ok(["-"]));
+        assert_eq!(parsed.size("--max-size"), Err("--max-size: not a size: big".to_owned()));
+        assert_eq!(Args::parse(args(&["a.png", "-o"])).err(), Some("-o needs a value".to_owned()));
+    }
+
+    #[test]
+    fn other_arguments_start_the_app() {
+        assert_eq!(run_args(&[]), None);
+        assert_eq!(run_args(&["frobnicate", "a.md"]), None);
+        assert_eq!(run_args(&["/notes/a.md"]), None);
+        assert_eq!(run_args(&["help"]), Some(0));
+    }
+
+    #[test]
+    fn failures_exit_with_2() {
+        let failing: &[&[&str]] = &[
+            &["compress"],
+            &["compress", "a.png", "b.png"],
+            &["compress", "a.png", "--max-size", "big"],
+            &["compress", "/nowhere/a.png"],
+            &["export-html", "a.md", "-o"],
+            &["export-html", "/nowhere/a.md"],
+            &["export-site", "/nowhere"],
+            &["export-site", "/nowhere", "/out", "--options", "{"],
+            &["search", "/nowhere"],
+            &["search", "/nowhere", "(", "--regex"],
+        ];
+        for arguments in failing {
+            assert_eq!(run_args(arguments), Some(2), "{arguments:?}");
+        }
+    }
+
+    #[test]
+    fn commands_write_their_output() {
+        let dir = std::env::temp_dir().join(format!("emmm-cli-test-{}", std::process::id()));
+        let notes = dir.join("notes");
+        fs::create_dir_all(&notes).expect("a workspace");
+        fs::write(notes.join("a.md"), "---\ntitle: Groceries\n---\n# List\n\nBuy milk\n").expect("a document");
+        RgbImage::new(4, 4).save(dir.join("image.png")).expect("an image");
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        assert_eq!(run_args(&["compress", &path("image.png"), "-o", &path("image.jpg")]), Some(0));
+        assert!(!fs::read(dir.join("image.jpg")).expect("the compressed image").is_empty());
+
+        assert_eq!(run_args(&["export-html", &path("notes/a.md"), "--output", &path("a.html")]), Some(0));
+        let html = fs::read_to_string(dir.join("a.html")).expect("the page");
+        assert!(html.contains("<title>Groceries</title>") && html.contains("Buy milk"), "{html}");
+
+        let site = ["export-site", &path("notes"), &path("site"), "--options", r#"{"title": "Notes"}"#];
+        assert_eq!(run_args(&site), Some(0));
+        assert!(dir.join("site/index.html").is_file());
+        assert!(dir.join("site/a.html").is_file());
+
+        assert_eq!(run_args(&["search", &path("notes"), "MILK"]), Some(0));
+        assert_eq!(run_args(&["search", &path("notes"), "m.lk"]), Some(1));
+        assert_eq!(run_args(&["search", &path("notes"), "m.lk", "--regex"]), Some(0));
+        fs::remove_dir_all(&dir).expect("cleaned up");
+    }
+}
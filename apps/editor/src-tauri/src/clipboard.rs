@@ -0,0 +1,320 @@
+//! Capture mode for research sessions: while it's on, everything copied,
+//! in any app, is logged in the database: text, URLs, and images, which
+//! are compressed and kept in the app data. It's off until started, and
+//! what was on the clipboard before isn't logged, nor what the app that
+//! copied it marked as secret or passing, like a password manager does.
+
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use image::{ImageFormat, RgbaImage};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager, Url};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+
+use crate::{compress_data, db::Database, error::BackendError, workspace::write_atomic};
+
+const POLL: Duration = Duration::from_secs(1);
+const IMAGES_DIR: &str = "clipboard";
+const IMAGE_MAX_SIZE: usize = 1024 * 1024;
+
+/// Counts capture sessions; the polling of an older one stops.
+static SESSION: AtomicU64 = AtomicU64::new(0);
+static RUNNING: Mutex<bool> = Mutex::new(false);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ClipKind {
+    Text,
+    Url,
+    Image,
+}
+
+impl ClipKind {
+    fn name(self) -> &'static str {
+        match self {
+            ClipKind::Text => "text",
+            ClipKind::Url => "url",
+            ClipKind::Image => "image",
+        }
+    }
+
+    fn from_name(name: &str) -> ClipKind {
+        match name {
+            "url" => ClipKind::Url,
+            "image" => ClipKind::Image,
+            _ => ClipKind::Text,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Clip {
+    id: i64,
+    kind: ClipKind,
+    /// Seconds since the epoch.
+    captured: i64,
+    /// The text or URL; `None` for images.
+    text: Option<String>,
+    /// Of the compressed image.
+    path: Option<String>,
+}
+
+/// What's on the clipboard, as a hash to notice changes by.
+enum Content {
+    Text(String),
+    Image(RgbaImage),
+}
+
+impl Content {
+    fn read(app: &AppHandle) -> Option<(Content, Vec<u8>)> {
+        let clipboard = app.clipboard();
+        if let Ok(text) = clipboard.read_text().map(|text| text.trim().to_owned()) {
+            if !text.is_empty() {
+                let hash = Sha256::digest(text.as_bytes()).to_vec();
+                return Some((Content::Text(text), hash));
+            }
+        }
+        let image = clipboard.read_image().ok()?;
+        let hash = Sha256::digest(image.rgba()).to_vec();
+        let image = RgbaImage::from_raw(image.width(), image.height(), image.rgba().to_vec())?;
+        Some((Content::Image(image), hash))
+    }
+}
+
+/// Pasteboard types apps add to say what they copied shouldn't be kept,
+/// from nspasteboard.org: secrets, and what's only passing through.
+#[cfg(target_os = "macos")]
+const PRIVATE_TYPES: [&str; 2] = ["org.nspasteboard.ConcealedType", "org.nspasteboard.TransientType"];
+
+/// Whether the app that copied what's on the clipboard asked clipboard
+/// managers not to keep it, like password managers do.
+fn is_private() -> bool {
+    #[cfg(target_os = "macos")]
+    {
+        let pasteboard = objc2_app_kit::NSPasteboard::generalPasteboard();
+        pasteboard.types().is_some_and(|types| {
+            types.iter().any(|kind| PRIVATE_TYPES.contains(&kind.to_string().as_str()))
+        })
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::{
+            DataExchange::{
+                CloseClipboard, GetClipboardData, IsClipboardFormatAvailable, OpenClipboard,
+                RegisterClipboardFormatW,
+            },
+            Memory::{GlobalLock, GlobalUnlock},
+        };
+        let format = |name: &str| {
+            let name: Vec<u16> = name.encode_utf16().chain([0]).collect();
+            // SAFETY: `name` is a NUL-terminated UTF-16 string.
+            unsafe { RegisterClipboardFormatW(name.as_ptr()) }
+        };
+        let (exclude, history) =
+            (format("ExcludeClipboardContentFromMonitorProcessing"), format("CanIncludeInClipboardHistory"));
+        // SAFETY: these only look at the clipboard, which is opened and
+        // closed again around the one read of its data; the data of
+        // CanIncludeInClipboardHistory is a DWORD.
+        unsafe {
+            if IsClipboardFormatAvailable(exclude) != 0 {
+                return true;
+            }
+            if IsClipboardFormatAvailable(history) == 0 || OpenClipboard(std::ptr::null_mut()) == 0 {
+                return false;
+            }
+            let data = GetClipboardData(history);
+            let value = GlobalLock(data).cast::<u32>();
+            let included = value.is_null() || *value != 0;
+            if !value.is_null() {
+                GlobalUnlock(data);
+            }
+            CloseClipboard();
+            !included
+        }
+    }
+    #[cfg(not(any(target_os = "macos", windows)))]
+    {
+        false
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| i64::try_from(d.as_secs()).unwrap_or(i64::MAX))
+}
+
+fn is_url(text: &str) -> bool {
+    !text.contains(char::is_whitespace)
+        && Url::parse(text).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Compresses `image` and saves it in `dir`, named after its hash.
+fn save_image(dir: &Path, image: &RgbaImage) -> Result<PathBuf, String> {
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png).map_err(|e| format!("encode png: {e}"))?;
+    let data = compress_data(png, IMAGE_MAX_SIZE)?;
+    let format = image::guess_format(&data).map_err(|e| format!("guess_format: {e}"))?;
+    let extension = format.extensions_str().first().copied().unwrap_or("bin");
+    std::fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    let path = dir.join(format!("{}.{extension}", &hex::encode(Sha256::digest(&data))[..16]));
+    write_atomic(&path, &data)?;
+    Ok(path)
+}
+
+fn record(database: &Database, images: &Path, content: Content) -> Result<(), String> {
+    let (kind, text, path) = match content {
+        Content::Text(text) if is_url(&text) => (ClipKind::Url, Some(text), None),
+        Content::Text(text) => (ClipKind::Text, Some(text), None),
+        Content::Image(image) => {
+            let path = save_image(images, &image)?;
+            (ClipKind::Image, None, Some(path.to_string_lossy().into_owned()))
+        }
+    };
+    log::info!("clipboard: captured {}", kind.name());
+    database.with(|connection| {
+        connection
+            .execute(
+                "INSERT INTO clipboard (kind, captured, text, path) VALUES (?1, ?2, ?3, ?4)",
+                params![kind.name(), now(), text, path],
+            )
+            .map(|_| ())
+    })
+}
+
+/// Polls the clipboard until another session starts or capture stops.
+async fn watch(app: AppHandle, session: u64) {
+    let database = app.state::<Arc<Database>>().inner().clone();
+    let images = match app.path().app_data_dir() {
+        Ok(dir) => dir.join(IMAGES_DIR),
+        Err(e) => {
+            log::warn!("clipboard: app data dir: {e}");
+            return;
+        }
+    };
+    let mut last = Content::read(&app).map(|(_, hash)| hash);
+    let mut ticks = tokio::time::interval(POLL);
+    while SESSION.load(Ordering::Relaxed) == session {
+        ticks.tick().await;
+        let (app, database, images, previous) = (app.clone(), database.clone(), images.clone(), last.clone());
+        let result = tokio::task::spawn_blocking(move || {
+            let Some((content, hash)) = Content::read(&app) else { return Ok(previous) };
+            if previous.as_ref() != Some(&hash) && !is_private() {
+                record(&database, &images, content)?;
+            }
+            Ok::<_, String>(Some(hash))
+        })
+        .await;
+        match result {
+            Ok(Ok(hash)) => last = hash,
+            Ok(Err(e)) => log::warn!("clipboard: {e}"),
+            Err(e) => log::warn!("clipboard: tokio::task::spawn_blocking: {e}"),
+        }
+    }
+}
+
+/// Starts logging what's copied; does nothing if it's on already.
+#[tauri::command]
+pub fn start_clipboard_capture(app: AppHandle) {
+    let mut running = RUNNING.lock().expect("clipboard lock poisoned");
+    if *running {
+        return;
+    }
+    *running = true;
+    let session = SESSION.fetch_add(1, Ordering::Relaxed) + 1;
+    log::info!("start_clipboard_capture: session {session}");
+    tauri::async_runtime::spawn(watch(app, session));
+}
+
+#[tauri::command]
+pub fn stop_clipboard_capture() {
+    let mut running = RUNNING.lock().expect("clipboard lock poisoned");
+    if *running {
+        log::info!("stop_clipboard_capture");
+        SESSION.fetch_add(1, Ordering::Relaxed);
+        *running = false;
+    }
+}
+
+#[tauri::command]
+pub fn clipboard_capture_running() -> bool {
+    *RUNNING.lock().expect("clipboard lock poisoned")
+}
+
+/// The captured items, the newest first: at most `limit`, of `kind` if
+/// given, captured at `since` or later.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn clipboard_captures(
+    kind: Option<ClipKind>, since: Option<i64>, limit: Option<u32>, app: AppHandle,
+) -> Result<Vec<Clip>, BackendError> {
+    let database = app.state::<Arc<Database>>().inner().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        database.with(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT id, kind, captured, text, path FROM clipboard
+                 WHERE (?1 IS NULL OR kind = ?1) AND captured >= ?2
+                 ORDER BY id DESC LIMIT ?3",
+            )?;
+            let rows = statement.query_map(
+                params![kind.map(ClipKind::name), since.unwrap_or(0), limit.unwrap_or(100)],
+                |row| {
+                    Ok(Clip {
+                        id: row.get(0)?,
+                        kind: ClipKind::from_name(&row.get::<_, String>(1)?),
+                        captured: row.get(2)?,
+                        text: row.get(3)?,
+                        path: row.get(4)?,
+                    })
+                },
+            )?;
+            rows.collect()
+        })
+    })
+    .await;
+    match result {
+        Ok(Ok(clips)) => Ok(clips),
+        Ok(Err(e)) => Err(format!("clipboard_captures task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_are_recorded_by_kind() {
+        let dir = std::env::temp_dir().join(format!("emmm-clipboard-test-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let database = Database::new(dir.join("emmm.sqlite"));
+        let images = dir.join(IMAGES_DIR);
+        record(&database, &images, Content::Text("https://example.com/a".to_owned())).expect("record a URL");
+        record(&database, &images, Content::Text("https://example.com and more".to_owned())).expect("record text");
+        record(&database, &images, Content::Image(RgbaImage::new(2, 2))).expect("record an image");
+
+        let rows: Vec<(String, Option<String>, Option<String>)> = database
+            .with(|connection| {
+                let mut statement = connection.prepare("SELECT kind, text, path FROM clipboard ORDER BY id")?;
+                let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+                rows.collect()
+            })
+            .expect("read the clips");
+        let kinds: Vec<_> = rows.iter().map(|(kind, _, _)| ClipKind::from_name(kind)).collect();
+        assert_eq!(kinds, [ClipKind::Url, ClipKind::Text, ClipKind::Image]);
+        assert_eq!(rows[0].1.as_deref(), Some("https://example.com/a"));
+        let image = rows[2].2.as_deref().expect("the image path");
+        assert!(image.ends_with(".png") && Path::new(image).starts_with(&images), "{image}");
+        assert!(Path::new(image).is_file());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,202 @@
+//! Completion of wiki-link targets, tags and headings from the
+//! [`WorkspaceIndex`], with fuzzy matching so `mtgn` finds "Meeting
+//! notes".
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::{
+    error::BackendError,
+    index::{Document, Documents, WorkspaceIndex},
+    metrics, workspace,
+};
+
+const DEFAULT_LIMIT: usize = 20;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum CompletionKind {
+    /// Documents, by title or path.
+    Note,
+    Tag,
+    /// Headings of the document at `path`.
+    Heading,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Completion {
+    pub(crate) label: String,
+    /// What to insert: the path relative to the workspace without its
+    /// extension for notes, the tag without `#`, or the heading text.
+    pub(crate) insert: String,
+    pub(crate) detail: Option<String>,
+    pub(crate) score: i64,
+}
+
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// How well `pattern` matches `candidate` as a case-insensitive
+/// subsequence, or `None` if it doesn't. Matches at the start, at word
+/// starts and in runs score higher; gaps and long candidates lower.
+#[allow(clippy::cast_possible_wrap)]
+pub(crate) fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    let chars: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+    let mut matched = 0;
+    for p in pattern.chars().map(lowercase).filter(|c| !c.is_whitespace()) {
+        let from = previous.map_or(0, |i| i + 1);
+        let found = (from..chars.len()).find(|&i| lowercase(chars[i]) == p)?;
+        score += 1;
+        if found == 0 {
+            score += 10;
+        } else if !chars[found - 1].is_alphanumeric()
+            || (chars[found - 1].is_lowercase() && chars[found].is_uppercase())
+        {
+            score += 8;
+        }
+        if previous.is_some_and(|i| i + 1 == found) {
+            score += 5;
+        }
+        score -= (found - from).min(5) as i64;
+        previous = Some(found);
+        matched += 1;
+    }
+    Some(score - (chars.len() - matched) as i64 / 4)
+}
+
+fn without_extension(relative: &str) -> &str {
+    match relative.rfind('.') {
+        Some(dot) if !relative[dot..].contains('/') => &relative[..dot],
+        _ => relative,
+    }
+}
+
+fn notes(root: &Path, documents: &Documents, prefix: &str) -> Vec<Completion> {
+    documents
+        .iter()
+        .filter_map(|(path, document)| {
+            let relative = workspace::relative_url_path(root, path)?;
+            let insert = without_extension(&relative).to_owned();
+            let score = fuzzy_score(prefix, &document.title).max(fuzzy_score(prefix, &insert))?;
+            Some(Completion { label: document.title.clone(), insert, detail: Some(relative), score })
+        })
+        .collect()
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn tags(documents: &Documents, prefix: &str) -> Vec<Completion> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for tag in documents.values().flat_map(|d| &d.tags) {
+        *counts.entry(tag).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .filter_map(|(tag, count)| {
+            // among equal matches, the tags used more come first
+            let score = fuzzy_score(prefix, tag)? + count.min(10) as i64;
+            let detail = if count == 1 { "1 document".to_owned() } else { format!("{count} documents") };
+            Some(Completion { label: tag.to_owned(), insert: tag.to_owned(), detail: Some(detail), score })
+        })
+        .collect()
+}
+
+fn headings(document: &Document, prefix: &str) -> Vec<Completion> {
+    document
+        .headings
+        .iter()
+        .filter_map(|heading| {
+            Some(Completion {
+                label: heading.text.clone(),
+                insert: heading.text.clone(),
+                detail: Some(format!("H{} #{}", heading.level, heading.id)),
+                score: fuzzy_score(prefix, &heading.text)?,
+            })
+        })
+        .collect()
+}
+
+fn candidates(
+    index: &WorkspaceIndex, workspace: &Path, prefix: &str, kind: CompletionKind, path: Option<&Path>,
+    limit: usize,
+) -> Result<Vec<Completion>, String> {
+    let mut result = match kind {
+        CompletionKind::Note => index.with(workspace, |documents| notes(workspace, documents, prefix))?,
+        CompletionKind::Tag => index.with(workspace, |documents| tags(documents, prefix))?,
+        CompletionKind::Heading => {
+            let path = path.ok_or("a path is needed to complete headings".to_owned())?;
+            let indexed = index.with(workspace, |documents| documents.get(path).map(|d| headings(d, prefix)))?;
+            match indexed {
+                Some(result) => result,
+                // not saved in the workspace yet
+                None => {
+                    let source = fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+                    headings(&Document::parse(path, &source, SystemTime::UNIX_EPOCH), prefix)
+                }
+            }
+        }
+    };
+    result.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.label.cmp(&b.label)));
+    result.truncate(limit);
+    Ok(result)
+}
+
+/// Candidates for `prefix`, the best first. Headings are those of the
+/// document at `path`.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn autocomplete(
+    workspace: String, prefix: String, kind: CompletionKind, path: Option<String>, limit: Option<usize>,
+    index: State<'_, Arc<WorkspaceIndex>>,
+) -> Result<Vec<Completion>, BackendError> {
+    let index = index.inner().clone();
+    let timer = metrics::timer("autocomplete");
+    let result = tokio::task::spawn_blocking(move || {
+        let path = path.as_deref().map(Path::new);
+        candidates(&index, Path::new(&workspace), &prefix, kind, path, limit.unwrap_or(DEFAULT_LIMIT))
+    }).await;
+    timer.finish(matches!(result, Ok(Ok(_))));
+    match result {
+        Ok(Ok(completions)) => Ok(completions),
+        Ok(Err(e)) => Err(format!("autocomplete task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_at_word_starts_score_higher() {
+        assert!(fuzzy_score("mtgn", "Meeting notes").is_some());
+        assert_eq!(fuzzy_score("xyz", "Meeting notes"), None);
+        assert_eq!(fuzzy_score("", "anything"), Some(-2));
+        let prefix = fuzzy_score("meet", "Meeting notes");
+        assert!(prefix > fuzzy_score("meet", "Some meeting"));
+        assert!(fuzzy_score("mt", "my tasks") > fuzzy_score("mt", "mytasks"));
+        assert!(fuzzy_score("mt", "myTasks") > fuzzy_score("mt", "mytasks"));
+        assert_eq!(fuzzy_score("MEET", "meeting"), fuzzy_score("meet", "MEETING"));
+    }
+
+    #[test]
+    fn notes_are_inserted_without_their_extension() {
+        assert_eq!(without_extension("notes/a.md"), "notes/a");
+        assert_eq!(without_extension("notes.d/a"), "notes.d/a");
+        let document = Document::parse(Path::new("a.md"), "# Intro\n\n## Next steps\n", SystemTime::UNIX_EPOCH);
+        let found = headings(&document, "next");
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].insert.as_str(), found[0].detail.as_deref()), ("Next steps", Some("H2 #next-steps")));
+    }
+}
@@ -0,0 +1,448 @@
+//! Saving without losing concurrent changes. The editor remembers the
+//! version of a document it loaded; if the file changed on disk since (in
+//! another editor, or by a sync), saving writes a conflict copy instead of
+//! overwriting it, and [`resolve_conflict`] merges the two with the loaded
+//! version as the common base.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::State;
+
+use crate::{
+    error::BackendError,
+    git, scripting,
+    webhooks::{self, WebhookEvent},
+    windows,
+    workspace::write_atomic,
+};
+
+/// Bases older than this are unlikely to be needed for a merge.
+const MAX_BASE_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Larger line diffs fall back to treating the changed middle as a whole.
+const MAX_DIFF_CELLS: usize = 16 * 1024 * 1024;
+
+/// What a document looked like when the editor loaded or saved it.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Version {
+    hash: String,
+    /// Modification time in ms.
+    modified: u64,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum SaveResult {
+    Saved { version: Version },
+    /// The file changed on disk since `expected`; nothing was overwritten
+    /// and the content was written to `copy` instead.
+    Conflict { copy: String, base_hash: String, current: Version },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    /// The merged text, with conflict markers around overlapping changes.
+    text: String,
+    conflicts: usize,
+    /// Set if the merge was applied to the document.
+    version: Option<Version>,
+}
+
+/// Earlier versions of documents by hash, for three-way merges.
+#[derive(Clone)]
+pub struct Bases {
+    dir: PathBuf,
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn modified_ms(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .and_then(|d| u64::try_from(d.as_millis()).ok())
+        .unwrap_or_default()
+}
+
+impl Bases {
+    pub fn new(dir: PathBuf) -> Self {
+        Bases { dir }
+    }
+
+    fn store(&self, hash: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.dir.join(hash);
+        if path.exists() {
+            // keep it from being pruned
+            let _ = fs::File::options().append(true).open(&path)
+                .and_then(|f| f.set_modified(SystemTime::now()));
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir).map_err(|e| format!("create {}: {e}", self.dir.display()))?;
+        write_atomic(&path, data)?;
+        self.prune();
+        Ok(())
+    }
+
+    fn load(&self, hash: &str) -> Option<Vec<u8>> {
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        fs::read(self.dir.join(hash)).ok()
+    }
+
+    /// Of the files, in bytes.
+    pub fn size(&self) -> u64 {
+        let Ok(entries) = fs::read_dir(&self.dir) else { return 0 };
+        entries.filter_map(Result::ok).filter_map(|e| e.metadata().ok()).map(|m| m.len()).sum()
+    }
+
+    /// Removes the bases that haven't been used for a while.
+    pub fn prune(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else { return };
+        for entry in entries.filter_map(Result::ok) {
+            let old = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.elapsed().ok())
+                .is_some_and(|age| age > MAX_BASE_AGE);
+            if old {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    /// Remembers the document at `path` as it is now.
+    fn snapshot(&self, path: &Path, data: &[u8]) -> Result<Version, String> {
+        let metadata = fs::metadata(path).map_err(|e| format!("stat {}: {e}", path.display()))?;
+        let hash = sha256(data);
+        self.store(&hash, data)?;
+        Ok(Version { hash, modified: modified_ms(&metadata) })
+    }
+}
+
+/// `notes/a.md` becomes `notes/a (conflict 1a2b3c4d).md`, named after the
+/// content so saving the same text again reuses the copy.
+fn conflict_path(path: &Path, data: &[u8]) -> PathBuf {
+    let tag = &sha256(data)[..8];
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{stem} (conflict {tag}).{ext}"),
+        _ => format!("{name} (conflict {tag})"),
+    };
+    path.with_file_name(name)
+}
+
+fn save(bases: &Bases, path: &Path, content: &str, expected: Option<&Version>) -> Result<SaveResult, String> {
+    if let (Some(expected), Ok(metadata)) = (expected, fs::metadata(path)) {
+        // a touched but unchanged file is not a conflict
+        if modified_ms(&metadata) != expected.modified {
+            let current = fs::read(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+            let hash = sha256(&current);
+            if hash != expected.hash && hash != sha256(content.as_bytes()) {
+                let copy = conflict_path(path, content.as_bytes());
+                write_atomic(&copy, content.as_bytes())?;
+                bases.store(&hash, &current)?;
+                return Ok(SaveResult::Conflict {
+                    copy: copy.to_string_lossy().into_owned(),
+                    base_hash: expected.hash.clone(),
+                    current: Version { hash, modified: modified_ms(&metadata) },
+                });
+            }
+        }
+    }
+    write_atomic(path, content.as_bytes())?;
+    Ok(SaveResult::Saved { version: bases.snapshot(path, content.as_bytes())? })
+}
+
+/// For each line of `a`, the line of `b` it is matched with in a longest
+/// common subsequence.
+fn matching(a: &[&str], b: &[&str]) -> Vec<Option<usize>> {
+    let mut result = vec![None; a.len()];
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    for (i, slot) in result.iter_mut().enumerate().take(prefix) {
+        *slot = Some(i);
+    }
+    for k in 0..suffix {
+        result[a.len() - 1 - k] = Some(b.len() - 1 - k);
+    }
+
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let (n, m) = (a_mid.len(), b_mid.len());
+    if n == 0 || m == 0 || (n + 1) * (m + 1) > MAX_DIFF_CELLS {
+        return result;
+    }
+    // lengths[i][j]: LCS of a_mid[i..] and b_mid[j..]
+    let width = m + 1;
+    let mut lengths = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * width + j] = if a_mid[i] == b_mid[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a_mid[i] == b_mid[j] {
+            result[prefix + i] = Some(prefix + j);
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
+}
+
+fn push_side(out: &mut String, lines: &[&str]) {
+    for line in lines {
+        out.push_str(line);
+    }
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Line-based three-way merge of `ours` and `theirs`, which both changed
+/// `base`. Returns the text and the number of conflicts, which are marked
+/// like git does.
+pub(crate) fn merge3(
+    base: &str, ours: &str, theirs: &str, ours_label: &str, theirs_label: &str,
+) -> (String, usize) {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let to_ours = matching(&base, &ours);
+    let to_theirs = matching(&base, &theirs);
+
+    let mut out = String::new();
+    let mut conflicts = 0;
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    loop {
+        if b < base.len() && to_ours[b] == Some(o) && to_theirs[b] == Some(t) {
+            out.push_str(base[b]);
+            b += 1;
+            o += 1;
+            t += 1;
+            continue;
+        }
+        // the next base line both sides kept ends the changed region
+        let next = (b..base.len()).find(|&i| to_ours[i].is_some() && to_theirs[i].is_some());
+        let (b_end, o_end, t_end) = match next {
+            Some(i) => (i, to_ours[i].unwrap_or(o), to_theirs[i].unwrap_or(t)),
+            None => (base.len(), ours.len(), theirs.len()),
+        };
+        let (base_part, ours_part, theirs_part) = (&base[b..b_end], &ours[o..o_end], &theirs[t..t_end]);
+        if ours_part == base_part || ours_part == theirs_part {
+            out.extend(theirs_part.iter().copied());
+        } else if theirs_part == base_part {
+            out.extend(ours_part.iter().copied());
+        } else {
+            conflicts += 1;
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(&format!("<<<<<<< {ours_label}\n"));
+            push_side(&mut out, ours_part);
+            out.push_str("=======\n");
+            push_side(&mut out, theirs_part);
+            out.push_str(&format!(">>>>>>> {theirs_label}\n"));
+        }
+        if next.is_none() {
+            return (out, conflicts);
+        }
+        (b, o, t) = (b_end, o_end, t_end);
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+fn resolve(bases: &Bases, path: &Path, copy: &Path, base_hash: &str, apply: bool) -> Result<MergeResult, String> {
+    let read = |path: &Path| {
+        fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))
+    };
+    let theirs = read(path)?;
+    let ours = read(copy)?;
+    // without the base, everything the two sides don't share conflicts
+    let base = bases
+        .load(base_hash)
+        .map(|data| String::from_utf8_lossy(&data).into_owned())
+        .unwrap_or_default();
+    let (text, conflicts) = merge3(&base, &ours, &theirs, &file_name(copy), &file_name(path));
+    let mut version = None;
+    if apply && conflicts == 0 {
+        write_atomic(path, text.as_bytes())?;
+        fs::remove_file(copy).map_err(|e| format!("remove {}: {e}", copy.display()))?;
+        version = Some(bases.snapshot(path, text.as_bytes())?);
+    }
+    Ok(MergeResult { text, conflicts, version })
+}
+
+/// The current version of the document at `path`, to pass to
+/// [`save_document`] later. Call it when the document is opened; it then
+/// belongs to the calling window, in place of the one it had open, and
+/// fails if another has it open.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn document_version(
+    path: String, bases: State<'_, Bases>, window: tauri::Window,
+) -> Result<Version, BackendError> {
+    windows::claim(window.label(), &path).map_err(|e| format!("document_version: {e}"))?;
+    let bases = bases.inner().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let path = Path::new(&path);
+        let data = fs::read(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+        bases.snapshot(path, &data)
+    }).await;
+
+    match result {
+        Ok(Ok(version)) => Ok(version),
+        Ok(Err(e)) => Err(format!("document_version task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Saves `content` to `path` unless the file changed on disk since
+/// `expected`, the version the editor started from; then the content goes
+/// to a conflict copy next to it, to be merged with [`resolve_conflict`].
+/// Only the window the document belongs to saves it.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn save_document(
+    path: String, content: String, expected: Option<Version>, bases: State<'_, Bases>, window: tauri::Window,
+) -> Result<SaveResult, BackendError> {
+    log::info!("save_document start: {path}");
+    windows::check_owner(window.label(), &path).map_err(|e| format!("save_document: {e}"))?;
+    let result = save_as_user(bases.inner().clone(), path, content, expected).await?;
+    log::info!("save_document done");
+    Ok(result)
+}
+
+/// Saves as [`save_document`] does, with what follows a save: the commit,
+/// webhooks and scripts. For commands that change a document themselves.
+pub(crate) async fn save_as_user(
+    bases: Bases, path: String, content: String, expected: Option<Version>,
+) -> Result<SaveResult, String> {
+    let saved_path = path.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let result = save(&bases, Path::new(&path), &content, expected.as_ref());
+        if let Ok(SaveResult::Saved { .. }) = result {
+            git::auto_commit(Path::new(&path));
+        }
+        result
+    }).await;
+
+    match result {
+        Ok(Ok(result)) => {
+            match &result {
+                SaveResult::Saved { .. } => {
+                    webhooks::emit(WebhookEvent::Save, &saved_path);
+                    scripting::on_save(&saved_path);
+                }
+                SaveResult::Conflict { copy, .. } => log::warn!("save {saved_path}: conflict, saved to {copy}"),
+            }
+            Ok(result)
+        }
+        Ok(Err(e)) => Err(format!("save task: {e}")),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}")),
+    }
+}
+
+/// Merges the conflict `copy` made by [`save_document`] with the document
+/// at `path`, starting from the version `base_hash`. With `apply`, a merge
+/// without conflicts is saved and the copy deleted.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn resolve_conflict(
+    path: String, copy: String, base_hash: String, apply: bool, bases: State<'_, Bases>,
+) -> Result<MergeResult, BackendError> {
+    log::info!("resolve_conflict start: {path}");
+    let bases = bases.inner().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        resolve(&bases, Path::new(&path), Path::new(&copy), &base_hash, apply)
+    }).await;
+
+    match result {
+        Ok(Ok(result)) => {
+            log::info!("resolve_conflict done: {} conflicts", result.conflicts);
+            Ok(result)
+        }
+        Ok(Err(e)) => Err(format!("resolve_conflict task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_to_different_lines_merge() {
+        let base = "a\nb\nc\nd\ne\n";
+        let merge = |ours, theirs| merge3(base, ours, theirs, "ours", "theirs");
+        assert_eq!(merge("a\nB\nc\nd\ne\n", "a\nb\nc\nD\ne\n"), ("a\nB\nc\nD\ne\n".to_owned(), 0));
+        assert_eq!(merge("a\nB\nc\nd\ne\n", "a\nB\nc\nd\ne\n"), ("a\nB\nc\nd\ne\n".to_owned(), 0));
+        assert_eq!(merge("a\nc\nd\ne\n", base), ("a\nc\nd\ne\n".to_owned(), 0));
+    }
+
+    #[test]
+    fn changes_to_the_same_lines_conflict() {
+        assert_eq!(
+            merge3("a\nb\nc\n", "a\nX\nc\n", "a\nY\nc\n", "ours", "theirs"),
+            ("a\n<<<<<<< ours\nX\n=======\nY\n>>>>>>> theirs\nc\n".to_owned(), 1),
+        );
+        // without a base and without line breaks at the end
+        assert_eq!(merge3("", "X", "Y", "a", "b"), ("<<<<<<< a\nX\n=======\nY\n>>>>>>> b\n".to_owned(), 1));
+        assert_eq!(matching(&["a", "b", "c"], &["b", "x", "c"]), [None, Some(0), Some(2)]);
+    }
+
+    #[test]
+    fn saving_over_a_changed_file_writes_a_copy() {
+        let root = std::env::temp_dir().join(format!("emmm-conflict-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).expect("a folder");
+        let bases = Bases::new(root.join("bases"));
+        let path = root.join("a.md");
+        let SaveResult::Saved { version } = save(&bases, &path, "one\ntwo\nthree\n", None).expect("saved") else {
+            panic!("not saved");
+        };
+
+        // changed elsewhere, with a different modification time
+        fs::write(&path, "one\ntwo\nTHREE\n").expect("changed");
+        let earlier = SystemTime::now() - Duration::from_secs(60);
+        fs::File::options().append(true).open(&path).and_then(|f| f.set_modified(earlier)).expect("touched");
+        let SaveResult::Conflict { copy, base_hash, .. } =
+            save(&bases, &path, "ONE\ntwo\nthree\n", Some(&version)).expect("saved")
+        else {
+            panic!("no conflict");
+        };
+        assert_eq!(copy, conflict_path(&path, b"ONE\ntwo\nthree\n").to_string_lossy());
+        assert!(Path::new(&copy).file_name().unwrap().to_string_lossy().starts_with("a (conflict "));
+        assert_eq!(fs::read_to_string(&path).expect("the file"), "one\ntwo\nTHREE\n");
+
+        let merged = resolve(&bases, &path, Path::new(&copy), &base_hash, true).expect("merged");
+        assert_eq!((merged.text.as_str(), merged.conflicts), ("ONE\ntwo\nTHREE\n", 0));
+        assert!(merged.version.is_some() && !Path::new(&copy).exists());
+        assert_eq!(fs::read_to_string(&path).expect("the file"), "ONE\ntwo\nTHREE\n");
+        assert!(bases.load("../a.md").is_none());
+        fs::remove_dir_all(&root).expect("cleaned up");
+    }
+}
@@ -0,0 +1,296 @@
+//! Conflict markers, as git, sync merges and [`crate::conflict`] leave them
+//! in documents:
+//!
+//! ```text
+//! <<<<<<< ours
+//! our lines
+//! ||||||| base
+//! the original lines, only in diff3 style
+//! =======
+//! their lines
+//! >>>>>>> theirs
+//! ```
+
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::BackendError, workspace};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConflictRegion {
+    /// The whole region, markers included, in UTF-16 code units from the
+    /// start of the text, like JavaScript string indices.
+    offset: usize,
+    length: usize,
+    /// Of the `<<<<<<<` marker, from 0.
+    line: usize,
+    ours_label: String,
+    ours: String,
+    base: Option<String>,
+    theirs_label: String,
+    theirs: String,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Accept {
+    Ours,
+    Theirs,
+    /// Ours, then theirs.
+    Both,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resolution {
+    /// Of the region, in the order [`find_conflict_markers`] returns them.
+    index: usize,
+    accept: Accept,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConflictedDocument {
+    path: String,
+    conflicts: usize,
+}
+
+/// A region in byte offsets.
+struct Region<'a> {
+    start: usize,
+    end: usize,
+    line: usize,
+    ours_label: &'a str,
+    ours: &'a str,
+    base: Option<&'a str>,
+    theirs_label: &'a str,
+    theirs: &'a str,
+    /// Whether the `>>>>>>>` line ends the text without a line break.
+    at_end: bool,
+}
+
+/// The label after `marker` if `line` is that marker.
+fn marker<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    let rest = line.trim_end_matches(['\n', '\r']).strip_prefix(marker)?;
+    if rest.is_empty() {
+        Some("")
+    } else {
+        rest.strip_prefix(' ').map(str::trim)
+    }
+}
+
+enum State {
+    Outside,
+    Ours,
+    Base,
+    Theirs,
+}
+
+/// Regions without all their markers are left alone.
+fn regions(text: &str) -> Vec<Region<'_>> {
+    let mut result = Vec::new();
+    let mut state = State::Outside;
+    // byte offsets of the region and of its parts
+    let (mut start, mut line_number, mut ours_label) = (0, 0, "");
+    let (mut ours, mut base, mut theirs) = (0..0, None, 0..0);
+    let mut position = 0;
+    for (i, line) in text.split_inclusive('\n').enumerate() {
+        let next = position + line.len();
+        match state {
+            State::Outside => {
+                if let Some(label) = marker(line, "<<<<<<<") {
+                    (start, line_number, ours_label) = (position, i, label);
+                    ours = next..next;
+                    base = None;
+                    state = State::Ours;
+                }
+            }
+            State::Ours | State::Base => {
+                if let Some(label) = marker(line, "<<<<<<<") {
+                    // an unfinished region; start over from here
+                    (start, line_number, ours_label) = (position, i, label);
+                    ours = next..next;
+                    base = None;
+                    state = State::Ours;
+                } else if matches!(state, State::Ours) && marker(line, "|||||||").is_some() {
+                    ours.end = position;
+                    base = Some(next..next);
+                    state = State::Base;
+                } else if line.trim_end_matches(['\n', '\r']) == "=======" {
+                    match &mut base {
+                        Some(base) => base.end = position,
+                        None => ours.end = position,
+                    }
+                    theirs = next..next;
+                    state = State::Theirs;
+                }
+            }
+            State::Theirs => {
+                if let Some(label) = marker(line, ">>>>>>>") {
+                    theirs.end = position;
+                    result.push(Region {
+                        start,
+                        end: next,
+                        line: line_number,
+                        ours_label,
+                        ours: &text[ours.clone()],
+                        base: base.clone().map(|base| &text[base]),
+                        theirs_label: label,
+                        theirs: &text[theirs.clone()],
+                        at_end: !line.ends_with('\n'),
+                    });
+                    state = State::Outside;
+                }
+            }
+        }
+        position = next;
+    }
+    result
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+fn find(text: &str) -> Vec<ConflictRegion> {
+    let mut result = Vec::new();
+    // UTF-16 offset of `done` bytes
+    let (mut done, mut offset) = (0, 0);
+    for region in regions(text) {
+        offset += utf16_len(&text[done..region.start]);
+        let length = utf16_len(&text[region.start..region.end]);
+        result.push(ConflictRegion {
+            offset,
+            length,
+            line: region.line,
+            ours_label: region.ours_label.to_owned(),
+            ours: region.ours.to_owned(),
+            base: region.base.map(str::to_owned),
+            theirs_label: region.theirs_label.to_owned(),
+            theirs: region.theirs.to_owned(),
+        });
+        offset += length;
+        done = region.end;
+    }
+    result
+}
+
+fn apply(text: &str, resolutions: &[Resolution]) -> Result<String, String> {
+    let regions = regions(text);
+    if let Some(resolution) = resolutions.iter().find(|r| r.index >= regions.len()) {
+        return Err(format!("no conflict {}; there are {}", resolution.index, regions.len()));
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut done = 0;
+    for (index, region) in regions.iter().enumerate() {
+        // the last resolution given for a region wins
+        let Some(resolution) = resolutions.iter().rev().find(|r| r.index == index) else {
+            continue;
+        };
+        out.push_str(&text[done..region.start]);
+        let start = out.len();
+        match resolution.accept {
+            Accept::Ours => out.push_str(region.ours),
+            Accept::Theirs => out.push_str(region.theirs),
+            Accept::Both => {
+                out.push_str(region.ours);
+                out.push_str(region.theirs);
+            }
+        }
+        if region.at_end && out.len() > start && out.ends_with('\n') {
+            out.pop();
+        }
+        done = region.end;
+    }
+    out.push_str(&text[done..]);
+    Ok(out)
+}
+
+fn scan(workspace: &Path) -> Result<Vec<ConflictedDocument>, String> {
+    let mut result = Vec::new();
+    for path in workspace::documents(workspace)? {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                log::warn!("scan_conflict_markers: {}: {e}", path.display());
+                continue;
+            }
+        };
+        let conflicts = regions(&text).len();
+        if conflicts > 0 {
+            result.push(ConflictedDocument { path: path.to_string_lossy().into_owned(), conflicts });
+        }
+    }
+    Ok(result)
+}
+
+/// The conflict regions in `text`, in order.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn find_conflict_markers(text: String) -> Vec<ConflictRegion> {
+    find(&text)
+}
+
+/// `text` with the regions in `resolutions` replaced by the side accepted;
+/// the others are left as they are.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn resolve_conflict_markers(text: String, resolutions: Vec<Resolution>) -> Result<String, BackendError> {
+    Ok(apply(&text, &resolutions)?)
+}
+
+/// The documents in `workspace` that have conflict markers.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn scan_conflict_markers(workspace: String) -> Result<Vec<ConflictedDocument>, BackendError> {
+    log::info!("scan_conflict_markers start: {workspace}");
+    let result = tokio::task::spawn_blocking(move || scan(Path::new(&workspace))).await;
+    match result {
+        Ok(Ok(documents)) => {
+            log::info!("scan_conflict_markers done: {} documents", documents.len());
+            Ok(documents)
+        }
+        Ok(Err(e)) => Err(format!("scan_conflict_markers task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "é\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> branch\nend\n";
+
+    fn resolve(text: &str, accept: Accept) -> Result<String, String> {
+        apply(text, &[Resolution { index: 0, accept }])
+    }
+
+    #[test]
+    fn regions_are_found_in_utf16_offsets() {
+        let [region] = &find(TEXT)[..] else { panic!("not one region") };
+        assert_eq!((region.offset, region.length, region.line), (2, 48, 1));
+        assert_eq!((region.ours_label.as_str(), region.ours.as_str()), ("HEAD", "ours\n"));
+        assert_eq!((region.theirs_label.as_str(), region.theirs.as_str()), ("branch", "theirs\n"));
+        assert!(region.base.is_none());
+
+        let diff3 = find("<<<<<<<\nx\n||||||| base\no\n=======\ny\n>>>>>>> b\n");
+        assert_eq!(diff3[0].base.as_deref(), Some("o\n"));
+        assert_eq!(diff3[0].ours_label, "");
+        // an unfinished region is skipped, and so are lookalike lines
+        let unfinished = find("<<<<<<< a\nx\n<<<<<<< c\ny\n=======\nz\n>>>>>>> d\n<<<<<<<< e\n=======\n>>>>>>>\n");
+        assert_eq!(unfinished.len(), 1);
+        assert_eq!((unfinished[0].line, unfinished[0].ours.as_str()), (2, "y\n"));
+    }
+
+    #[test]
+    fn resolutions_replace_the_regions() {
+        assert_eq!(resolve(TEXT, Accept::Ours).unwrap(), "é\nours\nend\n");
+        assert_eq!(resolve(TEXT, Accept::Theirs).unwrap(), "é\ntheirs\nend\n");
+        assert_eq!(resolve(TEXT, Accept::Both).unwrap(), "é\nours\ntheirs\nend\n");
+        assert_eq!(apply(TEXT, &[]).unwrap(), TEXT);
+        assert_eq!(resolve("<<<<<<< a\nx\n=======\ny\n>>>>>>> b", Accept::Theirs).unwrap(), "y");
+        assert_eq!(resolve("no conflicts", Accept::Ours).unwrap_err(), "no conflict 0; there are 0");
+    }
+}
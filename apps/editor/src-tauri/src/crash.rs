@@ -0,0 +1,378 @@
+//! Panics used to end up only on stderr, which nobody sees in a bundled
+//! app. The hook installed here writes a crash report with the backtrace
+//! and a breadcrumb file with the last log lines before it, and on the
+//! next start [`last_crash`] tells the frontend about it so it can offer to
+//! open the report.
+//!
+//! A crash in native code, like a segfault in a library, can't be reported
+//! from the process it brings down, so a monitor, the app's executable
+//! started again with [`MONITOR_ARG`], waits for it: when the app crashes,
+//! the monitor writes a minidump of it, with a report pointing to it and
+//! the end of the log as breadcrumbs.
+
+use std::{
+    backtrace::Backtrace,
+    collections::VecDeque,
+    env, fs,
+    io::Write,
+    panic::{self, PanicHookInfo},
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    sync::{atomic::AtomicBool, Mutex, OnceLock, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use crash_handler::{CrashContext, CrashEventResult, CrashHandler};
+use minidumper::{Client, LoopAction, MinidumpBinary, Server, ServerHandler};
+
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+use time::macros::format_description;
+
+use crate::{error::BackendError, logs};
+
+/// Log lines kept for the breadcrumb file.
+const BREADCRUMBS: usize = 200;
+/// Holds the name of the last report the user has seen.
+const SEEN: &str = "seen";
+/// Starts the executable as the monitor, with the socket to listen on,
+/// the directory for reports and the log file, instead of the app.
+pub const MONITOR_ARG: &str = "--crash-monitor";
+/// How long the monitor gets to start listening.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const CONNECT_POLL: Duration = Duration::from_millis(50);
+
+static TRAIL: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+/// Kept for as long as the app runs: dropping the handler detaches it.
+static MONITOR: Mutex<Option<(CrashHandler, Child)>> = Mutex::new(None);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Report<'a> {
+    message: String,
+    /// `file:line:column` of the panic.
+    location: Option<String>,
+    thread: Option<&'a str>,
+    system: logs::SystemInfo,
+    backtrace: String,
+}
+
+/// Of a crash in native code, which has no backtrace but a minidump.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NativeReport {
+    message: &'static str,
+    location: Option<String>,
+    minidump: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Header {
+    message: String,
+    location: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashInfo {
+    /// The path of the report, a JSON file.
+    report: String,
+    /// The path of a file with the log lines before the crash.
+    breadcrumbs: String,
+    /// The path of the minidump, for a crash in native code.
+    minidump: Option<String>,
+    message: String,
+    location: Option<String>,
+    /// Seconds since the epoch.
+    time: u64,
+}
+
+/// Remembers a log line for the breadcrumbs of a crash.
+pub fn breadcrumb(line: &str) {
+    let mut trail = TRAIL.lock().unwrap_or_else(PoisonError::into_inner);
+    if trail.len() == BREADCRUMBS {
+        trail.pop_front();
+    }
+    trail.push_back(line.to_owned());
+}
+
+/// Like `crash-2025-01-31_14-25-01`.
+fn report_name() -> Result<String, String> {
+    let date = logs::now()
+        .format(format_description!("[year]-[month]-[day]_[hour]-[minute]-[second]"))
+        .map_err(|e| format!("format date: {e}"))?;
+    Ok(format!("crash-{date}"))
+}
+
+fn write_report(dir: &Path, app: &AppHandle, info: &PanicHookInfo) -> Result<PathBuf, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    let message = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|s| (*s).to_owned())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "panic".to_owned());
+    let thread = thread::current();
+    let report = Report {
+        message,
+        location: info.location().map(ToString::to_string),
+        thread: thread.name(),
+        system: logs::SystemInfo::new(app),
+        backtrace: Backtrace::force_capture().to_string(),
+    };
+    let path = dir.join(report_name()?).with_extension("json");
+    let data = serde_json::to_vec_pretty(&report).map_err(|e| format!("serialize report: {e}"))?;
+    fs::write(&path, data).map_err(|e| format!("write {}: {e}", path.display()))?;
+    // the panic may have happened with the trail locked
+    let trail = match TRAIL.try_lock() {
+        Ok(trail) => trail.iter().map(|line| format!("{line}\n")).collect(),
+        Err(_) => String::from("(the log was being written when it crashed)\n"),
+    };
+    let breadcrumbs = path.with_extension("log");
+    fs::write(&breadcrumbs, trail).map_err(|e| format!("write {}: {e}", breadcrumbs.display()))?;
+    Ok(path)
+}
+
+/// Writes a report to `dir` on every panic, then does what the previous
+/// hook did; and starts the monitor, which writes one for native crashes,
+/// with the end of `log_file`.
+pub fn install(dir: PathBuf, log_file: PathBuf, app: AppHandle) {
+    let _ = DIR.set(dir.clone());
+    let previous = panic::take_hook();
+    let reports = dir.clone();
+    panic::set_hook(Box::new(move |info| {
+        // best effort: a panic here would abort without the previous hook
+        let written = panic::catch_unwind(panic::AssertUnwindSafe(|| write_report(&reports, &app, info)));
+        match written {
+            Ok(Ok(path)) => log::error!("panic, report in {}: {info}", path.display()),
+            Ok(Err(e)) => log::error!("panic, no report: {e}: {info}"),
+            Err(_) => log::error!("panic, no report: writing it panicked: {info}"),
+        }
+        previous(info);
+    }));
+    // connecting takes a moment; the app doesn't wait for it
+    thread::spawn(move || match start_monitor(&dir, &log_file) {
+        Ok(monitor) => *MONITOR.lock().unwrap_or_else(PoisonError::into_inner) = Some(monitor),
+        Err(e) => log::warn!("crash monitor: {e}"),
+    });
+}
+
+fn start_monitor(dir: &Path, log_file: &Path) -> Result<(CrashHandler, Child), String> {
+    let socket = format!("emmm-crash-{}", std::process::id());
+    let exe = env::current_exe().map_err(|e| format!("current_exe: {e}"))?;
+    let mut child = Command::new(exe)
+        .arg(MONITOR_ARG)
+        .arg(&socket)
+        .arg(dir)
+        .arg(log_file)
+        .spawn()
+        .map_err(|e| format!("start: {e}"))?;
+    let mut waited = Duration::ZERO;
+    let client = loop {
+        match Client::with_name(socket.as_str()) {
+            Ok(client) => break client,
+            Err(_) if waited < CONNECT_TIMEOUT => {
+                thread::sleep(CONNECT_POLL);
+                waited += CONNECT_POLL;
+            }
+            Err(e) => {
+                let _ = child.kill();
+                return Err(format!("connect: {e}"));
+            }
+        }
+    };
+    // SAFETY: the callback only asks the monitor for a dump, which is what
+    // crash_handler expects of it, in a crashed process
+    let event = unsafe {
+        crash_handler::make_crash_event(move |context: &CrashContext| {
+            CrashEventResult::Handled(client.request_dump(context).is_ok())
+        })
+    };
+    let handler = CrashHandler::attach(event).map_err(|e| {
+        let _ = child.kill();
+        format!("attach: {e}")
+    })?;
+    // only the monitor may inspect the app, where that has to be allowed
+    #[cfg(target_os = "linux")]
+    handler.set_ptracer(Some(child.id()));
+    Ok((handler, child))
+}
+
+/// The monitor, when the executable was started as one; returns the exit
+/// code, or `None` to start the app as usual.
+pub fn run_monitor() -> Option<i32> {
+    let mut args = env::args_os().skip(1);
+    if args.next().is_none_or(|arg| arg != MONITOR_ARG) {
+        return None;
+    }
+    let (Some(socket), Some(dir), Some(log_file)) = (args.next(), args.next(), args.next()) else {
+        eprintln!("usage: {MONITOR_ARG} <socket> <dir> <log file>");
+        return Some(2);
+    };
+    let socket = socket.to_string_lossy().into_owned();
+    let mut server = match Server::with_name(socket.as_str()) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("crash monitor: listen on {socket}: {e}");
+            return Some(1);
+        }
+    };
+    let monitor = Monitor { dir: PathBuf::from(dir), log_file: PathBuf::from(log_file), name: Mutex::default() };
+    let stop = AtomicBool::new(false);
+    match server.run(Box::new(monitor), &stop, None) {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("crash monitor: {e}");
+            Some(1)
+        }
+    }
+}
+
+/// Writes the reports of the monitor.
+struct Monitor {
+    dir: PathBuf,
+    log_file: PathBuf,
+    /// Of the report being written.
+    name: Mutex<String>,
+}
+
+impl Monitor {
+    fn write_report(&self, minidump: &Path) -> Result<(), String> {
+        let name = self.name.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        let report = NativeReport {
+            message: "crashed in native code",
+            location: None,
+            minidump: minidump.to_string_lossy().into_owned(),
+        };
+        let path = self.dir.join(&name).with_extension("json");
+        let data = serde_json::to_vec_pretty(&report).map_err(|e| format!("serialize report: {e}"))?;
+        fs::write(&path, data).map_err(|e| format!("write {}: {e}", path.display()))?;
+        // the log as the app left it
+        let log = fs::read_to_string(&self.log_file).unwrap_or_default();
+        let lines: Vec<&str> = log.lines().collect();
+        let mut trail: String = lines[lines.len().saturating_sub(BREADCRUMBS)..].join("\n");
+        trail.push('\n');
+        let breadcrumbs = path.with_extension("log");
+        fs::write(&breadcrumbs, trail).map_err(|e| format!("write {}: {e}", breadcrumbs.display()))
+    }
+}
+
+impl ServerHandler for Monitor {
+    fn create_minidump_file(&self) -> Result<(fs::File, PathBuf), std::io::Error> {
+        fs::create_dir_all(&self.dir)?;
+        let name = report_name().map_err(std::io::Error::other)?;
+        let path = self.dir.join(&name).with_extension("dmp");
+        *self.name.lock().unwrap_or_else(PoisonError::into_inner) = name;
+        Ok((fs::File::create(&path)?, path))
+    }
+
+    fn on_minidump_created(&self, result: Result<MinidumpBinary, minidumper::Error>) -> LoopAction {
+        match result {
+            Ok(mut minidump) => {
+                let _ = minidump.file.flush();
+                if let Err(e) = self.write_report(&minidump.path) {
+                    eprintln!("crash monitor: {e}");
+                }
+            }
+            Err(e) => eprintln!("crash monitor: write minidump: {e}"),
+        }
+        LoopAction::Exit
+    }
+
+    fn on_message(&self, _kind: u32, _buffer: Vec<u8>) {}
+
+    fn on_client_disconnected(&self, clients: usize) -> LoopAction {
+        // the app exited without crashing
+        if clients == 0 {
+            LoopAction::Exit
+        } else {
+            LoopAction::Continue
+        }
+    }
+}
+
+/// The newest crash report, unless [`dismiss_crash`] was called for it.
+/// The frontend asks at startup.
+#[tauri::command]
+pub fn last_crash() -> Result<Option<CrashInfo>, BackendError> {
+    let Some(dir) = DIR.get() else { return Ok(None) };
+    let Ok(entries) = fs::read_dir(dir) else { return Ok(None) };
+    let newest = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .max();
+    let Some(report) = newest else { return Ok(None) };
+    let name = report.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    if fs::read_to_string(dir.join(SEEN)).is_ok_and(|seen| seen.trim() == name) {
+        return Ok(None);
+    }
+    let data = fs::read(&report).map_err(|e| format!("read {}: {e}", report.display()))?;
+    let header: Header =
+        serde_json::from_slice(&data).map_err(|e| format!("parse {}: {e}", report.display()))?;
+    let time = fs::metadata(&report)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    let minidump = Some(report.with_extension("dmp")).filter(|dump| dump.is_file());
+    Ok(Some(CrashInfo {
+        breadcrumbs: report.with_extension("log").to_string_lossy().into_owned(),
+        minidump: minidump.map(|dump| dump.to_string_lossy().into_owned()),
+        report: report.to_string_lossy().into_owned(),
+        message: header.message,
+        location: header.location,
+        time,
+    }))
+}
+
+/// Stops [`last_crash`] from returning the newest report.
+#[tauri::command]
+pub fn dismiss_crash() -> Result<(), BackendError> {
+    let Some(dir) = DIR.get() else { return Ok(()) };
+    let Some(info) = last_crash()? else { return Ok(()) };
+    let name = Path::new(&info.report).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let seen = dir.join(SEEN);
+    fs::write(&seen, name).map_err(|e| BackendError::io(format!("write {}", seen.display()), &e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_crashes_are_reported_until_dismissed() {
+        let dir = std::env::temp_dir().join(format!("emmm-crash-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).expect("create the reports folder");
+        let log_file = dir.join("app.log");
+        let log: String = (1..=250).map(|i| format!("line {i}\n")).collect();
+        fs::write(&log_file, log).expect("write the log");
+        DIR.set(dir.clone()).expect("no other test sets the reports folder");
+        assert!(last_crash().expect("no reports yet").is_none());
+
+        let monitor = Monitor { dir: dir.clone(), log_file, name: Mutex::new("crash-1".to_owned()) };
+        let minidump = dir.join("crash-1.dmp");
+        fs::write(&minidump, "MDMP").expect("write the minidump");
+        monitor.write_report(&minidump).expect("write the report");
+        let breadcrumbs = fs::read_to_string(dir.join("crash-1.log")).expect("read the breadcrumbs");
+        assert_eq!(breadcrumbs.lines().count(), BREADCRUMBS);
+        assert!(breadcrumbs.starts_with("line 51\n") && breadcrumbs.ends_with("line 250\n"));
+
+        let info = last_crash().expect("read the report").expect("a report");
+        assert_eq!(info.message, "crashed in native code");
+        assert_eq!(info.minidump.as_deref(), Some(minidump.to_string_lossy().as_ref()));
+        assert!(info.report.ends_with("crash-1.json"));
+        dismiss_crash().expect("dismiss the report");
+        assert!(last_crash().expect("read the reports").is_none());
+
+        *monitor.name.lock().expect("name lock poisoned") = "crash-2".to_owned();
+        monitor.write_report(&dir.join("crash-2.dmp")).expect("write another report");
+        let info = last_crash().expect("read the report").expect("the newer report");
+        assert!(info.report.ends_with("crash-2.json"));
+        assert_eq!(info.minidump, None);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
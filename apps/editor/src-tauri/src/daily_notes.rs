@@ -0,0 +1,169 @@
+//! Daily notes: one document per day, at a path made from the date with a
+//! pattern like `journal/[year]/[year]-[month]-[day].md`. Patterns are
+//! `time` format descriptions, not the moment-style formats snippet dates
+//! also take, because notes are found again by parsing their paths with
+//! them. Dates are worked out here, in the local time zone, so "today"
+//! doesn't depend on how the webview sees the clock.
+
+use std::{fs, path::Path};
+
+use serde::Serialize;
+use tauri::AppHandle;
+use tauri_plugin_clipboard_manager::ClipboardExt;
+use time::{
+    format_description::{self, OwnedFormatItem},
+    macros::format_description,
+    Date, OffsetDateTime,
+};
+
+use crate::{
+    error::BackendError,
+    templates::{Renderer, TemplateContext},
+    workspace,
+};
+
+const DEFAULT_PATTERN: &str = "journal/[year]-[month]-[day].md";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyNote {
+    path: String,
+    /// Like `2025-03-01`.
+    date: String,
+    /// Whether opening it created it.
+    created: bool,
+}
+
+fn pattern(pattern: Option<&str>) -> Result<OwnedFormatItem, String> {
+    let pattern = pattern.filter(|p| !p.is_empty()).unwrap_or(DEFAULT_PATTERN);
+    format_description::parse_owned::<2>(pattern).map_err(|e| format!("daily note pattern {pattern}: {e}"))
+}
+
+fn iso(date: Date) -> String {
+    date.format(format_description!("[year]-[month]-[day]")).unwrap_or_default()
+}
+
+fn parse_date(date: &str) -> Result<Date, String> {
+    Date::parse(date, format_description!("[year]-[month]-[day]")).map_err(|e| format!("date {date}: {e}"))
+}
+
+fn today() -> Date {
+    OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc()).date()
+}
+
+/// What a new note starts with: the template, with the date of the note
+/// for `{{date}}` and the like, or a heading.
+fn initial_content(
+    app: &AppHandle, root: &Path, path: &Path, template: Option<&str>, date: Date,
+) -> Result<String, String> {
+    let title = date
+        .format(format_description!("[weekday], [month repr:long] [day padding:none], [year]"))
+        .unwrap_or_default();
+    let Some(template) = template else { return Ok(format!("# {title}\n\n")) };
+    let template = root.join(template);
+    let source = fs::read_to_string(&template).map_err(|e| format!("read {}: {e}", template.display()))?;
+    let context = TemplateContext {
+        path: Some(path.to_string_lossy().into_owned()),
+        workspace: Some(root.to_string_lossy().into_owned()),
+        title: Some(title),
+        ..TemplateContext::default()
+    };
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc()).replace_date(date);
+    Ok(Renderer { clipboard: &|| app.clipboard().read_text().ok(), context: &context, now }.render(&source).text)
+}
+
+fn open(
+    app: &AppHandle, root: &Path, date: Option<&str>, template: Option<&str>, format: Option<&str>,
+) -> Result<DailyNote, String> {
+    let date = date.map_or_else(|| Ok(today()), parse_date)?;
+    let relative = date.format(&pattern(format)?).map_err(|e| format!("daily note path: {e}"))?;
+    let path = root.join(&relative);
+    let created = !path.exists();
+    if created {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("create {}: {e}", parent.display()))?;
+        }
+        workspace::write_atomic(&path, initial_content(app, root, &path, template, date)?.as_bytes())?;
+    }
+    Ok(DailyNote { path: path.to_string_lossy().into_owned(), date: iso(date), created })
+}
+
+fn list(root: &Path, from: Option<&str>, to: Option<&str>, format: Option<&str>) -> Result<Vec<DailyNote>, String> {
+    let format = pattern(format)?;
+    let from = from.map(parse_date).transpose()?.unwrap_or(Date::MIN);
+    let to = to.map(parse_date).transpose()?.unwrap_or(Date::MAX);
+    let mut notes: Vec<(Date, String)> = workspace::documents(root)?
+        .into_iter()
+        .filter_map(|path| {
+            let relative = workspace::relative_url_path(root, &path)?;
+            // only a path the pattern makes for its own date; this also
+            // rules out one whose parts disagree, like 2024/2023-01-01.md
+            let date = Date::parse(&relative, &format).ok()?;
+            let canonical = date.format(&format).ok()?;
+            (canonical == relative && (from..=to).contains(&date)).then(|| (date, path.to_string_lossy().into_owned()))
+        })
+        .collect();
+    notes.sort();
+    Ok(notes.into_iter().map(|(date, path)| DailyNote { path, date: iso(date), created: false }).collect())
+}
+
+/// The daily note for `date`, today by default, created from the
+/// `template` document of the workspace if it doesn't exist yet. Prompts
+/// in the template get their defaults.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn open_daily_note(
+    workspace: String, date: Option<String>, template: Option<String>, pattern: Option<String>, app: AppHandle,
+) -> Result<DailyNote, BackendError> {
+    Ok(open(&app, Path::new(&workspace), date.as_deref(), template.as_deref(), pattern.as_deref())?)
+}
+
+/// The daily notes of the workspace from `from` to `to`, both included
+/// and both optional, oldest first; the last before a date is the
+/// previous note.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn list_daily_notes(
+    workspace: String, from: Option<String>, to: Option<String>, pattern: Option<String>,
+) -> Result<Vec<DailyNote>, BackendError> {
+    let result = tokio::task::spawn_blocking(move || {
+        list(Path::new(&workspace), from.as_deref(), to.as_deref(), pattern.as_deref())
+    }).await;
+    match result {
+        Ok(Ok(notes)) => Ok(notes),
+        Ok(Err(e)) => Err(format!("list_daily_notes task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes_are_listed_by_the_date_of_their_path() {
+        let root = std::env::temp_dir().join(format!("emmm-daily-notes-test-{}", std::process::id()));
+        let pattern = Some("journal/[year]/[year]-[month]-[day].md");
+        for path in [
+            "journal/2025/2025-03-02.md",
+            "journal/2025/2025-03-01.md",
+            "journal/2024/2024-12-31.md",
+            "journal/2024/2023-01-01.md",
+            "journal/2025/notes.md",
+            "2025-03-03.md",
+        ] {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().expect("a parent folder")).expect("create the journal");
+            fs::write(&path, "").expect("write a note");
+        }
+        let dates = |from, to| -> Vec<String> {
+            list(&root, from, to, pattern).expect("list the notes").into_iter().map(|n| n.date).collect()
+        };
+        assert_eq!(dates(None, None), ["2024-12-31", "2025-03-01", "2025-03-02"]);
+        assert_eq!(dates(Some("2025-01-01"), Some("2025-03-01")), ["2025-03-01"]);
+        assert!(list(&root, None, None, None).expect("list with the default pattern").is_empty());
+        assert!(list(&root, Some("2025-02-30"), None, pattern).is_err());
+        assert!(list(&root, None, None, Some("[year")).is_err());
+        fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,185 @@
+//! A SQLite database in the app data, for what's kept about files and
+//! workspaces between runs and is too much for a JSON file, like clipboard
+//! captures and alt text suggested for images. It's opened on first use
+//! and brought up to date by [`MIGRATIONS`]; tables are added by one when
+//! something first needs them.
+//!
+//! Paths are stored absolute, as strings. Times are seconds since the
+//! epoch.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::Connection;
+use serde::Serialize;
+use tauri::State;
+
+use crate::{
+    error::BackendError,
+    workers::{self, Priority},
+};
+
+/// Each brings the schema from the version of its index to the next one;
+/// the version is kept in `PRAGMA user_version`. Never change one that's
+/// been released, add another.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE clipboard (
+        id INTEGER PRIMARY KEY,
+        kind TEXT NOT NULL,
+        captured INTEGER NOT NULL,
+        text TEXT,
+        -- of the saved image
+        path TEXT
+    );
+    ",
+    "
+    CREATE TABLE assets (
+        path TEXT PRIMARY KEY,
+        sha256 TEXT NOT NULL,
+        alt_text TEXT,
+        -- the captioning model's folder it's from
+        alt_text_model TEXT
+    );
+    CREATE INDEX assets_sha256 ON assets (sha256);
+    ",
+];
+
+const TABLES: &[&str] = &["clipboard", "assets"];
+
+pub struct Database {
+    path: PathBuf,
+    connection: Mutex<Option<Connection>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableInfo {
+    name: &'static str,
+    rows: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseInfo {
+    path: String,
+    /// Of the schema.
+    version: usize,
+    /// Of the file, in bytes.
+    size: u64,
+    tables: Vec<TableInfo>,
+}
+
+fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        log::info!("database: migrating from version {from}");
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", from + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+impl Database {
+    pub fn new(path: PathBuf) -> Self {
+        Database { path, connection: Mutex::default() }
+    }
+
+    fn open(path: &Path) -> Result<Connection, String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+        }
+        let mut connection = Connection::open(path).map_err(|e| format!("open {}: {e}", path.display()))?;
+        // reads don't block a write, and a locked database is waited for
+        connection
+            .execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON; PRAGMA busy_timeout = 5000;")
+            .map_err(|e| format!("configure {}: {e}", path.display()))?;
+        migrate(&mut connection).map_err(|e| format!("migrate {}: {e}", path.display()))?;
+        Ok(connection)
+    }
+
+    /// Runs `f` with the connection, opening the database first if needed.
+    /// Calls are serialized; keep them short.
+    pub fn with<T>(&self, f: impl FnOnce(&Connection) -> rusqlite::Result<T>) -> Result<T, String> {
+        let mut connection = self.connection.lock().expect("database lock poisoned");
+        if connection.is_none() {
+            *connection = Some(Database::open(&self.path)?);
+        }
+        f(connection.as_ref().expect("opened above")).map_err(|e| format!("database: {e}"))
+    }
+
+    /// Writes a consistent copy of the database to `to`.
+    pub fn backup(&self, to: &Path) -> Result<(), String> {
+        let to = to.to_str().ok_or_else(|| format!("not a UTF-8 path: {}", to.display()))?;
+        self.with(|connection| connection.execute("VACUUM INTO ?1", [to]).map(|_| ()))
+    }
+
+    /// Moves the write-ahead log into the database file, so it's complete
+    /// on its own; does nothing if it was never opened.
+    pub fn checkpoint(&self) -> Result<(), String> {
+        if self.connection.lock().expect("database lock poisoned").is_none() {
+            return Ok(());
+        }
+        self.with(|connection| connection.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);"))
+    }
+
+    /// Of the file, in bytes; 0 before it's created.
+    pub fn size(&self) -> u64 {
+        fs::metadata(&self.path).map_or(0, |m| m.len())
+    }
+
+    fn info(&self) -> Result<DatabaseInfo, String> {
+        let (version, tables) = self.with(|connection| {
+            let version = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+            let tables = TABLES
+                .iter()
+                .map(|&name| {
+                    let rows = connection.query_row(&format!("SELECT count(*) FROM {name}"), [], |row| row.get(0))?;
+                    Ok(TableInfo { name, rows })
+                })
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            Ok((version, tables))
+        })?;
+        Ok(DatabaseInfo {
+            path: self.path.to_string_lossy().into_owned(),
+            version,
+            size: self.size(),
+            tables,
+        })
+    }
+}
+
+/// Where the database is, its schema version and how much it holds, for
+/// diagnostics.
+#[tauri::command]
+pub async fn database_info(database: State<'_, Arc<Database>>) -> Result<DatabaseInfo, BackendError> {
+    let database = database.inner().clone();
+    match workers::run(Priority::Interactive, move || database.info()).await {
+        Ok(Ok(info)) => Ok(info),
+        Ok(Err(e)) => Err(format!("database_info task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_make_every_table() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection).unwrap();
+        // and again does nothing
+        migrate(&mut connection).unwrap();
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        for name in TABLES {
+            connection.query_row(&format!("SELECT count(*) FROM {name}"), [], |row| row.get::<_, u64>(0)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! `emmm://` links from other apps, like
+//! - `emmm://open?path=/notes/todo.md&line=12`, opening a document at a
+//!   line;
+//! - `emmm://new-note?title=Groceries&workspace=/notes`, creating a note
+//!   in the workspace once the user agreed to, since any app can send
+//!   links; without a workspace the window creates it in the one it has
+//!   open.
+//!
+//! Links come in through the deep link plugin: at launch, and while the
+//! app is running from the OS on macOS and from a second instance
+//! elsewhere. They're routed to the windows that [`watch_deep_links`];
+//! ones arriving before a window does wait for it.
+//!
+//! Documents the app is launched with, like when one is double-clicked,
+//! or that macOS asks it to open later, are opened the same way. Only one
+//! instance runs: a second one hands its links and documents to the first
+//! and exits.
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::Serialize;
+use tauri::{ipc::Channel, AppHandle, Manager, Url};
+use tauri_plugin_deep_link::DeepLinkExt;
+
+use crate::{error::BackendError, markdown, workspace};
+
+pub const SCHEME: &str = "emmm";
+
+/// What a window should do for a link.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", tag = "action")]
+pub enum DeepLink {
+    /// Open the document, at `line` (from 1) if given.
+    #[serde(rename_all = "camelCase")]
+    Open { path: String, line: Option<u32> },
+    /// Create a note in `workspace`, with [`create_note`] once the user
+    /// agreed, or in the workspace that's open.
+    #[serde(rename_all = "camelCase")]
+    NewNote { title: String, workspace: Option<String> },
+}
+
+/// Links not yet sent to a window.
+static PENDING: Mutex<Vec<DeepLink>> = Mutex::new(Vec::new());
+static WATCHERS: Mutex<Vec<Channel<DeepLink>>> = Mutex::new(Vec::new());
+
+fn query(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.into_owned())
+}
+
+/// Creates the note `title` in `root`, named after it, with a number
+/// added if that's taken. Names are claimed by creating the file, so a note
+/// made at the same time is never overwritten.
+fn new_note_in(root: &Path, title: &str) -> Result<PathBuf, String> {
+    if !root.is_dir() {
+        return Err(format!("workspace {} not found", root.display()));
+    }
+    let stem = match markdown::slug(title) {
+        slug if slug.is_empty() => "untitled".to_owned(),
+        slug => slug,
+    };
+    let content = if title.is_empty() { String::new() } else { format!("# {title}\n\n") };
+    let mut n = 1;
+    loop {
+        let path = root.join(if n == 1 { format!("{stem}.md") } else { format!("{stem}-{n}.md") });
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(content.as_bytes()).map_err(|e| format!("write {}: {e}", path.display()))?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(format!("create {}: {e}", path.display())),
+        }
+    }
+}
+
+/// `path` made absolute, if it's a document that can be opened.
+fn document(path: &Path) -> Result<PathBuf, String> {
+    let path = std::path::absolute(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let metadata = path.metadata().map_err(|e| format!("{}: {e}", path.display()))?;
+    if !metadata.is_file() || !workspace::is_document(&path) {
+        return Err(format!("{} is not a document", path.display()));
+    }
+    Ok(path)
+}
+
+fn open(path: &Path, line: Option<u32>) -> Result<DeepLink, String> {
+    Ok(DeepLink::Open { path: document(path)?.to_string_lossy().into_owned(), line })
+}
+
+fn route(url: &Url) -> Result<DeepLink, String> {
+    if url.scheme() != SCHEME {
+        return Err(format!("not an {SCHEME}:// link"));
+    }
+    // emmm://open parses with `open` as the host, emmm:open without one
+    let action = url.host_str().filter(|host| !host.is_empty()).unwrap_or_else(|| url.path().trim_matches('/'));
+    match action {
+        "open" => {
+            let path = query(url, "path").filter(|path| !path.is_empty()).ok_or("open needs a path")?;
+            let line = query(url, "line")
+                .map(|line| line.parse().ok().filter(|&line| line > 0).ok_or(format!("invalid line {line}")))
+                .transpose()?;
+            open(Path::new(&path), line)
+        }
+        "new-note" => {
+            let title = query(url, "title").unwrap_or_default();
+            let workspace = query(url, "workspace").filter(|workspace| !workspace.is_empty());
+            Ok(DeepLink::NewNote { title, workspace })
+        }
+        action => Err(format!("unknown action {action}")),
+    }
+}
+
+fn send(link: DeepLink) {
+    let mut watchers = WATCHERS.lock().expect("deep link lock poisoned");
+    watchers.retain(|channel| channel.send(link.clone()).is_ok());
+    if watchers.is_empty() {
+        PENDING.lock().expect("deep link lock poisoned").push(link);
+    }
+}
+
+/// Asks the window to create a note, like `emmm://new-note` without a
+/// workspace does.
+pub fn new_note(title: &str) {
+    send(DeepLink::NewNote { title: title.to_owned(), workspace: None });
+}
+
+/// Routes `urls`; ones that can't be are only logged, as there's no one
+/// to tell.
+pub fn handle(urls: &[Url]) {
+    // documents opened with the app on macOS come to the deep link plugin
+    // too, but they're handled by open_files
+    for url in urls.iter().filter(|url| url.scheme() != "file") {
+        match route(url) {
+            Ok(link) => {
+                log::info!("deeplink: {url} -> {link:?}");
+                send(link);
+            }
+            Err(e) => log::warn!("deeplink: {url}: {e}"),
+        }
+    }
+}
+
+fn open_document(path: &Path) {
+    match open(path, None) {
+        Ok(link) => {
+            log::info!("deeplink: opening {}", path.display());
+            send(link);
+        }
+        Err(e) => log::warn!("deeplink: {e}"),
+    }
+}
+
+/// Opens the documents among the command line arguments `args`, without
+/// the program, relative to `cwd`; links and options are skipped.
+fn open_documents(args: impl IntoIterator<Item = String>, cwd: &Path) {
+    for arg in args.into_iter().filter(|arg| !arg.starts_with('-') && !arg.contains("://")) {
+        open_document(&cwd.join(arg));
+    }
+}
+
+/// Opens the documents in `urls`, from the OS's file-open events on macOS,
+/// where they don't come as arguments; other URLs are skipped.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+pub fn open_files(urls: &[Url]) {
+    for url in urls.iter().filter(|url| url.scheme() == "file") {
+        match url.to_file_path() {
+            Ok(path) => open_document(&path),
+            Err(()) => log::warn!("deeplink: {url} is not a local file"),
+        }
+    }
+}
+
+/// Called in the running instance when another one is started with
+/// `args`; its links go to the deep link plugin.
+pub fn on_second_instance(app: &AppHandle, args: Vec<String>, cwd: &str) {
+    log::info!("deeplink: second instance started with {args:?}");
+    open_documents(args.into_iter().skip(1), Path::new(cwd));
+    if let Some(window) = app.get_webview_window("main") {
+        if let Err(e) = window.unminimize().and_then(|()| window.show()).and_then(|()| window.set_focus()) {
+            log::warn!("deeplink: focus window: {e}");
+        }
+    }
+}
+
+/// Routes the links and opens the documents the app was launched with,
+/// and the links that come later.
+pub fn init(app: &AppHandle) {
+    if let Ok(cwd) = std::env::current_dir() {
+        open_documents(std::env::args().skip(1), &cwd);
+    }
+    let deep_link = app.deep_link();
+    // installed apps get the scheme from their bundle; this is for
+    // development builds and AppImages
+    #[cfg(any(windows, target_os = "linux"))]
+    if let Err(e) = deep_link.register(SCHEME) {
+        log::warn!("deeplink: register {SCHEME}://: {e}");
+    }
+    deep_link.on_open_url(|event| handle(&event.urls()));
+    match deep_link.get_current() {
+        Ok(urls) => handle(&urls.unwrap_or_default()),
+        Err(e) => log::warn!("deeplink: get_current: {e}"),
+    }
+}
+
+/// Creates the note `title` in `workspace`, for a `new-note` link the user
+/// agreed to, and returns its path.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn create_note(workspace: String, title: String) -> Result<String, BackendError> {
+    log::info!("create_note: {title:?} in {workspace}");
+    let path = new_note_in(Path::new(&workspace), title.trim())?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Sends the links that arrived so far and every later one to `channel`.
+#[tauri::command]
+pub fn watch_deep_links(channel: Channel<DeepLink>) {
+    let mut watchers = WATCHERS.lock().expect("deep link lock poisoned");
+    for link in PENDING.lock().expect("deep link lock poisoned").drain(..) {
+        if channel.send(link).is_err() {
+            return;
+        }
+    }
+    watchers.push(channel);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route_str(url: &str) -> Result<DeepLink, String> {
+        route(&Url::parse(url).expect("a URL"))
+    }
+
+    #[test]
+    fn new_notes_wait_for_the_window() {
+        let link = route_str("emmm://new-note?title=Groceries&workspace=/nowhere").expect("a link");
+        let DeepLink::NewNote { title, workspace } = link else { panic!("not a new note: {link:?}") };
+        assert_eq!(title, "Groceries");
+        assert_eq!(workspace.as_deref(), Some("/nowhere"));
+        assert!(!Path::new("/nowhere").exists());
+
+        let link = route_str("emmm:new-note?workspace=").expect("a link");
+        assert!(matches!(link, DeepLink::NewNote { workspace: None, .. }));
+    }
+
+    #[test]
+    fn bad_links() {
+        assert!(route_str("https://open?path=/a.md").is_err());
+        assert!(route_str("emmm://open").is_err());
+        assert_eq!(route_str("emmm://open?path=/a.md&line=0").unwrap_err(), "invalid line 0");
+        assert_eq!(route_str("emmm://delete?path=/a.md").unwrap_err(), "unknown action delete");
+    }
+
+    #[test]
+    fn notes_get_free_names() {
+        let root = std::env::temp_dir().join(format!("emmm-deeplink-{}", std::process::id()));
+        std::fs::create_dir_all(&root).expect("a folder");
+        let first = new_note_in(&root, "Shopping List").expect("a note");
+        let second = new_note_in(&root, "Shopping List").expect("a note");
+        assert_eq!(first, root.join("shopping-list.md"));
+        assert_eq!(second, root.join("shopping-list-2.md"));
+        assert_eq!(std::fs::read_to_string(&first).expect("the note"), "# Shopping List\n\n");
+        std::fs::remove_dir_all(&root).expect("cleaned up");
+        assert!(new_note_in(&root, "x").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! A health check for the Help → Diagnostics screen: whether the app can
+//! write where it keeps things, which external tools it finds, and whether
+//! the servers it's set up to talk to answer.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::{error::BackendError, languagetool, net, process, settings};
+
+const TOOL_TIMEOUT: Duration = Duration::from_secs(10);
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tools used for some features only, so missing ones are warnings. The
+/// second element is the argument that prints the version.
+const TOOLS: &[(&str, &str)] = &[("pandoc", "--version"), ("ffmpeg", "-version")];
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum CheckCategory {
+    Storage,
+    Tools,
+    Network,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum CheckStatus {
+    Ok,
+    /// Works, but something that might be wanted doesn't.
+    Warning,
+    Failed,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    category: CheckCategory,
+    name: String,
+    status: CheckStatus,
+    /// What was found, or what went wrong.
+    detail: String,
+    millis: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsReport {
+    /// The worst status of the checks.
+    status: CheckStatus,
+    checks: Vec<Check>,
+}
+
+fn millis_since(started: Instant) -> u64 {
+    u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Creates, reads back and removes a file in `dir`, creating `dir` first
+/// if needed.
+fn check_writable(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    let probe = dir.join(format!(".emmm-diagnostics-{}", std::process::id()));
+    let result = fs::write(&probe, b"emmm")
+        .map_err(|e| format!("write {}: {e}", probe.display()))
+        .and_then(|()| fs::read(&probe).map_err(|e| format!("read {}: {e}", probe.display())))
+        .and_then(|data| if data == b"emmm" { Ok(()) } else { Err("read back something else".to_owned()) });
+    let removed = fs::remove_file(&probe).map_err(|e| format!("remove {}: {e}", probe.display()));
+    result.and(removed)
+}
+
+fn storage_checks(app: &AppHandle, workspace: Option<&str>) -> Vec<Check> {
+    let resolver = app.path();
+    let dirs: Vec<(&str, Result<PathBuf, tauri::Error>)> = vec![
+        ("App data", resolver.app_data_dir()),
+        ("Settings", resolver.app_config_dir()),
+        ("Cache", resolver.app_cache_dir()),
+        ("Logs", resolver.app_log_dir()),
+    ];
+    let workspace = workspace.map(|w| ("Workspace", Ok(PathBuf::from(w))));
+    workspace
+        .into_iter()
+        .chain(dirs)
+        .map(|(name, dir)| {
+            let started = Instant::now();
+            let checked = dir.map_err(|e| format!("not found: {e}")).and_then(|dir| check_writable(&dir).map(|()| dir));
+            let (status, detail) = match checked {
+                Ok(dir) => (CheckStatus::Ok, format!("{} is writable", dir.display())),
+                Err(e) => (CheckStatus::Failed, e),
+            };
+            let millis = millis_since(started);
+            Check { category: CheckCategory::Storage, name: name.to_owned(), status, detail, millis }
+        })
+        .collect()
+}
+
+async fn tool_check(name: &'static str, version_arg: &'static str) -> Check {
+    let started = Instant::now();
+    let (status, detail) = match process::find_program(name) {
+        None => (CheckStatus::Warning, "not found; features that need it are unavailable".to_owned()),
+        Some(program) => match process::run_piped(&program, &[version_arg.to_owned()], Vec::new(), TOOL_TIMEOUT).await {
+            Ok(output) => {
+                let output = String::from_utf8_lossy(&output);
+                let version = output.lines().next().unwrap_or_default().trim();
+                (CheckStatus::Ok, format!("{}: {version}", program.display()))
+            }
+            Err(e) => (CheckStatus::Failed, e),
+        },
+    };
+    Check { category: CheckCategory::Tools, name: name.to_owned(), status, detail, millis: millis_since(started) }
+}
+
+/// The servers the settings point to, by what they're for.
+fn endpoints() -> Vec<(String, String)> {
+    let settings = settings::get();
+    let mut endpoints = Vec::new();
+    let language_tool = match settings.language_tool_server.as_str() {
+        "" => languagetool::PUBLIC_SERVER.to_owned(),
+        server => server.to_owned(),
+    };
+    endpoints.push(("LanguageTool".to_owned(), language_tool));
+    if let Some(provider) = &settings.ai_provider {
+        endpoints.push(("AI provider".to_owned(), provider.base_url().to_owned()));
+    }
+    for webhook in &settings.webhooks {
+        endpoints.push(("Webhook".to_owned(), webhook.url().to_owned()));
+    }
+    endpoints
+}
+
+/// Any answer counts, even an error status: the server was reached, which
+/// is what's checked. Only `HEAD` is sent, so webhooks aren't triggered.
+async fn network_check(name: String, url: String) -> Check {
+    let started = Instant::now();
+    let result = async {
+        let client = net::quick_client(NETWORK_TIMEOUT, 5)?;
+        let response = client.head(&url).send().await.map_err(|e| format!("{url}: {e}"))?;
+        Ok::<_, String>(response.status())
+    }
+    .await;
+    let (status, detail) = match result {
+        Ok(status) => (CheckStatus::Ok, format!("{url}: HTTP {status}")),
+        Err(e) => (CheckStatus::Failed, e),
+    };
+    Check { category: CheckCategory::Network, name, status, detail, millis: millis_since(started) }
+}
+
+/// Checks that the app data directories and `workspace`, if given, are
+/// writable, that the external tools are there and run, and that the
+/// servers in the settings answer. Checks run at the same time; a failing
+/// one doesn't fail the command.
+#[tauri::command]
+pub async fn run_diagnostics(workspace: Option<String>, app: AppHandle) -> Result<DiagnosticsReport, BackendError> {
+    log::info!("run_diagnostics start");
+    let tools: Vec<_> =
+        TOOLS.iter().map(|&(name, arg)| tauri::async_runtime::spawn(tool_check(name, arg))).collect();
+    let network: Vec<_> =
+        endpoints().into_iter().map(|(name, url)| tauri::async_runtime::spawn(network_check(name, url))).collect();
+    let storage = tokio::task::spawn_blocking(move || storage_checks(&app, workspace.as_deref()))
+        .await
+        .map_err(|e| format!("tokio::task::spawn_blocking: {e}"))?;
+
+    let mut checks = storage;
+    for task in tools.into_iter().chain(network) {
+        checks.push(task.await.map_err(|e| format!("run_diagnostics task: {e}"))?);
+    }
+    let status = checks.iter().map(|check| check.status).max().unwrap_or(CheckStatus::Ok);
+    for check in checks.iter().filter(|check| check.status != CheckStatus::Ok) {
+        log::warn!("run_diagnostics: {}: {}", check.name, check.detail);
+    }
+    log::info!("run_diagnostics done");
+    Ok(DiagnosticsReport { status, checks })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[test]
+    fn folders_are_checked_by_writing_to_them() {
+        let dir = std::env::temp_dir().join(format!("emmm-diagnostics-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        check_writable(&dir.join("nested")).expect("a folder that can be created is writable");
+        assert_eq!(fs::read_dir(dir.join("nested")).expect("list the folder").count(), 0);
+        fs::write(dir.join("file"), "").expect("write a file");
+        assert!(check_writable(&dir.join("file")).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn missing_tools_are_warnings() {
+        let check = tauri::async_runtime::block_on(tool_check("emmm-no-such-tool", "--version"));
+        assert_eq!(check.status, CheckStatus::Warning);
+    }
+
+    #[test]
+    fn any_answer_means_the_server_is_reached() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listen");
+        let url = format!("http://{}/hook", listener.local_addr().expect("an address"));
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("a request");
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).expect("read the request line");
+            let mut line = String::new();
+            while reader.read_line(&mut line).expect("read a header") > 2 {
+                line.clear();
+            }
+            let response = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            reader.get_mut().write_all(response.as_bytes()).expect("answer");
+            request
+        });
+        let check = tauri::async_runtime::block_on(network_check("Webhook".to_owned(), url.clone()));
+        assert_eq!(check.status, CheckStatus::Ok);
+        assert_eq!(check.detail, format!("{url}: HTTP 404 Not Found"));
+        assert!(server.join().expect("the server").starts_with("HEAD /hook "));
+
+        let closed = TcpListener::bind("127.0.0.1:0").and_then(|l| l.local_addr()).expect("a free port");
+        let check = tauri::async_runtime::block_on(network_check("Webhook".to_owned(), format!("http://{closed}")));
+        assert_eq!(check.status, CheckStatus::Failed);
+    }
+}
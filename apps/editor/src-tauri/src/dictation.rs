@@ -0,0 +1,324 @@
+//! Voice dictation: records the microphone and transcribes it locally with
+//! whisper.cpp, built in, a few seconds at a time, so text shows up while
+//! the user is still speaking. The model is the one in the settings.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use serde::Deserialize;
+use tokio::sync::oneshot;
+use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+
+use crate::{
+    error::BackendError,
+    send, settings,
+    workers::{self, Priority},
+    BackendEvent, EventChannel,
+};
+
+/// What whisper.cpp expects.
+const WHISPER_RATE: u32 = 16_000;
+const DEFAULT_SEGMENT_SECONDS: u32 = 5;
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Segments quieter than this (RMS) are not worth transcribing.
+const SILENCE_LEVEL: f32 = 0.01;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DictationOptions {
+    /// A code like `en`; detected if not given.
+    language: Option<String>,
+    /// How much audio to transcribe at once.
+    segment_seconds: Option<u32>,
+}
+
+/// Set to stop the dictation in progress.
+static STOP: Mutex<Option<Arc<AtomicBool>>> = Mutex::new(None);
+/// The model last loaded, by path, which is kept for the next dictation.
+static MODEL: LazyLock<Mutex<Option<(PathBuf, WhisperContext)>>> = LazyLock::new(Mutex::default);
+
+/// Mono samples as they come from the microphone.
+pub(crate) struct Recording {
+    pub samples: Arc<Mutex<Vec<f32>>>,
+    pub sample_rate: u32,
+}
+
+fn input_stream<T>(
+    device: &cpal::Device, config: cpal::StreamConfig, samples: Arc<Mutex<Vec<f32>>>,
+) -> Result<cpal::Stream, String>
+where
+    T: cpal::SizedSample,
+    f32: cpal::FromSample<T>,
+{
+    let channels = usize::from(config.channels.max(1));
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                let mut samples = samples.lock().expect("samples lock poisoned");
+                for frame in data.chunks(channels) {
+                    let sum: f32 = frame.iter().map(|&s| cpal::Sample::to_sample::<f32>(s)).sum();
+                    #[allow(clippy::cast_precision_loss)]
+                    samples.push(sum / frame.len() as f32);
+                }
+            },
+            |e| log::warn!("dictation: input stream: {e}"),
+            None,
+        )
+        .map_err(|e| format!("build_input_stream: {e}"))
+}
+
+/// Records on a thread of its own (streams can't move between threads)
+/// until `stop` is set.
+pub(crate) async fn record(stop: Arc<AtomicBool>) -> Result<Recording, String> {
+    let (ready, started) = oneshot::channel();
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    let recorded = samples.clone();
+    thread::spawn(move || {
+        let start = || -> Result<(cpal::Stream, u32), String> {
+            let device = cpal::default_host()
+                .default_input_device()
+                .ok_or("no microphone found".to_owned())?;
+            let supported = device
+                .default_input_config()
+                .map_err(|e| format!("default_input_config: {e}"))?;
+            let config = supported.config();
+            let stream = match supported.sample_format() {
+                cpal::SampleFormat::F32 => input_stream::<f32>(&device, config, recorded),
+                cpal::SampleFormat::I16 => input_stream::<i16>(&device, config, recorded),
+                cpal::SampleFormat::U16 => input_stream::<u16>(&device, config, recorded),
+                cpal::SampleFormat::I32 => input_stream::<i32>(&device, config, recorded),
+                other => Err(format!("unsupported sample format {other}")),
+            }?;
+            stream.play().map_err(|e| format!("play: {e}"))?;
+            Ok((stream, supported.sample_rate()))
+        };
+        match start() {
+            Ok((stream, sample_rate)) => {
+                let _ = ready.send(Ok(sample_rate));
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(POLL_INTERVAL);
+                }
+                drop(stream);
+            }
+            Err(e) => {
+                let _ = ready.send(Err(e));
+            }
+        }
+    });
+    let sample_rate = started.await.map_err(|e| format!("recording thread: {e}"))??;
+    Ok(Recording { samples, sample_rate })
+}
+
+/// Where to end a segment of `length` samples: at the quietest moment of
+/// its last second, so words aren't cut in half.
+fn cut_point(samples: &[f32], length: usize, sample_rate: usize) -> usize {
+    let window = sample_rate / 10;
+    let search_from = length.saturating_sub(sample_rate);
+    (search_from..length.saturating_sub(window))
+        .step_by(window.max(1))
+        .min_by(|&a, &b| {
+            let energy = |at: usize| samples[at..at + window].iter().map(|s| s * s).sum::<f32>();
+            energy(a).total_cmp(&energy(b))
+        })
+        .map_or(length, |at| at + window / 2)
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub(crate) fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Linear resampling to [`WHISPER_RATE`].
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn resample(samples: &[f32], sample_rate: u32) -> Vec<f32> {
+    if sample_rate == WHISPER_RATE || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let step = f64::from(sample_rate) / f64::from(WHISPER_RATE);
+    let length = (samples.len() as f64 / step) as usize;
+    (0..length)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let next = samples.get(index + 1).copied().unwrap_or(samples[index]);
+            samples[index] * (1.0 - fraction) + next * fraction
+        })
+        .collect()
+}
+
+/// 16-bit mono PCM.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_size = u32::try_from(samples.len() * 2).unwrap_or(u32::MAX);
+    let mut out = Vec::with_capacity(44 + samples.len() * 2);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_size).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out
+}
+
+/// The text of `audio`, at [`WHISPER_RATE`], with the model at
+/// `model_path`, which is loaded the first time and when it changes.
+fn transcribe(model_path: &Path, language: Option<&str>, audio: &[f32]) -> Result<String, String> {
+    let mut model = MODEL.lock().expect("dictation model lock poisoned");
+    if model.as_ref().is_none_or(|(loaded, _)| loaded != model_path) {
+        *model = None;
+        let path = model_path.to_str().ok_or_else(|| format!("{} is not UTF-8", model_path.display()))?;
+        let context = WhisperContext::new_with_params(path, WhisperContextParameters::default())
+            .map_err(|e| format!("load {path}: {e}"))?;
+        *model = Some((model_path.to_owned(), context));
+    }
+    let (_, context) = model.as_ref().expect("a model");
+    let mut state = context.create_state().map_err(|e| format!("create_state: {e}"))?;
+    let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+    params.set_language(Some(language.unwrap_or("auto")));
+    params.set_print_progress(false);
+    params.set_print_realtime(false);
+    params.set_print_special(false);
+    params.set_print_timestamps(false);
+    state.full(params, audio).map_err(|e| format!("transcribe: {e}"))?;
+    let mut words = Vec::new();
+    for segment in state.as_iter() {
+        let text = segment.to_str_lossy().map_err(|e| format!("segment: {e}"))?;
+        words.extend(text.split_whitespace().map(str::to_owned));
+    }
+    Ok(words.join(" "))
+}
+
+/// Records and transcribes until [`stop_dictation`], sending the text of
+/// each segment in a [`BackendEvent::Chunk`] as soon as it's ready.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn start_dictation(
+    options: DictationOptions, channel: EventChannel,
+) -> Result<(), BackendError> {
+    log::info!("start_dictation start");
+    // a whisper.cpp model such as `ggml-base.en.bin`
+    let setting = settings::get().dictation_model_path;
+    let model_path = PathBuf::from(&setting);
+    if setting.is_empty() || !model_path.is_file() {
+        return Err(BackendError::not_found(format!("no dictation model at {setting:?}; set one in the settings")));
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let mut current = STOP.lock().expect("dictation lock poisoned");
+        if current.is_some() {
+            return Err(BackendError::invalid("dictation is already running"));
+        }
+        *current = Some(stop.clone());
+    }
+    let result = dictate(options, model_path, &stop, &channel).await;
+    stop.store(true, Ordering::Relaxed);
+    *STOP.lock().expect("dictation lock poisoned") = None;
+    result?;
+    send(&channel, BackendEvent::Done);
+    log::info!("start_dictation done");
+    Ok(())
+}
+
+async fn dictate(
+    options: DictationOptions, model_path: PathBuf, stop: &Arc<AtomicBool>, channel: &EventChannel,
+) -> Result<(), String> {
+    let recording = record(stop.clone()).await?;
+    let rate = recording.sample_rate as usize;
+    let segment_length = rate * options.segment_seconds.unwrap_or(DEFAULT_SEGMENT_SECONDS) as usize;
+    let options = Arc::new(options);
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let stopped = stop.load(Ordering::Relaxed);
+        let segment: Vec<f32> = {
+            let mut samples = recording.samples.lock().expect("samples lock poisoned");
+            if stopped {
+                std::mem::take(&mut *samples)
+            } else if samples.len() >= segment_length {
+                let cut = cut_point(&samples, segment_length, rate);
+                samples.drain(..cut).collect()
+            } else {
+                continue;
+            }
+        };
+        if rms(&segment) >= SILENCE_LEVEL {
+            let audio = resample(&segment, recording.sample_rate);
+            let (options, model_path) = (options.clone(), model_path.clone());
+            let text = workers::run(Priority::Interactive, move || {
+                transcribe(&model_path, options.language.as_deref(), &audio)
+            })
+            .await
+            .map_err(|e| format!("transcribe task: {e}"))??;
+            if !text.is_empty() {
+                send(channel, BackendEvent::Chunk { text: format!("{text} ") });
+            }
+        }
+        if stopped {
+            return Ok(());
+        }
+    }
+}
+
+/// Ends the dictation; the rest of the recording is still transcribed.
+#[tauri::command]
+pub fn stop_dictation() {
+    if let Some(stop) = &*STOP.lock().expect("dictation lock poisoned") {
+        stop.store(true, Ordering::Relaxed);
+        log::info!("stop_dictation");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_end_where_it_is_quiet() {
+        let rate = 1000;
+        let mut samples = vec![0.5; 3 * rate];
+        samples[2400..2500].fill(0.0);
+        assert_eq!(cut_point(&samples, 3 * rate, rate), 2450);
+        // too short to search
+        assert_eq!(cut_point(&samples, 50, rate), 50);
+        assert!((rms(&[0.5, -0.5]) - 0.5).abs() < f32::EPSILON);
+        assert!(rms(&[]).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn audio_is_resampled_and_encoded() {
+        let samples: Vec<f32> = (0..48u8).map(f32::from).collect();
+        let resampled = resample(&samples, 48_000);
+        assert_eq!(resampled.len(), 16);
+        assert!((resampled[1] - 3.0).abs() < 1e-4, "{resampled:?}");
+        assert_eq!(resample(&samples, WHISPER_RATE), samples);
+
+        let wav = wav(&[0.0, 1.0, -2.0], 16_000);
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 16_000);
+        assert_eq!(&wav[44..], [0, 0, 0xff, 0x7f, 0x01, 0x80]);
+    }
+}
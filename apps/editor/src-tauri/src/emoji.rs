@@ -0,0 +1,97 @@
+//! Emoji completion for `:short_code:`, searched here so the webview
+//! doesn't need the whole emoji list.
+
+use serde::{Deserialize, Serialize};
+
+use crate::completion::fuzzy_score;
+
+const DEFAULT_LIMIT: usize = 20;
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum SkinTone {
+    #[default]
+    Default,
+    Light,
+    MediumLight,
+    Medium,
+    MediumDark,
+    Dark,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmojiMatch {
+    /// In the requested skin tone if it has them.
+    emoji: String,
+    shortcode: String,
+    name: String,
+    has_skin_tones: bool,
+}
+
+impl From<SkinTone> for emojis::SkinTone {
+    fn from(tone: SkinTone) -> Self {
+        match tone {
+            SkinTone::Default => emojis::SkinTone::Default,
+            SkinTone::Light => emojis::SkinTone::Light,
+            SkinTone::MediumLight => emojis::SkinTone::MediumLight,
+            SkinTone::Medium => emojis::SkinTone::Medium,
+            SkinTone::MediumDark => emojis::SkinTone::MediumDark,
+            SkinTone::Dark => emojis::SkinTone::Dark,
+        }
+    }
+}
+
+fn search(query: &str, tone: SkinTone, limit: usize) -> Vec<EmojiMatch> {
+    let query = query.trim().trim_matches(':').to_lowercase();
+    let mut matches: Vec<(i64, EmojiMatch)> = emojis::iter()
+        // the toned variants are reached through their base emoji
+        .filter(|emoji| emoji.skin_tone().is_none_or(|t| t == emojis::SkinTone::Default))
+        .filter_map(|emoji| {
+            // the best matching of its shortcodes, or its name
+            let (score, shortcode) = emoji
+                .shortcodes()
+                .filter_map(|code| {
+                    let exact = if code == query { 100 } else { 0 };
+                    Some((fuzzy_score(&query, code)? + exact, code))
+                })
+                .max_by_key(|&(score, _)| score)
+                // matching the name only counts for less than any shortcode
+                .or_else(|| Some((fuzzy_score(&query, emoji.name())? - 20, emoji.shortcode()?)))?;
+            let toned = emoji.with_skin_tone(tone.into()).unwrap_or(emoji);
+            Some((score, EmojiMatch {
+                emoji: toned.as_str().to_owned(),
+                shortcode: shortcode.to_owned(),
+                name: emoji.name().to_owned(),
+                has_skin_tones: emoji.skin_tones().is_some(),
+            }))
+        })
+        .collect();
+    matches.sort_by(|(a, x), (b, y)| b.cmp(a).then_with(|| x.shortcode.len().cmp(&y.shortcode.len())));
+    matches.into_iter().take(limit).map(|(_, m)| m).collect()
+}
+
+/// Emoji whose shortcode or name fuzzily matches `query`, with or without
+/// the colons, the best first.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn search_emoji(query: String, skin_tone: Option<SkinTone>, limit: Option<usize>) -> Vec<EmojiMatch> {
+    search(&query, skin_tone.unwrap_or_default(), limit.unwrap_or(DEFAULT_LIMIT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortcodes_match_with_their_colons_and_tone() {
+        let found = search(":thumbsup:", SkinTone::Default, 5);
+        assert_eq!((found[0].emoji.as_str(), found[0].shortcode.as_str()), ("👍", "thumbsup"));
+        assert!(found[0].has_skin_tones);
+        assert_eq!(search("thumbsup", SkinTone::Dark, 1)[0].emoji, "👍🏿");
+        assert_eq!(search("smile", SkinTone::Default, 3).len(), 3);
+        assert!(search("qqqqqq", SkinTone::Default, 5).is_empty());
+        // toned variants aren't listed of their own
+        assert!(search("thumbsup", SkinTone::Default, 20).iter().all(|m| m.emoji.chars().count() == 1));
+    }
+}
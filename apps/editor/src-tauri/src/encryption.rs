@@ -0,0 +1,255 @@
+//! Client-side encryption for sync, so the server only ever stores
+//! ciphertext.
+//!
+//! Every file is encrypted with a key of its own, which is stored in the
+//! file wrapped by the workspace key. The workspace keys are kept on the
+//! server too, wrapped by a key derived from the passphrase. Changing the
+//! passphrase only rewraps the workspace keys, and rotating the workspace
+//! key only rewraps the file keys; the file contents stay as they are.
+//! File names are not encrypted.
+
+use std::{collections::BTreeMap, num::NonZeroU32};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    pbkdf2,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+const MAGIC: &[u8; 5] = b"EMME\x01";
+const KEY_LEN: usize = 32;
+const WRAPPED_KEY_LEN: usize = NONCE_LEN + KEY_LEN + 16;
+const HEADER_LEN: usize = MAGIC.len() + 4 + WRAPPED_KEY_LEN;
+/// OWASP's recommendation for PBKDF2-HMAC-SHA256.
+const ITERATIONS: u32 = 600_000;
+
+type Key = [u8; KEY_LEN];
+
+fn random<const N: usize>() -> Result<[u8; N], String> {
+    let mut bytes = [0; N];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| "no random numbers available".to_owned())?;
+    Ok(bytes)
+}
+
+fn cipher(key: &Key) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&CHACHA20_POLY1305, key).expect("valid key length"))
+}
+
+/// `nonce || ciphertext || tag`.
+fn seal(key: &Key, aad: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce = random::<NONCE_LEN>()?;
+    let mut buffer = data.to_vec();
+    cipher(key)
+        .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(aad), &mut buffer)
+        .map_err(|_| "encryption failed".to_owned())?;
+    let mut out = nonce.to_vec();
+    out.append(&mut buffer);
+    Ok(out)
+}
+
+fn open(key: &Key, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    let (nonce, ciphertext) = sealed.split_at_checked(NONCE_LEN)?;
+    let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+    let mut buffer = ciphertext.to_vec();
+    let length = cipher(key).open_in_place(nonce, Aad::from(aad), &mut buffer).ok()?.len();
+    buffer.truncate(length);
+    Some(buffer)
+}
+
+fn unwrap_key(key: &Key, aad: &[u8], wrapped: &[u8]) -> Option<Key> {
+    open(key, aad, wrapped)?.try_into().ok()
+}
+
+/// Whether `data` is in the format [`Keys::encrypt`] produces.
+pub(crate) fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WrappedKey {
+    id: u32,
+    /// Base64, wrapped by the passphrase key.
+    key: String,
+}
+
+/// The workspace keys as stored on the server.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Keyring {
+    version: u32,
+    /// Base64.
+    salt: String,
+    iterations: u32,
+    /// The key new files are encrypted with.
+    current: u32,
+    keys: Vec<WrappedKey>,
+}
+
+/// The workspace keys, unwrapped.
+pub(crate) struct Keys {
+    current: u32,
+    keys: BTreeMap<u32, Key>,
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8], iterations: u32) -> Key {
+    let mut key = [0; KEY_LEN];
+    let iterations = NonZeroU32::new(iterations).unwrap_or(NonZeroU32::MIN);
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, passphrase.as_bytes(), &mut key);
+    key
+}
+
+impl Keyring {
+    /// Unwraps the keys; fails if `passphrase` is wrong.
+    pub fn unlock(&self, passphrase: &str) -> Result<Keys, String> {
+        if self.version != 1 {
+            return Err(format!("unsupported keyring version {}", self.version));
+        }
+        let salt = STANDARD.decode(&self.salt).map_err(|e| format!("invalid salt: {e}"))?;
+        let wrapping = passphrase_key(passphrase, &salt, self.iterations);
+        let mut keys = BTreeMap::new();
+        for wrapped in &self.keys {
+            let data = STANDARD.decode(&wrapped.key).map_err(|e| format!("invalid key: {e}"))?;
+            let key = unwrap_key(&wrapping, &wrapped.id.to_le_bytes(), &data)
+                .ok_or("wrong passphrase".to_owned())?;
+            keys.insert(wrapped.id, key);
+        }
+        if !keys.contains_key(&self.current) {
+            return Err(format!("keyring is missing its current key {}", self.current));
+        }
+        Ok(Keys { current: self.current, keys })
+    }
+}
+
+impl Keys {
+    /// A fresh workspace key.
+    pub fn new() -> Result<Self, String> {
+        Ok(Keys { current: 1, keys: BTreeMap::from([(1, random()?)]) })
+    }
+
+    /// Wraps the keys with `passphrase`, under a new salt.
+    pub fn lock(&self, passphrase: &str) -> Result<Keyring, String> {
+        let salt = random::<16>()?;
+        let wrapping = passphrase_key(passphrase, &salt, ITERATIONS);
+        let keys = self
+            .keys
+            .iter()
+            .map(|(&id, key)| Ok(WrappedKey {
+                id,
+                key: STANDARD.encode(seal(&wrapping, &id.to_le_bytes(), key)?),
+            }))
+            .collect::<Result<_, String>>()?;
+        Ok(Keyring {
+            version: 1,
+            salt: STANDARD.encode(salt),
+            iterations: ITERATIONS,
+            current: self.current,
+            keys,
+        })
+    }
+
+    /// Adds a new key for new files; the old ones are kept to read the
+    /// files not rewrapped yet.
+    pub fn rotate(&mut self) -> Result<(), String> {
+        let id = self.keys.keys().max().copied().unwrap_or_default() + 1;
+        self.keys.insert(id, random()?);
+        self.current = id;
+        Ok(())
+    }
+
+    /// Forgets all keys but the current one.
+    pub fn retire_old(&mut self) {
+        self.keys.retain(|&id, _| id == self.current);
+    }
+
+    /// `MAGIC || key id || wrapped file key || sealed content`, with `path`
+    /// authenticated so the server can't swap files around.
+    pub fn encrypt(&self, path: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let file_key: Key = random()?;
+        let mut out = self.header(&file_key)?;
+        out.extend(seal(&file_key, path.as_bytes(), data)?);
+        Ok(out)
+    }
+
+    fn header(&self, file_key: &Key) -> Result<Vec<u8>, String> {
+        let id = self.current.to_le_bytes();
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&id);
+        header.extend(seal(&self.keys[&self.current], &id, file_key)?);
+        Ok(header)
+    }
+
+    fn file_key(&self, data: &[u8]) -> Result<(u32, Key), String> {
+        if !is_encrypted(data) || data.len() < HEADER_LEN {
+            return Err("not an encrypted file".to_owned());
+        }
+        let id_bytes: [u8; 4] = data[MAGIC.len()..MAGIC.len() + 4].try_into().expect("4 bytes");
+        let id = u32::from_le_bytes(id_bytes);
+        let key = self.keys.get(&id).ok_or(format!("encrypted with unknown key {id}"))?;
+        let file_key = unwrap_key(key, &id_bytes, &data[MAGIC.len() + 4..HEADER_LEN])
+            .ok_or("corrupt file key".to_owned())?;
+        Ok((id, file_key))
+    }
+
+    pub fn decrypt(&self, path: &str, data: &[u8]) -> Result<Vec<u8>, String> {
+        let (_, file_key) = self.file_key(data)?;
+        open(&file_key, path.as_bytes(), &data[HEADER_LEN..])
+            .ok_or("decryption failed; the file was modified or moved".to_owned())
+    }
+
+    /// `data` with its file key wrapped by the current key, or `None` if it
+    /// already is.
+    pub fn rewrap(&self, data: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let (id, file_key) = self.file_key(data)?;
+        if id == self.current {
+            return Ok(None);
+        }
+        let mut out = self.header(&file_key)?;
+        out.extend_from_slice(&data[HEADER_LEN..]);
+        Ok(Some(out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_only_open_at_their_path() {
+        let keys = Keys::new().expect("keys");
+        let sealed = keys.encrypt("notes/a.md", b"secret").expect("encrypted");
+        assert!(is_encrypted(&sealed) && !is_encrypted(b"secret"));
+        assert_eq!(keys.decrypt("notes/a.md", &sealed).expect("decrypted"), b"secret");
+        assert!(keys.decrypt("notes/b.md", &sealed).is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().expect("a tag") ^= 1;
+        assert!(keys.decrypt("notes/a.md", &tampered).is_err());
+        assert_eq!(keys.decrypt("notes/a.md", b"EMME\x01").err().as_deref(), Some("not an encrypted file"));
+        assert!(Keys::new().expect("keys").decrypt("notes/a.md", &sealed).is_err());
+    }
+
+    #[test]
+    fn rotating_keeps_old_files_readable_until_rewrapped() {
+        let mut keys = Keys::new().expect("keys");
+        let old = keys.encrypt("a.md", b"old").expect("encrypted");
+        keys.rotate().expect("rotated");
+        let new = keys.encrypt("a.md", b"new").expect("encrypted");
+        assert_eq!(keys.decrypt("a.md", &old).expect("decrypted"), b"old");
+        assert!(keys.rewrap(&new).expect("checked").is_none());
+        let rewrapped = keys.rewrap(&old).expect("rewrapped").expect("a new header");
+        assert_eq!(rewrapped[HEADER_LEN..], old[HEADER_LEN..]);
+
+        keys.retire_old();
+        assert_eq!(keys.decrypt("a.md", &rewrapped).expect("decrypted"), b"old");
+        assert_eq!(keys.decrypt("a.md", &old).err().as_deref(), Some("encrypted with unknown key 1"));
+
+        let keyring = keys.lock("correct horse").expect("locked");
+        let keyring: Keyring =
+            serde_json::from_str(&serde_json::to_string(&keyring).expect("json")).expect("keyring");
+        let unlocked = keyring.unlock("correct horse").expect("unlocked");
+        assert_eq!(unlocked.decrypt("a.md", &new).expect("decrypted"), b"new");
+        assert_eq!(keyring.unlock("wrong horse").err().as_deref(), Some("wrong passphrase"));
+    }
+}
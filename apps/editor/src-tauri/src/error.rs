@@ -0,0 +1,185 @@
+//! The error every command returns and every [`crate::BackendEvent::Failed`]
+//! carries. Helpers still build their errors as `"step: cause"` strings;
+//! at the command boundary those become a [`BackendError`] with a code the
+//! frontend can branch on and localize. Commands give the code where they
+//! build an error, like [`BackendError::io`] for a file they failed to
+//! read; a chain from a helper only gets one from an OS error at its end.
+
+use std::{fmt, io};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{i18n, jobs};
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorCode {
+    /// A file, entry or secret that isn't there.
+    NotFound,
+    PermissionDenied,
+    AlreadyExists,
+    /// Bad input: a pattern, URL, option or document that can't be used.
+    Invalid,
+    /// A request that couldn't be sent, or that the server refused.
+    Network,
+    Timeout,
+    Cancelled,
+    /// Reading or writing files otherwise failed.
+    Io,
+    /// A command or tool that isn't installed, or exited with an error.
+    External,
+    Internal,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BackendError {
+    code: ErrorCode,
+    /// What was being done, like `read /notes/a.md` or `push`.
+    step: Option<String>,
+    /// The cause, or without a step, the whole chain.
+    message: String,
+    /// What the code means, in the user's language, if there's a message
+    /// for it.
+    summary: Option<String>,
+}
+
+impl ErrorCode {
+    fn summary(self) -> Option<String> {
+        match serde_json::to_value(self) {
+            Ok(Value::String(name)) => i18n::lookup(&format!("error.{name}")),
+            _ => None,
+        }
+    }
+}
+
+impl BackendError {
+    /// `step` failed with `cause`.
+    pub fn new(code: ErrorCode, step: impl Into<String>, cause: impl fmt::Display) -> BackendError {
+        BackendError {
+            code,
+            step: Some(step.into()),
+            message: cause.to_string(),
+            summary: code.summary(),
+        }
+    }
+
+    fn message(code: ErrorCode, message: impl Into<String>) -> BackendError {
+        BackendError { code, step: None, message: message.into(), summary: code.summary() }
+    }
+
+    /// With the code of the kind of `error`.
+    pub fn io(step: impl Into<String>, error: &io::Error) -> BackendError {
+        BackendError::new(io_code(error.kind()), step, error)
+    }
+
+    pub fn invalid(message: impl Into<String>) -> BackendError {
+        BackendError::message(ErrorCode::Invalid, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> BackendError {
+        BackendError::message(ErrorCode::NotFound, message)
+    }
+
+    pub fn external(message: impl Into<String>) -> BackendError {
+        BackendError::message(ErrorCode::External, message)
+    }
+
+    pub fn network(step: impl Into<String>, cause: impl fmt::Display) -> BackendError {
+        BackendError::new(ErrorCode::Network, step, cause)
+    }
+}
+
+fn io_code(kind: io::ErrorKind) -> ErrorCode {
+    match kind {
+        io::ErrorKind::NotFound => ErrorCode::NotFound,
+        io::ErrorKind::PermissionDenied => ErrorCode::PermissionDenied,
+        io::ErrorKind::AlreadyExists => ErrorCode::AlreadyExists,
+        io::ErrorKind::TimedOut => ErrorCode::Timeout,
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => ErrorCode::Invalid,
+        _ => ErrorCode::Io,
+    }
+}
+
+/// The code of a chain, from what's certain about it: an OS error at its
+/// end, as `io::Error` puts it, or a cancelled job. Anything else is
+/// internal; where it's known better, the error is built with a code.
+fn chain_code(chain: &str) -> ErrorCode {
+    if chain == jobs::CANCELLED || chain.ends_with(&format!(": {}", jobs::CANCELLED)) {
+        return ErrorCode::Cancelled;
+    }
+    let os_error = chain
+        .strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once("(os error "))
+        .and_then(|(_, number)| number.parse::<i32>().ok());
+    os_error.map_or(ErrorCode::Internal, |number| io_code(io::Error::from_raw_os_error(number).kind()))
+}
+
+impl From<String> for BackendError {
+    /// Of a `"step: cause"` chain from the helpers, kept whole, since a
+    /// cause can have `: ` in it too.
+    fn from(chain: String) -> BackendError {
+        BackendError::message(chain_code(&chain), chain)
+    }
+}
+
+impl From<&str> for BackendError {
+    fn from(chain: &str) -> BackendError {
+        BackendError::from(chain.to_owned())
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.step {
+            Some(step) => write!(f, "{step}: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(chain: &str) -> ErrorCode {
+        BackendError::from(chain).code
+    }
+
+    #[test]
+    fn chains_are_internal_without_a_marker() {
+        assert_eq!(code("thread pool: already shut down"), ErrorCode::Internal);
+        assert_eq!(code("request: unknown field"), ErrorCode::Internal);
+    }
+
+    #[test]
+    fn chains_are_kept_whole() {
+        let error = BackendError::from("export: render note.md: line 3: unexpected }");
+        assert_eq!(error.step, None);
+        assert_eq!(error.to_string(), "export: render note.md: line 3: unexpected }");
+    }
+
+    #[test]
+    fn os_errors_give_their_kind() {
+        let chain = format!("read /a.md: {}", io::Error::from_raw_os_error(2));
+        assert_eq!(code(&chain), ErrorCode::NotFound);
+        assert_eq!(code("read /a.md: (os error x)"), ErrorCode::Internal);
+    }
+
+    #[test]
+    fn cancelled_jobs() {
+        assert_eq!(code(jobs::CANCELLED), ErrorCode::Cancelled);
+        assert_eq!(code("export task: cancelled"), ErrorCode::Cancelled);
+        assert_eq!(code("export: not cancelled yet"), ErrorCode::Internal);
+    }
+
+    #[test]
+    fn io_errors_give_their_kind() {
+        let error = BackendError::io("write /a.md", &io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(error.code, ErrorCode::PermissionDenied);
+        assert_eq!(error.step.as_deref(), Some("write /a.md"));
+    }
+}
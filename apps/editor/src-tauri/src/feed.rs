@@ -0,0 +1,271 @@
+use std::{cmp::Reverse, fs, path::Path};
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::Deserialize;
+use time::{
+    format_description::well_known::{Rfc2822, Rfc3339},
+    OffsetDateTime,
+};
+
+use crate::{
+    error::BackendError,
+    frontmatter::{self, Frontmatter},
+    jobs::{self, JobKind},
+    markdown::{self, escape},
+    workers::{self, Priority},
+    workspace,
+};
+
+const DEFAULT_LIMIT: usize = 20;
+const SUMMARY_LENGTH: usize = 280;
+/// Characters escaped in a path segment of a post's URL.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'<').add(b'>')
+    .add(b'?').add(b'[').add(b']').add(b'\\').add(b'^').add(b'`').add(b'{')
+    .add(b'|').add(b'}');
+
+#[derive(Clone, Copy, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FeedFormat {
+    #[default]
+    Atom,
+    Rss,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedOptions {
+    #[serde(default)]
+    format: FeedFormat,
+    /// Where the published site lives; post URLs are built from it and
+    /// the post's path with the extension replaced by `.html`.
+    base_url: String,
+    title: String,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    author: Option<String>,
+    #[serde(default)]
+    limit: Option<usize>,
+    /// Include the rendered post instead of just a summary.
+    #[serde(default)]
+    full_content: bool,
+}
+
+impl FeedOptions {
+    pub(crate) fn new(base_url: String, title: String) -> Self {
+        FeedOptions {
+            format: FeedFormat::Atom,
+            base_url,
+            title,
+            description: None,
+            author: None,
+            limit: None,
+            full_content: false,
+        }
+    }
+}
+
+pub(crate) struct Post {
+    pub title: String,
+    pub date: OffsetDateTime,
+    pub url: String,
+    pub summary: String,
+    pub content: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Posts without a date in their frontmatter may still carry one in their
+/// file name, Jekyll style: `2024-05-01-hello.md`.
+fn date_from_file_name(path: &Path) -> Option<OffsetDateTime> {
+    let name = path.file_name()?.to_str()?;
+    frontmatter::parse_date(name.get(..10)?)
+}
+
+/// The first paragraph as plain text, cut at a word boundary.
+fn summarize(body: &str) -> String {
+    let paragraph = body
+        .split("\n\n")
+        .map(str::trim)
+        .find(|p| !p.is_empty() && !p.starts_with('#') && !p.starts_with("```"))
+        .unwrap_or("");
+    let plain: String = paragraph
+        .chars()
+        .filter(|c| !matches!(c, '*' | '_' | '`' | '[' | ']'))
+        .map(|c| if c == '\n' { ' ' } else { c })
+        .collect();
+    if plain.chars().count() <= SUMMARY_LENGTH {
+        return plain;
+    }
+    let cut: String = plain.chars().take(SUMMARY_LENGTH).collect();
+    match cut.rfind(' ') {
+        Some(i) => format!("{}…", &cut[..i]),
+        None => format!("{cut}…"),
+    }
+}
+
+/// Where the site puts the document at `relative`, a `/`-separated path.
+pub(crate) fn post_url(base_url: &str, relative: &str) -> String {
+    let stem = relative.rsplit_once('.').map_or(relative, |(s, _)| s);
+    let path: Vec<_> = stem.split('/').map(|s| utf8_percent_encode(s, SEGMENT).to_string()).collect();
+    format!("{}/{}.html", base_url.trim_end_matches('/'), path.join("/"))
+}
+
+/// Reads every dated document in `folder`, newest first.
+pub(crate) fn collect_posts(
+    folder: &Path, base_url: &str, full_content: bool
+) -> Result<Vec<Post>, String> {
+    let mut posts = Vec::new();
+    let documents = workspace::documents(folder)?;
+    for (i, path) in documents.iter().enumerate() {
+        jobs::checkpoint()?;
+        jobs::progress(i, documents.len(), &path.to_string_lossy());
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("read {}: {e}", path.display()))?;
+        let (meta, body) = Frontmatter::of(&source);
+        let Some(date) = meta.date("date").or_else(|| date_from_file_name(path)) else {
+            continue;
+        };
+        if meta.get("draft") == Some("true") {
+            continue;
+        }
+        let relative = workspace::relative_url_path(folder, path).unwrap_or_default();
+        let title = meta.get("title").map_or_else(
+            || path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+            str::to_owned);
+        let content = full_content.then(|| markdown::to_html(body, &markdown::RenderOptions {
+            base_dir: path.parent(),
+            ..Default::default()
+        }));
+        posts.push(Post {
+            title,
+            date,
+            url: post_url(base_url, &relative),
+            summary: meta.get("summary")
+                .or_else(|| meta.get("description"))
+                .map_or_else(|| summarize(body), str::to_owned),
+            content,
+            tags: meta.list("tags"),
+        });
+    }
+    posts.sort_by_key(|p| Reverse(p.date));
+    Ok(posts)
+}
+
+fn format_date(date: OffsetDateTime, format: FeedFormat) -> String {
+    match format {
+        FeedFormat::Atom => date.format(&Rfc3339),
+        FeedFormat::Rss => date.format(&Rfc2822),
+    }
+    .unwrap_or_default()
+}
+
+fn atom(posts: &[Post], options: &FeedOptions) -> String {
+    let base = options.base_url.trim_end_matches('/');
+    let updated = posts.first().map_or(OffsetDateTime::UNIX_EPOCH, |p| p.date);
+    let mut out = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+         <title>{title}</title>\n<id>{base}/</id>\n\
+         <link href=\"{base}/\"/>\n<link rel=\"self\" href=\"{base}/atom.xml\"/>\n\
+         <updated>{updated}</updated>\n",
+        title = escape(&options.title),
+        base = escape(base),
+        updated = format_date(updated, FeedFormat::Atom));
+    if let Some(description) = &options.description {
+        out.push_str(&format!("<subtitle>{}</subtitle>\n", escape(description)));
+    }
+    // atom requires an author, either on the feed or on every entry
+    out.push_str(&format!(
+        "<author><name>{}</name></author>\n",
+        escape(options.author.as_deref().unwrap_or(&options.title))));
+    for post in posts {
+        out.push_str(&format!(
+            "<entry>\n<title>{}</title>\n<id>{}</id>\n<link href=\"{1}\"/>\n\
+             <updated>{}</updated>\n<summary>{}</summary>\n",
+            escape(&post.title), escape(&post.url),
+            format_date(post.date, FeedFormat::Atom), escape(&post.summary)));
+        for tag in &post.tags {
+            out.push_str(&format!("<category term=\"{}\"/>\n", escape(tag)));
+        }
+        if let Some(content) = &post.content {
+            out.push_str(&format!("<content type=\"html\">{}</content>\n", escape(content)));
+        }
+        out.push_str("</entry>\n");
+    }
+    out.push_str("</feed>\n");
+    out
+}
+
+fn rss(posts: &[Post], options: &FeedOptions) -> String {
+    let base = options.base_url.trim_end_matches('/');
+    let mut out = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n<channel>\n\
+         <title>{}</title>\n<link>{}/</link>\n<description>{}</description>\n\
+         <atom:link href=\"{1}/rss.xml\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+        escape(&options.title), escape(base),
+        escape(options.description.as_deref().unwrap_or(&options.title)));
+    if let Some(post) = posts.first() {
+        out.push_str(&format!(
+            "<lastBuildDate>{}</lastBuildDate>\n", format_date(post.date, FeedFormat::Rss)));
+    }
+    for post in posts {
+        out.push_str(&format!(
+            "<item>\n<title>{}</title>\n<link>{}</link>\n<guid>{1}</guid>\n\
+             <pubDate>{}</pubDate>\n<description>{}</description>\n",
+            escape(&post.title), escape(&post.url),
+            format_date(post.date, FeedFormat::Rss),
+            escape(post.content.as_deref().unwrap_or(&post.summary))));
+        for tag in &post.tags {
+            out.push_str(&format!("<category>{}</category>\n", escape(tag)));
+        }
+        out.push_str("</item>\n");
+    }
+    out.push_str("</channel>\n</rss>\n");
+    out
+}
+
+pub(crate) fn build_feed(folder: &Path, options: &FeedOptions) -> Result<String, String> {
+    let mut posts = collect_posts(folder, &options.base_url, options.full_content)?;
+    posts.truncate(options.limit.unwrap_or(DEFAULT_LIMIT));
+    Ok(match options.format {
+        FeedFormat::Atom => atom(&posts, options),
+        FeedFormat::Rss => rss(&posts, options),
+    })
+}
+
+/// Generates an Atom or RSS feed from the dated documents in `folder`.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn generate_feed(folder: String, options: FeedOptions) -> Result<String, BackendError> {
+    log::info!("generate_feed start");
+    let job = jobs::start(JobKind::Export, Priority::Interactive, &folder);
+    let result = workers::run(Priority::Interactive, move || {
+        job.run(|| build_feed(Path::new(&folder), &options))
+    }).await;
+
+    match result {
+        Ok(Ok(xml)) => {
+            log::info!("generate_feed done");
+            Ok(xml)
+        }
+        Ok(Err(e)) => Err(format!("generate_feed task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn post_urls_escape_each_segment() {
+        assert_eq!(post_url("https://example.com/", "posts/a.md"), "https://example.com/posts/a.html");
+        assert_eq!(
+            post_url("https://example.com", "my posts/50% #1?.md"),
+            "https://example.com/my%20posts/50%25%20%231%3F.html",
+        );
+        assert_eq!(post_url("https://example.com", "日记.md"), "https://example.com/%E6%97%A5%E8%AE%B0.html");
+    }
+}
@@ -0,0 +1,109 @@
+//! Switches for experimental subsystems, so they can ship turned off and be
+//! turned on per user. The defaults are compiled in; the settings only keep
+//! what the user changed, under `flags`.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{error::BackendError, settings};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Flag {
+    /// WebDAV sync.
+    Sync,
+    /// Completions from AI providers.
+    Ai,
+    /// Editing together with others; nothing uses it yet.
+    Collaboration,
+}
+
+/// Every flag, with whether it's on by default and what it's for.
+const FLAGS: &[(Flag, bool, &str)] = &[
+    (Flag::Sync, true, "Sync workspaces with a WebDAV server"),
+    (Flag::Ai, true, "Completions from an AI provider"),
+    (Flag::Collaboration, false, "Edit documents together with others"),
+];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlagState {
+    flag: Flag,
+    enabled: bool,
+    default: bool,
+    description: &'static str,
+}
+
+impl Flag {
+    /// As in the settings.
+    fn name(self) -> String {
+        match serde_json::to_value(self) {
+            Ok(Value::String(name)) => name,
+            _ => unreachable!("flags serialize to strings"),
+        }
+    }
+
+    fn default(self) -> bool {
+        FLAGS.iter().find(|&&(flag, _, _)| flag == self).is_some_and(|&(_, default, _)| default)
+    }
+}
+
+fn state(flags: &BTreeMap<String, bool>) -> Vec<FlagState> {
+    FLAGS
+        .iter()
+        .map(|&(flag, default, description)| FlagState {
+            flag,
+            enabled: flags.get(&flag.name()).copied().unwrap_or(default),
+            default,
+            description,
+        })
+        .collect()
+}
+
+pub fn enabled(flag: Flag) -> bool {
+    settings::get().flags.get(&flag.name()).copied().unwrap_or_else(|| flag.default())
+}
+
+/// Fails if `flag` is turned off, for the commands it gates.
+pub fn require(flag: Flag) -> Result<(), String> {
+    if enabled(flag) {
+        Ok(())
+    } else {
+        Err(format!("{} is turned off; it can be turned on in the settings", flag.name()))
+    }
+}
+
+#[tauri::command]
+pub fn list_flags() -> Vec<FlagState> {
+    state(&settings::get().flags)
+}
+
+/// Turns `flag` on or off for this user, or back to its default if
+/// `enabled` is `None`; returns all the flags.
+#[tauri::command]
+pub fn set_flag(flag: Flag, enabled: Option<bool>) -> Result<Vec<FlagState>, BackendError> {
+    log::info!("set_flag: {} {enabled:?}", flag.name());
+    let mut flags = settings::get().flags;
+    match enabled {
+        Some(enabled) => flags.insert(flag.name(), enabled),
+        None => flags.remove(&flag.name()),
+    };
+    let value = serde_json::to_value(flags).map_err(|e| format!("serialize: {e}"))?;
+    Ok(state(&settings::update("flags", Some(value))?.flags))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_flags_override_the_defaults() {
+        assert_eq!(Flag::Collaboration.name(), "collaboration");
+        assert!(Flag::Sync.default() && !Flag::Collaboration.default());
+        let changed = BTreeMap::from([("sync".to_owned(), false), ("collaboration".to_owned(), true)]);
+        let enabled: Vec<_> = state(&changed).iter().map(|s| (s.flag, s.enabled, s.default)).collect();
+        assert_eq!(enabled, [(Flag::Sync, false, true), (Flag::Ai, true, true), (Flag::Collaboration, true, false)]);
+    }
+}
@@ -0,0 +1,392 @@
+//! Tidying footnotes: numbered footnotes are renumbered in the order they
+//! are first referenced, and every definition moves to the end of the
+//! document or of the section that first refers to it. Named footnotes
+//! like `[^source]` keep their names but move the same way.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FootnotePlacement {
+    #[default]
+    End,
+    /// Before the heading after the first reference.
+    Section,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FootnoteOptions {
+    placement: FootnotePlacement,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FootnoteReport {
+    text: String,
+    /// Labels referenced without a definition; left as they are.
+    missing: Vec<String>,
+    /// Labels defined but never referenced; their definitions are kept at
+    /// the end.
+    unreferenced: Vec<String>,
+    /// Labels defined more than once. The first definition is used and the
+    /// others are kept at the end.
+    duplicates: Vec<String>,
+}
+
+struct Definition {
+    label: String,
+    /// After the `]:`, with continuation lines and the line ending.
+    content: String,
+}
+
+fn is_fence(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.starts_with("```") || trimmed.starts_with("~~~")
+}
+
+fn is_heading(line: &str) -> bool {
+    let trimmed = line.trim_start_matches(' ');
+    line.len() - trimmed.len() < 4
+        && trimmed.starts_with('#')
+        && trimmed.trim_start_matches('#').chars().next().is_none_or(char::is_whitespace)
+}
+
+fn is_indented(line: &str) -> bool {
+    line.starts_with("    ") || line.starts_with('\t')
+}
+
+fn is_label(label: &str) -> bool {
+    !label.is_empty() && !label.contains(|c: char| c.is_whitespace() || c == '[' || c == ']')
+}
+
+/// The label of a definition starting on `line`, and the rest of the line.
+fn definition_start(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let rest = trimmed.strip_prefix("[^")?;
+    let end = rest.find("]:")?;
+    let label = &rest[..end];
+    is_label(label).then(|| (label, &rest[end + 2..]))
+}
+
+/// `line` with each footnote reference outside code spans replaced by
+/// what `f` returns for its label, or kept if that's `None`.
+fn replace_references(line: &str, mut f: impl FnMut(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(i) = rest.find(['`', '[']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with('`') {
+            let ticks = &rest[..rest.len() - rest.trim_start_matches('`').len()];
+            let span = rest[ticks.len()..].find(ticks).map_or(ticks.len(), |end| end + 2 * ticks.len());
+            out.push_str(&rest[..span]);
+            rest = &rest[span..];
+            continue;
+        }
+        let label = rest.strip_prefix("[^").and_then(|r| r.find(']').map(|end| &r[..end])).filter(|l| is_label(l));
+        match label {
+            Some(label) => {
+                match f(label) {
+                    Some(new) => out.push_str(&format!("[^{new}]")),
+                    None => out.push_str(&rest[..label.len() + 3]),
+                }
+                rest = &rest[label.len() + 3..];
+            }
+            None => {
+                out.push('[');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Takes the definitions out of `lines`, returning the other lines and the
+/// definitions in order.
+fn split_definitions<'a>(lines: &[&'a str]) -> (Vec<&'a str>, Vec<Definition>) {
+    let mut body = Vec::with_capacity(lines.len());
+    let mut definitions = Vec::new();
+    let mut fenced = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let start = if fenced || is_fence(line) { None } else { definition_start(line) };
+        let Some((label, first)) = start else {
+            if is_fence(line) {
+                fenced = !fenced;
+            }
+            body.push(line);
+            i += 1;
+            continue;
+        };
+        let mut content = first.to_owned();
+        i += 1;
+        while let Some(&next) = lines.get(i) {
+            let blank = next.trim().is_empty();
+            // a paragraph goes on without indentation, until a blank line
+            let lazy = !blank
+                && !lines[i - 1].trim().is_empty()
+                && definition_start(next).is_none()
+                && !is_heading(next)
+                && !is_fence(next);
+            let more_after_blank = blank
+                && lines[i + 1..].iter().find(|l| !l.trim().is_empty()).is_some_and(|l| is_indented(l));
+            if !(is_indented(next) || lazy || more_after_blank) {
+                break;
+            }
+            content.push_str(next);
+            i += 1;
+        }
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        definitions.push(Definition { label: label.to_owned(), content });
+        // no blank lines are left where it was
+        if body.last().is_none_or(|l: &&str| l.trim().is_empty()) {
+            while lines.get(i).is_some_and(|l| l.trim().is_empty()) {
+                i += 1;
+            }
+        }
+    }
+    (body, definitions)
+}
+
+/// Appends definitions to `out` after a blank line.
+fn emit<'a>(out: &mut String, definitions: impl IntoIterator<Item = (&'a str, &'a str)>) {
+    let mut definitions = definitions.into_iter().peekable();
+    if definitions.peek().is_none() {
+        return;
+    }
+    if !out.is_empty() {
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        if !out.ends_with("\n\n") {
+            out.push('\n');
+        }
+    }
+    for (label, content) in definitions {
+        out.push_str(&format!("[^{label}]:{content}"));
+    }
+}
+
+/// Finds the definitions referenced, in the order they're first referenced
+/// and in which section, numbering the numbered ones as it goes.
+struct Walk<'a> {
+    definitions: &'a [&'a Definition],
+    defined: HashMap<&'a str, usize>,
+    labels: HashMap<&'a str, String>,
+    order: Vec<(usize, usize)>,
+    /// How many of `order` have had their references found.
+    walked: usize,
+    missing: Vec<String>,
+    next_number: usize,
+}
+
+impl Walk<'_> {
+    fn references(&mut self, text: &str, section: usize) {
+        replace_references(text, |label| {
+            if self.labels.contains_key(label) {
+                return None;
+            }
+            match self.defined.get(label) {
+                Some(&index) => {
+                    let new = if label.chars().all(|c| c.is_ascii_digit()) {
+                        let number = self.next_number;
+                        self.next_number += 1;
+                        number.to_string()
+                    } else {
+                        label.to_owned()
+                    };
+                    self.labels.insert(self.definitions[index].label.as_str(), new);
+                    self.order.push((index, section));
+                }
+                None if !self.missing.iter().any(|m| m == label) => self.missing.push(label.to_owned()),
+                None => {}
+            }
+            None
+        });
+    }
+
+    /// The references in the definitions referenced so far, and in the ones
+    /// they refer to, in the section they'll be placed in; those come after
+    /// the text that refers to them, like it's rendered.
+    fn definitions_referenced(&mut self) {
+        while let Some(&(index, section)) = self.order.get(self.walked) {
+            self.walked += 1;
+            let definitions = self.definitions;
+            let mut fenced = false;
+            for line in definitions[index].content.split_inclusive('\n') {
+                if is_fence(line) {
+                    fenced = !fenced;
+                } else if !fenced {
+                    self.references(line, section);
+                }
+            }
+        }
+    }
+}
+
+fn renumber(text: &str, options: &FootnoteOptions) -> FootnoteReport {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let (body, all) = split_definitions(&lines);
+    let mut definitions: Vec<&Definition> = Vec::new();
+    let mut extra: Vec<&Definition> = Vec::new();
+    let mut duplicates: Vec<String> = Vec::new();
+    for definition in &all {
+        if definitions.iter().any(|d| d.label == definition.label) {
+            if !duplicates.contains(&definition.label) {
+                duplicates.push(definition.label.clone());
+            }
+            extra.push(definition);
+        } else {
+            definitions.push(definition);
+        }
+    }
+    let defined: HashMap<&str, usize> = definitions.iter().enumerate().map(|(i, d)| (d.label.as_str(), i)).collect();
+
+    // which definitions are referenced first in which section
+    let mut walk = Walk {
+        definitions: &definitions,
+        defined,
+        labels: HashMap::new(),
+        order: Vec::new(),
+        walked: 0,
+        missing: Vec::new(),
+        next_number: 1,
+    };
+    let (mut fenced, mut section) = (false, 0);
+    for &line in &body {
+        if is_fence(line) {
+            fenced = !fenced;
+            continue;
+        }
+        if fenced {
+            continue;
+        }
+        if is_heading(line) {
+            // a section's definitions come before the next heading, with
+            // what they refer to
+            if options.placement == FootnotePlacement::Section {
+                walk.definitions_referenced();
+            }
+            section += 1;
+        }
+        walk.references(line, section);
+    }
+    walk.definitions_referenced();
+    let Walk { labels, order, missing, mut next_number, .. } = walk;
+
+    let relabel = |line: &str| replace_references(line, |label| labels.get(label).cloned());
+    let contents: Vec<String> = definitions.iter().map(|d| relabel(&d.content)).collect();
+    let placed = |&(index, _): &(usize, usize)| {
+        (labels[definitions[index].label.as_str()].as_str(), contents[index].as_str())
+    };
+
+    let mut out = String::with_capacity(text.len());
+    let (mut fenced, mut section) = (false, 0);
+    for &line in &body {
+        if is_fence(line) {
+            fenced = !fenced;
+        } else if !fenced && is_heading(line) {
+            if options.placement == FootnotePlacement::Section {
+                let before = out.len();
+                emit(&mut out, order.iter().filter(|(_, s)| *s == section).map(placed));
+                if out.len() > before {
+                    out.push('\n');
+                }
+            }
+            section += 1;
+        }
+        if fenced || is_fence(line) {
+            out.push_str(line);
+        } else {
+            out.push_str(&relabel(line));
+        }
+    }
+    match options.placement {
+        FootnotePlacement::End => emit(&mut out, order.iter().map(placed)),
+        FootnotePlacement::Section => emit(&mut out, order.iter().filter(|(_, s)| *s == section).map(placed)),
+    }
+    let unreferenced: Vec<&Definition> =
+        definitions.iter().copied().filter(|d| !labels.contains_key(d.label.as_str())).collect();
+    // numbered ones carry on from the referenced ones, so none of them clash
+    let leftovers: Vec<(String, String)> = unreferenced
+        .iter()
+        .chain(&extra)
+        .map(|d| {
+            if d.label.chars().all(|c| c.is_ascii_digit()) {
+                let number = next_number;
+                next_number += 1;
+                (number.to_string(), relabel(&d.content))
+            } else {
+                (d.label.clone(), relabel(&d.content))
+            }
+        })
+        .collect();
+    emit(&mut out, leftovers.iter().map(|(label, content)| (label.as_str(), content.as_str())));
+
+    let unreferenced = unreferenced.iter().map(|d| d.label.clone()).collect();
+    FootnoteReport { text: out, missing, unreferenced, duplicates }
+}
+
+/// Renumbers and moves the footnotes of the markdown `text`, and reports
+/// the ones that aren't referenced or defined.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn renumber_footnotes(text: String, options: FootnoteOptions) -> FootnoteReport {
+    renumber(&text, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tidy(text: &str, placement: FootnotePlacement) -> FootnoteReport {
+        renumber(text, &FootnoteOptions { placement })
+    }
+
+    #[test]
+    fn renumbers_in_reference_order() {
+        let report = tidy("B[^2] and A[^1], `[^9]`.\n\n[^1]: A.\n[^2]: B.\n[^3]: Spare.\n", FootnotePlacement::End);
+        assert_eq!(report.text, "B[^1] and A[^2], `[^9]`.\n\n[^1]: B.\n[^2]: A.\n\n[^3]: Spare.\n");
+        assert_eq!(report.unreferenced, ["3"]);
+        assert!(report.missing.is_empty());
+    }
+
+    #[test]
+    fn follows_references_in_definitions() {
+        let text = "One[^a] two[^5].\n\n[^5]: Five, see[^7].\n[^7]: Seven, see[^a].\n[^a]: Named[^6].\n[^6]: Six.\n";
+        let report = tidy(text, FootnotePlacement::End);
+        assert_eq!(
+            report.text,
+            "One[^a] two[^1].\n\n[^a]: Named[^2].\n[^1]: Five, see[^3].\n[^2]: Six.\n[^3]: Seven, see[^a].\n"
+        );
+        assert!(report.missing.is_empty() && report.unreferenced.is_empty());
+    }
+
+    #[test]
+    fn places_nested_definitions_in_their_section() {
+        let text = "# One\n\nA[^1].\n\n# Two\n\nB[^3].\n\n[^1]: See[^2].\n[^2]: Nested.\n[^3]: Later.\n";
+        let report = tidy(text, FootnotePlacement::Section);
+        assert_eq!(
+            report.text,
+            "# One\n\nA[^1].\n\n[^1]: See[^2].\n[^2]: Nested.\n\n# Two\n\nB[^3].\n\n[^3]: Later.\n"
+        );
+    }
+
+    #[test]
+    fn reports_missing_and_duplicate_labels() {
+        let report = tidy("X[^x] Y[^y].\n\n[^x]: One.\n[^x]: Two.\n", FootnotePlacement::End);
+        assert_eq!(report.missing, ["y"]);
+        assert_eq!(report.duplicates, ["x"]);
+        assert_eq!(report.text, "X[^x] Y[^y].\n\n[^x]: One.\n\n[^x]: Two.\n");
+    }
+}
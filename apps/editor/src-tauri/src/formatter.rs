@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::BackendError, process, settings};
+
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+
+/// A formatter in the settings, by language, that replaces the built-in
+/// choice for it. The source is always given on stdin.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatterConfig {
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    timeout_ms: Option<u64>,
+}
+
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|&a| a.to_owned()).collect()
+}
+
+fn default_formatter(language: &str) -> Option<FormatterConfig> {
+    let (program, args) = match language.to_lowercase().as_str() {
+        "rust" | "rs" =>
+            ("rustfmt", strings(&["--emit", "stdout", "--edition", "2021"])),
+        "python" | "py" =>
+            ("black", strings(&["--quiet", "-"])),
+        "go" =>
+            ("gofmt", vec![]),
+        "c" | "h" =>
+            ("clang-format", strings(&["--assume-filename=code.c"])),
+        "cpp" | "c++" | "cc" | "hpp" =>
+            ("clang-format", strings(&["--assume-filename=code.cpp"])),
+        ext @ ("js" | "jsx" | "ts" | "tsx" | "css" | "scss" | "less" | "html"
+            | "json" | "md" | "yaml" | "yml" | "vue" | "svelte") =>
+            ("prettier", vec![format!("--stdin-filepath=code.{ext}")]),
+        "javascript" =>
+            ("prettier", strings(&["--stdin-filepath=code.js"])),
+        "typescript" =>
+            ("prettier", strings(&["--stdin-filepath=code.ts"])),
+        "markdown" =>
+            ("prettier", strings(&["--stdin-filepath=code.md"])),
+        _ => return None,
+    };
+    Some(FormatterConfig { program: program.to_owned(), args, timeout_ms: None })
+}
+
+/// The one in the settings for `language`, or else the built-in one.
+fn formatter(language: &str) -> Option<FormatterConfig> {
+    let language = language.to_lowercase();
+    settings::get().formatters.get(&language).cloned().or_else(|| default_formatter(&language))
+}
+
+/// Pipes a code block through the formatter for `language` and returns
+/// the result: the one in the settings, or rustfmt, black, prettier...
+#[tauri::command]
+pub async fn format_code(source: String, language: String) -> Result<String, BackendError> {
+    log::info!("format_code start: {language}");
+    let config = formatter(&language)
+        .ok_or_else(|| BackendError::not_found(format!("no formatter for language: {language}")))?;
+    let program = process::find_program(&config.program)
+        .ok_or_else(|| BackendError::not_found(format!("formatter not found: {}", config.program)))?;
+    let timeout = Duration::from_millis(config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS));
+
+    let output = process::run_piped(
+        &program, &config.args, source.into_bytes(), timeout)
+        .await
+        .map_err(|e| format!("format_code: {e}"))?;
+    let result = String::from_utf8(output)
+        .map_err(|e| format!("format_code: invalid utf-8 output: {e}"))?;
+    log::info!("format_code done");
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_formatters_by_language() {
+        let rust = default_formatter("rust").expect("rustfmt");
+        assert_eq!(rust.program, "rustfmt");
+        let tsx = default_formatter("tsx").expect("prettier");
+        assert_eq!(tsx.args, ["--stdin-filepath=code.tsx"]);
+        assert!(default_formatter("cobol").is_none());
+    }
+}
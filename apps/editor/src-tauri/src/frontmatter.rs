@@ -0,0 +1,200 @@
+//! The `---` delimited block at the start of a document. Only the subset of
+//! YAML people actually write there is understood: `key: value` pairs,
+//! inline `[a, b]` lists and `- item` block lists.
+
+use time::{
+    format_description::well_known::Rfc3339, macros::format_description,
+    Date, OffsetDateTime, PrimitiveDateTime, UtcOffset,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Scalar(String),
+    List(Vec<String>),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Frontmatter {
+    pub fields: Vec<(String, Value)>,
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    let quoted = value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')));
+    if quoted {
+        value[1..value.len() - 1].to_owned()
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Splits a document into its frontmatter source (without the delimiters)
+/// and the body.
+pub fn split(document: &str) -> Option<(&str, &str)> {
+    let rest = document
+        .strip_prefix("---\n")
+        .or_else(|| document.strip_prefix("---\r\n"))?;
+    if let Some(body) = rest.strip_prefix("---\n") {
+        return Some(("", body));
+    }
+    let end = rest.find("\n---\n").map(|i| (i, i + 5))
+        .or_else(|| rest.find("\r\n---\r\n").map(|i| (i, i + 7)))
+        .or_else(|| rest.strip_suffix("\n---").map(|r| (r.len(), rest.len())))?;
+    Some((&rest[..end.0], &rest[end.1..]))
+}
+
+/// Sets `key` to `value` in the frontmatter of `document`, adding a
+/// frontmatter block if there is none. Other lines are kept as written.
+pub fn set_field(document: &str, key: &str, value: &str) -> String {
+    let line = format!("{key}: {value}");
+    let Some((source, body)) = split(document) else {
+        return format!("---\n{line}\n---\n{document}");
+    };
+    let mut lines: Vec<_> = source.lines().map(str::to_owned).collect();
+    let existing = lines
+        .iter()
+        .position(|l| l.split_once(':').is_some_and(|(k, _)| k.trim_end() == key));
+    match existing {
+        Some(i) => {
+            lines[i] = line;
+            // along with the block list it may have had
+            while lines.get(i + 1).is_some_and(|l| l.trim_start().starts_with("- ")) {
+                lines.remove(i + 1);
+            }
+        }
+        None => lines.push(line),
+    }
+    format!("---\n{}\n---\n{body}", lines.join("\n"))
+}
+
+impl Frontmatter {
+    pub fn parse(source: &str) -> Self {
+        let mut fields: Vec<(String, Value)> = Vec::new();
+        for line in source.lines() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let trimmed = line.trim_start();
+            // `- item` continues the list of the key right above it
+            if let Some(item) = trimmed.strip_prefix("- ") {
+                if let Some((_, value)) = fields.last_mut() {
+                    match value {
+                        Value::List(items) => items.push(unquote(item)),
+                        Value::Scalar(s) if s.is_empty() =>
+                            *value = Value::List(vec![unquote(item)]),
+                        Value::Scalar(_) => {}
+                    }
+                }
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else { continue };
+            let value = value.trim();
+            let value = if let Some(inner) =
+                value.strip_prefix('[').and_then(|v| v.strip_suffix(']'))
+            {
+                Value::List(inner
+                    .split(',')
+                    .map(unquote)
+                    .filter(|s| !s.is_empty())
+                    .collect())
+            } else {
+                Value::Scalar(unquote(value))
+            };
+            fields.push((key.trim().to_owned(), value));
+        }
+        Frontmatter { fields }
+    }
+
+    /// Parses the frontmatter of a whole document and returns it with the
+    /// body. Documents without frontmatter get an empty one.
+    pub fn of(document: &str) -> (Self, &str) {
+        match split(document) {
+            Some((source, body)) => (Self::parse(source), body),
+            None => (Self::default(), document),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find_map(|(k, v)| match v {
+            Value::Scalar(s) if k == key && !s.is_empty() => Some(s.as_str()),
+            _ => None,
+        })
+    }
+
+    /// A list field; a scalar is taken as a comma-separated list.
+    pub fn list(&self, key: &str) -> Vec<String> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| match v {
+                Value::List(items) => items.clone(),
+                Value::Scalar(s) => s
+                    .split(',')
+                    .map(|s| s.trim().to_owned())
+                    .filter(|s| !s.is_empty())
+                    .collect(),
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn date(&self, key: &str) -> Option<OffsetDateTime> {
+        parse_date(self.get(key)?)
+    }
+}
+
+/// Accepts RFC 3339 as well as the plain `2024-05-01` and
+/// `2024-05-01 12:30` forms, the latter two taken as UTC.
+pub fn parse_date(value: &str) -> Option<OffsetDateTime> {
+    let value = value.trim();
+    if let Ok(date) = OffsetDateTime::parse(value, &Rfc3339) {
+        return Some(date);
+    }
+    let minutes = format_description!("[year]-[month]-[day] [hour]:[minute]");
+    if let Ok(date) = PrimitiveDateTime::parse(value, minutes) {
+        return Some(date.assume_offset(UtcOffset::UTC));
+    }
+    let day = format_description!("[year]-[month]-[day]");
+    let date = Date::parse(value.get(..10)?, day).ok()?;
+    Some(date.midnight().assume_offset(UtcOffset::UTC))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_lists_and_dates_are_read() {
+        let document = "---\ntitle: \"Hello: world\"\ntags: [a, 'b', ]\naliases:\n  - one\n  - two\n# note\n\
+                        date: 2024-05-01 12:30\n---\nBody\n";
+        let (meta, body) = Frontmatter::of(document);
+        assert_eq!(body, "Body\n");
+        assert_eq!(meta.get("title"), Some("Hello: world"));
+        assert_eq!(meta.list("tags"), ["a", "b"]);
+        assert_eq!(meta.list("aliases"), ["one", "two"]);
+        assert_eq!(meta.date("date").map(OffsetDateTime::unix_timestamp), Some(1_714_566_600));
+        assert_eq!(meta.get("missing"), None);
+
+        let (meta, body) = Frontmatter::of("No frontmatter\n---\n");
+        assert!(meta.fields.is_empty());
+        assert_eq!(body, "No frontmatter\n---\n");
+        assert_eq!(split("---\r\na: 1\r\n---\r\nx"), Some(("a: 1", "x")));
+    }
+
+    #[test]
+    fn setting_a_field_keeps_the_other_lines() {
+        let document = "---\ntitle: A\ngist:\n  - old\n# kept\n---\nBody";
+        assert_eq!(set_field(document, "gist", "abc"), "---\ntitle: A\ngist: abc\n# kept\n---\nBody");
+        assert_eq!(set_field("Body", "gist", "abc"), "---\ngist: abc\n---\nBody");
+    }
+
+    #[test]
+    fn dates_take_several_forms() {
+        let day = parse_date("2024-05-01").unwrap();
+        assert_eq!(day.unix_timestamp(), 1_714_521_600);
+        assert_eq!(parse_date("2024-05-01T02:00:00+02:00").unwrap(), day);
+        assert_eq!(parse_date("2024-05-01-notes").unwrap(), day);
+        assert!(parse_date("May 1st").is_none());
+    }
+}
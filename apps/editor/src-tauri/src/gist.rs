@@ -0,0 +1,160 @@
+//! Publishing documents as GitHub Gists. The gist ID is kept in the
+//! document's `gist` frontmatter field, so publishing again updates the
+//! same gist.
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    error::BackendError,
+    frontmatter::{self, Frontmatter},
+    jobs::{self, Job, JobKind},
+    markdown,
+    net::{self, reqwest},
+    workers::Priority,
+};
+
+const API: &str = "https://api.github.com/gists";
+const MAX_RESPONSE_SIZE: u64 = 10 * 1024 * 1024;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishedGist {
+    pub id: String,
+    pub url: String,
+    /// `doc` with the gist ID in its frontmatter.
+    pub document: String,
+}
+
+async fn send(
+    request: reqwest::RequestBuilder, token: &str,
+) -> Result<(reqwest::StatusCode, serde_json::Value), String> {
+    let response = request
+        .bearer_auth(token)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+        .header("X-GitHub-Api-Version", "2022-11-28")
+        .send()
+        .await
+        .map_err(|e| format!("request: {e}"))?;
+    let status = response.status();
+    let body = net::read_capped(response, MAX_RESPONSE_SIZE).await?;
+    let value = serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+    Ok((status, value))
+}
+
+fn api_error(status: reqwest::StatusCode, body: &serde_json::Value) -> String {
+    match body["message"].as_str() {
+        Some(message) => format!("GitHub returned {status}: {message}"),
+        None => format!("GitHub returned {status}"),
+    }
+}
+
+/// `files` for updating `gist`, deleting the files it has under other
+/// names.
+fn update(files: &serde_json::Value, gist: &serde_json::Value) -> serde_json::Value {
+    let mut files = files.clone();
+    for name in gist["files"].as_object().into_iter().flat_map(serde_json::Map::keys) {
+        if files.get(name).is_none() {
+            files[name] = serde_json::Value::Null;
+        }
+    }
+    files
+}
+
+/// Creates a gist with `doc`, or updates the one named in its frontmatter.
+/// `public` only applies to new gists; GitHub doesn't allow changing it.
+/// The file is named after `file_name`, the title or `document.md`; the
+/// gist holds just that one, so when the name changed, the file under the
+/// old one is deleted in the same update. It runs as a job, which can be
+/// cancelled until the gist is written.
+pub async fn publish(
+    doc: String, public: bool, token: &str, file_name: Option<String>, priority: Priority,
+) -> Result<PublishedGist, String> {
+    let (meta, _) = Frontmatter::of(&doc);
+    let file_name = file_name
+        .filter(|n| !n.is_empty())
+        .or_else(|| meta.get("title").map(|t| format!("{}.md", markdown::slug(t))))
+        .filter(|n| n != ".md")
+        .unwrap_or_else(|| "document.md".to_owned());
+    let job = jobs::start(JobKind::Upload, priority, &file_name);
+    let result = send_gist(&doc, public, token, file_name, &job).await;
+    job.finish(&result);
+    result
+}
+
+async fn send_gist(
+    doc: &str, public: bool, token: &str, file_name: String, job: &Job,
+) -> Result<PublishedGist, String> {
+    let (meta, _) = Frontmatter::of(doc);
+    let title = meta.get("title");
+    let files = json!({ &file_name: { "content": doc } });
+
+    let client = net::client()?;
+    let mut response = None;
+    if let Some(id) = meta.get("gist") {
+        let (status, value) = send(client.get(format!("{API}/{id}")), token).await?;
+        if job.is_cancelled() {
+            return Err(jobs::CANCELLED.to_owned());
+        }
+        // a deleted gist is published anew
+        if status != reqwest::StatusCode::NOT_FOUND {
+            if !status.is_success() {
+                return Err(api_error(status, &value));
+            }
+            let body = json!({ "description": title.unwrap_or_default(), "files": update(&files, &value) });
+            let request = client.patch(format!("{API}/{id}")).body(body.to_string());
+            response = Some(send(request, token).await?);
+        }
+    }
+    let (status, value) = match response {
+        Some(response) => response,
+        None => {
+            let body = json!({
+                "description": title.unwrap_or_default(),
+                "public": public,
+                "files": files,
+            });
+            send(client.post(API).body(body.to_string()), token).await?
+        }
+    };
+    if !status.is_success() {
+        return Err(api_error(status, &value));
+    }
+
+    let (Some(id), Some(url)) = (value["id"].as_str(), value["html_url"].as_str()) else {
+        return Err("unexpected response from GitHub".to_owned());
+    };
+    let document = if meta.get("gist") == Some(id) {
+        doc.to_owned()
+    } else {
+        frontmatter::set_field(doc, "gist", id)
+    };
+    Ok(PublishedGist { id: id.to_owned(), url: url.to_owned(), document })
+}
+
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn publish_gist(
+    doc: String, public: bool, token: String, file_name: Option<String>,
+) -> Result<PublishedGist, BackendError> {
+    log::info!("publish_gist start");
+    let published = publish(doc, public, &token, file_name, Priority::Interactive)
+        .await
+        .map_err(|e| format!("publish_gist: {e}"))?;
+    log::info!("publish_gist done: {}", published.url);
+    Ok(published)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renamed_files_replace_the_old_one() {
+        let files = json!({ "new.md": { "content": "text" } });
+        let gist = json!({ "files": { "old.md": { "content": "before" }, "new.md": { "content": "before" } } });
+        assert_eq!(update(&files, &gist), json!({ "new.md": { "content": "text" }, "old.md": null }));
+        assert_eq!(update(&files, &json!({})), files);
+    }
+}
@@ -0,0 +1,718 @@
+//! Version control for workspaces kept in a git repository.
+//!
+//! Paths given to and returned by these commands are relative to the root
+//! of the repository's working tree, with forward slashes; paths given may
+//! also be absolute.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use git2::{
+    build::TreeUpdateBuilder, BranchType, Commit, DiffFindOptions, FileMode, Oid, Patch, Repository,
+    RepositoryState, Sort, Status, StatusOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::BackendError;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+    TypeChanged,
+    Untracked,
+    Conflicted,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileStatus {
+    path: String,
+    /// The change in the index, to be committed.
+    staged: Option<Change>,
+    /// The change in the working tree, not staged yet.
+    unstaged: Option<Change>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GitStatus {
+    /// The working tree, where the relative paths start.
+    root: String,
+    /// `None` on a detached HEAD.
+    branch: Option<String>,
+    files: Vec<FileStatus>,
+}
+
+const DEFAULT_LOG_LIMIT: usize = 100;
+const DEFAULT_AUTO_COMMIT_MESSAGE: &str = "Update {files}";
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoCommitOptions {
+    enabled: bool,
+    /// `{files}` is replaced by the names of the files saved, `{date}` by
+    /// the local date and time; `Update {files}` by default.
+    message: Option<String>,
+    /// Saves this many seconds after an automatic commit are added to it
+    /// instead of making another; 0 to commit every save.
+    coalesce_seconds: u64,
+}
+
+impl Default for AutoCommitOptions {
+    fn default() -> AutoCommitOptions {
+        AutoCommitOptions { enabled: false, message: None, coalesce_seconds: 300 }
+    }
+}
+
+/// The last automatic commit, which later ones may be folded into.
+struct AutoCommit {
+    workdir: PathBuf,
+    id: Oid,
+    at: Instant,
+    files: BTreeSet<String>,
+}
+
+static AUTO_COMMIT: RwLock<Option<AutoCommitOptions>> = RwLock::new(None);
+static LAST_AUTO_COMMIT: Mutex<Option<AutoCommit>> = Mutex::new(None);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntry {
+    id: String,
+    short_id: String,
+    summary: String,
+    message: String,
+    author: String,
+    email: String,
+    /// Ms since the epoch.
+    time: i64,
+    /// The file's absolute path in this commit, which differs from the
+    /// current one before a rename.
+    path: String,
+    /// Lines added and deleted in the file.
+    additions: usize,
+    deletions: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlameCommit {
+    id: String,
+    short_id: String,
+    summary: String,
+    author: String,
+    email: String,
+    /// Ms since the epoch.
+    time: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Blame {
+    /// For each line, the index in `commits` of the commit that last
+    /// changed it; `None` for changes not committed yet.
+    lines: Vec<Option<usize>>,
+    commits: Vec<BlameCommit>,
+}
+
+/// The repository `path` is in; `path` need not exist anymore, but must be
+/// absolute, or it would be looked for from wherever the app was started.
+pub(crate) fn open(path: &str) -> Result<Repository, String> {
+    if Path::new(path).is_relative() {
+        return Err(format!("{path} is not an absolute path"));
+    }
+    let start = Path::new(path).ancestors().find(|p| p.exists()).unwrap_or(Path::new(path));
+    Repository::discover(start).map_err(|e| format!("no git repository at {path}: {}", e.message()))
+}
+
+pub(crate) fn workdir(repo: &Repository) -> Result<&Path, String> {
+    repo.workdir().ok_or("the repository is bare".to_owned())
+}
+
+/// `path` relative to the working tree; a relative `path` already is, and
+/// can't leave it.
+pub(crate) fn relative_path(repo: &Repository, path: &str) -> Result<PathBuf, String> {
+    let path = Path::new(path);
+    if path.is_relative() {
+        if !path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return Err(format!("{} is outside the repository", path.display()));
+        }
+        return Ok(path.to_owned());
+    }
+    let root = workdir(repo)?;
+    if let Ok(relative) = path.strip_prefix(root) {
+        return Ok(relative.to_owned());
+    }
+    // the working tree may be reached through a symlink; the file itself
+    // may not exist, so only its folder is resolved
+    let outside = || format!("{} is outside the repository", path.display());
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(outside());
+    };
+    let dir = dir.canonicalize().map_err(|_| outside())?;
+    let root = root.canonicalize().map_err(|e| format!("{}: {e}", root.display()))?;
+    Ok(dir.strip_prefix(root).map_err(|_| outside())?.join(name))
+}
+
+fn staged(status: Status) -> Option<Change> {
+    if status.is_conflicted() {
+        Some(Change::Conflicted)
+    } else if status.is_index_new() {
+        Some(Change::Added)
+    } else if status.is_index_modified() {
+        Some(Change::Modified)
+    } else if status.is_index_deleted() {
+        Some(Change::Deleted)
+    } else if status.is_index_renamed() {
+        Some(Change::Renamed)
+    } else if status.is_index_typechange() {
+        Some(Change::TypeChanged)
+    } else {
+        None
+    }
+}
+
+fn unstaged(status: Status) -> Option<Change> {
+    if status.is_conflicted() {
+        Some(Change::Conflicted)
+    } else if status.is_wt_new() {
+        Some(Change::Untracked)
+    } else if status.is_wt_modified() {
+        Some(Change::Modified)
+    } else if status.is_wt_deleted() {
+        Some(Change::Deleted)
+    } else if status.is_wt_renamed() {
+        Some(Change::Renamed)
+    } else if status.is_wt_typechange() {
+        Some(Change::TypeChanged)
+    } else {
+        None
+    }
+}
+
+/// The current branch; `None` on a detached HEAD.
+pub(crate) fn branch(repo: &Repository) -> Option<String> {
+    // symbolic even on an unborn branch, as in a new repository
+    let head = repo.find_reference("HEAD").ok()?;
+    head.symbolic_target()?.strip_prefix("refs/heads/").map(str::to_owned)
+}
+
+fn status(workspace: &str) -> Result<GitStatus, String> {
+    let repo = open(workspace)?;
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .renames_head_to_index(true)
+        .renames_index_to_workdir(true);
+    let statuses = repo
+        .statuses(Some(&mut options))
+        .map_err(|e| format!("status: {}", e.message()))?;
+    let files = statuses
+        .iter()
+        .filter_map(|entry| {
+            let status = entry.status();
+            let path = entry.path()?.to_owned();
+            let (staged, unstaged) = (staged(status), unstaged(status));
+            (staged.is_some() || unstaged.is_some()).then_some(FileStatus { path, staged, unstaged })
+        })
+        .collect();
+    Ok(GitStatus {
+        root: workdir(&repo)?.to_string_lossy().into_owned(),
+        branch: branch(&repo),
+        files,
+    })
+}
+
+/// Commits what is staged after staging `paths`, which may include deleted
+/// files; returns the new commit's ID.
+pub(crate) fn commit(repo: &Repository, paths: &[String], message: &str) -> Result<String, String> {
+    let root = workdir(repo)?.to_owned();
+    let mut index = repo.index().map_err(|e| format!("index: {}", e.message()))?;
+    for path in paths {
+        let relative = relative_path(repo, path)?;
+        let result = if root.join(&relative).exists() {
+            index.add_path(&relative)
+        } else {
+            index.remove_path(&relative)
+        };
+        result.map_err(|e| format!("stage {}: {}", relative.display(), e.message()))?;
+    }
+    index.write().map_err(|e| format!("write index: {}", e.message()))?;
+    let tree_id = index.write_tree().map_err(|e| format!("write tree: {}", e.message()))?;
+    let tree = repo.find_tree(tree_id).map_err(|e| format!("find tree: {}", e.message()))?;
+
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit().map_err(|e| format!("HEAD: {}", e.message()))?),
+        Err(e) if e.code() == git2::ErrorCode::UnbornBranch || e.code() == git2::ErrorCode::NotFound => None,
+        Err(e) => return Err(format!("HEAD: {}", e.message())),
+    };
+    if parent.as_ref().is_some_and(|p| p.tree_id() == tree_id) {
+        return Err("nothing to commit".to_owned());
+    }
+    let signature = repo
+        .signature()
+        .map_err(|e| format!("no author; set user.name and user.email in git config: {}", e.message()))?;
+    let parents: Vec<_> = parent.iter().collect();
+    let id = repo
+        .commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)
+        .map_err(|e| format!("commit: {}", e.message()))?;
+    Ok(id.to_string())
+}
+
+fn to_slashes(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn blob_at(commit: &Commit, path: &Path) -> Option<Oid> {
+    let entry = commit.tree().ok()?.get_path(path).ok()?;
+    (entry.kind() == Some(git2::ObjectType::Blob)).then(|| entry.id())
+}
+
+/// How a commit changed a file.
+struct FileChange {
+    /// The path and blob before, following renames; `None` if the file
+    /// was created.
+    old: Option<(PathBuf, Oid)>,
+    new: Oid,
+}
+
+/// How `commit` changed the file at `path`; `None` if it didn't.
+fn file_change(repo: &Repository, commit: &Commit, path: &Path) -> Result<Option<FileChange>, String> {
+    let Some(new) = blob_at(commit, path) else { return Ok(None) };
+    let Ok(parent) = commit.parent(0) else { return Ok(Some(FileChange { old: None, new })) };
+    if let Some(previous) = blob_at(&parent, path) {
+        let old = Some((path.to_owned(), previous));
+        return Ok((previous != new).then_some(FileChange { old, new }));
+    }
+    // added here, or renamed from somewhere else
+    let old_tree = parent.tree().map_err(|e| format!("tree: {}", e.message()))?;
+    let new_tree = commit.tree().map_err(|e| format!("tree: {}", e.message()))?;
+    let mut diff = repo
+        .diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)
+        .map_err(|e| format!("diff: {}", e.message()))?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true)))
+        .map_err(|e| format!("find renames: {}", e.message()))?;
+    let renamed = diff.deltas().find_map(|delta| {
+        let to = delta.new_file().path()?;
+        (delta.status() == git2::Delta::Renamed && to == path)
+            .then(|| Some((delta.old_file().path()?.to_owned(), delta.old_file().id())))
+            .flatten()
+    });
+    Ok(Some(FileChange { old: renamed, new }))
+}
+
+/// Lines added and deleted, and the unified diff if `text` is set.
+fn file_diff(
+    repo: &Repository, change: &FileChange, path: &Path, text: bool,
+) -> Result<(usize, usize, String), String> {
+    let old = change.old.as_ref();
+    let read = |id| {
+        repo.find_blob(id)
+            .map(|blob| blob.content().to_vec())
+            .map_err(|e| format!("blob {id}: {}", e.message()))
+    };
+    let old_content = old.map(|(_, id)| read(*id)).transpose()?.unwrap_or_default();
+    let new_content = read(change.new)?;
+    let mut patch = Patch::from_buffers(
+        &old_content, Some(old.map_or(path, |(old_path, _)| old_path.as_path())),
+        &new_content, Some(path),
+        None,
+    ).map_err(|e| format!("diff: {}", e.message()))?;
+    let (_, additions, deletions) = patch.line_stats().map_err(|e| format!("diff: {}", e.message()))?;
+    let diff = if text {
+        let buffer = patch.to_buf().map_err(|e| format!("diff: {}", e.message()))?;
+        String::from_utf8_lossy(&buffer).into_owned()
+    } else {
+        String::new()
+    };
+    Ok((additions, deletions, diff))
+}
+
+fn log(path: &str, limit: usize) -> Result<Vec<LogEntry>, String> {
+    let repo = open(path)?;
+    let root = workdir(&repo)?;
+    let mut path = relative_path(&repo, path)?;
+    let mut walk = repo.revwalk().map_err(|e| format!("revwalk: {}", e.message()))?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME).map_err(|e| format!("revwalk: {}", e.message()))?;
+    walk.push_head().map_err(|e| format!("HEAD: {}", e.message()))?;
+
+    let mut entries = Vec::new();
+    for id in walk {
+        if entries.len() >= limit {
+            break;
+        }
+        let id = id.map_err(|e| format!("revwalk: {}", e.message()))?;
+        let commit = repo.find_commit(id).map_err(|e| format!("commit {id}: {}", e.message()))?;
+        let Some(change) = file_change(&repo, &commit, &path)? else { continue };
+        let (additions, deletions, _) = file_diff(&repo, &change, &path, false)?;
+        let author = commit.author();
+        entries.push(LogEntry {
+            id: id.to_string(),
+            short_id: id.to_string()[..7].to_owned(),
+            summary: commit.summary().unwrap_or_default().to_owned(),
+            message: commit.message().unwrap_or_default().to_owned(),
+            author: author.name().unwrap_or_default().to_owned(),
+            email: author.email().unwrap_or_default().to_owned(),
+            time: author.when().seconds() * 1000,
+            path: root.join(&path).to_string_lossy().into_owned(),
+            additions,
+            deletions,
+        });
+        match change.old {
+            Some((old_path, _)) => path = old_path,
+            // the file was created here
+            None => break,
+        }
+    }
+    Ok(entries)
+}
+
+fn find_commit<'a>(repo: &'a Repository, rev: &str) -> Result<Commit<'a>, String> {
+    repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|e| format!("no commit {rev}: {}", e.message()))
+}
+
+fn show(path: &str, rev: &str) -> Result<String, String> {
+    let repo = open(path)?;
+    let relative = relative_path(&repo, path)?;
+    let commit = find_commit(&repo, rev)?;
+    let id = blob_at(&commit, &relative)
+        .ok_or_else(|| format!("{} is not in {rev}", to_slashes(&relative)))?;
+    let blob = repo.find_blob(id).map_err(|e| format!("blob {id}: {}", e.message()))?;
+    String::from_utf8(blob.content().to_vec()).map_err(|_| format!("{} is not text", to_slashes(&relative)))
+}
+
+fn diff(path: &str, rev: &str) -> Result<String, String> {
+    let repo = open(path)?;
+    let relative = relative_path(&repo, path)?;
+    let commit = find_commit(&repo, rev)?;
+    let Some(change) = file_change(&repo, &commit, &relative)? else {
+        return Ok(String::new());
+    };
+    let (_, _, diff) = file_diff(&repo, &change, &relative, true)?;
+    Ok(diff)
+}
+
+fn blame(path: &str, content: Option<&str>) -> Result<Blame, String> {
+    let repo = open(path)?;
+    let relative = relative_path(&repo, path)?;
+    let committed = match repo.blame_file(&relative, None) {
+        Ok(blame) => blame,
+        // never committed, so every line is a change
+        Err(e) if e.code() == git2::ErrorCode::NotFound => {
+            let text = match content {
+                Some(content) => content.to_owned(),
+                None => std::fs::read_to_string(path).map_err(|e| format!("read {path}: {e}"))?,
+            };
+            return Ok(Blame { lines: vec![None; text.lines().count()], commits: Vec::new() });
+        }
+        Err(e) => return Err(format!("blame {}: {}", to_slashes(&relative), e.message())),
+    };
+    let blame = match content {
+        Some(content) => committed
+            .blame_buffer(content.as_bytes())
+            .map_err(|e| format!("blame: {}", e.message()))?,
+        None => committed,
+    };
+
+    let mut result = Blame { lines: Vec::new(), commits: Vec::new() };
+    let mut indices = HashMap::new();
+    for hunk in blame.iter() {
+        let id = hunk.final_commit_id();
+        let index = if id.is_zero() {
+            None
+        } else if let Some(&index) = indices.get(&id) {
+            Some(index)
+        } else {
+            let commit = repo.find_commit(id).map_err(|e| format!("commit {id}: {}", e.message()))?;
+            let author = commit.author();
+            result.commits.push(BlameCommit {
+                id: id.to_string(),
+                short_id: id.to_string()[..7].to_owned(),
+                summary: commit.summary().unwrap_or_default().to_owned(),
+                author: author.name().unwrap_or_default().to_owned(),
+                email: author.email().unwrap_or_default().to_owned(),
+                time: author.when().seconds() * 1000,
+            });
+            indices.insert(id, result.commits.len() - 1);
+            Some(result.commits.len() - 1)
+        };
+        // hunks are in order and start at line 1
+        let end = hunk.final_start_line() - 1 + hunk.lines_in_hunk();
+        result.lines.resize(end.max(result.lines.len()), index);
+    }
+    Ok(result)
+}
+
+fn auto_commit_message(template: &str, files: &BTreeSet<String>) -> String {
+    let names: Vec<_> = files
+        .iter()
+        .map(|f| f.rsplit_once('/').map_or(f.as_str(), |(_, name)| name))
+        .collect();
+    let date = time::OffsetDateTime::now_local()
+        .unwrap_or_else(|_| time::OffsetDateTime::now_utc())
+        .format(time::macros::format_description!("[year]-[month]-[day] [hour]:[minute]"))
+        .unwrap_or_default();
+    template.replace("{files}", &names.join(", ")).replace("{date}", &date)
+}
+
+/// Commits the file at `path` alone, leaving whatever else is staged
+/// alone. Returns the commit ID, or `None` if there was nothing to do.
+fn commit_saved(path: &Path, options: &AutoCommitOptions) -> Result<Option<Oid>, String> {
+    let repo = open(&path.to_string_lossy())?;
+    if repo.state() != RepositoryState::Clean {
+        // in the middle of a merge or rebase
+        return Ok(None);
+    }
+    let relative = relative_path(&repo, &path.to_string_lossy())?;
+    if repo.status_should_ignore(&relative).unwrap_or(false) {
+        return Ok(None);
+    }
+    let file = to_slashes(&relative);
+
+    // staging it applies the filters, like line ending conversion
+    let mut index = repo.index().map_err(|e| format!("index: {}", e.message()))?;
+    index.add_path(&relative).map_err(|e| format!("stage {file}: {}", e.message()))?;
+    index.write().map_err(|e| format!("write index: {}", e.message()))?;
+    let entry = index.get_path(&relative, 0).ok_or_else(|| format!("{file} is not staged"))?;
+    let mode = if entry.mode == 0o100_755 { FileMode::BlobExecutable } else { FileMode::Blob };
+
+    let head = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let mut last = LAST_AUTO_COMMIT.lock().expect("auto commit lock poisoned");
+    let window = Duration::from_secs(options.coalesce_seconds);
+    let coalesce = last.as_ref().filter(|last| {
+        head.as_ref().is_some_and(|h| h.id() == last.id)
+            && last.workdir == workdir(&repo).unwrap_or(Path::new(""))
+            && last.at.elapsed() < window
+            && !published(&repo, last.id)
+    });
+    let mut files = coalesce.map(|last| last.files.clone()).unwrap_or_default();
+    files.insert(file);
+
+    let baseline = match &head {
+        Some(head) => head.tree(),
+        None => repo
+            .treebuilder(None)
+            .and_then(|builder| builder.write())
+            .and_then(|id| repo.find_tree(id)),
+    }.map_err(|e| format!("tree: {}", e.message()))?;
+    let tree_id = TreeUpdateBuilder::new()
+        .upsert(relative.as_path(), entry.id, mode)
+        .create_updated(&repo, &baseline)
+        .map_err(|e| format!("tree: {}", e.message()))?;
+    if tree_id == baseline.id() {
+        return Ok(None);
+    }
+    let tree = repo.find_tree(tree_id).map_err(|e| format!("tree: {}", e.message()))?;
+    let signature = repo
+        .signature()
+        .map_err(|e| format!("no author; set user.name and user.email in git config: {}", e.message()))?;
+    let template = options.message.as_deref().unwrap_or(DEFAULT_AUTO_COMMIT_MESSAGE);
+    let message = auto_commit_message(template, &files);
+    let id = match (&head, coalesce) {
+        (Some(head), Some(_)) => head.amend(Some("HEAD"), None, Some(&signature), None, Some(&message), Some(&tree)),
+        _ => {
+            let parents: Vec<_> = head.iter().collect();
+            repo.commit(Some("HEAD"), &signature, &signature, &message, &tree, &parents)
+        }
+    }.map_err(|e| format!("commit: {}", e.message()))?;
+
+    // the first save of a window starts it
+    let at = coalesce.map_or_else(Instant::now, |last| last.at);
+    *last = Some(AutoCommit { workdir: workdir(&repo)?.to_owned(), id, at, files });
+    Ok(Some(id))
+}
+
+/// Whether a remote branch has commit `id`, so it was pushed and mustn't
+/// be amended.
+fn published(repo: &Repository, id: Oid) -> bool {
+    let Ok(branches) = repo.branches(Some(BranchType::Remote)) else { return true };
+    branches.flatten().filter_map(|(branch, _)| branch.get().target()).any(|target| {
+        target == id || repo.graph_descendant_of(target, id).unwrap_or(true)
+    })
+}
+
+/// Commits the document just saved at `path`, if turned on and it's in a
+/// repository. Failures are logged rather than failing the save.
+pub(crate) fn auto_commit(path: &Path) {
+    let Some(options) = AUTO_COMMIT.read().expect("auto commit lock poisoned").clone() else {
+        return;
+    };
+    if !options.enabled {
+        return;
+    }
+    match commit_saved(path, &options) {
+        Ok(Some(id)) => log::info!("auto_commit: {} in {id}", path.display()),
+        Ok(None) => {}
+        Err(e) => log::warn!("auto_commit: {}: {e}", path.display()),
+    }
+}
+
+/// The branch and the changed files of the repository `workspace` is in.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_status(workspace: String) -> Result<GitStatus, BackendError> {
+    let result = tokio::task::spawn_blocking(move || status(&workspace)).await;
+    match result {
+        Ok(Ok(status)) => Ok(status),
+        Ok(Err(e)) => Err(format!("git_status task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Stages `paths` and commits everything staged with `message`; returns
+/// the commit ID.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_commit(workspace: String, paths: Vec<String>, message: String) -> Result<String, BackendError> {
+    log::info!("git_commit start: {} paths in {workspace}", paths.len());
+    if message.trim().is_empty() {
+        return Err(BackendError::invalid("git_commit: the commit message is empty"));
+    }
+    let result = tokio::task::spawn_blocking(move || commit(&open(&workspace)?, &paths, &message)).await;
+    match result {
+        Ok(Ok(id)) => {
+            log::info!("git_commit done: {id}");
+            Ok(id)
+        }
+        Ok(Err(e)) => Err(format!("git_commit task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// The newest `limit` (100 by default) commits that changed the file at
+/// `path`, following it through renames. Like all the paths below, `path`
+/// is absolute.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_log(path: String, limit: Option<usize>) -> Result<Vec<LogEntry>, BackendError> {
+    let result = tokio::task::spawn_blocking(move || {
+        log(&path, limit.unwrap_or(DEFAULT_LOG_LIMIT))
+    }).await;
+    match result {
+        Ok(Ok(entries)) => Ok(entries),
+        Ok(Err(e)) => Err(format!("git_log task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// The contents of the file at `path` in `rev`, a commit ID or anything
+/// else git understands, like `HEAD~2`. For a file that was renamed since,
+/// `path` is the path from its [`LogEntry`].
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_show(path: String, rev: String) -> Result<String, BackendError> {
+    let result = tokio::task::spawn_blocking(move || show(&path, &rev)).await;
+    match result {
+        Ok(Ok(content)) => Ok(content),
+        Ok(Err(e)) => Err(format!("git_show task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// How `rev` changed the file at `path`, as a unified diff; empty if it
+/// didn't.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_diff(path: String, rev: String) -> Result<String, BackendError> {
+    let result = tokio::task::spawn_blocking(move || diff(&path, &rev)).await;
+    match result {
+        Ok(Ok(diff)) => Ok(diff),
+        Ok(Err(e)) => Err(format!("git_diff task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Who last changed each line of the file at `path`. Pass the editor's
+/// `content` to account for unsaved and uncommitted edits.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_blame(path: String, content: Option<String>) -> Result<Blame, BackendError> {
+    let result = tokio::task::spawn_blocking(move || blame(&path, content.as_deref())).await;
+    match result {
+        Ok(Ok(blame)) => Ok(blame),
+        Ok(Err(e)) => Err(format!("git_blame task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Sets up committing on every save by `save_document`.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn configure_auto_commit(options: AutoCommitOptions) {
+    log::info!("configure_auto_commit: {}", if options.enabled { "on" } else { "off" });
+    *AUTO_COMMIT.write().expect("auto commit lock poisoned") = Some(options);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn pushed_auto_commits_are_not_amended() {
+        let root = std::env::temp_dir().join(format!("emmm-auto-commit-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let repo = Repository::init(&root).expect("a repository");
+        let mut config = repo.config().expect("a config");
+        config.set_str("user.name", "Tester").expect("a name");
+        config.set_str("user.email", "tester@example.com").expect("an email");
+        let options = AutoCommitOptions { enabled: true, message: None, coalesce_seconds: 300 };
+        let note = root.join("note.md");
+        let count = || {
+            let mut walk = repo.revwalk().expect("a walk");
+            walk.push_head().expect("a head");
+            walk.count()
+        };
+
+        fs::write(&note, "one").expect("saved");
+        commit_saved(&note, &options).expect("committed");
+        fs::write(&note, "two").expect("saved");
+        let amended = commit_saved(&note, &options).expect("committed").expect("a commit");
+        assert_eq!(count(), 1);
+
+        repo.reference("refs/remotes/origin/main", amended, true, "pushed").expect("a remote branch");
+        fs::write(&note, "three").expect("saved");
+        commit_saved(&note, &options).expect("committed");
+        assert_eq!(count(), 2);
+        fs::remove_dir_all(&root).expect("cleaned up");
+    }
+
+    #[test]
+    fn history_follows_renames_with_absolute_paths() {
+        let root = std::env::temp_dir().join(format!("emmm-git-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let repo = Repository::init(&root).expect("a repository");
+        let mut config = repo.config().expect("a config");
+        config.set_str("user.name", "Tester").expect("a name");
+        config.set_str("user.email", "tester@example.com").expect("an email");
+        fs::write(root.join("old.md"), "one\n").expect("saved");
+        commit(&repo, &["old.md".to_owned()], "add").expect("committed");
+        fs::rename(root.join("old.md"), root.join("new.md")).expect("renamed");
+        commit(&repo, &["old.md".to_owned(), "new.md".to_owned()], "rename").expect("committed");
+
+        let new = root.join("new.md").to_string_lossy().into_owned();
+        let entries = log(&new, 10).expect("a log");
+        assert_eq!(entries.iter().map(|e| e.summary.as_str()).collect::<Vec<_>>(), ["rename", "add"]);
+        assert_eq!(entries[1].path, root.join("old.md").to_string_lossy());
+        assert_eq!(show(&entries[1].path, &entries[1].id).expect("the old file"), "one\n");
+
+        assert!(log("new.md", 10).is_err());
+        assert!(relative_path(&repo, "../new.md").is_err());
+        fs::remove_dir_all(&root).expect("cleaned up");
+    }
+}
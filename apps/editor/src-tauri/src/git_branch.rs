@@ -0,0 +1,274 @@
+//! Branches of the workspace repository, for trying things out on a draft.
+//!
+//! Checking out never overwrites uncommitted changes. If there are any, the
+//! checkout either stops and lists them, so they can be saved and committed
+//! first, or stashes them for the branch they were made on; checking that
+//! branch out again brings them back.
+
+use git2::{build::CheckoutBuilder, BranchType, Oid, Repository, RepositoryState, StatusOptions};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::BackendError, git};
+
+/// Marks the stashes made here, followed by the branch they belong to.
+const AUTOSTASH: &str = "emmm autostash on";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Branch {
+    name: String,
+    current: bool,
+    /// The remote-tracking branch, e.g. `origin/main`.
+    upstream: Option<String>,
+    /// The commit the branch is at.
+    head: String,
+    summary: String,
+    /// Ms since the epoch, of the last commit.
+    time: i64,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum OnDirty {
+    /// Don't check out; list the changed files instead.
+    #[default]
+    Refuse,
+    /// Stash the changes until the current branch is checked out again.
+    Stash,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum CheckoutResult {
+    CheckedOut {
+        /// Whether changes were stashed for the previous branch.
+        stashed: bool,
+        /// Whether changes stashed for this branch were brought back. If
+        /// they conflict with the branch they stay stashed.
+        restored: bool,
+    },
+    /// Nothing was done because of uncommitted changes to these files.
+    Dirty { files: Vec<String> },
+}
+
+fn branches(workspace: &str) -> Result<Vec<Branch>, String> {
+    let repo = git::open(workspace)?;
+    let current = git::branch(&repo);
+    let mut result = Vec::new();
+    let all = repo
+        .branches(Some(BranchType::Local))
+        .map_err(|e| format!("branches: {}", e.message()))?;
+    for branch in all {
+        let (branch, _) = branch.map_err(|e| format!("branch: {}", e.message()))?;
+        let Some(name) = branch.name().ok().flatten().map(str::to_owned) else {
+            continue;
+        };
+        let commit = branch
+            .get()
+            .peel_to_commit()
+            .map_err(|e| format!("{name}: {}", e.message()))?;
+        let upstream = branch
+            .upstream()
+            .ok()
+            .and_then(|u| u.name().ok().flatten().map(str::to_owned));
+        result.push(Branch {
+            current: current.as_ref() == Some(&name),
+            name,
+            upstream,
+            head: commit.id().to_string(),
+            summary: commit.summary().unwrap_or_default().to_owned(),
+            time: commit.time().seconds() * 1000,
+        });
+    }
+    result.sort_by_key(|branch| std::cmp::Reverse(branch.time));
+    Ok(result)
+}
+
+fn create_branch(workspace: &str, name: &str, start: Option<&str>) -> Result<Branch, String> {
+    let repo = git::open(workspace)?;
+    if !git2::Branch::name_is_valid(name).unwrap_or(false) {
+        return Err(format!("{name} is not a valid branch name"));
+    }
+    let start = start.unwrap_or("HEAD");
+    let commit = repo
+        .revparse_single(start)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|e| format!("{start}: {}", e.message()))?;
+    repo.branch(name, &commit, false)
+        .map_err(|e| format!("create {name}: {}", e.message()))?;
+    Ok(Branch {
+        name: name.to_owned(),
+        current: false,
+        upstream: None,
+        head: commit.id().to_string(),
+        summary: commit.summary().unwrap_or_default().to_owned(),
+        time: commit.time().seconds() * 1000,
+    })
+}
+
+/// Files with changes a checkout could lose; untracked ones stay where
+/// they are, so they don't count.
+fn dirty_files(repo: &Repository) -> Result<Vec<String>, String> {
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+    let statuses = repo
+        .statuses(Some(&mut options))
+        .map_err(|e| format!("status: {}", e.message()))?;
+    Ok(statuses.iter().filter_map(|entry| entry.path().map(str::to_owned)).collect())
+}
+
+/// The newest stash made for `branch`.
+fn find_autostash(repo: &mut Repository, branch: &str) -> Option<usize> {
+    let suffix = format!("{AUTOSTASH} {branch}");
+    let mut found = None;
+    let _ = repo.stash_foreach(|index, message, _| {
+        if message.ends_with(&suffix) {
+            found = Some(index);
+        }
+        found.is_none()
+    });
+    found
+}
+
+fn checkout(workspace: &str, name: &str, on_dirty: OnDirty) -> Result<CheckoutResult, String> {
+    let mut repo = git::open(workspace)?;
+    if repo.state() != RepositoryState::Clean {
+        return Err("finish the merge or rebase in progress first".to_owned());
+    }
+    let current = git::branch(&repo);
+    if current.as_deref() == Some(name) {
+        return Ok(CheckoutResult::CheckedOut { stashed: false, restored: false });
+    }
+    let (refname, target): (String, Oid) = {
+        let branch = repo
+            .find_branch(name, BranchType::Local)
+            .map_err(|e| format!("branch {name}: {}", e.message()))?;
+        let reference = branch.get();
+        let commit = reference.peel_to_commit().map_err(|e| format!("{name}: {}", e.message()))?;
+        (reference.name().ok_or("branch name is not UTF-8".to_owned())?.to_owned(), commit.id())
+    };
+
+    let dirty = dirty_files(&repo)?;
+    let stashed = !dirty.is_empty();
+    if stashed {
+        if on_dirty == OnDirty::Refuse {
+            return Ok(CheckoutResult::Dirty { files: dirty });
+        }
+        let signature = repo
+            .signature()
+            .map_err(|e| format!("no author; set user.name and user.email in git config: {}", e.message()))?;
+        let message = format!("{AUTOSTASH} {}", current.as_deref().unwrap_or("HEAD"));
+        repo.stash_save(&signature, &message, None)
+            .map_err(|e| format!("stash: {}", e.message()))?;
+    }
+
+    let switched = repo
+        .find_object(target, None)
+        .and_then(|object| repo.checkout_tree(&object, Some(CheckoutBuilder::new().safe())))
+        .and_then(|()| repo.set_head(&refname));
+    if let Err(e) = switched {
+        if stashed {
+            let _ = repo.stash_pop(0, None);
+        }
+        return Err(format!("checkout {name}: {}", e.message()));
+    }
+
+    let restored = match find_autostash(&mut repo, name) {
+        Some(index) => repo.stash_pop(index, None).is_ok(),
+        None => false,
+    };
+    Ok(CheckoutResult::CheckedOut { stashed, restored })
+}
+
+/// Local branches, the most recently committed to first.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_branches(workspace: String) -> Result<Vec<Branch>, BackendError> {
+    let result = tokio::task::spawn_blocking(move || branches(&workspace)).await;
+    match result {
+        Ok(Ok(branches)) => Ok(branches),
+        Ok(Err(e)) => Err(format!("git_branches task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Creates `name` at `start`, a commit ID, branch or e.g. `HEAD~2`, or at
+/// the current commit. Doesn't check it out.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_create_branch(workspace: String, name: String, start: Option<String>) -> Result<Branch, BackendError> {
+    log::info!("git_create_branch start: {name}");
+    let result = tokio::task::spawn_blocking(move || create_branch(&workspace, &name, start.as_deref())).await;
+    match result {
+        Ok(Ok(branch)) => {
+            log::info!("git_create_branch done");
+            Ok(branch)
+        }
+        Ok(Err(e)) => Err(format!("git_create_branch task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Switches to the branch `name`. Open documents should be saved first so
+/// their changes are seen.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_checkout(
+    workspace: String, name: String, on_dirty: Option<OnDirty>,
+) -> Result<CheckoutResult, BackendError> {
+    log::info!("git_checkout start: {name}");
+    let result =
+        tokio::task::spawn_blocking(move || checkout(&workspace, &name, on_dirty.unwrap_or_default())).await;
+    match result {
+        Ok(Ok(result)) => {
+            log::info!("git_checkout done");
+            Ok(result)
+        }
+        Ok(Err(e)) => Err(format!("git_checkout task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn changes_are_stashed_for_their_branch() {
+        let root = std::env::temp_dir().join(format!("emmm-git-branch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let repo = Repository::init(&root).expect("a repository");
+        let mut config = repo.config().expect("a config");
+        config.set_str("user.name", "Tester").expect("a name");
+        config.set_str("user.email", "tester@example.com").expect("an email");
+        let note = root.join("note.md");
+        fs::write(&note, "one\n").expect("saved");
+        git::commit(&repo, &["note.md".to_owned()], "add").expect("committed");
+        let workspace = root.to_string_lossy().into_owned();
+        let main = git::branch(&repo).expect("a branch");
+
+        assert!(create_branch(&workspace, "a..b", None).is_err());
+        let draft = create_branch(&workspace, "draft", None).expect("a branch");
+        assert!(!draft.current);
+        assert!(matches!(checkout(&workspace, "draft", OnDirty::Refuse),
+            Ok(CheckoutResult::CheckedOut { stashed: false, restored: false })));
+
+        fs::write(&note, "draft\n").expect("saved");
+        let Ok(CheckoutResult::Dirty { files }) = checkout(&workspace, &main, OnDirty::Refuse) else {
+            panic!("checked out over changes");
+        };
+        assert_eq!(files, ["note.md"]);
+        assert!(matches!(checkout(&workspace, &main, OnDirty::Stash),
+            Ok(CheckoutResult::CheckedOut { stashed: true, restored: false })));
+        assert_eq!(fs::read_to_string(&note).expect("the note"), "one\n");
+        assert!(matches!(checkout(&workspace, "draft", OnDirty::Refuse),
+            Ok(CheckoutResult::CheckedOut { stashed: false, restored: true })));
+        assert_eq!(fs::read_to_string(&note).expect("the note"), "draft\n");
+
+        let names: Vec<_> = branches(&workspace).expect("branches").into_iter().map(|b| (b.name, b.current)).collect();
+        assert!(names.contains(&("draft".to_owned(), true)) && names.contains(&(main, false)), "{names:?}");
+        fs::remove_dir_all(&root).expect("cleaned up");
+    }
+}
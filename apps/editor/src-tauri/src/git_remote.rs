@@ -0,0 +1,361 @@
+//! Pulling and pushing the workspace repository. Credentials come from the
+//! keychain (an HTTPS token or the passphrase of an SSH key), the SSH agent
+//! or git's credential helper, in that order.
+
+use std::{
+    cell::{Cell, RefCell},
+    path::Path,
+};
+
+use git2::{
+    build::CheckoutBuilder, AnnotatedCommit, Cred, CredentialType, FetchOptions, PushOptions,
+    RemoteCallbacks, Repository,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::BackendError,
+    git,
+    jobs::{self, JobKind},
+    secrets, send,
+    workers::Priority,
+    BackendEvent, EventChannel,
+};
+
+/// libgit2 asks again after rejected credentials; give up eventually.
+const MAX_CREDENTIAL_ATTEMPTS: u32 = 4;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RemoteOptions {
+    /// `origin` by default.
+    remote: Option<String>,
+    /// The current branch by default.
+    branch: Option<String>,
+    /// For HTTPS; some servers want a particular one with tokens, e.g.
+    /// `oauth2` for GitLab.
+    username: Option<String>,
+    /// Keychain entry with the HTTPS token, or the passphrase of
+    /// `ssh_key_path`.
+    secret_name: Option<String>,
+    /// A private key to use instead of the SSH agent.
+    ssh_key_path: Option<String>,
+    /// Lets a pull merge when the branches have diverged, instead of
+    /// failing as it does by default.
+    allow_merge: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PullOutcome {
+    UpToDate,
+    FastForward,
+    Merged,
+    /// The merge stopped with conflicts to resolve and commit.
+    Conflicts,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PullSummary {
+    outcome: PullOutcome,
+    /// The commit the branch is at now.
+    head: Option<String>,
+    /// Files left with conflicts, relative to the repository root.
+    conflicts: Vec<String>,
+}
+
+impl RemoteOptions {
+    fn remote(&self) -> &str {
+        self.remote.as_deref().unwrap_or("origin")
+    }
+
+    fn branch(&self, repo: &Repository) -> Result<String, String> {
+        match &self.branch {
+            Some(branch) => Ok(branch.clone()),
+            None => git::branch(repo).ok_or("not on a branch".to_owned()),
+        }
+    }
+}
+
+fn callbacks<'a>(
+    repo: &'a Repository, options: &'a RemoteOptions, channel: &'a EventChannel,
+) -> Result<RemoteCallbacks<'a>, String> {
+    let secret = match &options.secret_name {
+        Some(name) => Some(secrets::read(name)?.ok_or_else(|| format!("no secret stored as {name}"))?),
+        None => None,
+    };
+    let attempts = Cell::new(0);
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username_from_url, allowed| {
+        attempts.set(attempts.get() + 1);
+        if attempts.get() > MAX_CREDENTIAL_ATTEMPTS {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+        let username = options.username.as_deref().or(username_from_url).unwrap_or("git");
+        if allowed.contains(CredentialType::SSH_KEY) {
+            return match &options.ssh_key_path {
+                Some(key) => Cred::ssh_key(username, None, Path::new(key), secret.as_deref()),
+                None => Cred::ssh_key_from_agent(username),
+            };
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if let (Some(token), 1) = (&secret, attempts.get()) {
+                return Cred::userpass_plaintext(username, token);
+            }
+            let config = repo.config()?;
+            return Cred::credential_helper(&config, url, options.username.as_deref().or(username_from_url));
+        }
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(username);
+        }
+        Cred::default()
+    });
+    // returning false stops the fetch when the job is cancelled
+    callbacks.transfer_progress(|progress| {
+        let (done, total) = (progress.received_objects(), progress.total_objects());
+        jobs::progress(done, total, "receiving");
+        send(channel, BackendEvent::Progress { done, total, message: "receiving".to_owned() });
+        jobs::checkpoint().is_ok()
+    });
+    callbacks.push_transfer_progress(|current, total, _| {
+        jobs::progress(current, total, "sending");
+        send(channel, BackendEvent::Progress { done: current, total, message: "sending".to_owned() });
+    });
+    Ok(callbacks)
+}
+
+fn conflicted_paths(repo: &Repository) -> Result<Vec<String>, String> {
+    let index = repo.index().map_err(|e| format!("index: {}", e.message()))?;
+    let conflicts = index.conflicts().map_err(|e| format!("conflicts: {}", e.message()))?;
+    Ok(conflicts
+        .filter_map(Result::ok)
+        .filter_map(|c| c.our.or(c.their).or(c.ancestor))
+        .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+        .collect())
+}
+
+/// Commits the merge of `theirs` that is in the index.
+fn commit_merge(repo: &Repository, theirs: &AnnotatedCommit, message: &str) -> Result<git2::Oid, String> {
+    let mut index = repo.index().map_err(|e| format!("index: {}", e.message()))?;
+    let tree_id = index.write_tree().map_err(|e| format!("write tree: {}", e.message()))?;
+    let tree = repo.find_tree(tree_id).map_err(|e| format!("find tree: {}", e.message()))?;
+    let ours = repo
+        .head()
+        .and_then(|h| h.peel_to_commit())
+        .map_err(|e| format!("HEAD: {}", e.message()))?;
+    let theirs = repo.find_commit(theirs.id()).map_err(|e| format!("commit: {}", e.message()))?;
+    let signature = repo
+        .signature()
+        .map_err(|e| format!("no author; set user.name and user.email in git config: {}", e.message()))?;
+    let id = repo
+        .commit(Some("HEAD"), &signature, &signature, message, &tree, &[&ours, &theirs])
+        .map_err(|e| format!("commit: {}", e.message()))?;
+    repo.cleanup_state().map_err(|e| format!("cleanup: {}", e.message()))?;
+    Ok(id)
+}
+
+fn pull(workspace: &str, options: &RemoteOptions, channel: &EventChannel) -> Result<PullSummary, String> {
+    let repo = git::open(workspace)?;
+    let branch = options.branch(&repo)?;
+    if git::branch(&repo).as_ref() != Some(&branch) {
+        return Err(format!("check out {branch} to pull it"));
+    }
+    let mut remote = repo
+        .find_remote(options.remote())
+        .map_err(|e| format!("remote {}: {}", options.remote(), e.message()))?;
+    let mut fetch = FetchOptions::new();
+    fetch.remote_callbacks(callbacks(&repo, options, channel)?);
+    let fetched = remote.fetch(&[&branch], Some(&mut fetch), None);
+    // a cancelled job stopped the fetch; fail with that rather than libgit2's error
+    jobs::checkpoint()?;
+    fetched.map_err(|e| format!("fetch: {}", e.message()))?;
+
+    let tracking = format!("refs/remotes/{}/{branch}", options.remote());
+    let theirs_ref = repo
+        .find_reference(&tracking)
+        .map_err(|e| format!("{tracking}: {}", e.message()))?;
+    let theirs = repo
+        .reference_to_annotated_commit(&theirs_ref)
+        .map_err(|e| format!("{tracking}: {}", e.message()))?;
+    let (analysis, _) = repo
+        .merge_analysis(&[&theirs])
+        .map_err(|e| format!("merge analysis: {}", e.message()))?;
+    let local = format!("refs/heads/{branch}");
+    let summary = |outcome, conflicts| PullSummary {
+        outcome,
+        head: repo.refname_to_id(&local).ok().map(|id| id.to_string()),
+        conflicts,
+    };
+
+    if analysis.is_up_to_date() {
+        return Ok(summary(PullOutcome::UpToDate, Vec::new()));
+    }
+    if analysis.is_fast_forward() || analysis.is_unborn() {
+        let target = repo.find_object(theirs.id(), None).map_err(|e| format!("object: {}", e.message()))?;
+        // fails rather than overwrite uncommitted changes
+        repo.checkout_tree(&target, Some(CheckoutBuilder::new().safe()))
+            .map_err(|e| format!("checkout: {}; commit or stash your changes first", e.message()))?;
+        let message = format!("pull: fast-forward to {}", theirs.id());
+        match repo.find_reference(&local) {
+            Ok(mut reference) => reference.set_target(theirs.id(), &message).map(|_| ()),
+            Err(_) => repo.reference(&local, theirs.id(), false, &message).map(|_| ()),
+        }.map_err(|e| format!("update {local}: {}", e.message()))?;
+        repo.set_head(&local).map_err(|e| format!("HEAD: {}", e.message()))?;
+        return Ok(summary(PullOutcome::FastForward, Vec::new()));
+    }
+    if !options.allow_merge {
+        return Err(format!("{branch} and {}/{branch} have diverged; allow merging to pull", options.remote()));
+    }
+    if repo.state() != git2::RepositoryState::Clean {
+        return Err("finish the merge or rebase in progress first".to_owned());
+    }
+    jobs::checkpoint()?;
+    repo.merge(&[&theirs], None, Some(CheckoutBuilder::new().safe()))
+        .map_err(|e| format!("merge: {}", e.message()))?;
+    let conflicts = conflicted_paths(&repo)?;
+    if !conflicts.is_empty() {
+        return Ok(summary(PullOutcome::Conflicts, conflicts));
+    }
+    let message = format!("Merge {}/{branch} into {branch}", options.remote());
+    commit_merge(&repo, &theirs, &message)?;
+    Ok(summary(PullOutcome::Merged, Vec::new()))
+}
+
+fn push(workspace: &str, options: &RemoteOptions, channel: &EventChannel) -> Result<(), String> {
+    let repo = git::open(workspace)?;
+    let branch = options.branch(&repo)?;
+    let mut remote = repo
+        .find_remote(options.remote())
+        .map_err(|e| format!("remote {}: {}", options.remote(), e.message()))?;
+    let rejected = RefCell::new(None);
+    let mut callbacks = callbacks(&repo, options, channel)?;
+    callbacks.push_update_reference(|reference, status| {
+        if let Some(status) = status {
+            *rejected.borrow_mut() = Some(format!("{reference} was rejected: {status}"));
+        }
+        Ok(())
+    });
+    let mut push = PushOptions::new();
+    push.remote_callbacks(callbacks);
+    // without a leading + the server refuses anything but a fast-forward
+    let refspec = format!("refs/heads/{branch}:refs/heads/{branch}");
+    remote
+        .push(&[&refspec], Some(&mut push))
+        .map_err(|e| format!("push: {}", e.message()))?;
+    drop(push);
+    match rejected.into_inner() {
+        Some(e) => Err(format!("{e}; pull first")),
+        None => Ok(()),
+    }
+}
+
+/// Fetches the branch and fast-forwards to it, reporting `Progress` events
+/// and then `Done`. If the branches have diverged this fails unless merging
+/// is allowed.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_pull(
+    workspace: String, options: RemoteOptions, channel: EventChannel,
+) -> Result<PullSummary, BackendError> {
+    log::info!("git_pull start: {workspace}");
+    let job = jobs::start_as(channel.job_id(), JobKind::Sync, Priority::Interactive, &workspace);
+    let result = tokio::task::spawn_blocking(move || job.run(|| {
+        let summary = pull(&workspace, &options, &channel)?;
+        send(&channel, BackendEvent::Done);
+        Ok(summary)
+    })).await;
+
+    match result {
+        Ok(Ok(summary)) => {
+            log::info!("git_pull done");
+            Ok(summary)
+        }
+        Ok(Err(e)) => Err(format!("git_pull task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Pushes the branch, reporting `Progress` events and then `Done`. Never
+/// forces: the push fails if the remote has commits that aren't here.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn git_push(
+    workspace: String, options: RemoteOptions, channel: EventChannel,
+) -> Result<(), BackendError> {
+    log::info!("git_push start: {workspace}");
+    let job = jobs::start_as(channel.job_id(), JobKind::Sync, Priority::Interactive, &workspace);
+    let result = tokio::task::spawn_blocking(move || job.run(|| {
+        push(&workspace, &options, &channel)?;
+        send(&channel, BackendEvent::Done);
+        Ok(())
+    })).await;
+
+    match result {
+        Ok(Ok(())) => {
+            log::info!("git_push done");
+            Ok(())
+        }
+        Ok(Err(e)) => Err(format!("git_push task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn clone(origin: &Path, path: &Path) -> Repository {
+        let repo = Repository::clone(&origin.to_string_lossy(), path).expect("a clone");
+        let mut config = repo.config().expect("a config");
+        config.set_str("user.name", "Tester").expect("a name");
+        config.set_str("user.email", "tester@example.com").expect("an email");
+        repo
+    }
+
+    fn save(repo: &Repository, name: &str, content: &str) {
+        fs::write(repo.workdir().expect("a workdir").join(name), content).expect("saved");
+        git::commit(repo, &[name.to_owned()], name).expect("committed");
+    }
+
+    #[test]
+    fn pulls_fast_forward_and_merge_only_when_allowed() {
+        let root = std::env::temp_dir().join(format!("emmm-git-remote-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let origin = root.join("origin.git");
+        Repository::init_bare(&origin).expect("a bare repository");
+        let (channel, _) = EventChannel::recording();
+        let options = RemoteOptions::default();
+
+        let a = clone(&origin, &root.join("a"));
+        let a_path = root.join("a").to_string_lossy().into_owned();
+        save(&a, "one.md", "one\n");
+        push(&a_path, &options, &channel).expect("pushed");
+        let b = clone(&origin, &root.join("b"));
+        let b_path = root.join("b").to_string_lossy().into_owned();
+
+        save(&a, "two.md", "two\n");
+        push(&a_path, &options, &channel).expect("pushed");
+        let pulled = pull(&b_path, &options, &channel).expect("pulled");
+        assert!(matches!(pulled.outcome, PullOutcome::FastForward));
+        assert_eq!(pulled.head, a.refname_to_id("HEAD").ok().map(|id| id.to_string()));
+        assert_eq!(fs::read_to_string(root.join("b/two.md")).expect("pulled file"), "two\n");
+        assert!(matches!(pull(&b_path, &options, &channel).expect("pulled").outcome, PullOutcome::UpToDate));
+
+        save(&a, "three.md", "three\n");
+        push(&a_path, &options, &channel).expect("pushed");
+        save(&b, "four.md", "four\n");
+        let diverged = pull(&b_path, &options, &channel).err().expect("an error");
+        assert!(diverged.contains("have diverged"), "{diverged}");
+        assert!(push(&b_path, &options, &channel).is_err());
+
+        let merging = RemoteOptions { allow_merge: true, ..RemoteOptions::default() };
+        let merged = pull(&b_path, &merging, &channel).expect("merged");
+        assert!(matches!(merged.outcome, PullOutcome::Merged));
+        assert!(root.join("b/three.md").exists() && root.join("b/four.md").exists());
+        push(&b_path, &options, &channel).expect("pushed");
+        fs::remove_dir_all(&root).expect("cleaned up");
+    }
+}
@@ -0,0 +1,221 @@
+//! Hierarchical numbers in headings, like "1.", "1.1" and "1.1.1", for
+//! specs and reports. Only ATX headings (`## Title`) are numbered. The
+//! numbers are followed by a no-break space, which marks them as ours: only
+//! those are replaced or stripped, so numbers in titles like "## 3.5 inch
+//! drives" are left alone. So are numbers followed by a space, unless
+//! they're exactly the number the heading would get.
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::frontmatter;
+
+/// Between a number and the heading text.
+const MARKER: char = '\u{a0}';
+
+/// A number before the heading text: "1. ", "1.2\u{a0}"...
+static NUMBER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^((?:\d+\.)+\d*)(?:(\x{a0})|[ \t]+)").expect("heading number regex"));
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NumberingOptions {
+    /// Only headings starting in this range are changed, numbered on from
+    /// the headings before it; the whole document by default. In UTF-16
+    /// code units from the start of the text, like JavaScript string
+    /// indices.
+    from: Option<usize>,
+    to: Option<usize>,
+    /// The level numbered "1.", "2."...; by default the highest level of
+    /// the headings, not counting a lone level 1 title.
+    top_level: Option<u8>,
+    /// Deeper headings aren't numbered; 6 by default.
+    max_level: Option<u8>,
+}
+
+struct HeadingLine {
+    level: u8,
+    /// Byte offset of the line.
+    start: usize,
+    /// Byte offset of the text after the `#`s.
+    text_start: usize,
+}
+
+fn byte_offset(text: &str, utf16: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        if units >= utf16 {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// The ATX headings outside code and the frontmatter.
+fn heading_lines(text: &str) -> Vec<HeadingLine> {
+    let mut at = frontmatter::split(text).map_or(0, |(_, body)| text.len() - body.len());
+    let mut result = Vec::new();
+    let mut fenced = false;
+    for line in text[at..].split_inclusive('\n') {
+        let start = at;
+        at += line.len();
+        let trimmed = line.trim_start_matches(' ');
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fenced = !fenced;
+            continue;
+        }
+        if fenced || line.len() - trimmed.len() > 3 {
+            continue;
+        }
+        let hashes = trimmed.len() - trimmed.trim_start_matches('#').len();
+        let after = &trimmed[hashes..];
+        if !(1..=6).contains(&hashes) || !after.starts_with([' ', '\t', '\n', '\r']) && !after.is_empty() {
+            continue;
+        }
+        let text_start = at - after.trim_start_matches([' ', '\t']).len();
+        #[allow(clippy::cast_possible_truncation)]
+        result.push(HeadingLine { level: hashes as u8, start, text_start });
+    }
+    result
+}
+
+enum Expected {
+    /// Above the top level, which starts the numbers over.
+    Chapter,
+    /// Below the maximum level, so it has none.
+    TooDeep,
+    /// Without what follows it.
+    Number(String),
+}
+
+impl Expected {
+    fn number(&self) -> Option<&str> {
+        match self {
+            Expected::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+}
+
+/// The number each heading gets.
+fn expected_numbers(headings: &[HeadingLine], options: &NumberingOptions) -> Vec<Expected> {
+    let top = options.top_level.unwrap_or_else(|| {
+        let titles = headings.iter().filter(|h| h.level == 1).count();
+        let lone_title = titles == 1 && headings.first().is_some_and(|h| h.level == 1);
+        headings.iter().filter(|h| !(lone_title && h.level == 1)).map(|h| h.level).min().unwrap_or(1)
+    });
+    let max = options.max_level.unwrap_or(6);
+    let mut counters = [0u32; 6];
+    headings
+        .iter()
+        .map(|heading| {
+            if heading.level < top {
+                // a new chapter starts over
+                counters = [0; 6];
+                return Expected::Chapter;
+            }
+            if heading.level > max {
+                return Expected::TooDeep;
+            }
+            let depth = usize::from(heading.level - top);
+            counters[depth] += 1;
+            counters[depth + 1..].fill(0);
+            let parts: Vec<String> = counters[..=depth].iter().map(u32::to_string).collect();
+            Expected::Number(if depth == 0 { format!("{}.", parts[0]) } else { parts.join(".") })
+        })
+        .collect()
+}
+
+/// How long the number at the start of `rest` is, with what follows it, if
+/// it's one we wrote or exactly `expected`.
+fn our_number(rest: &str, expected: Option<&str>) -> usize {
+    NUMBER.captures(rest).map_or(0, |captures| {
+        let marked = captures.get(2).is_some();
+        if marked || expected == Some(&captures[1]) {
+            captures[0].len()
+        } else {
+            0
+        }
+    })
+}
+
+/// `text` with the headings starting in the range of `options` given the
+/// number `f` returns for what they're expected to have, in place of ours,
+/// or left as they are for `None`.
+fn rewrite(text: &str, options: &NumberingOptions, f: impl Fn(&Expected) -> Option<String>) -> String {
+    let from = options.from.map_or(0, |f| byte_offset(text, f));
+    let to = options.to.map_or(text.len(), |t| byte_offset(text, t));
+    // all of them, so the range carries on the numbers before it
+    let headings = heading_lines(text);
+    let expected = expected_numbers(&headings, options);
+    let mut out = String::with_capacity(text.len() + headings.len() * 4);
+    let mut done = 0;
+    for (heading, expected) in headings.iter().zip(&expected) {
+        if heading.start < from || heading.start >= to {
+            continue;
+        }
+        let Some(number) = f(expected) else { continue };
+        out.push_str(&text[done..heading.text_start]);
+        out.push_str(&number);
+        done = heading.text_start + our_number(&text[heading.text_start..], expected.number());
+    }
+    out.push_str(&text[done..]);
+    out
+}
+
+/// Numbers the headings of `text`, replacing numbers they already have.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn number_headings(text: String, options: NumberingOptions) -> String {
+    rewrite(&text, &options, |expected| match expected {
+        Expected::Chapter => None,
+        Expected::TooDeep => Some(String::new()),
+        Expected::Number(number) => Some(format!("{number}{MARKER}")),
+    })
+}
+
+/// Takes our numbers off the headings of `text`, in the range of `options`.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn strip_heading_numbers(text: String, options: NumberingOptions) -> String {
+    rewrite(&text, &options, |_| Some(String::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(text: &str, options: NumberingOptions) -> String {
+        number_headings(text.to_owned(), options)
+    }
+
+    #[test]
+    fn numbers_levels_under_a_lone_title() {
+        let text = "# Title\n\n## Intro\n\n### Scope\n\n```\n## not a heading\n```\n\n## Usage\n";
+        assert_eq!(
+            number(text, NumberingOptions::default()),
+            "# Title\n\n## 1.\u{a0}Intro\n\n### 1.1\u{a0}Scope\n\n```\n## not a heading\n```\n\n## 2.\u{a0}Usage\n"
+        );
+    }
+
+    #[test]
+    fn only_replaces_our_numbers() {
+        let text = "## 3.5 inch drives\n## 2. Old\n## 9.\u{a0}Moved\n";
+        let numbered = number(text, NumberingOptions::default());
+        assert_eq!(numbered, "## 1.\u{a0}3.5 inch drives\n## 2.\u{a0}Old\n## 3.\u{a0}Moved\n");
+        assert_eq!(number(&numbered, NumberingOptions::default()), numbered);
+        let stripped = strip_heading_numbers(numbered, NumberingOptions::default());
+        assert_eq!(stripped, "## 3.5 inch drives\n## Old\n## Moved\n");
+    }
+
+    #[test]
+    fn ranges_carry_on_the_numbers_before_them() {
+        let text = "## A\n### B\n## C\n### D\n";
+        // from the third heading
+        let options = NumberingOptions { from: Some(11), ..NumberingOptions::default() };
+        assert_eq!(number(text, options), "## A\n### B\n## 2.\u{a0}C\n### 2.1\u{a0}D\n");
+    }
+}
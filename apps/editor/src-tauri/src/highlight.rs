@@ -0,0 +1,117 @@
+use std::sync::OnceLock;
+
+use serde::Serialize;
+use syntect::{
+    easy::HighlightLines,
+    highlighting::ThemeSet,
+    html::{styled_line_to_highlighted_html, IncludeBackground},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+use crate::{
+    error::BackendError,
+    workers::{self, Priority},
+};
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightLanguage {
+    name: String,
+    extensions: Vec<String>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightOptions {
+    languages: Vec<HighlightLanguage>,
+    themes: Vec<String>,
+}
+
+pub(crate) fn highlight(source: &str, language: &str, theme: &str) -> Result<String, String> {
+    let ss = syntaxes();
+    // accepts names ("Rust") as well as extensions ("rs"); unknown
+    // languages are not an error, they just come out unhighlighted
+    let syntax = ss
+        .find_syntax_by_token(language)
+        .unwrap_or_else(|| ss.find_syntax_plain_text());
+    let theme = themes()
+        .themes
+        .get(theme)
+        .ok_or(format!("unknown theme: {theme}"))?;
+
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut out = String::new();
+    for line in LinesWithEndings::from(source) {
+        let regions = highlighter
+            .highlight_line(line, ss)
+            .map_err(|e| format!("highlight_line: {e}"))?;
+        let html = styled_line_to_highlighted_html(&regions, IncludeBackground::No)
+            .map_err(|e| format!("styled_line_to_highlighted_html: {e}"))?;
+        out.push_str(&html);
+    }
+    Ok(out)
+}
+
+/// Returns the highlighted code as a sequence of `<span style=...>`, without
+/// any enclosing `<pre>`, so the caller decides how the block is wrapped.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn highlight_code(
+    source: String, language: String, theme: String
+) -> Result<String, BackendError> {
+    log::info!("highlight_code start");
+    let result = workers::run(Priority::Interactive, move || {
+        highlight(&source, &language, &theme)
+    }).await;
+
+    match result {
+        Ok(Ok(html)) => {
+            log::info!("highlight_code done");
+            Ok(html)
+        }
+        Ok(Err(e)) => Err(format!("highlight_code task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[tauri::command]
+pub fn list_highlight_options() -> HighlightOptions {
+    let languages = syntaxes()
+        .syntaxes()
+        .iter()
+        .map(|s| HighlightLanguage {
+            name: s.name.clone(),
+            extensions: s.file_extensions.clone(),
+        })
+        .collect();
+    let themes = themes().themes.keys().cloned().collect();
+    HighlightOptions { languages, themes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_are_found_by_name_or_extension() {
+        let theme = "InspiredGitHub";
+        let by_name = highlight("fn main() {}\n", "Rust", theme).unwrap();
+        assert!(by_name.contains("<span style="), "{by_name}");
+        assert_eq!(highlight("fn main() {}\n", "rs", theme).unwrap(), by_name);
+        // unknown languages come out plain, but escaped
+        let plain = highlight("a < b\n", "no-such-language", theme).unwrap();
+        assert!(plain.contains("a &lt; b"), "{plain}");
+        assert!(highlight("x", "rs", "no-such-theme").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! Translations of the strings the backend makes up itself, like error
+//! summaries and menu labels, from the JSON catalogs in the `locales`
+//! resources: one file per locale, like `zh-CN.json`, mapping keys to
+//! messages with `{name}` placeholders.
+//!
+//! The locale is the one in the settings, or the system's if that's empty,
+//! narrowed to its language if there's no catalog for it, like `zh-TW` to
+//! `zh`. Missing messages fall back to [`FALLBACK`], then to the key.
+
+use std::{collections::HashMap, fs, path::Path, sync::RwLock};
+
+use serde::Serialize;
+
+use crate::settings;
+
+pub const FALLBACK: &str = "en";
+
+/// Messages by key, by locale.
+static CATALOGS: RwLock<Option<HashMap<String, HashMap<String, String>>>> = RwLock::new(None);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Messages {
+    locale: String,
+    /// Every message of the locale, falling back to [`FALLBACK`]'s.
+    messages: HashMap<String, String>,
+}
+
+/// Loads the catalogs in `dir`; ones that can't be read are skipped.
+pub fn load(dir: &Path) {
+    let mut catalogs = HashMap::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("i18n: read {}: {e}", dir.display());
+            return;
+        }
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension() != Some("json".as_ref()) {
+            continue;
+        }
+        let Some(locale) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+        let catalog = fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| serde_json::from_slice::<HashMap<String, String>>(&data).map_err(|e| e.to_string()));
+        match catalog {
+            Ok(catalog) => {
+                catalogs.insert(locale.to_owned(), catalog);
+            }
+            Err(e) => log::warn!("i18n: {}: {e}", path.display()),
+        }
+    }
+    log::info!("i18n: loaded {} catalogs", catalogs.len());
+    *CATALOGS.write().expect("i18n lock poisoned") = Some(catalogs);
+}
+
+/// The locale with a catalog that's closest to `wanted`, like `zh-CN` for
+/// `zh_CN.UTF-8`.
+fn negotiate(catalogs: &HashMap<String, HashMap<String, String>>, wanted: &str) -> String {
+    let wanted = wanted.split('.').next().unwrap_or_default().replace('_', "-");
+    let find = |tag: &str| catalogs.keys().find(|locale| locale.eq_ignore_ascii_case(tag)).cloned();
+    let language = wanted.split('-').next().unwrap_or_default();
+    find(&wanted)
+        .or_else(|| find(language))
+        .or_else(|| {
+            // zh-TW for zh if that's all there is
+            let prefix = format!("{}-", language.to_lowercase());
+            catalogs.keys().filter(|locale| locale.to_lowercase().starts_with(&prefix)).min().cloned()
+        })
+        .unwrap_or_else(|| FALLBACK.to_owned())
+}
+
+fn wanted_locale() -> String {
+    match settings::get().locale {
+        locale if locale.is_empty() => sys_locale::get_locale().unwrap_or_else(|| FALLBACK.to_owned()),
+        locale => locale,
+    }
+}
+
+/// The locale messages are in.
+pub fn locale() -> String {
+    match CATALOGS.read().expect("i18n lock poisoned").as_ref() {
+        Some(catalogs) => negotiate(catalogs, &wanted_locale()),
+        None => FALLBACK.to_owned(),
+    }
+}
+
+/// The message `key`, if there's one in the locale or [`FALLBACK`].
+pub fn lookup(key: &str) -> Option<String> {
+    let locale = locale();
+    let catalogs = CATALOGS.read().expect("i18n lock poisoned");
+    let catalogs = catalogs.as_ref()?;
+    [locale.as_str(), FALLBACK].iter().find_map(|locale| catalogs.get(*locale)?.get(key)).cloned()
+}
+
+/// The message `key` with the `{name}`s in `args` filled in.
+pub fn message(key: &str, args: &[(&str, &str)]) -> String {
+    let mut message = lookup(key).unwrap_or_else(|| key.to_owned());
+    for (name, value) in args {
+        message = message.replace(&format!("{{{name}}}"), value);
+    }
+    message
+}
+
+/// The messages of `locale`, or of the one in the settings, for the
+/// frontend.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn get_messages(locale: Option<String>) -> Messages {
+    let catalogs = CATALOGS.read().expect("i18n lock poisoned");
+    let Some(catalogs) = catalogs.as_ref() else {
+        return Messages { locale: FALLBACK.to_owned(), messages: HashMap::new() };
+    };
+    let locale = negotiate(catalogs, &locale.unwrap_or_else(wanted_locale));
+    let mut messages = catalogs.get(FALLBACK).cloned().unwrap_or_default();
+    messages.extend(catalogs.get(&locale).cloned().unwrap_or_default());
+    Messages { locale, messages }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locales_narrow_to_the_closest_catalog() {
+        let catalogs: HashMap<String, HashMap<String, String>> =
+            ["en", "zh-CN", "zh-TW", "pt-BR", "de"].iter().map(|&l| (l.to_owned(), HashMap::new())).collect();
+        assert_eq!(negotiate(&catalogs, "zh_CN.UTF-8"), "zh-CN");
+        assert_eq!(negotiate(&catalogs, "ZH-tw"), "zh-TW");
+        assert_eq!(negotiate(&catalogs, "zh-HK"), "zh-CN");
+        assert_eq!(negotiate(&catalogs, "de-AT"), "de");
+        assert_eq!(negotiate(&catalogs, "pt"), "pt-BR");
+        assert_eq!(negotiate(&catalogs, "fr-FR"), FALLBACK);
+    }
+
+    #[test]
+    fn missing_messages_fall_back_to_the_key() {
+        assert_eq!(message("test.{name} of {count}", &[("name", "a"), ("count", "2")]), "test.a of 2");
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::State;
+
+use crate::{
+    error::BackendError,
+    net::{self, reqwest},
+    rate_limit,
+    workspace::write_atomic,
+};
+
+const MAX_CACHE_SIZE: u64 = 256 * 1024 * 1024;
+const MAX_IMAGE_SIZE: u64 = 50 * 1024 * 1024;
+/// How long a response without `Cache-Control: max-age` counts as fresh.
+const DEFAULT_MAX_AGE: u64 = 5 * 60;
+
+/// What we remember about a cached response, stored next to it.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_type: Option<String>,
+    /// Seconds since the epoch.
+    fetched_at: u64,
+    max_age: u64,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachedImage {
+    path: String,
+    mime_type: Option<String>,
+    from_cache: bool,
+}
+
+/// Disk cache for remote images shown in previews.
+pub struct ImageCache {
+    dir: PathBuf,
+    /// One lock per URL being fetched, so concurrent requests for the same
+    /// image wait for the first instead of downloading it again.
+    inflight: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn cache_control(headers: &reqwest::header::HeaderMap) -> Option<&str> {
+    headers.get(reqwest::header::CACHE_CONTROL).and_then(|v| v.to_str().ok())
+}
+
+/// The image mustn't be kept: it's only written for the webview to load
+/// it, and fetched again next time.
+fn no_store(headers: &reqwest::header::HeaderMap) -> bool {
+    cache_control(headers).is_some_and(|value| value.split(',').any(|d| d.trim() == "no-store"))
+}
+
+fn max_age(headers: &reqwest::header::HeaderMap) -> u64 {
+    let Some(value) = cache_control(headers) else {
+        return DEFAULT_MAX_AGE;
+    };
+    if value.contains("no-cache") || value.contains("no-store") {
+        return 0;
+    }
+    value
+        .split(',')
+        .find_map(|d| d.trim().strip_prefix("max-age=")?.parse().ok())
+        .unwrap_or(DEFAULT_MAX_AGE)
+}
+
+fn header(headers: &reqwest::header::HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_owned)
+}
+
+impl ImageCache {
+    pub fn new(dir: PathBuf) -> Self {
+        ImageCache { dir, inflight: Mutex::default() }
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key = hex::encode(Sha256::digest(url.as_bytes()));
+        (self.dir.join(&key), self.dir.join(format!("{key}.json")))
+    }
+
+    fn read_entry(meta_path: &Path) -> Option<Entry> {
+        serde_json::from_slice(&fs::read(meta_path).ok()?).ok()
+    }
+
+    fn write_entry(meta_path: &Path, entry: &Entry) -> Result<(), String> {
+        let json = serde_json::to_vec(entry).map_err(|e| format!("serialize: {e}"))?;
+        write_atomic(meta_path, &json)
+    }
+
+    pub fn size(&self) -> u64 {
+        let Ok(entries) = fs::read_dir(&self.dir) else { return 0 };
+        entries
+            .filter_map(Result::ok)
+            .filter_map(|e| e.metadata().ok())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    /// Deletes the least recently fetched images until the cache is back
+    /// under 80% of its limit; those that weren't to be kept go first.
+    pub fn evict(&self) {
+        if self.size() <= MAX_CACHE_SIZE {
+            return;
+        }
+        let Ok(dir) = fs::read_dir(&self.dir) else { return };
+        let mut entries: Vec<(u64, PathBuf, u64)> = dir
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_none())
+            .map(|data_path| {
+                let meta_path = data_path.with_extension("json");
+                let fetched_at = Self::read_entry(&meta_path).map_or(0, |entry| entry.fetched_at);
+                let size = fs::metadata(&data_path).map(|m| m.len()).unwrap_or(0);
+                (fetched_at, meta_path, size)
+            })
+            .collect();
+        entries.sort_by_key(|e| e.0);
+
+        let mut size = self.size();
+        for (_, meta_path, data_size) in entries {
+            if size <= MAX_CACHE_SIZE / 10 * 8 {
+                break;
+            }
+            let _ = fs::remove_file(meta_path.with_extension(""));
+            let _ = fs::remove_file(&meta_path);
+            size = size.saturating_sub(data_size);
+        }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<CachedImage, String> {
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("create_dir_all {}: {e}", self.dir.display()))?;
+        let (data_path, meta_path) = self.paths(url);
+        let cached = Self::read_entry(&meta_path).filter(|_| data_path.exists());
+        let result = |entry: &Entry, from_cache| CachedImage {
+            path: data_path.to_string_lossy().into_owned(),
+            mime_type: entry.content_type.clone(),
+            from_cache,
+        };
+
+        if let Some(entry) = &cached {
+            if now() < entry.fetched_at + entry.max_age {
+                return Ok(result(entry, true));
+            }
+        }
+
+        let mut request = net::client()?.get(url);
+        if let Some(entry) = &cached {
+            if let Some(etag) = &entry.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(date) = &entry.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, date);
+            }
+        }
+        let response = rate_limit::send(request).await.map_err(|e| format!("get {url}: {e}"))?;
+        let headers = response.headers().clone();
+
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            if let Some(mut entry) = cached {
+                entry.fetched_at = now();
+                entry.max_age = max_age(&headers);
+                Self::write_entry(&meta_path, &entry)?;
+                return Ok(result(&entry, true));
+            }
+        }
+        let response = response
+            .error_for_status()
+            .map_err(|e| format!("get {url}: {e}"))?;
+        let data = net::read_capped(response, MAX_IMAGE_SIZE).await?;
+        let store = !no_store(&headers);
+
+        let entry = Entry {
+            url: url.to_owned(),
+            etag: header(&headers, reqwest::header::ETAG),
+            last_modified: header(&headers, reqwest::header::LAST_MODIFIED),
+            content_type: header(&headers, reqwest::header::CONTENT_TYPE)
+                .map(|v| v.split(';').next().unwrap_or(&v).trim().to_owned()),
+            fetched_at: now(),
+            max_age: max_age(&headers),
+        };
+        write_atomic(&data_path, &data)?;
+        if store {
+            Self::write_entry(&meta_path, &entry)?;
+        } else {
+            let _ = fs::remove_file(&meta_path);
+        }
+        self.evict();
+        Ok(result(&entry, false))
+    }
+
+    pub async fn get(&self, url: &str) -> Result<CachedImage, String> {
+        let inflight = Inflight::new(self, url);
+        let _guard = inflight.lock.lock().await;
+        self.fetch(url).await
+    }
+}
+
+/// A request's share of the lock for its URL, given back when it's done or
+/// dropped, like when it timed out.
+struct Inflight<'a> {
+    cache: &'a ImageCache,
+    url: &'a str,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> Inflight<'a> {
+    fn new(cache: &'a ImageCache, url: &'a str) -> Self {
+        let mut inflight = cache.inflight.lock().expect("inflight lock poisoned");
+        let lock = inflight.entry(url.to_owned()).or_default().clone();
+        Inflight { cache, url, lock }
+    }
+}
+
+impl Drop for Inflight<'_> {
+    fn drop(&mut self) {
+        let mut inflight = self.cache.inflight.lock().expect("inflight lock poisoned");
+        // nobody else is waiting: the map and `lock` are the only owners
+        if Arc::strong_count(&self.lock) <= 2 {
+            inflight.remove(self.url);
+        }
+    }
+}
+
+/// Fetches a remote image through the disk cache and returns the path of
+/// the cached file, which the webview can load through the asset protocol.
+#[tauri::command]
+pub async fn fetch_remote_image(
+    url: String, cache: State<'_, ImageCache>
+) -> Result<CachedImage, BackendError> {
+    log::info!("fetch_remote_image start: {url}");
+    let result = tokio::time::timeout(Duration::from_secs(60), cache.get(&url))
+        .await
+        .map_err(|_| format!("fetch_remote_image: {url} timed out"))?
+        .map_err(|e| format!("fetch_remote_image: {e}"))?;
+    log::info!("fetch_remote_image done (cached: {})", result.from_cache);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_store_is_a_directive_of_its_own() {
+        let headers = |value: &'static str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(reqwest::header::CACHE_CONTROL, reqwest::header::HeaderValue::from_static(value));
+            headers
+        };
+        assert!(no_store(&headers("private, no-store")));
+        assert!(!no_store(&headers("max-age=60")));
+        assert!(!no_store(&reqwest::header::HeaderMap::new()));
+        assert_eq!(max_age(&headers("public, max-age=60")), 60);
+    }
+
+    #[test]
+    fn dropped_requests_give_their_lock_back() {
+        let cache = ImageCache::new(std::env::temp_dir().join("emmm-image-cache-test"));
+        let url = "https://example.com/a.png";
+        tauri::async_runtime::block_on(async {
+            let first = Inflight::new(&cache, url);
+            let guard = first.lock.lock().await;
+            // like a request timing out while waiting for the first
+            let second = Inflight::new(&cache, url);
+            assert!(tokio::time::timeout(Duration::from_millis(10), second.lock.lock()).await.is_err());
+            drop(second);
+            assert!(cache.inflight.lock().unwrap().contains_key(url));
+            drop(guard);
+            drop(first);
+        });
+        assert!(cache.inflight.lock().unwrap().is_empty());
+    }
+}
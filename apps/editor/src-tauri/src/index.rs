@@ -0,0 +1,229 @@
+//! What the documents of a workspace contain, kept in memory so features
+//! like completion don't have to read thousands of files on every key
+//! press. Only documents whose modification time changed are read again
+//! when the index is refreshed.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    frontmatter::Frontmatter,
+    jobs::{self, JobKind},
+    markdown::{self, Heading},
+    tasks::{self, Task},
+    workers::Priority,
+    workspace,
+};
+
+/// An index younger than this is used as it is.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Document {
+    modified: SystemTime,
+    /// The frontmatter `title`, the first level 1 heading, or the file name.
+    pub title: String,
+    /// From the frontmatter `tags` and `#tags` in the text, without `#`.
+    pub tags: Vec<String>,
+    pub headings: Vec<Heading>,
+    pub tasks: Vec<Task>,
+}
+
+pub type Documents = HashMap<PathBuf, Arc<Document>>;
+
+#[derive(Default)]
+struct Workspace {
+    refreshed: Option<Instant>,
+    /// Replaced as a whole, so a refresh that fails or is cancelled part way
+    /// leaves it as it was, and it can be used while the next is built.
+    documents: Arc<Documents>,
+}
+
+#[derive(Default)]
+pub struct WorkspaceIndex {
+    workspaces: Mutex<HashMap<PathBuf, Workspace>>,
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '/')
+}
+
+/// `#tags` outside code; a tag needs something besides digits, so issue
+/// numbers like `#12` don't count.
+fn inline_tags(body: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut fenced = false;
+    for line in body.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fenced = !fenced;
+            continue;
+        }
+        if fenced || line.starts_with("    ") || line.starts_with('\t') {
+            continue;
+        }
+        let mut in_code = false;
+        let mut previous = ' ';
+        for (i, c) in line.char_indices() {
+            if c == '`' {
+                in_code = !in_code;
+            } else if c == '#' && !in_code && (previous.is_whitespace() || previous == '(') {
+                let tag: String = line[i + 1..].chars().take_while(|&c| is_tag_char(c)).collect();
+                let tag = tag.trim_end_matches(['-', '/']);
+                if !tag.is_empty() && !tag.chars().all(|c| c.is_ascii_digit()) {
+                    tags.push(tag.to_owned());
+                }
+            }
+            previous = c;
+        }
+    }
+    tags
+}
+
+impl Document {
+    pub fn parse(path: &Path, source: &str, modified: SystemTime) -> Self {
+        let (meta, body) = Frontmatter::of(source);
+        let headings = markdown::headings(body);
+        let title = meta
+            .get("title")
+            .map(str::to_owned)
+            .or_else(|| headings.iter().find(|h| h.level == 1).map(|h| h.text.clone()))
+            .unwrap_or_else(|| path.file_stem().unwrap_or_default().to_string_lossy().into_owned());
+        let mut tags = meta.list("tags");
+        for tag in inline_tags(body) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        Document { modified, title, tags, headings, tasks: tasks::scan(source) }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl WorkspaceIndex {
+    /// The documents at `root` now, reusing those of `known` that haven't
+    /// changed.
+    fn refresh(root: &Path, known: &Documents) -> Result<Documents, String> {
+        let paths = workspace::documents(root)?;
+        let mut documents = HashMap::with_capacity(paths.len());
+        let total = paths.len();
+        for (i, path) in paths.into_iter().enumerate() {
+            jobs::checkpoint()?;
+            if i % 100 == 0 {
+                jobs::progress(i, total, "");
+            }
+            let Some(modified) = modified(&path) else { continue };
+            let document = match known.get(&path) {
+                Some(known) if known.modified == modified => known.clone(),
+                _ => match fs::read_to_string(&path) {
+                    Ok(source) => Arc::new(Document::parse(&path, &source, modified)),
+                    Err(e) => {
+                        log::warn!("index: {}: {e}", path.display());
+                        continue;
+                    }
+                },
+            };
+            documents.insert(path, document);
+        }
+        Ok(documents)
+    }
+
+    /// When the workspace at `root` was last refreshed, and its documents,
+    /// which are kept from then on.
+    fn snapshot(&self, root: &Path) -> (Option<Instant>, Arc<Documents>) {
+        let mut workspaces = self.workspaces.lock().expect("index lock poisoned");
+        let workspace = workspaces.entry(root.to_owned()).or_default();
+        (workspace.refreshed, workspace.documents.clone())
+    }
+
+    /// Swaps in the documents of a refresh that started at `started`,
+    /// unless one that started later already finished.
+    fn store(&self, root: &Path, started: Instant, documents: Arc<Documents>) {
+        let mut workspaces = self.workspaces.lock().expect("index lock poisoned");
+        let workspace = workspaces.entry(root.to_owned()).or_default();
+        if workspace.refreshed.is_none_or(|refreshed| refreshed < started) {
+            workspace.documents = documents;
+            workspace.refreshed = Some(started);
+        }
+    }
+
+    /// Refreshes every workspace indexed so far, each as a job.
+    pub fn refresh_all(&self) -> Result<(), String> {
+        let roots: Vec<PathBuf> = self.workspaces.lock().expect("index lock poisoned").keys().cloned().collect();
+        for root in roots {
+            jobs::checkpoint()?;
+            let (_, known) = self.snapshot(&root);
+            let started = Instant::now();
+            let documents = jobs::start(JobKind::Indexing, Priority::Background, root.to_string_lossy())
+                .run(|| Self::refresh(&root, &known))?;
+            self.store(&root, started, Arc::new(documents));
+        }
+        Ok(())
+    }
+
+    /// How many documents are indexed, over all workspaces.
+    pub fn document_count(&self) -> usize {
+        self.workspaces.lock().expect("index lock poisoned").values().map(|w| w.documents.len()).sum()
+    }
+
+    /// Calls `f` with the documents of the workspace at `root`, indexing
+    /// it first if needed. Neither holds up the other workspaces.
+    pub fn with<T>(&self, root: &Path, f: impl FnOnce(&Documents) -> T) -> Result<T, String> {
+        let (refreshed, known) = self.snapshot(root);
+        let started = Instant::now();
+        let documents = match refreshed {
+            // the first time reads every document, which can take a while
+            None => jobs::start(JobKind::Indexing, Priority::Background, root.to_string_lossy())
+                .run(|| Self::refresh(root, &known))?,
+            Some(at) if at.elapsed() > REFRESH_INTERVAL => Self::refresh(root, &known)?,
+            Some(_) => return Ok(f(&known)),
+        };
+        let documents = Arc::new(documents);
+        self.store(root, started, documents.clone());
+        Ok(f(&documents))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_tags_skip_code_and_numbers() {
+        let body = "#draft and (#work/emmm-) #12\n`#code` #\n\n```\n#fenced\n```\n    #indented\n";
+        assert_eq!(inline_tags(body), ["draft", "work/emmm"]);
+    }
+
+    #[test]
+    fn refreshes_replace_the_documents_as_a_whole() {
+        let root = std::env::temp_dir().join(format!("emmm-index-test-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.md"), "# A\n").unwrap();
+        fs::write(root.join("b.md"), "---\ntitle: B\ntags: [x]\n---\n").unwrap();
+        let index = WorkspaceIndex::default();
+        let titles = |documents: &Documents| {
+            let mut titles: Vec<String> = documents.values().map(|d| d.title.clone()).collect();
+            titles.sort();
+            titles
+        };
+        assert_eq!(index.with(&root, titles).unwrap(), ["A", "B"]);
+
+        let (_, before) = index.snapshot(&root);
+        fs::remove_file(root.join("b.md")).unwrap();
+        index.refresh_all().unwrap();
+        assert_eq!(index.with(&root, titles).unwrap(), ["A"]);
+        // what was handed out before is untouched, and unchanged documents
+        // are shared with it
+        assert_eq!(titles(&before), ["A", "B"]);
+        let (_, after) = index.snapshot(&root);
+        assert!(Arc::ptr_eq(&before[&root.join("a.md")], &after[&root.join("a.md")]));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,176 @@
+use std::path::{Component, Path, PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use percent_encoding::percent_decode_str;
+
+use crate::compress_file;
+
+/// Prefixes under which the webview addresses local files (see
+/// `convertFileSrc` on the frontend).
+const ASSET_PREFIXES: &[&str] =
+    &["asset://localhost/", "http://asset.localhost/", "https://asset.localhost/"];
+
+/// Resolves `.` and `..` without touching the file system, so that two
+/// references to the same file compare equal.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !result.pop() {
+                    result.push("..");
+                }
+            }
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+/// Finds the local file an `src`/`href` points to, if it points to one at
+/// all. Relative references are taken relative to `base_dir`.
+pub fn resolve_local(src: &str, base_dir: Option<&Path>) -> Option<PathBuf> {
+    if let Some(rest) = ASSET_PREFIXES.iter().find_map(|p| src.strip_prefix(p)) {
+        let path = PathBuf::from(percent_decode_str(rest).decode_utf8().ok()?.as_ref());
+        return Some(normalize(&path));
+    }
+    if src.starts_with("file:") {
+        return tauri::Url::parse(src).ok()?.to_file_path().ok();
+    }
+    // anything else with a scheme (http:, data:, mailto:...) is not ours
+    let scheme_end = src.find(':').unwrap_or(0);
+    if scheme_end > 1 && src[..scheme_end].chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let path = src.split(['?', '#']).next()?;
+    let path = PathBuf::from(percent_decode_str(path).decode_utf8().ok()?.as_ref());
+    if path.is_absolute() {
+        Some(normalize(&path))
+    } else {
+        Some(normalize(&base_dir?.join(path)))
+    }
+}
+
+pub fn data_uri(mime: &str, data: &[u8]) -> String {
+    format!("data:{mime};base64,{}", STANDARD.encode(data))
+}
+
+/// Reads an image, compresses it to under `max_size` bytes and returns it as
+/// a data URI.
+pub fn image_data_uri(path: &Path, max_size: usize) -> Result<String, String> {
+    let data = compress_file(&path.to_string_lossy(), max_size)?;
+    let format = image::guess_format(&data)
+        .map_err(|e| format!("guess_format: {e}"))?;
+    Ok(data_uri(format.to_mime_type(), &data))
+}
+
+/// Where a document or stylesheet came from, so that the references in it
+/// can be resolved.
+#[derive(Clone)]
+pub enum Origin {
+    Local(Option<PathBuf>),
+    Remote(tauri::Url),
+}
+
+pub enum Resource {
+    Local(PathBuf),
+    Remote(tauri::Url),
+}
+
+impl Resource {
+    pub fn origin(&self) -> Origin {
+        match self {
+            Resource::Local(path) => Origin::Local(path.parent().map(Path::to_owned)),
+            Resource::Remote(url) => Origin::Remote(url.clone()),
+        }
+    }
+
+    pub fn extension(&self) -> Option<String> {
+        let name = match self {
+            Resource::Local(path) => path.file_name()?.to_string_lossy().into_owned(),
+            Resource::Remote(url) => url.path_segments()?.next_back()?.to_owned(),
+        };
+        let (_, ext) = name.rsplit_once('.')?;
+        Some(ext.to_ascii_lowercase())
+    }
+}
+
+impl std::fmt::Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resource::Local(path) => write!(f, "{}", path.display()),
+            Resource::Remote(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+/// Resolves a reference found in a document from `origin`. Returns `None`
+/// for things that are not fetchable resources (`data:`, `mailto:`...).
+pub fn resolve(reference: &str, origin: &Origin) -> Option<Resource> {
+    let reference = reference.trim();
+    if reference.is_empty() || reference.starts_with('#') {
+        return None;
+    }
+    let remote = |url: tauri::Url| {
+        matches!(url.scheme(), "http" | "https").then_some(Resource::Remote(url))
+    };
+    match origin {
+        Origin::Remote(base) => remote(base.join(reference).ok()?),
+        Origin::Local(base_dir) => match resolve_local(reference, base_dir.as_deref()) {
+            Some(path) => Some(Resource::Local(path)),
+            None => remote(tauri::Url::parse(reference).ok()?),
+        },
+    }
+}
+
+/// Guesses a MIME type from a file extension, for the kinds of files that
+/// documents embed.
+pub fn mime_for_extension(ext: &str) -> Option<&'static str> {
+    Some(match ext {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        "svg" => "image/svg+xml",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "eot" => "application/vnd.ms-fontobject",
+        "css" => "text/css",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references_resolve_to_local_files() {
+        let base = Path::new("/notes/posts");
+        assert_eq!(resolve_local("img/a%20b.png?v=2#x", Some(base)), Some(PathBuf::from("/notes/posts/img/a b.png")));
+        assert_eq!(resolve_local("../a.png", Some(base)), Some(PathBuf::from("/notes/a.png")));
+        assert_eq!(resolve_local("asset://localhost/%2Fnotes%2Fa.png", None), Some(PathBuf::from("/notes/a.png")));
+        assert_eq!(resolve_local("a.png", None), None);
+        for other in ["https://example.com/a.png", "data:image/png;base64,", "mailto:a@b.c"] {
+            assert_eq!(resolve_local(other, Some(base)), None, "{other}");
+        }
+        assert_eq!(normalize(Path::new("../a/./b/../c")), PathBuf::from("../a/c"));
+    }
+
+    #[test]
+    fn remote_documents_resolve_against_their_url() {
+        let origin = Origin::Remote(tauri::Url::parse("https://example.com/blog/post.html").unwrap());
+        let Some(Resource::Remote(url)) = resolve("../style.css", &origin) else { panic!("not remote") };
+        assert_eq!(url.as_str(), "https://example.com/style.css");
+        assert!(resolve("#top", &origin).is_none());
+        assert!(resolve("mailto:a@b.c", &origin).is_none());
+        assert_eq!(Resource::Remote(url).extension().as_deref(), Some("css"));
+    }
+}
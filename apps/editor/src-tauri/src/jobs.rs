@@ -0,0 +1,328 @@
+//! Long-running operations register as jobs — compressing an image,
+//! exporting, syncing, indexing a workspace — so a background tasks panel
+//! can list what's running, follow every job's progress on one stream and
+//! cancel any of them. Cancelling is cooperative: the work calls
+//! [`checkpoint`] between steps and stops at the next one.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tauri::ipc::Channel;
+
+use crate::{error::BackendError, workers::Priority};
+
+static JOBS: LazyLock<Registry> = LazyLock::new(Registry::default);
+
+thread_local! {
+    /// The job the work on this thread belongs to, for [`checkpoint`] and
+    /// [`progress`].
+    static CURRENT: RefCell<Option<(u64, Arc<AtomicBool>)>> = const { RefCell::new(None) };
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum JobKind {
+    Compression,
+    Export,
+    Sync,
+    Indexing,
+    /// Scheduled upkeep, like pruning and backups.
+    Maintenance,
+    Script,
+    Upload,
+    /// Fetching from the web, like a link's preview.
+    Fetch,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct JobInfo {
+    id: u64,
+    kind: JobKind,
+    priority: Priority,
+    /// What it works on, like a path.
+    label: String,
+    done: usize,
+    /// 0 until it's known.
+    total: usize,
+    message: String,
+    /// Milliseconds since the epoch.
+    started_at: u64,
+    cancelled: bool,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase", tag = "event", content = "data")]
+pub enum JobEvent {
+    #[serde(rename_all = "camelCase")]
+    Started { job: JobInfo },
+    #[serde(rename_all = "camelCase")]
+    Progress { id: u64, done: usize, total: usize, message: String },
+    /// `error` is `None` if it succeeded.
+    #[serde(rename_all = "camelCase")]
+    Finished { id: u64, error: Option<BackendError> },
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: AtomicU64,
+    running: Mutex<HashMap<u64, (JobInfo, Arc<AtomicBool>)>>,
+    watchers: Mutex<Vec<Channel<JobEvent>>>,
+}
+
+impl Registry {
+    /// Sends `event` to every watcher, forgetting those that are gone,
+    /// like a reloaded window's.
+    fn broadcast(&self, event: &JobEvent) {
+        self.watchers.lock().expect("jobs lock poisoned").retain(|channel| channel.send(event.clone()).is_ok());
+    }
+}
+
+/// A registered job; it's finished when [`Job::run`] returns or when it's
+/// dropped.
+pub struct Job {
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+    error: Option<BackendError>,
+}
+
+/// A fresh job ID, also used for the events of commands that aren't jobs.
+pub fn next_id() -> u64 {
+    JOBS.next_id.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Registers a job of `kind` working on `label`. `priority` is what it
+/// runs with in the worker pool, if it does.
+pub fn start(kind: JobKind, priority: Priority, label: impl Into<String>) -> Job {
+    start_as(next_id(), kind, priority, label)
+}
+
+/// [`start`] with the ID of the command's [`crate::EventChannel`], so its
+/// events and the job's go together.
+pub fn start_as(id: u64, kind: JobKind, priority: Priority, label: impl Into<String>) -> Job {
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or_default();
+    let info = JobInfo {
+        id,
+        kind,
+        priority,
+        label: label.into(),
+        done: 0,
+        total: 0,
+        message: String::new(),
+        started_at,
+        cancelled: false,
+    };
+    let cancelled = Arc::new(AtomicBool::new(false));
+    JOBS.running.lock().expect("jobs lock poisoned").insert(id, (info.clone(), cancelled.clone()));
+    JOBS.broadcast(&JobEvent::Started { job: info });
+    Job { id, cancelled, error: None }
+}
+
+impl Job {
+    /// Runs `f` as the work of this job on the current thread, then
+    /// finishes the job with its result.
+    pub fn run<T>(mut self, f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+        let previous = CURRENT.with_borrow_mut(|current| current.replace((self.id, self.cancelled.clone())));
+        let result = {
+            let _restore = Restore(previous);
+            f()
+        };
+        if let Err(e) = &result {
+            self.error = Some(BackendError::from(e.as_str()));
+        }
+        result
+    }
+
+    /// Whether the job was asked to stop, for async work, which has no
+    /// thread of its own to [`checkpoint`] on.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// [`progress`] for async work.
+    pub fn progress(&self, done: usize, total: usize, message: &str) {
+        report(self.id, done, total, message);
+    }
+
+    /// Finishes the job with `result`, for async work that isn't
+    /// [`Job::run`].
+    pub fn finish<T>(mut self, result: &Result<T, String>) {
+        if let Err(e) = result {
+            self.error = Some(BackendError::from(e.as_str()));
+        }
+    }
+}
+
+/// Puts back the job [`CURRENT`] was before [`Job::run`], even if the
+/// work panics, so the pooled thread doesn't keep a finished job.
+struct Restore(Option<(u64, Arc<AtomicBool>)>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.set(self.0.take());
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        JOBS.running.lock().expect("jobs lock poisoned").remove(&self.id);
+        JOBS.broadcast(&JobEvent::Finished { id: self.id, error: self.error.take() });
+    }
+}
+
+/// What a cancelled job fails with, last in its chain, which gives the
+/// error its code.
+pub const CANCELLED: &str = "cancelled";
+
+/// Fails with [`CANCELLED`] if the job of this thread was cancelled.
+pub fn checkpoint() -> Result<(), String> {
+    let cancelled = CURRENT.with_borrow(|current| current.as_ref().is_some_and(|(_, c)| c.load(Ordering::Relaxed)));
+    if cancelled { Err(CANCELLED.to_owned()) } else { Ok(()) }
+}
+
+/// Reports the progress of the job of this thread, if there is one.
+pub fn progress(done: usize, total: usize, message: &str) {
+    let Some(id) = CURRENT.with_borrow(|current| current.as_ref().map(|&(id, _)| id)) else { return };
+    report(id, done, total, message);
+}
+
+fn report(id: u64, done: usize, total: usize, message: &str) {
+    if let Some((info, _)) = JOBS.running.lock().expect("jobs lock poisoned").get_mut(&id) {
+        info.done = done;
+        info.total = total;
+        message.clone_into(&mut info.message);
+    }
+    JOBS.broadcast(&JobEvent::Progress { id, done, total, message: message.to_owned() });
+}
+
+/// The jobs running now, the oldest first.
+#[tauri::command]
+pub fn list_jobs() -> Vec<JobInfo> {
+    let mut jobs: Vec<JobInfo> =
+        JOBS.running.lock().expect("jobs lock poisoned").values().map(|(info, _)| info.clone()).collect();
+    jobs.sort_by_key(|job| job.id);
+    jobs
+}
+
+impl JobInfo {
+    /// Like `sync /notes (3/10)`, for menus and tooltips.
+    pub fn summary(&self) -> String {
+        let kind = match serde_json::to_value(self.kind) {
+            Ok(serde_json::Value::String(kind)) => kind,
+            _ => String::new(),
+        };
+        match self.total {
+            0 => format!("{kind} {}", self.label),
+            total => format!("{kind} {} ({}/{total})", self.label, self.done),
+        }
+    }
+}
+
+/// Asks every running job of `kind` to stop; returns how many there were.
+pub fn cancel_kind(kind: JobKind) -> usize {
+    cancel_where(|info| info.kind == kind)
+}
+
+/// Asks every running job to stop, like before quitting; returns how many
+/// there were.
+pub fn cancel_all() -> usize {
+    cancel_where(|_| true)
+}
+
+fn cancel_where(filter: impl Fn(&JobInfo) -> bool) -> usize {
+    let mut running = JOBS.running.lock().expect("jobs lock poisoned");
+    let mut count = 0;
+    for (info, cancelled) in running.values_mut().filter(|(info, _)| filter(info)) {
+        log::info!("cancel: {} ({})", info.id, info.label);
+        info.cancelled = true;
+        cancelled.store(true, Ordering::Relaxed);
+        count += 1;
+    }
+    count
+}
+
+/// Asks the job `id` to stop; it finishes with a `cancelled` error at its
+/// next checkpoint.
+#[tauri::command]
+pub fn cancel_job(id: u64) -> Result<(), BackendError> {
+    let mut running = JOBS.running.lock().expect("jobs lock poisoned");
+    let (info, cancelled) = running.get_mut(&id).ok_or_else(|| format!("cancel_job: job {id} not found"))?;
+    log::info!("cancel_job: {id} ({})", info.label);
+    info.cancelled = true;
+    cancelled.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Sends every job's `Started`, `Progress` and `Finished` events to
+/// `channel` from now on.
+#[tauri::command]
+pub fn watch_jobs(channel: Channel<JobEvent>) {
+    JOBS.watchers.lock().expect("jobs lock poisoned").push(channel);
+}
+
+/// How many channels [`watch_jobs`] sends to; ones that are gone count
+/// until the next event.
+pub fn watcher_count() -> usize {
+    JOBS.watchers.lock().expect("jobs lock poisoned").len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancelled_jobs_stop_at_their_next_checkpoint() {
+        let job = start(JobKind::Export, Priority::Background, "/notes/a.md");
+        let id = job.id;
+        let result = job.run(|| {
+            progress(1, 3, "one");
+            let info = list_jobs().into_iter().find(|j| j.id == id).expect("the job is listed");
+            assert_eq!((info.done, info.total, info.message.as_str()), (1, 3, "one"));
+            assert_eq!(info.summary(), "export /notes/a.md (1/3)");
+            checkpoint()?;
+            cancel_job(id).expect("cancel the job");
+            assert!(list_jobs().iter().any(|j| j.id == id && j.cancelled));
+            checkpoint()?;
+            Ok(())
+        });
+        assert_eq!(result, Err(CANCELLED.to_owned()));
+        assert!(list_jobs().iter().all(|j| j.id != id));
+        assert!(checkpoint().is_ok());
+        assert!(cancel_job(id).is_err());
+    }
+
+    #[test]
+    fn panicking_work_leaves_no_job_on_the_thread() {
+        let job = start(JobKind::Export, Priority::Background, "/notes/b.md");
+        let id = job.id;
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            job.run(|| -> Result<(), String> {
+                let _ = cancel_job(id);
+                panic!("the work panics")
+            })
+        }));
+        assert!(panicked.is_err());
+        assert!(CURRENT.with_borrow(Option::is_none));
+        assert!(checkpoint().is_ok());
+        assert!(list_jobs().iter().all(|j| j.id != id));
+    }
+
+    #[test]
+    fn summaries_leave_out_an_unknown_total() {
+        let job = start(JobKind::Sync, Priority::Interactive, "/notes");
+        let info = list_jobs().into_iter().find(|j| j.id == job.id).expect("the job is listed");
+        assert_eq!(info.summary(), "sync /notes");
+        assert!(!job.is_cancelled());
+    }
+}
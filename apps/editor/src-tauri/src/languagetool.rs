@@ -0,0 +1,206 @@
+//! Grammar and style checking with a LanguageTool server, either the public
+//! one or a self-hosted instance.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::BackendError,
+    net::{self, reqwest},
+    secrets,
+};
+
+pub const PUBLIC_SERVER: &str = "https://api.languagetool.org";
+/// The public server takes up to 20 KB per request.
+const MAX_CHUNK_SIZE: usize = 15_000;
+const MAX_RESPONSE_SIZE: u64 = 4 * 1024 * 1024;
+const MAX_REPLACEMENTS: usize = 8;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CheckOptions {
+    /// Base URL of the server; the public one if not given.
+    server: Option<String>,
+    /// A code like `en-US`, or `auto` (the default) to detect it.
+    language: Option<String>,
+    /// The writer's native language, for false friends.
+    mother_tongue: Option<String>,
+    /// For premium accounts, with the API key in the keychain entry
+    /// `secret_name`.
+    username: Option<String>,
+    secret_name: Option<String>,
+    disabled_rules: Vec<String>,
+    /// Turns on the rules that are off by default.
+    picky: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrammarIssue {
+    /// In UTF-16 code units from the start of the text, like JavaScript
+    /// string indices.
+    offset: usize,
+    length: usize,
+    message: String,
+    short_message: Option<String>,
+    replacements: Vec<String>,
+    rule_id: String,
+    /// E.g. `misspelling`, `grammar` or `style`.
+    issue_type: Option<String>,
+    category: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CheckResponse {
+    matches: Vec<Match>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Match {
+    message: String,
+    #[serde(default)]
+    short_message: String,
+    offset: usize,
+    length: usize,
+    #[serde(default)]
+    replacements: Vec<Replacement>,
+    rule: Rule,
+}
+
+#[derive(Deserialize)]
+struct Replacement {
+    value: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rule {
+    id: String,
+    issue_type: Option<String>,
+    category: Option<Category>,
+}
+
+#[derive(Deserialize)]
+struct Category {
+    name: String,
+}
+
+/// Splits `text` into pieces of at most `max_size` bytes, preferring to cut
+/// between paragraphs, then lines, then words.
+pub(crate) fn chunks(text: &str, max_size: usize) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    while text.len() - start > max_size {
+        let mut end = start + max_size;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let window = &text[start..end];
+        let cut = window
+            .rfind("\n\n").map(|i| i + 2)
+            .or_else(|| window.rfind('\n').map(|i| i + 1))
+            .or_else(|| window.rfind(' ').map(|i| i + 1))
+            .filter(|&cut| cut > 0)
+            .unwrap_or(window.len());
+        result.push(&text[start..start + cut]);
+        start += cut;
+    }
+    if start < text.len() {
+        result.push(&text[start..]);
+    }
+    result
+}
+
+async fn check_chunk(
+    client: &reqwest::Client, url: &str, form: &[(&str, String)],
+) -> Result<Vec<Match>, String> {
+    let response = client
+        .post(url)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .form(form)
+        .send()
+        .await
+        .map_err(|e| format!("request: {e}"))?;
+    let status = response.status();
+    let body = net::read_capped(response, MAX_RESPONSE_SIZE).await?;
+    if !status.is_success() {
+        return Err(format!("{url} returned {status}: {}",
+            String::from_utf8_lossy(&body[..body.len().min(200)])));
+    }
+    let parsed: CheckResponse = serde_json::from_slice(&body)
+        .map_err(|e| format!("invalid response: {e}"))?;
+    Ok(parsed.matches)
+}
+
+/// Checks `text` and returns the issues found, in order. Long texts are
+/// sent in several requests.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn check_grammar(text: String, options: CheckOptions) -> Result<Vec<GrammarIssue>, BackendError> {
+    log::info!("check_grammar start: {} bytes", text.len());
+    let server = options.server.as_deref().unwrap_or(PUBLIC_SERVER).trim_end_matches('/');
+    let url = format!("{server}/v2/check");
+    let mut form = vec![
+        ("language", options.language.clone().unwrap_or_else(|| "auto".to_owned())),
+    ];
+    if let Some(mother_tongue) = &options.mother_tongue {
+        form.push(("motherTongue", mother_tongue.clone()));
+    }
+    if let Some(username) = &options.username {
+        let name = options.secret_name.clone().unwrap_or_else(|| "languagetool".to_owned());
+        form.push(("username", username.clone()));
+        form.push(("apiKey", secrets::require_secret(name).await?));
+    }
+    if !options.disabled_rules.is_empty() {
+        form.push(("disabledRules", options.disabled_rules.join(",")));
+    }
+    if options.picky {
+        form.push(("level", "picky".to_owned()));
+    }
+
+    let client = net::client()?;
+    let mut issues = Vec::new();
+    // the server counts in UTF-16 code units
+    let mut chunk_offset = 0;
+    for chunk in chunks(&text, MAX_CHUNK_SIZE) {
+        let mut chunk_form = form.clone();
+        chunk_form.push(("text", chunk.to_owned()));
+        let matches = check_chunk(&client, &url, &chunk_form)
+            .await
+            .map_err(|e| format!("check_grammar: {e}"))?;
+        issues.extend(matches.into_iter().map(|m| GrammarIssue {
+            offset: chunk_offset + m.offset,
+            length: m.length,
+            message: m.message,
+            short_message: (!m.short_message.is_empty()).then_some(m.short_message),
+            replacements: m
+                .replacements
+                .into_iter()
+                .take(MAX_REPLACEMENTS)
+                .map(|r| r.value)
+                .collect(),
+            rule_id: m.rule.id,
+            issue_type: m.rule.issue_type,
+            category: m.rule.category.map(|c| c.name),
+        }));
+        chunk_offset += chunk.encode_utf16().count();
+    }
+    log::info!("check_grammar done: {} issues", issues.len());
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_texts_are_cut_at_paragraphs_lines_then_words() {
+        assert_eq!(chunks("one\n\ntwo\nthree four", 12), ["one\n\n", "two\n", "three four"]);
+        assert_eq!(chunks("one two three", 7), ["one ", "two ", "three"]);
+        assert_eq!(chunks("abcdefgh", 3), ["abc", "def", "gh"]);
+        // never in the middle of a character
+        assert_eq!(chunks("ééé", 3), ["é", "é", "é"]);
+        assert!(chunks("", 3).is_empty());
+        assert_eq!(chunks("a b c".repeat(10).as_str(), 100).concat(), "a b c".repeat(10));
+    }
+}
@@ -1,10 +1,111 @@
-use std::{fs, io::Cursor};
+use std::{fs, io::Cursor, sync::Arc};
 
 use fast_image_resize::{images::Image, IntoImageView, Resizer};
 use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageReader};
 use num_traits::ToPrimitive;
 use serde::Serialize;
-use tauri::ipc::{Response};
+use tauri::{
+    ipc::{Channel, CommandArg, CommandItem, InvokeError, Response},
+    Manager, Runtime,
+};
+use tauri_plugin_log::{fern, Target, TargetKind};
+
+use crate::{error::BackendError, workers::Priority};
+
+mod ai;
+mod alt_text;
+mod background;
+mod benchmark;
+mod capture;
+mod citations;
+mod cli;
+mod clipboard;
+mod completion;
+mod conflict;
+mod conflict_markers;
+mod crash;
+mod daily_notes;
+mod db;
+mod deeplink;
+mod diagnostics;
+mod dictation;
+mod emoji;
+mod encryption;
+mod error;
+mod feed;
+mod flags;
+mod footnotes;
+mod formatter;
+mod frontmatter;
+mod gist;
+mod git;
+mod git_branch;
+mod git_remote;
+mod heading_numbers;
+mod highlight;
+mod i18n;
+mod image_cache;
+mod index;
+mod inline;
+mod jobs;
+mod languagetool;
+mod line_ops;
+mod link_preview;
+mod logs;
+mod markdown;
+mod markdown_lint;
+mod math;
+mod metrics;
+mod net;
+mod ocr;
+mod onnx;
+mod pandoc;
+mod paste;
+mod plugins;
+mod power;
+mod preview;
+mod print;
+mod process;
+mod prose_lint;
+mod qr;
+mod queue;
+mod rate_limit;
+mod readability;
+mod regex_transform;
+mod resources;
+mod s3;
+mod scheduler;
+mod scripting;
+mod secrets;
+mod settings;
+mod shutdown;
+mod single_file;
+mod site;
+mod slides;
+mod smart_crop;
+mod snippets;
+mod speech;
+mod spellcheck;
+mod sync;
+mod tasks;
+mod template_export;
+mod templates;
+mod text_case;
+mod toc;
+mod translate;
+mod tray;
+mod typography;
+mod updater;
+mod uploader;
+mod video;
+mod voice_memo;
+mod waveform;
+mod webhooks;
+mod weixin;
+mod windows;
+mod word_frequency;
+mod workers;
+mod workspace;
 
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase", tag = "event", content = "data")]
@@ -14,29 +115,94 @@ pub enum BackendEvent {
     #[serde(rename_all = "camelCase")]
     Inlined { result: String },
     #[serde(rename_all = "camelCase")]
-    Failed { msg: String },
+    Chunk { text: String },
+    #[serde(rename_all = "camelCase")]
+    Progress { done: usize, total: usize, message: String },
+    /// A word about to be read aloud, in UTF-16 code units.
+    #[serde(rename_all = "camelCase")]
+    Word { offset: usize, length: usize },
+    #[serde(rename_all = "camelCase")]
+    Failed { error: BackendError },
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TaggedEvent {
+    job_id: u64,
+    #[serde(flatten)]
+    event: BackendEvent,
+}
+
+/// The channel a command sends its [`BackendEvent`]s on. It gets an ID when
+/// the command is invoked, sent along with every event so that concurrent
+/// operations can be told apart; a command that runs as a job uses it as
+/// the job's ID too.
+#[derive(Clone)]
+pub struct EventChannel {
+    job_id: u64,
+    channel: Channel<TaggedEvent>,
 }
 
-// fn send(channel: &Channel<BackendEvent>, what: BackendEvent) {
-//     channel.send(what).expect("Error sending event");
-// }
+impl EventChannel {
+    pub fn job_id(&self) -> u64 {
+        self.job_id
+    }
+
+    /// A channel that keeps the events sent on it, as JSON.
+    #[cfg(test)]
+    pub(crate) fn recording() -> (Self, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        let events = std::sync::Arc::<std::sync::Mutex<Vec<String>>>::default();
+        let sent = events.clone();
+        let channel = Channel::new(move |body| {
+            if let tauri::ipc::InvokeResponseBody::Json(json) = body {
+                sent.lock().expect("events lock poisoned").push(json);
+            }
+            Ok(())
+        });
+        (EventChannel { job_id: 1, channel }, events)
+    }
+}
+
+impl<'de, R: Runtime> CommandArg<'de, R> for EventChannel {
+    fn from_command(command: CommandItem<'de, R>) -> Result<Self, InvokeError> {
+        let channel = Channel::from_command(command)?;
+        Ok(EventChannel { job_id: jobs::next_id(), channel })
+    }
+}
+
+fn send(channel: &EventChannel, what: BackendEvent) {
+    // the window may be gone, and the event with it
+    if let Err(e) = channel.channel.send(TaggedEvent { job_id: channel.job_id, event: what }) {
+        log::warn!("send event of job {}: {e}", channel.job_id);
+    }
+}
 
 #[allow(clippy::missing_panics_doc)]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    if let Some(code) = crash::run_monitor().or_else(cli::run) {
+        std::process::exit(code);
+    }
     let time_format = time::format_description::parse(
         "[year]-[month]-[day]@[hour]:[minute]:[second].[subsecond digits:3]",
     )
     .unwrap();
 
     tauri::Builder::default()
+        // first, so a second instance exits before setting anything up
+        .plugin(tauri_plugin_single_instance::init(|app, args, cwd| deeplink::on_second_instance(app, args, &cwd)))
+        .plugin(tauri_plugin_deep_link::init())
+        .plugin(tauri_plugin_updater::Builder::new().build())
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_http::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_clipboard_manager::init())
-        .plugin(
-            tauri_plugin_log::Builder::new()
+        .setup(|app| {
+            // set up here, as the log file is opened in the log directory
+            let log_file = logs::RotatingFile::open(&app.path().app_log_dir()?, &app.package_info().name)?;
+            let formatted = fern::Dispatch::new()
                 .format(move |out, message, record| {
                     out.finish(format_args!(
                         "{}[{}][{}] {}",
@@ -49,12 +215,230 @@ pub fn run() {
                         message
                     ));
                 })
+                .chain(std::io::stdout())
+                .chain(fern::Output::call(move |record| {
+                    let line = record.args().to_string();
+                    crash::breadcrumb(&line);
+                    log_file.lock().expect("log lock poisoned").write_line(&line);
+                }));
+            // the console gets records as they are, without the prefix
+            let (log_plugin, max_level, logger) = tauri_plugin_log::Builder::new()
+                .format(|out, message, _| out.finish(format_args!("{message}")))
+                .clear_targets()
+                .target(Target::new(TargetKind::Dispatch(formatted)))
+                .target(Target::new(TargetKind::Dispatch(fern::Dispatch::new().chain(fern::Output::call(logs::tail)))))
                 .filter(|metadata| !metadata.target().starts_with("tao::"))
-                .build(),
-        )
-        .invoke_handler(tauri::generate_handler![compress_image])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+                .split(app.handle())?;
+            app.handle().plugin(log_plugin)?;
+            tauri_plugin_log::attach_logger(max_level, logger)?;
+            let log_path = app.path().app_log_dir()?.join(format!("{}.log", app.package_info().name));
+            crash::install(app.path().app_data_dir()?.join("crashes"), log_path, app.handle().clone());
+            settings::load(&app.path().app_config_dir()?)?;
+            i18n::load(&app.path().resource_dir()?.join("locales"));
+
+            let cache_dir = app.path().app_cache_dir()?;
+            app.manage(image_cache::ImageCache::new(cache_dir.join("images")));
+            let data_dir = app.path().app_data_dir()?;
+            app.manage(conflict::Bases::new(data_dir.join("document-bases")));
+            app.manage(Arc::new(index::WorkspaceIndex::default()));
+            app.manage(Arc::new(citations::Citations::default()));
+            app.manage(Arc::new(db::Database::new(data_dir.join("emmm.sqlite"))));
+            app.manage(Arc::new(snippets::Snippets::new(data_dir.join("snippets.json"))));
+            app.manage(Arc::new(spellcheck::SpellChecker::new(
+                app.path().resource_dir()?.join("dictionaries"),
+                data_dir.join("dictionaries"),
+                data_dir.join("personal-dictionary.txt"),
+            )));
+            let queue = Arc::new(queue::UploadQueue::load(data_dir.join("upload-queue.json")));
+            tauri::async_runtime::spawn(queue.clone().run());
+            app.manage(queue);
+            power::start();
+            scheduler::start(app.handle().clone());
+            deeplink::init(app.handle());
+            tray::init(app.handle())?;
+            Ok(())
+        })
+        .on_window_event(|window, event| match event {
+            tauri::WindowEvent::CloseRequested { api, .. } => shutdown::on_close_requested(window, api),
+            tauri::WindowEvent::Destroyed => {
+                windows::forget(window.label());
+                shutdown::forget(window.label());
+            }
+            _ => {}
+        })
+        .invoke_handler(tauri::generate_handler![
+            compress_image,
+            math::render_math,
+            highlight::highlight_code,
+            highlight::list_highlight_options,
+            formatter::format_code,
+            pandoc::pandoc_convert,
+            print::print_to_pdf,
+            slides::export_slides,
+            single_file::export_single_html,
+            feed::generate_feed,
+            site::export_site,
+            template_export::export_with_template,
+            image_cache::fetch_remote_image,
+            net::configure_network,
+            sync::sync_now,
+            s3::upload_to_s3,
+            gist::publish_gist,
+            uploader::upload_image,
+            queue::enqueue_upload,
+            queue::enqueue_gist,
+            queue::list_queue,
+            queue::retry_queue,
+            queue::remove_from_queue,
+            link_preview::fetch_link_preview,
+            link_preview::fetch_title,
+            webhooks::configure_webhooks,
+            webhooks::emit_webhook_event,
+            weixin::weixin_request,
+            weixin::weixin_upload,
+            preview::start_preview_server,
+            preview::stop_preview_server,
+            preview::notify_preview_changed,
+            languagetool::check_grammar,
+            translate::translate,
+            ai::complete,
+            dictation::start_dictation,
+            dictation::stop_dictation,
+            speech::speak,
+            speech::pause_speech,
+            speech::resume_speech,
+            speech::stop_speech,
+            conflict::document_version,
+            conflict::save_document,
+            conflict::resolve_conflict,
+            sync::rotate_sync_key,
+            sync::change_sync_passphrase,
+            git::git_status,
+            git::git_commit,
+            git::git_log,
+            git::git_show,
+            git::git_diff,
+            git::configure_auto_commit,
+            git_remote::git_pull,
+            git_remote::git_push,
+            git::git_blame,
+            git_branch::git_branches,
+            git_branch::git_create_branch,
+            git_branch::git_checkout,
+            workspace::configure_workspace,
+            conflict_markers::find_conflict_markers,
+            conflict_markers::resolve_conflict_markers,
+            conflict_markers::scan_conflict_markers,
+            spellcheck::list_dictionaries,
+            spellcheck::spell_check,
+            spellcheck::add_to_dictionary,
+            spellcheck::install_dictionary,
+            prose_lint::lint_prose,
+            completion::autocomplete,
+            snippets::list_snippets,
+            snippets::save_snippet,
+            snippets::delete_snippet,
+            snippets::expand_snippet,
+            emoji::search_emoji,
+            typography::smarten_text,
+            text_case::slugify,
+            text_case::change_case,
+            citations::load_citation_library,
+            citations::cite_complete,
+            citations::format_bibliography,
+            citations::list_citation_styles,
+            footnotes::renumber_footnotes,
+            toc::generate_toc,
+            heading_numbers::number_headings,
+            heading_numbers::strip_heading_numbers,
+            markdown::render_markdown,
+            markdown_lint::lint_markdown,
+            markdown_lint::fix_markdown,
+            tasks::list_tasks,
+            tasks::toggle_task,
+            daily_notes::open_daily_note,
+            daily_notes::list_daily_notes,
+            templates::render_template,
+            readability::readability,
+            word_frequency::word_frequency,
+            regex_transform::regex_transform,
+            line_ops::transform_lines,
+            paste::classify_paste,
+            jobs::list_jobs,
+            jobs::cancel_job,
+            jobs::watch_jobs,
+            workers::configure_workers,
+            metrics::configure_metrics,
+            metrics::get_metrics,
+            metrics::clear_metrics,
+            logs::export_logs,
+            crash::last_crash,
+            crash::dismiss_crash,
+            logs::tail_logs,
+            logs::stop_tail_logs,
+            settings::get_settings,
+            settings::set_setting,
+            settings::reset_setting,
+            secrets::store_secret,
+            secrets::get_secret,
+            db::database_info,
+            scheduler::configure_scheduler,
+            scheduler::run_now,
+            power::report_activity,
+            power::configure_power,
+            power::power_status,
+            resources::resource_usage,
+            benchmark::benchmark_compression,
+            diagnostics::run_diagnostics,
+            flags::list_flags,
+            flags::set_flag,
+            plugins::list_plugins,
+            plugins::run_plugin,
+            scripting::run_script,
+            deeplink::create_note,
+            deeplink::watch_deep_links,
+            updater::check_for_update,
+            updater::download_update,
+            updater::install_update,
+            updater::restart_app,
+            i18n::get_messages,
+            windows::open_window,
+            windows::release_document,
+            windows::list_windows,
+            capture::configure_capture,
+            capture::capture,
+            sync::set_sync_paused,
+            clipboard::start_clipboard_capture,
+            clipboard::stop_clipboard_capture,
+            clipboard::clipboard_capture_running,
+            clipboard::clipboard_captures,
+            shutdown::watch_shutdown,
+            shutdown::flushed,
+            video::video_thumbnail,
+            video::compress_video,
+            voice_memo::start_voice_memo,
+            voice_memo::stop_voice_memo,
+            voice_memo::cancel_voice_memo,
+            waveform::audio_waveform,
+            video::convert_gif,
+            ocr::ocr_image,
+            qr::generate_qr,
+            qr::scan_codes,
+            alt_text::suggest_alt_text,
+            smart_crop::smart_crop,
+            background::remove_background
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| match event {
+            // without a code, the last window closed
+            tauri::RunEvent::ExitRequested { code: None, api, .. } if tray::keeps_running(app) => api.prevent_exit(),
+            tauri::RunEvent::ExitRequested { code, api, .. } => shutdown::on_exit_requested(app, code, &api),
+            // files opened with the app while it runs, or that launched it
+            #[cfg(target_os = "macos")]
+            tauri::RunEvent::Opened { urls } => deeplink::open_files(&urls),
+            _ => {}
+        });
 }
 
 fn try_compress_size(img: &DynamicImage, scaling: f64) -> Result<Vec<u8>, String> {
@@ -87,6 +471,61 @@ fn try_compress_size(img: &DynamicImage, scaling: f64) -> Result<Vec<u8>, String
     }
 }
 
+pub(crate) fn compress_file(path: &str, max_size: usize) -> Result<Vec<u8>, String> {
+    let original = 
+        fs::read(path).map_err(|e| format!("fs::read: {e}"))?;
+    compress_data(original, max_size)
+}
+
+/// Shrinks an encoded image until it is smaller than `max_size` bytes. Small
+/// enough non-JPEG images are returned unchanged; everything else comes out
+/// as JPEG.
+pub(crate) fn compress_data(original: Vec<u8>, max_size: usize) -> Result<Vec<u8>, String> {
+    let reader = 
+        ImageReader::new(Cursor::new(original.as_slice()))
+        .with_guessed_format()
+        .map_err(|e| format!("with_guessed_format: {e}"))?;
+    let format = reader
+        .format()
+        .ok_or("with_guessed_format: cannot guess format".to_owned())?;
+    let img = reader.decode().map_err(|e| format!("decode: {e}"))?;
+
+    log::info!("compress_image decoded image");
+
+    if format.to_mime_type() != "image/jpeg" {
+        if original.len() < max_size {
+            return Ok(original);
+        }
+
+        let result = try_compress_size(&img, 1.0)?;
+        if result.len() < max_size {
+            return Ok(result);
+        }
+    }
+
+    let mut l = 0.1;
+    let mut r = 1.0;
+    let mut last_ok: Option<Vec<u8>> = None;
+    let passable_size = (max_size.to_f64().unwrap() * 0.9).to_usize().unwrap();
+
+    for _ in 0..3 {
+        jobs::checkpoint()?;
+        let guess = (l + r) * 0.5;
+        let result = try_compress_size(&img, guess)?;
+        let size = result.len();
+        if size < max_size {
+            l = guess;
+            last_ok = Some(result);
+            if size > passable_size { break; }
+        } else {
+            r = guess;
+        }
+    }
+    let result = last_ok
+        .ok_or("Unable to compress within size limit".to_owned())?;
+    Ok(result)
+}
+
 /// format:
 /// {
 ///     `mime_type`: len([u32]) content(string);
@@ -95,56 +534,17 @@ fn try_compress_size(img: &DynamicImage, scaling: f64) -> Result<Vec<u8>, String
 #[tauri::command]
 #[allow(clippy::needless_pass_by_value)]
 async fn compress_image(
-    path: String, max_size: usize
-) -> Result<Response, String> {
+    path: String, max_size: usize, priority: Option<Priority>
+) -> Result<Response, BackendError> {
     log::info!("compress_image start");
+    let priority = priority.unwrap_or_default();
+    let job = jobs::start(jobs::JobKind::Compression, priority, path.clone());
+    let timer = metrics::timer("compress_image").size(fs::metadata(&path).map_or(0, |m| m.len()));
     let result = 
-    tokio::task::spawn_blocking(move || -> Result<Vec<u8>, String> {
-        let original = 
-            fs::read(path.clone()).map_err(|e| format!("fs::read: {e}"))?;
-        let reader = 
-            ImageReader::new(Cursor::new(original.as_slice()))
-            .with_guessed_format()
-            .map_err(|e| format!("with_guessed_format: {e}"))?;
-        let format = reader
-            .format()
-            .ok_or("with_guessed_format: cannot guess format".to_owned())?;
-        let img = reader.decode().map_err(|e| format!("decode: {e}"))?;
-
-        log::info!("compress_image decoded image");
-
-        if format.to_mime_type() != "image/jpeg" {
-            if original.len() < max_size {
-                return Ok(original);
-            }
-
-            let result = try_compress_size(&img, 1.0)?;
-            if result.len() < max_size {
-                return Ok(result);
-            }
-        }
-
-        let mut l = 0.1;
-        let mut r = 1.0;
-        let mut last_ok: Option<Vec<u8>> = None;
-        let passable_size = (max_size.to_f64().unwrap() * 0.9).to_usize().unwrap();
-
-        for _ in 0..3 {
-            let guess = (l + r) * 0.5;
-            let result = try_compress_size(&img, guess)?;
-            let size = result.len();
-            if size < max_size {
-                l = guess;
-                last_ok = Some(result);
-                if size > passable_size { break; }
-            } else {
-                r = guess;
-            }
-        }
-        let result = last_ok
-            .ok_or("Unable to compress within size limit".to_owned())?;
-        Ok(result)
+    workers::run(priority, move || {
+        job.run(|| compress_file(&path, max_size))
     }).await;
+    timer.finish(matches!(result, Ok(Ok(_))));
     
     match result {
         Ok(Ok(data)) => {
@@ -152,10 +552,10 @@ async fn compress_image(
             Ok(Response::new(data))
         }
         Ok(Err(e)) => {
-            Err(format!("compress_image task: {e}"))
+            Err(format!("compress_image task: {e}").into())
         }
         Err(e) => {
-            Err(format!("tokio::task::spawn_blocking: {e}"))
+            Err(format!("workers::run: {e}").into())
         }
     }
 }
\ No newline at end of file
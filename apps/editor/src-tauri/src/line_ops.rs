@@ -0,0 +1,157 @@
+//! Sorting, deduplicating, reversing and shuffling the lines of a
+//! selection, which can be megabytes long. Line endings stay what they
+//! were: CRLF if the text has any, and a final one if it ended with one.
+
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::RandomState, HashSet},
+    hash::BuildHasher,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    error::BackendError,
+    workers::{self, Priority},
+};
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum LineOperation {
+    Sort,
+    /// Numbers in lines compare by value, so "file2" comes before "file10".
+    NaturalSort,
+    /// Keeps the first of equal lines.
+    Unique,
+    Reverse,
+    Shuffle,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LineOptions {
+    /// For sorting.
+    descending: bool,
+    /// For sorting and deduplicating.
+    case_insensitive: bool,
+}
+
+/// Natural order: runs of digits compare as numbers.
+fn natural(a: &str, b: &str, case_insensitive: bool) -> Ordering {
+    let (mut i, mut j) = (0, 0);
+    loop {
+        let (x, y) = match (a[i..].chars().next(), b[j..].chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => (x, y),
+        };
+        let order = if x.is_ascii_digit() && y.is_ascii_digit() {
+            let end = |s: &str, at: usize| at + s[at..].find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len() - at);
+            let (x, y) = (&a[i..end(a, i)], &b[j..end(b, j)]);
+            i += x.len();
+            j += y.len();
+            let (tx, ty) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+            // longer numbers are larger; then digit by digit; then fewer
+            // leading zeros first
+            tx.len().cmp(&ty.len()).then_with(|| tx.cmp(ty)).then_with(|| x.len().cmp(&y.len()))
+        } else {
+            i += x.len_utf8();
+            j += y.len_utf8();
+            if case_insensitive && x != y { x.to_lowercase().cmp(y.to_lowercase()) } else { x.cmp(&y) }
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+}
+
+/// Fisher-Yates with xorshift, seeded by the hasher's random keys; good
+/// enough to shuffle lines.
+fn shuffle(lines: &mut [&str]) {
+    let mut state = RandomState::new().hash_one(lines.len()) | 1;
+    for i in (1..lines.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        #[allow(clippy::cast_possible_truncation)]
+        let j = (state % (i as u64 + 1)) as usize;
+        lines.swap(i, j);
+    }
+}
+
+fn apply(text: &str, operation: LineOperation, options: &LineOptions) -> String {
+    let ending = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let final_ending = text.ends_with('\n');
+    let mut lines: Vec<&str> = text.lines().collect();
+    let fold = |line: &str| if options.case_insensitive { line.to_lowercase() } else { line.to_owned() };
+    match operation {
+        LineOperation::Sort => {
+            if options.case_insensitive {
+                lines.sort_by_cached_key(|line| (line.to_lowercase(), *line));
+            } else {
+                lines.sort_unstable();
+            }
+        }
+        LineOperation::NaturalSort => {
+            lines.sort_by(|a, b| natural(a, b, options.case_insensitive).then_with(|| a.cmp(b)));
+        }
+        LineOperation::Unique => {
+            let mut seen = HashSet::with_capacity(lines.len());
+            lines.retain(|line| seen.insert(fold(line)));
+        }
+        LineOperation::Reverse => lines.reverse(),
+        LineOperation::Shuffle => shuffle(&mut lines),
+    }
+    if options.descending && matches!(operation, LineOperation::Sort | LineOperation::NaturalSort) {
+        lines.reverse();
+    }
+    let mut out = lines.join(ending);
+    if final_ending {
+        out.push_str(ending);
+    }
+    out
+}
+
+/// `text` with its lines rearranged by `operation`.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn transform_lines(
+    text: String, operation: LineOperation, options: LineOptions,
+) -> Result<String, BackendError> {
+    match workers::run(Priority::Interactive, move || apply(&text, operation, &options)).await {
+        Ok(text) => Ok(text),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_sort_by_value() {
+        assert_eq!(natural("file2", "file10", false), Ordering::Less);
+        assert_eq!(natural("file010", "file9", false), Ordering::Greater);
+        assert_eq!(natural("file02", "file2", false), Ordering::Greater);
+        assert_eq!(natural("B1", "a1", true), Ordering::Greater);
+        assert_eq!(natural("a", "a1", false), Ordering::Less);
+    }
+
+    #[test]
+    fn lines_keep_their_endings() {
+        let sorted = |text, operation, descending, case_insensitive| {
+            apply(text, operation, &LineOptions { descending, case_insensitive })
+        };
+        assert_eq!(sorted("b\na\nC\n", LineOperation::Sort, false, false), "C\na\nb\n");
+        assert_eq!(sorted("b\r\na\r\nC", LineOperation::Sort, false, true), "a\r\nb\r\nC");
+        assert_eq!(sorted("x10\nx9\nx1", LineOperation::NaturalSort, true, false), "x10\nx9\nx1");
+        assert_eq!(sorted("a\nA\nb\na\n", LineOperation::Unique, false, false), "a\nA\nb\n");
+        assert_eq!(sorted("a\nA\nb\na\n", LineOperation::Unique, false, true), "a\nb\n");
+        assert_eq!(sorted("1\n2\n3", LineOperation::Reverse, false, false), "3\n2\n1");
+
+        let text: String = (0..100).map(|i| format!("{i}\n")).collect();
+        let shuffled = sorted(&text, LineOperation::Shuffle, false, false);
+        assert_eq!(sorted(&shuffled, LineOperation::NaturalSort, false, false), text);
+    }
+}
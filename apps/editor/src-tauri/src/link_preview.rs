@@ -0,0 +1,292 @@
+//! Title, description and image of a web page, for rich link cards. Pages
+//! and images are fetched through [`crate::rate_limit`].
+
+use std::{cell::RefCell, fs, path::Path, time::Duration};
+
+use lol_html::{element, rewrite_str, text, RewriteStrSettings};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tauri::Url;
+
+use crate::{
+    compress_data,
+    error::BackendError,
+    jobs::{self, Job, JobKind},
+    net::{self, reqwest},
+    rate_limit,
+    workers::{self, Priority},
+    workspace::write_atomic,
+};
+
+/// The metadata is in the `<head>`, so there's no need for more.
+const MAX_PAGE_SIZE: usize = 512 * 1024;
+const MAX_IMAGE_SIZE: u64 = 20 * 1024 * 1024;
+const DEFAULT_IMAGE_MAX_SIZE: usize = 200 * 1024;
+/// `fetch_title` runs while the user waits for a paste to complete.
+const TITLE_TIMEOUT: Duration = Duration::from_secs(5);
+const TITLE_MAX_REDIRECTS: usize = 5;
+const TITLE_MAX_PAGE_SIZE: usize = 128 * 1024;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkPreview {
+    /// Canonical URL if the page names one, otherwise where redirects led.
+    url: String,
+    title: Option<String>,
+    description: Option<String>,
+    site_name: Option<String>,
+    image_url: Option<String>,
+    /// The compressed image saved in the assets folder.
+    image_path: Option<String>,
+}
+
+/// Decodes the character references in HTML text and attribute values;
+/// named ones beyond the common few are left as they are.
+pub(crate) fn unescape_html(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').filter(|&end| end <= 10).and_then(|end| {
+            let name = &rest[1..end];
+            let c = match name {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                "nbsp" => '\u{a0}',
+                _ => {
+                    let number = name.strip_prefix('#')?;
+                    let code = match number.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => number.parse().ok()?,
+                    };
+                    char::from_u32(code).filter(|&c| c != '\0')?
+                }
+            };
+            Some((c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Whitespace-collapsed and decoded; `None` if that leaves nothing.
+fn clean(text: &str) -> Option<String> {
+    let text = unescape_html(text);
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// The `<meta>` tags (by `property` or `name`) and the `<title>` of a page.
+#[derive(Default)]
+struct Meta {
+    tags: Vec<(String, String)>,
+    title: String,
+}
+
+impl Meta {
+    fn parse(html: &str) -> Self {
+        let meta = RefCell::new(Meta::default());
+        // only the handlers' side effects matter
+        let _ = rewrite_str(html, RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("meta[content]", |el| {
+                    let key = el.get_attribute("property").or_else(|| el.get_attribute("name"));
+                    if let (Some(key), Some(content)) = (key, el.get_attribute("content")) {
+                        meta.borrow_mut().tags.push((key.to_ascii_lowercase(), content));
+                    }
+                    Ok(())
+                }),
+                element!("link[rel=canonical][href]", |el| {
+                    if let Some(href) = el.get_attribute("href") {
+                        meta.borrow_mut().tags.push(("canonical".to_owned(), href));
+                    }
+                    Ok(())
+                }),
+                text!("head title", |chunk| {
+                    meta.borrow_mut().title.push_str(chunk.as_str());
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        });
+        meta.into_inner()
+    }
+
+    /// The first of `keys` that is present, in that order of preference.
+    fn first(&self, keys: &[&str]) -> Option<String> {
+        keys.iter().find_map(|key| {
+            self.tags.iter().find(|(k, _)| k == key).and_then(|(_, v)| clean(v))
+        })
+    }
+}
+
+async fn save_image(
+    client: &reqwest::Client, url: &Url, assets_dir: &Path, max_size: usize,
+) -> Result<String, String> {
+    let response = rate_limit::send(client.get(url.clone()))
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| format!("get {url}: {e}"))?;
+    let data = net::read_capped(response, MAX_IMAGE_SIZE).await?;
+    let assets_dir = assets_dir.to_owned();
+    let result = workers::run(Priority::Interactive, move || {
+        let data = compress_data(data, max_size)?;
+        let format = image::guess_format(&data).map_err(|e| format!("guess_format: {e}"))?;
+        let extension = format.extensions_str().first().copied().unwrap_or("bin");
+        let name = format!("{}.{extension}", &hex::encode(Sha256::digest(&data))[..16]);
+        fs::create_dir_all(&assets_dir)
+            .map_err(|e| format!("create {}: {e}", assets_dir.display()))?;
+        let path = assets_dir.join(name);
+        write_atomic(&path, &data)?;
+        Ok(path.to_string_lossy().into_owned())
+    }).await;
+    match result {
+        Ok(result) => result,
+        Err(e) => Err(format!("workers::run: {e}")),
+    }
+}
+
+fn check_url(url: &str) -> Result<Url, String> {
+    let parsed = Url::parse(url).map_err(|e| format!("invalid url {url}: {e}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("not an http(s) url: {url}"));
+    }
+    Ok(parsed)
+}
+
+/// Requests a web page; `None` if the response isn't HTML.
+async fn get_page(
+    client: &reqwest::Client, url: Url,
+) -> Result<Option<reqwest::Response>, String> {
+    let request = client.get(url.clone()).header(reqwest::header::ACCEPT, "text/html,application/xhtml+xml");
+    let response = rate_limit::send(request)
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| format!("get {url}: {e}"))?;
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_none_or(|v| v.contains("html"));
+    Ok(is_html.then_some(response))
+}
+
+/// The preview of `url`, stopping before the image if `job` was cancelled.
+async fn preview(url: &str, assets_dir: &Path, image_max_size: usize, job: &Job) -> Result<LinkPreview, String> {
+    let client = net::client()?;
+    let response = get_page(&client, check_url(url)?)
+        .await?
+        .ok_or_else(|| format!("{url} is not a web page"))?;
+    let final_url = response.url().clone();
+    let page = net::read_prefix(response, MAX_PAGE_SIZE).await?;
+    let meta = Meta::parse(&String::from_utf8_lossy(&page));
+
+    let resolve = |link: String| final_url.join(&link).ok();
+    let image = meta
+        .first(&["og:image:secure_url", "og:image", "og:image:url",
+            "twitter:image", "twitter:image:src"])
+        .and_then(resolve);
+    let mut preview = LinkPreview {
+        url: meta
+            .first(&["og:url", "canonical"])
+            .and_then(resolve)
+            .unwrap_or_else(|| final_url.clone())
+            .to_string(),
+        title: meta
+            .first(&["og:title", "twitter:title"])
+            .or_else(|| clean(&meta.title)),
+        description: meta.first(&["og:description", "twitter:description", "description"]),
+        site_name: meta.first(&["og:site_name", "application-name"]),
+        image_url: image.as_ref().map(Url::to_string),
+        image_path: None,
+    };
+    if job.is_cancelled() {
+        return Err(jobs::CANCELLED.to_owned());
+    }
+    if let Some(image) = image {
+        match save_image(&client, &image, assets_dir, image_max_size).await {
+            Ok(path) => preview.image_path = Some(path),
+            Err(e) => log::warn!("fetch_link_preview: image {image}: {e}"),
+        }
+    }
+    Ok(preview)
+}
+
+/// Fetches the OpenGraph / Twitter card metadata of `url`. The preview
+/// image is compressed below `image_max_size` bytes and saved into
+/// `assets_dir`; failing that, only its remote URL is returned.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn fetch_link_preview(
+    url: String, assets_dir: String, image_max_size: Option<usize>,
+) -> Result<LinkPreview, BackendError> {
+    log::info!("fetch_link_preview start: {url}");
+    let job = jobs::start(JobKind::Fetch, Priority::Interactive, &url);
+    let max_size = image_max_size.unwrap_or(DEFAULT_IMAGE_MAX_SIZE);
+    let result = preview(&url, Path::new(&assets_dir), max_size, &job).await;
+    job.finish(&result);
+    let preview = result.map_err(|e| format!("fetch_link_preview: {e}"))?;
+    log::info!("fetch_link_preview done");
+    Ok(preview)
+}
+
+/// The `<title>` of the page at `url` (or its `og:title`), for turning a
+/// pasted URL into a link. `None` if it isn't a web page or has no title.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn fetch_title(url: String) -> Result<Option<String>, BackendError> {
+    let client = net::quick_client(TITLE_TIMEOUT, TITLE_MAX_REDIRECTS)?;
+    let Some(response) = get_page(&client, check_url(&url)?)
+        .await
+        .map_err(|e| format!("fetch_title: {e}"))?
+    else {
+        return Ok(None);
+    };
+    let page = net::read_prefix(response, TITLE_MAX_PAGE_SIZE).await?;
+    let meta = Meta::parse(&String::from_utf8_lossy(&page));
+    Ok(clean(&meta.title).or_else(|| meta.first(&["og:title", "twitter:title"])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn character_references_are_decoded() {
+        assert_eq!(unescape_html("a &amp; b &lt;c&gt; &#39;d&#x27; &quot;e&quot;"), "a & b <c> 'd' \"e\"");
+        assert_eq!(unescape_html("&copy; &#0; &#xZZ; & &amp"), "&copy; &#0; &#xZZ; & &amp");
+        assert_eq!(clean("  two\n  lines&nbsp;"), Some("two lines".to_owned()));
+        assert_eq!(clean(" \n "), None);
+    }
+
+    #[test]
+    fn open_graph_comes_before_plain_meta() {
+        let meta = Meta::parse(r#"<html><head><title> The  title </title>
+            <meta name="description" content="Plain">
+            <meta property="OG:Description" content="Open &amp; graph">
+            <meta property="og:image" content="">
+            <link rel="canonical" href="https://example.com/a">
+            </head><body><title>Not this</title></body></html>"#);
+        assert_eq!(meta.first(&["og:description", "description"]).as_deref(), Some("Open & graph"));
+        assert_eq!(meta.first(&["twitter:description", "description"]).as_deref(), Some("Plain"));
+        assert_eq!(meta.first(&["og:image"]), None);
+        assert_eq!(meta.first(&["canonical"]).as_deref(), Some("https://example.com/a"));
+        assert_eq!(clean(&meta.title).as_deref(), Some("The title"));
+        assert!(check_url("https://example.com").is_ok());
+        assert!(check_url("file:///etc/passwd").is_err());
+    }
+}
@@ -0,0 +1,489 @@
+//! The log file, rotated when it grows past [`MAX_SIZE`] or a day ends,
+//! with old logs removed after [`RETENTION`], and bundled with some system
+//! information into a zip to attach to a bug report. Records are also
+//! streamed to an in-app console that asked for them with [`tail_logs`].
+
+use std::{
+    cell::Cell,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{write::DeflateEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use tauri::{ipc::Channel, AppHandle, Manager};
+use tauri_plugin_log::TimezoneStrategy;
+use time::{macros::format_description, Date, OffsetDateTime};
+
+use crate::{
+    error::BackendError,
+    metrics,
+    workers::{self, Priority},
+};
+
+/// A log is rotated once it's this big.
+const MAX_SIZE: u64 = 5 << 20;
+/// Rotated logs are kept this long, and at most [`KEEP`] of them.
+const RETENTION: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+const KEEP: usize = 20;
+/// The newest logs are bundled until they add up to this.
+const BUNDLE_SIZE: u64 = 50 << 20;
+
+static TAILS: Mutex<Vec<Tail>> = Mutex::new(Vec::new());
+static NEXT_TAIL: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// Set while sending to tails, so that anything logged on the way
+    /// isn't sent again.
+    static SENDING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<log::Level> for LogLevel {
+    fn from(level: log::Level) -> LogLevel {
+        match level {
+            log::Level::Error => LogLevel::Error,
+            log::Level::Warn => LogLevel::Warn,
+            log::Level::Info => LogLevel::Info,
+            log::Level::Debug => LogLevel::Debug,
+            log::Level::Trace => LogLevel::Trace,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LogFilter {
+    /// The least severe level sent.
+    level: LogLevel,
+    /// Prefixes of the targets sent, like `kfgui::sync`; all if empty.
+    targets: Vec<String>,
+    /// Only records with this in the message, ignoring case.
+    contains: Option<String>,
+}
+
+impl LogFilter {
+    fn matches(&self, record: &LogRecord) -> bool {
+        record.level <= self.level
+            && (self.targets.is_empty() || self.targets.iter().any(|t| record.target.starts_with(t.as_str())))
+            && self.contains.as_ref().is_none_or(|s| record.message.to_lowercase().contains(&s.to_lowercase()))
+    }
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LogRecord {
+    level: LogLevel,
+    target: String,
+    message: String,
+    /// Milliseconds since the epoch.
+    time: u64,
+}
+
+struct Tail {
+    id: u64,
+    filter: LogFilter,
+    channel: Channel<LogRecord>,
+}
+
+/// Appends to `<name>.log`, renaming it to `<name>_<date>.log` first when
+/// it's too big or from another day, like the log plugin does at startup.
+pub struct RotatingFile {
+    dir: PathBuf,
+    name: String,
+    file: File,
+    size: u64,
+    day: Date,
+}
+
+pub fn now() -> OffsetDateTime {
+    TimezoneStrategy::UseLocal.get_now()
+}
+
+fn open(path: &Path) -> Result<File, String> {
+    File::options().create(true).append(true).open(path).map_err(|e| format!("open {}: {e}", path.display()))
+}
+
+impl RotatingFile {
+    pub fn open(dir: &Path, name: &str) -> Result<Mutex<RotatingFile>, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+        let path = dir.join(format!("{name}.log"));
+        let file = open(&path)?;
+        let metadata = file.metadata().map_err(|e| format!("metadata {}: {e}", path.display()))?;
+        let today = now();
+        let day = metadata
+            .modified()
+            .map_or(today.date(), |m| OffsetDateTime::from(m).to_offset(today.offset()).date());
+        let mut log = RotatingFile { dir: dir.to_owned(), name: name.to_owned(), file, size: metadata.len(), day };
+        if log.day != today.date() {
+            log.rotate()?;
+        }
+        log.prune();
+        Ok(Mutex::new(log))
+    }
+
+    fn rotate(&mut self) -> Result<(), String> {
+        let path = self.dir.join(format!("{}.log", self.name));
+        let date = now().format(format_description!("[year]-[month]-[day]_[hour]-[minute]-[second]"))
+            .map_err(|e| format!("format date: {e}"))?;
+        let rotated = self.dir.join(format!("{}_{date}.log", self.name));
+        fs::rename(&path, &rotated).map_err(|e| format!("rename {}: {e}", path.display()))?;
+        self.file = open(&path)?;
+        self.size = 0;
+        self.day = now().date();
+        self.prune();
+        Ok(())
+    }
+
+    /// Removes rotated logs past the retention.
+    fn prune(&self) {
+        let prefix = format!("{}_", self.name);
+        let mut rotated: Vec<(PathBuf, SystemTime)> = log_files(&self.dir)
+            .into_iter()
+            .filter(|(path, _)| path.file_name().is_some_and(|n| n.to_string_lossy().starts_with(&prefix)))
+            .collect();
+        rotated.sort_by_key(|&(_, modified)| std::cmp::Reverse(modified));
+        for (i, (path, modified)) in rotated.iter().enumerate() {
+            let expired = modified.elapsed().is_ok_and(|age| age > RETENTION);
+            if i >= KEEP || expired {
+                if let Err(e) = fs::remove_file(path) {
+                    eprintln!("remove {}: {e}", path.display());
+                }
+            }
+        }
+    }
+
+    /// Writes one formatted line. Errors go to stderr, as there's nowhere
+    /// else to log them.
+    pub fn write_line(&mut self, line: &str) {
+        if self.size + line.len() as u64 > MAX_SIZE || self.day != now().date() {
+            if let Err(e) = self.rotate() {
+                eprintln!("rotate log: {e}");
+            }
+        }
+        match writeln!(self.file, "{line}") {
+            Ok(()) => self.size += line.len() as u64 + 1,
+            Err(e) => eprintln!("write log: {e}"),
+        }
+    }
+}
+
+/// The `.log` files in `dir` with when they were last written.
+fn log_files(dir: &Path) -> Vec<(PathBuf, SystemTime)> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let path = entry.path();
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            (path.extension().is_some_and(|e| e == "log")).then_some((path, modified))
+        })
+        .collect()
+}
+
+/// What's attached to a bug report about the system.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemInfo {
+    app: String,
+    version: String,
+    tauri: &'static str,
+    os: &'static str,
+    family: &'static str,
+    arch: &'static str,
+    cores: usize,
+    /// Of the report, in the local time zone.
+    created: String,
+}
+
+impl SystemInfo {
+    pub fn new(app: &AppHandle) -> SystemInfo {
+        SystemInfo {
+            app: app.package_info().name.clone(),
+            version: app.package_info().version.to_string(),
+            tauri: tauri::VERSION,
+            os: std::env::consts::OS,
+            family: std::env::consts::FAMILY,
+            arch: std::env::consts::ARCH,
+            cores: thread::available_parallelism().map_or(1, usize::from),
+            created: now().to_string(),
+        }
+    }
+}
+
+/// Writes a zip, deflating every entry.
+struct ZipWriter {
+    out: File,
+    offset: u32,
+    central: Vec<u8>,
+    entries: u16,
+    /// MS-DOS time and date, for every entry.
+    modified: (u16, u16),
+}
+
+impl ZipWriter {
+    fn new(out: File) -> ZipWriter {
+        let now = now();
+        let time = u16::from(now.hour()) << 11 | u16::from(now.minute()) << 5 | u16::from(now.second() / 2);
+        let year = u16::try_from(now.year() - 1980).unwrap_or(0);
+        let date = year << 9 | u16::from(u8::from(now.month())) << 5 | u16::from(now.day());
+        ZipWriter { out, offset: 0, central: Vec::new(), entries: 0, modified: (time, date) }
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).map_err(|e| format!("deflate {name}: {e}"))?;
+        let compressed = encoder.finish().map_err(|e| format!("deflate {name}: {e}"))?;
+        let too_big = || format!("{name} is too big for a zip");
+        let size = u32::try_from(data.len()).map_err(|_| too_big())?;
+        let compressed_size = u32::try_from(compressed.len()).map_err(|_| too_big())?;
+        let name_len = u16::try_from(name.len()).map_err(|_| too_big())?;
+        // version 2.0, UTF-8 names, deflate
+        let mut fields = Vec::new();
+        for n in [20, 0x0800, 8, self.modified.0, self.modified.1] {
+            fields.extend_from_slice(&u16::to_le_bytes(n));
+        }
+        for n in [crc32fast::hash(data), compressed_size, size] {
+            fields.extend_from_slice(&n.to_le_bytes());
+        }
+        fields.extend_from_slice(&name_len.to_le_bytes());
+        // no extra field
+        fields.extend_from_slice(&[0, 0]);
+
+        let mut local = 0x0403_4b50_u32.to_le_bytes().to_vec();
+        local.extend_from_slice(&fields);
+        local.extend_from_slice(name.as_bytes());
+        self.out.write_all(&local).map_err(|e| format!("write zip: {e}"))?;
+        self.out.write_all(&compressed).map_err(|e| format!("write zip: {e}"))?;
+
+        self.central.extend_from_slice(&0x0201_4b50_u32.to_le_bytes());
+        // made by version 2.0
+        self.central.extend_from_slice(&20_u16.to_le_bytes());
+        self.central.extend_from_slice(&fields);
+        // no comment, disk 0, no attributes
+        self.central.extend_from_slice(&[0; 10]);
+        self.central.extend_from_slice(&self.offset.to_le_bytes());
+        self.central.extend_from_slice(name.as_bytes());
+
+        let written = u32::try_from(local.len() + compressed.len()).map_err(|_| too_big())?;
+        self.offset = self.offset.checked_add(written).ok_or_else(|| "the bundle is too big for a zip".to_owned())?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), String> {
+        let central_size = u32::try_from(self.central.len()).map_err(|_| "too many files for a zip".to_owned())?;
+        let mut end = 0x0605_4b50_u32.to_le_bytes().to_vec();
+        // disk 0, with the central directory
+        end.extend_from_slice(&[0; 4]);
+        end.extend_from_slice(&self.entries.to_le_bytes());
+        end.extend_from_slice(&self.entries.to_le_bytes());
+        end.extend_from_slice(&central_size.to_le_bytes());
+        end.extend_from_slice(&self.offset.to_le_bytes());
+        // no comment
+        end.extend_from_slice(&[0, 0]);
+        self.out.write_all(&self.central).map_err(|e| format!("write zip: {e}"))?;
+        self.out.write_all(&end).map_err(|e| format!("write zip: {e}"))?;
+        self.out.sync_all().map_err(|e| format!("write zip: {e}"))
+    }
+}
+
+fn bundle(log_dir: &Path, info: &SystemInfo, target: &Path) -> Result<usize, String> {
+    let mut logs = log_files(log_dir);
+    logs.sort_by_key(|&(_, modified)| std::cmp::Reverse(modified));
+    let out = File::create(target).map_err(|e| format!("create {}: {e}", target.display()))?;
+    let mut zip = ZipWriter::new(out);
+    let info = serde_json::to_vec_pretty(info).expect("system info serialize");
+    zip.add("system.json", &info)?;
+    if metrics::is_enabled() {
+        let metrics = serde_json::to_vec_pretty(&metrics::get_metrics()).expect("metrics serialize");
+        zip.add("metrics.json", &metrics)?;
+    }
+    let mut total = 0;
+    let mut count = 0;
+    for (path, _) in logs {
+        let data = fs::read(&path).map_err(|e| format!("read {}: {e}", path.display()))?;
+        total += data.len() as u64;
+        if total > BUNDLE_SIZE && count > 0 {
+            break;
+        }
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        zip.add(&format!("logs/{name}"), &data)?;
+        count += 1;
+    }
+    zip.finish()?;
+    Ok(count)
+}
+
+/// Sends `record` to every tail it matches, forgetting those that are gone.
+pub fn tail(record: &log::Record) {
+    if SENDING.get() {
+        return;
+    }
+    let mut tails = TAILS.lock().expect("tails lock poisoned");
+    if tails.is_empty() {
+        return;
+    }
+    let record = LogRecord {
+        level: record.level().into(),
+        target: record.target().to_owned(),
+        message: record.args().to_string(),
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+            .unwrap_or_default(),
+    };
+    SENDING.set(true);
+    tails.retain(|tail| !tail.filter.matches(&record) || tail.channel.send(record.clone()).is_ok());
+    SENDING.set(false);
+}
+
+/// Streams the log records matching `filter` to `channel` from now on,
+/// until [`stop_tail_logs`] with the returned ID.
+#[tauri::command]
+pub fn tail_logs(filter: LogFilter, channel: Channel<LogRecord>) -> u64 {
+    let id = NEXT_TAIL.fetch_add(1, Ordering::Relaxed);
+    TAILS.lock().expect("tails lock poisoned").push(Tail { id, filter, channel });
+    id
+}
+
+#[tauri::command]
+pub fn stop_tail_logs(id: u64) {
+    TAILS.lock().expect("tails lock poisoned").retain(|tail| tail.id != id);
+}
+
+/// How many [`tail_logs`] are running.
+pub fn tail_count() -> usize {
+    TAILS.lock().expect("tails lock poisoned").len()
+}
+
+/// Writes the newest logs and a description of the system, and the metrics
+/// if they're collected, to the zip at `target_zip`; returns how many logs
+/// it has.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn export_logs(target_zip: String, app: AppHandle) -> Result<usize, BackendError> {
+    log::info!("export_logs start: {target_zip}");
+    let log_dir = app.path().app_log_dir().map_err(|e| format!("app_log_dir: {e}"))?;
+    let info = SystemInfo::new(&app);
+    let result = workers::run(Priority::Interactive, move || bundle(&log_dir, &info, Path::new(&target_zip))).await;
+    match result {
+        Ok(Ok(count)) => {
+            log::info!("export_logs done: {count} logs");
+            Ok(count)
+        }
+        Ok(Err(e)) => Err(format!("export_logs task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::DeflateDecoder;
+
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("emmm-logs-{name}-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).expect("create the test folder");
+        dir
+    }
+
+    #[test]
+    fn filters_match_level_target_and_text() {
+        let record = LogRecord {
+            level: LogLevel::Warn,
+            target: "kfgui_lib::sync".to_owned(),
+            message: "Upload FAILED".to_owned(),
+            time: 0,
+        };
+        let filter = |level, targets: &[&str], contains: Option<&str>| LogFilter {
+            level,
+            targets: targets.iter().map(|&t| t.to_owned()).collect(),
+            contains: contains.map(str::to_owned),
+        };
+        assert!(filter(LogLevel::Info, &[], None).matches(&record));
+        assert!(!filter(LogLevel::Error, &[], None).matches(&record));
+        assert!(filter(LogLevel::Warn, &["kfgui_lib::s", "other"], Some("failed")).matches(&record));
+        assert!(!filter(LogLevel::Trace, &["other"], None).matches(&record));
+        assert!(!filter(LogLevel::Trace, &[], Some("timeout")).matches(&record));
+    }
+
+    #[test]
+    fn big_logs_are_rotated_and_old_ones_removed() {
+        let dir = temp_dir("rotate");
+        let old = dir.join("app_2000-01-01_00-00-00.log");
+        File::create(&old)
+            .and_then(|f| f.set_modified(SystemTime::now() - RETENTION - Duration::from_secs(60)))
+            .expect("write an old log");
+        let log = RotatingFile::open(&dir, "app").expect("open the log");
+        assert!(!old.exists());
+        let mut log = log.into_inner().expect("log lock poisoned");
+        log.write_line("first");
+        log.size = MAX_SIZE;
+        log.write_line("second");
+        let mut logs: Vec<_> = log_files(&dir)
+            .into_iter()
+            .map(|(path, _)| fs::read_to_string(path).expect("read a log"))
+            .collect();
+        logs.sort();
+        assert_eq!(logs, ["first\n", "second\n"]);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn bundles_are_zips_of_the_logs() {
+        let dir = temp_dir("bundle");
+        fs::write(dir.join("app.log"), "a line\n".repeat(100)).expect("write a log");
+        let info = SystemInfo {
+            app: "emmm".to_owned(),
+            version: "1.0.0".to_owned(),
+            tauri: tauri::VERSION,
+            os: "linux",
+            family: "unix",
+            arch: "x86_64",
+            cores: 4,
+            created: String::new(),
+        };
+        let target = dir.join("bundle.zip");
+        assert_eq!(bundle(&dir, &info, &target).expect("bundle the logs"), 1);
+
+        let zip = fs::read(&target).expect("read the zip");
+        let u16_at = |at: usize| u16::from_le_bytes([zip[at], zip[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(zip[at..at + 4].try_into().expect("four bytes"));
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(end), 0x0605_4b50);
+        assert_eq!(u16_at(end + 10), 2);
+        // the first entry, from its local header
+        assert_eq!(u32_at(0), 0x0403_4b50);
+        let (compressed, name_len) = (u32_at(18) as usize, u16_at(26) as usize);
+        assert_eq!(&zip[30..30 + name_len], b"system.json");
+        let mut json = String::new();
+        DeflateDecoder::new(&zip[30 + name_len..30 + name_len + compressed])
+            .read_to_string(&mut json)
+            .expect("inflate the entry");
+        assert_eq!(u32_at(14), crc32fast::hash(json.as_bytes()));
+        assert!(json.contains("\"app\": \"emmm\""), "{json}");
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,336 @@
+use std::{collections::HashMap, path::Path};
+
+use pulldown_cmark::{
+    html, CodeBlockKind, CowStr, Event, HeadingLevel, Options, Parser, Tag, TagEnd,
+};
+use serde::Serialize;
+
+use crate::{
+    citations,
+    error::BackendError,
+    frontmatter::Frontmatter,
+    highlight, inline, math,
+    workers::{self, Priority},
+};
+
+const DEFAULT_CODE_THEME: &str = "InspiredGitHub";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    Link,
+    Image,
+}
+
+/// Gets to change link and image destinations before they are rendered;
+/// `None` keeps the original.
+pub type UrlRewriter<'a> = &'a dyn Fn(&str, LinkKind) -> Option<String>;
+
+/// How markdown is turned into HTML for exports. Images are only inlined
+/// when `image_max_size` is set; math is always rendered to SVG.
+#[derive(Clone, Copy, Default)]
+pub struct RenderOptions<'a> {
+    pub base_dir: Option<&'a Path>,
+    pub image_max_size: Option<usize>,
+    pub code_theme: Option<&'a str>,
+    pub rewrite_url: Option<UrlRewriter<'a>>,
+}
+
+/// A lowercase, dash-separated form of `text` that is safe in file names,
+/// URLs and anchors. Letters of any script are kept.
+pub(crate) fn slug(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            out.push(c);
+        } else if !out.is_empty() && !out.ends_with('-') {
+            out.push('-');
+        }
+    }
+    out.trim_end_matches('-').to_owned()
+}
+
+/// Hands out heading anchors, numbering repeats like GitHub does:
+/// `intro`, `intro-1`, `intro-2`...
+#[derive(Default)]
+pub(crate) struct Anchors {
+    seen: HashMap<String, usize>,
+}
+
+impl Anchors {
+    pub fn next(&mut self, text: &str) -> String {
+        let base = match slug(text) {
+            s if s.is_empty() => "section".to_owned(),
+            s => s,
+        };
+        let count = self.seen.entry(base.clone()).or_insert(0);
+        let id = if *count == 0 { base.clone() } else { format!("{base}-{count}") };
+        *count += 1;
+        id
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Heading {
+    pub level: u8,
+    pub text: String,
+    /// The anchor `to_html` gives this heading.
+    pub id: String,
+}
+
+fn level_number(level: HeadingLevel) -> u8 {
+    match level {
+        HeadingLevel::H1 => 1,
+        HeadingLevel::H2 => 2,
+        HeadingLevel::H3 => 3,
+        HeadingLevel::H4 => 4,
+        HeadingLevel::H5 => 5,
+        HeadingLevel::H6 => 6,
+    }
+}
+
+/// All headings of a document, in order.
+pub fn headings(markdown: &str) -> Vec<Heading> {
+    let mut anchors = Anchors::default();
+    let mut result = Vec::new();
+    let mut current: Option<Heading> = None;
+    for event in Parser::new_ext(markdown, Options::all()) {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                current = Some(Heading {
+                    level: level_number(level),
+                    text: String::new(),
+                    id: id.map(|i| i.to_string()).unwrap_or_default(),
+                });
+            }
+            Event::Text(t) | Event::Code(t) | Event::InlineMath(t) => {
+                if let Some(h) = &mut current {
+                    h.text.push_str(&t);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(mut h) = current.take() {
+                    if h.id.is_empty() {
+                        h.id = anchors.next(&h.text);
+                    }
+                    result.push(h);
+                }
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// Gives every heading without an explicit `{#id}` the anchor from
+/// [`Anchors`], so links into exported documents are predictable.
+fn assign_heading_ids(events: &mut [Event]) {
+    let mut anchors = Anchors::default();
+    let mut i = 0;
+    while i < events.len() {
+        if let Event::Start(Tag::Heading { id: None, .. }) = &events[i] {
+            let mut text = String::new();
+            for event in &events[i + 1..] {
+                match event {
+                    Event::End(TagEnd::Heading(_)) => break,
+                    Event::Text(t) | Event::Code(t) | Event::InlineMath(t) => text.push_str(t),
+                    _ => {}
+                }
+            }
+            if let Event::Start(Tag::Heading { id, .. }) = &mut events[i] {
+                *id = Some(anchors.next(&text).into());
+            }
+        }
+        i += 1;
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn render_math(tex: &str, display: bool) -> String {
+    match math::tex_to_svg(tex, display) {
+        Ok(svg) if display => format!("<div class=\"math display\">{svg}</div>"),
+        Ok(svg) => format!("<span class=\"math inline\">{svg}</span>"),
+        Err(e) => {
+            log::warn!("render_math: {e}");
+            format!("<code class=\"math-error\">{}</code>", escape(tex))
+        }
+    }
+}
+
+fn render_code(code: &str, language: &str, theme: &str) -> String {
+    let class = if language.is_empty() {
+        String::new()
+    } else {
+        format!(" class=\"language-{}\"", escape(language))
+    };
+    let body = highlight::highlight(code, language, theme).unwrap_or_else(|e| {
+        log::warn!("highlight: {e}");
+        escape(code)
+    });
+    format!("<pre><code{class}>{body}</code></pre>\n")
+}
+
+fn rewrite<'a>(url: CowStr<'a>, kind: LinkKind, options: &RenderOptions) -> CowStr<'a> {
+    options
+        .rewrite_url
+        .and_then(|f| f(&url, kind))
+        .map_or(url, CowStr::from)
+}
+
+fn inline_image<'a>(url: CowStr<'a>, options: &RenderOptions) -> CowStr<'a> {
+    let Some(max_size) = options.image_max_size else { return url };
+    let Some(path) = inline::resolve_local(&url, options.base_dir) else {
+        return url;
+    };
+    match inline::image_data_uri(&path, max_size) {
+        Ok(uri) => uri.into(),
+        Err(e) => {
+            log::warn!("inline image {}: {e}", path.display());
+            url
+        }
+    }
+}
+
+pub fn to_html(markdown: &str, options: &RenderOptions) -> String {
+    let parser = Parser::new_ext(markdown, Options::all());
+    let theme = options.code_theme.unwrap_or(DEFAULT_CODE_THEME);
+
+    let mut events = Vec::new();
+    let mut code: Option<(String, String)> = None;
+    for event in parser {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) =>
+                        info.split_whitespace().next().unwrap_or("").to_owned(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((language, String::new()));
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, buf)) = &mut code {
+                    buf.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((language, buf)) = code.take() {
+                    events.push(Event::Html(render_code(&buf, &language, theme).into()));
+                }
+            }
+            Event::InlineMath(tex) =>
+                events.push(Event::InlineHtml(render_math(&tex, false).into())),
+            Event::DisplayMath(tex) =>
+                events.push(Event::Html(render_math(&tex, true).into())),
+            Event::Start(Tag::Image { link_type, dest_url, title, id }) => {
+                events.push(Event::Start(Tag::Image {
+                    link_type,
+                    dest_url: inline_image(rewrite(dest_url, LinkKind::Image, options), options),
+                    title,
+                    id,
+                }));
+            }
+            Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+                events.push(Event::Start(Tag::Link {
+                    link_type,
+                    dest_url: rewrite(dest_url, LinkKind::Link, options),
+                    title,
+                    id,
+                }));
+            }
+            other => events.push(other),
+        }
+    }
+
+    assign_heading_ids(&mut events);
+    let mut out = String::new();
+    html::push_html(&mut out, events.into_iter());
+    out
+}
+
+/// A document as exports render it.
+pub(crate) struct Rendered {
+    pub content: String,
+    /// Of what the citations cite, if any.
+    pub bibliography: Option<String>,
+}
+
+impl Rendered {
+    /// The content with the bibliography after it.
+    pub(crate) fn into_html(self) -> String {
+        match self.bibliography {
+            Some(bibliography) => format!("{}{bibliography}", self.content),
+            None => self.content,
+        }
+    }
+}
+
+/// The `body` of a document as HTML with its citations rendered, if its
+/// frontmatter names a `bibliography`, in its `csl` style, like pandoc.
+/// Both are found relative to `options.base_dir`.
+pub(crate) fn render_document(body: &str, meta: &Frontmatter, options: &RenderOptions) -> Result<Rendered, String> {
+    let (cited, bibliography) = citations::for_export(body, meta, options.base_dir)?;
+    Ok(Rendered { content: to_html(&cited, options), bibliography })
+}
+
+/// The markdown `source` of the document at `path`, if it's saved, as the
+/// HTML of its body, bibliography included; to print or export as a
+/// single file.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn render_markdown(source: String, path: Option<String>) -> Result<String, BackendError> {
+    log::info!("render_markdown start: {} bytes", source.len());
+    let result = workers::run(Priority::Interactive, move || {
+        let (meta, body) = Frontmatter::of(&source);
+        let base_dir = path.as_deref().map(Path::new).and_then(Path::parent);
+        render_document(body, &meta, &RenderOptions { base_dir, ..RenderOptions::default() }).map(Rendered::into_html)
+    })
+    .await;
+    match result {
+        Ok(Ok(html)) => {
+            log::info!("render_markdown done");
+            Ok(html)
+        }
+        Ok(Err(e)) => Err(format!("render_markdown task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_render_their_citations() {
+        let dir = std::env::temp_dir().join(format!("emmm-markdown-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let library = r#"[{"id": "roe21", "type": "book", "title": "Writing Well",
+            "author": [{"family": "Roe", "given": "Richard"}], "issued": {"date-parts": [[2021]]}}]"#;
+        std::fs::write(dir.join("refs.json"), library).unwrap();
+        let options = RenderOptions { base_dir: Some(&dir), ..RenderOptions::default() };
+
+        let (meta, body) = Frontmatter::of("---\nbibliography: refs.json\n---\nSee [@roe21], not `[@roe21]`.\n");
+        let html = render_document(body, &meta, &options).unwrap().into_html();
+        assert!(html.contains("<span class=\"citation\">(Roe"), "{html}");
+        assert!(html.contains("<code>[@roe21]</code>"), "{html}");
+        assert!(html.contains("id=\"ref-roe21\""), "{html}");
+
+        let (meta, body) = Frontmatter::of("See [@roe21].\n");
+        let rendered = render_document(body, &meta, &options).unwrap();
+        assert_eq!((rendered.content.as_str(), rendered.bibliography), ("<p>See [@roe21].</p>\n", None));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
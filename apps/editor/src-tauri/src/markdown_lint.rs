@@ -0,0 +1,348 @@
+//! Markdown style checks in the spirit of markdownlint, with its rule
+//! names and its configuration file: a `.markdownlint.json` at the root of
+//! the workspace can turn rules off (`"MD009": false`, or `"default":
+//! false` for all) and set their options.
+
+use std::{fs, path::Path};
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::BackendError;
+
+const CONFIG_FILE: &str = ".markdownlint.json";
+
+struct Rule {
+    id: &'static str,
+    name: &'static str,
+}
+
+const HEADING_INCREMENT: Rule = Rule { id: "MD001", name: "heading-increment" };
+const LIST_STYLE: Rule = Rule { id: "MD004", name: "ul-style" };
+const TRAILING_SPACES: Rule = Rule { id: "MD009", name: "no-trailing-spaces" };
+const BARE_URLS: Rule = Rule { id: "MD034", name: "no-bare-urls" };
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LintDiagnostic {
+    /// In UTF-16 code units from the start of the text, like JavaScript
+    /// string indices.
+    offset: usize,
+    length: usize,
+    /// Like `MD009`.
+    rule: &'static str,
+    /// Like `no-trailing-spaces`.
+    name: &'static str,
+    message: String,
+    /// Replaces the range to fix it, if it can be fixed automatically.
+    fix: Option<String>,
+}
+
+/// A diagnostic in byte offsets.
+struct Finding {
+    start: usize,
+    end: usize,
+    rule: &'static Rule,
+    message: String,
+    fix: Option<String>,
+}
+
+struct Config {
+    value: Value,
+}
+
+impl Config {
+    fn load(workspace: Option<&Path>) -> Result<Self, String> {
+        let value = match workspace.map(|w| w.join(CONFIG_FILE)) {
+            Some(path) if path.is_file() => {
+                let source = fs::read_to_string(&path).map_err(|e| format!("read {}: {e}", path.display()))?;
+                serde_json::from_str(&source).map_err(|e| format!("parse {}: {e}", path.display()))?
+            }
+            _ => Value::Null,
+        };
+        Ok(Config { value })
+    }
+
+    fn rule(&self, rule: &Rule) -> Option<&Value> {
+        self.value.get(rule.id).or_else(|| self.value.get(rule.name))
+    }
+
+    fn enabled(&self, rule: &Rule) -> bool {
+        match self.rule(rule) {
+            Some(Value::Bool(enabled)) => *enabled,
+            Some(_) => true,
+            None => self.value.get("default").and_then(Value::as_bool).unwrap_or(true),
+        }
+    }
+
+    fn option(&self, rule: &Rule, name: &str) -> Option<&Value> {
+        self.rule(rule)?.get(name)
+    }
+}
+
+/// Trailing white space, except the two spaces of a hard line break.
+fn trailing_spaces(text: &str, config: &Config, out: &mut Vec<Finding>) {
+    let br_spaces = config
+        .option(&TRAILING_SPACES, "br_spaces")
+        .and_then(Value::as_u64)
+        .map_or(2, |n| usize::try_from(n).unwrap_or(usize::MAX));
+    let mut at = 0;
+    let mut fenced = false;
+    let mut lines = text.split_inclusive('\n').peekable();
+    while let Some(line) = lines.next() {
+        let start = at;
+        at += line.len();
+        let content = line.trim_end_matches(['\n', '\r']);
+        let trimmed = content.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fenced = !fenced;
+        }
+        let stripped = content.trim_end_matches([' ', '\t']);
+        let trailing = &content[stripped.len()..];
+        if trailing.is_empty() {
+            continue;
+        }
+        // a break only counts in the middle of a paragraph
+        let next_has_text = lines.peek().is_some_and(|next| !next.trim().is_empty());
+        let is_break = !fenced
+            && !stripped.is_empty()
+            && br_spaces >= 2
+            && trailing.len() == br_spaces
+            && !trailing.contains('\t')
+            && next_has_text;
+        if is_break {
+            continue;
+        }
+        out.push(Finding {
+            start: start + stripped.len(),
+            end: start + content.len(),
+            rule: &TRAILING_SPACES,
+            message: format!("{} trailing space{}", trailing.len(), if trailing.len() == 1 { "" } else { "s" }),
+            fix: Some(String::new()),
+        });
+    }
+}
+
+fn marker_name(marker: char) -> &'static str {
+    match marker {
+        '*' => "asterisk",
+        '+' => "plus",
+        _ => "dash",
+    }
+}
+
+/// Skipped heading levels, bullet list markers other than the configured
+/// or first one, and URLs that aren't links.
+fn structure(text: &str, config: &Config, out: &mut Vec<Finding>) {
+    let check_headings = config.enabled(&HEADING_INCREMENT);
+    let check_lists = config.enabled(&LIST_STYLE);
+    let check_urls = config.enabled(&BARE_URLS);
+    let mut expected = match config.option(&LIST_STYLE, "style").and_then(Value::as_str) {
+        Some("dash") => Some('-'),
+        Some("asterisk") => Some('*'),
+        Some("plus") => Some('+'),
+        _ => None,
+    };
+    let mut previous_level = None;
+    let mut lists: Vec<bool> = Vec::new();
+    // links, code blocks and the frontmatter can have URLs as they are
+    let mut verbatim = 0;
+    for (event, range) in Parser::new_ext(text, Options::all()).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                let level = level as usize;
+                if check_headings && previous_level.is_some_and(|p| level > p + 1) {
+                    let p = previous_level.unwrap_or_default();
+                    out.push(Finding {
+                        start: range.start,
+                        end: range.start + text[range.clone()].trim_end().len(),
+                        rule: &HEADING_INCREMENT,
+                        message: format!("Level {level} heading after a level {p} one; expected level {}", p + 1),
+                        fix: None,
+                    });
+                }
+                previous_level = Some(level);
+            }
+            Event::Start(Tag::List(start)) => lists.push(start.is_none()),
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+            }
+            Event::Start(Tag::Item) if check_lists && lists.last() == Some(&true) => {
+                let Some(marker) = text[range.start..].chars().next().filter(|c| matches!(c, '-' | '*' | '+'))
+                else {
+                    continue;
+                };
+                let expected = *expected.get_or_insert(marker);
+                if marker != expected {
+                    out.push(Finding {
+                        start: range.start,
+                        end: range.start + 1,
+                        rule: &LIST_STYLE,
+                        message: format!("List marker {marker}; expected {}", marker_name(expected)),
+                        fix: Some(expected.to_string()),
+                    });
+                }
+            }
+            Event::Start(Tag::Link { .. } | Tag::Image { .. } | Tag::CodeBlock(_) | Tag::MetadataBlock(_)) => {
+                verbatim += 1;
+            }
+            Event::End(TagEnd::Link | TagEnd::Image | TagEnd::CodeBlock | TagEnd::MetadataBlock(_)) => verbatim -= 1,
+            Event::Text(t) if check_urls && verbatim == 0 => bare_urls(&t, range.start, &text[range], out),
+            _ => {}
+        }
+    }
+}
+
+fn bare_urls(text: &str, offset: usize, source: &str, out: &mut Vec<Finding>) {
+    // escapes make the text differ from the source
+    if text != source {
+        return;
+    }
+    let mut from = 0;
+    while let Some(found) = ["https://", "http://"].iter().filter_map(|s| text[from..].find(s)).min() {
+        let start = from + found;
+        let rest = &text[start..];
+        let mut url = &rest[..rest.find(|c: char| c.is_whitespace() || c == '<').unwrap_or(rest.len())];
+        // trailing punctuation belongs to the sentence, and a closing
+        // parenthesis too unless the URL has the opening one
+        loop {
+            let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '"']);
+            let trimmed = if trimmed.ends_with(')') && trimmed.matches('(').count() < trimmed.matches(')').count() {
+                &trimmed[..trimmed.len() - 1]
+            } else {
+                trimmed
+            };
+            if trimmed.len() == url.len() {
+                break;
+            }
+            url = trimmed;
+        }
+        from = start + url.len().max(1);
+        if url.len() <= "https://".len() {
+            continue;
+        }
+        out.push(Finding {
+            start: offset + start,
+            end: offset + start + url.len(),
+            rule: &BARE_URLS,
+            message: "Bare URL; put it in <> to make it a link".to_owned(),
+            fix: Some(format!("<{url}>")),
+        });
+    }
+}
+
+fn lint(text: &str, config: &Config) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if config.enabled(&TRAILING_SPACES) {
+        trailing_spaces(text, config, &mut findings);
+    }
+    structure(text, config, &mut findings);
+    findings.sort_by_key(|f| (f.start, f.end));
+    findings
+}
+
+fn diagnostics(text: &str, findings: Vec<Finding>) -> Vec<LintDiagnostic> {
+    // UTF-16 offsets, counted in one pass since findings are sorted
+    let (mut done, mut offset) = (0, 0);
+    findings
+        .into_iter()
+        .map(|finding| {
+            offset += text[done..finding.start].encode_utf16().count();
+            done = finding.start;
+            LintDiagnostic {
+                offset,
+                length: text[finding.start..finding.end].encode_utf16().count(),
+                rule: finding.rule.id,
+                name: finding.rule.name,
+                message: finding.message,
+                fix: finding.fix,
+            }
+        })
+        .collect()
+}
+
+fn fix(text: &str, findings: &[Finding]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut done = 0;
+    for finding in findings {
+        let Some(replacement) = &finding.fix else { continue };
+        // overlapping fixes wait for the next run
+        if finding.start < done {
+            continue;
+        }
+        out.push_str(&text[done..finding.start]);
+        out.push_str(replacement);
+        done = finding.end;
+    }
+    out.push_str(&text[done..]);
+    out
+}
+
+/// Diagnostics for `text`, in order, with the configuration of
+/// `workspace` if it has one.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn lint_markdown(text: String, workspace: Option<String>) -> Result<Vec<LintDiagnostic>, BackendError> {
+    let config = Config::load(workspace.as_deref().map(Path::new))?;
+    Ok(diagnostics(&text, lint(&text, &config)))
+}
+
+/// `text` with every fixable diagnostic fixed at once, so applying it is a
+/// single edit.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn fix_markdown(text: String, workspace: Option<String>) -> Result<String, BackendError> {
+    let config = Config::load(workspace.as_deref().map(Path::new))?;
+    Ok(fix(&text, &lint(&text, &config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules<'a>(text: &'a str, config: &Config) -> Vec<(&'static str, &'a str)> {
+        lint(text, config).into_iter().map(|f| (f.rule.id, &text[f.start..f.end])).collect()
+    }
+
+    #[test]
+    fn trailing_spaces_spare_line_breaks() {
+        let config = Config { value: Value::Null };
+        let text = "line one  \nmore \nend  \n\n```\ncode  \n```\n";
+        assert_eq!(rules(text, &config), [("MD009", " "), ("MD009", "  "), ("MD009", "  ")]);
+        assert_eq!(fix(text, &lint(text, &config)), "line one  \nmore\nend\n\n```\ncode\n```\n");
+    }
+
+    #[test]
+    fn headings_lists_and_urls_are_checked() {
+        let config = Config { value: Value::Null };
+        let text = "# A\n\n### C\n\n- a\n* b\n\nSee https://example.com/x(y). and <https://ok.com> \
+                    [l](https://l.com) `https://code`\n";
+        assert_eq!(rules(text, &config), [
+            ("MD001", "### C"),
+            ("MD004", "*"),
+            ("MD034", "https://example.com/x(y)"),
+        ]);
+        assert_eq!(
+            fix(text, &lint(text, &config)),
+            text.replace("* b", "- b").replace("See https://example.com/x(y)", "See <https://example.com/x(y)>"),
+        );
+    }
+
+    #[test]
+    fn the_configuration_turns_rules_off_and_sets_options() {
+        let config = Config {
+            value: serde_json::json!({ "default": false, "ul-style": { "style": "asterisk" } }),
+        };
+        assert_eq!(rules("# A\n\n### C  \n\n- a\n* b\n\nhttps://example.com\n", &config), [("MD004", "-")]);
+        let config = Config { value: serde_json::json!({ "MD009": { "br_spaces": 3 } }) };
+        assert_eq!(rules("a  \nb\n", &config), [("MD009", "  ")]);
+    }
+
+    #[test]
+    fn offsets_are_in_utf_16() {
+        let text = "日本 \u{1F600} \n";
+        let found = diagnostics(text, lint(text, &Config { value: Value::Null }));
+        assert_eq!((found[0].offset, found[0].length), (5, 1));
+    }
+}
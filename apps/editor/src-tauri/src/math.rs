@@ -0,0 +1,179 @@
+use std::sync::OnceLock;
+
+use typst::{
+    diag::{FileError, FileResult},
+    foundations::{Bytes, Datetime},
+    layout::{Abs, PagedDocument},
+    syntax::{FileId, Source, VirtualPath},
+    text::{Font, FontBook},
+    utils::LazyHash,
+    Library, LibraryExt, World,
+};
+
+use crate::{
+    error::BackendError,
+    workers::{self, Priority},
+};
+
+/// Definitions for the helpers that `mitex` emits but Typst lacks.
+const PRELUDE: &str = r#"
+#set page(width: auto, height: auto, margin: 0pt, fill: none)
+#set text(size: 11pt)
+#let zws = sym.zws
+#let rows(args) = {
+  let a = args.pos()
+  if a.all(x => type(x) != array) { (a,) } else { a }
+}
+#let matrix(..args) = math.mat(delim: none, ..rows(args))
+#let pmatrix(..args) = math.mat(delim: "(", ..rows(args))
+#let bmatrix(..args) = math.mat(delim: "[", ..rows(args))
+#let Bmatrix(..args) = math.mat(delim: "{", ..rows(args))
+#let vmatrix(..args) = math.mat(delim: "|", ..rows(args))
+#let Vmatrix(..args) = math.mat(delim: "‖", ..rows(args))
+#let mitexarray(arg0: none, ..args) = math.mat(delim: none, ..rows(args))
+#let aligned(it) = it
+#let mitexsqrt(..args) = {
+  let a = args.pos()
+  if a.len() == 1 { math.sqrt(a.at(0)) } else { math.root(a.at(0), a.at(1)) }
+}
+#let operatorname(it) = math.op(it)
+#let textmath(it) = it
+#let mitexdisplay(it) = math.display(it)
+#let mitexinline(it) = math.inline(it)
+#let mitexscript(it) = math.script(it)
+#let mitexsscript(it) = math.sscript(it)
+#let mitexbold(it) = math.bold(it)
+#let mitexmathbf(it) = math.bold(math.upright(it))
+#let mitexupright(it) = math.upright(it)
+#let mitexitalic(it) = math.italic(it)
+#let mitexsans(it) = math.sans(it)
+#let mitexmono(it) = math.mono(it)
+#let mitexcal(it) = math.cal(it)
+#let mitexfrak(it) = math.frak(it)
+#let mitexoverbrace(it) = math.overbrace(it)
+#let mitexunderbrace(it) = math.underbrace(it)
+#let mitexoverbracket(it) = math.overbracket(it)
+#let mitexunderbracket(it) = math.underbracket(it)
+#let mitexcolor(color, ..body) = body.pos().join()
+#let colortext(color, body) = body
+#let mitexlabel(..args) = []
+#let mitexcaption(..args) = []
+#let mitexcite(..args) = []
+#let mitexref(..args) = []
+#let miteximage(..args) = []
+"#;
+
+struct Fonts {
+    book: LazyHash<FontBook>,
+    fonts: Vec<Font>,
+}
+
+fn fonts() -> &'static Fonts {
+    static FONTS: OnceLock<Fonts> = OnceLock::new();
+    FONTS.get_or_init(|| {
+        let fonts: Vec<Font> = typst_assets::fonts()
+            .flat_map(|data| Font::iter(Bytes::new(data)))
+            .collect();
+        Fonts {
+            book: LazyHash::new(FontBook::from_fonts(&fonts)),
+            fonts,
+        }
+    })
+}
+
+fn library() -> &'static LazyHash<Library> {
+    static LIBRARY: OnceLock<LazyHash<Library>> = OnceLock::new();
+    LIBRARY.get_or_init(|| LazyHash::new(Library::default()))
+}
+
+struct MathWorld {
+    main: Source,
+}
+
+impl World for MathWorld {
+    fn library(&self) -> &LazyHash<Library> {
+        library()
+    }
+
+    fn book(&self) -> &LazyHash<FontBook> {
+        &fonts().book
+    }
+
+    fn main(&self) -> FileId {
+        self.main.id()
+    }
+
+    fn source(&self, id: FileId) -> FileResult<Source> {
+        if id == self.main.id() {
+            Ok(self.main.clone())
+        } else {
+            Err(FileError::NotFound(id.vpath().as_rootless_path().into()))
+        }
+    }
+
+    fn file(&self, id: FileId) -> FileResult<Bytes> {
+        Err(FileError::NotFound(id.vpath().as_rootless_path().into()))
+    }
+
+    fn font(&self, index: usize) -> Option<Font> {
+        fonts().fonts.get(index).cloned()
+    }
+
+    fn today(&self, _offset: Option<i64>) -> Option<Datetime> {
+        None
+    }
+}
+
+pub(crate) fn tex_to_svg(tex: &str, display: bool) -> Result<String, String> {
+    let math =
+        mitex::convert_math(tex, None).map_err(|e| format!("convert_math: {e}"))?;
+    // spaces inside the dollars make typst lay it out as a display block
+    let body = if display {
+        format!("$ {math} $")
+    } else {
+        format!("${math}$")
+    };
+    let world = MathWorld {
+        main: Source::new(
+            FileId::new(None, VirtualPath::new("main.typ")),
+            format!("{PRELUDE}\n{body}"),
+        ),
+    };
+    let document: PagedDocument = typst::compile(&world).output.map_err(|errors| {
+        let msgs: Vec<_> = errors.iter().map(|e| e.message.to_string()).collect();
+        format!("compile: {}", msgs.join("; "))
+    })?;
+    Ok(typst_svg::svg_merged(&document, Abs::zero()))
+}
+
+/// Renders a TeX formula to a standalone SVG string. Goes through typst
+/// (after converting with mitex) so that no javascript engine is needed.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn render_math(tex: String, display: bool) -> Result<String, BackendError> {
+    log::info!("render_math start");
+    let result =
+        workers::run(Priority::Interactive, move || tex_to_svg(&tex, display)).await;
+
+    match result {
+        Ok(Ok(svg)) => {
+            log::info!("render_math done");
+            Ok(svg)
+        }
+        Ok(Err(e)) => Err(format!("render_math task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formulas_come_out_as_svg() {
+        let svg = tex_to_svg(r"\frac{a}{b}", false).unwrap();
+        assert!(svg.starts_with("<svg"), "{svg}");
+        assert!(tex_to_svg(r"\frac{a}{b}", true).unwrap().starts_with("<svg"));
+        assert!(tex_to_svg(r"\frac{a", false).is_err());
+    }
+}
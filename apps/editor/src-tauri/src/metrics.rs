@@ -0,0 +1,302 @@
+//! Opt-in performance metrics, kept in a local file and never sent
+//! anywhere: how often each operation ran, how long it took and how often
+//! it failed, and for operations on files, time by input size. Users can
+//! attach them to a bug report about something being slow.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs, io,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::{error::BackendError, workspace::write_atomic};
+
+/// Durations kept per operation for the percentiles.
+const RECENT: usize = 200;
+/// Pending changes are written at most this often.
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// Upper bounds of the size buckets, in bytes; the last is open ended.
+const SIZE_BUCKETS: [u64; 4] = [100 << 10, 1 << 20, 5 << 20, 20 << 20];
+
+static METRICS: Mutex<Option<Recorder>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+struct SizeStats {
+    count: u64,
+    total_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+struct Stats {
+    count: u64,
+    failures: u64,
+    total_ms: u64,
+    max_ms: u64,
+    recent_ms: VecDeque<u64>,
+    /// By the upper bound of the bucket in bytes, `u64::MAX` for the last.
+    by_size: BTreeMap<u64, SizeStats>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct Saved {
+    /// Seconds since the epoch.
+    since: u64,
+    operations: BTreeMap<String, Stats>,
+}
+
+struct Recorder {
+    path: PathBuf,
+    saved: Saved,
+    dirty: bool,
+    last_save: Instant,
+}
+
+impl Recorder {
+    fn save(&mut self) {
+        let data = serde_json::to_vec(&self.saved).expect("metrics serialize");
+        if let Err(e) = write_atomic(&self.path, &data) {
+            log::warn!("metrics: {e}");
+        }
+        self.dirty = false;
+        self.last_save = Instant::now();
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SizeBucket {
+    /// Bytes, inclusive; `None` for the last bucket.
+    max_bytes: Option<u64>,
+    count: u64,
+    average_ms: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationMetrics {
+    name: String,
+    count: u64,
+    failures: u64,
+    /// 0 to 1.
+    failure_rate: f64,
+    average_ms: f64,
+    max_ms: u64,
+    /// Of the last 200 runs.
+    median_ms: u64,
+    p95_ms: u64,
+    by_size: Vec<SizeBucket>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metrics {
+    enabled: bool,
+    /// When collecting started, in seconds since the epoch.
+    since: Option<u64>,
+    operations: Vec<OperationMetrics>,
+}
+
+/// Times one run of an operation.
+pub struct Timer {
+    operation: &'static str,
+    started: Instant,
+    size: Option<u64>,
+}
+
+/// Starts timing `operation`; nothing is recorded unless metrics are on.
+pub fn timer(operation: &'static str) -> Timer {
+    Timer { operation, started: Instant::now(), size: None }
+}
+
+impl Timer {
+    /// The size of the input in bytes, to see time against size.
+    pub fn size(mut self, bytes: u64) -> Timer {
+        self.size = Some(bytes);
+        self
+    }
+
+    pub fn finish(self, ok: bool) {
+        let mut metrics = METRICS.lock().expect("metrics lock poisoned");
+        let Some(recorder) = metrics.as_mut() else { return };
+        let ms = u64::try_from(self.started.elapsed().as_millis()).unwrap_or(u64::MAX);
+        let stats = recorder.saved.operations.entry(self.operation.to_owned()).or_default();
+        stats.count += 1;
+        stats.failures += u64::from(!ok);
+        stats.total_ms = stats.total_ms.saturating_add(ms);
+        stats.max_ms = stats.max_ms.max(ms);
+        if stats.recent_ms.len() == RECENT {
+            stats.recent_ms.pop_front();
+        }
+        stats.recent_ms.push_back(ms);
+        if let Some(size) = self.size {
+            let bucket = SIZE_BUCKETS.iter().copied().find(|&max| size <= max).unwrap_or(u64::MAX);
+            let by_size = stats.by_size.entry(bucket).or_default();
+            by_size.count += 1;
+            by_size.total_ms = by_size.total_ms.saturating_add(ms);
+        }
+        recorder.dirty = true;
+        if recorder.last_save.elapsed() > SAVE_INTERVAL {
+            recorder.save();
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+fn metrics_path(app: &AppHandle) -> Result<PathBuf, String> {
+    let dir = app.path().app_data_dir().map_err(|e| format!("app_data_dir: {e}"))?;
+    Ok(dir.join("metrics.json"))
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn average(total: u64, count: u64) -> f64 {
+    if count == 0 { 0.0 } else { (total as f64 / count as f64 * 10.0).round() / 10.0 }
+}
+
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    if sorted.is_empty() { 0 } else { sorted[(sorted.len() - 1) * p / 100] }
+}
+
+fn report(name: &str, stats: &Stats) -> OperationMetrics {
+    let mut recent: Vec<u64> = stats.recent_ms.iter().copied().collect();
+    recent.sort_unstable();
+    #[allow(clippy::cast_precision_loss)]
+    let failure_rate = if stats.count == 0 { 0.0 } else { stats.failures as f64 / stats.count as f64 };
+    OperationMetrics {
+        name: name.to_owned(),
+        count: stats.count,
+        failures: stats.failures,
+        failure_rate,
+        average_ms: average(stats.total_ms, stats.count),
+        max_ms: stats.max_ms,
+        median_ms: percentile(&recent, 50),
+        p95_ms: percentile(&recent, 95),
+        by_size: stats
+            .by_size
+            .iter()
+            .map(|(&max, s)| SizeBucket {
+                max_bytes: (max != u64::MAX).then_some(max),
+                count: s.count,
+                average_ms: average(s.total_ms, s.count),
+            })
+            .collect(),
+    }
+}
+
+/// Turns collecting on or off. What was collected stays in the file
+/// until [`clear_metrics`].
+#[tauri::command]
+pub fn configure_metrics(enabled: bool, app: AppHandle) -> Result<(), BackendError> {
+    log::info!("configure_metrics: {enabled}");
+    let mut metrics = METRICS.lock().expect("metrics lock poisoned");
+    if !enabled {
+        if let Some(mut recorder) = metrics.take() {
+            if recorder.dirty {
+                recorder.save();
+            }
+        }
+        return Ok(());
+    }
+    if metrics.is_some() {
+        return Ok(());
+    }
+    let path = metrics_path(&app)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    }
+    let saved = match fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+            log::warn!("metrics: {}: {e}", path.display());
+            Saved::default()
+        }),
+        Err(_) => Saved::default(),
+    };
+    let since = if saved.since == 0 { now() } else { saved.since };
+    *metrics = Some(Recorder { path, saved: Saved { since, ..saved }, dirty: true, last_save: Instant::now() });
+    Ok(())
+}
+
+/// Writes what wasn't saved yet, like before quitting.
+pub fn flush() {
+    if let Some(recorder) = METRICS.lock().expect("metrics lock poisoned").as_mut().filter(|r| r.dirty) {
+        recorder.save();
+    }
+}
+
+pub fn is_enabled() -> bool {
+    METRICS.lock().expect("metrics lock poisoned").is_some()
+}
+
+/// What was collected, by operation name.
+#[tauri::command]
+pub fn get_metrics() -> Metrics {
+    let mut metrics = METRICS.lock().expect("metrics lock poisoned");
+    let Some(recorder) = metrics.as_mut() else {
+        return Metrics { enabled: false, since: None, operations: Vec::new() };
+    };
+    if recorder.dirty {
+        recorder.save();
+    }
+    Metrics {
+        enabled: true,
+        since: Some(recorder.saved.since),
+        operations: recorder.saved.operations.iter().map(|(name, stats)| report(name, stats)).collect(),
+    }
+}
+
+/// Forgets everything collected so far, deleting the file if collecting
+/// is off.
+#[tauri::command]
+pub fn clear_metrics(app: AppHandle) -> Result<(), BackendError> {
+    let mut metrics = METRICS.lock().expect("metrics lock poisoned");
+    if let Some(recorder) = metrics.as_mut() {
+        recorder.saved = Saved { since: now(), operations: BTreeMap::new() };
+        recorder.save();
+        return Ok(());
+    }
+    let path = metrics_path(&app)?;
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(BackendError::io(format!("remove {}", path.display()), &e))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_have_percentiles_and_size_buckets() {
+        let stats = Stats {
+            count: 4,
+            failures: 1,
+            total_ms: 100,
+            max_ms: 70,
+            recent_ms: VecDeque::from([70, 10, 15, 5]),
+            by_size: BTreeMap::from([
+                (100 << 10, SizeStats { count: 3, total_ms: 10 }),
+                (u64::MAX, SizeStats { count: 1, total_ms: 70 }),
+            ]),
+        };
+        let compress = report("compress", &stats);
+        assert_eq!((compress.failure_rate, compress.average_ms), (0.25, 25.0));
+        assert_eq!((compress.median_ms, compress.p95_ms, compress.max_ms), (10, 15, 70));
+        let buckets: Vec<_> = compress.by_size.iter().map(|b| (b.max_bytes, b.count, b.average_ms)).collect();
+        assert_eq!(buckets, [(Some(100 << 10), 3, 3.3), (None, 1, 70.0)]);
+
+        let empty = report("none", &Stats::default());
+        assert_eq!((empty.failure_rate, empty.average_ms, empty.median_ms), (0.0, 0.0, 0));
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    fs,
+    io::Read,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{error::BackendError, rate_limit};
+pub use tauri_plugin_http::reqwest;
+
+const USER_AGENT: &str = concat!("emmm/", env!("CARGO_PKG_VERSION"));
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Network settings from the frontend, applied to every client we build.
+#[derive(Clone, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NetworkConfig {
+    /// `http://`, `https://`, `socks5://` or `socks5h://` URL, optionally
+    /// with credentials.
+    proxy: Option<String>,
+    /// Comma-separated hosts that bypass the proxy.
+    no_proxy: Option<String>,
+    /// PEM files with extra root certificates to trust.
+    ca_certificates: Vec<String>,
+}
+
+/// The parsed form of a [`NetworkConfig`].
+#[derive(Default)]
+struct Network {
+    proxy: Option<reqwest::Proxy>,
+    certificates: Vec<reqwest::Certificate>,
+}
+
+static NETWORK: RwLock<Option<Arc<Network>>> = RwLock::new(None);
+
+impl Network {
+    fn load(config: &NetworkConfig) -> Result<Self, String> {
+        let proxy = match config.proxy.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(url) => {
+                let proxy = reqwest::Proxy::all(url)
+                    .map_err(|e| format!("invalid proxy {url}: {e}"))?
+                    .no_proxy(config.no_proxy.as_deref().and_then(reqwest::NoProxy::from_string));
+                Some(proxy)
+            }
+        };
+        let mut certificates = Vec::new();
+        for path in &config.ca_certificates {
+            let pem = fs::read(path).map_err(|e| format!("read {path}: {e}"))?;
+            let bundle = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| format!("invalid certificate {path}: {e}"))?;
+            if bundle.is_empty() {
+                return Err(format!("no certificates in {path}"));
+            }
+            certificates.extend(bundle);
+        }
+        Ok(Network { proxy, certificates })
+    }
+
+    fn current() -> Arc<Network> {
+        NETWORK
+            .read()
+            .expect("network lock poisoned")
+            .clone()
+            .unwrap_or_default()
+    }
+}
+
+/// The two builders have the same methods but no common trait.
+macro_rules! configure {
+    ($builder:expr, $network:expr) => {{
+        let mut builder = $builder.user_agent(USER_AGENT);
+        if let Some(proxy) = &$network.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        for certificate in &$network.certificates {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+        builder.build().map_err(|e| format!("build client: {e}"))
+    }};
+}
+
+pub fn client() -> Result<reqwest::Client, String> {
+    configure!(reqwest::Client::builder().timeout(TIMEOUT), Network::current())
+}
+
+pub fn blocking_client() -> Result<reqwest::blocking::Client, String> {
+    configure!(reqwest::blocking::Client::builder().timeout(TIMEOUT), Network::current())
+}
+
+/// A client for lookups that shouldn't keep the user waiting.
+pub fn quick_client(timeout: Duration, max_redirects: usize) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::limited(max_redirects));
+    configure!(builder, Network::current())
+}
+
+/// A client for responses that stream for as long as they need, giving up
+/// only when nothing arrives for `idle`.
+pub fn streaming_client(idle: Duration) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder()
+        .connect_timeout(TIMEOUT)
+        .read_timeout(idle);
+    configure!(builder, Network::current())
+}
+
+/// Sets the proxy and extra certificates used for all backend requests
+/// from now on. Nothing changes if the configuration is invalid.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn configure_network(config: NetworkConfig) -> Result<(), BackendError> {
+    let network = Network::load(&config)?;
+    // make sure a client can actually be built with these
+    configure!(reqwest::Client::builder().timeout(TIMEOUT), network)?;
+    log::info!(
+        "configure_network: proxy {}, {} extra certificates",
+        if network.proxy.is_some() { "on" } else { "off" },
+        network.certificates.len());
+    *NETWORK.write().expect("network lock poisoned") = Some(Arc::new(network));
+    Ok(())
+}
+
+/// Downloads `url`, refusing bodies larger than `max_bytes`, politely: see
+/// [`rate_limit`]. Returns the content type along with the data.
+pub fn fetch_blocking(
+    client: &reqwest::blocking::Client, url: &str, max_bytes: u64
+) -> Result<(Option<String>, Vec<u8>), String> {
+    let response = rate_limit::send_blocking(client.get(url))
+        .and_then(reqwest::blocking::Response::error_for_status)
+        .map_err(|e| format!("get {url}: {e}"))?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or(v).trim().to_owned());
+    Ok((content_type, read_capped_blocking(response, max_bytes)?))
+}
+
+/// Blocking version of [`read_capped`].
+pub fn read_capped_blocking(
+    response: reqwest::blocking::Response, max_bytes: u64
+) -> Result<Vec<u8>, String> {
+    let url = response.url().to_string();
+    let mut data = Vec::new();
+    response
+        .take(max_bytes + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| format!("read {url}: {e}"))?;
+    if data.len() as u64 > max_bytes {
+        return Err(format!("{url} is larger than {max_bytes} bytes"));
+    }
+    Ok(data)
+}
+
+/// Reads a response body, refusing bodies larger than `max_bytes`.
+pub async fn read_capped(
+    mut response: reqwest::Response, max_bytes: u64
+) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| format!("read body: {e}"))? {
+        data.extend_from_slice(&chunk);
+        if data.len() as u64 > max_bytes {
+            return Err(format!("response is larger than {max_bytes} bytes"));
+        }
+    }
+    Ok(data)
+}
+
+/// Reads at most `max_bytes` of a response body and drops the rest, for
+/// when the beginning is all we need.
+pub async fn read_prefix(
+    mut response: reqwest::Response, max_bytes: usize
+) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    while data.len() < max_bytes {
+        let Some(chunk) = response.chunk().await.map_err(|e| format!("read body: {e}"))? else {
+            break;
+        };
+        data.extend_from_slice(&chunk);
+    }
+    data.truncate(max_bytes);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(json: &str) -> Result<Network, String> {
+        Network::load(&serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn network_settings_are_checked_when_set() {
+        let network = load(r#"{"proxy": " ", "caCertificates": []}"#).unwrap();
+        assert!(network.proxy.is_none() && network.certificates.is_empty());
+        assert!(load(r#"{"proxy": "socks5://127.0.0.1:1080", "noProxy": "localhost"}"#).unwrap().proxy.is_some());
+        assert!(load(r#"{"proxy": "not a url"}"#).is_err());
+        let missing = load(r#"{"caCertificates": ["/no/such/ca.pem"]}"#).err().unwrap();
+        assert!(missing.starts_with("read /no/such/ca.pem"), "{missing}");
+    }
+}
@@ -0,0 +1,118 @@
+//! Text in images, like screenshots of error messages, recognized by the
+//! tesseract the user installed, so it can be searched and quoted.
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{error::BackendError, metrics, process};
+
+const TIMEOUT: Duration = Duration::from_secs(120);
+const DEFAULT_LANG: &str = "eng";
+/// Of tesseract's TSV output, the rows of words.
+const WORD_LEVEL: &str = "5";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OcrWord {
+    text: String,
+    /// From 0 to 100.
+    confidence: f32,
+    /// In pixels, from the top left of the image.
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OcrResult {
+    /// Lines as they're laid out, with a blank line between paragraphs.
+    text: String,
+    /// Of the words on average, from 0 to 100; 0 if there are none.
+    confidence: f32,
+    /// Only if asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    words: Option<Vec<OcrWord>>,
+}
+
+/// Like `eng` or `eng+deu`, as tesseract names its trained data.
+fn check_lang(lang: &str) -> Result<(), String> {
+    let valid = lang.split('+').all(|part| {
+        !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid language {lang:?}; use names like eng or eng+deu"))
+    }
+}
+
+/// A word of tesseract's TSV output, with the paragraph and line it's in.
+struct Row {
+    paragraph: (u32, u32),
+    line: u32,
+    word: OcrWord,
+}
+
+fn parse_row(line: &str) -> Option<Row> {
+    let columns: Vec<&str> = line.split('\t').collect();
+    // level page block par line word left top width height conf text
+    if columns.len() < 12 || columns[0] != WORD_LEVEL {
+        return None;
+    }
+    let number = |i: usize| columns[i].parse::<u32>().ok();
+    let text = columns[11..].join("\t").trim().to_owned();
+    let confidence = columns[10].parse::<f32>().ok().filter(|&c| c >= 0.0)?;
+    if text.is_empty() {
+        return None;
+    }
+    Some(Row {
+        paragraph: (number(2)?, number(3)?),
+        line: number(4)?,
+        word: OcrWord { text, confidence, x: number(6)?, y: number(7)?, width: number(8)?, height: number(9)? },
+    })
+}
+
+fn parse_tsv(tsv: &str, with_words: bool) -> OcrResult {
+    let rows: Vec<Row> = tsv.lines().skip(1).filter_map(parse_row).collect();
+    let mut text = String::new();
+    let mut last: Option<((u32, u32), u32)> = None;
+    for row in &rows {
+        match last {
+            Some((paragraph, _)) if paragraph != row.paragraph => text.push_str("\n\n"),
+            Some((_, line)) if line != row.line => text.push('\n'),
+            Some(_) => text.push(' '),
+            None => {}
+        }
+        text.push_str(&row.word.text);
+        last = Some((row.paragraph, row.line));
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let confidence =
+        if rows.is_empty() { 0.0 } else { rows.iter().map(|r| r.word.confidence).sum::<f32>() / rows.len() as f32 };
+    let words = with_words.then(|| rows.into_iter().map(|r| r.word).collect());
+    OcrResult { text, confidence, words }
+}
+
+/// Recognizes the text in the image at `path`, in `lang`, English by
+/// default, whose trained data must be installed with tesseract. With
+/// `boxes`, also where each word is.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn ocr_image(path: String, lang: Option<String>, boxes: Option<bool>) -> Result<OcrResult, BackendError> {
+    log::info!("ocr_image start: {path}");
+    let lang = lang.filter(|l| !l.is_empty()).unwrap_or_else(|| DEFAULT_LANG.to_owned());
+    check_lang(&lang).map_err(BackendError::invalid)?;
+    let tesseract = process::find_program("tesseract")
+        .ok_or_else(|| BackendError::not_found("tesseract not found; install it to recognize text in images"))?;
+    let args = [path.clone(), "stdout".to_owned(), "-l".to_owned(), lang, "tsv".to_owned()];
+    let timer = metrics::timer("ocr_image");
+    let result = process::run_piped(&tesseract, &args, Vec::new(), TIMEOUT).await;
+    timer.finish(result.is_ok());
+    let output = result.map_err(|e| format!("ocr_image: {e}"))?;
+    let result = parse_tsv(&String::from_utf8_lossy(&output), boxes.unwrap_or(false));
+    log::info!("ocr_image done: {} characters", result.text.len());
+    Ok(result)
+}
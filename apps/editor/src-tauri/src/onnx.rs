@@ -0,0 +1,45 @@
+//! ONNX Runtime, for the local models, like the one suggesting alt text.
+//! It's an optional download: its library is loaded when a model is first
+//! run, not linked, so the app runs without it.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+use ort::session::Session;
+
+/// `runtime_path`, or the library next to the app's executable, or where
+/// `ORT_DYLIB_PATH` says.
+fn library(runtime_path: Option<&str>) -> Result<PathBuf, String> {
+    if let Some(path) = runtime_path.filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(path)).filter(|p| p.is_file()).ok_or_else(|| format!("no ONNX Runtime at {path}"));
+    }
+    let name = if cfg!(windows) {
+        "onnxruntime.dll"
+    } else if cfg!(target_os = "macos") {
+        "libonnxruntime.dylib"
+    } else {
+        "libonnxruntime.so"
+    };
+    let bundled = env::current_exe().ok().and_then(|exe| exe.parent().map(|dir| dir.join(name)));
+    bundled
+        .into_iter()
+        .chain(env::var_os("ORT_DYLIB_PATH").map(PathBuf::from))
+        .find(|path| path.is_file())
+        .ok_or_else(|| "ONNX Runtime not found; download it to use local models".to_owned())
+}
+
+/// Loads ONNX Runtime from `runtime_path` (see [`library`]), unless it's
+/// loaded already; only the first one is used until the app restarts.
+pub fn init(runtime_path: Option<&str>) -> Result<(), String> {
+    // ort panics on a library it can't load, so it's looked for first
+    let library = library(runtime_path)?;
+    ort::init_from(library.to_string_lossy()).commit().map(|_| ()).map_err(|e| format!("ONNX Runtime: {e}"))
+}
+
+pub fn session(path: &Path) -> Result<Session, String> {
+    Session::builder()
+        .and_then(|builder| builder.commit_from_file(path))
+        .map_err(|e| format!("load {}: {e}", path.display()))
+}
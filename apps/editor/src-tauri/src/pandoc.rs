@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use crate::{
+    error::BackendError,
+    jobs::{self, JobKind},
+    process, send,
+    workers::Priority,
+    BackendEvent, EventChannel,
+};
+
+const DEFAULT_TIMEOUT_MS: u64 = 60_000;
+
+/// Options that only affect how the document is converted. Everything else
+/// is refused: pandoc can run filters, read arbitrary files into the output
+/// (`--include-in-header`, `--template`, `--defaults`...) and write files.
+const ALLOWED_FLAGS: &[&str] = &[
+    "-s", "--standalone", "--toc", "--table-of-contents", "-N",
+    "--number-sections", "--mathml", "--mathjax", "--katex", "--webtex",
+    "--embed-resources", "--no-highlight", "--reference-links", "--ascii",
+    "--preserve-tabs", "--strip-comments", "--section-divs", "--html-q-tags",
+    "--listings", "--incremental", "-i",
+];
+
+const ALLOWED_OPTIONS: &[&str] = &[
+    "--toc-depth", "--wrap", "--columns", "--shift-heading-level-by",
+    "--highlight-style", "--eol", "--markdown-headings", "--tab-stop",
+    "--top-level-division", "--id-prefix", "--metadata", "-M", "--variable",
+    "-V", "--title-prefix", "-T", "--slide-level", "--reference-location",
+    "--number-offset", "--dpi", "--track-changes",
+];
+
+/// Formats pandoc can only write to a file, never to stdout as text.
+const BINARY_FORMATS: &[&str] = &[
+    "docx", "odt", "epub", "epub2", "epub3", "pptx", "pdf", "fb2",
+];
+
+fn check_format(format: &str) -> Result<(), String> {
+    let valid = !format.is_empty()
+        && format.chars().all(|c| {
+            c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-')
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid format: {format:?}"))
+    }
+}
+
+fn base_format(format: &str) -> &str {
+    format.split(['+', '-']).next().unwrap_or(format)
+}
+
+/// Checks user arguments against the allowlist. Options taking a value are
+/// accepted both as `--opt=value` and `--opt value`.
+fn sanitize_args(args: &[String]) -> Result<Vec<String>, String> {
+    let mut result = Vec::with_capacity(args.len());
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let name = arg.split('=').next().unwrap_or(arg);
+        if ALLOWED_FLAGS.contains(&arg.as_str()) {
+            result.push(arg.clone());
+        } else if ALLOWED_OPTIONS.contains(&name) {
+            result.push(arg.clone());
+            if !arg.contains('=') {
+                let value = iter
+                    .next()
+                    .ok_or(format!("missing value for {arg}"))?;
+                result.push(value.clone());
+            }
+        } else {
+            return Err(format!("argument not allowed: {arg}"));
+        }
+    }
+    process::check_args(&result)?;
+    Ok(result)
+}
+
+/// Converts `input` with a user-installed pandoc. Text output is streamed
+/// over `channel` as `Chunk` events; binary formats (docx, epub...) need an
+/// `output_path` and are written there instead. Ends with `Done`.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn pandoc_convert(
+    input: String,
+    from: String,
+    to: String,
+    args: Vec<String>,
+    output_path: Option<String>,
+    timeout_ms: Option<u64>,
+    channel: EventChannel,
+) -> Result<(), BackendError> {
+    log::info!("pandoc_convert start: {from} -> {to}");
+    check_format(&from)?;
+    check_format(&to)?;
+    let program = process::find_program("pandoc")
+        .ok_or_else(|| BackendError::not_found("pandoc not found; is it installed?"))?;
+
+    let mut full_args = vec![
+        "--sandbox".to_owned(),
+        format!("--from={from}"),
+        format!("--to={to}"),
+    ];
+    full_args.extend(sanitize_args(&args)?);
+    match &output_path {
+        Some(path) => full_args.push(format!("--output={path}")),
+        None if BINARY_FORMATS.contains(&base_format(&to)) => {
+            return Err(BackendError::invalid(format!("{to} output requires an output path")));
+        }
+        None => {}
+    }
+    let timeout =
+        Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS));
+
+    let job = jobs::start_as(channel.job_id(), JobKind::Export, Priority::Interactive, output_path.unwrap_or(to));
+    // a chunk may end in the middle of a multibyte character, so the
+    // incomplete tail waits for the next one
+    let mut pending = Vec::<u8>::new();
+    let result = process::run_streamed(
+        &program, &full_args, input.into_bytes(), timeout,
+        |bytes| {
+            // failing kills pandoc
+            if job.is_cancelled() {
+                return Err(jobs::CANCELLED.to_owned());
+            }
+            pending.extend_from_slice(bytes);
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(s) => s.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(e) => return Err(format!("invalid utf-8 output: {e}")),
+            };
+            if valid > 0 {
+                let rest = pending.split_off(valid);
+                let text = String::from_utf8(std::mem::replace(&mut pending, rest))
+                    .map_err(|e| format!("invalid utf-8 output: {e}"))?;
+                send(&channel, BackendEvent::Chunk { text });
+            }
+            Ok(())
+        },
+    )
+    .await;
+    job.finish(&result);
+    result.map_err(|e| format!("pandoc_convert: {e}"))?;
+
+    if !pending.is_empty() {
+        return Err(BackendError::external("pandoc_convert: output ends with incomplete utf-8"));
+    }
+    send(&channel, BackendEvent::Done);
+    log::info!("pandoc_convert done");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&a| a.to_owned()).collect()
+    }
+
+    #[test]
+    fn only_allowed_arguments_pass() {
+        let allowed = args(&["-s", "--toc-depth=2", "--wrap", "none", "-M", "title=x"]);
+        assert_eq!(sanitize_args(&allowed).unwrap(), allowed);
+        assert!(sanitize_args(&args(&["--lua-filter=x.lua"])).is_err());
+        assert!(sanitize_args(&args(&["--template", "t.html"])).is_err());
+        assert!(sanitize_args(&args(&["--wrap"])).is_err());
+        assert!(sanitize_args(&args(&["-M", "a\nb"])).is_err());
+    }
+
+    #[test]
+    fn formats_keep_their_extensions_apart() {
+        assert!(check_format("markdown+smart-auto_identifiers").is_ok());
+        assert!(check_format("").is_err());
+        assert!(check_format("html --lua-filter").is_err());
+        assert_eq!(base_format("markdown+smart-auto_identifiers"), "markdown");
+        assert_eq!(base_format("docx"), "docx");
+    }
+}
@@ -0,0 +1,299 @@
+//! What to insert for a paste, decided from every flavor on the clipboard:
+//! files become image embeds or links, a lone image becomes an asset, a
+//! URL becomes a link with the page's title, tab or comma separated rows
+//! become a table, and code copied from an editor becomes a code block.
+//! Anything else is pasted as it is.
+
+use std::{path::Path, sync::LazyLock};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tauri::Url;
+
+use crate::{inline, link_preview, workspace};
+
+static ANCHOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?is)<a\s[^>]*href=["']([^"']+)["'][^>]*>(.*?)</a>"#).expect("anchor regex"));
+static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)<[^>]*>").expect("tag regex"));
+/// How editors mark copied code: a `<pre>`, a monospace font in HTML, or
+/// one in an RTF font table.
+static MONOSPACE_HTML: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)<pre[\s>]|font-family:[^;>]*(monospace|menlo|consolas|courier|monaco|jetbrains|fira code)")
+        .expect("monospace html regex")
+});
+static MONOSPACE_RTF: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\\fmodern|\\f\d+\\fnil[^;]*(menlo|consolas|courier|monaco)").expect("monospace rtf regex")
+});
+
+/// Rows need this many columns at least to be a table.
+const MIN_COLUMNS: usize = 2;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Clipboard {
+    /// `text/plain`.
+    text: Option<String>,
+    /// `text/html`.
+    html: Option<String>,
+    /// `text/rtf`.
+    rtf: Option<String>,
+    /// Paths of copied files.
+    files: Vec<String>,
+    /// The MIME type of a copied image, like `image/png`.
+    image_type: Option<String>,
+    /// Where it's pasted, for relative paths.
+    document: Option<String>,
+    /// Fetches the title of a pasted URL.
+    fetch_title: bool,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PasteKind {
+    /// Save the clipboard image as an asset and embed it.
+    Image,
+    Files,
+    Link,
+    Table,
+    Code,
+    /// Paste the text as it is.
+    Text,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasteSuggestion {
+    kind: PasteKind,
+    /// What to insert; `None` for an image, which has to be saved first,
+    /// and for plain text.
+    markdown: Option<String>,
+    /// Of a code block, if it could be guessed.
+    language: Option<String>,
+}
+
+/// `path` as a link target from the folder of `document`.
+fn link_target(path: &Path, document: Option<&Path>) -> String {
+    let relative = document.and_then(Path::parent).and_then(|folder| workspace::relative_url_path(folder, path));
+    match relative {
+        Some(relative) => relative.replace(' ', "%20"),
+        None => Url::from_file_path(path).map_or_else(|()| path.to_string_lossy().into_owned(), String::from),
+    }
+}
+
+fn files(files: &[String], document: Option<&Path>) -> String {
+    let lines: Vec<String> = files
+        .iter()
+        .map(|file| {
+            let path = Path::new(file);
+            let name = path.file_name().map_or_else(|| file.clone(), |n| n.to_string_lossy().into_owned());
+            let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            let is_image = inline::mime_for_extension(&extension).is_some_and(|mime| mime.starts_with("image/"));
+            let bang = if is_image { "!" } else { "" };
+            format!("{bang}[{}]({})", name.replace(['[', ']'], ""), link_target(path, document))
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn html_text(html: &str) -> String {
+    link_preview::unescape_html(&TAG.replace_all(html, "")).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Splits a CSV line, with `"quoted, fields"`.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().expect("a field").push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().expect("a field").push(c),
+        }
+    }
+    fields
+}
+
+/// A markdown table if every line of `text` has as many cells, tab or
+/// comma separated, the first row being the header.
+fn table(text: &str) -> Option<String> {
+    let lines: Vec<&str> = text.trim_end_matches(['\n', '\r']).lines().collect();
+    if lines.len() < 2 {
+        return None;
+    }
+    let tabbed = lines.iter().all(|l| l.contains('\t'));
+    let rows: Vec<Vec<String>> = if tabbed {
+        lines.iter().map(|l| l.split('\t').map(str::to_owned).collect()).collect()
+    } else {
+        lines.iter().map(|l| csv_fields(l)).collect()
+    };
+    let columns = rows[0].len();
+    if columns < MIN_COLUMNS || rows.iter().any(|r| r.len() != columns) {
+        return None;
+    }
+    // prose with commas isn't a table: cells are short, and commas in
+    // prose are followed by spaces
+    let long_cells = || rows.iter().flatten().any(|c| c.split_whitespace().count() > 8);
+    if !tabbed && (lines.iter().all(|l| l.contains(", ")) || long_cells()) {
+        return None;
+    }
+    let cell = |c: &String| c.trim().replace('|', "\\|");
+    let row = |r: &Vec<String>| format!("| {} |", r.iter().map(cell).collect::<Vec<_>>().join(" | "));
+    let mut out = vec![row(&rows[0]), format!("|{}", " --- |".repeat(columns))];
+    out.extend(rows[1..].iter().map(row));
+    Some(out.join("\n"))
+}
+
+/// A guess from the look of the code.
+fn language(code: &str) -> Option<&'static str> {
+    let trimmed = code.trim_start();
+    let has = |s: &str| code.contains(s);
+    Some(if trimmed.starts_with("#!") && code.lines().next().is_some_and(|l| l.contains("sh")) {
+        "sh"
+    } else if trimmed.starts_with(['{', '[']) && serde_json::from_str::<serde_json::Value>(code).is_ok() {
+        "json"
+    } else if trimmed.starts_with('<') {
+        "html"
+    } else if has("fn ") && (has("let ") || has("->") || has("::")) {
+        "rust"
+    } else if has("def ") && has(":\n") || trimmed.starts_with("import ") && !has(";") {
+        "python"
+    } else if has("func ") && has("package ") {
+        "go"
+    } else if has("=>") || has("const ") || has("function ") || has("console.") {
+        if has(": string") || has("interface ") { "typescript" } else { "javascript" }
+    } else if trimmed.to_uppercase().starts_with("SELECT ") || trimmed.to_uppercase().starts_with("CREATE TABLE") {
+        "sql"
+    } else if has("#include") {
+        "c"
+    } else if trimmed.starts_with("$ ") {
+        "sh"
+    } else {
+        return None;
+    })
+}
+
+/// A fence longer than any run of backticks in `code`.
+fn code_block(code: &str, language: Option<&str>) -> String {
+    let longest = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{fence}{}\n{}\n{fence}", language.unwrap_or_default(), code.trim_end_matches(['\n', '\r']))
+}
+
+fn is_url(text: &str) -> bool {
+    !text.contains(char::is_whitespace)
+        && Url::parse(text).is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some())
+}
+
+async fn classify(clipboard: Clipboard) -> PasteSuggestion {
+    let suggest = |kind, markdown: Option<String>, language: Option<&str>| PasteSuggestion {
+        kind,
+        markdown,
+        language: language.map(str::to_owned),
+    };
+    let document = clipboard.document.as_deref().map(Path::new);
+    if !clipboard.files.is_empty() {
+        return suggest(PasteKind::Files, Some(files(&clipboard.files, document)), None);
+    }
+    let text = clipboard.text.as_deref().unwrap_or_default();
+    let html = clipboard.html.as_deref().unwrap_or_default();
+    // a copied image often comes with an <img> tag, but not with text
+    if clipboard.image_type.as_deref().is_some_and(|t| t.starts_with("image/")) && text.trim().is_empty() {
+        return suggest(PasteKind::Image, None, None);
+    }
+    let url = text.trim();
+    if is_url(url) {
+        // copied links carry their text in the HTML
+        let anchor = ANCHOR
+            .captures(html)
+            .filter(|c| c[1] == *url)
+            .map(|c| html_text(&c[2]))
+            .filter(|t| !t.is_empty() && t != url);
+        let title = match anchor {
+            Some(title) => Some(title),
+            None if clipboard.fetch_title => link_preview::fetch_title(url.to_owned()).await.ok().flatten(),
+            None => None,
+        };
+        let markdown = match title {
+            Some(title) => format!("[{}]({url})", title.replace(['[', ']'], "")),
+            None => format!("<{url}>"),
+        };
+        return suggest(PasteKind::Link, Some(markdown), None);
+    }
+    if let Some(table) = table(text) {
+        return suggest(PasteKind::Table, Some(table), None);
+    }
+    let from_editor =
+        MONOSPACE_HTML.is_match(html) || clipboard.rtf.as_deref().is_some_and(|r| MONOSPACE_RTF.is_match(r));
+    if from_editor && !text.trim().is_empty() {
+        let language = language(text);
+        return suggest(PasteKind::Code, Some(code_block(text, language)), language);
+    }
+    suggest(PasteKind::Text, None, None)
+}
+
+/// The recommended way to insert what's on `clipboard`, given every
+/// flavor the paste event has.
+#[tauri::command]
+pub async fn classify_paste(clipboard: Clipboard) -> PasteSuggestion {
+    classify(clipboard).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_become_tables_but_prose_does_not() {
+        assert_eq!(table("a\tb\n1\t2|3\n").as_deref(), Some("| a | b |\n| --- | --- |\n| 1 | 2\\|3 |"));
+        assert_eq!(
+            table("name,note\n\"Doe, J\",\"said \"\"hi\"\"\"").as_deref(),
+            Some("| name | note |\n| --- | --- |\n| Doe, J | said \"hi\" |"),
+        );
+        assert_eq!(table("one, two\nthree, four"), None);
+        assert_eq!(table("a,b\nc"), None);
+        assert_eq!(table("a,b"), None);
+    }
+
+    #[test]
+    fn code_gets_a_fence_and_a_language() {
+        assert_eq!(language("fn main() -> u8 { 1 }"), Some("rust"));
+        assert_eq!(language("{\"a\": 1}"), Some("json"));
+        assert_eq!(language("const x: string = 'a';"), Some("typescript"));
+        assert_eq!(language("SELECT * FROM t"), Some("sql"));
+        assert_eq!(language("just words"), None);
+        assert_eq!(code_block("a ``` b\n", Some("md")), "````md\na ``` b\n````");
+    }
+
+    #[test]
+    fn files_and_links_are_suggested() {
+        let document = Some("/notes/day.md".to_owned());
+        let clipboard = Clipboard {
+            files: vec!["/notes/img/a b.png".to_owned(), "/elsewhere/[x].pdf".to_owned()],
+            document: document.clone(),
+            ..Clipboard::default()
+        };
+        let suggestion = tauri::async_runtime::block_on(classify(clipboard));
+        assert_eq!(suggestion.kind, PasteKind::Files);
+        assert_eq!(
+            suggestion.markdown.as_deref(),
+            Some("![a b.png](img/a%20b.png)\n[x.pdf](file:///elsewhere/[x].pdf)"),
+        );
+
+        let link = |text: &str, html: &str| {
+            let clipboard =
+                Clipboard { text: Some(text.to_owned()), html: Some(html.to_owned()), ..Clipboard::default() };
+            tauri::async_runtime::block_on(classify(clipboard))
+        };
+        let suggestion = link("https://example.com/a", "<a href=\"https://example.com/a\">The <b>page</b></a>");
+        assert_eq!(suggestion.markdown.as_deref(), Some("[The page](https://example.com/a)"));
+        assert_eq!(link("https://example.com", "").markdown.as_deref(), Some("<https://example.com>"));
+        let suggestion = link("let x = 1;", "<pre>let x = 1;</pre>");
+        assert_eq!(suggestion.kind, PasteKind::Code);
+        assert_eq!(link("ftp://example.com", "").kind, PasteKind::Text);
+    }
+}
@@ -0,0 +1,285 @@
+//! User-provided WebAssembly plugins, registered in the settings, that
+//! transform text, handle pastes or export documents.
+//!
+//! A plugin is a module without imports, so it can't touch anything but
+//! its input. It exports
+//! - `memory`;
+//! - `alloc(len: i32) -> i32`, returning where `len` bytes of input may be
+//!   written;
+//! - the function of its hook, `transform`, `paste` or `export`, taking
+//!   the pointer and length of the UTF-8 input and returning those of the
+//!   UTF-8 output packed into an `i64`, as `ptr << 32 | len`.
+//!
+//! What the input is depends on the hook: the selected text or document
+//! for `transform`, the pasted text for `paste`, and the document's
+//! Markdown for `export`, which returns the exported file's content.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::{
+    error::BackendError,
+    settings,
+    workers::{self, Priority},
+};
+
+/// How much a plugin may compute per run, roughly a few seconds' worth.
+const FUEL: u64 = 5_000_000_000;
+/// How large a plugin's memory may grow.
+const MAX_MEMORY: usize = 256 << 20;
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    Engine::new(&config).expect("wasmtime config is valid")
+});
+
+/// Compiled modules by path, with the modification time they were
+/// compiled at.
+static MODULES: LazyLock<Mutex<HashMap<PathBuf, (SystemTime, Module)>>> = LazyLock::new(Mutex::default);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum PluginHook {
+    Transform,
+    Paste,
+    Export,
+}
+
+impl PluginHook {
+    fn export_name(self) -> &'static str {
+        match self {
+            PluginHook::Transform => "transform",
+            PluginHook::Paste => "paste",
+            PluginHook::Export => "export",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Plugin {
+    name: String,
+    /// Of the `.wasm` file.
+    path: String,
+    hook: PluginHook,
+}
+
+impl Plugin {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("a plugin needs a name".to_owned());
+        }
+        if self.path.is_empty() {
+            return Err(format!("plugin {} has no path", self.name));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginInfo {
+    #[serde(flatten)]
+    plugin: Plugin,
+    /// Why the plugin can't be run, if it can't.
+    error: Option<String>,
+}
+
+/// The module at `plugin.path`, compiled again only when the file changed.
+fn module(plugin: &Plugin) -> Result<Module, String> {
+    let path = PathBuf::from(&plugin.path);
+    let modified = fs::metadata(&path)
+        .and_then(|m| m.modified())
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    if let Some((compiled, module)) = MODULES.lock().expect("plugins lock poisoned").get(&path) {
+        if *compiled == modified {
+            return Ok(module.clone());
+        }
+    }
+    log::info!("plugins: compiling {}", path.display());
+    let module = Module::from_file(&ENGINE, &path).map_err(|e| format!("{}: {e:#}", path.display()))?;
+    if let Some(import) = module.imports().next() {
+        let import = format!("{}::{}", import.module(), import.name());
+        return Err(format!("{} imports {import}, plugins can't import anything", plugin.name));
+    }
+    MODULES.lock().expect("plugins lock poisoned").insert(path, (modified, module.clone()));
+    Ok(module)
+}
+
+fn find(name: &str) -> Result<Plugin, String> {
+    settings::get()
+        .plugins
+        .into_iter()
+        .find(|plugin| plugin.name == name)
+        .ok_or_else(|| format!("no plugin named {name}"))
+}
+
+fn instantiate(module: &Module) -> Result<(Store<StoreLimits>, Instance), String> {
+    let mut store = Store::new(&ENGINE, StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build());
+    store.limiter(|limits| limits);
+    store.set_fuel(FUEL).map_err(|e| format!("set_fuel: {e:#}"))?;
+    let instance = Instance::new(&mut store, module, &[]).map_err(|e| format!("instantiate: {e:#}"))?;
+    Ok((store, instance))
+}
+
+fn run(plugin: &Plugin, input: &str) -> Result<String, String> {
+    let (mut store, instance) = instantiate(&module(plugin)?)?;
+    let memory = instance.get_memory(&mut store, "memory").ok_or("no memory exported".to_owned())?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&mut store, "alloc")
+        .map_err(|e| format!("alloc: {e:#}"))?;
+    let hook = plugin.hook.export_name();
+    let function = instance
+        .get_typed_func::<(i32, i32), i64>(&mut store, hook)
+        .map_err(|e| format!("{hook}: {e:#}"))?;
+
+    let len = i32::try_from(input.len()).map_err(|_| "input too large".to_owned())?;
+    let ptr = alloc.call(&mut store, len).map_err(|e| format!("alloc: {e:#}"))?;
+    let offset = usize::try_from(ptr).map_err(|_| format!("alloc returned {ptr}"))?;
+    memory.write(&mut store, offset, input.as_bytes()).map_err(|e| format!("write input: {e}"))?;
+
+    let packed = function.call(&mut store, (ptr, len)).map_err(|e| format!("{hook}: {e:#}"))?;
+    let packed = u64::from_ne_bytes(packed.to_ne_bytes());
+    let (offset, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+    let output = memory
+        .data(&store)
+        .get(offset..offset.saturating_add(len))
+        .ok_or_else(|| format!("{hook} returned output outside its memory"))?;
+    String::from_utf8(output.to_vec()).map_err(|_| format!("{hook} returned invalid UTF-8"))
+}
+
+/// Checks that `plugin` compiles and exports what its hook needs.
+fn check(plugin: &Plugin) -> Result<(), String> {
+    let module = module(plugin)?;
+    let exports: Vec<&str> = module.exports().map(|export| export.name()).collect();
+    for name in ["memory", "alloc", plugin.hook.export_name()] {
+        if !exports.contains(&name) {
+            return Err(format!("doesn't export {name}"));
+        }
+    }
+    Ok(())
+}
+
+/// The plugins in the settings, checked.
+#[tauri::command]
+pub async fn list_plugins() -> Result<Vec<PluginInfo>, BackendError> {
+    let result = workers::run(Priority::Interactive, || {
+        settings::get()
+            .plugins
+            .into_iter()
+            .map(|plugin| PluginInfo { error: check(&plugin).err(), plugin })
+            .collect()
+    })
+    .await;
+    match result {
+        Ok(plugins) => Ok(plugins),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+/// Runs the plugin `name` from the settings on `input` and returns its
+/// output; what both are depends on the plugin's hook.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn run_plugin(name: String, input: String) -> Result<String, BackendError> {
+    log::info!("run_plugin start: {name}");
+    let result = workers::run(Priority::Interactive, move || find(&name).and_then(|plugin| run(&plugin, &input))).await;
+    match result {
+        Ok(Ok(output)) => {
+            log::info!("run_plugin done");
+            Ok(output)
+        }
+        Ok(Err(e)) => Err(format!("run_plugin task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A plugin for `hook` compiled from the WAT `module`, in a file of its
+    /// own, since modules are cached by path.
+    fn plugin(name: &str, hook: PluginHook, module: &str) -> Plugin {
+        let dir = std::env::temp_dir().join(format!("emmm-plugins-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("a plugins folder");
+        let path = dir.join(format!("{name}.wasm"));
+        fs::write(&path, wat::parse_str(module).expect("valid WAT")).expect("a plugin");
+        Plugin { name: name.to_owned(), path: path.to_string_lossy().into_owned(), hook }
+    }
+
+    /// A module with a page of memory, `alloc` and `transform`, whose body
+    /// gets the input's pointer and length as locals 0 and 1.
+    fn transform(body: &str) -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 16) "refused")
+                (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "transform") (param i32 i32) (result i64) {body}))"#)
+    }
+
+    #[test]
+    fn output_is_packed_as_pointer_and_length() {
+        // the input without its first byte
+        let skip = plugin("skip", PluginHook::Transform, &transform(
+            "(i64.or
+                (i64.shl (i64.extend_i32_u (i32.add (local.get 0) (i32.const 1))) (i64.const 32))
+                (i64.extend_i32_u (i32.sub (local.get 1) (i32.const 1))))"));
+        assert_eq!(run(&skip, "hello"), Ok("ello".to_owned()));
+
+        let outside = plugin("outside", PluginHook::Transform, &transform("(i64.const 0x10000_00000004)"));
+        assert_eq!(run(&outside, "hello"), Err("transform returned output outside its memory".to_owned()));
+    }
+
+    #[test]
+    fn modules_with_imports_are_refused() {
+        let importing = plugin("importing", PluginHook::Paste, r#"(module
+            (import "env" "open" (func))
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "paste") (param i32 i32) (result i64) (i64.const 0)))"#);
+        let expected = "importing imports env::open, plugins can't import anything".to_owned();
+        assert_eq!(check(&importing), Err(expected.clone()));
+        assert_eq!(run(&importing, "text"), Err(expected));
+
+        let exporting = plugin("exporting", PluginHook::Export, &transform("(i64.const 0)"));
+        assert_eq!(check(&exporting), Err("doesn't export export".to_owned()));
+    }
+
+    #[test]
+    fn plugins_stop_when_out_of_fuel() {
+        let looping = plugin("looping", PluginHook::Transform, &transform("(loop (br 0)) (i64.const 0)"));
+        let error = run(&looping, "text").expect_err("the loop is stopped");
+        assert!(error.contains("fuel"), "{error}");
+    }
+
+    #[test]
+    fn memory_grows_only_up_to_the_limit() {
+        // 7 bytes of "refused" if growing by the limit fails, 0 if not
+        let growing = plugin("growing", PluginHook::Transform, &transform(&format!(
+            "(if (result i64) (i32.eq (memory.grow (i32.const {})) (i32.const -1))
+                (then (i64.const 0x10_00000007)) (else (i64.const 0)))",
+            MAX_MEMORY >> 16)));
+        assert_eq!(run(&growing, ""), Ok("refused".to_owned()));
+
+        let large = plugin("large", PluginHook::Transform,
+            &transform("(i64.const 0)").replacen("(memory (export \"memory\") 1)",
+                &format!("(memory (export \"memory\") {})", (MAX_MEMORY >> 16) + 1), 1));
+        let error = run(&large, "").expect_err("instantiating fails");
+        assert!(error.starts_with("instantiate:"), "{error}");
+    }
+}
@@ -0,0 +1,165 @@
+//! Whether the machine runs on battery and whether the user is at the app,
+//! so that work that can wait does: background tasks of [`crate::workers`],
+//! like bulk recompression and reindexing, are held back while
+//! [`throttled`], and go on once it isn't anymore, like when the charger's
+//! plugged back in.
+//!
+//! Idle means no input in the app, as the frontend reports it with
+//! [`report_activity`]: typing in another app doesn't count.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::workers;
+
+/// How often the power source is looked at.
+const CHECK: Duration = Duration::from_secs(15);
+
+static OPTIONS: RwLock<Option<PowerOptions>> = RwLock::new(None);
+static ON_BATTERY: LazyLock<AtomicBool> = LazyLock::new(|| AtomicBool::new(read_on_battery()));
+static LAST_ACTIVITY: LazyLock<Mutex<Instant>> = LazyLock::new(|| Mutex::new(Instant::now()));
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PowerOptions {
+    /// Holds background work back on battery.
+    defer_on_battery: bool,
+    /// Holds background work back until [`idle`].
+    defer_while_active: bool,
+    /// Seconds without input in the app after which the user is idle, for
+    /// background work and scheduled tasks alike.
+    idle_seconds: u64,
+}
+
+impl Default for PowerOptions {
+    fn default() -> PowerOptions {
+        PowerOptions { defer_on_battery: true, defer_while_active: false, idle_seconds: 60 }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PowerStatus {
+    on_battery: bool,
+    /// Since the last input in the app.
+    idle_seconds: u64,
+    /// Whether background work is held back.
+    throttled: bool,
+}
+
+/// `false` on mains power, and when it can't be told, like on desktops.
+fn read_on_battery() -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        let Ok(supplies) = std::fs::read_dir("/sys/class/power_supply") else { return false };
+        let read = |path: &std::path::Path, name| std::fs::read_to_string(path.join(name)).unwrap_or_default();
+        let mut discharging = false;
+        for supply in supplies.filter_map(Result::ok).map(|e| e.path()) {
+            match read(&supply, "type").trim() {
+                "Mains" if read(&supply, "online").trim() == "1" => return false,
+                "Battery" => discharging |= read(&supply, "status").trim() == "Discharging",
+                _ => {}
+            }
+        }
+        discharging
+    }
+    #[cfg(target_os = "macos")]
+    {
+        std::process::Command::new("pmset")
+            .args(["-g", "batt"])
+            .output()
+            .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains("'Battery Power'"))
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+        // SAFETY: plain data, all zeroes is valid
+        let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+        // SAFETY: `status` is valid for writes; 0 is offline, 1 online, 255 unknown
+        unsafe { GetSystemPowerStatus(&raw mut status) != 0 && status.ACLineStatus == 0 }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        false
+    }
+}
+
+/// As of the last check, at most [`CHECK`] ago.
+pub fn on_battery() -> bool {
+    ON_BATTERY.load(Ordering::Relaxed)
+}
+
+fn options() -> PowerOptions {
+    OPTIONS.read().expect("power lock poisoned").clone().unwrap_or_default()
+}
+
+/// How long ago the last input in the app was.
+pub fn idle_for() -> Duration {
+    LAST_ACTIVITY.lock().expect("power lock poisoned").elapsed()
+}
+
+/// Whether there was no input in the app for `idle_seconds`.
+pub fn idle() -> bool {
+    idle_for() >= Duration::from_secs(options().idle_seconds)
+}
+
+/// Whether background work should wait.
+pub fn throttled() -> bool {
+    let options = options();
+    (options.defer_on_battery && on_battery()) || (options.defer_while_active && !idle())
+}
+
+/// Checks the power source from now on, letting held back work go when
+/// it's no longer [`throttled`].
+pub fn start() {
+    tauri::async_runtime::spawn(async {
+        let mut ticks = tokio::time::interval(CHECK);
+        let mut was_throttled = throttled();
+        loop {
+            ticks.tick().await;
+            match tokio::task::spawn_blocking(read_on_battery).await {
+                Ok(battery) => {
+                    if ON_BATTERY.swap(battery, Ordering::Relaxed) != battery {
+                        log::info!("power: on {}", if battery { "battery" } else { "mains" });
+                    }
+                }
+                Err(e) => log::warn!("power: tokio::task::spawn_blocking: {e}"),
+            }
+            let now_throttled = throttled();
+            if was_throttled && !now_throttled {
+                log::info!("power: resuming background work");
+                workers::wake();
+            }
+            was_throttled = now_throttled;
+        }
+    });
+}
+
+#[tauri::command]
+pub fn configure_power(options: PowerOptions) {
+    log::info!("configure_power: on battery {}, while active {}", options.defer_on_battery, options.defer_while_active);
+    *OPTIONS.write().expect("power lock poisoned") = Some(options);
+    // work may go now
+    workers::wake();
+}
+
+/// Tells that there was input in the app, so background work and
+/// scheduled tasks wait. The frontend calls it on input, at most every
+/// few seconds.
+#[tauri::command]
+pub fn report_activity() {
+    *LAST_ACTIVITY.lock().expect("power lock poisoned") = Instant::now();
+}
+
+#[tauri::command]
+pub fn power_status() -> PowerStatus {
+    PowerStatus { on_battery: on_battery(), idle_seconds: idle_for().as_secs(), throttled: throttled() }
+}
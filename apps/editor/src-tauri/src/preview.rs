@@ -0,0 +1,491 @@
+//! A local web server showing the workspace as [`site::export_site`] would
+//! publish it, to preview documents in a real browser or on a phone over
+//! the LAN. Pages are rendered again whenever a document has changed, and
+//! open pages reload themselves through a WebSocket at `/.live`. Requests
+//! naming another host are refused, so a web page can't read the workspace
+//! through a domain that it rebinds to this address.
+
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::broadcast::{self, error::RecvError},
+    task::JoinSet,
+};
+
+use crate::{
+    error::BackendError,
+    inline,
+    site::{self, RenderedSite, SiteOptions},
+    workspace,
+};
+
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often documents are checked for changes while pages are open.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+const LIVE_PATH: &str = ".live";
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const LIVE_SCRIPT: &str = "<script>
+(function connect() {
+    const scheme = location.protocol == 'https:' ? 'wss://' : 'ws://';
+    const socket = new WebSocket(scheme + location.host + '/.live');
+    socket.onmessage = () => location.reload();
+    socket.onclose = () => setTimeout(connect, 2000);
+})();
+</script>
+";
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PreviewOptions {
+    /// Any free port if not given.
+    port: Option<u16>,
+    /// Listen on every interface, not just localhost.
+    lan: bool,
+    /// As for [`site::export_site`].
+    theme: String,
+    site: SiteOptions,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewServer {
+    url: String,
+    /// The address for other devices, when listening on the LAN.
+    lan_url: Option<String>,
+}
+
+/// Modification time and size of every document.
+type Fingerprint = Vec<(PathBuf, Option<SystemTime>, u64)>;
+
+struct Preview {
+    root: PathBuf,
+    options: PreviewOptions,
+    cache: Mutex<Option<(Fingerprint, Arc<RenderedSite>)>>,
+    /// The path of each changed document, relative to `root`, if known.
+    changes: broadcast::Sender<Option<String>>,
+    /// The `Host` headers requests may have, like `localhost:8080`.
+    hosts: Vec<String>,
+}
+
+struct Running {
+    preview: Arc<Preview>,
+    tasks: Vec<tauri::async_runtime::JoinHandle<()>>,
+}
+
+static SERVER: Mutex<Option<Running>> = Mutex::new(None);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LiveMessage {
+    event: &'static str,
+    path: Option<String>,
+}
+
+impl Preview {
+    fn fingerprint(&self) -> Result<Fingerprint, String> {
+        Ok(workspace::documents(&self.root)?
+            .into_iter()
+            .map(|path| {
+                let meta = fs::metadata(&path).ok();
+                let modified = meta.as_ref().and_then(|m| m.modified().ok());
+                let size = meta.map(|m| m.len()).unwrap_or_default();
+                (path, modified, size)
+            })
+            .collect())
+    }
+
+    /// The rendered site, rendering it again if a document has changed.
+    fn site(&self) -> Result<Arc<RenderedSite>, String> {
+        let fingerprint = self.fingerprint()?;
+        let mut cache = self.cache.lock().expect("preview cache lock poisoned");
+        if let Some((cached, site)) = &*cache {
+            if *cached == fingerprint {
+                return Ok(site.clone());
+            }
+        }
+        log::info!("preview: rendering {}", self.root.display());
+        let site = Arc::new(site::render_site(&self.root, &self.options.theme, &self.options.site)?);
+        *cache = Some((fingerprint, site.clone()));
+        Ok(site)
+    }
+
+    /// The content type and contents at `path` in the site.
+    fn get(&self, path: &str) -> Result<Option<(&'static str, Vec<u8>)>, String> {
+        let site = self.site()?;
+        let content_type = content_type(path);
+        if let Some(data) = site.files.get(path) {
+            let mut data = data.clone();
+            if content_type.starts_with("text/html") {
+                let end = String::from_utf8_lossy(&data).rfind("</body>").unwrap_or(data.len());
+                data.splice(end..end, LIVE_SCRIPT.bytes());
+            }
+            return Ok(Some((content_type, data)));
+        }
+        match site.assets.get(path) {
+            Some(source) => match fs::read(source) {
+                Ok(data) => Ok(Some((content_type, data))),
+                Err(e) => {
+                    log::warn!("preview: {}: {e}", source.display());
+                    Ok(None)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+fn content_type(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "html" => "text/html; charset=utf-8",
+        "xml" => "application/atom+xml",
+        "css" => "text/css; charset=utf-8",
+        other => inline::mime_for_extension(other).unwrap_or("application/octet-stream"),
+    }
+}
+
+/// The path in the site that a request target points to; `None` if it
+/// tries to leave it.
+fn site_path(target: &str) -> Option<String> {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    let mut path = path.trim_start_matches('/').to_owned();
+    if path.split('/').any(|part| part == "..") {
+        return None;
+    }
+    if path.is_empty() || path.ends_with('/') {
+        path.push_str("index.html");
+    }
+    Some(path)
+}
+
+async fn respond(
+    stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8], head_only: bool,
+) -> std::io::Result<()> {
+    let header = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        body.len());
+    stream.write_all(header.as_bytes()).await?;
+    if !head_only {
+        stream.write_all(body).await?;
+    }
+    stream.shutdown().await
+}
+
+/// Reads up to the end of the request headers; `None` if the client went
+/// away or sent too much.
+async fn read_head(stream: &mut TcpStream) -> std::io::Result<Option<String>> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 4096];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_SIZE {
+            return Ok(None);
+        }
+        let n = stream.read(&mut buffer).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buffer[..n]);
+    }
+    Ok(Some(String::from_utf8_lossy(&head).into_owned()))
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+/// An unmasked WebSocket text frame.
+fn text_frame(text: &str) -> Vec<u8> {
+    let length = text.len();
+    let mut frame = vec![0x81];
+    match u16::try_from(length) {
+        Ok(short) if short < 126 => frame.push(short.to_le_bytes()[0]),
+        Ok(short) => {
+            frame.push(126);
+            frame.extend_from_slice(&short.to_be_bytes());
+        }
+        Err(_) => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(text.as_bytes());
+    frame
+}
+
+/// Upgrades the connection to a WebSocket and sends a message for every
+/// change until the page goes away.
+async fn live(preview: &Preview, stream: &mut TcpStream, key: &str) -> std::io::Result<()> {
+    let mut hash = Sha1::new();
+    hash.update(key.as_bytes());
+    hash.update(WEBSOCKET_GUID.as_bytes());
+    let accept = STANDARD.encode(hash.finalize());
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n");
+    stream.write_all(response.as_bytes()).await?;
+
+    let mut changes = preview.changes.subscribe();
+    let mut buffer = [0; 1024];
+    loop {
+        tokio::select! {
+            // nothing the page sends matters, but it has to be read to
+            // notice it closing
+            read = stream.read(&mut buffer) => {
+                let n = read?;
+                if n == 0 || buffer[0] & 0x0f == 0x8 {
+                    return Ok(());
+                }
+            }
+            change = changes.recv() => {
+                let path = match change {
+                    Ok(path) => path,
+                    Err(RecvError::Lagged(_)) => None,
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                let message = serde_json::to_string(&LiveMessage { event: "changed", path })
+                    .expect("message serializes");
+                stream.write_all(&text_frame(&message)).await?;
+            }
+        }
+    }
+}
+
+async fn handle(preview: Arc<Preview>, mut stream: TcpStream) -> std::io::Result<()> {
+    let Ok(head) = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream)).await else {
+        return Ok(());
+    };
+    let Some(head) = head? else {
+        return Ok(());
+    };
+    let host = header(&head, "host").unwrap_or_default();
+    if !preview.hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)) {
+        return respond(&mut stream, "403 Forbidden", "text/plain", b"forbidden", false).await;
+    }
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let target = request_line.next().unwrap_or_default();
+    let head_only = method == "HEAD";
+    if method != "GET" && !head_only {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"", false).await;
+    }
+    let Some(path) = site_path(target) else {
+        return respond(&mut stream, "404 Not Found", "text/plain", b"not found", head_only).await;
+    };
+    if path == LIVE_PATH {
+        let upgrade = header(&head, "upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket"));
+        return match header(&head, "sec-websocket-key") {
+            Some(key) if upgrade => live(&preview, &mut stream, key).await,
+            _ => respond(&mut stream, "426 Upgrade Required", "text/plain", b"", head_only).await,
+        };
+    }
+
+    let result = tokio::task::spawn_blocking(move || preview.get(&path)).await;
+    match result {
+        Ok(Ok(Some((content_type, data)))) =>
+            respond(&mut stream, "200 OK", content_type, &data, head_only).await,
+        Ok(Ok(None)) =>
+            respond(&mut stream, "404 Not Found", "text/plain", b"not found", head_only).await,
+        Ok(Err(e)) => {
+            log::warn!("preview: {e}");
+            respond(&mut stream, "500 Internal Server Error", "text/plain; charset=utf-8",
+                e.as_bytes(), head_only).await
+        }
+        Err(e) => {
+            let message = format!("tokio::task::spawn_blocking: {e}");
+            respond(&mut stream, "500 Internal Server Error", "text/plain; charset=utf-8",
+                message.as_bytes(), head_only).await
+        }
+    }
+}
+
+async fn serve(listener: TcpListener, preview: Arc<Preview>) {
+    // dropped with this task when the server stops, which closes the
+    // connections still open, like those of live pages
+    let mut connections = JoinSet::new();
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                while connections.try_join_next().is_some() {}
+                let preview = preview.clone();
+                connections.spawn(async move {
+                    if let Err(e) = handle(preview, stream).await {
+                        log::warn!("preview: {e}");
+                    }
+                });
+            }
+            Err(e) => {
+                log::warn!("preview: accept: {e}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+/// The first document that differs between two fingerprints.
+fn changed_document(old: &Fingerprint, new: &Fingerprint) -> Option<PathBuf> {
+    let differs = |a: &Fingerprint, b: &Fingerprint| {
+        a.iter().find(|entry| !b.contains(entry)).map(|(path, _, _)| path.clone())
+    };
+    differs(new, old).or_else(|| differs(old, new))
+}
+
+/// Tells open pages about documents changing on disk, e.g. from autosaves
+/// or another editor.
+async fn watch(preview: Arc<Preview>) {
+    let mut last: Option<Fingerprint> = None;
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+        if preview.changes.receiver_count() == 0 {
+            last = None;
+            continue;
+        }
+        let current = preview.clone();
+        let Ok(Ok(fingerprint)) = tokio::task::spawn_blocking(move || current.fingerprint()).await
+        else {
+            continue;
+        };
+        if let Some(last) = &last {
+            if let Some(path) = changed_document(last, &fingerprint) {
+                let relative = workspace::relative_url_path(&preview.root, &path);
+                let _ = preview.changes.send(relative);
+            }
+        }
+        last = Some(fingerprint);
+    }
+}
+
+/// The address other devices on the network can reach us at.
+fn lan_address() -> Option<IpAddr> {
+    // connecting a UDP socket sends nothing, but picks the interface that
+    // routes outside
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 80)).ok()?;
+    socket.local_addr().ok().map(|a| a.ip()).filter(|ip| !ip.is_loopback())
+}
+
+/// Starts serving `workspace`, replacing the server that's already running.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn start_preview_server(
+    workspace: String, options: PreviewOptions,
+) -> Result<PreviewServer, BackendError> {
+    log::info!("start_preview_server start: {workspace}");
+    stop_preview_server();
+    let host = if options.lan { Ipv4Addr::UNSPECIFIED } else { Ipv4Addr::LOCALHOST };
+    let listener = TcpListener::bind(SocketAddr::from((host, options.port.unwrap_or(0))))
+        .await
+        .map_err(|e| format!("bind: {e}"))?;
+    let port = listener.local_addr().map_err(|e| format!("local_addr: {e}"))?.port();
+    let lan = options.lan.then(lan_address).flatten().map(|ip| SocketAddr::new(ip, port));
+    let server = PreviewServer {
+        url: format!("http://localhost:{port}/"),
+        lan_url: lan.map(|address| format!("http://{address}/")),
+    };
+    let mut hosts = vec![format!("localhost:{port}"), format!("127.0.0.1:{port}")];
+    hosts.extend(lan.map(|address| address.to_string()));
+
+    let preview = Arc::new(Preview {
+        root: PathBuf::from(workspace),
+        options,
+        cache: Mutex::default(),
+        changes: broadcast::channel(16).0,
+        hosts,
+    });
+    let tasks = vec![
+        tauri::async_runtime::spawn(serve(listener, preview.clone())),
+        tauri::async_runtime::spawn(watch(preview.clone())),
+    ];
+    *SERVER.lock().expect("preview server lock poisoned") = Some(Running { preview, tasks });
+    log::info!("start_preview_server done: {}", server.url);
+    Ok(server)
+}
+
+/// How many pages listen for changes to the workspace being served; `None`
+/// if there is no server, so nothing is watched.
+pub fn listening_pages() -> Option<usize> {
+    let server = SERVER.lock().expect("preview server lock poisoned");
+    server.as_ref().map(|running| running.preview.changes.receiver_count())
+}
+
+/// Stops listening and closes the connections still open.
+#[tauri::command]
+pub fn stop_preview_server() {
+    if let Some(running) = SERVER.lock().expect("preview server lock poisoned").take() {
+        for task in running.tasks {
+            task.abort();
+        }
+        log::info!("stop_preview_server: stopped");
+    }
+}
+
+/// Reloads the open preview pages right away, without waiting for the
+/// change to be noticed; `path` is the document that changed.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn notify_preview_changed(path: Option<String>) {
+    if let Some(running) = &*SERVER.lock().expect("preview server lock poisoned") {
+        let relative = path.and_then(|p| workspace::relative_url_path(&running.preview.root, Path::new(&p)));
+        let _ = running.preview.changes.send(relative);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stopping_closes_live_pages() {
+        let root = std::env::temp_dir().join(format!("emmm-preview-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        tauri::async_runtime::block_on(async {
+            let server = start_preview_server(root.to_string_lossy().into_owned(), PreviewOptions::default())
+                .await
+                .unwrap();
+            let address = server.url.trim_start_matches("http://").trim_end_matches('/').to_owned();
+            let port = address.trim_start_matches("localhost:");
+            let request = |host: &str| format!(
+                "GET /.live HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n");
+            let mut buffer = [0; 1024];
+            // like a page on a domain rebound to this address
+            for host in [format!("attacker.example:{port}"), "localhost".to_owned()] {
+                let mut stream = TcpStream::connect(&address).await.unwrap();
+                stream.write_all(request(&host).as_bytes()).await.unwrap();
+                let n = stream.read(&mut buffer).await.unwrap();
+                assert!(buffer[..n].starts_with(b"HTTP/1.1 403"), "{host}");
+            }
+
+            let mut stream = TcpStream::connect(&address).await.unwrap();
+            stream.write_all(request(&format!("127.0.0.1:{port}")).as_bytes()).await.unwrap();
+            let n = stream.read(&mut buffer).await.unwrap();
+            assert!(buffer[..n].starts_with(b"HTTP/1.1 101"));
+
+            stop_preview_server();
+            let closed = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buffer)).await;
+            assert!(matches!(closed, Ok(Ok(0) | Err(_))));
+        });
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,441 @@
+//! Exports documents as PDF files without the print dialog, at the paper
+//! size, orientation and margins asked for. Where the webview can write a
+//! PDF itself, WebView2's `PrintToPdf` on Windows and WKWebView's
+//! `createPDF` on macOS, it's rendered in a hidden window; elsewhere, and
+//! if that fails, by a Chromium-based browser run headlessly.
+
+#[cfg(any(target_os = "macos", windows))]
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use tauri::{AppHandle, Url};
+#[cfg(any(target_os = "macos", windows))]
+use tauri::{
+    webview::{PageLoadEvent, PlatformWebview},
+    WebviewUrl, WebviewWindowBuilder,
+};
+#[cfg(any(target_os = "macos", windows))]
+use tokio::sync::oneshot;
+
+use crate::{
+    error::BackendError,
+    process,
+    webhooks::{self, WebhookEvent},
+};
+
+/// Of the hidden windows documents are printed from.
+pub const PREFIX: &str = "print-";
+const DEFAULT_TIMEOUT_MS: u64 = 60_000;
+/// Named paper sizes, as `@page` knows them, in millimeters portrait.
+const KNOWN: &[(&str, f64, f64)] = &[
+    ("A3", 297.0, 420.0),
+    ("A4", 210.0, 297.0),
+    ("A5", 148.0, 210.0),
+    ("B4", 250.0, 353.0),
+    ("B5", 176.0, 250.0),
+    ("letter", 215.9, 279.4),
+    ("legal", 215.9, 355.6),
+    ("ledger", 279.4, 431.8),
+];
+/// Of a custom paper size's sides, in millimeters.
+const MIN_PAPER_MM: f64 = 25.0;
+const MAX_PAPER_MM: f64 = 2000.0;
+/// What the margins must leave of the page each way, in millimeters.
+const MIN_CONTENT_MM: f64 = 10.0;
+#[cfg(any(target_os = "macos", windows))]
+const MM_PER_INCH: f64 = 25.4;
+
+/// Chromium-based browsers all support headless printing with the same
+/// flags. Edge is preinstalled on Windows, so there's usually one around.
+const BROWSERS: &[&str] = &[
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+    "microsoft-edge",
+    "msedge",
+    "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
+    "/Applications/Microsoft Edge.app/Contents/MacOS/Microsoft Edge",
+    "/Applications/Chromium.app/Contents/MacOS/Chromium",
+    r"C:\Program Files (x86)\Microsoft\Edge\Application\msedge.exe",
+    r"C:\Program Files\Microsoft\Edge\Application\msedge.exe",
+    r"C:\Program Files\Google\Chrome\Application\chrome.exe",
+];
+
+#[cfg(any(target_os = "macos", windows))]
+static NEXT_WINDOW: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone, Copy, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Orientation {
+    #[default]
+    Portrait,
+    Landscape,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase", untagged)]
+pub enum Paper {
+    Named(String),
+    #[serde(rename_all = "camelCase")]
+    Custom { width_mm: f64, height_mm: f64 },
+}
+
+impl Default for Paper {
+    fn default() -> Self {
+        Paper::Named("A4".to_owned())
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Margins {
+    top: f64,
+    right: f64,
+    bottom: f64,
+    left: f64,
+}
+
+impl Default for Margins {
+    fn default() -> Self {
+        Margins { top: 20.0, right: 20.0, bottom: 20.0, left: 20.0 }
+    }
+}
+
+/// Margins are in millimeters.
+#[derive(Clone, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PrintOptions {
+    paper: Paper,
+    orientation: Orientation,
+    margins: Margins,
+    timeout_ms: Option<u64>,
+}
+
+/// The page [`page_style`] describes, in millimeters, turned to its
+/// orientation, for the hidden window's size and WebView2's print
+/// settings; headless browsers go by the style.
+#[derive(Clone, Copy)]
+#[cfg_attr(not(windows), allow(dead_code))]
+struct Page {
+    width: f64,
+    height: f64,
+    margins: Margins,
+}
+
+/// The paper's `@page` name, if it has one, and its orientation and sides
+/// in millimeters, turned to it.
+fn paper(options: &PrintOptions) -> Result<(Option<&'static str>, &'static str, f64, f64), String> {
+    let (name, width, height) = match &options.paper {
+        Paper::Named(name) => {
+            let &(name, width, height) = KNOWN
+                .iter()
+                .find(|(known, ..)| known.eq_ignore_ascii_case(name))
+                .ok_or(format!("unknown paper size: {name}"))?;
+            (Some(name), width, height)
+        }
+        &Paper::Custom { width_mm, height_mm } => {
+            for side in [width_mm, height_mm] {
+                if !(MIN_PAPER_MM..=MAX_PAPER_MM).contains(&side) {
+                    return Err(format!(
+                        "paper sides must be {MIN_PAPER_MM}mm to {MAX_PAPER_MM}mm, not {side}mm"));
+                }
+            }
+            (None, width_mm, height_mm)
+        }
+    };
+    Ok(match options.orientation {
+        Orientation::Portrait => (name, "portrait", width, height),
+        Orientation::Landscape => (name, "landscape", height, width),
+    })
+}
+
+/// The `@page` rule for `options`, which fails for paper sizes and
+/// margins that don't make a page.
+fn page_style(options: &PrintOptions) -> Result<String, String> {
+    let (name, orientation, width, height) = paper(options)?;
+    let size = match name {
+        Some(name) => format!("{name} {orientation}"),
+        None => format!("{width}mm {height}mm"),
+    };
+
+    let Margins { top, right, bottom, left } = options.margins;
+    if [top, right, bottom, left].iter().any(|m| !m.is_finite() || *m < 0.0) {
+        return Err("margins must be zero or more millimeters".to_owned());
+    }
+    if width - left - right < MIN_CONTENT_MM || height - top - bottom < MIN_CONTENT_MM {
+        return Err(format!("margins leave less than {MIN_CONTENT_MM}mm of the {width}mm by {height}mm page"));
+    }
+    Ok(format!(
+        "<style>@page {{ size: {size}; margin: {top}mm {right}mm {bottom}mm {left}mm; }}</style>"))
+}
+
+/// Puts our `@page` rule last in `<head>` so it wins over the document's.
+fn inject_style(html: &str, style: &str) -> String {
+    match html.find("</head>") {
+        Some(i) => format!("{}{style}{}", &html[..i], &html[i..]),
+        None => format!("{style}{html}"),
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    env::temp_dir().join(format!("emmm-{}-{nanos}-{name}", std::process::id()))
+}
+
+#[cfg(any(target_os = "macos", windows))]
+type Reply = oneshot::Sender<Result<(), String>>;
+
+/// Where the webview reports how writing the PDF went, once.
+#[cfg(any(target_os = "macos", windows))]
+#[derive(Clone)]
+struct Done(Arc<Mutex<Option<Reply>>>);
+
+#[cfg(any(target_os = "macos", windows))]
+impl Done {
+    fn send(&self, result: Result<(), String>) {
+        if let Some(sender) = self.0.lock().expect("print lock poisoned").take() {
+            let _ = sender.send(result);
+        }
+    }
+}
+
+/// Writes the page to `output` with WKWebView's `createPDF`, which lays it
+/// out at the width of the window. Called on the main thread.
+#[cfg(target_os = "macos")]
+fn create_pdf(webview: &PlatformWebview, _page: Page, output: &Path, done: Done) -> Result<(), String> {
+    use block2::RcBlock;
+    use objc2::MainThreadMarker;
+    use objc2_foundation::{NSData, NSError};
+    use objc2_web_kit::{WKPDFConfiguration, WKWebView};
+
+    let mtm = MainThreadMarker::new().ok_or("createPDF: not on the main thread")?;
+    let output = output.to_owned();
+    let handler = RcBlock::new(move |data: *mut NSData, error: *mut NSError| {
+        // SAFETY: WebKit passes the data, or else the error, valid for the call.
+        let result = match unsafe { (data.as_ref(), error.as_ref()) } {
+            (Some(data), _) => {
+                fs::write(&output, data.to_vec()).map_err(|e| format!("write {}: {e}", output.display()))
+            }
+            (None, Some(error)) => Err(format!("createPDF: {}", error.localizedDescription())),
+            (None, None) => Err("createPDF: no PDF".to_owned()),
+        };
+        done.send(result);
+    });
+    // SAFETY: tauri's WKWebView, alive while the window is, used on the
+    // main thread; a configuration with no rect takes the whole page.
+    unsafe {
+        let webview: &WKWebView = &*webview.inner().cast();
+        let configuration = WKPDFConfiguration::new(mtm);
+        webview.createPDFWithConfiguration_completionHandler(Some(&configuration), &handler);
+    }
+    Ok(())
+}
+
+/// Writes the page to `output` with WebView2's `PrintToPdf`, which pages
+/// it like printing does. Called on the main thread.
+#[cfg(windows)]
+fn create_pdf(webview: &PlatformWebview, page: Page, output: &Path, done: Done) -> Result<(), String> {
+    use webview2_com::{
+        Microsoft::Web::WebView2::Win32::{
+            ICoreWebView2Environment6, ICoreWebView2_7, COREWEBVIEW2_PRINT_ORIENTATION_PORTRAIT,
+        },
+        PrintToPdfCompletedHandler,
+    };
+    use windows::core::{Interface, HSTRING};
+
+    let inches = |mm: f64| mm / MM_PER_INCH;
+    let Margins { top, right, bottom, left } = page.margins;
+    // SAFETY: tauri's WebView2, alive while the window is, used on the
+    // main thread.
+    unsafe {
+        let core: ICoreWebView2_7 = webview
+            .controller()
+            .CoreWebView2()
+            .and_then(|core| core.cast())
+            .map_err(|e| format!("this WebView2 can't print to PDF: {e}"))?;
+        let environment: ICoreWebView2Environment6 =
+            webview.environment().cast().map_err(|e| format!("this WebView2 can't print to PDF: {e}"))?;
+        let settings = environment.CreatePrintSettings().map_err(|e| format!("print settings: {e}"))?;
+        // the page is turned already
+        settings.SetOrientation(COREWEBVIEW2_PRINT_ORIENTATION_PORTRAIT)
+            .and_then(|()| settings.SetPageWidth(inches(page.width)))
+            .and_then(|()| settings.SetPageHeight(inches(page.height)))
+            .and_then(|()| settings.SetMarginTop(inches(top)))
+            .and_then(|()| settings.SetMarginRight(inches(right)))
+            .and_then(|()| settings.SetMarginBottom(inches(bottom)))
+            .and_then(|()| settings.SetMarginLeft(inches(left)))
+            .and_then(|()| settings.SetShouldPrintBackgrounds(true))
+            .and_then(|()| settings.SetShouldPrintHeaderAndFooter(false))
+            .map_err(|e| format!("print settings: {e}"))?;
+        let handler = PrintToPdfCompletedHandler::create(Box::new(move |result, written| {
+            done.send(match result {
+                Ok(()) if written => Ok(()),
+                Ok(()) => Err("PrintToPdf: nothing was written".to_owned()),
+                Err(e) => Err(format!("PrintToPdf: {e}")),
+            });
+            Ok(())
+        }));
+        core.PrintToPdf(&HSTRING::from(output), &settings, &handler).map_err(|e| format!("PrintToPdf: {e}"))
+    }
+}
+
+/// Renders `url` in a hidden window, a page wide, and has its webview
+/// write the PDF.
+#[cfg(any(target_os = "macos", windows))]
+async fn print_webview(app: &AppHandle, url: Url, page: Page, output: &Path, timeout: Duration) -> Result<(), String> {
+    let label = format!("{PREFIX}{}", NEXT_WINDOW.fetch_add(1, Ordering::Relaxed));
+    let (loaded, on_loaded) = oneshot::channel();
+    let loaded = Mutex::new(Some(loaded));
+    let window = WebviewWindowBuilder::new(app, &label, WebviewUrl::External(url))
+        .visible(false)
+        .inner_size(page.width / MM_PER_INCH * 96.0, page.height / MM_PER_INCH * 96.0)
+        .on_page_load(move |_, payload| {
+            if matches!(payload.event(), PageLoadEvent::Finished) {
+                if let Some(loaded) = loaded.lock().expect("print lock poisoned").take() {
+                    let _ = loaded.send(());
+                }
+            }
+        })
+        .build()
+        .map_err(|e| format!("create window {label}: {e}"))?;
+    let printed = async {
+        on_loaded.await.map_err(|_| "the page didn't load".to_owned())?;
+        let (sender, on_done) = oneshot::channel();
+        let done = Done(Arc::new(Mutex::new(Some(sender))));
+        let output = output.to_owned();
+        window
+            .with_webview(move |webview| {
+                if let Err(e) = create_pdf(&webview, page, &output, done.clone()) {
+                    done.send(Err(e));
+                }
+            })
+            .map_err(|e| format!("with_webview: {e}"))?;
+        on_done.await.map_err(|_| "the webview didn't finish".to_owned())?
+    };
+    let result = tokio::time::timeout(timeout, printed)
+        .await
+        .unwrap_or_else(|_| Err(format!("timed out after {}s", timeout.as_secs())));
+    let _ = window.destroy();
+    result
+}
+
+/// Runs a Chromium-based browser headlessly to print `url` to `output`.
+async fn print_headless(url: &Url, output: &Path, timeout: Duration) -> Result<(), String> {
+    let browser = BROWSERS
+        .iter()
+        .find_map(|b| process::find_program(b))
+        .ok_or("no Chromium-based browser found for printing".to_owned())?;
+    let profile = temp_path("profile");
+    let args = vec![
+        "--headless".to_owned(),
+        "--disable-gpu".to_owned(),
+        "--no-first-run".to_owned(),
+        "--no-pdf-header-footer".to_owned(),
+        "--print-to-pdf-no-header".to_owned(),
+        format!("--user-data-dir={}", profile.display()),
+        format!("--print-to-pdf={}", output.display()),
+        url.to_string(),
+    ];
+    let result = process::run_piped(&browser, &args, Vec::new(), timeout).await;
+    let _ = fs::remove_dir_all(&profile);
+    result.map(|_| ())
+}
+
+/// Prints an HTML document to a PDF file at `output_path`, without the
+/// print dialog; see the module docs for how.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn print_to_pdf(
+    html: String, options: PrintOptions, output_path: String, app: AppHandle,
+) -> Result<(), BackendError> {
+    log::info!("print_to_pdf start");
+    let style = page_style(&options).map_err(BackendError::invalid)?;
+    let (_, _, width, height) = paper(&options).map_err(BackendError::invalid)?;
+    let page = Page { width, height, margins: options.margins };
+    let timeout = Duration::from_millis(options.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS));
+    let output = Path::new(&output_path);
+    let input = temp_path("print.html");
+    fs::write(&input, inject_style(&html, &style)).map_err(|e| BackendError::io("write print page", &e))?;
+    let url = Url::from_file_path(&input).map_err(|()| format!("print_to_pdf: invalid temp path {}", input.display()))?;
+
+    #[cfg(any(target_os = "macos", windows))]
+    let result = match print_webview(&app, url.clone(), page, output, timeout).await {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            log::warn!("print_to_pdf: the webview couldn't, trying a browser: {e}");
+            print_headless(&url, output, timeout).await.map_err(|headless| format!("{e}; {headless}"))
+        }
+    };
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let result = {
+        let _ = (app, page);
+        print_headless(&url, output, timeout).await
+    };
+    let _ = fs::remove_file(&input);
+    result.map_err(|e| format!("print_to_pdf: {e}"))?;
+
+    if !output.is_file() {
+        return Err(BackendError::external("print_to_pdf: no PDF was written"));
+    }
+    log::info!("print_to_pdf done");
+    webhooks::emit(WebhookEvent::Export, &output_path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(paper: Paper, orientation: Orientation, margins: Margins) -> PrintOptions {
+        PrintOptions { paper, orientation, margins, timeout_ms: None }
+    }
+
+    #[test]
+    fn page_style_names_known_sizes() {
+        let style = page_style(&options(Paper::Named("a4".to_owned()), Orientation::Landscape, Margins::default()));
+        assert_eq!(style.unwrap(), "<style>@page { size: A4 landscape; margin: 20mm 20mm 20mm 20mm; }</style>");
+        assert!(page_style(&options(Paper::Named("A0".to_owned()), Orientation::Portrait, Margins::default()))
+            .is_err());
+    }
+
+    #[test]
+    fn page_style_turns_custom_sizes() {
+        let paper = Paper::Custom { width_mm: 100.0, height_mm: 150.0 };
+        let margins = Margins { top: 0.0, right: 5.0, bottom: 0.0, left: 5.0 };
+        let style = page_style(&options(paper, Orientation::Landscape, margins)).unwrap();
+        assert!(style.contains("size: 150mm 100mm;"), "{style}");
+    }
+
+    #[test]
+    fn page_style_checks_the_page() {
+        let bad_paper = [f64::NAN, f64::INFINITY, -10.0, 0.0, 5000.0];
+        for side in bad_paper {
+            let paper = Paper::Custom { width_mm: side, height_mm: 100.0 };
+            assert!(page_style(&options(paper, Orientation::Portrait, Margins::default())).is_err(), "{side}");
+        }
+        let bad_margins = [
+            Margins { top: -1.0, ..Margins::default() },
+            Margins { left: f64::NAN, ..Margins::default() },
+            // 210mm wide, less 101mm each side
+            Margins { left: 101.0, right: 101.0, ..Margins::default() },
+        ];
+        for margins in bad_margins {
+            assert!(page_style(&options(Paper::default(), Orientation::Portrait, margins)).is_err());
+        }
+        // but fine on its side, 297mm wide
+        let margins = Margins { left: 101.0, right: 101.0, ..Margins::default() };
+        assert!(page_style(&options(Paper::default(), Orientation::Landscape, margins)).is_ok());
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Command,
+};
+
+/// Directories where package managers commonly put binaries, but which a GUI
+/// app does not necessarily inherit in its `PATH` (notably on macOS).
+const EXTRA_DIRS: &[&str] = &[
+    "/usr/local/bin",
+    "/opt/homebrew/bin",
+    "/opt/local/bin",
+    "/usr/bin",
+];
+
+/// Resolves an executable name to a full path; paths are returned as-is if
+/// they exist.
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let direct = Path::new(name);
+    if direct.components().count() > 1 {
+        return direct.is_file().then(|| direct.to_owned());
+    }
+
+    let mut dirs: Vec<PathBuf> = env::var_os("PATH")
+        .map(|p| env::split_paths(&p).collect())
+        .unwrap_or_default();
+    dirs.extend(EXTRA_DIRS.iter().map(PathBuf::from));
+    if let Some(home) = env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        dirs.push(home.join(".cargo/bin"));
+        dirs.push(home.join(".local/bin"));
+    }
+
+    let candidates: &[String] = if cfg!(windows) {
+        &[format!("{name}.exe"), format!("{name}.cmd"), name.to_owned()]
+    } else {
+        &[name.to_owned()]
+    };
+    dirs.iter()
+        .flat_map(|dir| candidates.iter().map(move |c| dir.join(c)))
+        .find(|p| p.is_file())
+}
+
+/// Arguments are passed straight to the program and never through a shell,
+/// but we still refuse the ones that can only be a mistake or an attack.
+pub fn check_args(args: &[String]) -> Result<(), String> {
+    for arg in args {
+        if arg.contains(['\0', '\n', '\r']) {
+            return Err(format!("invalid argument: {arg:?}"));
+        }
+    }
+    Ok(())
+}
+
+/// Runs `program` with `input` on stdin and returns its stdout. The child is
+/// killed if it is still running after `timeout`.
+pub async fn run_piped(
+    program: &Path, args: &[String], input: Vec<u8>, timeout: Duration
+) -> Result<Vec<u8>, String> {
+    check_args(args)?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("spawn {}: {e}", program.display()))?;
+
+    // write from another task, otherwise a child that fills its stdout pipe
+    // before reading all of stdin would deadlock with us
+    let mut stdin = child.stdin.take().ok_or("no stdin".to_owned())?;
+    let writer = tokio::spawn(async move {
+        let result = stdin.write_all(&input).await;
+        drop(stdin);
+        result
+    });
+
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| format!("{} timed out after {timeout:?}", program.display()))?
+        .map_err(|e| format!("wait_with_output: {e}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "{} exited with {}: {}",
+            program.display(), output.status, stderr.trim()));
+    }
+    writer
+        .await
+        .map_err(|e| format!("tokio::spawn: {e}"))?
+        .map_err(|e| format!("write stdin: {e}"))?;
+    Ok(output.stdout)
+}
+
+/// Like [`run_piped`], but hands stdout to `on_output` chunk by chunk as it
+/// arrives instead of collecting it.
+pub async fn run_streamed(
+    program: &Path,
+    args: &[String],
+    input: Vec<u8>,
+    timeout: Duration,
+    mut on_output: impl FnMut(&[u8]) -> Result<(), String> + Send,
+) -> Result<(), String> {
+    check_args(args)?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("spawn {}: {e}", program.display()))?;
+
+    let mut stdin = child.stdin.take().ok_or("no stdin".to_owned())?;
+    let mut stdout = child.stdout.take().ok_or("no stdout".to_owned())?;
+    let mut stderr = child.stderr.take().ok_or("no stderr".to_owned())?;
+    let writer = tokio::spawn(async move {
+        let result = stdin.write_all(&input).await;
+        drop(stdin);
+        result
+    });
+    let stderr_reader = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        stderr.read_to_end(&mut buf).await.map(|_| buf)
+    });
+
+    let run = async {
+        let mut buf = vec![0u8; 8192];
+        loop {
+            let n = stdout
+                .read(&mut buf)
+                .await
+                .map_err(|e| format!("read stdout: {e}"))?;
+            if n == 0 {
+                break;
+            }
+            on_output(&buf[..n])?;
+        }
+        child.wait().await.map_err(|e| format!("wait: {e}"))
+    };
+    let status = tokio::time::timeout(timeout, run)
+        .await
+        .map_err(|_| format!("{} timed out after {timeout:?}", program.display()))??;
+
+    if !status.success() {
+        let stderr = stderr_reader
+            .await
+            .ok()
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        return Err(format!(
+            "{} exited with {}: {}",
+            program.display(), status, String::from_utf8_lossy(&stderr).trim()));
+    }
+    writer
+        .await
+        .map_err(|e| format!("tokio::spawn: {e}"))?
+        .map_err(|e| format!("write stdin: {e}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_with_line_breaks_are_refused() {
+        assert!(check_args(&["--to=html".to_owned(), "a b".to_owned()]).is_ok());
+        for bad in ["a\nb", "a\rb", "a\0b"] {
+            assert!(check_args(&[bad.to_owned()]).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn paths_to_programs_are_kept() {
+        let exe = std::env::current_exe().unwrap();
+        assert_eq!(find_program(&exe.to_string_lossy()), Some(exe.clone()));
+        assert_eq!(find_program(&exe.with_extension("missing").to_string_lossy()), None);
+        assert_eq!(find_program("emmm-no-such-program"), None);
+    }
+}
@@ -0,0 +1,266 @@
+//! Style suggestions for English prose that don't need a server: passive
+//! voice, weasel words, very long sentences and repeated words. These are
+//! heuristics, meant to be underlined rather than fixed automatically.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::BackendError,
+    metrics, spellcheck,
+    workers::{self, Priority},
+};
+
+const DEFAULT_MAX_SENTENCE_WORDS: usize = 35;
+
+const BE_VERBS: &[&str] = &["am", "is", "are", "was", "were", "be", "been", "being"];
+
+/// Past participles that don't end in -ed.
+const IRREGULAR_PARTICIPLES: &[&str] = &[
+    "beaten", "begun", "bitten", "blown", "born", "bought", "bound", "broken", "brought", "built",
+    "caught", "chosen", "done", "drawn", "driven", "eaten", "fallen", "felt", "forbidden",
+    "forgiven", "forgotten", "found", "frozen", "given", "gone", "gotten", "grown", "heard",
+    "held", "hidden", "hit", "hung", "hurt", "kept", "known", "laid", "led", "left", "lent",
+    "lost", "made", "meant", "met", "paid", "read", "ridden", "rung", "said", "seen", "sent",
+    "set", "shaken", "shown", "shut", "sold", "sought", "spent", "spoken", "spun", "stolen",
+    "struck", "sung", "sunk", "sworn", "taken", "taught", "thought", "thrown", "told", "torn",
+    "understood", "woken", "won", "worn", "written",
+];
+
+const WEASEL_WORDS: &[&str] = &[
+    "actually", "arguably", "basically", "clearly", "completely", "exceedingly", "excellent",
+    "extremely", "fairly", "few", "huge", "interestingly", "largely", "literally", "many",
+    "mostly", "notably", "obviously", "quite", "really", "relatively", "remarkably", "several",
+    "significantly", "simply", "somewhat", "substantially", "surprisingly", "tiny", "totally",
+    "truly", "vast", "various", "very",
+];
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ProseRule {
+    PassiveVoice,
+    WeaselWord,
+    LongSentence,
+    DuplicateWord,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProseLintOptions {
+    disabled_rules: Vec<ProseRule>,
+    /// Sentences with more words are flagged; 35 by default.
+    max_sentence_words: Option<usize>,
+    /// Flagged along with the built-in ones.
+    extra_weasel_words: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProseDiagnostic {
+    /// In UTF-16 code units from the start of the text, like JavaScript
+    /// string indices.
+    offset: usize,
+    length: usize,
+    rule: ProseRule,
+    message: String,
+    /// Replacing the range with one of these fixes it.
+    replacements: Vec<String>,
+}
+
+/// A diagnostic in byte offsets.
+struct Finding {
+    start: usize,
+    end: usize,
+    rule: ProseRule,
+    message: String,
+    replacements: Vec<String>,
+}
+
+fn is_participle(word: &str) -> bool {
+    (word.len() > 4 && word.ends_with("ed")) || IRREGULAR_PARTICIPLES.contains(&word)
+}
+
+/// Whether only white space within a paragraph separates the words at `a`
+/// and `b`.
+fn adjacent(text: &str, a: (usize, &str), b: (usize, &str)) -> bool {
+    let gap = &text[a.0 + a.1.len()..b.0];
+    gap.chars().all(char::is_whitespace) && gap.matches('\n').count() < 2
+}
+
+fn passive_voice(text: &str, words: &[(usize, &str)], out: &mut Vec<Finding>) {
+    for (i, &be) in words.iter().enumerate() {
+        if !BE_VERBS.contains(&be.1.to_lowercase().as_str()) {
+            continue;
+        }
+        // "was quickly written"
+        let mut next = i + 1;
+        if words.get(next).is_some_and(|w| w.1.len() > 3 && w.1.ends_with("ly")) {
+            next += 1;
+        }
+        let Some(&participle) = words.get(next) else { continue };
+        let chain = words[i..=next].windows(2).all(|pair| adjacent(text, pair[0], pair[1]));
+        if chain && is_participle(&participle.1.to_lowercase()) {
+            out.push(Finding {
+                start: be.0,
+                end: participle.0 + participle.1.len(),
+                rule: ProseRule::PassiveVoice,
+                message: "Passive voice; consider saying who does it".to_owned(),
+                replacements: Vec::new(),
+            });
+        }
+    }
+}
+
+fn weasel_words(words: &[(usize, &str)], extra: &[String], out: &mut Vec<Finding>) {
+    let extra: HashSet<String> = extra.iter().map(|w| w.to_lowercase()).collect();
+    for &(start, word) in words {
+        let lower = word.to_lowercase();
+        if WEASEL_WORDS.contains(&lower.as_str()) || extra.contains(&lower) {
+            out.push(Finding {
+                start,
+                end: start + word.len(),
+                rule: ProseRule::WeaselWord,
+                message: format!("\u{201c}{word}\u{201d} weakens the sentence; be specific or leave it out"),
+                replacements: Vec::new(),
+            });
+        }
+    }
+}
+
+fn duplicate_words(text: &str, words: &[(usize, &str)], out: &mut Vec<Finding>) {
+    for pair in words.windows(2) {
+        let (first, second) = (pair[0], pair[1]);
+        if first.1.to_lowercase() == second.1.to_lowercase() && adjacent(text, first, second) {
+            out.push(Finding {
+                start: first.0,
+                end: second.0 + second.1.len(),
+                rule: ProseRule::DuplicateWord,
+                message: format!("\u{201c}{}\u{201d} is repeated", second.1),
+                replacements: vec![first.1.to_owned()],
+            });
+        }
+    }
+}
+
+/// Byte ranges of the sentences in `text`: they end after `.`, `!` or `?`
+/// followed by a space, and at blank lines.
+pub(crate) fn sentences(text: &str) -> Vec<(usize, usize)> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, n)| n);
+        let end = match c {
+            '.' | '!' | '?' if next.is_none_or(char::is_whitespace) => Some(i + 1),
+            '。' | '！' | '？' => Some(i + c.len_utf8()),
+            '\n' if next == Some('\n') => Some(i),
+            _ => None,
+        };
+        if let Some(end) = end {
+            result.push((start, end));
+            start = end;
+        }
+    }
+    result.push((start, text.len()));
+    result
+        .into_iter()
+        .map(|(start, end)| {
+            let sentence = &text[start..end];
+            let trimmed = sentence.trim_start();
+            (start + sentence.len() - trimmed.len(), start + sentence.trim_end().len())
+        })
+        .filter(|(start, end)| start < end)
+        .collect()
+}
+
+fn long_sentences(text: &str, words: &[(usize, &str)], max: usize, out: &mut Vec<Finding>) {
+    // both are in order, so each word is looked at once
+    let mut next = 0;
+    for (start, end) in sentences(text) {
+        while words.get(next).is_some_and(|&(at, _)| at < start) {
+            next += 1;
+        }
+        let first = next;
+        while words.get(next).is_some_and(|&(at, _)| at < end) {
+            next += 1;
+        }
+        let count = next - first;
+        if count > max {
+            out.push(Finding {
+                start,
+                end,
+                rule: ProseRule::LongSentence,
+                message: format!("{count} words; consider splitting this sentence"),
+                replacements: Vec::new(),
+            });
+        }
+    }
+}
+
+fn lint(text: &str, options: &ProseLintOptions) -> Vec<ProseDiagnostic> {
+    let enabled = |rule| !options.disabled_rules.contains(&rule);
+    let words = spellcheck::words(text);
+    let mut findings = Vec::new();
+    if enabled(ProseRule::PassiveVoice) {
+        passive_voice(text, &words, &mut findings);
+    }
+    if enabled(ProseRule::WeaselWord) {
+        weasel_words(&words, &options.extra_weasel_words, &mut findings);
+    }
+    if enabled(ProseRule::DuplicateWord) {
+        duplicate_words(text, &words, &mut findings);
+    }
+    if enabled(ProseRule::LongSentence) {
+        let max = options.max_sentence_words.unwrap_or(DEFAULT_MAX_SENTENCE_WORDS);
+        long_sentences(text, &words, max, &mut findings);
+    }
+    findings.sort_by_key(|f| (f.start, f.end));
+
+    // UTF-16 offsets, counted in one pass since findings are sorted
+    let (mut done, mut offset) = (0, 0);
+    findings
+        .into_iter()
+        .map(|finding| {
+            offset += text[done..finding.start].encode_utf16().count();
+            done = finding.start;
+            ProseDiagnostic {
+                offset,
+                length: text[finding.start..finding.end].encode_utf16().count(),
+                rule: finding.rule,
+                message: finding.message,
+                replacements: finding.replacements,
+            }
+        })
+        .collect()
+}
+
+/// Style diagnostics for `text`, in order.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn lint_prose(text: String, options: ProseLintOptions) -> Result<Vec<ProseDiagnostic>, BackendError> {
+    let timer = metrics::timer("lint_prose").size(text.len() as u64);
+    let result = workers::run(Priority::Interactive, move || lint(&text, &options)).await;
+    timer.finish(result.is_ok());
+    result.map_err(|e| format!("workers::run: {e}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_sentences_count_their_own_words() {
+        let text = "One two three four. Five six.\n\nSeven eight nine ten eleven!";
+        let options = ProseLintOptions { max_sentence_words: Some(3), ..ProseLintOptions::default() };
+        let long: Vec<_> = lint(text, &options)
+            .into_iter()
+            .filter(|d| d.rule == ProseRule::LongSentence)
+            .map(|d| (d.offset, d.length, d.message))
+            .collect();
+        assert_eq!(long, [
+            (0, 19, "4 words; consider splitting this sentence".to_owned()),
+            (31, 28, "5 words; consider splitting this sentence".to_owned()),
+        ]);
+    }
+}
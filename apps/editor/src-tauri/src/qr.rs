@@ -0,0 +1,222 @@
+//! QR codes for links, to embed in documents and exports like handouts
+//! and slides, where a link can't be clicked; and the other way around,
+//! what the QR codes and barcodes in an image, like a pasted screenshot,
+//! say.
+
+use std::{fmt::Write, io::Cursor, path::Path};
+
+use image::{imageops::FilterType, GrayImage, ImageFormat, Luma};
+use qrcode::{Color, QrCode};
+use rxing::Exceptions;
+use serde::{Deserialize, Serialize};
+use tauri::{ipc::Response, Url};
+
+use crate::{
+    error::BackendError,
+    workers::{self, Priority},
+};
+
+const DEFAULT_SIZE: u32 = 256;
+const MAX_SIZE: u32 = 4096;
+/// Light modules around the code, which scanners need to find it.
+const QUIET_ZONE: usize = 4;
+/// Bigger images are scaled down to scan, which codes survive.
+const MAX_SCAN_EDGE: u32 = 3000;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScannedCode {
+    text: String,
+    /// Like `qrcode`, `ean 13` or `code 128`.
+    format: String,
+    /// Whether `text` is an http(s) link, to insert as one.
+    is_url: bool,
+    /// Where the code was found, in pixels of the image: its corners, or
+    /// for a barcode, the ends of the line it was read along.
+    points: Vec<[f32; 2]>,
+}
+
+/// The code's modules, quiet zone included, as rows of whether each is
+/// dark.
+fn modules(text: &str) -> Result<Vec<Vec<bool>>, String> {
+    let code = QrCode::new(text.as_bytes()).map_err(|e| format!("encode {} bytes: {e}", text.len()))?;
+    let width = code.width();
+    let colors = code.to_colors();
+    let edge = width + 2 * QUIET_ZONE;
+    Ok((0..edge)
+        .map(|y| {
+            (0..edge)
+                .map(|x| {
+                    let (Some(x), Some(y)) = (x.checked_sub(QUIET_ZONE), y.checked_sub(QUIET_ZONE)) else {
+                        return false;
+                    };
+                    x < width && y < width && colors[y * width + x] == Color::Dark
+                })
+                .collect()
+        })
+        .collect())
+}
+
+/// `size` pixels wide and high, or as much bigger as takes a pixel per
+/// module; modules are whole pixels, centered, so the code stays sharp.
+fn render_png(modules: &[Vec<bool>], size: u32) -> Result<Vec<u8>, String> {
+    let count = u32::try_from(modules.len()).map_err(|_| "code too big".to_owned())?;
+    let scale = (size / count).max(1);
+    let edge = size.max(count * scale);
+    let offset = (edge - count * scale) / 2;
+    let mut image = GrayImage::from_pixel(edge, edge, Luma([255]));
+    for (y, row) in (0..).zip(modules) {
+        for (x, _) in (0..).zip(row).filter(|(_, &dark)| dark) {
+            for py in 0..scale {
+                for px in 0..scale {
+                    image.put_pixel(offset + x * scale + px, offset + y * scale + py, Luma([0]));
+                }
+            }
+        }
+    }
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png).map_err(|e| format!("encode png: {e}"))?;
+    Ok(png)
+}
+
+/// Scales to `size` pixels, sharp at any size since it's in modules.
+fn render_svg(modules: &[Vec<bool>], size: u32) -> String {
+    let count = modules.len();
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 {count} {count}" "#
+    );
+    let _ = write!(svg, r##"shape-rendering="crispEdges"><rect width="{count}" height="{count}" fill="#fff"/>"##);
+    svg.push_str(r##"<path fill="#000" d=""##);
+    for (y, row) in modules.iter().enumerate() {
+        for (x, _) in row.iter().enumerate().filter(|(_, &dark)| dark) {
+            let _ = write!(svg, "M{x} {y}h1v1h-1z");
+        }
+    }
+    svg.push_str("\"/></svg>");
+    svg
+}
+
+/// A QR code of `text`, `size` pixels wide and high, as PNG or SVG data.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn generate_qr(text: String, size: Option<u32>, format: Option<QrFormat>) -> Result<Response, BackendError> {
+    log::info!("generate_qr start: {} bytes", text.len());
+    if text.is_empty() {
+        return Err(BackendError::invalid("generate_qr: nothing to encode"));
+    }
+    let size = size.unwrap_or(DEFAULT_SIZE).clamp(1, MAX_SIZE);
+    let modules = modules(&text).map_err(|e| format!("generate_qr: {e}"))?;
+    let data = match format.unwrap_or_default() {
+        QrFormat::Png => render_png(&modules, size).map_err(|e| format!("generate_qr: {e}"))?,
+        QrFormat::Svg => render_svg(&modules, size).into_bytes(),
+    };
+    log::info!("generate_qr done");
+    Ok(Response::new(data))
+}
+
+fn scan(path: &Path) -> Result<Vec<ScannedCode>, String> {
+    let mut image = image::open(path).map_err(|e| format!("open {}: {e}", path.display()))?;
+    let width = image.width();
+    if width.max(image.height()) > MAX_SCAN_EDGE {
+        image = image.resize(MAX_SCAN_EDGE, MAX_SCAN_EDGE, FilterType::Triangle);
+    }
+    // to go back to pixels of the image as it is
+    #[allow(clippy::cast_precision_loss)]
+    let scale = width as f32 / image.width().max(1) as f32;
+    let luma = image.into_luma8();
+    let (width, height) = luma.dimensions();
+    let results = match rxing::helpers::detect_multiple_in_luma(luma.into_raw(), width, height) {
+        Ok(results) => results,
+        Err(Exceptions::NotFoundException(_)) => return Ok(Vec::new()),
+        Err(e) => return Err(format!("scan {}: {e}", path.display())),
+    };
+    let mut codes: Vec<ScannedCode> = Vec::new();
+    for result in results {
+        let text = result.getText().to_owned();
+        if codes.iter().any(|code| code.text == text) {
+            continue;
+        }
+        let is_url = Url::parse(&text).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+        let points = result.getPoints().iter().map(|p| [p.x * scale, p.y * scale]).collect();
+        codes.push(ScannedCode { text, format: result.getBarcodeFormat().to_string(), is_url, points });
+    }
+    Ok(codes)
+}
+
+/// What the QR codes and barcodes in the image at `path` say, each once;
+/// empty if there are none.
+#[tauri::command]
+pub async fn scan_codes(path: String) -> Result<Vec<ScannedCode>, BackendError> {
+    log::info!("scan_codes start: {path}");
+    match workers::run(Priority::Interactive, move || scan(Path::new(&path))).await {
+        Ok(Ok(codes)) => {
+            log::info!("scan_codes done: {} codes", codes.len());
+            Ok(codes)
+        }
+        Ok(Err(e)) => Err(format!("scan_codes task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_have_a_quiet_zone() {
+        assert!(modules(&"x".repeat(8000)).is_err());
+        let modules = modules("hi").expect("encode a short text");
+        // version 1, 21 modules across
+        assert_eq!(modules.len(), 21 + 2 * QUIET_ZONE);
+        assert!(modules.iter().all(|row| row.len() == modules.len()));
+        assert!(modules[..QUIET_ZONE].iter().flatten().all(|&dark| !dark));
+        assert!(modules.iter().all(|row| row[..QUIET_ZONE].iter().all(|&dark| !dark)));
+        // the corner of a finder pattern
+        assert!(modules[QUIET_ZONE][QUIET_ZONE]);
+
+        let svg = render_svg(&modules, 100);
+        assert!(svg.contains(r#"width="100" height="100" viewBox="0 0 29 29""#), "{svg}");
+        assert!(svg.contains("M4 4h1v1h-1z"), "{svg}");
+    }
+
+    #[test]
+    fn modules_are_whole_pixels() {
+        let modules = modules("hi").expect("encode a short text");
+        let png = render_png(&modules, 100).expect("render a png");
+        let image = image::load_from_memory(&png).expect("decode the png").to_luma8();
+        // 3 pixels a module, centered
+        assert_eq!(image.dimensions(), (100, 100));
+        let first = 6 + 3 * u32::try_from(QUIET_ZONE).expect("a small number");
+        assert_eq!(image.get_pixel(first - 1, first)[0], 255);
+        assert_eq!(image.get_pixel(first, first)[0], 0);
+        // never smaller than a pixel a module
+        let png = render_png(&modules, 10).expect("render a png");
+        assert_eq!(image::load_from_memory(&png).expect("decode the png").width(), 29);
+    }
+
+    #[test]
+    fn generated_codes_scan() {
+        let dir = std::env::temp_dir().join(format!("emmm-qr-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create the folder");
+        let path = dir.join("code.png");
+        let modules = modules("https://example.com/notes").expect("encode a link");
+        std::fs::write(&path, render_png(&modules, 256).expect("render a png")).expect("write the png");
+        let codes = scan(&path).expect("scan the code");
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].text, "https://example.com/notes");
+        assert!(codes[0].is_url);
+        assert!(!codes[0].points.is_empty());
+
+        GrayImage::from_pixel(64, 64, Luma([255])).save(&path).expect("write a blank png");
+        assert!(scan(&path).expect("scan a blank image").is_empty());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,367 @@
+//! A persistent queue for uploads and publishes, so they survive being
+//! offline. Each operation is tried right away; failures are retried with
+//! exponential backoff, and as soon as one operation succeeds again (we're
+//! back online) everything waiting is retried immediately.
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::{
+    error::BackendError,
+    frontmatter::{self, Frontmatter},
+    gist, secrets,
+    uploader::{self, UploadTarget},
+    webhooks::{self, WebhookEvent},
+    workers::Priority,
+    workspace::write_atomic,
+};
+
+/// After this many failed attempts an entry waits for a manual retry.
+const MAX_ATTEMPTS: u32 = 12;
+const FIRST_RETRY: u64 = 15;
+const LONGEST_RETRY: u64 = 30 * 60;
+/// Finished entries are dropped after a week.
+const KEEP_DONE: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum Operation {
+    UploadImage {
+        path: String,
+        target: UploadTarget,
+        max_size: Option<usize>,
+    },
+    /// Publishes the document at `path` as it is when the attempt is made,
+    /// and writes the gist ID back into it.
+    PublishGist {
+        path: String,
+        public: bool,
+        /// Keychain entry with the GitHub token; `github` by default.
+        secret_name: Option<String>,
+        file_name: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Status {
+    Pending,
+    Running,
+    Done,
+    /// Gave up after [`MAX_ATTEMPTS`].
+    Failed,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    id: u64,
+    operation: Operation,
+    status: Status,
+    attempts: u32,
+    /// Seconds since the epoch, like the other times.
+    created_at: u64,
+    next_attempt_at: u64,
+    last_error: Option<String>,
+    /// The URL of the uploaded or published result.
+    result: Option<String>,
+}
+
+pub struct UploadQueue {
+    path: PathBuf,
+    entries: Mutex<Vec<Entry>>,
+    wake: tokio::sync::Notify,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn backoff(attempts: u32) -> u64 {
+    FIRST_RETRY
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(LONGEST_RETRY)
+}
+
+impl Operation {
+    async fn perform(&self) -> Result<String, String> {
+        match self {
+            Operation::UploadImage { path, target, max_size } =>
+                uploader::upload(path.clone(), target, *max_size, Priority::Background).await,
+            Operation::PublishGist { path, public, secret_name, file_name } => {
+                let token = secrets::require_secret(
+                    secret_name.clone().unwrap_or_else(|| "github".to_owned())).await?;
+                let doc = fs::read_to_string(path).map_err(|e| format!("read {path}: {e}"))?;
+                let published = gist::publish(doc, *public, &token, file_name.clone(), Priority::Background).await?;
+                // the document may have been edited in the meantime
+                let current = fs::read_to_string(path).map_err(|e| format!("read {path}: {e}"))?;
+                let (meta, _) = Frontmatter::of(&current);
+                if meta.get("gist") != Some(published.id.as_str()) {
+                    let updated = frontmatter::set_field(&current, "gist", &published.id);
+                    fs::write(path, updated).map_err(|e| format!("write {path}: {e}"))?;
+                }
+                webhooks::emit(WebhookEvent::Publish, path);
+                Ok(published.url)
+            }
+        }
+    }
+}
+
+impl UploadQueue {
+    /// Loads the queue saved at `path`. Entries that were running when the
+    /// app quit are tried again.
+    pub fn load(path: PathBuf) -> Self {
+        let mut entries: Vec<Entry> = fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        let now = now();
+        entries.retain(|e| e.status != Status::Done || e.created_at + KEEP_DONE > now);
+        for entry in &mut entries {
+            if entry.status == Status::Running {
+                entry.status = Status::Pending;
+                entry.next_attempt_at = now;
+            }
+        }
+        UploadQueue { path, entries: Mutex::new(entries), wake: tokio::sync::Notify::new() }
+    }
+
+    fn save(&self, entries: &[Entry]) {
+        let result = serde_json::to_vec_pretty(entries)
+            .map_err(|e| format!("serialize: {e}"))
+            .and_then(|json| {
+                if let Some(dir) = self.path.parent() {
+                    fs::create_dir_all(dir)
+                        .map_err(|e| format!("create {}: {e}", dir.display()))?;
+                }
+                write_atomic(&self.path, &json)
+            });
+        if let Err(e) = result {
+            log::error!("saving upload queue: {e}");
+        }
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut Vec<Entry>) -> T) -> T {
+        let mut entries = self.entries.lock().expect("upload queue lock poisoned");
+        let result = f(&mut entries);
+        self.save(&entries);
+        result
+    }
+
+    fn push(&self, operation: Operation) -> u64 {
+        let now = now();
+        let id = self.update(|entries| {
+            let id = entries.iter().map(|e| e.id).max().unwrap_or_default() + 1;
+            entries.push(Entry {
+                id,
+                operation,
+                status: Status::Pending,
+                attempts: 0,
+                created_at: now,
+                next_attempt_at: now,
+                last_error: None,
+                result: None,
+            });
+            id
+        });
+        self.wake.notify_one();
+        id
+    }
+
+    /// Marks the entries that are due as running and returns them.
+    fn take_due(&self) -> Vec<(u64, Operation)> {
+        let now = now();
+        self.update(|entries| {
+            entries
+                .iter_mut()
+                .filter(|e| e.status == Status::Pending && e.next_attempt_at <= now)
+                .map(|e| {
+                    e.status = Status::Running;
+                    (e.id, e.operation.clone())
+                })
+                .collect()
+        })
+    }
+
+    fn finish(&self, id: u64, result: Result<String, String>) {
+        let now = now();
+        self.update(|entries| {
+            let succeeded = result.is_ok();
+            if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+                entry.attempts += 1;
+                match result {
+                    Ok(url) => {
+                        entry.status = Status::Done;
+                        entry.result = Some(url);
+                        entry.last_error = None;
+                    }
+                    Err(e) => {
+                        log::warn!("queued operation {id} failed: {e}");
+                        entry.last_error = Some(e);
+                        if entry.attempts >= MAX_ATTEMPTS {
+                            entry.status = Status::Failed;
+                        } else {
+                            entry.status = Status::Pending;
+                            entry.next_attempt_at = now + backoff(entry.attempts);
+                        }
+                    }
+                }
+            }
+            if succeeded {
+                for entry in entries.iter_mut().filter(|e| e.status == Status::Pending) {
+                    entry.next_attempt_at = now;
+                }
+            }
+        });
+    }
+
+    /// How long until the next pending entry is due.
+    fn next_wakeup(&self) -> Option<Duration> {
+        let entries = self.entries.lock().expect("upload queue lock poisoned");
+        let now = now();
+        entries
+            .iter()
+            .filter(|e| e.status == Status::Pending)
+            .map(|e| Duration::from_secs(e.next_attempt_at.saturating_sub(now)))
+            .min()
+    }
+
+    /// Works through the queue forever; spawned once at startup.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            for (id, operation) in self.take_due() {
+                let result = operation.perform().await;
+                self.finish(id, result);
+            }
+            match self.next_wakeup() {
+                Some(wait) if wait.is_zero() => {}
+                Some(wait) => {
+                    let _ = tokio::time::timeout(wait, self.wake.notified()).await;
+                }
+                None => self.wake.notified().await,
+            }
+        }
+    }
+}
+
+/// Queues an image upload; see [`uploader::upload_image`].
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn enqueue_upload(
+    path: String, target: UploadTarget, max_size: Option<usize>,
+    queue: State<'_, Arc<UploadQueue>>,
+) -> u64 {
+    queue.push(Operation::UploadImage { path, target, max_size })
+}
+
+/// Queues publishing the document at `path` as a gist.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn enqueue_gist(
+    path: String, public: bool, secret_name: Option<String>, file_name: Option<String>,
+    queue: State<'_, Arc<UploadQueue>>,
+) -> u64 {
+    queue.push(Operation::PublishGist { path, public, secret_name, file_name })
+}
+
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn list_queue(queue: State<'_, Arc<UploadQueue>>) -> Vec<Entry> {
+    queue.entries.lock().expect("upload queue lock poisoned").clone()
+}
+
+/// Retries entry `id`, or every unfinished entry, right now; e.g. when the
+/// frontend notices it's back online.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn retry_queue(id: Option<u64>, queue: State<'_, Arc<UploadQueue>>) {
+    let now = now();
+    queue.update(|entries| {
+        let retried = entries
+            .iter_mut()
+            .filter(|e| id.is_none_or(|id| e.id == id))
+            .filter(|e| matches!(e.status, Status::Pending | Status::Failed));
+        for entry in retried {
+            if entry.status == Status::Failed {
+                entry.attempts = 0;
+            }
+            entry.status = Status::Pending;
+            entry.next_attempt_at = now;
+        }
+    });
+    queue.wake.notify_one();
+}
+
+/// Removes an entry that isn't running.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn remove_from_queue(id: u64, queue: State<'_, Arc<UploadQueue>>) -> Result<(), BackendError> {
+    Ok(queue.update(|entries| {
+        match entries.iter().position(|e| e.id == id) {
+            Some(i) if entries[i].status == Status::Running =>
+                Err(format!("entry {id} is running")),
+            Some(i) => {
+                entries.remove(i);
+                Ok(())
+            }
+            None => Err(format!("no entry {id}")),
+        }
+    })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operation() -> Operation {
+        Operation::PublishGist { path: "a.md".to_owned(), public: false, secret_name: None, file_name: None }
+    }
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        assert_eq!([1, 2, 3, 4].map(backoff), [15, 30, 60, 120]);
+        assert_eq!(backoff(0), FIRST_RETRY);
+        assert_eq!(backoff(MAX_ATTEMPTS), LONGEST_RETRY);
+        assert_eq!(backoff(u32::MAX), LONGEST_RETRY);
+    }
+
+    #[test]
+    fn entries_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("emmm-queue-test-{}", std::process::id())).join("queue.json");
+        let _ = fs::remove_file(&path);
+        let queue = UploadQueue::load(path.clone());
+        let (first, second) = (queue.push(operation()), queue.push(operation()));
+        assert_eq!((first, second), (1, 2));
+        assert_eq!(queue.take_due().len(), 2);
+        assert!(queue.take_due().is_empty());
+
+        queue.finish(first, Err("offline".to_owned()));
+        let entry = queue.entries.lock().unwrap()[0].clone();
+        assert!(entry.status == Status::Pending && entry.attempts == 1);
+        assert_eq!(entry.last_error.as_deref(), Some("offline"));
+        assert!(queue.next_wakeup().unwrap() > Duration::from_secs(FIRST_RETRY - 5));
+
+        // the second one was running when the app quit
+        let queue = UploadQueue::load(path.clone());
+        assert_eq!(queue.next_wakeup(), Some(Duration::ZERO));
+        assert_eq!(queue.take_due().iter().map(|(id, _)| *id).collect::<Vec<_>>(), [second]);
+
+        // succeeding means we're back online, so the first one is retried at once
+        queue.finish(second, Ok("https://gist.github.com/1".to_owned()));
+        assert_eq!(queue.next_wakeup(), Some(Duration::ZERO));
+        let entries = queue.entries.lock().unwrap().clone();
+        assert!(entries[1].status == Status::Done);
+        assert_eq!(entries[1].result.as_deref(), Some("https://gist.github.com/1"));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,202 @@
+//! Politeness towards the servers we fetch pages and images from: at most
+//! [`PER_HOST`] requests to a host at once, started at least [`DELAY`]
+//! apart, and none while it told us to back off with `429 Too Many
+//! Requests` or `503 Service Unavailable`. Those are retried after the
+//! `Retry-After` wait, if it's short enough. So a document with 200 links
+//! to one site has their previews fetched a couple at a time.
+//!
+//! A request counts as running until its response headers arrive; reading
+//! the body isn't limited.
+
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tauri::Url;
+
+use crate::net::reqwest;
+
+const PER_HOST: usize = 2;
+const DELAY: Duration = Duration::from_millis(250);
+/// How often a host that's busy is looked at again.
+const POLL: Duration = Duration::from_millis(50);
+/// Longer `Retry-After` waits aren't waited for: the response is returned.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// The wait when a server doesn't say how long, or says it as a date.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+const RETRIES: usize = 2;
+
+#[derive(Default)]
+struct Host {
+    running: usize,
+    /// No request starts before then.
+    next_start: Option<Instant>,
+}
+
+static HOSTS: LazyLock<Mutex<HashMap<String, Host>>> = LazyLock::new(Mutex::default);
+
+/// Held while a request to its host runs.
+struct Permit(String);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut hosts = HOSTS.lock().expect("rate limit lock poisoned");
+        if let Some(host) = hosts.get_mut(&self.0) {
+            host.running -= 1;
+            // hosts that are done with are forgotten
+            if host.running == 0 && host.next_start.is_none_or(|next| next <= Instant::now()) {
+                hosts.remove(&self.0);
+            }
+        }
+    }
+}
+
+fn host(url: &Url) -> String {
+    url.host_str().map_or_else(String::new, str::to_lowercase)
+}
+
+/// A permit for `host`, or how long to wait before asking again.
+fn try_acquire(host: &str) -> Result<Permit, Duration> {
+    let mut hosts = HOSTS.lock().expect("rate limit lock poisoned");
+    let state = hosts.entry(host.to_owned()).or_default();
+    let now = Instant::now();
+    if let Some(next) = state.next_start.filter(|&next| next > now) {
+        return Err(next - now);
+    }
+    if state.running >= PER_HOST {
+        return Err(POLL);
+    }
+    state.running += 1;
+    state.next_start = Some(now + DELAY);
+    Ok(Permit(host.to_owned()))
+}
+
+async fn acquire(host: &str) -> Permit {
+    loop {
+        match try_acquire(host) {
+            Ok(permit) => return permit,
+            Err(wait) => tokio::time::sleep(wait).await,
+        }
+    }
+}
+
+fn acquire_blocking(host: &str) -> Permit {
+    loop {
+        match try_acquire(host) {
+            Ok(permit) => return permit,
+            Err(wait) => thread::sleep(wait),
+        }
+    }
+}
+
+/// Keeps requests to `host` from starting for `wait`.
+fn back_off(host: &str, wait: Duration) {
+    let mut hosts = HOSTS.lock().expect("rate limit lock poisoned");
+    let state = hosts.entry(host.to_owned()).or_default();
+    let until = Instant::now() + wait.min(MAX_RETRY_AFTER);
+    state.next_start = Some(state.next_start.map_or(until, |next| next.max(until)));
+}
+
+/// How long the server asked us to wait, if it did.
+fn retry_after(status: reqwest::StatusCode, headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    if status != reqwest::StatusCode::TOO_MANY_REQUESTS && status != reqwest::StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+    let seconds = headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+    Some(seconds.map_or(DEFAULT_RETRY_AFTER, Duration::from_secs))
+}
+
+/// What to do once a response to `host` arrived: back off if asked to,
+/// and whether to try again.
+fn should_retry(host: &str, url: &Url, wait: Option<Duration>, attempt: usize) -> bool {
+    let Some(wait) = wait else { return false };
+    back_off(host, wait);
+    let retry = attempt < RETRIES && wait <= MAX_RETRY_AFTER;
+    log::info!("rate limit: {url} asked to wait {}s{}", wait.as_secs(), if retry { ", retrying" } else { "" });
+    retry
+}
+
+/// Sends `request` once its host lets us, retrying if it asks to.
+pub async fn send(request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+    let (client, request) = request.build_split();
+    let mut request = request?;
+    let host = host(request.url());
+    let mut attempt = 0;
+    loop {
+        let retry = request.try_clone();
+        let url = request.url().clone();
+        let permit = acquire(&host).await;
+        let response = client.execute(request).await?;
+        drop(permit);
+        let wait = retry_after(response.status(), response.headers());
+        let again = should_retry(&host, &url, wait, attempt);
+        // requests with a streamed body can't be sent again
+        match retry.filter(|_| again) {
+            Some(retry) => request = retry,
+            None => return Ok(response),
+        }
+        attempt += 1;
+    }
+}
+
+/// Blocking version of [`send`].
+pub fn send_blocking(request: reqwest::blocking::RequestBuilder) -> reqwest::Result<reqwest::blocking::Response> {
+    let (client, request) = request.build_split();
+    let mut request = request?;
+    let host = host(request.url());
+    let mut attempt = 0;
+    loop {
+        let retry = request.try_clone();
+        let url = request.url().clone();
+        let permit = acquire_blocking(&host);
+        let response = client.execute(request)?;
+        drop(permit);
+        let wait = retry_after(response.status(), response.headers());
+        let again = should_retry(&host, &url, wait, attempt);
+        match retry.filter(|_| again) {
+            Some(retry) => request = retry,
+            None => return Ok(response),
+        }
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::reqwest::{header::HeaderMap, StatusCode};
+
+    use super::*;
+
+    #[test]
+    fn retry_after_is_read_from_throttling_responses() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(StatusCode::TOO_MANY_REQUESTS, &headers), Some(DEFAULT_RETRY_AFTER));
+        headers.insert(reqwest::header::RETRY_AFTER, " 12 ".parse().expect("a header value"));
+        assert_eq!(retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers), Some(Duration::from_secs(12)));
+        assert_eq!(retry_after(StatusCode::OK, &headers), None);
+        headers.insert(reqwest::header::RETRY_AFTER, "Wed, 21 Oct 2015 07:28:00 GMT".parse().expect("a header value"));
+        assert_eq!(retry_after(StatusCode::TOO_MANY_REQUESTS, &headers), Some(DEFAULT_RETRY_AFTER));
+    }
+
+    #[test]
+    fn requests_to_a_host_are_spaced_out() {
+        let host = "spaced.rate-limit.test";
+        let first = try_acquire(host).expect("the first permit");
+        let wait = try_acquire(host).err().expect("a wait for the second permit");
+        assert!(wait > Duration::ZERO && wait <= DELAY, "{wait:?}");
+        drop(first);
+
+        // other hosts aren't held up
+        let other = try_acquire("other.rate-limit.test").expect("a permit for another host");
+        back_off("other.rate-limit.test", Duration::from_secs(3600));
+        drop(other);
+        let wait = try_acquire("other.rate-limit.test").err().expect("a wait after backing off");
+        assert!(wait > MAX_RETRY_AFTER - Duration::from_secs(1) && wait <= MAX_RETRY_AFTER, "{wait:?}");
+    }
+}
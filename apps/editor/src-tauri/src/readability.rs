@@ -0,0 +1,243 @@
+//! Readability statistics for the stats panel: Flesch reading ease and
+//! Flesch-Kincaid grade, how long the sentences are, and which ones are
+//! hard to read. Only prose counts; headings, code and the frontmatter
+//! don't. Syllables are estimated for English.
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use serde::Serialize;
+
+use crate::{
+    error::BackendError,
+    prose_lint, spellcheck, typography,
+    workers::{self, Priority},
+};
+
+/// Sentence lengths in words are counted in buckets this wide, with the
+/// last open ended.
+const BUCKET_WIDTH: usize = 10;
+const BUCKETS: usize = 5;
+
+/// Sentences shorter than this aren't hard, whatever their words.
+const MIN_HARD_WORDS: usize = 14;
+const HARD_GRADE: f64 = 10.0;
+const VERY_HARD_GRADE: f64 = 14.0;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Difficulty {
+    Hard,
+    VeryHard,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HardSentence {
+    /// In UTF-16 code units from the start of the text, like JavaScript
+    /// string indices.
+    offset: usize,
+    length: usize,
+    difficulty: Difficulty,
+    /// The Flesch-Kincaid grade of the sentence alone.
+    grade: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SentenceLengths {
+    /// Words, inclusive; `max` is `None` for the last bucket.
+    min: usize,
+    max: Option<usize>,
+    count: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Readability {
+    words: usize,
+    sentences: usize,
+    syllables: usize,
+    /// 0 to 100, higher is easier; `None` without sentences.
+    reading_ease: Option<f64>,
+    /// A US school grade.
+    grade_level: Option<f64>,
+    average_sentence_words: f64,
+    longest_sentence_words: usize,
+    sentence_lengths: Vec<SentenceLengths>,
+    hard_sentences: Vec<HardSentence>,
+}
+
+/// An estimate: groups of vowels, less a silent `e`.
+fn syllables(word: &str) -> usize {
+    let word = word.to_lowercase();
+    let letters: Vec<char> = word.chars().filter(char::is_ascii_alphabetic).collect();
+    if letters.is_empty() {
+        // not English; one per word keeps the scores sensible
+        return 1;
+    }
+    let is_vowel = |c: char| matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y');
+    let mut count = 0;
+    let mut previous_vowel = false;
+    for &c in &letters {
+        let vowel = is_vowel(c);
+        if vowel && !previous_vowel {
+            count += 1;
+        }
+        previous_vowel = vowel;
+    }
+    let ends = |suffix: &str| word.ends_with(suffix);
+    let silent = (ends("e") && !ends("le") && !ends("ee"))
+        || (ends("es") && !["ses", "zes", "ces", "ges", "xes", "shes", "ches"].iter().any(|s| ends(s)))
+        || (ends("ed") && !ends("ted") && !ends("ded"));
+    if silent && count > 1 {
+        count -= 1;
+    }
+    count.max(1)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn grade(words: usize, sentences: usize, syllables: usize) -> f64 {
+    0.39 * words as f64 / sentences as f64 + 11.8 * syllables as f64 / words as f64 - 15.59
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn reading_ease(words: usize, sentences: usize, syllables: usize) -> f64 {
+    206.835 - 1.015 * words as f64 / sentences as f64 - 84.6 * syllables as f64 / words as f64
+}
+
+fn round(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+/// `text` with everything but prose outside headings blanked out, so
+/// offsets stay the same.
+pub(crate) fn prose(text: &str) -> String {
+    let headings: Vec<_> = Parser::new_ext(text, Options::all())
+        .into_offset_iter()
+        .filter_map(|(event, range)| matches!(event, Event::Start(Tag::Heading { .. })).then_some(range))
+        .collect();
+    let mut out = String::with_capacity(text.len());
+    let mut done = 0;
+    let blank = |out: &mut String, part: &str| out.extend(part.bytes().map(|b| if b == b'\n' { '\n' } else { ' ' }));
+    for range in typography::prose_ranges(text) {
+        if headings.iter().any(|h| h.contains(&range.start)) {
+            continue;
+        }
+        blank(&mut out, &text[done..range.start]);
+        out.push_str(&text[range.clone()]);
+        done = range.end;
+    }
+    blank(&mut out, &text[done..]);
+    out
+}
+
+fn analyze(text: &str) -> Readability {
+    let prose = prose(text);
+    let words = spellcheck::words(&prose);
+    let mut sentence_lengths: Vec<SentenceLengths> = (0..BUCKETS)
+        .map(|i| SentenceLengths {
+            min: i * BUCKET_WIDTH + 1,
+            max: (i + 1 < BUCKETS).then_some((i + 1) * BUCKET_WIDTH),
+            count: 0,
+        })
+        .collect();
+    let (mut sentences, mut syllable_count, mut longest) = (0, 0, 0);
+    let mut hard = Vec::new();
+    let mut next_word = 0;
+    for (start, end) in prose_lint::sentences(&prose) {
+        let first = next_word;
+        while words.get(next_word).is_some_and(|&(at, _)| at < end) {
+            next_word += 1;
+        }
+        let in_sentence = &words[first..next_word];
+        if in_sentence.is_empty() {
+            continue;
+        }
+        let count = in_sentence.len();
+        let syllables: usize = in_sentence.iter().map(|(_, w)| syllables(w)).sum();
+        sentences += 1;
+        syllable_count += syllables;
+        longest = longest.max(count);
+        sentence_lengths[((count - 1) / BUCKET_WIDTH).min(BUCKETS - 1)].count += 1;
+        let grade = grade(count, 1, syllables);
+        if count >= MIN_HARD_WORDS && grade >= HARD_GRADE {
+            let difficulty = if grade >= VERY_HARD_GRADE { Difficulty::VeryHard } else { Difficulty::Hard };
+            hard.push((start, end, difficulty, round(grade)));
+        }
+    }
+
+    // UTF-16 offsets, counted in one pass since sentences are in order
+    let (mut done, mut offset) = (0, 0);
+    let hard_sentences = hard
+        .into_iter()
+        .map(|(start, end, difficulty, grade)| {
+            offset += text[done..start].encode_utf16().count();
+            done = start;
+            HardSentence { offset, length: text[start..end].encode_utf16().count(), difficulty, grade }
+        })
+        .collect();
+    let word_count = words.len();
+    let scores = (sentences > 0).then_some((word_count, sentences, syllable_count));
+    #[allow(clippy::cast_precision_loss)]
+    Readability {
+        words: word_count,
+        sentences,
+        syllables: syllable_count,
+        reading_ease: scores.map(|(w, s, y)| round(reading_ease(w, s, y))),
+        grade_level: scores.map(|(w, s, y)| round(grade(w, s, y))),
+        average_sentence_words: if sentences == 0 { 0.0 } else { round(word_count as f64 / sentences as f64) },
+        longest_sentence_words: longest,
+        sentence_lengths,
+        hard_sentences,
+    }
+}
+
+/// Readability of the markdown `text`, worked out off the main thread so
+/// long documents can be scored as they're typed.
+#[tauri::command]
+pub async fn readability(text: String) -> Result<Readability, BackendError> {
+    match workers::run(Priority::Interactive, move || analyze(&text)).await {
+        Ok(readability) => Ok(readability),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn syllables_are_estimated() {
+        let counts: Vec<_> = ["cat", "table", "make", "wanted", "jumped", "rhythm", "boxes", "日本"]
+            .into_iter()
+            .map(syllables)
+            .collect();
+        assert_eq!(counts, [1, 2, 1, 2, 1, 1, 2, 1]);
+    }
+
+    #[test]
+    fn only_prose_is_scored() {
+        let text = "# A heading that is rather long\n\nThe cat sat. The dog ran away.\n\n```\nnot counted\n```\n";
+        let prose = prose(text);
+        assert_eq!(prose.len(), text.len());
+        assert!(!prose.contains("heading") && !prose.contains("counted"), "{prose}");
+        let readability = analyze(text);
+        assert_eq!((readability.words, readability.sentences, readability.syllables), (7, 2, 8));
+        assert_eq!(readability.average_sentence_words, 3.5);
+        assert_eq!(readability.longest_sentence_words, 4);
+        assert_eq!(readability.sentence_lengths[0].count, 2);
+        assert!(readability.hard_sentences.is_empty());
+        assert!(readability.reading_ease.is_some_and(|ease| ease > 90.0));
+        assert_eq!(analyze("```\ncode\n```\n").reading_ease, None);
+    }
+
+    #[test]
+    fn hard_sentences_are_found_by_utf_16_offset() {
+        let hard = "Administrative regulations necessitate considerable documentation regarding environmental \
+                    sustainability initiatives throughout governmental agencies, particularly concerning \
+                    infrastructure modernization.";
+        let readability = analyze(&format!("Café 😀 is open. {hard}"));
+        let found: Vec<_> = readability.hard_sentences.iter().map(|s| (s.offset, s.length, s.difficulty)).collect();
+        assert_eq!(found, [(17, hard.len(), Difficulty::VeryHard)]);
+        assert_eq!(readability.sentence_lengths[1].count, 1);
+    }
+}
@@ -0,0 +1,143 @@
+//! Find and replace with a regular expression the user typed, run here so
+//! a slow one can't freeze the webview. The `regex` crate never
+//! backtracks, so the time is bounded by the size of the text; a limit on
+//! the compiled size and a deadline bound it further.
+
+use std::time::{Duration, Instant};
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::BackendError,
+    workers::{self, Priority},
+};
+
+/// Larger patterns, like `\w{1000}{1000}`, are refused.
+const SIZE_LIMIT: usize = 10 << 20;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Matches listed for the preview; all of them are replaced.
+const DEFAULT_PREVIEW_LIMIT: usize = 1000;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RegexOptions {
+    case_insensitive: bool,
+    /// `^` and `$` match at line ends.
+    multi_line: bool,
+    /// `.` matches line ends.
+    dot_all: bool,
+    /// 2000 by default.
+    timeout_ms: Option<u64>,
+    /// Matches listed at most; 1000 by default.
+    preview_limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegexMatch {
+    /// In UTF-16 code units from the start of the text, like JavaScript
+    /// string indices.
+    offset: usize,
+    length: usize,
+    /// What the match becomes.
+    replacement: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegexTransform {
+    text: String,
+    /// All of them, even past the preview limit.
+    count: usize,
+    matches: Vec<RegexMatch>,
+}
+
+fn compile(pattern: &str, options: &RegexOptions) -> Result<Regex, String> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(options.case_insensitive)
+        .multi_line(options.multi_line)
+        .dot_matches_new_line(options.dot_all)
+        .size_limit(SIZE_LIMIT)
+        .dfa_size_limit(SIZE_LIMIT)
+        .build()
+        .map_err(|e| format!("invalid pattern: {e}"))
+}
+
+fn transform(text: &str, pattern: &str, replacement: &str, options: &RegexOptions) -> Result<RegexTransform, String> {
+    let regex = compile(pattern, options)?;
+    let timeout = options.timeout_ms.map_or(DEFAULT_TIMEOUT, Duration::from_millis);
+    let preview_limit = options.preview_limit.unwrap_or(DEFAULT_PREVIEW_LIMIT);
+    let deadline = Instant::now() + timeout;
+    let mut out = String::with_capacity(text.len());
+    let mut matches = Vec::new();
+    let (mut done, mut offset, mut count) = (0, 0, 0);
+    for captures in regex.captures_iter(text) {
+        if Instant::now() > deadline {
+            return Err(format!("gave up after {} ms", timeout.as_millis()));
+        }
+        let whole = captures.get(0).expect("group 0 is the match");
+        let start = out.len();
+        out.push_str(&text[done..whole.start()]);
+        let prefix = out.len() - start;
+        captures.expand(replacement, &mut out);
+        count += 1;
+        if matches.len() < preview_limit {
+            offset += text[done..whole.start()].encode_utf16().count();
+            matches.push(RegexMatch {
+                offset,
+                length: whole.as_str().encode_utf16().count(),
+                replacement: out[start + prefix..].to_owned(),
+            });
+            offset += whole.as_str().encode_utf16().count();
+        }
+        done = whole.end();
+    }
+    out.push_str(&text[done..]);
+    Ok(RegexTransform { text: out, count, matches })
+}
+
+/// `text` with every match of `pattern` replaced, where `$1` or `${name}`
+/// in `replacement` are groups, and the matches for a preview. Offsets
+/// are in the original text.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn regex_transform(
+    text: String, pattern: String, replacement: String, options: RegexOptions,
+) -> Result<RegexTransform, BackendError> {
+    let result = workers::run(Priority::Interactive, move || transform(&text, &pattern, &replacement, &options)).await;
+    match result {
+        Ok(Ok(transformed)) => Ok(transformed),
+        Ok(Err(e)) => Err(format!("regex_transform task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_are_replaced_with_their_groups() {
+        let options = RegexOptions { case_insensitive: true, preview_limit: Some(2), ..RegexOptions::default() };
+        let transformed = transform("café 😀 cat and Cat, CAT", r"(?P<first>c)at", "${first}ow", &options)
+            .expect("replace");
+        assert_eq!(transformed.text, "café 😀 cow and Cow, Cow");
+        assert_eq!(transformed.count, 3);
+        let matches: Vec<_> =
+            transformed.matches.iter().map(|m| (m.offset, m.length, m.replacement.as_str())).collect();
+        assert_eq!(matches, [(8, 3, "cow"), (16, 3, "Cow")]);
+
+        let options = RegexOptions { multi_line: true, ..RegexOptions::default() };
+        assert_eq!(transform("a\nb", "^", "> ", &options).expect("quote").text, "> a\n> b");
+        assert_eq!(transform("a\nb", "a.b", "", &RegexOptions::default()).expect("no match").count, 0);
+        let options = RegexOptions { dot_all: true, ..RegexOptions::default() };
+        assert_eq!(transform("a\nb", "a.b", "ab", &options).expect("join").text, "ab");
+    }
+
+    #[test]
+    fn bad_and_huge_patterns_are_refused() {
+        assert!(transform("x", "(", "", &RegexOptions::default()).is_err());
+        assert!(transform("x", r"\w{1000}{1000}", "", &RegexOptions::default()).is_err());
+    }
+}
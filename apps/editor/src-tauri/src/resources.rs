@@ -0,0 +1,115 @@
+//! What the backend holds on to, for a diagnostics panel to show when the
+//! app gets big: its memory, the caches in memory and on disk, what's
+//! being watched and the jobs running.
+
+use std::{fs, path::Path, sync::Arc};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::{
+    conflict::Bases,
+    db::Database,
+    error::BackendError,
+    image_cache::ImageCache,
+    index::WorkspaceIndex,
+    jobs::{self, JobInfo},
+    logs, preview,
+    spellcheck::SpellChecker,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Memory {
+    /// Resident, in bytes.
+    resident: u64,
+    /// Reserved, in bytes; much of it may never be used.
+    reserved: u64,
+}
+
+/// Sizes in bytes.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskUsage {
+    image_cache: u64,
+    document_bases: u64,
+    database: u64,
+    logs: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryCaches {
+    indexed_documents: usize,
+    /// Languages of the spell checking dictionaries loaded.
+    dictionaries: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Watchers {
+    /// Whether the preview server watches a workspace for changes.
+    preview_server: bool,
+    /// Preview pages listening for those changes.
+    preview_pages: usize,
+    log_tails: usize,
+    job_listeners: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceUsage {
+    /// `None` where it can't be told.
+    memory: Option<Memory>,
+    disk: DiskUsage,
+    caches: MemoryCaches,
+    watchers: Watchers,
+    jobs: Vec<JobInfo>,
+}
+
+/// Of the files in `dir` and below, in bytes.
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else { return 0 };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|e| Some((e.path(), e.metadata().ok()?)))
+        .map(|(path, metadata)| if metadata.is_dir() { dir_size(&path) } else { metadata.len() })
+        .sum()
+}
+
+fn usage(app: &AppHandle) -> Result<ResourceUsage, String> {
+    let log_dir = app.path().app_log_dir().map_err(|e| format!("app_log_dir: {e}"))?;
+    let preview_pages = preview::listening_pages();
+    Ok(ResourceUsage {
+        memory: memory_stats::memory_stats()
+            .map(|stats| Memory { resident: stats.physical_mem as u64, reserved: stats.virtual_mem as u64 }),
+        disk: DiskUsage {
+            image_cache: app.state::<ImageCache>().size(),
+            document_bases: app.state::<Bases>().size(),
+            database: app.state::<Arc<Database>>().size(),
+            logs: dir_size(&log_dir),
+        },
+        caches: MemoryCaches {
+            indexed_documents: app.state::<Arc<WorkspaceIndex>>().document_count(),
+            dictionaries: app.state::<Arc<SpellChecker>>().loaded_languages(),
+        },
+        watchers: Watchers {
+            preview_server: preview_pages.is_some(),
+            preview_pages: preview_pages.unwrap_or(0),
+            log_tails: logs::tail_count(),
+            job_listeners: jobs::watcher_count(),
+        },
+        jobs: jobs::list_jobs(),
+    })
+}
+
+/// How much memory the backend uses and what it holds on to.
+#[tauri::command]
+pub async fn resource_usage(app: AppHandle) -> Result<ResourceUsage, BackendError> {
+    let result = tokio::task::spawn_blocking(move || usage(&app)).await;
+    match result {
+        Ok(Ok(usage)) => Ok(usage),
+        Ok(Err(e)) => Err(format!("resource_usage task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
@@ -0,0 +1,231 @@
+//! Image uploads to S3-compatible object storage (AWS, R2, MinIO, OSS...),
+//! signed with AWS Signature Version 4.
+
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::Url;
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::{
+    error::BackendError,
+    net::{self, reqwest},
+    secrets,
+    uploader::{self, Image, UploadTarget, Uploader},
+    workers::Priority,
+};
+
+/// Everything but the unreserved characters, as SigV4 requires.
+const KEY_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-west-1.amazonaws.com`.
+    endpoint: String,
+    #[serde(default = "default_region")]
+    region: String,
+    bucket: String,
+    access_key_id: String,
+    /// Keychain entry with the secret access key; `s3:<access key id>` by
+    /// default.
+    secret_name: Option<String>,
+    /// Address objects as `endpoint/bucket/key` instead of
+    /// `bucket.endpoint/key`; needed by MinIO and most self-hosted servers.
+    #[serde(default)]
+    path_style: bool,
+    /// Prepended to object keys, e.g. `images/`.
+    #[serde(default)]
+    prefix: String,
+    /// Sent as `x-amz-acl`, e.g. `public-read`.
+    acl: Option<String>,
+    /// Base of the returned links, e.g. a CDN domain; the object URL by
+    /// default.
+    public_url: Option<String>,
+}
+
+fn default_region() -> String {
+    "us-east-1".to_owned()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes any key size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn encode_key(key: &str) -> String {
+    key.split('/')
+        .map(|s| utf8_percent_encode(s, KEY_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl S3Config {
+    fn object_url(&self, key: &str) -> Result<Url, String> {
+        let endpoint = Url::parse(self.endpoint.trim_end_matches('/'))
+            .map_err(|e| format!("invalid endpoint {}: {e}", self.endpoint))?;
+        let mut url = endpoint.clone();
+        if self.path_style {
+            url.set_path(&format!("/{}/{}", self.bucket, encode_key(key)));
+        } else {
+            let host = endpoint.host_str().ok_or("endpoint has no host".to_owned())?;
+            url.set_host(Some(&format!("{}.{host}", self.bucket)))
+                .map_err(|e| format!("invalid bucket {}: {e}", self.bucket))?;
+            url.set_path(&format!("/{}", encode_key(key)));
+        }
+        Ok(url)
+    }
+
+    fn public_url(&self, key: &str, object_url: &Url) -> String {
+        match &self.public_url {
+            Some(base) if !base.is_empty() =>
+                format!("{}/{}", base.trim_end_matches('/'), encode_key(key)),
+            _ => object_url.to_string(),
+        }
+    }
+
+    /// Headers for a signed `PUT` of `data` to `url`.
+    fn sign_put(
+        &self, url: &Url, content_type: &str, data: &[u8], secret: &str, now: OffsetDateTime,
+    ) -> Result<Vec<(String, String)>, String> {
+        let amz_date = now
+            .format(format_description!("[year][month][day]T[hour][minute][second]Z"))
+            .map_err(|e| format!("format date: {e}"))?;
+        let date = &amz_date[..8];
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        };
+        let payload_hash = sha256_hex(data);
+
+        let mut headers = vec![
+            ("content-type".to_owned(), content_type.to_owned()),
+            ("host".to_owned(), host),
+            ("x-amz-content-sha256".to_owned(), payload_hash.clone()),
+            ("x-amz-date".to_owned(), amz_date.clone()),
+        ];
+        if let Some(acl) = &self.acl {
+            headers.push(("x-amz-acl".to_owned(), acl.clone()));
+        }
+        headers.sort();
+        let canonical_headers: String =
+            headers.iter().map(|(k, v)| format!("{k}:{}\n", v.trim())).collect();
+        let signed_headers =
+            headers.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>().join(";");
+        let canonical_request = format!(
+            "PUT\n{}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}", url.path());
+
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            sha256_hex(canonical_request.as_bytes()));
+        let key = ["s3", "aws4_request"].iter().fold(
+            hmac(&hmac(format!("AWS4{secret}").as_bytes(), date), &self.region),
+            |key, part| hmac(&key, part));
+        let signature = hex::encode(hmac(&key, &string_to_sign));
+
+        headers.retain(|(k, _)| k != "host");
+        headers.push(("authorization".to_owned(), format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.access_key_id)));
+        Ok(headers)
+    }
+}
+
+impl Uploader for S3Config {
+    async fn upload(&self, client: &reqwest::Client, image: Image) -> Result<String, String> {
+        let secret = secrets::require_secret(self.secret_name
+            .clone()
+            .unwrap_or_else(|| format!("s3:{}", self.access_key_id))).await?;
+        let key = format!("{}{}", self.prefix, image.name);
+        let url = self.object_url(&key)?;
+        let headers = self.sign_put(
+            &url, image.mime_type, &image.data, &secret, OffsetDateTime::now_utc())?;
+
+        let mut request = client.put(url.clone()).body(image.data);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.map_err(|e| format!("put {url}: {e}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = net::read_capped(response, 4096).await.unwrap_or_default();
+            return Err(format!(
+                "put {url}: server returned {status}: {}", String::from_utf8_lossy(&body)));
+        }
+        Ok(self.public_url(&key, &url))
+    }
+}
+
+/// Compresses the image at `path` below `max_size` bytes and uploads it,
+/// returning the URL to link to. Objects are named by content hash, so
+/// uploading the same image twice yields the same URL.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn upload_to_s3(
+    path: String, max_size: usize, config: S3Config,
+) -> Result<String, BackendError> {
+    log::info!("upload_to_s3 start: {path}");
+    let url = uploader::upload(path, &UploadTarget::S3(config), Some(max_size), Priority::Interactive)
+        .await
+        .map_err(|e| format!("upload_to_s3: {e}"))?;
+    log::info!("upload_to_s3 done: {url}");
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(path_style: bool) -> S3Config {
+        S3Config {
+            endpoint: "https://s3.eu-west-1.amazonaws.com/".to_owned(),
+            region: "eu-west-1".to_owned(),
+            bucket: "photos".to_owned(),
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_name: None,
+            path_style,
+            prefix: String::new(),
+            acl: Some("public-read".to_owned()),
+            public_url: None,
+        }
+    }
+
+    #[test]
+    fn objects_are_addressed_by_style() {
+        let key = "images/a b+c.png";
+        assert_eq!(config(true).object_url(key).unwrap().as_str(),
+            "https://s3.eu-west-1.amazonaws.com/photos/images/a%20b%2Bc.png");
+        let url = config(false).object_url(key).unwrap();
+        assert_eq!(url.as_str(), "https://photos.s3.eu-west-1.amazonaws.com/images/a%20b%2Bc.png");
+        assert_eq!(config(false).public_url(key, &url), url.as_str());
+        let cdn = S3Config { public_url: Some("https://cdn.example.com/".to_owned()), ..config(false) };
+        assert_eq!(cdn.public_url(key, &url), "https://cdn.example.com/images/a%20b%2Bc.png");
+    }
+
+    #[test]
+    fn puts_are_signed() {
+        let config = config(false);
+        let url = config.object_url("a.png").unwrap();
+        let now = OffsetDateTime::from_unix_timestamp(1_369_353_600).unwrap();
+        let headers = config.sign_put(&url, "image/png", b"data", "secret", now).unwrap();
+        let header = |name: &str| headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str());
+        assert_eq!(header("x-amz-date"), Some("20130524T000000Z"));
+        assert_eq!(header("host"), None);
+        let authorization = header("authorization").unwrap();
+        assert!(authorization.starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20130524/eu-west-1/s3/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-acl;x-amz-content-sha256;x-amz-date, Signature="),
+            "{authorization}");
+        // the signature covers the payload
+        let other = config.sign_put(&url, "image/png", b"other", "secret", now).unwrap();
+        assert_ne!(other.iter().find(|(k, _)| k == "authorization").map(|(_, v)| v.as_str()), Some(authorization));
+    }
+}
@@ -0,0 +1,238 @@
+//! Upkeep done now and then in the background: refreshing the workspace
+//! indexes, pruning the document bases kept for merges, trimming the image
+//! cache and backing up the app's data. A task only starts while the user
+//! is idle, and not on battery unless allowed; [`run_now`] runs one anyway.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{Arc, LazyLock, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+use time::macros::format_description;
+
+use crate::{
+    conflict::Bases,
+    db::Database,
+    error::BackendError,
+    image_cache::ImageCache,
+    index::WorkspaceIndex,
+    jobs::{self, JobKind},
+    logs, power, settings,
+    workers::{self, Priority},
+};
+
+/// How often due tasks are looked for.
+const TICK: Duration = Duration::from_secs(60);
+/// Backups kept; older ones are removed.
+const BACKUPS: usize = 7;
+/// Files of the app data that are backed up besides the database and the
+/// settings.
+const BACKED_UP: &[&str] = &["snippets.json", "personal-dictionary.txt", "upload-queue.json"];
+
+static SCHEDULER: LazyLock<Scheduler> = LazyLock::new(|| Scheduler {
+    options: RwLock::default(),
+    last_run: Mutex::default(),
+    running: tokio::sync::Mutex::new(()),
+});
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum MaintenanceTask {
+    Reindex,
+    PruneSnapshots,
+    EvictImageCache,
+    Backup,
+}
+
+const TASKS: [MaintenanceTask; 4] = [
+    MaintenanceTask::Reindex,
+    MaintenanceTask::PruneSnapshots,
+    MaintenanceTask::EvictImageCache,
+    MaintenanceTask::Backup,
+];
+
+impl MaintenanceTask {
+    fn default_minutes(self) -> u64 {
+        match self {
+            MaintenanceTask::Reindex => 30,
+            MaintenanceTask::EvictImageCache => 6 * 60,
+            MaintenanceTask::PruneSnapshots | MaintenanceTask::Backup => 24 * 60,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ScheduleOptions {
+    /// Minutes between runs by task, 0 to turn it off; tasks that aren't
+    /// listed run at their default interval.
+    intervals: HashMap<MaintenanceTask, u64>,
+    /// Runs tasks on battery too, unless background work is held back on
+    /// battery anyway: see [`crate::power::PowerOptions`].
+    on_battery: bool,
+}
+
+impl ScheduleOptions {
+    fn interval(&self, task: MaintenanceTask) -> Option<Duration> {
+        let minutes = self.intervals.get(&task).copied().unwrap_or_else(|| task.default_minutes());
+        (minutes > 0).then(|| Duration::from_secs(minutes * 60))
+    }
+}
+
+struct Scheduler {
+    options: RwLock<ScheduleOptions>,
+    /// Tasks that haven't run yet count from startup.
+    last_run: Mutex<HashMap<MaintenanceTask, Instant>>,
+    /// Held while a task runs, so they run one at a time.
+    running: tokio::sync::Mutex<()>,
+}
+
+/// The task that's been waiting the longest, if it's time for one.
+fn due(started: Instant) -> Option<MaintenanceTask> {
+    let options = SCHEDULER.options.read().expect("scheduler lock poisoned").clone();
+    if !power::idle() || !workers::interactive_idle() {
+        return None;
+    }
+    let last_run = SCHEDULER.last_run.lock().expect("scheduler lock poisoned").clone();
+    let task = TASKS
+        .iter()
+        .filter_map(|&task| {
+            let since = last_run.get(&task).copied().unwrap_or(started).elapsed();
+            options.interval(task).filter(|&interval| since >= interval).map(|interval| (task, since - interval))
+        })
+        .max_by_key(|&(_, overdue)| overdue)
+        .map(|(task, _)| task)?;
+    (options.on_battery || !power::on_battery()).then_some(task)
+}
+
+fn backup(app: &AppHandle) -> Result<(), String> {
+    let data_dir = app.path().app_data_dir().map_err(|e| format!("app_data_dir: {e}"))?;
+    let backups = data_dir.join("backups");
+    let name = logs::now()
+        .format(format_description!("[year]-[month]-[day]_[hour]-[minute]-[second]"))
+        .map_err(|e| format!("format date: {e}"))?;
+    let dir = backups.join(name);
+    fs::create_dir_all(&dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    app.state::<Arc<Database>>().backup(&dir.join("emmm.sqlite"))?;
+    let settings = settings::path();
+    let files = BACKED_UP.iter().map(|name| data_dir.join(name)).chain(settings);
+    for file in files.filter(|file| file.exists()) {
+        let to = dir.join(file.file_name().unwrap_or_default());
+        fs::copy(&file, &to).map_err(|e| format!("copy {} to {}: {e}", file.display(), to.display()))?;
+    }
+    prune_backups(&backups);
+    Ok(())
+}
+
+/// Removes all but the newest [`BACKUPS`], which have the newest names.
+fn prune_backups(backups: &Path) {
+    let Ok(entries) = fs::read_dir(backups) else { return };
+    let mut dirs: Vec<_> = entries.filter_map(Result::ok).map(|e| e.path()).filter(|p| p.is_dir()).collect();
+    dirs.sort();
+    for dir in dirs.iter().rev().skip(BACKUPS) {
+        if let Err(e) = fs::remove_dir_all(dir) {
+            log::warn!("scheduler: remove {}: {e}", dir.display());
+        }
+    }
+}
+
+fn perform(app: &AppHandle, task: MaintenanceTask) -> Result<(), String> {
+    match task {
+        MaintenanceTask::Reindex => app.state::<Arc<WorkspaceIndex>>().refresh_all(),
+        MaintenanceTask::PruneSnapshots => {
+            app.state::<Bases>().prune();
+            Ok(())
+        }
+        MaintenanceTask::EvictImageCache => {
+            app.state::<ImageCache>().evict();
+            Ok(())
+        }
+        MaintenanceTask::Backup => backup(app),
+    }
+}
+
+async fn run(app: AppHandle, task: MaintenanceTask, priority: Priority) -> Result<(), String> {
+    let _running = SCHEDULER.running.lock().await;
+    log::info!("scheduler: {task:?} start");
+    // a task that failed isn't retried until its next time
+    SCHEDULER.last_run.lock().expect("scheduler lock poisoned").insert(task, Instant::now());
+    let job = jobs::start(JobKind::Maintenance, priority, format!("{task:?}"));
+    let result = workers::run(priority, move || job.run(|| perform(&app, task))).await;
+    match result {
+        Ok(Ok(())) => {
+            log::info!("scheduler: {task:?} done");
+            Ok(())
+        }
+        Ok(Err(e)) => Err(format!("{task:?}: {e}")),
+        Err(e) => Err(format!("workers::run: {e}")),
+    }
+}
+
+/// Runs the tasks as they're due from now on.
+pub fn start(app: AppHandle) {
+    let started = Instant::now();
+    tauri::async_runtime::spawn(async move {
+        let mut ticks = tokio::time::interval(TICK);
+        loop {
+            ticks.tick().await;
+            let Some(task) = due(started) else { continue };
+            if let Err(e) = run(app.clone(), task, Priority::Background).await {
+                log::warn!("scheduler: {e}");
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub fn configure_scheduler(options: ScheduleOptions) {
+    log::info!("configure_scheduler: on battery {}", options.on_battery);
+    *SCHEDULER.options.write().expect("scheduler lock poisoned") = options;
+}
+
+/// Runs `task` now, whether the user is idle or not and on battery or not,
+/// after any task already running.
+#[tauri::command]
+pub async fn run_now(task: MaintenanceTask, app: AppHandle) -> Result<(), BackendError> {
+    Ok(run(app, task, Priority::Interactive).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_default_per_task_and_zero_turns_off() {
+        let options = ScheduleOptions {
+            intervals: HashMap::from([(MaintenanceTask::Backup, 0), (MaintenanceTask::Reindex, 5)]),
+            on_battery: false,
+        };
+        assert_eq!(options.interval(MaintenanceTask::Reindex), Some(Duration::from_secs(5 * 60)));
+        assert_eq!(options.interval(MaintenanceTask::EvictImageCache), Some(Duration::from_secs(6 * 60 * 60)));
+        assert_eq!(options.interval(MaintenanceTask::Backup), None);
+    }
+
+    #[test]
+    fn only_the_newest_backups_are_kept() {
+        let backups = std::env::temp_dir().join(format!("emmm-backups-test-{}", std::process::id()));
+        fs::remove_dir_all(&backups).ok();
+        for day in 1..=9 {
+            fs::create_dir_all(backups.join(format!("2025-01-0{day}_12-00-00"))).expect("create a backup");
+        }
+        fs::write(backups.join("notes.txt"), "").expect("write a file");
+        prune_backups(&backups);
+        let mut left: Vec<_> = fs::read_dir(&backups)
+            .expect("list the backups")
+            .map(|e| e.expect("a backup").file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        let expected: Vec<_> =
+            (3..=9).map(|day| format!("2025-01-0{day}_12-00-00")).chain(["notes.txt".to_owned()]).collect();
+        assert_eq!(left, expected);
+        fs::remove_dir_all(&backups).ok();
+    }
+}
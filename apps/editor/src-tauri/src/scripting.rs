@@ -0,0 +1,316 @@
+//! Automation scripts in Rhai, registered in the settings with the
+//! workspace they work on: run by hand with [`run_script`], or after every
+//! save of one of the workspace's documents, e.g. to regenerate an index
+//! note.
+//!
+//! Scripts only get what their capabilities allow:
+//! - `read`: `read(path)` and `documents()`;
+//! - `write`: `write(path, text)`;
+//! - `export`: `export_html(path, target)`;
+//! - `compress`: `compress_image(path, target, max_size)`.
+//!
+//! Paths are relative to the workspace and can't leave it. `print` and
+//! `debug` go to the output; `workspace` is the workspace's path and
+//! `saved` the document that was saved, or `()` when run by hand. Scripts
+//! can't `import` modules, which would read any file. A script is stopped
+//! after [`TIMEOUT`].
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rhai::{Dynamic, Engine, EvalAltResult, Scope};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compress_file,
+    error::BackendError,
+    jobs::{self, JobKind},
+    markdown::{self, RenderOptions},
+    settings,
+    workers::{self, Priority},
+    workspace::{self, write_atomic},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_OPERATIONS: u64 = 100_000_000;
+const MAX_CALL_LEVELS: usize = 64;
+const MAX_STRING_SIZE: usize = 16 << 20;
+const MAX_ARRAY_SIZE: usize = 100_000;
+/// Lines of output kept; later ones are dropped.
+const MAX_OUTPUT_LINES: usize = 1000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Capability {
+    Read,
+    Write,
+    Export,
+    Compress,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Script {
+    name: String,
+    /// Of the `.rhai` file.
+    path: String,
+    /// The workspace it works on.
+    workspace: String,
+    #[serde(default)]
+    capabilities: Vec<Capability>,
+    /// Runs after every save of a document in the workspace.
+    #[serde(default)]
+    on_save: bool,
+}
+
+impl Script {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("a script needs a name".to_owned());
+        }
+        if self.path.is_empty() || self.workspace.is_empty() {
+            return Err(format!("script {} needs a path and a workspace", self.name));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptRun {
+    /// What the script printed.
+    output: Vec<String>,
+    /// The value of its last expression.
+    result: String,
+    millis: u64,
+}
+
+/// `relative` inside `root`; `..`, absolute paths and the like are refused,
+/// and so are symlinks out of it. A file that doesn't exist yet is checked
+/// by the folder it would be in.
+fn resolve(root: &Path, relative: &str) -> Result<PathBuf, String> {
+    let path = Path::new(relative);
+    let outside = || format!("{relative} is outside the workspace");
+    if !path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return Err(outside());
+    }
+    let root = root.canonicalize().map_err(|e| format!("workspace {}: {e}", root.display()))?;
+    let path = root.join(path);
+    let resolved = match path.canonicalize() {
+        Ok(resolved) => resolved,
+        Err(_) => {
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else { return Err(outside()) };
+            parent.canonicalize().map_err(|e| format!("{relative}: {e}"))?.join(name)
+        }
+    };
+    if resolved.starts_with(&root) { Ok(resolved) } else { Err(outside()) }
+}
+
+fn require(script: &Script, capability: Capability) -> Result<(), Box<EvalAltResult>> {
+    if script.capabilities.contains(&capability) {
+        Ok(())
+    } else {
+        Err(format!("script {} lacks the {capability:?} capability", script.name).into())
+    }
+}
+
+/// An engine with the functions and limits for `script`, and the output it
+/// prints to.
+fn engine(script: &Arc<Script>, root: &Arc<PathBuf>) -> (Engine, Arc<Mutex<Vec<String>>>) {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new())
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_ARRAY_SIZE)
+        .set_max_map_size(MAX_ARRAY_SIZE)
+        .disable_symbol("eval");
+
+    let started = Instant::now();
+    engine.on_progress(move |_| {
+        if started.elapsed() > TIMEOUT {
+            Some(format!("timed out after {TIMEOUT:?}").into())
+        } else {
+            jobs::checkpoint().err().map(Dynamic::from)
+        }
+    });
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let print = output.clone();
+    let push = move |line: String| {
+        let mut output = print.lock().expect("script output lock poisoned");
+        if output.len() < MAX_OUTPUT_LINES {
+            output.push(line);
+        }
+    };
+    let debug = push.clone();
+    engine.on_print(move |text| push(text.to_owned()));
+    engine.on_debug(move |text, _, position| debug(format!("{position:?}: {text}")));
+
+    let (s, r) = (script.clone(), root.clone());
+    engine.register_fn("read", move |path: &str| -> Result<String, Box<EvalAltResult>> {
+        require(&s, Capability::Read)?;
+        let path = resolve(&r, path)?;
+        Ok(fs::read_to_string(&path).map_err(|e| format!("read {}: {e}", path.display()))?)
+    });
+    let (s, r) = (script.clone(), root.clone());
+    engine.register_fn("documents", move || -> Result<rhai::Array, Box<EvalAltResult>> {
+        require(&s, Capability::Read)?;
+        let documents = workspace::documents(&r)?;
+        Ok(documents
+            .iter()
+            .filter_map(|path| path.strip_prefix(r.as_path()).ok())
+            .map(|path| path.to_string_lossy().replace('\\', "/").into())
+            .collect())
+    });
+    let (s, r) = (script.clone(), root.clone());
+    engine.register_fn("write", move |path: &str, text: &str| -> Result<(), Box<EvalAltResult>> {
+        require(&s, Capability::Write)?;
+        Ok(write_atomic(&resolve(&r, path)?, text.as_bytes())?)
+    });
+    let (s, r) = (script.clone(), root.clone());
+    engine.register_fn("export_html", move |path: &str, target: &str| -> Result<(), Box<EvalAltResult>> {
+        require(&s, Capability::Export)?;
+        let path = resolve(&r, path)?;
+        let markdown = fs::read_to_string(&path).map_err(|e| format!("read {}: {e}", path.display()))?;
+        let options = RenderOptions { base_dir: path.parent(), ..RenderOptions::default() };
+        Ok(write_atomic(&resolve(&r, target)?, markdown::to_html(&markdown, &options).as_bytes())?)
+    });
+    let (s, r) = (script.clone(), root.clone());
+    engine.register_fn(
+        "compress_image",
+        move |path: &str, target: &str, max_size: i64| -> Result<(), Box<EvalAltResult>> {
+            require(&s, Capability::Compress)?;
+            let max_size = usize::try_from(max_size).map_err(|_| format!("invalid size {max_size}"))?;
+            let source = resolve(&r, path)?;
+            let compressed = compress_file(&source.to_string_lossy(), max_size)?;
+            Ok(write_atomic(&resolve(&r, target)?, &compressed)?)
+        },
+    );
+    (engine, output)
+}
+
+fn run(script: Script, saved: Option<String>) -> Result<ScriptRun, String> {
+    let started = Instant::now();
+    let source = fs::read_to_string(&script.path).map_err(|e| format!("read {}: {e}", script.path))?;
+    let root = Arc::new(PathBuf::from(&script.workspace));
+    let mut scope = Scope::new();
+    scope.push_constant("workspace", script.workspace.clone());
+    scope.push_constant("saved", saved.map_or(Dynamic::UNIT, Dynamic::from));
+    let (engine, output) = engine(&Arc::new(script), &root);
+    let result = engine.eval_with_scope::<Dynamic>(&mut scope, &source).map_err(|e| match *e {
+        // stopped by on_progress, which says why
+        EvalAltResult::ErrorTerminated(reason, position) => format!("{reason} ({position})"),
+        e => e.to_string(),
+    })?;
+    let output = std::mem::take(&mut *output.lock().expect("script output lock poisoned"));
+    let millis = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    Ok(ScriptRun { output, result: if result.is_unit() { String::new() } else { result.to_string() }, millis })
+}
+
+/// Runs the scripts that run on saves of documents in their workspace, in
+/// the background; failures are only logged.
+pub fn on_save(path: &str) {
+    let scripts = settings::get().scripts;
+    let path = Path::new(path);
+    for script in scripts.into_iter().filter(|script| script.on_save) {
+        let Ok(relative) = path.strip_prefix(&script.workspace) else { continue };
+        let saved = relative.to_string_lossy().replace('\\', "/");
+        tauri::async_runtime::spawn(async move {
+            let name = script.name.clone();
+            let job = jobs::start(JobKind::Script, Priority::Background, &name);
+            let result = workers::run(Priority::Background, move || job.run(|| run(script, Some(saved)))).await;
+            match result {
+                Ok(Ok(run)) => log::info!("scripting: {name} ran on save in {}ms", run.millis),
+                Ok(Err(e)) => log::warn!("scripting: {name}: {e}"),
+                Err(e) => log::warn!("scripting: workers::run: {e}"),
+            }
+        });
+    }
+}
+
+/// Runs the script `name` from the settings as a job and returns what it
+/// printed and its result.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn run_script(name: String) -> Result<ScriptRun, BackendError> {
+    log::info!("run_script start: {name}");
+    let script = settings::get()
+        .scripts
+        .into_iter()
+        .find(|script| script.name == name)
+        .ok_or_else(|| format!("no script named {name}"))?;
+    let job = jobs::start(JobKind::Script, Priority::Interactive, &name);
+    let result = workers::run(Priority::Interactive, move || job.run(|| run(script, None))).await;
+    match result {
+        Ok(Ok(run)) => {
+            log::info!("run_script done");
+            Ok(run)
+        }
+        Ok(Err(e)) => Err(format!("run_script task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn paths_stay_in_the_workspace() {
+        let base = std::env::temp_dir().join(format!("emmm-scripting-{}", std::process::id()));
+        let root = base.join("workspace");
+        fs::create_dir_all(root.join("notes")).expect("a workspace");
+        fs::write(base.join("secret.md"), "").expect("a file outside");
+        let root = root.canonicalize().expect("a path");
+
+        assert_eq!(resolve(&root, "notes/a.md"), Ok(root.join("notes/a.md")));
+        assert_eq!(resolve(&root, "./b.md"), Ok(root.join("b.md")));
+        assert!(resolve(&root, "../secret.md").is_err());
+        assert!(resolve(&root, "/etc/passwd").is_err());
+        assert!(resolve(&root, "missing/a.md").is_err());
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&base, root.join("out")).expect("a symlink");
+            assert!(resolve(&root, "out/secret.md").is_err());
+            assert!(resolve(&root, "out/new.md").is_err());
+        }
+        fs::remove_dir_all(&base).expect("cleaned up");
+    }
+
+    #[test]
+    fn scripts_cannot_import_modules() {
+        let base = std::env::temp_dir().join(format!("emmm-scripting-import-{}", std::process::id()));
+        fs::create_dir_all(&base).expect("a workspace");
+        fs::write(base.join("secret.rhai"), "export const SECRET = 42;").expect("a module");
+        let script = |path: &str, source: &str| {
+            let path = base.join(path);
+            fs::write(&path, source).expect("a script");
+            Script {
+                name: "test".to_owned(),
+                path: path.to_string_lossy().into_owned(),
+                workspace: base.to_string_lossy().into_owned(),
+                capabilities: Vec::new(),
+                on_save: false,
+            }
+        };
+
+        let plain = run(script("plain.rhai", "40 + 2"), None).expect("the script runs");
+        assert_eq!(plain.result, "42");
+        let module = base.join("secret").to_string_lossy().replace('\\', "/");
+        let source = format!("import \"{module}\" as secret; secret::SECRET");
+        let Err(error) = run(script("import.rhai", &source), None) else { panic!("the import succeeded") };
+        assert!(error.contains("secret"), "{error}");
+        fs::remove_dir_all(&base).expect("cleaned up");
+    }
+}
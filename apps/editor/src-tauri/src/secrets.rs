@@ -0,0 +1,73 @@
+//! Credentials kept in the platform keychain instead of config files:
+//! API keys, tokens for uploaders and AI providers, sync passphrases.
+//! Settings only hold the name of the entry.
+
+use crate::error::BackendError;
+
+const SERVICE: &str = "com.kfgui.app";
+
+fn entry(name: &str) -> Result<keyring::Entry, String> {
+    if name.trim().is_empty() {
+        return Err("a secret name is needed".to_owned());
+    }
+    keyring::Entry::new(SERVICE, name).map_err(|e| format!("keychain entry {name}: {e}"))
+}
+
+/// Looks up the secret stored as `name`; `None` if there is none.
+pub fn read(name: &str) -> Result<Option<String>, String> {
+    match entry(name)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("keychain entry {name}: {e}")),
+    }
+}
+
+pub fn write(name: &str, secret: &str) -> Result<(), String> {
+    entry(name)?.set_password(secret).map_err(|e| format!("keychain entry {name}: {e}"))
+}
+
+/// Removes the secret stored as `name`, if there is one.
+pub fn remove(name: &str) -> Result<(), String> {
+    match entry(name)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(format!("keychain entry {name}: {e}")),
+    }
+}
+
+/// [`read`] for async code, failing if the secret isn't stored. The
+/// keychain backends block, so this runs on the blocking pool.
+pub async fn require_secret(name: String) -> Result<String, String> {
+    let result = tokio::task::spawn_blocking(move || {
+        read(&name)?.ok_or_else(|| format!("no secret stored as {name}"))
+    }).await;
+    match result {
+        Ok(result) => result,
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}")),
+    }
+}
+
+/// Stores `value` in the keychain as `name`, replacing what was there;
+/// removes the entry if `value` is empty.
+#[tauri::command]
+pub async fn store_secret(name: String, value: String) -> Result<(), BackendError> {
+    log::info!("store_secret: {name}");
+    let result = tokio::task::spawn_blocking(move || {
+        if value.is_empty() { remove(&name) } else { write(&name, &value) }
+    }).await;
+    match result {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(format!("store_secret task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// The secret stored as `name`; `None` if there is none.
+#[tauri::command]
+pub async fn get_secret(name: String) -> Result<Option<String>, BackendError> {
+    let result = tokio::task::spawn_blocking(move || read(&name)).await;
+    match result {
+        Ok(Ok(secret)) => Ok(secret),
+        Ok(Err(e)) => Err(format!("get_secret task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
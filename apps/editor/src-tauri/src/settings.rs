@@ -0,0 +1,367 @@
+//! The app's settings, in `settings.json` in the config directory, so
+//! that every window and the backend itself see the same values. Every
+//! value is checked against [`Settings`] before it's saved, and files from
+//! older versions are brought up to date by [`MIGRATIONS`] when loaded.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tauri::Url;
+
+use crate::{
+    ai::AiProvider, capture::CaptureOptions, error::BackendError, formatter::FormatterConfig, git::AutoCommitOptions,
+    plugins::Plugin, power::PowerOptions, scheduler::ScheduleOptions, scripting::Script, secrets,
+    updater::UpdateChannel, webhooks::Webhook, workspace::write_atomic,
+};
+
+const FILE: &str = "settings.json";
+/// What the frontend wrote itself before there was a version; the first
+/// migration imports it.
+const LEGACY_FILE: &str = "config.json";
+/// The keychain entry of the WeChat app secret.
+pub const WEIXIN_SECRET: &str = "weixin-app-secret";
+
+/// Brings a file from the version of its index in [`MIGRATIONS`] to the
+/// next one.
+type Migration = fn(&Path, &mut Map<String, Value>);
+
+/// The current version is their count.
+const MIGRATIONS: &[Migration] = &[import_legacy, move_weixin_secret];
+
+static STORE: RwLock<Option<Store>> = RwLock::new(None);
+
+struct Store {
+    path: PathBuf,
+    settings: Settings,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub weixin_app_id: String,
+    /// The secret is in the keychain, as [`WEIXIN_SECRET`].
+    pub weixin_small_image_cache: Vec<(String, String)>,
+    pub weixin_asset_cache: Vec<(String, String)>,
+
+    /// For backend requests: image downloads, exports, uploads.
+    pub network_proxy: String,
+    pub network_no_proxy: String,
+    pub network_ca_certificates: Vec<String>,
+
+    pub webhooks: Vec<Webhook>,
+
+    pub plugins: Vec<Plugin>,
+    pub scripts: Vec<Script>,
+
+    /// The public LanguageTool server if empty.
+    pub language_tool_server: String,
+    pub language_tool_language: String,
+
+    pub ai_provider: Option<AiProvider>,
+
+    /// For code blocks, by language, lowercase, instead of the built-in
+    /// ones.
+    pub formatters: BTreeMap<String, FormatterConfig>,
+
+    /// The whisper.cpp model for dictation.
+    pub dictation_model_path: String,
+    /// The captioning model's folder, for alt text.
+    pub caption_model_dir: String,
+    /// The U²-Net model for background removal.
+    pub background_model_path: String,
+    /// ONNX Runtime's library, for the local models; next to the app if
+    /// empty.
+    pub onnx_runtime_path: String,
+
+    pub auto_commit: AutoCommitOptions,
+
+    pub respect_gitignore: bool,
+
+    /// Every core but one if 0.
+    pub max_parallelism: usize,
+    pub collect_metrics: bool,
+    pub schedule: ScheduleOptions,
+    pub power: PowerOptions,
+    pub capture: CaptureOptions,
+    /// The [`crate::flags`] turned on or off by the user, by name.
+    pub flags: BTreeMap<String, bool>,
+    /// Of the backend's messages, like `zh-CN`; the system's if empty.
+    pub locale: String,
+    pub update_channel: UpdateChannel,
+    /// [`crate::updater::DEFAULT_FEED`] if empty.
+    pub update_feed: String,
+
+    pub temp_source: String,
+    pub temp_library: String,
+    pub temp_stylesheet: String,
+
+    pub window_w: f64,
+    pub window_h: f64,
+    pub size_left: f64,
+    pub size_right: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            weixin_app_id: String::new(),
+            weixin_small_image_cache: Vec::new(),
+            weixin_asset_cache: Vec::new(),
+            network_proxy: String::new(),
+            network_no_proxy: String::new(),
+            network_ca_certificates: Vec::new(),
+            webhooks: Vec::new(),
+            plugins: Vec::new(),
+            scripts: Vec::new(),
+            language_tool_server: String::new(),
+            language_tool_language: "auto".to_owned(),
+            ai_provider: None,
+            formatters: BTreeMap::new(),
+            dictation_model_path: String::new(),
+            caption_model_dir: String::new(),
+            background_model_path: String::new(),
+            onnx_runtime_path: String::new(),
+            auto_commit: AutoCommitOptions::default(),
+            respect_gitignore: true,
+            max_parallelism: 0,
+            collect_metrics: false,
+            schedule: ScheduleOptions::default(),
+            power: PowerOptions::default(),
+            capture: CaptureOptions::default(),
+            flags: BTreeMap::new(),
+            locale: String::new(),
+            update_channel: UpdateChannel::Stable,
+            update_feed: String::new(),
+            temp_source: String::new(),
+            temp_library: String::new(),
+            temp_stylesheet: String::new(),
+            window_w: 1400.0,
+            window_h: 900.0,
+            size_left: 250.0,
+            size_right: 350.0,
+        }
+    }
+}
+
+fn check_url(name: &str, url: &str) -> Result<(), String> {
+    if url.is_empty() {
+        return Ok(());
+    }
+    let parsed = Url::parse(url).map_err(|e| format!("invalid {name} {url}: {e}"))?;
+    if parsed.host().is_none() {
+        return Err(format!("invalid {name} {url}: no host"));
+    }
+    Ok(())
+}
+
+impl Settings {
+    fn validate(&self) -> Result<(), String> {
+        check_url("networkProxy", &self.network_proxy)?;
+        check_url("languageToolServer", &self.language_tool_server)?;
+        check_url("updateFeed", &self.update_feed.replace("{{channel}}", "stable"))?;
+        for webhook in &self.webhooks {
+            webhook.validate()?;
+        }
+        for (i, plugin) in self.plugins.iter().enumerate() {
+            plugin.validate()?;
+            if self.plugins[..i].iter().any(|other| other.name() == plugin.name()) {
+                return Err(format!("two plugins are named {}", plugin.name()));
+            }
+        }
+        for (i, script) in self.scripts.iter().enumerate() {
+            script.validate()?;
+            if self.scripts[..i].iter().any(|other| other.name() == script.name()) {
+                return Err(format!("two scripts are named {}", script.name()));
+            }
+        }
+        let sizes = [self.window_w, self.window_h, self.size_left, self.size_right];
+        if sizes.iter().any(|&size| !size.is_finite() || size < 0.0) {
+            return Err("invalid size: must be a positive number".to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// The settings the frontend kept in `config.json`, which had the same
+/// keys.
+fn import_legacy(dir: &Path, file: &mut Map<String, Value>) {
+    let path = dir.join(LEGACY_FILE);
+    let Ok(data) = fs::read(&path) else { return };
+    match serde_json::from_slice::<Map<String, Value>>(&data) {
+        Ok(legacy) => {
+            log::info!("settings: imported {}", path.display());
+            file.extend(legacy);
+        }
+        Err(e) => log::warn!("settings: {}: {e}", path.display()),
+    }
+}
+
+/// The WeChat app secret used to be kept in the file. If the keychain
+/// can't take it, it's dropped all the same and has to be entered again.
+fn move_weixin_secret(_: &Path, file: &mut Map<String, Value>) {
+    let Some(Value::String(secret)) = file.get("weixinAppSecret") else { return };
+    if !secret.is_empty() {
+        if let Err(e) = secrets::write(WEIXIN_SECRET, secret) {
+            log::warn!("settings: weixinAppSecret dropped: {e}");
+        }
+    }
+    file.remove("weixinAppSecret");
+}
+
+/// Brings `file` up to the current version.
+fn migrate(dir: &Path, file: &mut Map<String, Value>) {
+    let version = file.get("version").and_then(Value::as_u64).unwrap_or(0);
+    let version = usize::try_from(version).unwrap_or(usize::MAX);
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        log::info!("settings: migrating from version {from}");
+        migration(dir, file);
+    }
+    file.insert("version".to_owned(), MIGRATIONS.len().into());
+}
+
+/// Moves the file at `path` out of the way, so it isn't overwritten by the
+/// defaults.
+fn set_aside(path: &Path, error: &str) {
+    let aside = path.with_extension("json.invalid");
+    log::warn!("settings: {}: {error}, moving it to {}", path.display(), aside.display());
+    if let Err(e) = fs::rename(path, &aside) {
+        log::warn!("settings: rename {}: {e}", path.display());
+    }
+}
+
+/// Reads the settings in `dir`, migrated; the defaults if there are none
+/// yet. A file that can't be used is set aside rather than overwritten.
+pub fn load(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+    let path = dir.join(FILE);
+    let mut file = match fs::read(&path) {
+        Ok(data) => serde_json::from_slice::<Map<String, Value>>(&data).unwrap_or_else(|e| {
+            set_aside(&path, &e.to_string());
+            Map::new()
+        }),
+        Err(_) => Map::new(),
+    };
+    let current = file.get("version").and_then(Value::as_u64) == Some(MIGRATIONS.len() as u64);
+    migrate(dir, &mut file);
+    let settings = serde_json::from_value::<Settings>(Value::Object(file))
+        .map_err(|e| e.to_string())
+        .and_then(|settings| settings.validate().map(|()| settings));
+    let store = match settings {
+        Ok(settings) => Store { path, settings },
+        Err(e) => {
+            set_aside(&path, &e);
+            Store { path, settings: Settings::default() }
+        }
+    };
+    if !current {
+        save(&store)?;
+    }
+    *STORE.write().expect("settings lock poisoned") = Some(store);
+    Ok(())
+}
+
+fn save(store: &Store) -> Result<(), String> {
+    let Value::Object(mut file) = serde_json::to_value(&store.settings).expect("settings serialize") else {
+        unreachable!("settings serialize to an object")
+    };
+    file.insert("version".to_owned(), MIGRATIONS.len().into());
+    let data = serde_json::to_vec_pretty(&file).expect("settings serialize");
+    write_atomic(&store.path, &data)
+}
+
+/// Where the settings are saved, once they're loaded.
+pub fn path() -> Option<PathBuf> {
+    STORE.read().expect("settings lock poisoned").as_ref().map(|store| store.path.clone())
+}
+
+/// The current settings, for backend code.
+pub fn get() -> Settings {
+    STORE.read().expect("settings lock poisoned").as_ref().map(|store| store.settings.clone()).unwrap_or_default()
+}
+
+/// Replaces `key` with `value`, or its default if `None`, if the result
+/// is valid.
+pub fn update(key: &str, value: Option<Value>) -> Result<Settings, String> {
+    let mut guard = STORE.write().expect("settings lock poisoned");
+    let store = guard.as_mut().ok_or_else(|| "settings aren't loaded".to_owned())?;
+    let Value::Object(mut object) = serde_json::to_value(&store.settings).expect("settings serialize") else {
+        unreachable!("settings serialize to an object")
+    };
+    if !object.contains_key(key) {
+        return Err(format!("unknown setting {key}"));
+    }
+    match value {
+        Some(value) => object.insert(key.to_owned(), value),
+        None => object.remove(key),
+    };
+    let settings: Settings =
+        serde_json::from_value(Value::Object(object)).map_err(|e| format!("invalid {key}: {e}"))?;
+    settings.validate()?;
+    let previous = std::mem::replace(&mut store.settings, settings);
+    if let Err(e) = save(store) {
+        store.settings = previous;
+        return Err(e);
+    }
+    Ok(store.settings.clone())
+}
+
+#[tauri::command]
+pub fn get_settings() -> Settings {
+    get()
+}
+
+/// Sets `key` to `value` and saves; returns all the settings. Nothing
+/// changes if the value is invalid.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn set_setting(key: String, value: Value) -> Result<Settings, BackendError> {
+    log::info!("set_setting: {key}");
+    Ok(update(&key, Some(value))?)
+}
+
+/// Puts `key` back to its default and saves; returns all the settings.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn reset_setting(key: String) -> Result<Settings, BackendError> {
+    log::info!("reset_setting: {key}");
+    Ok(update(&key, None)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_migrated_checked_and_set_aside() {
+        let dir = std::env::temp_dir().join(format!("emmm-settings-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).expect("create the config folder");
+        fs::write(dir.join(LEGACY_FILE), r#"{"windowW": 1000, "maxParallelism": 3}"#).expect("write the legacy file");
+        load(&dir).expect("load the settings");
+        assert_eq!((get().window_w, get().max_parallelism), (1000.0, 3));
+        let saved: Value =
+            serde_json::from_slice(&fs::read(dir.join(FILE)).expect("read the settings")).expect("parse the settings");
+        assert_eq!(saved["version"], MIGRATIONS.len());
+
+        assert!(update("maxParallelism", Some("x".into())).is_err());
+        assert!(update("networkProxy", Some("no host".into())).is_err());
+        assert!(update("windowH", Some((-1.0).into())).is_err());
+        assert!(update("bogus", Some(1.into())).is_err());
+        assert_eq!(update("windowH", Some(700.5.into())).expect("set the height").window_h, 700.5);
+        assert_eq!(update("windowW", None).expect("reset the width").window_w, 1400.0);
+        load(&dir).expect("load the settings again");
+        assert_eq!((get().window_w, get().window_h, get().max_parallelism), (1400.0, 700.5, 3));
+
+        fs::write(dir.join(FILE), r#"{"version": 2, "windowW": -5}"#).expect("write invalid settings");
+        load(&dir).expect("load invalid settings");
+        assert_eq!(get().window_w, 1400.0);
+        assert!(dir.join("settings.json.invalid").is_file());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,167 @@
+//! Quitting without losing work. A window that asked to with
+//! [`watch_shutdown`] gets to flush what it hasn't saved yet, like a
+//! pending autosave, before it closes. When the app exits, every window
+//! does; then the jobs are cancelled and given a moment to stop at their
+//! next checkpoint, so no compression output is cut off half written, and
+//! the metrics and the database are written out in full.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use tauri::{ipc::Channel, AppHandle, CloseRequestApi, ExitRequestApi, Manager, Window};
+use tokio::{sync::oneshot, time::Instant};
+
+use crate::{clipboard, db::Database, jobs, metrics};
+
+/// How long windows get to flush.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long jobs get to stop once cancelled.
+const JOBS_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase", tag = "event")]
+pub enum ShutdownEvent {
+    /// Save everything now and call [`flushed`].
+    Flush,
+}
+
+/// The windows to flush, by label.
+static FLUSHERS: LazyLock<Mutex<HashMap<String, Channel<ShutdownEvent>>>> = LazyLock::new(Mutex::default);
+/// The windows being flushed, by label.
+static FLUSHING: LazyLock<Mutex<HashMap<String, oneshot::Sender<()>>>> = LazyLock::new(Mutex::default);
+/// Set once exiting started, so it's done once.
+static EXITING: AtomicBool = AtomicBool::new(false);
+/// Set once everything's flushed, to let the exit through.
+static DONE: AtomicBool = AtomicBool::new(false);
+
+/// Asks the window `label` to flush; `None` if it didn't ask to be.
+fn request_flush(label: &str) -> Option<oneshot::Receiver<()>> {
+    let channel = FLUSHERS.lock().expect("shutdown lock poisoned").get(label).cloned()?;
+    let (sender, receiver) = oneshot::channel();
+    FLUSHING.lock().expect("shutdown lock poisoned").insert(label.to_owned(), sender);
+    if let Err(e) = channel.send(ShutdownEvent::Flush) {
+        log::warn!("shutdown: flush {label}: {e}");
+        FLUSHING.lock().expect("shutdown lock poisoned").remove(label);
+        return None;
+    }
+    Some(receiver)
+}
+
+async fn wait_flushed(label: &str, receiver: oneshot::Receiver<()>, deadline: Instant) {
+    if tokio::time::timeout_at(deadline, receiver).await.is_err() {
+        log::warn!("shutdown: {label} didn't flush in time");
+    }
+    FLUSHING.lock().expect("shutdown lock poisoned").remove(label);
+}
+
+/// Keeps a window that asked to be flushed open until it did.
+pub fn on_close_requested(window: &Window, api: &CloseRequestApi) {
+    let label = window.label().to_owned();
+    let Some(receiver) = request_flush(&label) else { return };
+    api.prevent_close();
+    let window = window.clone();
+    tauri::async_runtime::spawn(async move {
+        wait_flushed(&label, receiver, Instant::now() + FLUSH_TIMEOUT).await;
+        FLUSHERS.lock().expect("shutdown lock poisoned").remove(&label);
+        // unlike `close`, doesn't ask again
+        if let Err(e) = window.destroy() {
+            log::warn!("shutdown: close {label}: {e}");
+        }
+    });
+}
+
+/// Holds the exit back until [`shutdown`] is done, then lets it through.
+pub fn on_exit_requested(app: &AppHandle, code: Option<i32>, api: &ExitRequestApi) {
+    if DONE.load(Ordering::Relaxed) {
+        return;
+    }
+    api.prevent_exit();
+    if !EXITING.swap(true, Ordering::Relaxed) {
+        tauri::async_runtime::spawn(shutdown(app.clone(), code));
+    }
+}
+
+async fn shutdown(app: AppHandle, code: Option<i32>) {
+    finish(&app).await;
+    app.exit(code.unwrap_or(0));
+}
+
+/// Like quitting, flushes the windows and stops the jobs first, then
+/// starts the app again.
+pub async fn restart(app: AppHandle) {
+    if EXITING.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    finish(&app).await;
+    // goes through `on_exit_requested`, which lets it by now
+    app.request_restart();
+}
+
+async fn finish(app: &AppHandle) {
+    log::info!("shutdown start");
+    let deadline = Instant::now() + FLUSH_TIMEOUT;
+    let labels: Vec<String> = FLUSHERS.lock().expect("shutdown lock poisoned").keys().cloned().collect();
+    let waiting: Vec<_> =
+        labels.into_iter().filter_map(|label| request_flush(&label).map(|receiver| (label, receiver))).collect();
+    for (label, receiver) in waiting {
+        wait_flushed(&label, receiver, deadline).await;
+    }
+
+    clipboard::stop_clipboard_capture();
+    let cancelled = jobs::cancel_all();
+    if cancelled > 0 {
+        log::info!("shutdown: waiting for {cancelled} jobs");
+        let deadline = Instant::now() + JOBS_TIMEOUT;
+        while !jobs::list_jobs().is_empty() && Instant::now() < deadline {
+            tokio::time::sleep(POLL).await;
+        }
+        let left = jobs::list_jobs();
+        if !left.is_empty() {
+            log::warn!("shutdown: {} jobs still running", left.len());
+        }
+    }
+
+    let database = app.state::<Arc<Database>>().inner().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        metrics::flush();
+        database.checkpoint()
+    })
+    .await;
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::warn!("shutdown: {e}"),
+        Err(e) => log::warn!("shutdown: tokio::task::spawn_blocking: {e}"),
+    }
+    log::info!("shutdown done");
+    DONE.store(true, Ordering::Relaxed);
+}
+
+/// Sends [`ShutdownEvent::Flush`] to `channel` before the calling window
+/// closes or the app exits, which waits a few seconds for [`flushed`].
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn watch_shutdown(channel: Channel<ShutdownEvent>, window: Window) {
+    FLUSHERS.lock().expect("shutdown lock poisoned").insert(window.label().to_owned(), channel);
+}
+
+/// Tells that the calling window saved everything it had to.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn flushed(window: Window) {
+    if let Some(sender) = FLUSHING.lock().expect("shutdown lock poisoned").remove(window.label()) {
+        let _ = sender.send(());
+    }
+}
+
+/// Forgets the window `label`, once it's closed.
+pub fn forget(label: &str) {
+    FLUSHERS.lock().expect("shutdown lock poisoned").remove(label);
+}
@@ -0,0 +1,250 @@
+use std::{fs, path::Path, sync::OnceLock};
+
+use lol_html::{element, html_content::ContentType, rewrite_str, text, RewriteStrSettings};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+use crate::{
+    compress_data,
+    error::BackendError,
+    inline::{data_uri, mime_for_extension, resolve, Origin, Resource},
+    jobs::{self, JobKind},
+    metrics,
+    net::{self, reqwest::blocking::Client},
+    workers::{self, Priority},
+};
+
+const DEFAULT_IMAGE_MAX_SIZE: usize = 512 * 1024;
+const MAX_RESOURCE_SIZE: u64 = 20 * 1024 * 1024;
+const MAX_IMPORT_DEPTH: usize = 4;
+
+#[derive(Clone, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SingleFileOptions {
+    /// Each image is compressed to under this many bytes.
+    pub(crate) image_max_size: Option<usize>,
+    /// Also download and embed `http(s)` resources.
+    pub(crate) fetch_remote: bool,
+}
+
+fn url_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r#"url\(\s*(?:"([^"]*)"|'([^']*)'|([^'")\s]*))\s*\)"#)
+            .expect("valid regex")
+    })
+}
+
+fn import_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(concat!(
+            r#"@import\s+(?:url\(\s*)?(?:"([^"]*)"|'([^']*)'|([^'")\s;]+))"#,
+            r#"\s*\)?([^;]*);"#))
+            .expect("valid regex")
+    })
+}
+
+/// The reference in a match of the patterns above, whichever way it's quoted.
+fn reference<'a>(caps: &Captures<'a>) -> &'a str {
+    caps.get(1)
+        .or_else(|| caps.get(2))
+        .or_else(|| caps.get(3))
+        .map_or("", |m| m.as_str())
+}
+
+struct Inliner {
+    client: Option<Client>,
+    image_max_size: usize,
+}
+
+impl Inliner {
+    fn load(&self, resource: &Resource) -> Result<(Option<String>, Vec<u8>), String> {
+        match resource {
+            Resource::Local(path) => {
+                let data = fs::read(path)
+                    .map_err(|e| format!("fs::read {}: {e}", path.display()))?;
+                Ok((None, data))
+            }
+            Resource::Remote(url) => {
+                let client = self.client.as_ref()
+                    .ok_or("remote resources are not fetched".to_owned())?;
+                net::fetch_blocking(client, url.as_str(), MAX_RESOURCE_SIZE)
+            }
+        }
+    }
+
+    fn mime(resource: &Resource, content_type: Option<String>) -> String {
+        resource
+            .extension()
+            .and_then(|ext| mime_for_extension(&ext))
+            .map(str::to_owned)
+            .or(content_type)
+            .unwrap_or_else(|| "application/octet-stream".to_owned())
+    }
+
+    /// Embeds any resource, compressing it first if it's a raster image.
+    fn embed(&self, resource: &Resource) -> Result<String, String> {
+        let (content_type, data) = self.load(resource)?;
+        let mime = Self::mime(resource, content_type);
+        if mime.starts_with("image/") && mime != "image/svg+xml" {
+            let data = compress_data(data, self.image_max_size)?;
+            let format = image::guess_format(&data)
+                .map_err(|e| format!("guess_format: {e}"))?;
+            Ok(data_uri(format.to_mime_type(), &data))
+        } else {
+            Ok(data_uri(&mime, &data))
+        }
+    }
+
+    fn embed_or_keep(&self, reference: &str, origin: &Origin) -> String {
+        let Some(resource) = resolve(reference, origin) else {
+            return reference.to_owned();
+        };
+        self.embed(&resource).unwrap_or_else(|e| {
+            log::warn!("inline {resource}: {e}");
+            reference.to_owned()
+        })
+    }
+
+    fn stylesheet(&self, resource: &Resource, depth: usize) -> Result<String, String> {
+        let (_, data) = self.load(resource)?;
+        let css = String::from_utf8_lossy(&data);
+        Ok(self.css(&css, &resource.origin(), depth + 1))
+    }
+
+    /// Inlines `@import`ed stylesheets and everything referenced by `url()`.
+    fn css(&self, css: &str, origin: &Origin, depth: usize) -> String {
+        let imported = import_pattern().replace_all(css, |caps: &Captures| {
+            let whole = caps[0].to_owned();
+            if depth >= MAX_IMPORT_DEPTH {
+                return whole;
+            }
+            let Some(resource) = resolve(reference(caps), origin) else {
+                return whole;
+            };
+            match self.stylesheet(&resource, depth) {
+                Ok(sheet) => {
+                    let media = caps.get(4).map_or("", |m| m.as_str()).trim();
+                    if media.is_empty() {
+                        sheet
+                    } else {
+                        format!("@media {media} {{\n{sheet}\n}}")
+                    }
+                }
+                Err(e) => {
+                    log::warn!("inline {resource}: {e}");
+                    whole
+                }
+            }
+        });
+        url_pattern()
+            .replace_all(&imported, |caps: &Captures| {
+                format!("url(\"{}\")", self.embed_or_keep(reference(caps), origin))
+            })
+            .into_owned()
+    }
+
+    fn html(&self, html: &str, origin: &Origin) -> Result<String, String> {
+        let mut style_text = String::new();
+        let result = rewrite_str(html, RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("img[src]", |el| {
+                    if let Some(src) = el.get_attribute("src") {
+                        el.set_attribute("src", &self.embed_or_keep(&src, origin))?;
+                        // the other candidates would still point outside
+                        el.remove_attribute("srcset");
+                    }
+                    Ok(())
+                }),
+                element!("link[rel=stylesheet][href]", |el| {
+                    let Some(href) = el.get_attribute("href") else { return Ok(()) };
+                    let Some(resource) = resolve(&href, origin) else { return Ok(()) };
+                    match self.stylesheet(&resource, 0) {
+                        Ok(css) => {
+                            let css = css.replace("</style", "<\\/style");
+                            el.replace(&format!("<style>{css}</style>"), ContentType::Html);
+                        }
+                        Err(e) => log::warn!("inline {resource}: {e}"),
+                    }
+                    Ok(())
+                }),
+                element!("[style]", |el| {
+                    if let Some(style) = el.get_attribute("style") {
+                        el.set_attribute("style", &self.css(&style, origin, 0))?;
+                    }
+                    Ok(())
+                }),
+                // text can arrive in several chunks, so collect it first
+                text!("style", move |chunk| {
+                    style_text.push_str(chunk.as_str());
+                    if chunk.last_in_text_node() {
+                        let css = self.css(&style_text, origin, 0);
+                        chunk.replace(&css, ContentType::Html);
+                        style_text.clear();
+                    } else {
+                        chunk.remove();
+                    }
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        });
+        result.map_err(|e| format!("rewrite_str: {e}"))
+    }
+}
+
+/// Embeds the stylesheets, fonts and images (compressed) of `html` as data
+/// URIs. Relative references are resolved against `base_dir`.
+pub(crate) fn inline_html(html: &str, base_dir: Option<&Path>, options: &SingleFileOptions) -> Result<String, String> {
+    let inliner = Inliner {
+        client: options.fetch_remote.then(net::blocking_client).transpose()?,
+        image_max_size: options.image_max_size.unwrap_or(DEFAULT_IMAGE_MAX_SIZE),
+    };
+    inliner.html(html, &Origin::Local(base_dir.map(Path::to_path_buf)))
+}
+
+/// Produces one portable HTML file with [`inline_html`].
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn export_single_html(
+    html: String, base_dir: Option<String>, options: SingleFileOptions
+) -> Result<String, BackendError> {
+    log::info!("export_single_html start");
+    let job = jobs::start(JobKind::Export, Priority::Interactive, base_dir.clone().unwrap_or_default());
+    let timer = metrics::timer("export_single_html").size(html.len() as u64);
+    let result = workers::run(Priority::Interactive, move || {
+        job.run(|| inline_html(&html, base_dir.as_deref().map(Path::new), &options))
+    }).await;
+    timer.finish(matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(Ok(html)) => {
+            log::info!("export_single_html done");
+            Ok(html)
+        }
+        Ok(Err(e)) => Err(format!("export_single_html task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_stylesheets_and_their_urls_are_inlined() {
+        let dir = std::env::temp_dir().join(format!("emmm-single-file-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("css")).unwrap();
+        fs::write(dir.join("css/main.css"), "@font-face { src: url('../font.woff2') }").unwrap();
+        fs::write(dir.join("font.woff2"), b"wOF2").unwrap();
+
+        let html = r#"<link rel="stylesheet" href="css/main.css"><img src="missing.png" srcset="x.png 2x">"#;
+        let inlined = inline_html(html, Some(&dir), &SingleFileOptions::default()).unwrap();
+        assert!(inlined.starts_with("<style>@font-face { src: url(\"data:font/woff2;base64,d09GMg==\") }</style>"),
+            "{inlined}");
+        // kept, but without the candidates pointing outside
+        assert!(inlined.ends_with(r#"<img src="missing.png">"#), "{inlined}");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::{
+    compress_data,
+    error::BackendError,
+    feed,
+    frontmatter::Frontmatter,
+    inline,
+    jobs::{self, JobKind},
+    markdown::{self, escape, LinkKind, RenderOptions},
+    metrics,
+    webhooks::{self, WebhookEvent},
+    workers::{self, Priority},
+    workspace,
+};
+
+const DEFAULT_IMAGE_MAX_SIZE: usize = 1024 * 1024;
+
+const LIGHT_THEME: &str = r"
+body { max-width: 46em; margin: 0 auto; padding: 2em 1em; font-family: system-ui, sans-serif;
+       line-height: 1.6; color: #222; background: #fff; }
+a { color: #2a62b8; }
+nav { margin-bottom: 2em; font-size: 0.9em; }
+img { max-width: 100%; }
+pre { padding: 0.8em; overflow: auto; background: #f6f8fa; }
+.meta { color: #777; font-size: 0.9em; }
+.tags a { margin-right: 0.5em; }
+ul.pages { list-style: none; padding: 0; }
+ul.pages li { margin: 0.4em 0; }
+";
+
+const DARK_THEME: &str = r"
+body { max-width: 46em; margin: 0 auto; padding: 2em 1em; font-family: system-ui, sans-serif;
+       line-height: 1.6; color: #ddd; background: #1b1b1d; }
+a { color: #7fb0f5; }
+nav { margin-bottom: 2em; font-size: 0.9em; }
+img { max-width: 100%; }
+pre { padding: 0.8em; overflow: auto; background: #26262a; }
+.meta { color: #999; font-size: 0.9em; }
+.tags a { margin-right: 0.5em; }
+ul.pages { list-style: none; padding: 0; }
+ul.pages li { margin: 0.4em 0; }
+";
+
+#[derive(Clone, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SiteOptions {
+    title: Option<String>,
+    /// Enables the Atom feed, which needs absolute URLs.
+    base_url: Option<String>,
+    /// Images larger than this are recompressed when copied.
+    image_max_size: Option<usize>,
+    code_theme: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SiteSummary {
+    pages: usize,
+    assets: usize,
+    tags: usize,
+}
+
+/// The tag page's file name; tags of nothing but symbols, like `#++`, get
+/// one from their hash.
+fn tag_slug(tag: &str) -> String {
+    let slug = markdown::slug(tag);
+    if slug.is_empty() {
+        format!("tag-{}", &hex::encode(Sha256::digest(tag.as_bytes()))[..8])
+    } else {
+        slug
+    }
+}
+
+struct Page {
+    title: String,
+    date: Option<OffsetDateTime>,
+    tags: Vec<String>,
+    /// Relative to the output root, with forward slashes.
+    url: String,
+}
+
+fn html_path(relative: &str) -> String {
+    match relative.rsplit_once('.') {
+        Some((stem, _)) => format!("{stem}.html"),
+        None => format!("{relative}.html"),
+    }
+}
+
+/// `../` repeated enough to get from `url` back to the root.
+fn root_prefix(url: &str) -> String {
+    "../".repeat(url.matches('/').count())
+}
+
+fn format_date(date: OffsetDateTime) -> String {
+    date.format(format_description!("[year]-[month]-[day]")).unwrap_or_default()
+}
+
+pub(crate) fn page_html(site_title: &str, title: &str, root: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title} · {site}</title>\n\
+         <link rel=\"stylesheet\" href=\"{root}style.css\">\n</head>\n<body>\n\
+         <nav><a href=\"{root}index.html\">{site}</a></nav>\n<main>\n{body}\n</main>\n\
+         </body>\n</html>\n",
+        title = escape(title), site = escape(site_title))
+}
+
+fn page_list(pages: &[&Page], root: &str) -> String {
+    let mut out = String::from("<ul class=\"pages\">\n");
+    for page in pages {
+        let date = page
+            .date
+            .map(|d| format!("<span class=\"meta\">{}</span> ", format_date(d)))
+            .unwrap_or_default();
+        out.push_str(&format!(
+            "<li>{date}<a href=\"{root}{}\">{}</a></li>\n",
+            escape(&page.url), escape(&page.title)));
+    }
+    out.push_str("</ul>\n");
+    out
+}
+
+struct Exporter<'a> {
+    root: &'a Path,
+    /// `None` to keep the pages in `rendered` instead.
+    out_dir: Option<&'a Path>,
+    options: &'a SiteOptions,
+    /// Files to copy: source path and destination relative to `out_dir`.
+    assets: RefCell<BTreeMap<PathBuf, String>>,
+    rendered: RefCell<BTreeMap<String, Vec<u8>>>,
+}
+
+/// A site rendered in memory, for the preview server.
+pub(crate) struct RenderedSite {
+    /// Pages, stylesheet and feed by their path in the site.
+    pub files: BTreeMap<String, Vec<u8>>,
+    /// Where the assets are, by their path in the site.
+    pub assets: BTreeMap<String, PathBuf>,
+}
+
+impl Exporter<'_> {
+    /// Points a link at the exported page or asset. Documents become
+    /// `.html`; other local files are queued for copying, keeping their
+    /// place in the tree (or put under `assets/` if outside the workspace).
+    fn rewrite(&self, url: &str, kind: LinkKind, doc_dir: &Path, prefix: &str) -> Option<String> {
+        let path = match inline::resolve(url, &inline::Origin::Local(Some(doc_dir.to_owned())))? {
+            inline::Resource::Local(path) => path,
+            inline::Resource::Remote(_) => return None,
+        };
+        let fragment = url.find('#').map(|i| &url[i..]).unwrap_or("");
+        let inside = workspace::relative_url_path(self.root, &path)
+            .filter(|r| !r.starts_with(".."));
+
+        match inside {
+            Some(relative) if kind == LinkKind::Link && workspace::is_document(&path) =>
+                Some(format!("{prefix}{}{fragment}", html_path(&relative))),
+            Some(relative) => {
+                self.assets.borrow_mut().insert(path, relative.clone());
+                Some(format!("{prefix}{relative}{fragment}"))
+            }
+            None => {
+                let name = path.file_name()?.to_string_lossy().into_owned();
+                let mut assets = self.assets.borrow_mut();
+                let taken: HashSet<&String> = assets.values().collect();
+                let mut target = format!("assets/{name}");
+                let mut n = 1;
+                while taken.contains(&target) && assets.get(&path) != Some(&target) {
+                    target = format!("assets/{n}-{name}");
+                    n += 1;
+                }
+                let target = assets.entry(path).or_insert(target).clone();
+                Some(format!("{prefix}{target}"))
+            }
+        }
+    }
+
+    fn write(&self, relative: &str, contents: &[u8]) -> Result<(), String> {
+        let Some(out_dir) = self.out_dir else {
+            self.rendered.borrow_mut().insert(relative.to_owned(), contents.to_vec());
+            return Ok(());
+        };
+        let path = out_dir.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("create_dir_all {}: {e}", parent.display()))?;
+        }
+        fs::write(&path, contents).map_err(|e| format!("write {}: {e}", path.display()))
+    }
+
+    fn render_document(&self, path: &Path, site_title: &str) -> Result<Page, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("read {}: {e}", path.display()))?;
+        let (meta, body) = Frontmatter::of(&source);
+        let relative = workspace::relative_url_path(self.root, path).unwrap_or_default();
+        let url = html_path(&relative);
+        let prefix = root_prefix(&url);
+        let doc_dir = path.parent().unwrap_or(self.root);
+
+        let rewrite = |u: &str, kind: LinkKind| self.rewrite(u, kind, doc_dir, &prefix);
+        let content = markdown::render_document(body, &meta, &RenderOptions {
+            base_dir: Some(doc_dir),
+            code_theme: self.options.code_theme.as_deref(),
+            rewrite_url: Some(&rewrite),
+            ..Default::default()
+        })
+        .map_err(|e| format!("{}: {e}", path.display()))?
+        .into_html();
+
+        let page = Page {
+            title: meta.get("title").map_or_else(
+                || path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+                str::to_owned),
+            date: meta.date("date"),
+            tags: meta.list("tags"),
+            url,
+        };
+        let mut header = format!("<h1>{}</h1>\n", escape(&page.title));
+        if page.date.is_some() || !page.tags.is_empty() {
+            header.push_str("<p class=\"meta\">");
+            if let Some(date) = page.date {
+                header.push_str(&format_date(date));
+                header.push(' ');
+            }
+            header.push_str("<span class=\"tags\">");
+            for tag in &page.tags {
+                header.push_str(&format!(
+                    "<a href=\"{prefix}tags/{}.html\">#{}</a>", tag_slug(tag), escape(tag)));
+            }
+            header.push_str("</span></p>\n");
+        }
+        let html = page_html(site_title, &page.title, &prefix, &format!("{header}{content}"));
+        self.write(&page.url, html.as_bytes())?;
+        Ok(page)
+    }
+
+    fn copy_assets(&self) -> Result<usize, String> {
+        let max_size = self.options.image_max_size.unwrap_or(DEFAULT_IMAGE_MAX_SIZE);
+        let assets = self.assets.borrow();
+        if self.out_dir.is_none() {
+            return Ok(assets.len());
+        }
+        for (source, target) in assets.iter() {
+            let data = match fs::read(source) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("export_site: missing asset {}: {e}", source.display());
+                    continue;
+                }
+            };
+            let is_raster = image::guess_format(&data).is_ok();
+            let data = if is_raster && data.len() > max_size {
+                compress_data(data, max_size)?
+            } else {
+                data
+            };
+            self.write(target, &data)?;
+        }
+        Ok(assets.len())
+    }
+
+    fn export(&self, theme: &str) -> Result<SiteSummary, String> {
+        let site_title = self.options.title.clone().unwrap_or_else(|| {
+            self.root.file_name().unwrap_or_default().to_string_lossy().into_owned()
+        });
+
+        let mut pages = Vec::new();
+        let documents = workspace::documents(self.root)?;
+        for (i, path) in documents.iter().enumerate() {
+            jobs::checkpoint()?;
+            jobs::progress(i, documents.len(), &path.to_string_lossy());
+            // exporting into a folder inside the workspace must not pick up
+            // the previous export
+            if self.out_dir.is_some_and(|out_dir| path.starts_with(out_dir)) {
+                continue;
+            }
+            pages.push(self.render_document(path, &site_title)?);
+        }
+        pages.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.title.cmp(&b.title)));
+
+        let mut tags: BTreeMap<String, (String, Vec<&Page>)> = BTreeMap::new();
+        for page in &pages {
+            for tag in &page.tags {
+                tags.entry(tag_slug(tag))
+                    .or_insert_with(|| (tag.clone(), Vec::new()))
+                    .1
+                    .push(page);
+            }
+        }
+
+        let all: Vec<&Page> = pages.iter().collect();
+        let mut index = format!("<h1>{}</h1>\n{}", escape(&site_title), page_list(&all, ""));
+        if !tags.is_empty() {
+            index.push_str("<h2>Tags</h2>\n<p class=\"tags\">");
+            for (slug, (name, tagged)) in &tags {
+                index.push_str(&format!(
+                    "<a href=\"tags/{slug}.html\">#{}</a> ({}) ", escape(name), tagged.len()));
+            }
+            index.push_str("</p>\n");
+        }
+        self.write("index.html", page_html(&site_title, &site_title, "", &index).as_bytes())?;
+
+        for (slug, (name, tagged)) in &tags {
+            let body = format!("<h1>#{}</h1>\n{}", escape(name), page_list(tagged, "../"));
+            let html = page_html(&site_title, &format!("#{name}"), "../", &body);
+            self.write(&format!("tags/{slug}.html"), html.as_bytes())?;
+        }
+
+        let css = match theme {
+            "" | "light" => LIGHT_THEME.to_owned(),
+            "dark" => DARK_THEME.to_owned(),
+            path => fs::read_to_string(path).map_err(|e| format!("read theme {path}: {e}"))?,
+        };
+        self.write("style.css", css.as_bytes())?;
+
+        if let Some(base_url) = &self.options.base_url {
+            let options = feed::FeedOptions::new(base_url.clone(), site_title.clone());
+            self.write("atom.xml", feed::build_feed(self.root, &options)?.as_bytes())?;
+        }
+
+        Ok(SiteSummary {
+            pages: pages.len(),
+            assets: self.copy_assets()?,
+            tags: tags.len(),
+        })
+    }
+}
+
+/// Renders the site [`export_site`] would write, without writing it.
+pub(crate) fn render_site(
+    root: &Path, theme: &str, options: &SiteOptions,
+) -> Result<RenderedSite, String> {
+    let exporter = Exporter {
+        root,
+        out_dir: None,
+        options,
+        assets: RefCell::default(),
+        rendered: RefCell::default(),
+    };
+    exporter.export(theme)?;
+    Ok(RenderedSite {
+        files: exporter.rendered.into_inner(),
+        assets: exporter
+            .assets
+            .into_inner()
+            .into_iter()
+            .map(|(source, target)| (target, source))
+            .collect(),
+    })
+}
+
+/// Publishes a workspace as a static website: one page per document with
+/// internal links rewritten, an index, a page per tag, the referenced assets
+/// and, given a base URL, an Atom feed. `theme` is `light`, `dark` or the
+/// path to a stylesheet.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn export_site(
+    workspace: String, out_dir: String, theme: String, options: SiteOptions
+) -> Result<SiteSummary, BackendError> {
+    log::info!("export_site start: {workspace} -> {out_dir}");
+    let root = workspace.clone();
+    let job = jobs::start(JobKind::Export, Priority::Background, &out_dir);
+    let timer = metrics::timer("export_site");
+    let result = workers::run(Priority::Background, move || job.run(|| {
+        let exporter = Exporter {
+            root: Path::new(&workspace),
+            out_dir: Some(Path::new(&out_dir)),
+            options: &options,
+            assets: RefCell::default(),
+            rendered: RefCell::default(),
+        };
+        exporter.export(&theme)
+    })).await;
+    timer.finish(matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(Ok(summary)) => {
+            log::info!("export_site done");
+            webhooks::emit(WebhookEvent::Export, &root);
+            Ok(summary)
+        }
+        Ok(Err(e)) => Err(format!("export_site task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_tags_get_a_page_of_their_own() {
+        assert_eq!(tag_slug("Rust Lang"), "rust-lang");
+        let (plus, party) = (tag_slug("++"), tag_slug("🎉"));
+        assert!(plus.starts_with("tag-") && plus.len() == 12, "{plus}");
+        assert_ne!(plus, party);
+        assert_eq!(plus, tag_slug("++"));
+    }
+}
@@ -0,0 +1,208 @@
+//! Slide decks from markdown documents: slides are split at `---` and,
+//! optionally, at headings, and played with reveal.js, which is bundled
+//! with the app and inlined into every deck, so decks are a single file
+//! that plays offline.
+
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+use tauri::{AppHandle, Manager};
+
+use crate::{
+    error::BackendError,
+    frontmatter::Frontmatter,
+    jobs::{self, JobKind},
+    markdown::{self, escape, RenderOptions},
+    workers::{self, Priority},
+};
+
+const DEFAULT_IMAGE_MAX_SIZE: usize = 512 * 1024;
+
+/// Of reveal.js, what a deck needs, in the `reveal` folder of the app's
+/// resources: its style, a theme and its script.
+const REVEAL_FILES: [&str; 3] = ["reveal.css", "white.css", "reveal.js"];
+
+#[derive(Clone, Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SlideOptions {
+    /// Headings at this level or above start a new slide; `---` always does.
+    split_level: Option<u8>,
+    title: Option<String>,
+    image_max_size: Option<usize>,
+    code_theme: Option<String>,
+    /// Base URL of a reveal.js distribution to link to instead of inlining
+    /// ours. The deck is then no longer self-contained.
+    reveal_url: Option<String>,
+}
+
+fn heading_level(line: &str) -> Option<u8> {
+    let hashes = line.bytes().take_while(|&b| b == b'#').count();
+    let rest = &line[hashes..];
+    let level = u8::try_from(hashes).ok()?;
+    ((1..=6).contains(&level) && (rest.is_empty() || rest.starts_with([' ', '\t'])))
+        .then_some(level)
+}
+
+/// Splits markdown into slide sources, ignoring separators in code fences.
+pub(crate) fn split_slides(markdown: &str, split_level: Option<u8>) -> Vec<String> {
+    let mut slides = vec![String::new()];
+    let mut fence: Option<&str> = None;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+        } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fence = Some(&trimmed[..3]);
+        } else if line.trim_end() == "---" {
+            slides.push(String::new());
+            continue;
+        } else if let (Some(max), Some(level)) = (split_level, heading_level(line)) {
+            let last = slides.last().map_or("", String::as_str);
+            if level <= max && !last.trim().is_empty() {
+                slides.push(String::new());
+            }
+        }
+        let last = slides.last_mut().expect("slides is never empty");
+        last.push_str(line);
+        last.push('\n');
+    }
+    slides.retain(|s| !s.trim().is_empty());
+    slides
+}
+
+/// `text` with every `end` tag, like `</script`, escaped, so it can go
+/// in the element's body without closing it early.
+fn inline(text: &str, end: &str) -> String {
+    let lower = text.to_ascii_lowercase();
+    let mut inlined = String::with_capacity(text.len());
+    let mut last = 0;
+    for (i, _) in lower.match_indices(end) {
+        inlined.push_str(&text[last..i]);
+        inlined.push_str("<\\/");
+        last = i + 2;
+    }
+    inlined.push_str(&text[last..]);
+    inlined
+}
+
+/// How a deck gets reveal.js.
+enum Reveal {
+    /// From the base URL of a distribution.
+    Linked(String),
+    /// In the deck: its style, theme and script.
+    Inlined([String; 3]),
+}
+
+/// reveal.js's files, as [`REVEAL_FILES`], from the app's resources.
+fn reveal(app: &AppHandle) -> Result<[String; 3], BackendError> {
+    let dir = app
+        .path()
+        .resource_dir()
+        .map_err(|e| format!("resource_dir: {e}"))?
+        .join("reveal");
+    let mut files = Vec::with_capacity(REVEAL_FILES.len());
+    for file in REVEAL_FILES {
+        let path = dir.join(file);
+        let text = fs::read_to_string(&path).map_err(|e| BackendError::io(format!("read {}", path.display()), &e))?;
+        files.push(text);
+    }
+    Ok(files.try_into().map_err(|_| "reveal.js files missing".to_owned())?)
+}
+
+/// The deck, rendering a slide at a time, between [`jobs::checkpoint`]s.
+fn build_deck(
+    markdown: &str, base_dir: Option<&Path>, options: &SlideOptions, reveal: &Reveal,
+) -> Result<String, String> {
+    let (meta, body) = Frontmatter::of(markdown);
+    let title = options.title.as_deref().or(meta.get("title")).unwrap_or_default();
+    let render = RenderOptions {
+        base_dir,
+        image_max_size: Some(options.image_max_size.unwrap_or(DEFAULT_IMAGE_MAX_SIZE)),
+        code_theme: options.code_theme.as_deref(),
+        ..Default::default()
+    };
+
+    let slides = split_slides(body, options.split_level);
+    let mut sections = String::new();
+    for (i, slide) in slides.iter().enumerate() {
+        jobs::checkpoint()?;
+        jobs::progress(i, slides.len(), "");
+        sections.push_str(&format!("<section>\n{}</section>\n", markdown::to_html(slide, &render)));
+    }
+
+    let (head, tail) = match reveal {
+        Reveal::Linked(url) => {
+            let url = escape(url.trim_end_matches('/'));
+            (
+                format!(
+                    "<link rel=\"stylesheet\" href=\"{url}/dist/reveal.css\">\n\
+                     <link rel=\"stylesheet\" href=\"{url}/dist/theme/white.css\">"),
+                format!("<script src=\"{url}/dist/reveal.js\"></script>"),
+            )
+        }
+        Reveal::Inlined([style, theme, script]) => (
+            format!("<style>{}</style>\n<style>{}</style>", inline(style, "</style"), inline(theme, "</style")),
+            format!("<script>{}</script>", inline(script, "</script")),
+        ),
+    };
+
+    Ok(format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n{head}\n</head>\n<body>\n\
+         <div class=\"reveal\"><div class=\"slides\">\n{sections}</div>\
+         </div>\n{tail}\n<script>Reveal.initialize({{ hash: true }});</script>\n</body>\n</html>\n",
+        escape(title)))
+}
+
+/// Turns a markdown document into a reveal.js HTML deck with reveal.js and
+/// images compressed and inlined. Relative image paths are resolved against
+/// `base_dir`.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn export_slides(
+    markdown: String, base_dir: Option<String>, options: SlideOptions, app: AppHandle
+) -> Result<String, BackendError> {
+    log::info!("export_slides start");
+    let reveal = match &options.reveal_url {
+        Some(url) => Reveal::Linked(url.clone()),
+        None => Reveal::Inlined(reveal(&app)?),
+    };
+    let job = jobs::start(JobKind::Export, Priority::Interactive, base_dir.clone().unwrap_or_default());
+    let result = workers::run(Priority::Interactive, move || {
+        job.run(|| build_deck(&markdown, base_dir.as_deref().map(Path::new), &options, &reveal))
+    }).await;
+
+    match result {
+        Ok(Ok(html)) => {
+            log::info!("export_slides done");
+            Ok(html)
+        }
+        Ok(Err(e)) => Err(format!("export_slides task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_escapes_end_tags() {
+        assert_eq!(inline("a('</script>'); b('</SCRIPT')", "</script"), "a('<\\/script>'); b('<\\/SCRIPT')");
+        assert_eq!(inline("p { color: red }", "</style"), "p { color: red }");
+    }
+
+    #[test]
+    fn decks_inline_reveal() {
+        let reveal = Reveal::Inlined(["/* style */".to_owned(), "/* theme */".to_owned(), "var Reveal;".to_owned()]);
+        let deck = build_deck("# One\n\n---\n\n# Two\n", None, &SlideOptions::default(), &reveal)
+            .expect("a deck");
+        assert!(deck.contains("<style>/* style */</style>\n<style>/* theme */</style>"), "{deck}");
+        assert!(deck.contains("<script>var Reveal;</script>"), "{deck}");
+        assert_eq!(deck.matches("<section>").count(), 2);
+        assert!(!deck.contains("href="), "{deck}");
+    }
+}
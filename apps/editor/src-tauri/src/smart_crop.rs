@@ -0,0 +1,266 @@
+//! Crops photos to one aspect ratio, for uniform cards and covers, keeping
+//! what matters in them: a map of how interesting each part of the image
+//! is, from its edges, skin tones and saturated colors, picks the window
+//! that keeps the most of it, centered and not cut through. Skin tones
+//! stand in for face detection, which would need a model.
+
+use std::path::Path;
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use serde::Serialize;
+
+use crate::{
+    error::BackendError,
+    workers::{self, Priority},
+};
+
+/// Of the downscaled image the map is made from, in pixels.
+const ANALYSIS_EDGE: u32 = 256;
+/// Windows are tried at these fractions of the largest that fits.
+const SCALES: [f32; 3] = [1.0, 0.9, 0.8];
+/// Windows are tried this many times along each axis.
+const STEPS: u32 = 24;
+const SKIN_WEIGHT: f32 = 1.8;
+const SATURATION_WEIGHT: f32 = 0.3;
+/// What's left outside the window counts against it.
+const OUTSIDE_WEIGHT: f32 = -0.5;
+/// What's near a side of the window that cuts through the image counts
+/// against it, so subjects aren't cut in half.
+const BORDER: f32 = 0.9;
+const BORDER_WEIGHT: f32 = -1.0;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SmartCrop {
+    path: String,
+    /// In pixels of the original.
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// How close to a skin tone, from 0 to 1, for pixels neither too dark nor
+/// too bright.
+fn skin([r, g, b]: [f32; 3]) -> f32 {
+    const TONE: [f32; 3] = [0.78, 0.57, 0.44];
+    const THRESHOLD: f32 = 0.8;
+    let length = (r * r + g * g + b * b).sqrt();
+    let lightness = luminance([r, g, b]);
+    if length == 0.0 || !(0.2..=1.0).contains(&lightness) {
+        return 0.0;
+    }
+    let distance = [r, g, b].iter().zip(TONE).map(|(c, t)| (c / length - t).powi(2)).sum::<f32>().sqrt();
+    ((1.0 - distance - THRESHOLD) / (1.0 - THRESHOLD)).max(0.0)
+}
+
+/// How saturated, from 0 to 1, for pixels neither too dark nor too bright.
+fn saturation([r, g, b]: [f32; 3]) -> f32 {
+    const THRESHOLD: f32 = 0.4;
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let lightness = (max + min) / 2.0;
+    if max == min || !(0.05..=0.9).contains(&lightness) {
+        return 0.0;
+    }
+    let delta = max - min;
+    let saturation = if lightness > 0.5 { delta / (2.0 - max - min) } else { delta / (max + min) };
+    ((saturation - THRESHOLD) / (1.0 - THRESHOLD)).max(0.0)
+}
+
+/// How interesting each pixel of `image` is, in rows.
+fn saliency(image: &DynamicImage) -> Vec<f32> {
+    let rgb = image.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let colors: Vec<[f32; 3]> =
+        rgb.pixels().map(|p| [f32::from(p[0]) / 255.0, f32::from(p[1]) / 255.0, f32::from(p[2]) / 255.0]).collect();
+    let lightness: Vec<f32> = colors.iter().copied().map(luminance).collect();
+    let (width, height) = (width as usize, height as usize);
+    let at = |x: usize, y: usize| lightness[y * width + x];
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            // the Laplacian: how unlike its neighbors
+            let around = [
+                at(x.saturating_sub(1), y),
+                at((x + 1).min(width - 1), y),
+                at(x, y.saturating_sub(1)),
+                at(x, (y + 1).min(height - 1)),
+            ];
+            let edge = (4.0 * at(x, y) - around.iter().sum::<f32>()).abs().min(1.0);
+            let color = colors[y * width + x];
+            edge + SKIN_WEIGHT * skin(color) + SATURATION_WEIGHT * saturation(color)
+        })
+        .collect()
+}
+
+/// A window `(x, y, width, height)` in the map.
+type Window = (usize, usize, usize, usize);
+
+/// Higher for windows keeping more, centered; see the weights.
+fn score(map: &[f32], map_width: usize, map_height: usize, total: f32, (x0, y0, w, h): Window) -> f32 {
+    // which sides cut through the image
+    let cuts = [x0 > 0, y0 > 0, x0 + w < map_width, y0 + h < map_height];
+    let mut inside = 0.0;
+    let mut score = 0.0;
+    for y in y0..y0 + h {
+        #[allow(clippy::cast_precision_loss)]
+        let v = 2.0 * (y - y0) as f32 / h as f32 - 1.0;
+        for x in x0..x0 + w {
+            #[allow(clippy::cast_precision_loss)]
+            let u = 2.0 * (x - x0) as f32 / w as f32 - 1.0;
+            let value = map[y * map_width + x];
+            inside += value;
+            let near_cut = (cuts[0] && u < -BORDER)
+                || (cuts[1] && v < -BORDER)
+                || (cuts[2] && u > BORDER)
+                || (cuts[3] && v > BORDER);
+            let weight = if near_cut { BORDER_WEIGHT } else { 1.0 - 0.5 * (u * u + v * v) };
+            score += value * weight;
+        }
+    }
+    score + OUTSIDE_WEIGHT * (total - inside)
+}
+
+/// The best window of `aspect_ratio` in the map.
+fn best_window(map: &[f32], width: usize, height: usize, aspect_ratio: f32) -> Window {
+    let total: f32 = map.iter().sum();
+    #[allow(clippy::cast_precision_loss)]
+    let (largest_w, largest_h) = if width as f32 / height as f32 > aspect_ratio {
+        (height as f32 * aspect_ratio, height as f32)
+    } else {
+        (width as f32, width as f32 / aspect_ratio)
+    };
+    let mut best = (0, 0, width, height);
+    let mut best_score = f32::MIN;
+    for scale in SCALES {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let (w, h) = (
+            ((largest_w * scale).round() as usize).clamp(1, width),
+            ((largest_h * scale).round() as usize).clamp(1, height),
+        );
+        let (room_x, room_y) = (width - w, height - h);
+        let step_x = (room_x / STEPS as usize).max(1);
+        let step_y = (room_y / STEPS as usize).max(1);
+        for y in (0..=room_y).step_by(step_y) {
+            for x in (0..=room_x).step_by(step_x) {
+                let window = (x, y, w, h);
+                let score = score(map, width, height, total, window);
+                if score > best_score {
+                    (best, best_score) = (window, score);
+                }
+            }
+        }
+    }
+    best
+}
+
+fn crop(path: &Path, aspect_ratio: f32, out: &Path) -> Result<SmartCrop, String> {
+    let image = image::open(path).map_err(|e| format!("open {}: {e}", path.display()))?;
+    let (width, height) = image.dimensions();
+    let small = if width.max(height) > ANALYSIS_EDGE {
+        image.resize(ANALYSIS_EDGE, ANALYSIS_EDGE, FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+    let (small_width, small_height) = (small.width() as usize, small.height() as usize);
+    let (x, y, _, h) = best_window(&saliency(&small), small_width, small_height, aspect_ratio);
+
+    // back to pixels of the original, at exactly the aspect ratio
+    #[allow(clippy::cast_precision_loss)]
+    let scale = width as f32 / small_width as f32;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+    let (crop_width, crop_height) = {
+        let crop_height = ((h as f32 * scale).round() as u32).clamp(1, height);
+        let crop_width = ((crop_height as f32 * aspect_ratio).round() as u32).max(1);
+        if crop_width > width {
+            (width, ((width as f32 / aspect_ratio).round() as u32).clamp(1, height))
+        } else {
+            (crop_width, crop_height)
+        }
+    };
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+    let (x, y) = (
+        ((x as f32 * scale).round() as u32).min(width - crop_width),
+        ((y as f32 * scale).round() as u32).min(height - crop_height),
+    );
+    image
+        .crop_imm(x, y, crop_width, crop_height)
+        .save(out)
+        .map_err(|e| format!("save {}: {e}", out.display()))?;
+    Ok(SmartCrop { path: out.to_string_lossy().into_owned(), x, y, width: crop_width, height: crop_height })
+}
+
+/// Crops the image at `path` to `aspect_ratio`, width over height, keeping
+/// what's most interesting in it, and saves it to `out`, in the format of
+/// its extension.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn smart_crop(path: String, aspect_ratio: f32, out: String) -> Result<SmartCrop, BackendError> {
+    log::info!("smart_crop start: {path}");
+    if !aspect_ratio.is_finite() || aspect_ratio <= 0.0 {
+        return Err(BackendError::invalid(format!("smart_crop: invalid aspect ratio {aspect_ratio}")));
+    }
+    let result =
+        workers::run(Priority::Interactive, move || crop(Path::new(&path), aspect_ratio, Path::new(&out))).await;
+    match result {
+        Ok(Ok(crop)) => {
+            log::info!("smart_crop done: {} x {} at {}, {}", crop.width, crop.height, crop.x, crop.y);
+            Ok(crop)
+        }
+        Ok(Err(e)) => Err(format!("smart_crop task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn skin_tones_and_saturated_colors_stand_out() {
+        assert!(skin([0.78, 0.57, 0.44]) > 0.5);
+        for other in [[0.5, 0.5, 0.5], [0.0, 0.0, 0.0], [0.1, 0.2, 0.9], [1.0, 1.0, 1.0]] {
+            assert_eq!(skin(other), 0.0, "{other:?}");
+        }
+        assert_eq!(saturation([1.0, 0.0, 0.0]), 1.0);
+        for other in [[0.5, 0.5, 0.5], [1.0, 1.0, 1.0], [0.02, 0.0, 0.0], [0.5, 0.45, 0.45]] {
+            assert_eq!(saturation(other), 0.0, "{other:?}");
+        }
+    }
+
+    #[test]
+    fn the_window_keeps_what_stands_out() {
+        let (width, height) = (40, 20);
+        // a bright stripe near the right
+        let map: Vec<f32> =
+            (0..width * height).map(|i| if (30..35).contains(&(i % width)) { 1.0 } else { 0.0 }).collect();
+        let (x, y, w, h) = best_window(&map, width, height, 1.0);
+        assert_eq!(w, h);
+        assert!(y + h <= height);
+        assert!(x < 30 && x + w > 35, "{x} + {w}");
+        // a window as big as the map keeps it all
+        assert_eq!(best_window(&map, width, height, 2.0), (0, 0, width, height));
+    }
+
+    #[test]
+    fn photos_are_cropped_to_the_aspect_ratio() {
+        let dir = std::env::temp_dir().join(format!("emmm-smart-crop-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create the folder");
+        let (path, out) = (dir.join("photo.png"), dir.join("card.png"));
+        let photo = RgbImage::from_fn(400, 200, |x, y| {
+            if (300..340).contains(&x) && (80..120).contains(&y) { Rgb([220, 30, 30]) } else { Rgb([128, 128, 128]) }
+        });
+        photo.save(&path).expect("write the photo");
+        let crop = crop(&path, 1.0, &out).expect("crop the photo");
+        assert_eq!(crop.width, crop.height);
+        assert!(crop.x < 300 && crop.x + crop.width > 340, "{} + {}", crop.x, crop.width);
+        assert_eq!(image::open(&out).expect("open the card").dimensions(), (crop.width, crop.height));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
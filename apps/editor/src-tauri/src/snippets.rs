@@ -0,0 +1,354 @@
+//! User-defined snippets, expanded here so they behave the same on every
+//! platform and are kept in the app data rather than the webview's
+//! storage.
+//!
+//! Bodies use the TextMate syntax: `$1` or `${1:default}` are tab stops,
+//! visited in order and `$0` last, and `${name}` or `${name:argument}` are
+//! variables:
+//!
+//! - `date`, with an optional format like `YYYY/MM/DD` or
+//!   `[year]/[month]/[day]`, and offsets like `${date+7d}`; see
+//!   [`templates`]
+//! - `time` and `now`, likewise
+//! - `clipboard`, `selection`, and `file`, the document's name without
+//!   extension; the argument is the default when they're empty
+//!
+//! A backslash makes `$`, `}` or `\` literal.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+use time::OffsetDateTime;
+
+use crate::{error::BackendError, templates, workspace::write_atomic};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snippet {
+    /// What the user types to expand it; unique.
+    trigger: String,
+    body: String,
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SnippetContext {
+    /// The text selected when expanding.
+    selection: Option<String>,
+    /// The document expanded in.
+    path: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Placeholder {
+    /// The tab stop; several placeholders with the same one mirror each
+    /// other.
+    index: u32,
+    /// In UTF-16 code units from the start of the expansion, like
+    /// JavaScript string indices.
+    offset: usize,
+    length: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Expansion {
+    text: String,
+    /// In the order to visit them.
+    placeholders: Vec<Placeholder>,
+    /// Where the cursor ends up: at `$0`, or the end.
+    cursor: usize,
+}
+
+pub struct Snippets {
+    path: PathBuf,
+    /// Loaded on first use.
+    snippets: RwLock<Option<Vec<Snippet>>>,
+}
+
+/// Reads variables only when a snippet uses them.
+struct Variables<'a> {
+    clipboard: &'a dyn Fn() -> Option<String>,
+    context: &'a SnippetContext,
+    now: OffsetDateTime,
+}
+
+impl Variables<'_> {
+    fn value(&self, name: &str, argument: Option<&str>) -> Option<String> {
+        let or_default = |value: Option<String>| {
+            value.filter(|v| !v.is_empty()).or_else(|| argument.map(str::to_owned)).or(Some(String::new()))
+        };
+        if let Some(date) = templates::date_value(self.now, name, argument) {
+            return Some(date);
+        }
+        match name {
+            "clipboard" => or_default((self.clipboard)()),
+            "selection" => or_default(self.context.selection.clone()),
+            "file" => or_default(self.context.path.as_deref().and_then(|p| {
+                Path::new(p).file_stem().map(|s| s.to_string_lossy().into_owned())
+            })),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    position: usize,
+    variables: &'a Variables<'a>,
+    out: String,
+    /// Tab stops with byte ranges of `out`.
+    stops: Vec<(u32, usize, usize)>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn digits(&mut self) -> Option<u32> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect::<String>().parse().ok()
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+
+    /// Up to an unescaped `}` if `nested`, or the end.
+    fn body(&mut self, nested: bool) {
+        while let Some(c) = self.peek() {
+            self.position += 1;
+            match c {
+                '\\' if matches!(self.peek(), Some('$' | '}' | '\\')) => {
+                    self.out.push(self.chars[self.position]);
+                    self.position += 1;
+                }
+                '}' if nested => return,
+                '$' => self.dollar(),
+                c => self.out.push(c),
+            }
+        }
+    }
+
+    /// After a `$`.
+    fn dollar(&mut self) {
+        let start = self.position;
+        let braced = self.peek() == Some('{');
+        if braced {
+            self.position += 1;
+        }
+        if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            let index = self.digits().unwrap_or_default();
+            let from = self.out.len();
+            if braced && self.peek() == Some(':') {
+                self.position += 1;
+                self.body(true);
+            } else {
+                if braced && self.peek() == Some('}') {
+                    self.position += 1;
+                }
+                // a mirror starts out like the first one
+                if let Some(&(_, start, end)) = self.stops.iter().find(|(i, ..)| *i == index) {
+                    let text = self.out[start..end].to_owned();
+                    self.out.push_str(&text);
+                }
+            }
+            self.stops.push((index, from, self.out.len()));
+            return;
+        }
+        let mut name = self.name();
+        if braced {
+            // date offsets, like `${date+7d}`
+            while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-') {
+                name.push(self.chars[self.position]);
+                self.position += 1;
+            }
+        }
+        let argument = if braced && self.peek() == Some(':') {
+            self.position += 1;
+            let begin = self.position;
+            // the raw argument; variables aren't expanded in it
+            while self.peek().is_some_and(|c| c != '}') {
+                self.position += 1;
+            }
+            Some(self.chars[begin..self.position].iter().collect::<String>())
+        } else {
+            None
+        };
+        let closed = !braced || self.peek() == Some('}');
+        if braced && closed {
+            self.position += 1;
+        }
+        match self.variables.value(&name, argument.as_deref()).filter(|_| !name.is_empty() && closed) {
+            Some(value) => self.out.push_str(&value),
+            None => {
+                // not a variable after all
+                self.out.push('$');
+                self.position = start;
+            }
+        }
+    }
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+fn expand(body: &str, variables: &Variables) -> Expansion {
+    let mut parser = Parser {
+        chars: body.chars().collect(),
+        position: 0,
+        variables,
+        out: String::new(),
+        stops: Vec::new(),
+    };
+    parser.body(false);
+    let Parser { out: text, mut stops, .. } = parser;
+    // $0 comes last
+    stops.sort_by_key(|&(index, start, _)| (index == 0, index, start));
+    let cursor = stops
+        .iter()
+        .find(|(index, ..)| *index == 0)
+        .map_or(utf16_len(&text), |&(_, start, _)| utf16_len(&text[..start]));
+    let placeholders = stops
+        .into_iter()
+        .filter(|&(index, ..)| index != 0)
+        .map(|(index, start, end)| Placeholder {
+            index,
+            offset: utf16_len(&text[..start]),
+            length: utf16_len(&text[start..end]),
+        })
+        .collect();
+    Expansion { text, placeholders, cursor }
+}
+
+impl Snippets {
+    pub fn new(path: PathBuf) -> Self {
+        Snippets { path, snippets: RwLock::new(None) }
+    }
+
+    /// Calls `f` with the snippets; saves them if it returns `true`.
+    fn update<T>(&self, f: impl FnOnce(&mut Vec<Snippet>) -> (bool, T)) -> Result<T, String> {
+        let mut snippets = self.snippets.write().expect("snippets lock poisoned");
+        let snippets = match &mut *snippets {
+            Some(snippets) => snippets,
+            None => snippets.insert(match fs::read(&self.path) {
+                Ok(data) => serde_json::from_slice(&data)
+                    .map_err(|e| format!("parse {}: {e}", self.path.display()))?,
+                Err(_) => Vec::new(),
+            }),
+        };
+        let (changed, result) = f(snippets);
+        if changed {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+            }
+            let json = serde_json::to_vec_pretty(snippets).map_err(|e| format!("serialize: {e}"))?;
+            write_atomic(&self.path, &json)?;
+        }
+        Ok(result)
+    }
+}
+
+#[tauri::command]
+pub fn list_snippets(snippets: State<'_, Arc<Snippets>>) -> Result<Vec<Snippet>, BackendError> {
+    Ok(snippets.update(|all| (false, all.clone()))?)
+}
+
+/// Adds `snippet`, or replaces the one with the same trigger.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn save_snippet(snippet: Snippet, snippets: State<'_, Arc<Snippets>>) -> Result<(), BackendError> {
+    if snippet.trigger.trim().is_empty() {
+        return Err(BackendError::invalid("a snippet needs a trigger"));
+    }
+    Ok(snippets.update(|all| {
+        match all.iter_mut().find(|s| s.trigger == snippet.trigger) {
+            Some(existing) => *existing = snippet,
+            None => all.push(snippet),
+        }
+        (true, ())
+    })?)
+}
+
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn delete_snippet(trigger: String, snippets: State<'_, Arc<Snippets>>) -> Result<(), BackendError> {
+    Ok(snippets.update(|all| {
+        let count = all.len();
+        all.retain(|s| s.trigger != trigger);
+        (all.len() != count, ())
+    })?)
+}
+
+/// The text of the snippet `trigger` with its variables filled in, and
+/// where its tab stops are.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn expand_snippet(
+    trigger: String, context: SnippetContext, app: AppHandle, snippets: State<'_, Arc<Snippets>>,
+) -> Result<Expansion, BackendError> {
+    let body = snippets
+        .update(|all| (false, all.iter().find(|s| s.trigger == trigger).map(|s| s.body.clone())))?
+        .ok_or_else(|| format!("no snippet {trigger}"))?;
+    let variables = Variables {
+        clipboard: &|| app.clipboard().read_text().ok(),
+        context: &context,
+        now: OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc()),
+    };
+    Ok(expand(&body, &variables))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expanded(body: &str, selection: Option<&str>) -> Expansion {
+        let context = SnippetContext {
+            selection: selection.map(str::to_owned),
+            path: Some("/notes/plan.md".to_owned()),
+        };
+        let variables = Variables {
+            clipboard: &|| Some("copied".to_owned()),
+            context: &context,
+            now: OffsetDateTime::from_unix_timestamp(1_714_566_600).expect("a time"),
+        };
+        expand(body, &variables)
+    }
+
+    #[test]
+    fn tab_stops_are_placed_in_order() {
+        let expansion = expanded("[${1:text}](${2:url})$0 $1", None);
+        assert_eq!(expansion.text, "[text](url) text");
+        let stops: Vec<_> = expansion.placeholders.iter().map(|p| (p.index, p.offset, p.length)).collect();
+        assert_eq!(stops, [(1, 1, 4), (1, 12, 4), (2, 7, 3)]);
+        assert_eq!(expansion.cursor, 11);
+        assert_eq!(expanded("é$1", None).cursor, 1);
+        assert_eq!(expanded("${1:a ${2:b}}", None).placeholders.len(), 2);
+    }
+
+    #[test]
+    fn variables_are_filled_in() {
+        let expansion = expanded("${file} ${date} ${date+1d:DD.MM.} ${clipboard} ${selection:none}", None);
+        assert_eq!(expansion.text, "plan 2024-05-01 02.05. copied none");
+        assert_eq!(expanded("**${selection}**", Some("bold")).text, "**bold**");
+        // escapes, and what only looks like a variable
+        assert_eq!(expanded(r"\$1 \} \\ $ $price ${unclosed", None).text, r"$1 } \ $ $price ${unclosed");
+    }
+}
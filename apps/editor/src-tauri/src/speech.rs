@@ -0,0 +1,633 @@
+//! Reading text aloud with the system's speech synthesizer
+//! (AVSpeechSynthesizer on macOS, System.Speech on Windows and
+//! speech-dispatcher elsewhere), for proofreading by ear. Each reports the
+//! words as it reaches them, which are highlighted. The text is spoken a
+//! sentence at a time, which is what makes pausing and resuming possible.
+
+#[cfg(target_os = "macos")]
+use std::cell::RefCell;
+#[cfg(not(target_os = "macos"))]
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+#[cfg(all(unix, not(target_os = "macos")))]
+use std::{env, time::Duration};
+
+#[cfg(target_os = "macos")]
+use objc2::{define_class, msg_send, rc::Retained, runtime::ProtocolObject, AnyThread, DefinedClass};
+#[cfg(target_os = "macos")]
+use objc2_avf_audio::{
+    AVSpeechBoundary, AVSpeechSynthesisVoice, AVSpeechSynthesizer, AVSpeechSynthesizerDelegate, AVSpeechUtterance,
+};
+#[cfg(target_os = "macos")]
+use objc2_foundation::{NSObject, NSObjectProtocol, NSRange, NSString};
+use tauri::AppHandle;
+#[cfg(not(target_os = "macos"))]
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+#[cfg(all(unix, not(target_os = "macos")))]
+use tokio::net::{
+    unix::{OwnedReadHalf, OwnedWriteHalf},
+    UnixStream,
+};
+use tokio::sync::{mpsc, oneshot, watch};
+
+#[cfg(not(target_os = "macos"))]
+use crate::process;
+#[cfg(all(unix, not(target_os = "macos")))]
+use crate::markdown::escape;
+use crate::{error::BackendError, send, BackendEvent, EventChannel};
+
+/// Reads its standard input as UTF-8, and prints the position and length
+/// of each word as it's spoken.
+#[cfg(windows)]
+const WINDOWS_SCRIPT: &str = "[Console]::InputEncoding = [Text.Encoding]::UTF8; \
+    Add-Type -AssemblyName System.Speech; \
+    $s = New-Object System.Speech.Synthesis.SpeechSynthesizer; \
+    if ($env:EMMM_VOICE) { $s.SelectVoice($env:EMMM_VOICE) }; \
+    $s.Rate = [int]$env:EMMM_RATE; \
+    $null = Register-ObjectEvent $s SpeakProgress -SourceIdentifier word; \
+    $null = Register-ObjectEvent $s SpeakCompleted -SourceIdentifier done; \
+    $null = $s.SpeakAsync([Console]::In.ReadToEnd()); \
+    while ($true) { \
+        $e = Wait-Event; Remove-Event -EventIdentifier $e.EventIdentifier; \
+        if ($e.SourceIdentifier -eq 'done') { break }; \
+        [Console]::Out.WriteLine(\"$($e.SourceEventArgs.CharacterPosition) $($e.SourceEventArgs.CharacterCount)\") \
+    }";
+/// For speech-dispatcher to start, if it isn't running.
+#[cfg(all(unix, not(target_os = "macos")))]
+const SPAWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PlayState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+/// Controls the speech in progress, by its ID.
+static SPEECH: Mutex<Option<(u64, watch::Sender<PlayState>)>> = Mutex::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Gets the offset and length of each word as the synthesizer reaches it,
+/// in UTF-16 code units of what it was given.
+type Words = mpsc::UnboundedSender<(usize, usize)>;
+
+enum Synthesizer {
+    /// Used on the main thread, where it calls back.
+    #[cfg(target_os = "macos")]
+    Apple(AppHandle),
+    /// PowerShell, to run [`WINDOWS_SCRIPT`].
+    #[cfg(windows)]
+    Windows(PathBuf),
+    /// The socket of speech-dispatcher.
+    #[cfg(all(unix, not(target_os = "macos")))]
+    Dispatcher(PathBuf),
+}
+
+impl Synthesizer {
+    #[allow(clippy::needless_pass_by_value)]
+    async fn find(app: AppHandle) -> Result<Self, String> {
+        #[cfg(target_os = "macos")]
+        {
+            Ok(Synthesizer::Apple(app))
+        }
+        #[cfg(windows)]
+        {
+            let _ = app;
+            process::find_program("powershell").map(Synthesizer::Windows).ok_or("PowerShell not found".to_owned())
+        }
+        #[cfg(all(unix, not(target_os = "macos")))]
+        {
+            let _ = app;
+            let socket = dispatcher_socket().ok_or("no speech-dispatcher socket; is XDG_RUNTIME_DIR set?")?;
+            if !socket.exists() {
+                let program = process::find_program("speech-dispatcher")
+                    .ok_or("no speech synthesizer found; is speech-dispatcher installed?")?;
+                // fails if another one started meanwhile, which is fine
+                let _ = process::run_piped(&program, &["--spawn".to_owned()], Vec::new(), SPAWN_TIMEOUT).await;
+            }
+            if !socket.exists() {
+                return Err(format!("speech-dispatcher didn't start; no socket at {}", socket.display()));
+            }
+            Ok(Synthesizer::Dispatcher(socket))
+        }
+    }
+
+    /// Speaks `text` until it's done or `stop`, sending `words` as it goes.
+    async fn speak(
+        &self, text: &str, voice: Option<&str>, rate: f32, words: Words, stop: oneshot::Receiver<()>,
+    ) -> Result<(), String> {
+        match self {
+            #[cfg(target_os = "macos")]
+            Synthesizer::Apple(app) => speak_apple(app, text, voice, rate, words, stop).await,
+            #[cfg(windows)]
+            Synthesizer::Windows(program) => speak_windows(program, text, voice, rate, words, stop).await,
+            #[cfg(all(unix, not(target_os = "macos")))]
+            Synthesizer::Dispatcher(socket) => speak_dispatcher(socket, text, voice, rate, words, stop).await,
+        }
+    }
+}
+
+/// Offsets are in UTF-16 code units, like JavaScript string indices.
+struct Sentence<'a> {
+    text: &'a str,
+    offset: usize,
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// Splits after sentence-ending punctuation followed by a space, and at
+/// line breaks.
+fn sentences(text: &str) -> Vec<Sentence<'_>> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, n)| n);
+        let ends = c == '\n'
+            || matches!(c, '。' | '！' | '？')
+            || (matches!(c, '.' | '!' | '?') && next.is_none_or(char::is_whitespace));
+        if ends || next.is_none() {
+            let end = i + c.len_utf8();
+            let sentence = &text[start..end];
+            if !sentence.trim().is_empty() {
+                result.push(Sentence { text: sentence, offset });
+            }
+            offset += utf16_len(sentence);
+            start = end;
+        }
+    }
+    result
+}
+
+/// The words of `text`: where each starts in it, in bytes, and its offset
+/// and length in UTF-16 code units.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn word_spans(text: &str) -> Vec<(usize, usize, usize)> {
+    let mut words = Vec::new();
+    let (mut position, mut offset) = (0, 0);
+    for word in text.split_whitespace() {
+        let start = position + text[position..].find(word).unwrap_or_default();
+        offset += utf16_len(&text[position..start]);
+        let length = utf16_len(word);
+        words.push((start, offset, length));
+        position = start + word.len();
+        offset += length;
+    }
+    words
+}
+
+/// `text` in SSML, with a mark before each word named for its index in
+/// [`word_spans`].
+#[cfg(all(unix, not(target_os = "macos")))]
+fn ssml(text: &str) -> String {
+    let mut ssml = "<speak>".to_owned();
+    let mut position = 0;
+    for (i, (start, ..)) in word_spans(text).into_iter().enumerate() {
+        ssml.push_str(&escape(&text[position..start]));
+        ssml.push_str(&format!("<mark name=\"{i}\"/>"));
+        position = start;
+    }
+    ssml.push_str(&escape(&text[position..]));
+    ssml.push_str("</speak>");
+    ssml
+}
+
+/// `rate` from 1/4 to 4 times normal as a step from -`steps` to `steps`,
+/// where the ends are a quarter and four times as fast.
+#[cfg(all(unix, not(target_os = "macos")))]
+#[allow(clippy::cast_possible_truncation)]
+fn rate_steps(rate: f32, steps: f32) -> i32 {
+    (steps * rate.log(4.0)).round().clamp(-steps, steps) as i32
+}
+
+#[cfg(windows)]
+async fn speak_windows(
+    program: &Path, text: &str, voice: Option<&str>, rate: f32, words: Words,
+    mut stop: oneshot::Receiver<()>,
+) -> Result<(), String> {
+    use std::process::Stdio;
+    // -10 to 10, where each 10 steps are about three times slower or faster,
+    // so 4 times is past the end
+    #[allow(clippy::cast_possible_truncation)]
+    let steps = (10.0 * rate.ln() / 3f32.ln()).round().clamp(-10.0, 10.0) as i32;
+    let mut child = tokio::process::Command::new(program)
+        .args(["-NoProfile", "-NonInteractive", "-Command", WINDOWS_SCRIPT])
+        .env("EMMM_RATE", steps.to_string())
+        .env("EMMM_VOICE", voice.unwrap_or_default())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("spawn PowerShell: {e}"))?;
+    let mut stdin = child.stdin.take().ok_or("no stdin".to_owned())?;
+    stdin.write_all(text.as_bytes()).await.map_err(|e| format!("write stdin: {e}"))?;
+    drop(stdin);
+    let mut lines = BufReader::new(child.stdout.take().ok_or("no stdout".to_owned())?).lines();
+    loop {
+        tokio::select! {
+            line = lines.next_line() => match line.map_err(|e| format!("read stdout: {e}"))? {
+                Some(line) => {
+                    if let Some((offset, length)) = line.trim().split_once(' ') {
+                        if let (Ok(offset), Ok(length)) = (offset.parse(), length.parse()) {
+                            let _ = words.send((offset, length));
+                        }
+                    }
+                }
+                None => break,
+            },
+            _ = &mut stop => {
+                let _ = child.kill().await;
+                return Ok(());
+            }
+        }
+    }
+    let status = child.wait().await.map_err(|e| format!("wait: {e}"))?;
+    if !status.success() {
+        return Err(format!("PowerShell exited with {status}"));
+    }
+    Ok(())
+}
+
+/// Where speech-dispatcher listens: `SPEECHD_ADDRESS` if it's a Unix socket,
+/// or where it does by default.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn dispatcher_socket() -> Option<PathBuf> {
+    if let Some(path) = env::var("SPEECHD_ADDRESS").ok().as_deref().and_then(|a| a.strip_prefix("unix_socket:")) {
+        return Some(PathBuf::from(path));
+    }
+    Some(PathBuf::from(env::var_os("XDG_RUNTIME_DIR")?).join("speech-dispatcher/speechd.sock"))
+}
+
+/// A line of an SSIP reply, like `225-21` or `225 OK MESSAGE QUEUED`: its
+/// code, whether it's the last, and its text.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn parse_ssip_line(line: &str) -> Option<(u16, bool, &str)> {
+    let code = line.get(..3)?.parse().ok()?;
+    match line.as_bytes().get(3) {
+        Some(b'-') => Some((code, false, &line[4..])),
+        Some(b' ') => Some((code, true, &line[4..])),
+        None => Some((code, true, "")),
+        Some(_) => None,
+    }
+}
+
+/// The code and lines of the next reply or event.
+#[cfg(all(unix, not(target_os = "macos")))]
+async fn read_ssip(
+    reader: &mut BufReader<OwnedReadHalf>,
+) -> Result<(u16, Vec<String>), String> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.map_err(|e| format!("read speech-dispatcher: {e}"))? == 0 {
+            return Err("speech-dispatcher hung up".to_owned());
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (code, last, text) = parse_ssip_line(line).ok_or_else(|| format!("speech-dispatcher said {line:?}"))?;
+        lines.push(text.to_owned());
+        if last {
+            return Ok((code, lines));
+        }
+    }
+}
+
+/// Sends `command`, or data, and fails unless its reply is a success.
+#[cfg(all(unix, not(target_os = "macos")))]
+async fn ssip_request(
+    reader: &mut BufReader<OwnedReadHalf>, writer: &mut OwnedWriteHalf,
+    command: &str,
+) -> Result<(), String> {
+    writer
+        .write_all(format!("{command}\r\n").as_bytes())
+        .await
+        .map_err(|e| format!("write speech-dispatcher: {e}"))?;
+    loop {
+        let (code, lines) = read_ssip(reader).await?;
+        match code {
+            // events, which can't be for this yet
+            700..=799 => {}
+            200..=299 => return Ok(()),
+            _ => {
+                let what = command.lines().next().unwrap_or_default();
+                return Err(format!("speech-dispatcher: {what}: {code} {}", lines.last().cloned().unwrap_or_default()));
+            }
+        }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+async fn speak_dispatcher(
+    socket: &Path, text: &str, voice: Option<&str>, rate: f32, words: Words, mut stop: oneshot::Receiver<()>,
+) -> Result<(), String> {
+    let stream = UnixStream::connect(socket)
+        .await
+        .map_err(|e| format!("connect to speech-dispatcher at {}: {e}", socket.display()))?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut commands = vec![
+        "SET SELF CLIENT_NAME user:emmm:speech".to_owned(),
+        "SET SELF SSML_MODE on".to_owned(),
+        "SET SELF NOTIFICATION INDEX_MARKS on".to_owned(),
+        "SET SELF NOTIFICATION END on".to_owned(),
+        "SET SELF NOTIFICATION CANCEL on".to_owned(),
+        format!("SET SELF RATE {}", rate_steps(rate, 100.0)),
+    ];
+    if let Some(voice) = voice {
+        commands.push(format!("SET SELF SYNTHESIS_VOICE {voice}"));
+    }
+    commands.push("SPEAK".to_owned());
+    // lines starting with a dot are escaped with another
+    let data: Vec<String> = ssml(text)
+        .lines()
+        .map(|line| if line.starts_with('.') { format!(".{line}") } else { line.to_owned() })
+        .collect();
+    commands.push(format!("{}\r\n.", data.join("\r\n")));
+    for command in &commands {
+        ssip_request(&mut reader, &mut writer, command).await?;
+    }
+
+    let spans = word_spans(text);
+    loop {
+        tokio::select! {
+            event = read_ssip(&mut reader) => match event? {
+                // an index mark: message, client, mark
+                (700, lines) => {
+                    let index = lines.get(2).and_then(|mark| mark.parse::<usize>().ok());
+                    if let Some(&(_, offset, length)) = index.and_then(|i| spans.get(i)) {
+                        let _ = words.send((offset, length));
+                    }
+                }
+                // the end, or cancelled
+                (702 | 703, _) => break,
+                _ => {}
+            },
+            _ = &mut stop => {
+                writer.write_all(b"CANCEL SELF\r\n").await.map_err(|e| format!("write speech-dispatcher: {e}"))?;
+                break;
+            }
+        }
+    }
+    let _ = writer.write_all(b"QUIT\r\n").await;
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+struct DelegateIvars {
+    words: Words,
+    done: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+#[cfg(target_os = "macos")]
+define_class!(
+    #[unsafe(super(NSObject))]
+    #[name = "EmmmSpeechDelegate"]
+    #[ivars = DelegateIvars]
+    struct SpeechDelegate;
+
+    unsafe impl NSObjectProtocol for SpeechDelegate {}
+
+    unsafe impl AVSpeechSynthesizerDelegate for SpeechDelegate {
+        #[unsafe(method(speechSynthesizer:willSpeakRangeOfSpeechString:utterance:))]
+        fn will_speak(&self, _synthesizer: &AVSpeechSynthesizer, range: NSRange, _utterance: &AVSpeechUtterance) {
+            let _ = self.ivars().words.send((range.location, range.length));
+        }
+
+        #[unsafe(method(speechSynthesizer:didFinishSpeechUtterance:))]
+        fn did_finish(&self, _synthesizer: &AVSpeechSynthesizer, _utterance: &AVSpeechUtterance) {
+            self.finish();
+        }
+
+        #[unsafe(method(speechSynthesizer:didCancelSpeechUtterance:))]
+        fn did_cancel(&self, _synthesizer: &AVSpeechSynthesizer, _utterance: &AVSpeechUtterance) {
+            self.finish();
+        }
+    }
+);
+
+#[cfg(target_os = "macos")]
+impl SpeechDelegate {
+    fn new(words: Words, done: oneshot::Sender<()>) -> Retained<Self> {
+        let this = Self::alloc().set_ivars(DelegateIvars { words, done: Mutex::new(Some(done)) });
+        // SAFETY: NSObject's init, on a freshly allocated object.
+        unsafe { msg_send![super(this), init] }
+    }
+
+    fn finish(&self) {
+        if let Some(done) = self.ivars().done.lock().expect("speech delegate lock poisoned").take() {
+            let _ = done.send(());
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+thread_local! {
+    /// What's speaking, on the main thread; the delegate is only weakly
+    /// held by the synthesizer.
+    static SPEAKING: RefCell<Option<(Retained<AVSpeechSynthesizer>, Retained<SpeechDelegate>)>> =
+        const { RefCell::new(None) };
+}
+
+/// Starts speaking `text` on the main thread.
+#[cfg(target_os = "macos")]
+fn start_apple(text: &str, voice: Option<&str>, rate: f32, words: Words, done: oneshot::Sender<()>) {
+    // SAFETY: these are used on the main thread, as AVSpeechSynthesizer
+    // expects, and the rates are constants.
+    unsafe {
+        let utterance = AVSpeechUtterance::speechUtteranceWithString(&NSString::from_str(text));
+        let (min, normal, max) = (
+            objc2_avf_audio::AVSpeechUtteranceMinimumSpeechRate,
+            objc2_avf_audio::AVSpeechUtteranceDefaultSpeechRate,
+            objc2_avf_audio::AVSpeechUtteranceMaximumSpeechRate,
+        );
+        // a quarter of normal is the slowest, and four times the fastest
+        let step = rate.log(4.0);
+        utterance.setRate(if step < 0.0 { normal + (normal - min) * step } else { normal + (max - normal) * step });
+        if let Some(voice) = voice {
+            let name = NSString::from_str(voice);
+            let found = AVSpeechSynthesisVoice::voiceWithIdentifier(&name)
+                .or_else(|| AVSpeechSynthesisVoice::speechVoices().iter().find(|v| *v.name() == *name));
+            utterance.setVoice(found.as_deref());
+        }
+        let synthesizer = AVSpeechSynthesizer::new();
+        let delegate = SpeechDelegate::new(words, done);
+        synthesizer.setDelegate(Some(ProtocolObject::from_ref(&*delegate)));
+        synthesizer.speakUtterance(&utterance);
+        SPEAKING.set(Some((synthesizer, delegate)));
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn stop_apple() {
+    if let Some((synthesizer, _)) = SPEAKING.take() {
+        // SAFETY: on the main thread, like the rest.
+        unsafe { synthesizer.stopSpeakingAtBoundary(AVSpeechBoundary::Immediate) };
+    }
+}
+
+#[cfg(target_os = "macos")]
+async fn speak_apple(
+    app: &AppHandle, text: &str, voice: Option<&str>, rate: f32, words: Words, stop: oneshot::Receiver<()>,
+) -> Result<(), String> {
+    let (done_sender, done) = oneshot::channel();
+    let (text, voice) = (text.to_owned(), voice.map(str::to_owned));
+    app.run_on_main_thread(move || start_apple(&text, voice.as_deref(), rate, words, done_sender))
+        .map_err(|e| format!("run on main thread: {e}"))?;
+    tokio::select! {
+        _ = done => {}
+        _ = stop => app.run_on_main_thread(stop_apple).map_err(|e| format!("run on main thread: {e}"))?,
+    }
+    Ok(())
+}
+
+/// Speaks one sentence; `Ok(false)` if interrupted by a pause or stop.
+async fn speak_sentence(
+    synthesizer: &Synthesizer, voice: Option<&str>, rate: f32, sentence: &Sentence<'_>,
+    state: &mut watch::Receiver<PlayState>, channel: &EventChannel,
+) -> Result<bool, String> {
+    let spoken = sentence.text.trim();
+    // what's trimmed off the start, to go back to offsets in the text
+    let lead = utf16_len(&sentence.text[..sentence.text.len() - sentence.text.trim_start().len()]);
+    let (words, mut reached) = mpsc::unbounded_channel();
+    let (stop, stopped) = oneshot::channel();
+    let mut stop = Some(stop);
+    let speaking = synthesizer.speak(spoken, voice, rate, words, stopped);
+    tokio::pin!(speaking);
+    loop {
+        tokio::select! {
+            result = &mut speaking => {
+                result?;
+                return Ok(stop.is_some());
+            }
+            Some((offset, length)) = reached.recv() => {
+                if stop.is_some() {
+                    send(channel, BackendEvent::Word { offset: sentence.offset + lead + offset, length });
+                }
+            }
+            changed = state.changed(), if stop.is_some() => {
+                if changed.is_err() || *state.borrow() != PlayState::Playing {
+                    let _ = stop.take().expect("not stopped yet").send(());
+                }
+            }
+        }
+    }
+}
+
+/// Reads `text` aloud; `rate` 1 is normal speed. Sends a
+/// [`BackendEvent::Word`] as the synthesizer reaches each word, and
+/// [`BackendEvent::Done`] when finished or stopped. Starting to speak
+/// stops whatever was being spoken.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn speak(
+    text: String, voice: Option<String>, rate: Option<f32>, channel: EventChannel, app: AppHandle,
+) -> Result<(), BackendError> {
+    log::info!("speak start: {} bytes", text.len());
+    let synthesizer = Synthesizer::find(app).await?;
+    let rate = rate.unwrap_or(1.0).clamp(0.25, 4.0);
+    let (control, mut state) = watch::channel(PlayState::Playing);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    if let Some((_, previous)) = SPEECH.lock().expect("speech lock poisoned").replace((id, control)) {
+        let _ = previous.send(PlayState::Stopped);
+    }
+
+    let sentences = sentences(&text);
+    let mut index = 0;
+    let result = loop {
+        if *state.borrow() == PlayState::Paused
+            && state.wait_for(|s| *s != PlayState::Paused).await.is_err()
+        {
+            break Ok(());
+        }
+        if *state.borrow() == PlayState::Stopped {
+            break Ok(());
+        }
+        let Some(sentence) = sentences.get(index) else {
+            break Ok(());
+        };
+        // a paused sentence starts over when resumed
+        match speak_sentence(&synthesizer, voice.as_deref(), rate, sentence, &mut state, &channel)
+            .await
+        {
+            Ok(true) => index += 1,
+            Ok(false) => {}
+            Err(e) => break Err(format!("speak: {e}")),
+        }
+    };
+
+    let mut current = SPEECH.lock().expect("speech lock poisoned");
+    if current.as_ref().is_some_and(|(current_id, _)| *current_id == id) {
+        *current = None;
+    }
+    drop(current);
+    result?;
+    send(&channel, BackendEvent::Done);
+    log::info!("speak done");
+    Ok(())
+}
+
+fn control(state: PlayState) {
+    if let Some((_, control)) = &*SPEECH.lock().expect("speech lock poisoned") {
+        control.send_if_modified(|current| {
+            let changed = *current != state && *current != PlayState::Stopped;
+            if changed {
+                *current = state;
+            }
+            changed
+        });
+    }
+}
+
+#[tauri::command]
+pub fn pause_speech() {
+    control(PlayState::Paused);
+}
+
+#[tauri::command]
+pub fn resume_speech() {
+    control(PlayState::Playing);
+}
+
+#[tauri::command]
+pub fn stop_speech() {
+    control(PlayState::Stopped);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentences_keep_their_offsets() {
+        let text = "Hi there. 你好。\nBye";
+        let found: Vec<(&str, usize)> = sentences(text).iter().map(|s| (s.text, s.offset)).collect();
+        assert_eq!(found, [("Hi there.", 0), (" 你好。", 9), ("Bye", 14)]);
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn ssml_marks_each_word() {
+        assert_eq!(word_spans("a  😀b c"), [(0, 0, 1), (3, 3, 3), (9, 7, 1)]);
+        assert_eq!(
+            ssml("Tom & Jerry"),
+            r#"<speak><mark name="0"/>Tom <mark name="1"/>&amp; <mark name="2"/>Jerry</speak>"#
+        );
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn ssip_lines_are_parsed() {
+        assert_eq!(parse_ssip_line("225-21"), Some((225, false, "21")));
+        assert_eq!(parse_ssip_line("225 OK MESSAGE QUEUED"), Some((225, true, "OK MESSAGE QUEUED")));
+        assert_eq!(parse_ssip_line("700"), Some((700, true, "")));
+        assert_eq!(parse_ssip_line("hello"), None);
+        assert_eq!(rate_steps(4.0, 100.0), 100);
+        assert_eq!(rate_steps(0.5, 100.0), -50);
+    }
+}
@@ -0,0 +1,329 @@
+//! Spell checking with Hunspell dictionaries, which behaves the same on
+//! every platform, unlike the webview's. Dictionaries are `<language>.aff`
+//! and `<language>.dic` pairs, e.g. `en_US.aff`, bundled with the app or
+//! added by the user; words added to the personal dictionary are accepted
+//! in every language.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use serde::Serialize;
+use spellbook::Dictionary;
+use tauri::State;
+
+use crate::{
+    error::BackendError,
+    metrics,
+    workers::{self, Priority},
+};
+
+const MAX_SUGGESTIONS: usize = 5;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DictionaryInfo {
+    /// E.g. `en_US`.
+    language: String,
+    /// Whether it came with the app rather than being added.
+    bundled: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Misspelling {
+    /// In UTF-16 code units from the start of the text, like JavaScript
+    /// string indices.
+    offset: usize,
+    length: usize,
+    word: String,
+    suggestions: Vec<String>,
+}
+
+pub struct SpellChecker {
+    bundled_dir: PathBuf,
+    user_dir: PathBuf,
+    personal_path: PathBuf,
+    loaded: Mutex<HashMap<String, Arc<Dictionary>>>,
+    /// Loaded on first use.
+    personal: RwLock<Option<BTreeSet<String>>>,
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))
+}
+
+/// The languages with both files in `dir`.
+fn languages(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else { return Vec::new() };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "dic") && path.with_extension("aff").exists())
+        .filter_map(|path| path.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .collect()
+}
+
+fn is_apostrophe(c: char) -> bool {
+    c == '\'' || c == '’'
+}
+
+/// Runs of `text` matching `inside`, with their byte offsets.
+fn runs(text: &str, inside: impl Fn(char) -> bool) -> Vec<(usize, &str)> {
+    let mut result = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (inside(c) && i < text.len(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                result.push((s, &text[s..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// The words of `text` with their byte offsets. Apostrophes inside words
+/// belong to them; words with digits, and URLs and email addresses, are
+/// skipped.
+pub(crate) fn words(text: &str) -> Vec<(usize, &str)> {
+    let mut result = Vec::new();
+    for (chunk_start, chunk) in runs(text, |c| !c.is_whitespace()) {
+        if chunk.contains("://") || chunk.contains('@') || chunk.starts_with("www.") {
+            continue;
+        }
+        for (start, part) in runs(chunk, |c| c.is_alphanumeric() || is_apostrophe(c)) {
+            let word = part.trim_start_matches(is_apostrophe);
+            let start = chunk_start + start + part.len() - word.len();
+            let word = word.trim_end_matches(is_apostrophe);
+            if !word.is_empty() && !word.chars().any(char::is_numeric) {
+                result.push((start, word));
+            }
+        }
+    }
+    result
+}
+
+impl SpellChecker {
+    pub fn new(bundled_dir: PathBuf, user_dir: PathBuf, personal_path: PathBuf) -> Self {
+        SpellChecker {
+            bundled_dir,
+            user_dir,
+            personal_path,
+            loaded: Mutex::new(HashMap::new()),
+            personal: RwLock::new(None),
+        }
+    }
+
+    fn list(&self) -> Vec<DictionaryInfo> {
+        let user = languages(&self.user_dir);
+        let mut result: Vec<_> = languages(&self.bundled_dir)
+            .into_iter()
+            .filter(|language| !user.contains(language))
+            .map(|language| DictionaryInfo { language, bundled: true })
+            .chain(user.iter().map(|language| DictionaryInfo { language: language.clone(), bundled: false }))
+            .collect();
+        result.sort_by(|a, b| a.language.cmp(&b.language));
+        result
+    }
+
+    /// `en-US` finds `en_US`, and `en` the first English dictionary.
+    fn resolve(&self, language: &str) -> Result<String, String> {
+        let wanted = language.replace('-', "_");
+        let available = self.list();
+        available
+            .iter()
+            .find(|d| d.language.eq_ignore_ascii_case(&wanted))
+            .or_else(|| available.iter().find(|d| {
+                d.language.split('_').next().is_some_and(|l| l.eq_ignore_ascii_case(&wanted))
+            }))
+            .map(|d| d.language.clone())
+            .ok_or_else(|| format!("no dictionary for {language}"))
+    }
+
+    fn dictionary(&self, language: &str) -> Result<Arc<Dictionary>, String> {
+        let language = self.resolve(language)?;
+        if let Some(dictionary) = self.loaded.lock().expect("dictionary lock poisoned").get(&language) {
+            return Ok(dictionary.clone());
+        }
+        log::info!("spellcheck: loading {language}");
+        // user dictionaries replace bundled ones of the same language
+        let dir = if languages(&self.user_dir).contains(&language) { &self.user_dir } else { &self.bundled_dir };
+        let aff = read(&dir.join(format!("{language}.aff")))?;
+        let dic = read(&dir.join(format!("{language}.dic")))?;
+        let dictionary = Arc::new(Dictionary::new(&aff, &dic).map_err(|e| format!("{language}: {e}"))?);
+        self.loaded
+            .lock()
+            .expect("dictionary lock poisoned")
+            .insert(language, dictionary.clone());
+        Ok(dictionary)
+    }
+
+    /// The languages of the dictionaries in memory.
+    pub fn loaded_languages(&self) -> Vec<String> {
+        let loaded = self.loaded.lock().expect("dictionary lock poisoned");
+        let mut languages: Vec<String> = loaded.keys().cloned().collect();
+        languages.sort();
+        languages
+    }
+
+    fn is_personal(&self, word: &str) -> bool {
+        let mut personal = self.personal.write().expect("personal dictionary lock poisoned");
+        let words = personal.get_or_insert_with(|| {
+            fs::read_to_string(&self.personal_path)
+                .unwrap_or_default()
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned)
+                .collect()
+        });
+        words.contains(word) || words.contains(&word.to_lowercase())
+    }
+
+    fn check(&self, text: &str, language: &str) -> Result<Vec<Misspelling>, String> {
+        let dictionary = self.dictionary(language)?;
+        let mut suggestions: HashMap<&str, Vec<String>> = HashMap::new();
+        let mut result = Vec::new();
+        // UTF-16 offset of `done` bytes
+        let (mut done, mut offset) = (0, 0);
+        for (start, word) in words(text) {
+            // dictionaries spell apostrophes the ASCII way
+            let normalized = word.replace('’', "'");
+            if dictionary.check(&normalized) || self.is_personal(word) || self.is_personal(&normalized) {
+                continue;
+            }
+            offset += text[done..start].encode_utf16().count();
+            done = start;
+            let suggested = suggestions.entry(word).or_insert_with(|| {
+                let mut out = Vec::new();
+                dictionary.suggest(&normalized, &mut out);
+                out.truncate(MAX_SUGGESTIONS);
+                out
+            });
+            result.push(Misspelling {
+                offset,
+                length: word.encode_utf16().count(),
+                word: word.to_owned(),
+                suggestions: suggested.clone(),
+            });
+        }
+        Ok(result)
+    }
+
+    fn add_word(&self, word: &str) -> Result<(), String> {
+        let word = word.trim();
+        if word.is_empty() || word.contains(char::is_whitespace) {
+            return Err(format!("not a single word: {word:?}"));
+        }
+        if self.is_personal(word) {
+            return Ok(());
+        }
+        if let Some(dir) = self.personal_path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+        }
+        fs::File::options()
+            .create(true)
+            .append(true)
+            .open(&self.personal_path)
+            .and_then(|mut file| writeln!(file, "{word}"))
+            .map_err(|e| format!("write {}: {e}", self.personal_path.display()))?;
+        if let Some(words) = &mut *self.personal.write().expect("personal dictionary lock poisoned") {
+            words.insert(word.to_owned());
+        }
+        Ok(())
+    }
+
+    /// Copies `<language>.aff` and `.dic` from next to `dic_path` into the
+    /// user's dictionaries; returns the language.
+    fn install(&self, dic_path: &Path) -> Result<String, String> {
+        let language = dic_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .ok_or_else(|| format!("not a dictionary: {}", dic_path.display()))?;
+        let aff_path = dic_path.with_extension("aff");
+        let (aff, dic) = (read(&aff_path)?, read(dic_path)?);
+        // fail now rather than when checking
+        Dictionary::new(&aff, &dic).map_err(|e| format!("{language}: {e}"))?;
+        fs::create_dir_all(&self.user_dir).map_err(|e| format!("create {}: {e}", self.user_dir.display()))?;
+        for (extension, data) in [("aff", &aff), ("dic", &dic)] {
+            let target = self.user_dir.join(format!("{language}.{extension}"));
+            fs::write(&target, data).map_err(|e| format!("write {}: {e}", target.display()))?;
+        }
+        self.loaded.lock().expect("dictionary lock poisoned").remove(&language);
+        Ok(language)
+    }
+}
+
+#[tauri::command]
+pub fn list_dictionaries(checker: State<'_, Arc<SpellChecker>>) -> Vec<DictionaryInfo> {
+    checker.list()
+}
+
+/// The misspelled words in `text`, with suggestions. `language` is e.g.
+/// `en_US`, `en-US` or just `en`.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn spell_check(
+    text: String, language: String, checker: State<'_, Arc<SpellChecker>>,
+) -> Result<Vec<Misspelling>, BackendError> {
+    let checker = checker.inner().clone();
+    let timer = metrics::timer("spell_check").size(text.len() as u64);
+    let result = workers::run(Priority::Interactive, move || checker.check(&text, &language)).await;
+    timer.finish(matches!(result, Ok(Ok(_))));
+    match result {
+        Ok(Ok(misspellings)) => Ok(misspellings),
+        Ok(Err(e)) => Err(format!("spell_check task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+/// Accepts `word` from now on, in every language.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn add_to_dictionary(word: String, checker: State<'_, Arc<SpellChecker>>) -> Result<(), BackendError> {
+    log::info!("add_to_dictionary: {word}");
+    Ok(checker.add_word(&word)?)
+}
+
+/// Adds the Hunspell dictionary at `dic_path`, with its `.aff` file next
+/// to it; returns its language.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn install_dictionary(
+    dic_path: String, checker: State<'_, Arc<SpellChecker>>,
+) -> Result<String, BackendError> {
+    log::info!("install_dictionary start: {dic_path}");
+    let checker = checker.inner().clone();
+    let result = workers::run(Priority::Interactive, move || checker.install(Path::new(&dic_path))).await;
+    match result {
+        Ok(Ok(language)) => {
+            log::info!("install_dictionary done: {language}");
+            Ok(language)
+        }
+        Ok(Err(e)) => Err(format!("install_dictionary task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_english_checks_words() {
+        let bundled = Path::new(env!("CARGO_MANIFEST_DIR")).join("dictionaries");
+        let personal = std::env::temp_dir().join("emmm-spellcheck-test-personal.txt");
+        let checker = SpellChecker::new(bundled, PathBuf::from("/nonexistent"), personal);
+        let found = checker.check("The quick brwn fox’s adventures", "en-US").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].offset, found[0].length, found[0].word.as_str()), (10, 4, "brwn"));
+        assert!(found[0].suggestions.iter().any(|s| s == "brown"), "{:?}", found[0].suggestions);
+    }
+}
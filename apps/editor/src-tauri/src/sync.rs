@@ -0,0 +1,934 @@
+//! Two-way mirroring of a workspace with a WebDAV collection (Nextcloud,
+//! ownCloud, ...).
+//!
+//! Each run compares the local files and the remote listing with the state
+//! saved after the previous run: a file changed on one side only is copied
+//! over, one deleted on one side only is deleted on the other, and one
+//! changed on both keeps the local version while the remote one is saved
+//! next to it as a conflict copy.
+//!
+//! With encryption turned on, files are encrypted before they are uploaded
+//! (see [`crate::encryption`]) and the wrapped keys are stored in the
+//! collection as `.emmm-keys.json`.
+//!
+//! Sync can be paused, e.g. on a metered connection: running syncs are
+//! cancelled and new ones refused until it's resumed.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::UNIX_EPOCH,
+};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::Url;
+
+use crate::{
+    encryption::{self, Keyring, Keys},
+    error::BackendError,
+    flags::{self, Flag},
+    jobs::{self, JobKind},
+    metrics,
+    net::{self, reqwest},
+    secrets, send,
+    workers::Priority,
+    workspace::{self, write_atomic},
+    BackendEvent, EventChannel,
+};
+
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+const STATE_FILE: &str = ".emmm/webdav-sync.json";
+const KEYRING_FILE: &str = ".emmm-keys.json";
+const DEFAULT_PASSPHRASE_SECRET: &str = "sync-passphrase";
+const MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Characters escaped in a path segment.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'/').add(b'<').add(b'>')
+    .add(b'?').add(b'[').add(b']').add(b'\\').add(b'^').add(b'`').add(b'{')
+    .add(b'|').add(b'}');
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getetag/></d:prop></d:propfind>"#;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebDavConfig {
+    /// The collection mirroring the workspace root.
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    /// Encrypts the files on the server if set.
+    encryption: Option<EncryptionConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptionConfig {
+    /// Keychain entry with the passphrase; `sync-passphrase` by default.
+    secret_name: Option<String>,
+}
+
+impl EncryptionConfig {
+    fn secret_name(&self) -> &str {
+        self.secret_name.as_deref().unwrap_or(DEFAULT_PASSPHRASE_SECRET)
+    }
+
+    fn passphrase(&self) -> Result<String, String> {
+        let name = self.secret_name();
+        secrets::read(name)?.ok_or_else(|| format!("no sync passphrase stored as {name}"))
+    }
+}
+
+/// A file as of the last successful sync.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileState {
+    hash: String,
+    etag: Option<String>,
+    /// Modification time (ms) and size the hash was computed for, so
+    /// unchanged files need not be read again.
+    modified: u64,
+    size: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct SyncState {
+    url: String,
+    files: BTreeMap<String, FileState>,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncSummary {
+    uploaded: usize,
+    downloaded: usize,
+    deleted_local: usize,
+    deleted_remote: usize,
+    /// Workspace-relative paths of the conflict copies created.
+    conflicts: Vec<String>,
+    /// Files that failed; they are retried on the next sync.
+    errors: Vec<String>,
+}
+
+enum Action {
+    Upload,
+    Download,
+    DeleteLocal,
+    DeleteRemote,
+    /// Changed on both sides, or present on both without a previous state.
+    Reconcile,
+    Forget,
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+struct WebDav {
+    client: reqwest::blocking::Client,
+    base: Url,
+    username: Option<String>,
+    password: Option<String>,
+    /// Collections known to exist.
+    collections: HashSet<String>,
+    /// Set once unlocked, if the files are encrypted.
+    keys: Option<Keys>,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RotationSummary {
+    rewrapped: usize,
+    /// Files uploaded before encryption was turned on.
+    encrypted: usize,
+    errors: Vec<String>,
+}
+
+impl WebDav {
+    fn new(config: &WebDavConfig) -> Result<Self, String> {
+        let mut url = config.url.trim().to_owned();
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        let base = Url::parse(&url).map_err(|e| format!("invalid url {url}: {e}"))?;
+        if !matches!(base.scheme(), "http" | "https") {
+            return Err(format!("not an http(s) url: {url}"));
+        }
+        Ok(WebDav {
+            client: net::blocking_client()?,
+            base,
+            username: config.username.clone().filter(|u| !u.is_empty()),
+            password: config.password.clone(),
+            collections: HashSet::new(),
+            keys: None,
+        })
+    }
+
+    fn url(&self, path: &str) -> Url {
+        let encoded: Vec<_> = path
+            .split('/')
+            .map(|s| utf8_percent_encode(s, SEGMENT).to_string())
+            .collect();
+        let mut url = self.base.clone();
+        url.set_path(&format!("{}{}", self.base.path(), encoded.join("/")));
+        url
+    }
+
+    fn request(&self, method: &str, path: &str) -> reqwest::blocking::RequestBuilder {
+        let method = reqwest::Method::from_bytes(method.as_bytes()).expect("valid method");
+        let builder = self.client.request(method, self.url(path));
+        match &self.username {
+            Some(user) => builder.basic_auth(user, self.password.as_deref()),
+            None => builder,
+        }
+    }
+
+    fn send(
+        builder: reqwest::blocking::RequestBuilder, what: &str,
+    ) -> Result<reqwest::blocking::Response, String> {
+        let response = builder.send().map_err(|e| format!("{what}: {e}"))?;
+        Self::check(response, what)
+    }
+
+    fn check(
+        response: reqwest::blocking::Response, what: &str,
+    ) -> Result<reqwest::blocking::Response, String> {
+        let status = response.status();
+        if status.is_success() {
+            Ok(response)
+        } else if status == reqwest::StatusCode::PRECONDITION_FAILED {
+            Err(format!("{what}: changed on the server during sync"))
+        } else {
+            Err(format!("{what}: server returned {status}"))
+        }
+    }
+
+    /// Lists every file below the base collection with its etag, skipping
+    /// hidden files and folders like the local scan does.
+    fn list(&mut self) -> Result<BTreeMap<String, Option<String>>, String> {
+        let mut files = BTreeMap::new();
+        let mut pending = vec![String::new()];
+        while let Some(dir) = pending.pop() {
+            let response = self
+                .request("PROPFIND", &dir)
+                .header("Depth", "1")
+                .header(reqwest::header::CONTENT_TYPE, "application/xml")
+                .body(PROPFIND_BODY)
+                .send()
+                .map_err(|e| format!("PROPFIND /{dir}: {e}"))?;
+            if dir.is_empty() && response.status() == reqwest::StatusCode::NOT_FOUND {
+                // first sync to a new folder
+                Self::send(self.request("MKCOL", ""), "MKCOL /")?;
+                self.collections.insert(dir);
+                continue;
+            }
+            let response = Self::check(response, &format!("PROPFIND /{dir}"))?;
+            let xml = response.text().map_err(|e| format!("PROPFIND /{dir}: {e}"))?;
+            self.collections.insert(dir.clone());
+            for (path, collection, etag) in self.parse_multistatus(&xml)? {
+                if path == dir || path.split('/').any(|s| s.starts_with('.')) {
+                    continue;
+                }
+                if collection {
+                    pending.push(path);
+                } else {
+                    files.insert(path, etag);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// `(path, is collection, etag)` for each response, with paths relative
+    /// to the base and without a trailing slash.
+    fn parse_multistatus(&self, xml: &str) -> Result<Vec<(String, bool, Option<String>)>, String> {
+        let document = roxmltree::Document::parse(xml)
+            .map_err(|e| format!("invalid PROPFIND response: {e}"))?;
+        let dav = |node: &roxmltree::Node, name: &str| {
+            node.is_element()
+                && node.tag_name().name() == name
+                && node.tag_name().namespace() == Some("DAV:")
+        };
+        let mut result = Vec::new();
+        for response in document.descendants().filter(|n| dav(n, "response")) {
+            let Some(href) = response
+                .children()
+                .find(|n| dav(n, "href"))
+                .and_then(|n| n.text())
+            else {
+                continue;
+            };
+            let Ok(url) = self.base.join(href.trim()) else { continue };
+            let Some(relative) = url.path().strip_prefix(self.base.path()) else {
+                continue;
+            };
+            let Ok(relative) = percent_decode_str(relative).decode_utf8() else {
+                continue;
+            };
+            let ok_props = response
+                .children()
+                .filter(|n| dav(n, "propstat"))
+                .filter(|n| {
+                    n.children()
+                        .find(|s| dav(s, "status"))
+                        .and_then(|s| s.text())
+                        .is_none_or(|s| s.contains(" 200 "))
+                })
+                .flat_map(|n| n.children().filter(|p| dav(p, "prop")).collect::<Vec<_>>());
+            let mut collection = false;
+            let mut etag = None;
+            for prop in ok_props.flat_map(|p| p.children().collect::<Vec<_>>()) {
+                if dav(&prop, "resourcetype") {
+                    collection |= prop.children().any(|c| dav(&c, "collection"));
+                } else if dav(&prop, "getetag") {
+                    etag = prop.text().map(|t| t.trim().to_owned());
+                }
+            }
+            result.push((relative.trim_end_matches('/').to_owned(), collection, etag));
+        }
+        Ok(result)
+    }
+
+    fn etag(response: &reqwest::blocking::Response) -> Option<String> {
+        response
+            .headers()
+            .get(reqwest::header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned)
+    }
+
+    fn get(&self, path: &str) -> Result<(Vec<u8>, Option<String>), String> {
+        let response = Self::send(self.request("GET", path), &format!("GET /{path}"))?;
+        let etag = Self::etag(&response);
+        let data = net::read_capped_blocking(response, MAX_FILE_SIZE)?;
+        Ok((data, etag))
+    }
+
+    /// Downloads and decrypts. Files uploaded before encryption was turned
+    /// on are still plain text.
+    fn download(&self, path: &str) -> Result<(Vec<u8>, Option<String>), String> {
+        let (data, etag) = self.get(path)?;
+        let encrypted = encryption::is_encrypted(&data);
+        let data = match &self.keys {
+            Some(keys) if encrypted => keys.decrypt(path, &data)
+                .map_err(|e| format!("decrypt /{path}: {e}"))?,
+            None if encrypted =>
+                return Err(format!("/{path} is encrypted; turn on encryption to sync it")),
+            _ => data,
+        };
+        Ok((data, etag))
+    }
+
+    /// Uses the keyring on the server, creating one on the first sync.
+    fn unlock(&mut self, passphrase: &str) -> Result<(), String> {
+        let keys = match self.keyring()? {
+            Some((keyring, _)) => keyring.unlock(passphrase)?,
+            None => {
+                let keys = Keys::new()?;
+                self.put_keyring(&keys.lock(passphrase)?, None)?;
+                keys
+            }
+        };
+        self.keys = Some(keys);
+        Ok(())
+    }
+
+    fn keyring(&self) -> Result<Option<(Keyring, Option<String>)>, String> {
+        let response = self
+            .request("GET", KEYRING_FILE)
+            .send()
+            .map_err(|e| format!("GET /{KEYRING_FILE}: {e}"))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = Self::check(response, &format!("GET /{KEYRING_FILE}"))?;
+        let etag = Self::etag(&response);
+        let data = net::read_capped_blocking(response, MAX_FILE_SIZE)?;
+        let keyring = serde_json::from_slice(&data)
+            .map_err(|e| format!("invalid {KEYRING_FILE}: {e}"))?;
+        Ok(Some((keyring, etag)))
+    }
+
+    fn put_keyring(&mut self, keyring: &Keyring, etag: Option<&str>) -> Result<Option<String>, String> {
+        let json = serde_json::to_vec_pretty(keyring).map_err(|e| format!("serialize: {e}"))?;
+        self.put(KEYRING_FILE, json, etag)
+    }
+
+    fn ensure_parents(&mut self, path: &str) -> Result<(), String> {
+        let segments: Vec<_> = path.split('/').collect();
+        for i in 1..segments.len() {
+            let dir = segments[..i].join("/");
+            if self.collections.contains(&dir) {
+                continue;
+            }
+            let response = self
+                .request("MKCOL", &dir)
+                .send()
+                .map_err(|e| format!("MKCOL /{dir}: {e}"))?;
+            // 405: it exists already
+            let status = response.status();
+            if !status.is_success() && status != reqwest::StatusCode::METHOD_NOT_ALLOWED {
+                return Err(format!("MKCOL /{dir}: server returned {status}"));
+            }
+            self.collections.insert(dir);
+        }
+        Ok(())
+    }
+
+    /// Encrypts if turned on and uploads, see [`WebDav::put`].
+    fn upload(
+        &mut self, path: &str, data: Vec<u8>, etag: Option<&str>,
+    ) -> Result<Option<String>, String> {
+        let data = match &self.keys {
+            Some(keys) => keys.encrypt(path, &data)?,
+            None => data,
+        };
+        self.put(path, data, etag)
+    }
+
+    /// Uploads unless the remote file changed since `etag` was seen (or, if
+    /// `etag` is `None`, unless something was created there meanwhile).
+    /// Returns the new etag.
+    fn put(
+        &mut self, path: &str, data: Vec<u8>, etag: Option<&str>,
+    ) -> Result<Option<String>, String> {
+        self.ensure_parents(path)?;
+        let builder = self.request("PUT", path).body(data);
+        let builder = match etag {
+            Some(etag) => builder.header(reqwest::header::IF_MATCH, etag),
+            None => builder.header(reqwest::header::IF_NONE_MATCH, "*"),
+        };
+        let response = Self::send(builder, &format!("PUT /{path}"))?;
+        if let Some(etag) = Self::etag(&response) {
+            return Ok(Some(etag));
+        }
+        // not all servers return it for PUT
+        let response = Self::send(
+            self.request("PROPFIND", path)
+                .header("Depth", "0")
+                .header(reqwest::header::CONTENT_TYPE, "application/xml")
+                .body(PROPFIND_BODY),
+            &format!("PROPFIND /{path}"))?;
+        let xml = response.text().map_err(|e| format!("PROPFIND /{path}: {e}"))?;
+        Ok(self.parse_multistatus(&xml)?.into_iter().find_map(|(_, _, etag)| etag))
+    }
+
+    fn delete(&self, path: &str, etag: Option<&str>) -> Result<(), String> {
+        let mut builder = self.request("DELETE", path);
+        if let Some(etag) = etag {
+            builder = builder.header(reqwest::header::IF_MATCH, etag);
+        }
+        let response = builder.send().map_err(|e| format!("DELETE /{path}: {e}"))?;
+        match response.status() {
+            s if s.is_success() || s == reqwest::StatusCode::NOT_FOUND => Ok(()),
+            reqwest::StatusCode::PRECONDITION_FAILED =>
+                Err(format!("DELETE /{path}: changed on the server during sync")),
+            s => Err(format!("DELETE /{path}: server returned {s}")),
+        }
+    }
+}
+
+fn modified_ms(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .and_then(|d| u64::try_from(d.as_millis()).ok())
+        .unwrap_or_default()
+}
+
+/// Name for the remote version of `path` when both sides changed, e.g.
+/// `notes/a (conflict 2025-01-31 142530).md`.
+fn conflict_path(path: &str) -> String {
+    let stamp = time::OffsetDateTime::now_local()
+        .unwrap_or_else(|_| time::OffsetDateTime::now_utc())
+        .format(time::macros::format_description!(
+            "[year]-[month]-[day] [hour][minute][second]"))
+        .unwrap_or_default();
+    let (dir, name) = path.rsplit_once('/').map_or(("", path), |(d, n)| (d, n));
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (name, String::new()),
+    };
+    let name = format!("{stem} (conflict {stamp}){ext}");
+    if dir.is_empty() { name } else { format!("{dir}/{name}") }
+}
+
+struct Syncer<'a> {
+    root: &'a Path,
+    remote: WebDav,
+    /// Set if the files are encrypted.
+    passphrase: Option<String>,
+    state: SyncState,
+    /// Content hash of each local file.
+    local: BTreeMap<String, String>,
+    summary: SyncSummary,
+}
+
+impl<'a> Syncer<'a> {
+    fn new(root: &'a Path, config: &WebDavConfig) -> Result<Self, String> {
+        Ok(Syncer {
+            root,
+            remote: WebDav::new(config)?,
+            passphrase: config.encryption.as_ref().map(EncryptionConfig::passphrase).transpose()?,
+            state: SyncState::default(),
+            local: BTreeMap::new(),
+            summary: SyncSummary::default(),
+        })
+    }
+
+    fn local_path(&self, path: &str) -> PathBuf {
+        path.split('/').fold(self.root.to_owned(), |p, s| p.join(s))
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.root.join(STATE_FILE)
+    }
+
+    fn load_state(&mut self) {
+        let url = self.remote.base.to_string();
+        self.state = fs::read(self.state_path())
+            .ok()
+            .and_then(|data| serde_json::from_slice::<SyncState>(&data).ok())
+            // state for a different server says nothing about this one
+            .filter(|state| state.url == url)
+            .unwrap_or(SyncState { url, files: BTreeMap::new() });
+    }
+
+    fn save_state(&self) -> Result<(), String> {
+        let path = self.state_path();
+        let dir = path.parent().expect("state file has a parent");
+        fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+        let json = serde_json::to_vec_pretty(&self.state)
+            .map_err(|e| format!("serialize: {e}"))?;
+        write_atomic(&path, &json)
+    }
+
+    fn scan_local(&mut self) -> Result<(), String> {
+        // a file that became ignored would otherwise be deleted remotely
+        for path in workspace::all_files(self.root)? {
+            let Some(relative) = workspace::relative_url_path(self.root, &path) else {
+                continue;
+            };
+            let metadata = fs::metadata(&path)
+                .map_err(|e| format!("metadata {}: {e}", path.display()))?;
+            let modified = modified_ms(&metadata);
+            let size = metadata.len();
+            let hash = match self.state.files.get(&relative) {
+                Some(known) if known.modified == modified && known.size == size =>
+                    known.hash.clone(),
+                _ => sha256(&fs::read(&path)
+                    .map_err(|e| format!("read {}: {e}", path.display()))?),
+            };
+            self.local.insert(relative, hash);
+        }
+        Ok(())
+    }
+
+    fn plan(&self, remote: &BTreeMap<String, Option<String>>) -> Vec<(String, Action)> {
+        let paths: BTreeSet<_> = self.local.keys()
+            .chain(remote.keys())
+            .chain(self.state.files.keys())
+            .cloned()
+            .collect();
+        paths.into_iter().filter_map(|path| {
+            let local = self.local.get(&path);
+            let etag = remote.get(&path);
+            let action = match (self.state.files.get(&path), local, etag) {
+                (Some(known), Some(hash), Some(etag)) => {
+                    match (*hash != known.hash, *etag != known.etag) {
+                        (false, false) => return None,
+                        (true, false) => Action::Upload,
+                        (false, true) => Action::Download,
+                        (true, true) => Action::Reconcile,
+                    }
+                }
+                (Some(known), Some(hash), None) if *hash == known.hash => Action::DeleteLocal,
+                (Some(known), None, Some(etag)) if *etag == known.etag => Action::DeleteRemote,
+                (Some(_), None, None) => Action::Forget,
+                (_, Some(_), None) => Action::Upload,
+                (_, None, Some(_)) => Action::Download,
+                (None, Some(_), Some(_)) => Action::Reconcile,
+                (None, None, None) => return None,
+            };
+            Some((path, action))
+        }).collect()
+    }
+
+    fn remember(&mut self, path: &str, data: &[u8], etag: Option<String>) -> Result<(), String> {
+        let local = self.local_path(path);
+        let metadata = fs::metadata(&local)
+            .map_err(|e| format!("metadata {}: {e}", local.display()))?;
+        self.state.files.insert(path.to_owned(), FileState {
+            hash: sha256(data),
+            etag,
+            modified: modified_ms(&metadata),
+            size: metadata.len(),
+        });
+        Ok(())
+    }
+
+    fn read_local(&self, path: &str) -> Result<Vec<u8>, String> {
+        let local = self.local_path(path);
+        fs::read(&local).map_err(|e| format!("read {}: {e}", local.display()))
+    }
+
+    fn write_local(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        let local = self.local_path(path);
+        if let Some(dir) = local.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+        }
+        write_atomic(&local, data)
+    }
+
+    fn upload(&mut self, path: &str, etag: Option<&str>) -> Result<(), String> {
+        let data = self.read_local(path)?;
+        let etag = self.remote.upload(path, data.clone(), etag)?;
+        self.remember(path, &data, etag)?;
+        self.summary.uploaded += 1;
+        Ok(())
+    }
+
+    fn run(&mut self, path: &str, action: &Action, remote: &BTreeMap<String, Option<String>>)
+        -> Result<(), String>
+    {
+        let known_etag = || self.state.files.get(path).and_then(|f| f.etag.clone());
+        let remote_etag = remote.get(path).cloned().flatten();
+        match action {
+            Action::Upload => {
+                let etag = if remote.contains_key(path) { remote_etag } else { None };
+                self.upload(path, etag.as_deref())?;
+            }
+            Action::Download => {
+                let (data, etag) = self.remote.download(path)?;
+                self.write_local(path, &data)?;
+                self.remember(path, &data, etag.or(remote_etag))?;
+                self.summary.downloaded += 1;
+            }
+            Action::DeleteLocal => {
+                let local = self.local_path(path);
+                fs::remove_file(&local)
+                    .map_err(|e| format!("remove {}: {e}", local.display()))?;
+                self.state.files.remove(path);
+                self.summary.deleted_local += 1;
+            }
+            Action::DeleteRemote => {
+                self.remote.delete(path, known_etag().as_deref())?;
+                self.state.files.remove(path);
+                self.summary.deleted_remote += 1;
+            }
+            Action::Reconcile => {
+                let (data, etag) = self.remote.download(path)?;
+                let etag = etag.or(remote_etag);
+                if sha256(&data) == self.local[path] {
+                    // same content on both sides
+                    self.remember(path, &data, etag)?;
+                    return Ok(());
+                }
+                let copy = conflict_path(path);
+                self.write_local(&copy, &data)?;
+                let copy_etag = self.remote.upload(&copy, data.clone(), None)?;
+                self.remember(&copy, &data, copy_etag)?;
+                self.summary.conflicts.push(copy);
+                self.upload(path, etag.as_deref())?;
+            }
+            Action::Forget => {
+                self.state.files.remove(path);
+            }
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self, channel: &EventChannel) -> Result<(), String> {
+        self.load_state();
+        self.scan_local()?;
+        let remote = self.remote.list()?;
+        if let Some(passphrase) = &self.passphrase {
+            self.remote.unlock(passphrase)?;
+        }
+        let plan = self.plan(&remote);
+        let total = plan.len();
+        for (i, (path, action)) in plan.iter().enumerate() {
+            jobs::checkpoint()?;
+            jobs::progress(i, total, path);
+            send(channel, BackendEvent::Progress {
+                done: i, total, message: path.clone() });
+            if let Err(e) = self.run(path, action, &remote) {
+                log::warn!("sync_now: {path}: {e}");
+                self.summary.errors.push(format!("{path}: {e}"));
+            }
+        }
+        send(channel, BackendEvent::Progress { done: total, total, message: String::new() });
+        self.save_state()
+    }
+
+    /// Rewraps the key of the file at `path` with the current key, or
+    /// encrypts it if it isn't yet. Returns whether it was plain text.
+    fn rewrap(&mut self, keys: &Keys, path: &str) -> Result<bool, String> {
+        let (data, etag) = self.remote.get(path)?;
+        let plain = !encryption::is_encrypted(&data);
+        let rewrapped = if plain { Some(keys.encrypt(path, &data)?) } else { keys.rewrap(&data)? };
+        let Some(rewrapped) = rewrapped else { return Ok(false) };
+        let new_etag = self.remote.put(path, rewrapped, etag.as_deref())?;
+        // the content is the same, so the next sync needn't download it
+        if let Some(known) = self.state.files.get_mut(path) {
+            if known.etag == etag {
+                known.etag = new_etag;
+            }
+        }
+        Ok(plain)
+    }
+
+    /// Switches to a new workspace key, locked with `new_passphrase`, which
+    /// replaces the one in the keychain entry `secret_name`, and rewraps
+    /// every file with it; the old keys are deleted once all files are done.
+    fn rotate(
+        &mut self, secret_name: &str, new_passphrase: &str, channel: &EventChannel,
+    ) -> Result<RotationSummary, String> {
+        let old_passphrase = self.passphrase.clone().ok_or("encryption is turned off".to_owned())?;
+        if new_passphrase.is_empty() {
+            return Err("the new passphrase is empty".to_owned());
+        }
+        // whoever has the old one could still unlock the new key
+        if new_passphrase == old_passphrase {
+            return Err("the new passphrase is the same as the old one".to_owned());
+        }
+        self.load_state();
+        let remote = self.remote.list()?;
+        let (mut keys, etag) = match self.remote.keyring()? {
+            Some((keyring, etag)) => (keyring.unlock(&old_passphrase)?, etag),
+            None => (Keys::new()?, None),
+        };
+        keys.rotate()?;
+        let passphrase = new_passphrase.to_owned();
+        let etag = self.remote.put_keyring(&keys.lock(&passphrase)?, etag.as_deref())?;
+        // from now on only the new one unlocks the keys
+        secrets::write(secret_name, &passphrase)?;
+        self.passphrase = Some(passphrase.clone());
+
+        let mut summary = RotationSummary::default();
+        let total = remote.len();
+        for (i, path) in remote.keys().enumerate() {
+            jobs::checkpoint()?;
+            jobs::progress(i, total, path);
+            send(channel, BackendEvent::Progress { done: i, total, message: path.clone() });
+            match self.rewrap(&keys, path) {
+                Ok(true) => summary.encrypted += 1,
+                Ok(false) => summary.rewrapped += 1,
+                Err(e) => {
+                    log::warn!("rotate_sync_key: {path}: {e}");
+                    summary.errors.push(format!("{path}: {e}"));
+                }
+            }
+        }
+        send(channel, BackendEvent::Progress { done: total, total, message: String::new() });
+        if summary.errors.is_empty() {
+            keys.retire_old();
+            self.remote.put_keyring(&keys.lock(&passphrase)?, etag.as_deref())?;
+        }
+        self.save_state()?;
+        Ok(summary)
+    }
+}
+
+/// Mirrors `workspace` with the WebDAV collection in `config`, reporting
+/// each file handled as a `Progress` event, then `Done`. A failure on one
+/// file doesn't stop the others; it is listed in the summary instead.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn sync_now(
+    workspace: String, config: WebDavConfig, channel: EventChannel,
+) -> Result<SyncSummary, BackendError> {
+    log::info!("sync_now start: {workspace} <-> {}", config.url);
+    flags::require(Flag::Sync)?;
+    if paused() {
+        return Err(BackendError::invalid("sync_now: sync is paused"));
+    }
+    let job = jobs::start_as(channel.job_id(), JobKind::Sync, Priority::Background, &workspace);
+    let timer = metrics::timer("sync_now");
+    let result = tokio::task::spawn_blocking(move || job.run(|| {
+        let mut syncer = Syncer::new(Path::new(&workspace), &config)?;
+        syncer.sync(&channel)?;
+        send(&channel, BackendEvent::Done);
+        Ok(syncer.summary)
+    })).await;
+    timer.finish(matches!(result, Ok(Ok(_))));
+
+    match result {
+        Ok(Ok(summary)) => {
+            log::info!("sync_now done");
+            Ok(summary)
+        }
+        Ok(Err(e)) => Err(format!("sync_now task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Replaces the key the files of `workspace` are encrypted with on the
+/// server, and the passphrase it's locked with by `new_passphrase`, which
+/// is stored in the keychain in place of the old one: after a device was
+/// lost, what it knows no longer unlocks the files. Other devices need the
+/// new passphrase before their next sync. Reports each file as a
+/// `Progress` event, then `Done`. Files uploaded before encryption was
+/// turned on are encrypted along the way.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn rotate_sync_key(
+    workspace: String, config: WebDavConfig, new_passphrase: String, channel: EventChannel,
+) -> Result<RotationSummary, BackendError> {
+    log::info!("rotate_sync_key start: {}", config.url);
+    flags::require(Flag::Sync)?;
+    let job = jobs::start_as(channel.job_id(), JobKind::Sync, Priority::Background, &workspace);
+    let result = tokio::task::spawn_blocking(move || job.run(|| {
+        let encryption = config.encryption.as_ref().ok_or("encryption is turned off".to_owned())?;
+        let mut syncer = Syncer::new(Path::new(&workspace), &config)?;
+        let summary = syncer.rotate(encryption.secret_name(), &new_passphrase, &channel)?;
+        send(&channel, BackendEvent::Done);
+        Ok(summary)
+    })).await;
+
+    match result {
+        Ok(Ok(summary)) => {
+            log::info!("rotate_sync_key done");
+            Ok(summary)
+        }
+        Ok(Err(e)) => Err(format!("rotate_sync_key task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Rewraps the workspace keys on the server with `passphrase` and stores
+/// it in the keychain in place of the old one. Other devices need the new
+/// passphrase before their next sync.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn change_sync_passphrase(config: WebDavConfig, passphrase: String) -> Result<(), BackendError> {
+    log::info!("change_sync_passphrase start: {}", config.url);
+    flags::require(Flag::Sync)?;
+    let result = tokio::task::spawn_blocking(move || {
+        let encryption = config.encryption.as_ref().ok_or("encryption is turned off".to_owned())?;
+        if passphrase.is_empty() {
+            return Err("the passphrase is empty".to_owned());
+        }
+        let mut remote = WebDav::new(&config)?;
+        let (keyring, etag) = remote
+            .keyring()?
+            .ok_or("nothing is encrypted on the server yet; sync first".to_owned())?;
+        let keys = keyring.unlock(&encryption.passphrase()?)?;
+        remote.put_keyring(&keys.lock(&passphrase)?, etag.as_deref())?;
+        secrets::write(encryption.secret_name(), &passphrase)
+    }).await;
+
+    match result {
+        Ok(Ok(())) => {
+            log::info!("change_sync_passphrase done");
+            Ok(())
+        }
+        Ok(Err(e)) => Err(format!("change_sync_passphrase task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+/// Pauses or resumes syncing; pausing cancels the syncs running.
+#[tauri::command]
+pub fn set_sync_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::Relaxed);
+    let cancelled = if paused { jobs::cancel_kind(JobKind::Sync) } else { 0 };
+    log::info!("set_sync_paused: {paused}, {cancelled} cancelled");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(url: &str) -> WebDavConfig {
+        WebDavConfig { url: url.to_owned(), username: None, password: None, encryption: None }
+    }
+
+    #[test]
+    fn listings_are_relative_to_the_collection() {
+        let dav = WebDav::new(&config("https://dav.example.com/remote.php/dav/files/me/notes")).unwrap();
+        assert_eq!(dav.url("a b/c#1.md").as_str(),
+            "https://dav.example.com/remote.php/dav/files/me/notes/a%20b/c%231.md");
+        let xml = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:">
+  <d:response><d:href>/remote.php/dav/files/me/notes/</d:href>
+    <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+  <d:response><d:href>/remote.php/dav/files/me/notes/a%20b/</d:href>
+    <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+  <d:response><d:href>https://dav.example.com/remote.php/dav/files/me/notes/c.md</d:href>
+    <d:propstat><d:prop><d:resourcetype/><d:getetag>"1"</d:getetag></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+    <d:propstat><d:prop><d:getetag>"2"</d:getetag></d:prop>
+      <d:status>HTTP/1.1 404 Not Found</d:status></d:propstat></d:response>
+  <d:response><d:href>/elsewhere/d.md</d:href></d:response>
+</d:multistatus>"#;
+        let entries = dav.parse_multistatus(xml).unwrap();
+        assert_eq!(entries, [
+            (String::new(), true, None),
+            ("a b".to_owned(), true, None),
+            ("c.md".to_owned(), false, Some("\"1\"".to_owned())),
+        ]);
+        assert!(WebDav::new(&config("ftp://dav.example.com/")).is_err());
+    }
+
+    #[test]
+    fn changes_are_planned_against_the_last_sync() {
+        let root = Path::new("/nonexistent");
+        let mut syncer = Syncer::new(root, &config("https://dav.example.com/")).unwrap();
+        let known = |hash: &str, etag: &str| FileState {
+            hash: hash.to_owned(), etag: Some(etag.to_owned()), modified: 0, size: 0,
+        };
+        for path in ["same", "local", "remote", "both", "gone-remote", "gone-local", "gone"] {
+            syncer.state.files.insert(path.to_owned(), known("h", "e"));
+        }
+        for (path, hash) in [("same", "h"), ("local", "h2"), ("remote", "h"), ("both", "h2"), ("gone-remote", "h"),
+                             ("new-local", "h"), ("new-both", "h")] {
+            syncer.local.insert(path.to_owned(), hash.to_owned());
+        }
+        let remote: BTreeMap<_, _> = [("same", "e"), ("local", "e"), ("remote", "e2"), ("both", "e2"),
+                                      ("gone-local", "e"), ("new-remote", "e"), ("new-both", "e")]
+            .into_iter()
+            .map(|(path, etag)| (path.to_owned(), Some(etag.to_owned())))
+            .collect();
+
+        let plan: Vec<_> = syncer.plan(&remote).into_iter().map(|(path, action)| {
+            let action = match action {
+                Action::Upload => "upload",
+                Action::Download => "download",
+                Action::DeleteLocal => "delete local",
+                Action::DeleteRemote => "delete remote",
+                Action::Reconcile => "reconcile",
+                Action::Forget => "forget",
+            };
+            (path, action)
+        }).collect();
+        let expected = [
+            ("both", "reconcile"), ("gone", "forget"), ("gone-local", "delete remote"),
+            ("gone-remote", "delete local"), ("local", "upload"), ("new-both", "reconcile"),
+            ("new-local", "upload"), ("new-remote", "download"), ("remote", "download"),
+        ];
+        assert_eq!(plan, expected.map(|(p, a)| (p.to_owned(), a)));
+    }
+
+    #[test]
+    fn conflict_copies_keep_the_extension() {
+        let copy = conflict_path("notes/a.md");
+        assert!(copy.starts_with("notes/a (conflict ") && copy.ends_with(").md"), "{copy}");
+        let copy = conflict_path(".env");
+        assert!(copy.starts_with(".env (conflict ") && copy.ends_with(')'), "{copy}");
+    }
+}
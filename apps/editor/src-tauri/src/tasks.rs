@@ -0,0 +1,247 @@
+//! Tasks across the workspace for the task panel: `- [ ]` checkboxes and
+//! `TODO:` markers, with due dates written like `due: 2025-03-01`,
+//! `@due(2025-03-01)` or `📅 2025-03-01`. Marking a `TODO:` done makes it
+//! `DONE:`.
+
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, LazyLock},
+};
+
+use regex::Regex;
+use serde::Serialize;
+use tauri::State;
+use time::{macros::format_description, Date};
+
+use crate::{error::BackendError, frontmatter, index::WorkspaceIndex, workspace};
+
+static CHECKBOX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^([ \t]*(?:[-*+]|\d+[.)])[ \t]+\[)([ xX])(\][ \t]+)(.*)").expect("checkbox regex")
+});
+static MARKER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b(TODO|DONE):[ \t]*").expect("todo regex"));
+static DUE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(?:\bdue:?[ \t]*|@due\(|📅[ \t]*)(\d{4}-\d{2}-\d{2})").expect("due date regex")
+});
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskKind {
+    Checkbox,
+    Todo,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Task {
+    /// Among the tasks of its document, for [`toggle_task`].
+    index: usize,
+    /// 1-based, counting the frontmatter.
+    line: usize,
+    kind: TaskKind,
+    done: bool,
+    text: String,
+    /// The heading the task is under.
+    context: Option<String>,
+    /// Like `2025-03-01`.
+    due: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceTask {
+    path: String,
+    /// The title of the document.
+    title: String,
+    #[serde(flatten)]
+    task: Task,
+}
+
+fn due(text: &str) -> Option<String> {
+    let date = DUE.captures(text)?.get(1)?.as_str();
+    // well formed but impossible, like 2025-02-30, isn't a date
+    Date::parse(date, format_description!("[year]-[month]-[day]")).ok()?;
+    Some(date.to_owned())
+}
+
+/// Whether `at` in `line` is inside a code span.
+fn in_code(line: &str, at: usize) -> bool {
+    line[..at].matches('`').count() % 2 == 1
+}
+
+fn heading_text(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start_matches(' ');
+    let hashes = trimmed.len() - trimmed.trim_start_matches('#').len();
+    let rest = &trimmed[hashes..];
+    let is_heading = (1..=6).contains(&hashes) && line.len() - trimmed.len() < 4;
+    let is_heading = is_heading && (rest.is_empty() || rest.starts_with([' ', '\t']));
+    is_heading.then(|| rest.trim().trim_end_matches('#').trim_end())
+}
+
+/// The tasks of a document, outside code and the frontmatter.
+pub fn scan(source: &str) -> Vec<Task> {
+    let skip = frontmatter::split(source).map_or(0, |(_, body)| source[..source.len() - body.len()].lines().count());
+    let mut tasks = Vec::new();
+    let mut fenced = false;
+    let mut context = None;
+    for (number, line) in source.lines().enumerate().skip(skip) {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            fenced = !fenced;
+            continue;
+        }
+        if fenced {
+            continue;
+        }
+        if let Some(heading) = heading_text(line) {
+            context = Some(heading.to_owned());
+            continue;
+        }
+        let (kind, done, text) = if let Some(checkbox) = CHECKBOX.captures(line) {
+            (TaskKind::Checkbox, &checkbox[2] != " ", checkbox.get(4).map_or("", |m| m.as_str()))
+        } else if let Some(marker) = MARKER.find_iter(line).find(|m| !in_code(line, m.start())) {
+            (TaskKind::Todo, marker.as_str().starts_with("DONE"), &line[marker.end()..])
+        } else {
+            continue;
+        };
+        tasks.push(Task {
+            index: tasks.len(),
+            line: number + 1,
+            kind,
+            done,
+            text: text.trim().to_owned(),
+            context: context.clone(),
+            due: due(text),
+        });
+    }
+    tasks
+}
+
+fn workspace_tasks(index: &WorkspaceIndex, root: &Path, open: bool) -> Result<Vec<WorkspaceTask>, String> {
+    let mut result = index.with(root, |documents| {
+        documents
+            .iter()
+            .flat_map(|(path, document)| {
+                document.tasks.iter().filter(|t| !open || !t.done).map(|task| WorkspaceTask {
+                    path: path.to_string_lossy().into_owned(),
+                    title: document.title.clone(),
+                    task: task.clone(),
+                })
+            })
+            .collect::<Vec<_>>()
+    })?;
+    // the soonest first, then by document
+    result.sort_by(|a, b| {
+        let key = |t: &WorkspaceTask| (t.task.due.is_none(), t.task.due.clone(), t.path.clone(), t.task.index);
+        key(a).cmp(&key(b))
+    });
+    Ok(result)
+}
+
+fn toggle(path: &Path, index: usize) -> Result<bool, String> {
+    let source = fs::read_to_string(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    let task = scan(&source).into_iter().nth(index).ok_or_else(|| format!("no task {index} in {}", path.display()))?;
+    let mut out = String::with_capacity(source.len());
+    for (number, line) in source.split_inclusive('\n').enumerate() {
+        if number + 1 != task.line {
+            out.push_str(line);
+            continue;
+        }
+        let toggled = match task.kind {
+            TaskKind::Checkbox => {
+                let mark = if task.done { "${1} ${3}${4}" } else { "${1}x${3}${4}" };
+                CHECKBOX.replace(line, mark)
+            }
+            TaskKind::Todo => {
+                let (from, to) = if task.done { ("DONE:", "TODO:") } else { ("TODO:", "DONE:") };
+                let at = MARKER
+                    .find_iter(line)
+                    .find(|m| !in_code(line, m.start()))
+                    .map_or(0, |m| m.start());
+                format!("{}{}{}", &line[..at], to, &line[at + from.len()..]).into()
+            }
+        };
+        out.push_str(&toggled);
+    }
+    workspace::write_atomic(path, out.as_bytes())?;
+    Ok(!task.done)
+}
+
+/// The tasks of the workspace, with due dates first, soonest first; only
+/// those not done with `open`.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn list_tasks(
+    workspace: String, open: Option<bool>, index: State<'_, Arc<WorkspaceIndex>>,
+) -> Result<Vec<WorkspaceTask>, BackendError> {
+    log::info!("list_tasks start...");
+    let index = index.inner().clone();
+    let result = tokio::task::spawn_blocking(move || {
+        workspace_tasks(&index, Path::new(&workspace), open.unwrap_or_default())
+    }).await;
+    log::info!("list_tasks done");
+    match result {
+        Ok(Ok(tasks)) => Ok(tasks),
+        Ok(Err(e)) => Err(format!("list_tasks task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Marks the task `index` of the document at `path` done, or not done if
+/// it was, and returns whether it's done now.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn toggle_task(path: String, index: usize) -> Result<bool, BackendError> {
+    let result = tokio::task::spawn_blocking(move || toggle(Path::new(&path), index)).await;
+    match result {
+        Ok(Ok(done)) => Ok(done),
+        Ok(Err(e)) => Err(format!("toggle_task task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE: &str = "---\ntitle: x\n---\n# Plans\n- [ ] buy milk due: 2025-03-01\n  * [x] done already\n\
+                        TODO: call `TODO: not this` @due(2025-02-30)\n```\n- [ ] in code\n```\n\
+                        ## Later\nsee `DONE: x` and DONE: shipped 📅 2025-04-02\n";
+
+    #[test]
+    fn checkboxes_and_markers_are_found_outside_code() {
+        let tasks = scan(NOTE);
+        let found: Vec<_> = tasks.iter().map(|t| (t.line, t.kind, t.done, t.text.as_str())).collect();
+        assert_eq!(found, [
+            (5, TaskKind::Checkbox, false, "buy milk due: 2025-03-01"),
+            (6, TaskKind::Checkbox, true, "done already"),
+            (7, TaskKind::Todo, false, "call `TODO: not this` @due(2025-02-30)"),
+            (12, TaskKind::Todo, true, "shipped 📅 2025-04-02"),
+        ]);
+        let dues: Vec<_> = tasks.iter().map(|t| t.due.as_deref()).collect();
+        assert_eq!(dues, [Some("2025-03-01"), None, None, Some("2025-04-02")]);
+        assert_eq!(tasks[0].context.as_deref(), Some("Plans"));
+        assert_eq!(tasks[3].context.as_deref(), Some("Later"));
+    }
+
+    #[test]
+    fn toggling_rewrites_only_the_task() {
+        let dir = std::env::temp_dir().join(format!("emmm-tasks-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create the test folder");
+        let path = dir.join("note.md");
+        fs::write(&path, NOTE).expect("write the note");
+
+        assert!(toggle(&path, 0).expect("check the box"));
+        assert!(!toggle(&path, 1).expect("uncheck the box"));
+        assert!(toggle(&path, 2).expect("mark it done"));
+        assert!(!toggle(&path, 3).expect("mark it to do"));
+        let expected = NOTE
+            .replace("- [ ] buy", "- [x] buy")
+            .replace("* [x] done", "* [ ] done")
+            .replace("TODO: call", "DONE: call")
+            .replace("and DONE: shipped", "and TODO: shipped");
+        assert_eq!(fs::read_to_string(&path).expect("read the note"), expected);
+        assert!(toggle(&path, 4).is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
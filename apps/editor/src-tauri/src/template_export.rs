@@ -0,0 +1,251 @@
+use std::{error::Error, fs, path::Path};
+
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag};
+use serde_json::{json, Map, Value};
+use tera::{Context, Tera};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    error::BackendError,
+    frontmatter::{self, Frontmatter},
+    jobs::{self, JobKind},
+    markdown::{self, RenderOptions},
+    workers::{self, Priority},
+};
+
+fn tag_node(tag: &Tag) -> Value {
+    match tag {
+        Tag::Paragraph => json!({ "type": "paragraph" }),
+        Tag::Heading { level, id, classes, .. } => json!({
+            "type": "heading",
+            "level": *level as u8,
+            "id": id.as_deref(),
+            "classes": classes.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+        }),
+        Tag::BlockQuote(_) => json!({ "type": "blockquote" }),
+        Tag::CodeBlock(kind) => json!({
+            "type": "code_block",
+            "language": match kind {
+                CodeBlockKind::Fenced(info) => info.split_whitespace().next(),
+                CodeBlockKind::Indented => None,
+            },
+        }),
+        Tag::HtmlBlock => json!({ "type": "html_block" }),
+        Tag::List(start) => json!({ "type": "list", "ordered": start.is_some(), "start": start }),
+        Tag::Item => json!({ "type": "item" }),
+        Tag::FootnoteDefinition(label) =>
+            json!({ "type": "footnote_definition", "label": label.as_ref() }),
+        Tag::Table(_) => json!({ "type": "table" }),
+        Tag::TableHead => json!({ "type": "table_head" }),
+        Tag::TableRow => json!({ "type": "table_row" }),
+        Tag::TableCell => json!({ "type": "table_cell" }),
+        Tag::Emphasis => json!({ "type": "emphasis" }),
+        Tag::Strong => json!({ "type": "strong" }),
+        Tag::Strikethrough => json!({ "type": "strikethrough" }),
+        Tag::Superscript => json!({ "type": "superscript" }),
+        Tag::Subscript => json!({ "type": "subscript" }),
+        Tag::Link { dest_url, title, .. } =>
+            json!({ "type": "link", "url": dest_url.as_ref(), "title": title.as_ref() }),
+        Tag::Image { dest_url, title, .. } =>
+            json!({ "type": "image", "url": dest_url.as_ref(), "title": title.as_ref() }),
+        Tag::DefinitionList => json!({ "type": "definition_list" }),
+        Tag::DefinitionListTitle => json!({ "type": "definition_title" }),
+        Tag::DefinitionListDefinition => json!({ "type": "definition" }),
+        Tag::MetadataBlock(_) => json!({ "type": "metadata" }),
+    }
+}
+
+fn leaf_node(event: &Event) -> Option<Value> {
+    Some(match event {
+        Event::Text(t) => json!({ "type": "text", "text": t.as_ref() }),
+        Event::Code(t) => json!({ "type": "code", "text": t.as_ref() }),
+        Event::InlineMath(t) => json!({ "type": "math", "display": false, "text": t.as_ref() }),
+        Event::DisplayMath(t) => json!({ "type": "math", "display": true, "text": t.as_ref() }),
+        Event::Html(t) | Event::InlineHtml(t) => json!({ "type": "html", "text": t.as_ref() }),
+        Event::FootnoteReference(label) =>
+            json!({ "type": "footnote_reference", "label": label.as_ref() }),
+        Event::SoftBreak => json!({ "type": "break", "hard": false }),
+        Event::HardBreak => json!({ "type": "break", "hard": true }),
+        Event::Rule => json!({ "type": "rule" }),
+        Event::TaskListMarker(checked) => json!({ "type": "task", "checked": checked }),
+        Event::Start(_) | Event::End(_) => return None,
+    })
+}
+
+/// The document as a tree of `{type, ..., text, children}` nodes, where
+/// `text` is all the plain text inside a node.
+fn ast(markdown: &str) -> Value {
+    let mut stack: Vec<Map<String, Value>> = vec![Map::new()];
+    let append = |stack: &mut Vec<Map<String, Value>>, node: Value| {
+        let text = node.get("text").and_then(Value::as_str).unwrap_or("").to_owned();
+        for ancestor in stack.iter_mut().skip(1) {
+            let t = ancestor.entry("text").or_insert_with(|| json!(""));
+            if let Value::String(s) = t {
+                s.push_str(&text);
+            }
+        }
+        let parent = stack.last_mut().expect("stack has the root");
+        if let Value::Array(children) =
+            parent.entry("children").or_insert_with(|| json!([]))
+        {
+            children.push(node);
+        }
+    };
+
+    for event in Parser::new_ext(markdown, Options::all()) {
+        match &event {
+            Event::Start(tag) => {
+                let Value::Object(mut node) = tag_node(tag) else { unreachable!() };
+                node.insert("text".to_owned(), json!(""));
+                node.insert("children".to_owned(), json!([]));
+                stack.push(node);
+            }
+            Event::End(_) => {
+                if stack.len() > 1 {
+                    let node = stack.pop().expect("checked above");
+                    // the text was already added to the ancestors
+                    let parent = stack.last_mut().expect("stack has the root");
+                    if let Value::Array(children) =
+                        parent.entry("children").or_insert_with(|| json!([]))
+                    {
+                        children.push(Value::Object(node));
+                    }
+                }
+            }
+            leaf => {
+                if let Some(node) = leaf_node(leaf) {
+                    append(&mut stack, node);
+                }
+            }
+        }
+    }
+    stack.truncate(1);
+    let root = stack.pop().unwrap_or_default();
+    root.get("children").cloned().unwrap_or_else(|| json!([]))
+}
+
+fn meta_value(meta: &Frontmatter) -> Value {
+    let map = meta
+        .fields
+        .iter()
+        .map(|(k, v)| {
+            let value = match v {
+                frontmatter::Value::Scalar(s) => json!(s),
+                frontmatter::Value::List(items) => json!(items),
+            };
+            (k.clone(), value)
+        })
+        .collect();
+    Value::Object(map)
+}
+
+fn format_date(date: OffsetDateTime) -> Option<String> {
+    date.format(&Rfc3339).ok()
+}
+
+/// Tera's own messages are vague ("Failed to render 'x'"); the cause is
+/// further down the chain.
+fn describe(e: &tera::Error) -> String {
+    let mut msg = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        msg.push_str(": ");
+        msg.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    msg
+}
+
+fn render(template_path: &Path, source: &str, path: Option<&Path>) -> Result<String, String> {
+    let dir = template_path.parent().unwrap_or(Path::new("."));
+    let name = template_path
+        .file_name()
+        .ok_or(format!("invalid template path: {}", template_path.display()))?
+        .to_string_lossy()
+        .into_owned();
+    // with the templates beside it, which it can extend and include, but
+    // nothing else in the folder or under it
+    let mut files = vec![(template_path.to_owned(), Some(name.clone()))];
+    let entries = fs::read_dir(dir).map_err(|e| format!("read {}: {e}", dir.display()))?;
+    for entry in entries.flatten() {
+        let sibling = entry.path();
+        let is_template = sibling.extension().is_some_and(|e| e == "html" || e == "tera");
+        let sibling_name = entry.file_name().to_string_lossy().into_owned();
+        if is_template && sibling_name != name && entry.file_type().is_ok_and(|t| t.is_file()) {
+            files.push((sibling, Some(sibling_name)));
+        }
+    }
+    let mut tera = Tera::default();
+    tera.add_template_files(files).map_err(|e| describe(&e))?;
+
+    let (meta, body) = Frontmatter::of(source);
+    let rendered = markdown::render_document(body, &meta, &RenderOptions {
+        base_dir: path.and_then(Path::parent),
+        ..Default::default()
+    })?;
+    let title = meta.get("title").map(str::to_owned).or_else(|| {
+        path.and_then(Path::file_stem).map(|s| s.to_string_lossy().into_owned())
+    });
+
+    let mut context = Context::new();
+    context.insert("meta", &meta_value(&meta));
+    context.insert("title", &title);
+    context.insert("date", &meta.date("date").and_then(format_date));
+    context.insert("tags", &meta.list("tags"));
+    context.insert("content", &rendered.content);
+    context.insert("bibliography", &rendered.bibliography);
+    context.insert("headings", &markdown::headings(body));
+    context.insert("ast", &ast(body));
+    context.insert("source", body);
+    context.insert("path", &path.map(|p| p.to_string_lossy().into_owned()));
+    context.insert("now", &format_date(OffsetDateTime::now_utc()));
+
+    tera.render(&name, &context).map_err(|e| describe(&e))
+}
+
+/// Exports a document through a user-provided tera template. Templates get
+/// `meta` (the frontmatter), `title`, `date`, `tags`, `content` (rendered
+/// HTML; use `| safe`), `bibliography` (HTML too, if the frontmatter names
+/// one), `headings`, `ast`, `source`, `path` and `now`. The `.html` and
+/// `.tera` templates in the same folder can be extended or included.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn export_with_template(
+    template_path: String, source: String, path: Option<String>
+) -> Result<String, BackendError> {
+    log::info!("export_with_template start: {template_path}");
+    let label = path.clone().unwrap_or_else(|| template_path.clone());
+    let job = jobs::start(JobKind::Export, Priority::Interactive, label);
+    let result = workers::run(Priority::Interactive, move || {
+        job.run(|| render(Path::new(&template_path), &source, path.as_deref().map(Path::new)))
+    }).await;
+
+    match result {
+        Ok(Ok(output)) => {
+            log::info!("export_with_template done");
+            Ok(output)
+        }
+        Ok(Err(e)) => Err(format!("export_with_template task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_loads_only_the_templates_beside_it() {
+        let dir = std::env::temp_dir().join(format!("emmm-template-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("drafts")).unwrap();
+        fs::write(dir.join("base.html"), "<main>{% block body %}{% endblock %}</main>").unwrap();
+        fs::write(dir.join("page.tpl"), "{% extends \"base.html\" %}{% block body %}{{ title }}{% endblock %}")
+            .unwrap();
+        // broken, so loading either would fail
+        fs::write(dir.join("notes.txt"), "{% if %}").unwrap();
+        fs::write(dir.join("drafts").join("old.html"), "{% if %}").unwrap();
+        let output = render(&dir.join("page.tpl"), "---\ntitle: Hello\n---\nBody", None).unwrap();
+        assert_eq!(output, "<main>Hello</main>");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
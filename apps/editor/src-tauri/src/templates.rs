@@ -0,0 +1,293 @@
+//! Variables in note templates, like `{{title}}` or `{{date+7d:YYYY-MM-DD}}`.
+//! A variable is `{{name}}` or `{{name:argument}}`:
+//!
+//! - `date`, `time` and `now`, shifted by offsets like `+7d` or `-1m`
+//!   (`d`ays, `w`eeks, `m`onths, `y`ears, `h`ours), with a format like
+//!   `YYYY-MM-DD`, or one in the syntax of `time` like `[year]-[month]`
+//! - `title`, `file`, the name without extension, `path`, relative to the
+//!   workspace, `folder`, and `workspace`, the name of its folder
+//! - `clipboard` and `selection`
+//! - `prompt:Question|default`, whose answer comes from the context; the
+//!   questions without one are returned so they can be asked
+//!
+//! Snippets share the dates, so `${date+1d:dddd}` works there too. Anything
+//! else in braces, including an unknown name, is left as it is.
+
+use std::{collections::HashMap, path::Path, sync::LazyLock};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+use tauri_plugin_clipboard_manager::ClipboardExt;
+use time::{
+    format_description::{self, FormatItem},
+    macros::format_description,
+    Duration, Month, OffsetDateTime,
+};
+
+use crate::workspace;
+
+static OFFSET: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"([+-])(\d+)([dwmyh])").expect("offset regex"));
+
+/// Moment-style tokens, longest first so `MMMM` isn't read as `MM` twice;
+/// `Do`, like "1st", is the other one.
+const TOKENS: &[(&str, &[FormatItem<'static>])] = &[
+    ("YYYY", format_description!("[year]")),
+    ("YY", format_description!("[year repr:last_two]")),
+    ("MMMM", format_description!("[month repr:long]")),
+    ("MMM", format_description!("[month repr:short]")),
+    ("MM", format_description!("[month]")),
+    ("M", format_description!("[month padding:none]")),
+    ("DDDD", format_description!("[ordinal]")),
+    ("DD", format_description!("[day]")),
+    ("D", format_description!("[day padding:none]")),
+    ("dddd", format_description!("[weekday]")),
+    ("ddd", format_description!("[weekday repr:short]")),
+    ("WW", format_description!("[week_number]")),
+    ("W", format_description!("[week_number padding:none]")),
+    ("HH", format_description!("[hour]")),
+    ("H", format_description!("[hour padding:none]")),
+    ("hh", format_description!("[hour repr:12]")),
+    ("h", format_description!("[hour repr:12 padding:none]")),
+    ("mm", format_description!("[minute]")),
+    ("ss", format_description!("[second]")),
+    ("A", format_description!("[period]")),
+    ("a", format_description!("[period case:lower]")),
+];
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TemplateContext {
+    /// The document the template is for.
+    pub(crate) path: Option<String>,
+    pub(crate) workspace: Option<String>,
+    /// By default the file name without extension.
+    pub(crate) title: Option<String>,
+    pub(crate) selection: Option<String>,
+    /// By question.
+    pub(crate) answers: HashMap<String, String>,
+}
+
+#[derive(Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplatePrompt {
+    question: String,
+    default: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rendered {
+    /// With the defaults of the prompts not answered.
+    pub(crate) text: String,
+    /// Those not answered, in order.
+    prompts: Vec<TemplatePrompt>,
+}
+
+fn add_months(date: OffsetDateTime, months: i64) -> Option<OffsetDateTime> {
+    let total = i64::from(date.year()) * 12 + i64::from(u8::from(date.month())) - 1 + months;
+    let year = i32::try_from(total.div_euclid(12)).ok()?;
+    let month = Month::try_from(u8::try_from(total.rem_euclid(12) + 1).ok()?).ok()?;
+    // the 31st a month later is the end of a shorter month
+    let day = date.day().min(month.length(year));
+    date.replace_day(1).ok()?.replace_year(year).ok()?.replace_month(month).ok()?.replace_day(day).ok()
+}
+
+/// `date` shifted by offsets like `+7d-1h`; `None` if `offsets` aren't
+/// just that.
+pub(crate) fn shift(date: OffsetDateTime, offsets: &str) -> Option<OffsetDateTime> {
+    let mut date = date;
+    let mut matched = 0;
+    for offset in OFFSET.captures_iter(offsets) {
+        matched += offset[0].len();
+        let amount: i64 = offset[2].parse().ok()?;
+        let amount = if &offset[1] == "-" { -amount } else { amount };
+        date = match &offset[3] {
+            "d" => date.checked_add(Duration::days(amount))?,
+            "w" => date.checked_add(Duration::weeks(amount))?,
+            "h" => date.checked_add(Duration::hours(amount))?,
+            "m" => add_months(date, amount)?,
+            _ => add_months(date, amount.checked_mul(12)?)?,
+        };
+    }
+    (matched == offsets.len()).then_some(date)
+}
+
+/// `date` with a moment-style format like `dddd, MMMM D` where `[...]` is
+/// literal, or one in the syntax of `time`.
+pub(crate) fn format_date(date: OffsetDateTime, format: &str) -> Option<String> {
+    if format.contains('[') {
+        if let Ok(description) = format_description::parse_borrowed::<2>(format) {
+            return date.format(&description).ok();
+        }
+    }
+    let mut out = String::new();
+    let mut rest = format;
+    'outer: while let Some(c) = rest.chars().next() {
+        if c == '[' {
+            let end = rest.find(']')?;
+            out.push_str(&rest[1..end]);
+            rest = &rest[end + 1..];
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("Do") {
+            let day = date.day();
+            let suffix = match (day % 10, day / 10) {
+                (_, 1) => "th",
+                (1, _) => "st",
+                (2, _) => "nd",
+                (3, _) => "rd",
+                _ => "th",
+            };
+            out.push_str(&format!("{day}{suffix}"));
+            rest = after;
+            continue;
+        }
+        for (token, description) in TOKENS {
+            if let Some(after) = rest.strip_prefix(token) {
+                out.push_str(&date.format(description).ok()?);
+                rest = after;
+                continue 'outer;
+            }
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    Some(out)
+}
+
+/// `date`, `time` or `now`, shifted and formatted, for a variable `name`
+/// like `date+7d`; `None` for other names.
+pub(crate) fn date_value(now: OffsetDateTime, name: &str, format: Option<&str>) -> Option<String> {
+    let split = name.find(['+', '-']).unwrap_or(name.len());
+    let (base, offsets) = name.split_at(split);
+    let default = match base {
+        "date" => "YYYY-MM-DD",
+        "time" => "HH:mm",
+        "now" => "YYYY-MM-DD HH:mm",
+        _ => return None,
+    };
+    let date = shift(now, offsets)?;
+    // an invalid format shows as it is
+    Some(format_date(date, format.unwrap_or(default)).unwrap_or_else(|| format.unwrap_or_default().to_owned()))
+}
+
+pub(crate) struct Renderer<'a> {
+    pub(crate) clipboard: &'a dyn Fn() -> Option<String>,
+    pub(crate) context: &'a TemplateContext,
+    pub(crate) now: OffsetDateTime,
+}
+
+impl Renderer<'_> {
+    fn value(&self, name: &str, argument: Option<&str>, prompts: &mut Vec<TemplatePrompt>) -> Option<String> {
+        if let Some(date) = date_value(self.now, name, argument) {
+            return Some(date);
+        }
+        let context = self.context;
+        let path = context.path.as_deref().map(Path::new);
+        let root = context.workspace.as_deref().map(Path::new);
+        let relative = path.zip(root).and_then(|(path, root)| workspace::relative_url_path(root, path));
+        let file = || path.and_then(Path::file_stem).map(|s| s.to_string_lossy().into_owned());
+        let value = match name {
+            "title" => context.title.clone().or_else(file),
+            "file" => file(),
+            "path" => relative,
+            "folder" => relative.map(|r| r.rsplit_once('/').map_or(String::new(), |(folder, _)| folder.to_owned())),
+            "workspace" => root.and_then(Path::file_name).map(|n| n.to_string_lossy().into_owned()),
+            "clipboard" => (self.clipboard)(),
+            "selection" => context.selection.clone(),
+            "prompt" => {
+                let (question, default) = match argument?.split_once('|') {
+                    Some((question, default)) => (question.trim(), Some(default.trim().to_owned())),
+                    None => (argument?.trim(), None),
+                };
+                if let Some(answer) = context.answers.get(question) {
+                    return Some(answer.clone());
+                }
+                let prompt = TemplatePrompt { question: question.to_owned(), default: default.clone() };
+                if !prompts.contains(&prompt) {
+                    prompts.push(prompt);
+                }
+                return Some(default.unwrap_or_default());
+            }
+            _ => return None,
+        };
+        Some(value.unwrap_or_default())
+    }
+
+    pub(crate) fn render(&self, text: &str) -> Rendered {
+        let mut out = String::with_capacity(text.len());
+        let mut prompts = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(end) = rest.find("}}") else { break };
+            let inner = rest[2..end].trim();
+            let (name, argument) = match inner.split_once(':') {
+                Some((name, argument)) => (name.trim(), Some(argument)),
+                None => (inner, None),
+            };
+            match self.value(name, argument, &mut prompts) {
+                Some(value) => out.push_str(&value),
+                None => out.push_str(&rest[..end + 2]),
+            }
+            rest = &rest[end + 2..];
+        }
+        out.push_str(rest);
+        Rendered { text: out, prompts }
+    }
+}
+
+/// `text` with its variables filled in. Prompts not answered in `context`
+/// get their defaults, and are returned to ask and render again.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn render_template(text: String, context: TemplateContext, app: AppHandle) -> Rendered {
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    Renderer { clipboard: &|| app.clipboard().read_text().ok(), context: &context, now }.render(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+
+    use super::*;
+
+    #[test]
+    fn dates_are_shifted_and_formatted() {
+        let now = datetime!(2024-01-31 12:30 UTC);
+        assert_eq!(shift(now, "+1m"), Some(datetime!(2024-02-29 12:30 UTC)));
+        assert_eq!(shift(now, "-1y+1w-2h"), Some(datetime!(2023-02-07 10:30 UTC)));
+        assert_eq!(shift(now, "+1x"), None);
+        assert_eq!(format_date(now, "dddd, MMMM Do").as_deref(), Some("Wednesday, January 31st"));
+        assert_eq!(format_date(now, "[week] W, h a").as_deref(), Some("week 5, 12 pm"));
+        assert_eq!(format_date(now, "[year]/[month]").as_deref(), Some("2024/01"));
+        assert_eq!(date_value(now, "date+1d", None).as_deref(), Some("2024-02-01"));
+        assert_eq!(date_value(now, "time", None).as_deref(), Some("12:30"));
+        assert_eq!(date_value(now, "date", Some("[unclosed")).as_deref(), Some("[unclosed"));
+        assert_eq!(date_value(now, "dates", None), None);
+    }
+
+    #[test]
+    fn variables_and_prompts_are_filled_in() {
+        let context = TemplateContext {
+            path: Some("/notes/projects/plan.md".to_owned()),
+            workspace: Some("/notes".to_owned()),
+            answers: HashMap::from([("Owner?".to_owned(), "Kim".to_owned())]),
+            ..TemplateContext::default()
+        };
+        let renderer = Renderer {
+            clipboard: &|| Some("copied".to_owned()),
+            context: &context,
+            now: datetime!(2024-05-01 12:30 UTC),
+        };
+        let rendered = renderer.render(
+            "{{title}} in {{folder}} of {{ workspace }}, {{date:YYYY}}: {{clipboard}} {{selection}}|\
+             {{prompt:Owner?}} {{prompt:Due?|soon}} {{prompt: Due? | soon }} {{unknown}} {{unclosed",
+        );
+        assert_eq!(rendered.text, "plan in projects of notes, 2024: copied |Kim soon soon {{unknown}} {{unclosed");
+        let prompts: Vec<_> = rendered.prompts.iter().map(|p| (p.question.as_str(), p.default.as_deref())).collect();
+        assert_eq!(prompts, [("Due?", Some("soon"))]);
+    }
+}
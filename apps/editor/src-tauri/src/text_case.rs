@@ -0,0 +1,179 @@
+//! Slugs and letter case, for "copy anchor link", file names made from
+//! titles and the case actions of the editor.
+
+use serde::Deserialize;
+
+use crate::markdown;
+
+/// Lowercase in titles unless they come first or last. Each of them is
+/// all lowercase.
+const SMALL_WORDS: &[&str] = &[
+    "a", "an", "and", "as", "at", "but", "by", "en", "for", "if", "in", "nor", "of", "off", "on",
+    "or", "per", "so", "the", "to", "up", "via", "vs", "yet",
+];
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SlugOptions {
+    /// Spells other scripts in ASCII, so "北京" becomes `bei-jing` rather
+    /// than staying as it is.
+    transliterate: bool,
+    /// In characters; the slug is cut at a dash if it's longer.
+    max_length: Option<usize>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum TextCase {
+    /// Capitals for every word but small ones like "of" and "the".
+    Title,
+    /// A capital for the first word of every sentence only.
+    Sentence,
+}
+
+fn slug(text: &str, options: &SlugOptions) -> String {
+    let slug = if options.transliterate {
+        markdown::slug(&deunicode::deunicode(text))
+    } else {
+        markdown::slug(text)
+    };
+    match options.max_length {
+        Some(max) if slug.chars().count() > max => {
+            let cut: String = slug.chars().take(max + 1).collect();
+            let cut = match cut.rfind('-') {
+                Some(dash) if dash > 0 => &cut[..dash],
+                _ => &cut[..cut.char_indices().nth(max).map_or(cut.len(), |(i, _)| i)],
+            };
+            cut.trim_end_matches('-').to_owned()
+        }
+        _ => slug,
+    }
+}
+
+/// Words written with capitals inside, like "iPhone" or "NASA", or with
+/// dots and digits, like "example.com" or "mp3", are kept as written.
+fn is_special(word: &str) -> bool {
+    let bare = word.trim_matches(|c: char| !c.is_alphanumeric());
+    bare.chars().skip(1).any(char::is_uppercase)
+        || bare.contains(['.', '@', '/'])
+        || bare.chars().any(|c| c.is_ascii_digit())
+}
+
+fn capitalize(word: &str) -> String {
+    match word.char_indices().find(|(_, c)| c.is_alphabetic()) {
+        Some((i, c)) => format!("{}{}{}", &word[..i], c.to_uppercase(), &word[i + c.len_utf8()..]),
+        None => word.to_owned(),
+    }
+}
+
+/// Text and the white space before it, in order.
+fn words(text: &str) -> Vec<(&str, &str)> {
+    let mut result = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let start = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+        let end = rest[start..].find(char::is_whitespace).map_or(rest.len(), |i| start + i);
+        result.push((&rest[..start], &rest[start..end]));
+        rest = &rest[end..];
+    }
+    result
+}
+
+fn ends_clause(word: &str) -> bool {
+    word.trim_end_matches(['"', '\'', ')', '”', '’']).ends_with([':', '.', '?', '!', '—', '–'])
+}
+
+fn title_case(text: &str) -> String {
+    let words = words(text);
+    let mut out = String::with_capacity(text.len());
+    let mut starts = true;
+    for (n, &(space, word)) in words.iter().enumerate() {
+        out.push_str(space);
+        // the last word, also of a subtitle
+        let ends = n + 1 == words.len() || ends_clause(word);
+        let parts: Vec<&str> = word.split('-').collect();
+        for (part_index, &part) in parts.iter().enumerate() {
+            if part_index > 0 {
+                out.push('-');
+            }
+            let bare = part.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase();
+            let forced = part_index == 0 && starts || part_index + 1 == parts.len() && ends;
+            if is_special(part) {
+                out.push_str(part);
+            } else if !forced && SMALL_WORDS.contains(&bare.as_str()) {
+                out.push_str(&part.to_lowercase());
+            } else {
+                out.push_str(&capitalize(part));
+            }
+        }
+        starts = ends_clause(word) || word == "-";
+    }
+    out
+}
+
+fn sentence_case(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut starts = true;
+    for (space, word) in words(text) {
+        out.push_str(space);
+        let bare = word.trim_matches(|c: char| !c.is_alphanumeric());
+        if is_special(word) || bare == "I" || bare.starts_with("I'") || bare.starts_with("I’") {
+            out.push_str(word);
+        } else if starts {
+            out.push_str(&capitalize(&word.to_lowercase()));
+        } else {
+            out.push_str(&word.to_lowercase());
+        }
+        starts = word.trim_end_matches(['"', '\'', ')', '”', '’']).ends_with(['.', '?', '!']);
+    }
+    out
+}
+
+/// `text` as a lowercase, dash-separated slug. Without transliteration
+/// it's the same as the anchors given to headings.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn slugify(text: String, options: SlugOptions) -> String {
+    slug(&text, &options)
+}
+
+/// `text` in title or sentence case, following the English rules. Names
+/// aren't recognized, so sentence case lowercases them unless they're
+/// written like "iPhone" or "NASA".
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn change_case(text: String, case: TextCase) -> String {
+    match case {
+        TextCase::Title => title_case(&text),
+        TextCase::Sentence => sentence_case(&text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_keep_small_words_and_special_ones() {
+        assert_eq!(title_case("the lord of the rings"), "The Lord of the Rings");
+        assert_eq!(title_case("a guide to iPhone and NASA: the end of it"),
+            "A Guide to iPhone and NASA: The End of It");
+        assert_eq!(title_case("step-by-step with example.com"), "Step-by-Step With example.com");
+        assert_eq!(title_case("  spaced   out "), "  Spaced   Out ");
+    }
+
+    #[test]
+    fn sentences_get_one_capital() {
+        assert_eq!(sentence_case("The Quick Fox. And I'm Done! WHO Knew"), "The quick fox. And I'm done! WHO knew");
+        assert_eq!(sentence_case("\"hello there.\" then"), "\"Hello there.\" Then");
+    }
+
+    #[test]
+    fn slugs_can_be_transliterated_and_cut() {
+        assert_eq!(slug("Hello, World!", &SlugOptions::default()), "hello-world");
+        assert_eq!(slug("北京 2024", &SlugOptions::default()), "北京-2024");
+        let options = SlugOptions { transliterate: true, max_length: Some(12) };
+        assert_eq!(slug("北京 is a big city", &options), "bei-jing-is");
+        assert_eq!(slug("abcdefghijklmnop", &options), "abcdefghijkl");
+    }
+}
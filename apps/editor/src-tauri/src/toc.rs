@@ -0,0 +1,122 @@
+//! A table of contents kept in the document between `<!-- toc -->` and
+//! `<!-- /toc -->`, so it's regenerated in place. The links use the same
+//! anchors as exported HTML.
+
+use serde::Deserialize;
+
+use crate::{frontmatter, markdown};
+
+const START: &str = "<!-- toc -->";
+const END: &str = "<!-- /toc -->";
+const DEFAULT_DEPTH: u8 = 3;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TocStyle {
+    #[default]
+    Bullets,
+    Numbered,
+}
+
+/// `text` for the inside of a link.
+fn link_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '[' | ']' | '\\' | '*' | '_' | '`') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn toc(body: &str, depth: u8, style: TocStyle) -> String {
+    let mut headings = markdown::headings(body);
+    // a lone level 1 heading at the start is the title, not a part
+    if headings.first().is_some_and(|h| h.level == 1) && headings.iter().filter(|h| h.level == 1).count() == 1 {
+        headings.remove(0);
+    }
+    headings.retain(|h| h.level <= depth);
+    let top = headings.iter().map(|h| h.level).min().unwrap_or(1);
+    let mut out = format!("{START}\n");
+    let mut previous = 0;
+    for (i, heading) in headings.iter().enumerate() {
+        // a skipped level doesn't indent twice
+        let nesting = usize::from(heading.level - top).min(if i == 0 { 0 } else { previous + 1 });
+        let (marker, indent) = match style {
+            TocStyle::Bullets => ("-", 2),
+            TocStyle::Numbered => ("1.", 3),
+        };
+        out.push_str(&" ".repeat(nesting * indent));
+        out.push_str(&format!("{marker} [{}](#{})\n", link_text(&heading.text), heading.id));
+        previous = nesting;
+    }
+    out.push_str(END);
+    out
+}
+
+/// Byte ranges of the markers, if both are there in order.
+fn markers(doc: &str) -> Option<(usize, usize)> {
+    let lower = doc.to_ascii_lowercase();
+    let start = lower.find(START)?;
+    let end = start + lower[start..].find(END)? + END.len();
+    Some((start, end))
+}
+
+fn generate(doc: &str, depth: u8, style: TocStyle) -> String {
+    let body_start = frontmatter::split(doc).map_or(0, |(_, body)| doc.len() - body.len());
+    let body = &doc[body_start..];
+    if let Some((start, end)) = markers(doc) {
+        // the old table has no headings, so it doesn't change the new one
+        return format!("{}{}{}", &doc[..start], toc(body, depth, style), &doc[end..]);
+    }
+    // after the title, if the document starts with one
+    let first = body.lines().find(|l| !l.trim().is_empty()).unwrap_or_default();
+    let at = if first.starts_with("# ") {
+        let title = body.find(first).unwrap_or_default();
+        body_start + body[title..].find('\n').map_or(body.len(), |n| title + n + 1)
+    } else {
+        body_start
+    };
+    let (before, after) = doc.split_at(at);
+    let mut out = before.to_owned();
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    if at > body_start {
+        out.push('\n');
+    }
+    out.push_str(&toc(body, depth, style));
+    out.push_str("\n\n");
+    out.push_str(after.trim_start_matches(['\n', '\r']));
+    out
+}
+
+/// `doc` with its table of contents updated, or inserted after the title
+/// if it has none. Headings down to `depth` are listed; 3 by default.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn generate_toc(doc: String, depth: Option<u8>, style: Option<TocStyle>) -> String {
+    generate(&doc, depth.unwrap_or(DEFAULT_DEPTH), style.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_new_table_goes_after_the_title() {
+        let doc = "---\ntitle: x\n---\n# Title\n\nIntro\n\n## One [a]\n\n#### Deep\n\n### Two\n\n## Three\n";
+        assert_eq!(generate(doc, 3, TocStyle::Bullets), "---\ntitle: x\n---\n# Title\n\n<!-- toc -->\n\
+            - [One \\[a\\]](#one-a)\n  - [Two](#two)\n- [Three](#three)\n<!-- /toc -->\n\nIntro\n\n## One [a]\n\n\
+            #### Deep\n\n### Two\n\n## Three\n");
+    }
+
+    #[test]
+    fn an_existing_table_is_replaced() {
+        let doc = "Intro\n\n<!-- TOC -->\n- old\n<!-- /toc -->\n\n## A\n\n### B\n";
+        assert_eq!(generate(doc, 3, TocStyle::Numbered),
+            "Intro\n\n<!-- toc -->\n1. [A](#a)\n   1. [B](#b)\n<!-- /toc -->\n\n## A\n\n### B\n");
+        assert_eq!(generate("## A\n", 1, TocStyle::Bullets), "<!-- toc -->\n<!-- /toc -->\n\n## A\n");
+    }
+}
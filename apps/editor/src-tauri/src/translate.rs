@@ -0,0 +1,215 @@
+//! Machine translation through DeepL or a LibreTranslate server. Long
+//! texts are translated a few paragraphs at a time and streamed back.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    error::BackendError,
+    languagetool::chunks,
+    net::{self, reqwest},
+    secrets, send, BackendEvent, EventChannel,
+};
+
+/// Small enough that the first part shows up quickly.
+const MAX_CHUNK_SIZE: usize = 4000;
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum TranslationProvider {
+    /// Free API keys (ending in `:fx`) use the free endpoint.
+    DeepL {
+        /// Keychain entry with the API key; `deepl` by default.
+        secret_name: Option<String>,
+        /// `more`, `less`, `prefer_more` or `prefer_less`.
+        formality: Option<String>,
+    },
+    LibreTranslate {
+        url: String,
+        /// Keychain entry with the API key, if the server needs one.
+        secret_name: Option<String>,
+    },
+}
+
+async fn post_json(request: reqwest::RequestBuilder, body: &Value) -> Result<Value, String> {
+    let response = request
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| format!("request: {e}"))?;
+    let status = response.status();
+    let body = net::read_capped(response, MAX_RESPONSE_SIZE).await?;
+    let value: Value = serde_json::from_slice(&body).map_err(|e| {
+        format!("{status} with invalid JSON response: {e}: {}",
+            String::from_utf8_lossy(&body[..body.len().min(200)]))
+    })?;
+    if !status.is_success() {
+        let message = value["message"].as_str().or(value["error"].as_str()).unwrap_or("");
+        return Err(format!("{status}: {message}"));
+    }
+    Ok(value)
+}
+
+/// The provider with its API key looked up.
+enum Translator {
+    DeepL { endpoint: &'static str, key: String, formality: Option<String> },
+    LibreTranslate { url: String, key: Option<String> },
+}
+
+impl Translator {
+    async fn new(provider: TranslationProvider) -> Result<Self, String> {
+        Ok(match provider {
+            TranslationProvider::DeepL { secret_name, formality } => {
+                let key = secrets::require_secret(
+                    secret_name.unwrap_or_else(|| "deepl".to_owned())).await?;
+                let endpoint = if key.ends_with(":fx") {
+                    "https://api-free.deepl.com/v2/translate"
+                } else {
+                    "https://api.deepl.com/v2/translate"
+                };
+                Translator::DeepL { endpoint, key, formality }
+            }
+            TranslationProvider::LibreTranslate { url, secret_name } => {
+                let key = match secret_name {
+                    Some(name) => Some(secrets::require_secret(name).await?),
+                    None => None,
+                };
+                let url = format!("{}/translate", url.trim_end_matches('/'));
+                Translator::LibreTranslate { url, key }
+            }
+        })
+    }
+
+    async fn translate(
+        &self, client: &reqwest::Client, text: &str, target: &str, source: Option<&str>,
+    ) -> Result<String, String> {
+        match self {
+            Translator::DeepL { endpoint, key, formality } => {
+                let mut body = json!({
+                    "text": [text],
+                    "target_lang": target.to_ascii_uppercase(),
+                    "preserve_formatting": true,
+                });
+                if let Some(source) = source {
+                    body["source_lang"] = source.to_ascii_uppercase().into();
+                }
+                if let Some(formality) = formality {
+                    body["formality"] = formality.clone().into();
+                }
+                let value = post_json(client
+                    .post(*endpoint)
+                    .header(reqwest::header::AUTHORIZATION, format!("DeepL-Auth-Key {key}")),
+                    &body).await?;
+                value["translations"][0]["text"]
+                    .as_str().map(str::to_owned).ok_or("no translation in response".to_owned())
+            }
+            Translator::LibreTranslate { url, key } => {
+                let mut body = json!({
+                    "q": text,
+                    "source": source.unwrap_or("auto"),
+                    "target": target,
+                    "format": "text",
+                });
+                if let Some(key) = key {
+                    body["api_key"] = key.clone().into();
+                }
+                let value = post_json(client.post(url), &body).await?;
+                value["translatedText"]
+                    .as_str().map(str::to_owned).ok_or("no translation in response".to_owned())
+            }
+        }
+    }
+}
+
+/// Translates `text` into `target_lang` (e.g. `de`, `en-US`; detecting
+/// the source language unless `source_lang` is given), sending it back in
+/// [`BackendEvent::Chunk`]s as the parts come in.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn translate(
+    text: String, target_lang: String, provider: TranslationProvider,
+    source_lang: Option<String>, channel: EventChannel,
+) -> Result<(), BackendError> {
+    log::info!("translate start: {} bytes to {target_lang}", text.len());
+    let translator = Translator::new(provider).await?;
+    let client = net::client()?;
+    for chunk in chunks(&text, MAX_CHUNK_SIZE) {
+        // translators drop the blank lines between paragraphs
+        let content = chunk.trim();
+        if content.is_empty() {
+            send(&channel, BackendEvent::Chunk { text: chunk.to_owned() });
+            continue;
+        }
+        let start = chunk.len() - chunk.trim_start().len();
+        let end = chunk.trim_end().len();
+        let translated = translator
+            .translate(&client, content, &target_lang, source_lang.as_deref())
+            .await
+            .map_err(|e| format!("translate: {e}"))?;
+        send(&channel, BackendEvent::Chunk {
+            text: format!("{}{translated}{}", &chunk[..start], &chunk[end..]),
+        });
+    }
+    send(&channel, BackendEvent::Done);
+    log::info!("translate done");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Answers one request with `response` and returns the request's body.
+    fn serve_once(response: &'static str) -> (String, std::thread::JoinHandle<Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            write!(reader.get_mut(), "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                Content-Length: {}\r\nConnection: close\r\n\r\n{response}", response.len()).unwrap();
+            serde_json::from_slice(&body).unwrap()
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn libre_translate_is_asked_for_plain_text() {
+        let (url, server) = serve_once(r#"{"translatedText": "Hallo"}"#);
+        let translator = Translator::LibreTranslate { url: format!("{url}/translate"), key: Some("k".to_owned()) };
+        let client = net::client().unwrap();
+        let translated = tauri::async_runtime::block_on(translator.translate(&client, "Hello", "de", None));
+        assert_eq!(translated.unwrap(), "Hallo");
+        assert_eq!(server.join().unwrap(), json!({
+            "q": "Hello", "source": "auto", "target": "de", "format": "text", "api_key": "k",
+        }));
+
+        let (url, server) = serve_once(r#"{"error": "no such language"}"#);
+        let translator = Translator::LibreTranslate { url, key: None };
+        let error = tauri::async_runtime::block_on(translator.translate(&client, "Hello", "xx", Some("en")));
+        assert_eq!(error.unwrap_err(), "no translation in response");
+        assert_eq!(server.join().unwrap()["source"], "en");
+    }
+}
@@ -0,0 +1,141 @@
+//! The tray icon. Its menu lists the jobs running, so a long export or
+//! sync can be followed with the window closed, and has quick actions:
+//! a new note, quick capture, and pausing sync. While it's there, closing
+//! the last window leaves the app running, until it's quit from the menu.
+
+use std::{sync::Mutex, time::Duration};
+
+use tauri::{
+    menu::{CheckMenuItem, Menu, MenuBuilder, MenuEvent, MenuItem},
+    tray::TrayIconBuilder,
+    AppHandle, Manager, WebviewWindowBuilder,
+};
+
+use crate::{capture, deeplink, i18n, jobs, sync};
+
+const ID: &str = "main";
+/// How often the job list is looked at; the menu is only rebuilt when it
+/// changed.
+const REFRESH: Duration = Duration::from_secs(1);
+/// More jobs than this are summed up in one line.
+const MAX_JOBS: usize = 8;
+
+/// The job lines and sync state the menu was last built with.
+static SHOWN: Mutex<Option<(Vec<String>, bool)>> = Mutex::new(None);
+
+fn job_lines() -> Vec<String> {
+    let jobs = jobs::list_jobs();
+    let mut lines: Vec<String> = jobs.iter().take(MAX_JOBS).map(jobs::JobInfo::summary).collect();
+    if jobs.len() > MAX_JOBS {
+        let more = (jobs.len() - MAX_JOBS).to_string();
+        lines.push(i18n::message("tray.moreJobs", &[("count", &more)]));
+    }
+    lines
+}
+
+fn menu(app: &AppHandle, jobs: &[String], sync_paused: bool) -> tauri::Result<Menu<tauri::Wry>> {
+    let mut builder = MenuBuilder::new(app);
+    if jobs.is_empty() {
+        builder = builder.item(&MenuItem::new(app, i18n::message("tray.noJobs", &[]), false, None::<&str>)?);
+    }
+    for line in jobs {
+        builder = builder.item(&MenuItem::new(app, line, false, None::<&str>)?);
+    }
+    let item = |id: &str| MenuItem::with_id(app, id, i18n::message(&format!("tray.{id}"), &[]), true, None::<&str>);
+    let pause = CheckMenuItem::with_id(
+        app, "pauseSync", i18n::message("tray.pauseSync", &[]), true, sync_paused, None::<&str>,
+    )?;
+    builder
+        .separator()
+        .item(&item("newNote")?)
+        .item(&item("quickCapture")?)
+        .item(&pause)
+        .separator()
+        .item(&item("show")?)
+        .item(&item("quit")?)
+        .build()
+}
+
+/// Shows the main window, opening it again if it was closed.
+fn show_main(app: &AppHandle) {
+    let window = match app.get_webview_window("main") {
+        Some(window) => Ok(window),
+        None => match app.config().app.windows.iter().find(|config| config.label == "main") {
+            Some(config) => WebviewWindowBuilder::from_config(app, config).and_then(WebviewWindowBuilder::build),
+            None => return,
+        },
+    };
+    let result = window.and_then(|window| {
+        window.unminimize().and_then(|()| window.show()).and_then(|()| window.set_focus())
+    });
+    if let Err(e) = result {
+        log::warn!("tray: show window: {e}");
+    }
+}
+
+/// Whether the app keeps running with no window open, which it does with
+/// a tray icon to come back from.
+pub fn keeps_running(app: &AppHandle) -> bool {
+    app.tray_by_id(ID).is_some()
+}
+
+fn on_menu_event(app: &AppHandle, event: &MenuEvent) {
+    log::info!("tray: {}", event.id().as_ref());
+    match event.id().as_ref() {
+        "newNote" => {
+            deeplink::new_note("");
+            show_main(app);
+        }
+        "quickCapture" => {
+            if let Err(e) = capture::show(app) {
+                log::warn!("tray: {e}");
+            }
+        }
+        "pauseSync" => sync::set_sync_paused(!sync::paused()),
+        "show" => show_main(app),
+        "quit" => app.exit(0),
+        _ => {}
+    }
+    refresh(app);
+}
+
+/// Rebuilds the menu if the jobs or the sync state changed.
+fn refresh(app: &AppHandle) {
+    let Some(tray) = app.tray_by_id(ID) else { return };
+    let state = (job_lines(), sync::paused());
+    let mut shown = SHOWN.lock().expect("tray lock poisoned");
+    if shown.as_ref() == Some(&state) {
+        return;
+    }
+    let (jobs, sync_paused) = &state;
+    let result = menu(app, jobs, *sync_paused).and_then(|menu| tray.set_menu(Some(menu)));
+    let tooltip = match jobs.len() {
+        0 => "emmm".to_owned(),
+        _ => format!("emmm\n{}", jobs.join("\n")),
+    };
+    match result.and_then(|()| tray.set_tooltip(Some(tooltip))) {
+        Ok(()) => *shown = Some(state),
+        Err(e) => log::warn!("tray: update menu: {e}"),
+    }
+}
+
+/// Adds the tray icon and keeps its menu up to date.
+pub fn init(app: &AppHandle) -> tauri::Result<()> {
+    let mut builder = TrayIconBuilder::with_id(ID)
+        .tooltip("emmm")
+        .menu(&menu(app, &[], sync::paused())?)
+        .on_menu_event(|app, event| on_menu_event(app, &event));
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app)?;
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut ticks = tokio::time::interval(REFRESH);
+        loop {
+            ticks.tick().await;
+            refresh(&app);
+        }
+    });
+    Ok(())
+}
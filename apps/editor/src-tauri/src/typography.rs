@@ -0,0 +1,280 @@
+//! Typographic cleanup of markdown in one pass: curly quotes, dashes,
+//! ellipses and no-break spaces, following the conventions of the
+//! document's language. Only prose changes; code, math, HTML, URLs and the
+//! frontmatter are left as they are.
+
+use std::ops::Range;
+
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag, TagEnd};
+use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::frontmatter;
+
+const NO_BREAK_SPACE: char = '\u{a0}';
+const NARROW_NO_BREAK_SPACE: char = '\u{202f}';
+
+/// Written after a number and a space, which becomes a no-break space.
+const UNITS: &[&str] = &[
+    "%", "‰", "°C", "°F", "K", "nm", "µm", "mm", "cm", "m", "km", "mm²", "cm²", "m²", "km²", "m³",
+    "mg", "g", "kg", "t", "ml", "mL", "cl", "l", "L", "ms", "s", "min", "h", "Hz", "kHz", "MHz",
+    "GHz", "B", "kB", "KB", "MB", "GB", "TB", "KiB", "MiB", "GiB", "TiB", "W", "kW", "kWh", "V",
+    "mA", "A", "€", "£", "¥",
+];
+
+/// Written before a space and a number.
+const BEFORE_NUMBER: &[char] = &['$', '€', '£', '¥', '§', '№'];
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Smartening {
+    Quotes,
+    /// `--` and `---`, and a hyphen between spaces.
+    Dashes,
+    Ellipses,
+    /// Between numbers and units, and before `;:!?` in French.
+    NoBreakSpaces,
+    /// Unicode NFC, applied to the whole text.
+    Normalize,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SmartenOptions {
+    /// A language tag like `de-CH`; English by default.
+    language: Option<String>,
+    disabled: Vec<Smartening>,
+}
+
+struct Style {
+    double: (char, char),
+    single: (char, char),
+    /// French spacing: narrow no-break spaces inside guillemets and
+    /// before `;!?`, a no-break space before `:`.
+    spaced: bool,
+}
+
+fn style(language: &str) -> Style {
+    let language = language.to_lowercase().replace('_', "-");
+    let primary = language.split('-').next().unwrap_or_default();
+    let (double, single, spaced) = match primary {
+        "fr" => (('«', '»'), ('“', '”'), true),
+        "de" | "cs" | "sk" | "sl" | "et" | "lt" | "is" | "bg" => (('„', '“'), ('‚', '‘'), false),
+        "pl" | "ro" | "hu" | "hr" => (('„', '”'), ('‚', '’'), false),
+        "ru" | "uk" | "be" => (('«', '»'), ('„', '“'), false),
+        "pt" if language == "pt-br" => (('“', '”'), ('‘', '’'), false),
+        "es" | "it" | "pt" | "ca" | "el" | "nb" | "no" => (('«', '»'), ('“', '”'), false),
+        "da" => (('»', '«'), ('›', '‹'), false),
+        "sv" | "fi" => (('”', '”'), ('’', '’'), false),
+        "ja" => (('「', '」'), ('『', '』'), false),
+        _ => (('“', '”'), ('‘', '’'), false),
+    };
+    Style { double, single, spaced }
+}
+
+/// Byte ranges of the text that is prose: markdown text outside code,
+/// math, HTML, autolinks and the frontmatter.
+pub(crate) fn prose_ranges(text: &str) -> Vec<Range<usize>> {
+    let start = frontmatter::split(text).map_or(0, |(_, body)| text.len() - body.len());
+    let mut ranges = Vec::new();
+    let mut verbatim = 0;
+    for (event, range) in Parser::new_ext(&text[start..], Options::all()).into_offset_iter() {
+        match event {
+            Event::Start(
+                Tag::CodeBlock(_)
+                | Tag::HtmlBlock
+                | Tag::MetadataBlock(_)
+                | Tag::Link { link_type: LinkType::Autolink | LinkType::Email, .. },
+            ) => verbatim += 1,
+            Event::End(TagEnd::CodeBlock | TagEnd::HtmlBlock | TagEnd::MetadataBlock(_)) => verbatim -= 1,
+            Event::End(TagEnd::Link) if verbatim > 0 => verbatim -= 1,
+            Event::Text(_) if verbatim == 0 => ranges.push(start + range.start..start + range.end),
+            _ => {}
+        }
+    }
+    ranges.sort_by_key(|r| r.start);
+    ranges
+}
+
+/// Whether a quote after `previous` opens rather than closes.
+fn opens(previous: Option<char>) -> bool {
+    previous.is_none_or(|p| p.is_whitespace() || "([{<-–—/“‘„‚«‹»›「『".contains(p))
+}
+
+fn is_unit_after(rest: &str) -> bool {
+    UNITS.iter().any(|unit| {
+        rest.strip_prefix(unit).is_some_and(|after| after.chars().next().is_none_or(|c| !c.is_alphanumeric()))
+    })
+}
+
+fn smarten(text: &str, options: &SmartenOptions) -> String {
+    let enabled = |s| !options.disabled.contains(&s);
+    let normalized: String;
+    let text = if enabled(Smartening::Normalize) {
+        normalized = text.nfc().collect();
+        &normalized
+    } else {
+        text
+    };
+    let style = style(options.language.as_deref().unwrap_or("en"));
+
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let ranges = prose_ranges(text);
+    let mut next_range = ranges.iter().peekable();
+    let prose: Vec<bool> = chars
+        .iter()
+        .map(|&(at, _)| {
+            while next_range.peek().is_some_and(|r| r.end <= at) {
+                next_range.next();
+            }
+            next_range.peek().is_some_and(|r| r.start <= at)
+        })
+        .collect();
+
+    let mut out = String::with_capacity(text.len());
+    let (mut double_open, mut single_open) = (false, false);
+    let mut i = 0;
+    while i < chars.len() {
+        let (at, c) = chars[i];
+        let previous = i.checked_sub(1).map(|j| chars[j].1);
+        let next = chars.get(i + 1).map(|&(_, c)| c);
+        if c == '\n' && text[at + 1..].split('\n').next().is_some_and(|line| line.trim().is_empty()) {
+            // quotes don't carry over into the next paragraph
+            double_open = false;
+            single_open = false;
+        }
+        if !prose[i] || previous == Some('\\') {
+            out.push(c);
+            i += 1;
+            continue;
+        }
+        let rest = &text[at..];
+        if rest.starts_with("http://") || rest.starts_with("https://") {
+            let url = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            out.push_str(&rest[..url]);
+            i += rest[..url].chars().count();
+            continue;
+        }
+        // the same character repeated, as long as it's prose
+        let run = chars[i..].iter().zip(&prose[i..]).take_while(|&(&(_, d), &p)| d == c && p).count();
+        if matches!(c, '.' | '-') && run > 1 {
+            match (c, run) {
+                ('.', 3) if enabled(Smartening::Ellipses) => out.push('…'),
+                ('-', 3) if enabled(Smartening::Dashes) => out.push('—'),
+                ('-', 2) if enabled(Smartening::Dashes) => out.push('–'),
+                _ => out.extend(std::iter::repeat_n(c, run)),
+            }
+            i += run;
+            continue;
+        }
+        match c {
+            '-' if enabled(Smartening::Dashes)
+                && previous == Some(' ')
+                && next == Some(' ')
+                && i >= 2
+                && !chars[i - 2].1.is_whitespace() =>
+            {
+                out.push('–');
+            }
+            '"' if enabled(Smartening::Quotes) => {
+                if opens(previous) && next.is_some_and(|n| !n.is_whitespace()) {
+                    double_open = true;
+                    out.push(style.double.0);
+                    if style.spaced {
+                        out.push(NARROW_NO_BREAK_SPACE);
+                    }
+                } else if !opens(previous) && (double_open || !previous.is_some_and(|p| p.is_ascii_digit())) {
+                    double_open = false;
+                    if style.spaced {
+                        out.push(NARROW_NO_BREAK_SPACE);
+                    }
+                    out.push(style.double.1);
+                } else {
+                    // inches, or a quote on its own
+                    out.push(c);
+                }
+            }
+            '\'' if enabled(Smartening::Quotes) => {
+                let letter_before = previous.is_some_and(char::is_alphanumeric);
+                let digits: String = rest[1..].chars().take_while(char::is_ascii_digit).collect();
+                if letter_before && next.is_some_and(char::is_alphanumeric) {
+                    out.push('’');
+                } else if opens(previous) && digits.len() == 2 {
+                    // '90s
+                    out.push('’');
+                } else if opens(previous) && next.is_some_and(|n| !n.is_whitespace()) {
+                    single_open = true;
+                    out.push(style.single.0);
+                } else if single_open && !opens(previous) {
+                    single_open = false;
+                    out.push(style.single.1);
+                } else if letter_before {
+                    // the plural possessive, like "the Joneses' house"
+                    out.push('’');
+                } else {
+                    out.push(c);
+                }
+            }
+            ' ' if enabled(Smartening::NoBreakSpaces) => {
+                let after = &rest[1..];
+                if previous.is_some_and(|p| p.is_ascii_digit()) && is_unit_after(after)
+                    || previous.is_some_and(|p| BEFORE_NUMBER.contains(&p)) && next.is_some_and(|n| n.is_ascii_digit())
+                {
+                    out.push(NO_BREAK_SPACE);
+                } else if style.spaced && matches!(next, Some(';' | '!' | '?')) {
+                    out.push(NARROW_NO_BREAK_SPACE);
+                } else if style.spaced && next == Some(':') {
+                    out.push(NO_BREAK_SPACE);
+                } else {
+                    out.push(c);
+                }
+            }
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    out
+}
+
+/// `text` with typographic cleanup applied to its prose, for the
+/// "Typographic cleanup" action.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn smarten_text(text: String, options: SmartenOptions) -> String {
+    smarten(&text, &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn smartened(text: &str, language: &str) -> String {
+        smarten(text, &SmartenOptions { language: Some(language.to_owned()), disabled: Vec::new() })
+    }
+
+    #[test]
+    fn quotes_follow_the_language() {
+        assert_eq!(smartened(r#""It's 'fine'," she said."#, "en"), "“It’s ‘fine’,” she said.");
+        assert_eq!(smartened(r#"Er sagte "ja"."#, "de-CH"), "Er sagte „ja“.");
+        assert_eq!(smartened(r#"Il dit "oui" ?"#, "fr"), "Il dit «\u{202f}oui\u{202f}»\u{202f}?");
+        assert_eq!(smartened(r#"A 12" pipe in the '90s, the Joneses' house"#, "en"),
+            "A 12\" pipe in the ’90s, the Joneses’ house");
+    }
+
+    #[test]
+    fn dashes_ellipses_and_spaces() {
+        assert_eq!(smartened("Wait... 1--2 and a---b - c", "en"), "Wait… 1–2 and a—b – c");
+        assert_eq!(smartened("It costs $ 5 for 10 kg or 20 %.", "en"),
+            "It costs $\u{a0}5 for 10\u{a0}kg or 20\u{a0}%.");
+        assert_eq!(smartened("10 kgs", "en"), "10 kgs");
+        let disabled = SmartenOptions { language: None, disabled: vec![Smartening::Dashes, Smartening::Quotes] };
+        assert_eq!(smarten("\"a\" -- b...", &disabled), "\"a\" -- b…");
+    }
+
+    #[test]
+    fn only_prose_changes() {
+        let text = "---\ntitle: \"x\"\n---\n`\"code\"` and\n\n```\n\"block\"\n```\n\n<https://a.b/--c> \\\"x\\\" \
+                    https://example.com/a--b\n";
+        assert_eq!(smartened(text, "en"), text);
+    }
+}
@@ -0,0 +1,200 @@
+//! Updates of the app itself, from a release feed on the channel chosen in
+//! the settings. The feed is a manifest in the updater plugin's format,
+//! fetched from [`DEFAULT_FEED`] unless the settings name another one,
+//! with `{{channel}}` replaced by `stable` or `beta`.
+//!
+//! A manifest may have a `rollout` between 0 and 1: the share of stable
+//! installs that are offered the release yet, decided by a number each
+//! install draws once. Beta installs are offered everything.
+//!
+//! Packages are checked against the key the build was made with, from
+//! `EMMM_UPDATER_PUBKEY`, or the updater's `pubkey` in the Tauri config;
+//! builds without one can't install updates.
+
+use std::{fs, path::Path, sync::Mutex, time::Duration};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, Url};
+use tauri_plugin_updater::{Update, UpdaterExt};
+
+use crate::{error::BackendError, i18n, send, settings, shutdown, BackendEvent, EventChannel};
+
+pub const DEFAULT_FEED: &str = "https://github.com/ivorkchan/emmm/releases/download/updater/{{channel}}.json";
+/// Compiled in by release builds.
+pub const PUBKEY: Option<&str> = option_env!("EMMM_UPDATER_PUBKEY");
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Where an install keeps its place in staged rollouts.
+const BUCKET_FILE: &str = "update-bucket";
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl UpdateChannel {
+    fn name(self) -> &'static str {
+        match self {
+            UpdateChannel::Stable => "stable",
+            UpdateChannel::Beta => "beta",
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateInfo {
+    version: String,
+    current_version: String,
+    /// The release notes.
+    notes: Option<String>,
+    /// RFC 3339.
+    date: Option<String>,
+}
+
+/// The update found by the last check, and its package once downloaded
+/// and verified.
+static PENDING: Mutex<Option<(Update, Option<Vec<u8>>)>> = Mutex::new(None);
+
+/// The feed URL for the channel in the settings.
+pub fn feed() -> String {
+    let settings = settings::get();
+    let feed = if settings.update_feed.is_empty() { DEFAULT_FEED } else { &settings.update_feed };
+    feed.replace("{{channel}}", settings.update_channel.name())
+}
+
+/// This install's number between 0 and 1, drawn the first time.
+fn bucket(data_dir: &Path) -> Result<f64, String> {
+    let path = data_dir.join(BUCKET_FILE);
+    if let Some(bucket) = fs::read_to_string(&path).ok().and_then(|s| s.trim().parse().ok()) {
+        return Ok(bucket);
+    }
+    let mut bytes = [0; 8];
+    SystemRandom::new().fill(&mut bytes).map_err(|_| "no random numbers available".to_owned())?;
+    #[allow(clippy::cast_precision_loss)]
+    let bucket = u64::from_le_bytes(bytes) as f64 / u64::MAX as f64;
+    fs::create_dir_all(data_dir).map_err(|e| format!("create {}: {e}", data_dir.display()))?;
+    fs::write(&path, bucket.to_string()).map_err(|e| format!("write {}: {e}", path.display()))?;
+    Ok(bucket)
+}
+
+/// Whether this install is offered `update` yet.
+fn rolled_out(app: &AppHandle, update: &Update) -> Result<bool, String> {
+    let rollout = update.raw_json.get("rollout").and_then(serde_json::Value::as_f64);
+    match rollout {
+        Some(rollout) if settings::get().update_channel == UpdateChannel::Stable => {
+            let data_dir = app.path().app_data_dir().map_err(|e| format!("app data dir: {e}"))?;
+            Ok(bucket(&data_dir)? < rollout)
+        }
+        _ => Ok(true),
+    }
+}
+
+fn take_pending() -> Result<(Update, Option<Vec<u8>>), String> {
+    PENDING.lock().expect("updater lock poisoned").take().ok_or("no update was found; check first".to_owned())
+}
+
+/// Looks for a newer release on the channel in the settings; `None` if
+/// there's none, or none offered to this install yet.
+#[tauri::command]
+pub async fn check_for_update(app: AppHandle) -> Result<Option<UpdateInfo>, BackendError> {
+    let feed = feed();
+    log::info!("check_for_update start: {feed}");
+    let url = Url::parse(&feed).map_err(|e| format!("invalid update feed {feed}: {e}"))?;
+    let mut builder = app.updater_builder().timeout(TIMEOUT);
+    builder = builder.endpoints(vec![url]).map_err(|e| format!("update feed: {e}"))?;
+    if let Some(pubkey) = PUBKEY {
+        builder = builder.pubkey(pubkey);
+    }
+    let proxy = settings::get().network_proxy;
+    if !proxy.is_empty() {
+        builder = builder.proxy(Url::parse(&proxy).map_err(|e| format!("invalid proxy {proxy}: {e}"))?);
+    }
+    let updater = builder.build().map_err(|e| format!("updater: {e}"))?;
+    let update = updater.check().await.map_err(|e| format!("check_for_update: {e}"))?;
+    let update = match update {
+        Some(update) if rolled_out(&app, &update)? => update,
+        Some(update) => {
+            log::info!("check_for_update: {} is not rolled out to this install yet", update.version);
+            return Ok(None);
+        }
+        None => {
+            log::info!("check_for_update done: up to date");
+            return Ok(None);
+        }
+    };
+    log::info!("check_for_update done: {}", update.version);
+    let info = UpdateInfo {
+        version: update.version.clone(),
+        current_version: update.current_version.clone(),
+        notes: update.body.clone(),
+        date: update.date.and_then(|date| date.format(&time::format_description::well_known::Rfc3339).ok()),
+    };
+    *PENDING.lock().expect("updater lock poisoned") = Some((update, None));
+    Ok(Some(info))
+}
+
+/// Downloads the update the last check found and verifies its signature,
+/// reporting the bytes received as `Progress` events, then `Done`.
+#[tauri::command]
+pub async fn download_update(channel: EventChannel) -> Result<(), BackendError> {
+    let (update, _) = take_pending()?;
+    log::info!("download_update start: {}", update.version);
+    let message = i18n::message("update.downloading", &[("version", &update.version)]);
+    let mut done = 0;
+    let result = update
+        .download(
+            |chunk, total| {
+                done += chunk;
+                let total = total.and_then(|total| usize::try_from(total).ok()).unwrap_or(0);
+                send(&channel, BackendEvent::Progress { done, total, message: message.clone() });
+            },
+            || {},
+        )
+        .await;
+    match result {
+        Ok(bytes) => {
+            log::info!("download_update done: {} bytes", bytes.len());
+            *PENDING.lock().expect("updater lock poisoned") = Some((update, Some(bytes)));
+            send(&channel, BackendEvent::Done);
+            Ok(())
+        }
+        Err(e) => {
+            // so it can be tried again
+            *PENDING.lock().expect("updater lock poisoned") = Some((update, None));
+            Err(BackendError::network("download_update", e))
+        }
+    }
+}
+
+/// Installs the downloaded update, which runs after [`restart_app`]. On
+/// Windows the installer takes over and the app exits right away.
+#[tauri::command]
+pub async fn install_update() -> Result<(), BackendError> {
+    let (update, bytes) = take_pending()?;
+    let Some(bytes) = bytes else {
+        *PENDING.lock().expect("updater lock poisoned") = Some((update, None));
+        return Err(BackendError::invalid("the update wasn't downloaded"));
+    };
+    log::info!("install_update start: {}", update.version);
+    let result = tokio::task::spawn_blocking(move || update.install(bytes)).await;
+    match result {
+        Ok(Ok(())) => {
+            log::info!("install_update done");
+            Ok(())
+        }
+        Ok(Err(e)) => Err(format!("install_update task: {e}").into()),
+        Err(e) => Err(format!("tokio::task::spawn_blocking: {e}").into()),
+    }
+}
+
+/// Restarts once unsaved work is flushed and the jobs stopped, as when
+/// quitting.
+#[tauri::command]
+pub async fn restart_app(app: AppHandle) {
+    log::info!("restart_app");
+    shutdown::restart(app).await;
+}
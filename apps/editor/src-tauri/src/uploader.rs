@@ -0,0 +1,287 @@
+//! Image hosts ("picbeds") that published documents can link to instead of
+//! local files. Each host implements [`Uploader`]; [`UploadTarget`] is how
+//! the frontend picks one and configures it.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    compress_file,
+    error::BackendError,
+    jobs::{self, Job, JobKind},
+    net::{self, reqwest},
+    s3::S3Config,
+    secrets,
+    workers::{self, Priority},
+};
+
+/// What most hosts accept for free accounts.
+const DEFAULT_MAX_SIZE: usize = 5 * 1024 * 1024;
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+
+/// An encoded image ready for upload.
+pub struct Image {
+    /// Content hash with the format's extension, e.g. `0123abcd.jpg`.
+    pub name: String,
+    pub mime_type: &'static str,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Reads and compresses the image at `path` below `max_size` bytes.
+    pub fn prepare(path: &str, max_size: usize) -> Result<Self, String> {
+        let data = compress_file(path, max_size)?;
+        let format = image::guess_format(&data).map_err(|e| format!("guess_format: {e}"))?;
+        let extension = format.extensions_str().first().copied().unwrap_or("bin");
+        let hash = hex::encode(Sha256::digest(&data));
+        Ok(Image {
+            name: format!("{}.{extension}", &hash[..16]),
+            mime_type: format.to_mime_type(),
+            data,
+        })
+    }
+
+    fn part(&self) -> Result<reqwest::multipart::Part, String> {
+        reqwest::multipart::Part::bytes(self.data.clone())
+            .file_name(self.name.clone())
+            .mime_str(self.mime_type)
+            .map_err(|e| format!("mime_str: {e}"))
+    }
+}
+
+pub trait Uploader {
+    /// Uploads `image` and returns the URL to link to.
+    async fn upload(&self, client: &reqwest::Client, image: Image) -> Result<String, String>;
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum UploadTarget {
+    Smms(Smms),
+    Imgur(Imgur),
+    Custom(Custom),
+    S3(S3Config),
+}
+
+/// <https://smms.app>
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Smms {
+    /// Keychain entry with the API token; `smms` by default.
+    secret_name: Option<String>,
+}
+
+/// <https://imgur.com>, uploading anonymously.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Imgur {
+    client_id: String,
+}
+
+/// Any endpoint taking the image as a multipart form field and answering
+/// with JSON that contains its URL.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Custom {
+    url: String,
+    /// Form field holding the file.
+    #[serde(default = "default_field")]
+    field: String,
+    /// Other form fields to send.
+    #[serde(default)]
+    form: BTreeMap<String, String>,
+    /// `{secret}` in values is replaced by the keychain entry `secret_name`.
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    secret_name: Option<String>,
+    /// Where the URL is in the response, e.g. `$.data.links[0].url`.
+    url_path: String,
+}
+
+fn default_field() -> String {
+    "file".to_owned()
+}
+
+/// Looks up a value with a JSONPath subset: `$`, `.key`, `['key']` and
+/// `[index]`.
+fn json_path<'a>(value: &'a Value, path: &str) -> Result<&'a Value, String> {
+    let invalid = || format!("invalid path {path}");
+    let mut rest = path.trim();
+    rest = rest.strip_prefix('$').unwrap_or(rest);
+    let mut current = value;
+    while !rest.is_empty() {
+        let key;
+        if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            key = after[..end].trim();
+            rest = &after[end + 1..];
+            if let Ok(index) = key.parse::<usize>() {
+                current = current.get(index).ok_or_else(|| format!("no {path} in response"))?;
+                continue;
+            }
+            let unquoted = key
+                .strip_prefix('\'').and_then(|k| k.strip_suffix('\''))
+                .or_else(|| key.strip_prefix('"').and_then(|k| k.strip_suffix('"')))
+                .ok_or_else(invalid)?;
+            current = current.get(unquoted).ok_or_else(|| format!("no {path} in response"))?;
+        } else {
+            let after = rest.strip_prefix('.').unwrap_or(rest);
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            key = &after[..end];
+            if key.is_empty() {
+                return Err(invalid());
+            }
+            rest = &after[end..];
+            current = current.get(key).ok_or_else(|| format!("no {path} in response"))?;
+        }
+    }
+    Ok(current)
+}
+
+async fn send_json(request: reqwest::RequestBuilder) -> Result<(reqwest::StatusCode, Value), String> {
+    let response = request.send().await.map_err(|e| format!("request: {e}"))?;
+    let status = response.status();
+    let body = net::read_capped(response, MAX_RESPONSE_SIZE).await?;
+    let value = serde_json::from_slice(&body).map_err(|e| {
+        format!("{status} with invalid JSON response: {e}: {}",
+            String::from_utf8_lossy(&body[..body.len().min(200)]))
+    })?;
+    Ok((status, value))
+}
+
+impl Uploader for Smms {
+    async fn upload(&self, client: &reqwest::Client, image: Image) -> Result<String, String> {
+        let token = secrets::require_secret(
+            self.secret_name.clone().unwrap_or_else(|| "smms".to_owned())).await?;
+        let form = reqwest::multipart::Form::new().part("smfile", image.part()?);
+        let (_, value) = send_json(client
+            .post("https://smms.app/api/v2/upload")
+            .header(reqwest::header::AUTHORIZATION, token)
+            .multipart(form)).await?;
+        if value["success"].as_bool() == Some(true) {
+            return json_path(&value, "$.data.url")?
+                .as_str().map(str::to_owned).ok_or("no URL in response".to_owned());
+        }
+        // uploaded before; the response points to the existing copy
+        if value["code"] == "image_repeated" {
+            if let Some(url) = value["images"].as_str() {
+                return Ok(url.to_owned());
+            }
+        }
+        Err(value["message"].as_str().unwrap_or("upload failed").to_owned())
+    }
+}
+
+impl Uploader for Imgur {
+    async fn upload(&self, client: &reqwest::Client, image: Image) -> Result<String, String> {
+        let form = reqwest::multipart::Form::new().part("image", image.part()?);
+        let (status, value) = send_json(client
+            .post("https://api.imgur.com/3/image")
+            .header(reqwest::header::AUTHORIZATION, format!("Client-ID {}", self.client_id))
+            .multipart(form)).await?;
+        if !status.is_success() {
+            let message = value["data"]["error"].as_str().unwrap_or("upload failed");
+            return Err(format!("imgur returned {status}: {message}"));
+        }
+        json_path(&value, "$.data.link")?
+            .as_str().map(str::to_owned).ok_or("no URL in response".to_owned())
+    }
+}
+
+impl Uploader for Custom {
+    async fn upload(&self, client: &reqwest::Client, image: Image) -> Result<String, String> {
+        let secret = match &self.secret_name {
+            Some(name) => Some(secrets::require_secret(name.clone()).await?),
+            None => None,
+        };
+        let mut form = reqwest::multipart::Form::new().part(self.field.clone(), image.part()?);
+        for (name, value) in &self.form {
+            form = form.text(name.clone(), value.clone());
+        }
+        let mut request = client.post(&self.url).multipart(form);
+        for (name, value) in &self.headers {
+            let value = match &secret {
+                Some(secret) => value.replace("{secret}", secret),
+                None => value.clone(),
+            };
+            request = request.header(name, value);
+        }
+        let (status, value) = send_json(request).await?;
+        if !status.is_success() {
+            return Err(format!("{} returned {status}: {value}", self.url));
+        }
+        match json_path(&value, &self.url_path)? {
+            Value::String(url) => Ok(url.clone()),
+            other => Err(format!("{} is not a string: {other}", self.url_path)),
+        }
+    }
+}
+
+/// Compresses the image at `path` below `max_size` bytes (5 MB by
+/// default) and uploads it to `target`, returning the URL to insert. It
+/// runs as a job, which can be cancelled until the upload starts.
+pub async fn upload(
+    path: String, target: &UploadTarget, max_size: Option<usize>, priority: Priority,
+) -> Result<String, String> {
+    let job = jobs::start(JobKind::Upload, priority, &path);
+    let result = prepare_and_send(path, target, max_size.unwrap_or(DEFAULT_MAX_SIZE), priority, &job).await;
+    job.finish(&result);
+    result
+}
+
+async fn prepare_and_send(
+    path: String, target: &UploadTarget, max_size: usize, priority: Priority, job: &Job,
+) -> Result<String, String> {
+    let result = workers::run(priority, move || Image::prepare(&path, max_size)).await;
+    let image = match result {
+        Ok(Ok(image)) => image,
+        Ok(Err(e)) => return Err(format!("prepare image: {e}")),
+        Err(e) => return Err(format!("workers::run: {e}")),
+    };
+    if job.is_cancelled() {
+        return Err(jobs::CANCELLED.to_owned());
+    }
+
+    let client = net::client()?;
+    match target {
+        UploadTarget::Smms(smms) => smms.upload(&client, image).await,
+        UploadTarget::Imgur(imgur) => imgur.upload(&client, image).await,
+        UploadTarget::Custom(custom) => custom.upload(&client, image).await,
+        UploadTarget::S3(s3) => s3.upload(&client, image).await,
+    }
+}
+
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn upload_image(
+    path: String, target: UploadTarget, max_size: Option<usize>,
+) -> Result<String, BackendError> {
+    log::info!("upload_image start: {path}");
+    let url = upload(path, &target, max_size, Priority::Interactive)
+        .await
+        .map_err(|e| format!("upload_image: {e}"))?;
+    log::info!("upload_image done: {url}");
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls_are_found_by_path() {
+        let response = serde_json::json!({"data": {"links": [{"url": "a"}, {"url": "b"}], "file name": "c"}});
+        assert_eq!(json_path(&response, "$.data.links[1].url").unwrap(), "b");
+        assert_eq!(json_path(&response, "data.links[0]['url']").unwrap(), "a");
+        assert_eq!(json_path(&response, "$['data'][\"file name\"]").unwrap(), "c");
+        assert_eq!(json_path(&response, "$").unwrap(), &response);
+        assert_eq!(json_path(&response, "$.data.missing").unwrap_err(), "no $.data.missing in response");
+        assert_eq!(json_path(&response, "$.data.links[2]").unwrap_err(), "no $.data.links[2] in response");
+        for invalid in ["$.data..links", "$[data]", "$.data[0"] {
+            assert_eq!(json_path(&response, invalid).unwrap_err(), format!("invalid path {invalid}"));
+        }
+    }
+}
@@ -0,0 +1,526 @@
+//! Videos embedded in notes, through ffmpeg: the one bundled with the app
+//! if there is one, else one the user installed. Screen recordings dropped
+//! into notes are often hundreds of megabytes, so [`compress_video`]
+//! re-encodes them to a size or bitrate, and [`convert_gif`] turns animated
+//! GIFs, which are even bigger, into looping videos.
+
+use std::{
+    env, fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use image::ImageFormat;
+use num_traits::ToPrimitive;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager, State};
+
+use crate::{
+    compress_data,
+    conflict::{self, Bases, SaveResult, Version},
+    error::BackendError,
+    inline,
+    jobs::{self, Job, JobKind},
+    markdown, metrics, process, send, windows,
+    workers::{self, Priority},
+    workspace::{self, write_atomic},
+    BackendEvent, EventChannel,
+};
+
+const THUMBNAILS_DIR: &str = "video-thumbnails";
+/// More thumbnails than this are evicted, the oldest first.
+const MAX_THUMBNAILS: usize = 500;
+const THUMBNAIL_MAX_SIZE: usize = 200 * 1024;
+const DEFAULT_MAX_EDGE: u32 = 640;
+const DEFAULT_TIMESTAMP: f64 = 1.0;
+const FRAME_TIMEOUT: Duration = Duration::from_secs(30);
+/// Of the files in the cache, which `compress_data` can return.
+const EXTENSIONS: [&str; 2] = ["png", "jpg"];
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+const COMPRESS_TIMEOUT: Duration = Duration::from_secs(6 * 60 * 60);
+/// Bits per second.
+const AUDIO_BITRATE: u64 = 128_000;
+const MIN_VIDEO_BITRATE: u64 = 100_000;
+/// Of a size to fit in, what's left for the streams after the container.
+const PAYLOAD: f64 = 0.95;
+/// Of VP9 at constant quality, from 0 to 63.
+const WEBM_CRF: &str = "35";
+const EVEN_SCALE: &str = "scale=trunc(iw/2)*2:trunc(ih/2)*2";
+
+/// What [`compress_video`] aims for.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum VideoTarget {
+    /// Bytes the whole file should fit in; needs ffprobe to tell the
+    /// duration.
+    MaxSize(u64),
+    /// Bits per second of the video stream.
+    Bitrate(u64),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressedVideo {
+    path: String,
+    size: u64,
+    original_size: u64,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum GifTarget {
+    /// H.264, which plays everywhere.
+    #[default]
+    Mp4,
+    /// VP9, smaller still.
+    Webm,
+}
+
+impl GifTarget {
+    fn extension(self) -> &'static str {
+        match self {
+            GifTarget::Mp4 => "mp4",
+            GifTarget::Webm => "webm",
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConvertedGif {
+    path: String,
+    size: u64,
+    original_size: u64,
+    /// Embeds of the GIF in the document that now embed the video.
+    rewritten: usize,
+    /// Of the document, if it was changed: saved, or on conflict, to a
+    /// copy next to it.
+    saved: Option<SaveResult>,
+    /// Whether the GIF was deleted.
+    removed: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VideoThumbnail {
+    path: String,
+    width: u32,
+    height: u32,
+    from_cache: bool,
+}
+
+/// The bundled ffmpeg, next to the app's executable, or the user's.
+pub fn ffmpeg() -> Result<PathBuf, String> {
+    let name = if cfg!(windows) { "ffmpeg.exe" } else { "ffmpeg" };
+    let bundled = env::current_exe().ok().and_then(|exe| exe.parent().map(|dir| dir.join(name)));
+    bundled
+        .filter(|path| path.is_file())
+        .or_else(|| process::find_program("ffmpeg"))
+        .ok_or_else(|| "ffmpeg not found; install it to work with videos".to_owned())
+}
+
+/// The frame at `timestamp` seconds as PNG; empty if the video is shorter.
+async fn extract_frame(ffmpeg: &Path, path: &str, timestamp: f64) -> Result<Vec<u8>, String> {
+    let args: Vec<String> = [
+        "-hide_banner", "-loglevel", "error", "-ss", &format!("{timestamp:.3}"), "-i", path,
+        "-frames:v", "1", "-f", "image2pipe", "-vcodec", "png", "-",
+    ]
+    .iter()
+    .map(|&arg| arg.to_owned())
+    .collect();
+    process::run_piped(ffmpeg, &args, Vec::new(), FRAME_TIMEOUT).await
+}
+
+/// Scales `frame` down to `max_edge` and compresses it.
+fn make_thumbnail(frame: &[u8], max_edge: u32) -> Result<(Vec<u8>, u32, u32), String> {
+    let mut image = image::load_from_memory(frame).map_err(|e| format!("decode frame: {e}"))?;
+    if image.width().max(image.height()) > max_edge {
+        image = image.thumbnail(max_edge, max_edge);
+    }
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png).map_err(|e| format!("encode png: {e}"))?;
+    Ok((compress_data(png, THUMBNAIL_MAX_SIZE)?, image.width(), image.height()))
+}
+
+/// Of the thumbnail of `path` as it is now, so a changed video gets a new
+/// one.
+fn cache_name(path: &str, timestamp: f64, max_edge: u32) -> Result<String, String> {
+    let modified = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| format!("stat {path}: {e}"))?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let key = format!("{path}\0{modified}\0{timestamp}\0{max_edge}");
+    Ok(hex::encode(Sha256::digest(key.as_bytes()))[..32].to_owned())
+}
+
+/// Removes all but the newest [`MAX_THUMBNAILS`] in `dir`.
+fn evict(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let mut files: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter_map(|e| Some((e.metadata().and_then(|m| m.modified()).ok()?, e.path())))
+        .collect();
+    if files.len() <= MAX_THUMBNAILS {
+        return;
+    }
+    files.sort();
+    for (_, file) in &files[..files.len() - MAX_THUMBNAILS] {
+        if let Err(e) = fs::remove_file(file) {
+            log::warn!("video: remove {}: {e}", file.display());
+        }
+    }
+}
+
+/// A poster for the video at `path`: its frame at `timestamp` seconds, or
+/// the first if it's shorter, at most `max_edge` pixels wide and high.
+/// Thumbnails are cached until the video changes.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn video_thumbnail(
+    path: String, timestamp: Option<f64>, max_edge: Option<u32>, app: AppHandle,
+) -> Result<VideoThumbnail, BackendError> {
+    log::info!("video_thumbnail start: {path}");
+    let timestamp = timestamp.filter(|t| t.is_finite() && *t >= 0.0).unwrap_or(DEFAULT_TIMESTAMP);
+    let max_edge = max_edge.filter(|&edge| edge > 0).unwrap_or(DEFAULT_MAX_EDGE);
+    let dir = app.path().app_cache_dir().map_err(|e| format!("app_cache_dir: {e}"))?.join(THUMBNAILS_DIR);
+    let name = cache_name(&path, timestamp, max_edge)?;
+    let cached = EXTENSIONS.iter().map(|ext| dir.join(format!("{name}.{ext}"))).find(|file| file.is_file());
+    if let Some(file) = cached {
+        if let Ok((width, height)) = image::image_dimensions(&file) {
+            log::info!("video_thumbnail done (cached)");
+            let path = file.to_string_lossy().into_owned();
+            return Ok(VideoThumbnail { path, width, height, from_cache: true });
+        }
+    }
+
+    let ffmpeg = ffmpeg()?;
+    let mut frame = extract_frame(&ffmpeg, &path, timestamp).await?;
+    if frame.is_empty() && timestamp > 0.0 {
+        frame = extract_frame(&ffmpeg, &path, 0.0).await?;
+    }
+    if frame.is_empty() {
+        return Err(BackendError::external(format!("video_thumbnail: no frame in {path}")));
+    }
+    let result = workers::run(Priority::Interactive, move || {
+        let (data, width, height) = make_thumbnail(&frame, max_edge)?;
+        let format = image::guess_format(&data).map_err(|e| format!("guess_format: {e}"))?;
+        let extension = if format == ImageFormat::Png { "png" } else { "jpg" };
+        fs::create_dir_all(&dir).map_err(|e| format!("create {}: {e}", dir.display()))?;
+        let file = dir.join(format!("{name}.{extension}"));
+        write_atomic(&file, &data)?;
+        evict(&dir);
+        Ok::<_, String>(VideoThumbnail { path: file.to_string_lossy().into_owned(), width, height, from_cache: false })
+    })
+    .await;
+    match result {
+        Ok(Ok(thumbnail)) => {
+            log::info!("video_thumbnail done: {} x {}", thumbnail.width, thumbnail.height);
+            Ok(thumbnail)
+        }
+        Ok(Err(e)) => Err(format!("video_thumbnail task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+/// In seconds, if ffprobe can tell.
+async fn duration(ffmpeg: &Path, path: &str) -> Option<f64> {
+    let name = if cfg!(windows) { "ffprobe.exe" } else { "ffprobe" };
+    let ffprobe = Some(ffmpeg.with_file_name(name))
+        .filter(|ffprobe| ffprobe.is_file())
+        .or_else(|| process::find_program("ffprobe"))?;
+    let args = ["-v", "error", "-show_entries", "format=duration", "-of", "default=noprint_wrappers=1:nokey=1", path]
+        .map(str::to_owned);
+    let output = process::run_piped(&ffprobe, &args, Vec::new(), PROBE_TIMEOUT).await.ok()?;
+    String::from_utf8_lossy(&output).trim().parse().ok().filter(|d: &f64| d.is_finite() && *d > 0.0)
+}
+
+fn video_bitrate(target: VideoTarget, duration: Option<f64>) -> Result<u64, String> {
+    match target {
+        VideoTarget::Bitrate(bitrate) => Ok(bitrate.max(MIN_VIDEO_BITRATE)),
+        VideoTarget::MaxSize(max_size) => {
+            let duration = duration.ok_or("fitting a video in a size needs its duration; is ffprobe installed?")?;
+            let total = (max_size.to_f64().unwrap_or(f64::MAX) * 8.0 * PAYLOAD / duration).to_u64().unwrap_or(u64::MAX);
+            Ok(total.saturating_sub(AUDIO_BITRATE).max(MIN_VIDEO_BITRATE))
+        }
+    }
+}
+
+/// Where ffmpeg writes before the output is complete, with the same
+/// extension, which it picks the container by.
+fn partial_path(out: &Path) -> PathBuf {
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    match out.extension() {
+        Some(extension) => out.with_file_name(format!("{stem}.partial.{}", extension.to_string_lossy())),
+        None => out.with_file_name(format!("{stem}.partial")),
+    }
+}
+
+/// Runs ffmpeg with `args`, then the output, written to a partial file
+/// first so a cancelled run leaves nothing behind; returns the output's size.
+/// Progress is in milliseconds of the video, out of `total` if known.
+async fn encode(
+    ffmpeg: &Path, args: &[&str], out: &str, total: usize, job: &Job, channel: &EventChannel,
+) -> Result<u64, String> {
+    let partial = partial_path(Path::new(out));
+    let args: Vec<String> = ["-hide_banner", "-loglevel", "error", "-nostats", "-progress", "pipe:1", "-y"]
+        .iter()
+        .chain(args)
+        .map(|&arg| arg.to_owned())
+        .chain([partial.to_string_lossy().into_owned()])
+        .collect();
+    let mut pending = String::new();
+    let result = process::run_streamed(ffmpeg, &args, Vec::new(), COMPRESS_TIMEOUT, |chunk| {
+        if job.is_cancelled() {
+            return Err(jobs::CANCELLED.to_owned());
+        }
+        pending.push_str(&String::from_utf8_lossy(chunk));
+        while let Some(end) = pending.find('\n') {
+            let line: String = pending.drain(..=end).collect();
+            let Some(micros) = line.trim().strip_prefix("out_time_us=").and_then(|v| v.parse::<u64>().ok()) else {
+                continue;
+            };
+            let done = usize::try_from(micros / 1000).unwrap_or(usize::MAX);
+            let done = if total > 0 { done.min(total) } else { done };
+            job.progress(done, total, "");
+            send(channel, BackendEvent::Progress { done, total, message: String::new() });
+        }
+        Ok(())
+    })
+    .await;
+    if let Err(e) = result {
+        // ffmpeg was killed, maybe halfway through
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, out).map_err(|e| format!("rename {} to {out}: {e}", partial.display()))?;
+    Ok(fs::metadata(out).map_err(|e| format!("stat {out}: {e}"))?.len())
+}
+
+/// In milliseconds, or 0 if ffprobe can't tell.
+fn total_ms(duration: Option<f64>) -> usize {
+    duration.and_then(|d| (d * 1000.0).to_usize()).unwrap_or(0)
+}
+
+/// Re-encodes `path` to `out` as H.264 and AAC; returns its size.
+async fn transcode(
+    path: &str, out: &str, target: VideoTarget, job: &Job, channel: &EventChannel,
+) -> Result<u64, String> {
+    if Path::new(path) == Path::new(out) {
+        return Err("the output must be another file".to_owned());
+    }
+    let ffmpeg = ffmpeg()?;
+    let duration = duration(&ffmpeg, path).await;
+    let bitrate = video_bitrate(target, duration)?;
+    log::info!("compress_video: {} kbit/s", bitrate / 1000);
+    let args = [
+        "-i", path,
+        // H.264 needs even dimensions
+        "-vf", EVEN_SCALE, "-c:v", "libx264", "-preset", "medium",
+        "-b:v", &bitrate.to_string(), "-maxrate", &bitrate.to_string(), "-bufsize", &(bitrate * 2).to_string(),
+        "-pix_fmt", "yuv420p", "-c:a", "aac", "-b:a", &AUDIO_BITRATE.to_string(), "-movflags", "+faststart",
+    ];
+    encode(&ffmpeg, &args, out, total_ms(duration), job, channel).await
+}
+
+/// Re-encodes the video at `path` to `out`, which can't be the same file,
+/// to fit `target`. Progress, in milliseconds of the video, goes to
+/// `channel`, followed by `Done`; it runs as a job that can be cancelled.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn compress_video(
+    path: String, out: String, target: VideoTarget, priority: Option<Priority>, channel: EventChannel,
+) -> Result<CompressedVideo, BackendError> {
+    log::info!("compress_video start: {path}");
+    let original_size = fs::metadata(&path).map_err(|e| format!("compress_video: stat {path}: {e}"))?.len();
+    let job = jobs::start_as(channel.job_id(), JobKind::Compression, priority.unwrap_or_default(), path.clone());
+    let timer = metrics::timer("compress_video").size(original_size);
+    let result = transcode(&path, &out, target, &job, &channel).await;
+    timer.finish(result.is_ok());
+    job.finish(&result);
+    match result {
+        Ok(size) => {
+            send(&channel, BackendEvent::Done);
+            log::info!("compress_video done: {original_size} to {size} bytes");
+            Ok(CompressedVideo { path: out, size, original_size })
+        }
+        Err(e) => Err(format!("compress_video: {e}").into()),
+    }
+}
+
+/// `![alt](src "title")`, as embeds are written.
+static IMAGE_EMBED: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"!\[([^\]]*)\]\(\s*(<[^>]*>|[^)\s]+)(?:\s+"[^"]*")?\s*\)"#).expect("embed regex"));
+
+/// `src` with the extension of its path swapped for `extension`, keeping
+/// any query or fragment.
+fn swap_extension(src: &str, extension: &str) -> String {
+    let end = src.find(['?', '#']).unwrap_or(src.len());
+    let (path, rest) = src.split_at(end);
+    let stem = match path.rfind('.') {
+        Some(dot) if !path[dot..].contains('/') => &path[..dot],
+        _ => path,
+    };
+    format!("{stem}.{extension}{rest}")
+}
+
+/// Whether `markdown`, a document in `base_dir`, embeds `gif`.
+fn embeds(markdown: &str, base_dir: &Path, gif: &Path) -> bool {
+    IMAGE_EMBED.captures_iter(markdown).any(|captures| {
+        let src = &captures[2];
+        let bare = src.strip_prefix('<').and_then(|s| s.strip_suffix('>')).unwrap_or(src);
+        inline::resolve_local(bare, Some(base_dir)).as_deref() == Some(gif)
+    })
+}
+
+/// The documents in `root` that still embed `gif`.
+fn embedded_in(root: &Path, gif: &Path) -> Result<Vec<PathBuf>, String> {
+    let gif = inline::normalize(gif);
+    let mut found = Vec::new();
+    for document in workspace::documents(root)? {
+        let Ok(markdown) = fs::read_to_string(&document) else { continue };
+        if embeds(&markdown, document.parent().unwrap_or(Path::new("")), &gif) {
+            found.push(document);
+        }
+    }
+    Ok(found)
+}
+
+/// Replaces the embeds of `gif` in `markdown`, a document in `base_dir`,
+/// with looping, muted videos, as GIFs play; returns how many there were.
+fn rewrite_embeds(markdown: &str, base_dir: &Path, gif: &Path, extension: &str) -> (String, usize) {
+    let gif = inline::normalize(gif);
+    let mut count = 0;
+    let rewritten = IMAGE_EMBED.replace_all(markdown, |captures: &Captures| {
+        let src = &captures[2];
+        let bare = src.strip_prefix('<').and_then(|s| s.strip_suffix('>')).unwrap_or(src);
+        if inline::resolve_local(bare, Some(base_dir)).as_deref() != Some(gif.as_path()) {
+            return captures[0].to_owned();
+        }
+        count += 1;
+        let src = markdown::escape(&swap_extension(bare, extension));
+        let label = match captures[1].trim() {
+            "" => String::new(),
+            alt => format!(r#" aria-label="{}""#, markdown::escape(alt)),
+        };
+        format!(r#"<video src="{src}"{label} autoplay loop muted playsinline></video>"#)
+    });
+    (rewritten.into_owned(), count)
+}
+
+/// Converts `path` to a video next to it; returns its path and size.
+async fn gif_to_video(
+    path: &str, target: GifTarget, job: &Job, channel: &EventChannel,
+) -> Result<(String, u64), String> {
+    let out = Path::new(path).with_extension(target.extension());
+    if out.exists() {
+        return Err(format!("{} already exists", out.display()));
+    }
+    let out = out.to_string_lossy().into_owned();
+    let ffmpeg = ffmpeg()?;
+    let duration = duration(&ffmpeg, path).await;
+    let codec: &[&str] = match target {
+        GifTarget::Mp4 => &["-c:v", "libx264", "-preset", "slow", "-crf", "23", "-pix_fmt", "yuv420p",
+            "-movflags", "+faststart"],
+        GifTarget::Webm => &["-c:v", "libvpx-vp9", "-b:v", "0", "-crf", WEBM_CRF, "-pix_fmt", "yuv420p"],
+    };
+    // GIFs have no sound; both codecs need even dimensions with yuv420p
+    let args: Vec<&str> = ["-i", path, "-an", "-vf", EVEN_SCALE].iter().chain(codec).copied().collect();
+    let size = encode(&ffmpeg, &args, &out, total_ms(duration), job, channel).await?;
+    Ok((out, size))
+}
+
+/// Converts the animated GIF at `path` to an MP4 or WebM next to it, which
+/// is usually a tenth of the size, and if `document` is given, embeds the
+/// video wherever it embedded the GIF; it's saved as [`conflict::save_document`]
+/// does, from the version `expected`. With `remove_original`, the GIF is
+/// deleted once no document in `workspace`, or the folder of the document,
+/// embeds it. Progress goes to `channel` as for [`compress_video`].
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value, clippy::too_many_arguments)]
+pub async fn convert_gif(
+    path: String, target: Option<GifTarget>, document: Option<String>, expected: Option<Version>,
+    workspace: Option<String>, remove_original: Option<bool>, bases: State<'_, Bases>, window: tauri::Window,
+    channel: EventChannel,
+) -> Result<ConvertedGif, BackendError> {
+    log::info!("convert_gif start: {path}");
+    if let Some(document) = &document {
+        windows::check_owner(window.label(), document).map_err(|e| format!("convert_gif: {e}"))?;
+    }
+    let original_size = fs::metadata(&path).map_err(|e| format!("convert_gif: stat {path}: {e}"))?.len();
+    let job = jobs::start_as(channel.job_id(), JobKind::Compression, Priority::Background, path.clone());
+    let timer = metrics::timer("convert_gif").size(original_size);
+    let result = gif_to_video(&path, target.unwrap_or_default(), &job, &channel).await;
+    timer.finish(result.is_ok());
+    job.finish(&result);
+    let (out, size) = result.map_err(|e| format!("convert_gif: {e}"))?;
+
+    let (mut rewritten, mut saved) = (0, None);
+    if let Some(document) = &document {
+        let markdown = fs::read_to_string(document).map_err(|e| format!("convert_gif: read {document}: {e}"))?;
+        let base_dir = Path::new(document).parent().unwrap_or(Path::new(""));
+        let extension = target.unwrap_or_default().extension();
+        let (updated, count) = rewrite_embeds(&markdown, base_dir, Path::new(&path), extension);
+        if count > 0 {
+            let result = conflict::save_as_user(bases.inner().clone(), document.clone(), updated, expected).await;
+            saved = Some(result.map_err(|e| format!("convert_gif: {e}"))?);
+        }
+        rewritten = count;
+    }
+
+    let mut removed = false;
+    if remove_original.unwrap_or(false) {
+        let root = workspace
+            .map(PathBuf::from)
+            .or_else(|| document.as_ref().and_then(|d| Path::new(d).parent().map(Path::to_owned)))
+            .unwrap_or_else(|| Path::new(&path).parent().unwrap_or(Path::new("")).to_owned());
+        match embedded_in(&root, Path::new(&path)) {
+            Ok(documents) if documents.is_empty() => match fs::remove_file(&path) {
+                Ok(()) => removed = true,
+                Err(e) => log::warn!("convert_gif: remove {path}: {e}"),
+            },
+            Ok(documents) => log::info!("convert_gif: keeping {path}, embedded in {} documents", documents.len()),
+            Err(e) => log::warn!("convert_gif: keeping {path}: {e}"),
+        }
+    }
+    send(&channel, BackendEvent::Done);
+    log::info!("convert_gif done: {original_size} to {size} bytes, {rewritten} embeds");
+    Ok(ConvertedGif { path: out, size, original_size, rewritten, saved, removed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embeds_resolve_against_the_document() {
+        let gif = Path::new("/notes/media/cat.gif");
+        assert!(embeds("![a cat](media/cat.gif)", Path::new("/notes"), gif));
+        assert!(embeds(r#"![](<../media/cat.gif> "title")"#, Path::new("/notes/daily"), gif));
+        assert!(!embeds("![a cat](media/cat.gif)", Path::new("/elsewhere"), gif));
+        assert!(!embeds("[a link](media/cat.gif)", Path::new("/notes"), gif));
+    }
+
+    #[test]
+    fn rewritten_embeds_keep_the_rest() {
+        let (markdown, count) = rewrite_embeds(
+            "![a cat](media/cat.gif) and ![](media/dog.gif)",
+            Path::new("/notes"),
+            Path::new("/notes/media/cat.gif"),
+            "mp4",
+        );
+        assert_eq!(count, 1);
+        assert_eq!(
+            markdown,
+            concat!(
+                r#"<video src="media/cat.mp4" aria-label="a cat" autoplay loop muted playsinline></video>"#,
+                " and ![](media/dog.gif)"
+            )
+        );
+        assert_eq!(swap_extension("a/b.gif?x=1#t", "webm"), "a/b.webm?x=1#t");
+    }
+}
@@ -0,0 +1,213 @@
+//! Voice notes: records the microphone, sending its level while it does so
+//! a meter can show it, and saves the recording in a document's asset
+//! folder, encoded with ffmpeg as Opus or AAC.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::ipc::Channel;
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::{
+    dictation::{self, Recording},
+    error::BackendError,
+    process, video,
+};
+
+/// How often the level is sent.
+const LEVEL_INTERVAL: Duration = Duration::from_millis(100);
+const ENCODE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum MemoFormat {
+    #[default]
+    Opus,
+    M4a,
+}
+
+impl MemoFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            MemoFormat::Opus => "opus",
+            MemoFormat::M4a => "m4a",
+        }
+    }
+
+    fn codec_args(self) -> [&'static str; 4] {
+        match self {
+            MemoFormat::Opus => ["-c:a", "libopus", "-b:a", "32k"],
+            MemoFormat::M4a => ["-c:a", "aac", "-b:a", "64k"],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoOptions {
+    /// The asset folder the recording is saved in.
+    dir: String,
+    #[serde(default)]
+    format: MemoFormat,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase", tag = "event", content = "data")]
+pub enum MemoEvent {
+    /// Of the last moment, from 0 to 1.
+    #[serde(rename_all = "camelCase")]
+    Level { rms: f32, peak: f32, elapsed_ms: u64 },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VoiceMemo {
+    path: String,
+    duration_ms: u64,
+    size: u64,
+}
+
+struct Memo {
+    options: MemoOptions,
+    recording: Recording,
+    stop: Arc<AtomicBool>,
+    started: Instant,
+}
+
+static MEMO: Mutex<Option<Memo>> = Mutex::new(None);
+
+/// Sends the level of what was recorded since the last time until `stop`.
+async fn meter(samples: Arc<Mutex<Vec<f32>>>, stop: Arc<AtomicBool>, started: Instant, channel: Channel<MemoEvent>) {
+    let mut seen = 0;
+    let mut ticks = tokio::time::interval(LEVEL_INTERVAL);
+    while !stop.load(Ordering::Relaxed) {
+        ticks.tick().await;
+        let (rms, peak) = {
+            let samples = samples.lock().expect("samples lock poisoned");
+            let new = &samples[seen.min(samples.len())..];
+            seen = samples.len();
+            (dictation::rms(new), new.iter().fold(0.0_f32, |peak, s| peak.max(s.abs())))
+        };
+        let elapsed_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+        if channel.send(MemoEvent::Level { rms: rms.min(1.0), peak: peak.min(1.0), elapsed_ms }).is_err() {
+            // the page is gone; the recording goes on until stopped
+            return;
+        }
+    }
+}
+
+/// Like `memo-2025-01-31-142501.opus`, numbered if that's taken.
+fn memo_path(dir: &Path, format: MemoFormat) -> PathBuf {
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let stem = now
+        .format(format_description!("memo-[year]-[month]-[day]-[hour][minute][second]"))
+        .unwrap_or_else(|_| "memo".to_owned());
+    let extension = format.extension();
+    let mut path = dir.join(format!("{stem}.{extension}"));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{stem}-{n}.{extension}"));
+        n += 1;
+    }
+    path
+}
+
+async fn encode(samples: &[f32], sample_rate: u32, format: MemoFormat, out: &Path) -> Result<(), String> {
+    let ffmpeg = video::ffmpeg()?;
+    let mut args: Vec<String> = ["-hide_banner", "-loglevel", "error", "-f", "wav", "-i", "pipe:0"]
+        .iter()
+        .chain(format.codec_args().iter())
+        .map(|&arg| arg.to_owned())
+        .collect();
+    args.push("-y".to_owned());
+    args.push(out.to_string_lossy().into_owned());
+    let result = process::run_piped(&ffmpeg, &args, dictation::wav(samples, sample_rate), ENCODE_TIMEOUT).await;
+    if result.is_err() {
+        let _ = fs::remove_file(out);
+    }
+    result.map(|_| ())
+}
+
+/// Starts recording the microphone, sending its level to `channel` until
+/// [`stop_voice_memo`] or [`cancel_voice_memo`].
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn start_voice_memo(options: MemoOptions, channel: Channel<MemoEvent>) -> Result<(), BackendError> {
+    log::info!("start_voice_memo start");
+    let already = || BackendError::invalid("a voice memo is already being recorded");
+    if MEMO.lock().expect("voice memo lock poisoned").is_some() {
+        return Err(already());
+    }
+    let stop = Arc::new(AtomicBool::new(false));
+    let recording = dictation::record(stop.clone()).await.map_err(|e| format!("start_voice_memo: {e}"))?;
+    let mut memo = MEMO.lock().expect("voice memo lock poisoned");
+    // another one started while the microphone was opened
+    if memo.is_some() {
+        stop.store(true, Ordering::Relaxed);
+        return Err(already());
+    }
+    let started = Instant::now();
+    tauri::async_runtime::spawn(meter(recording.samples.clone(), stop.clone(), started, channel));
+    *memo = Some(Memo { options, recording, stop, started });
+    log::info!("start_voice_memo done");
+    Ok(())
+}
+
+/// Stops recording and saves the memo in the asset folder.
+#[tauri::command]
+pub async fn stop_voice_memo() -> Result<VoiceMemo, BackendError> {
+    log::info!("stop_voice_memo start");
+    let memo = MEMO.lock().expect("voice memo lock poisoned").take().ok_or("stop_voice_memo: not recording")?;
+    memo.stop.store(true, Ordering::Relaxed);
+    let duration_ms = u64::try_from(memo.started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    let samples = std::mem::take(&mut *memo.recording.samples.lock().expect("samples lock poisoned"));
+    if samples.is_empty() {
+        return Err(BackendError::invalid("stop_voice_memo: nothing was recorded"));
+    }
+    let dir = Path::new(&memo.options.dir);
+    fs::create_dir_all(dir).map_err(|e| format!("stop_voice_memo: create {}: {e}", dir.display()))?;
+    let path = memo_path(dir, memo.options.format);
+    encode(&samples, memo.recording.sample_rate, memo.options.format, &path)
+        .await
+        .map_err(|e| format!("stop_voice_memo: {e}"))?;
+    let size = fs::metadata(&path).map_or(0, |m| m.len());
+    log::info!("stop_voice_memo done: {}", path.display());
+    Ok(VoiceMemo { path: path.to_string_lossy().into_owned(), duration_ms, size })
+}
+
+/// Stops recording and throws the recording away.
+#[tauri::command]
+pub fn cancel_voice_memo() {
+    if let Some(memo) = MEMO.lock().expect("voice memo lock poisoned").take() {
+        memo.stop.store(true, Ordering::Relaxed);
+        log::info!("cancel_voice_memo");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memos_do_not_overwrite_each_other() {
+        let dir = std::env::temp_dir().join(format!("emmm-voice-memo-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("create the asset folder");
+        let first = memo_path(&dir, MemoFormat::M4a);
+        let name = first.file_name().and_then(|n| n.to_str()).expect("a file name");
+        assert!(name.starts_with("memo-") && name.ends_with(".m4a"), "{name}");
+        fs::write(&first, b"").expect("write the first memo");
+        let second = memo_path(&dir, MemoFormat::M4a);
+        assert_ne!(second, first);
+        assert!(!second.exists());
+        assert_eq!(memo_path(&dir, MemoFormat::default()).extension().and_then(|e| e.to_str()), Some("opus"));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,311 @@
+//! Waveform images of audio attachments, for a preview of audio embeds in
+//! documents and exports. Audio is decoded with symphonia, or with ffmpeg
+//! for what symphonia can't read, like the Opus of voice memos.
+
+use std::{fmt::Write, fs::File, io::Cursor, path::Path, time::Duration};
+
+use image::{ImageFormat, Rgba, RgbaImage};
+use serde::Deserialize;
+use symphonia::core::{
+    audio::SampleBuffer, codecs::DecoderOptions, errors::Error as DecodeError, formats::FormatOptions,
+    io::MediaSourceStream, meta::MetadataOptions, probe::Hint,
+};
+use tauri::ipc::Response;
+
+use crate::{
+    error::BackendError,
+    process, video,
+    workers::{self, Priority},
+};
+
+/// Samples of a channel summed up in one value of the envelope.
+const BLOCK: usize = 256;
+const MAX_WIDTH: u32 = 8192;
+const MAX_HEIGHT: u32 = 2048;
+/// What ffmpeg decodes to; plenty for an envelope.
+const FFMPEG_RATE: &str = "8000";
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum WaveformFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WaveformOptions {
+    /// In pixels.
+    width: u32,
+    height: u32,
+    format: WaveformFormat,
+    /// Like `#4a90e2`.
+    color: String,
+    bar_width: u32,
+    gap: u32,
+}
+
+impl Default for WaveformOptions {
+    fn default() -> WaveformOptions {
+        WaveformOptions {
+            width: 600,
+            height: 64,
+            format: WaveformFormat::Png,
+            color: "#4a90e2".to_owned(),
+            bar_width: 2,
+            gap: 1,
+        }
+    }
+}
+
+/// The loudest sample of each [`BLOCK`], as they're decoded.
+#[derive(Default)]
+struct Envelope {
+    peaks: Vec<f32>,
+    peak: f32,
+    count: usize,
+}
+
+impl Envelope {
+    fn extend(&mut self, samples: impl IntoIterator<Item = f32>) {
+        for sample in samples {
+            self.peak = self.peak.max(sample.abs());
+            self.count += 1;
+            if self.count == BLOCK {
+                self.peaks.push(self.peak);
+                (self.peak, self.count) = (0.0, 0);
+            }
+        }
+    }
+
+    fn finish(mut self) -> Vec<f32> {
+        if self.count > 0 {
+            self.peaks.push(self.peak);
+        }
+        self.peaks
+    }
+}
+
+/// `Ok(None)` if symphonia doesn't know the format or codec.
+fn decode(path: &Path) -> Result<Option<Vec<f32>>, String> {
+    let file = File::open(path).map_err(|e| format!("open {}: {e}", path.display()))?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let probed =
+        symphonia::default::get_probe().format(&hint, source, &FormatOptions::default(), &MetadataOptions::default());
+    let mut format = match probed {
+        Ok(probed) => probed.format,
+        Err(DecodeError::Unsupported(_)) => return Ok(None),
+        Err(e) => return Err(format!("probe {}: {e}", path.display())),
+    };
+    let Some(track) = format.default_track() else { return Ok(None) };
+    let track_id = track.id;
+    let mut decoder = match symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default()) {
+        Ok(decoder) => decoder,
+        Err(DecodeError::Unsupported(_)) => return Ok(None),
+        Err(e) => return Err(format!("decoder for {}: {e}", path.display())),
+    };
+    let mut envelope = Envelope::default();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(DecodeError::ResetRequired) => break,
+            Err(e) => return Err(format!("read {}: {e}", path.display())),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // a damaged packet is skipped
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(e) => return Err(format!("decode {}: {e}", path.display())),
+        };
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+        buffer.copy_interleaved_ref(decoded);
+        // interleaved: a block's peak is of whichever channel is loudest
+        envelope.extend(buffer.samples().iter().copied());
+    }
+    Ok(Some(envelope.finish()))
+}
+
+/// Decodes `path` with ffmpeg, to mono.
+async fn decode_ffmpeg(path: &Path) -> Result<Vec<f32>, String> {
+    let ffmpeg = video::ffmpeg()?;
+    let args: Vec<String> = ["-hide_banner", "-loglevel", "error", "-i", &path.to_string_lossy(), "-ac", "1",
+        "-ar", FFMPEG_RATE, "-f", "s16le", "-"]
+        .iter()
+        .map(|&arg| arg.to_owned())
+        .collect();
+    let output = process::run_piped(&ffmpeg, &args, Vec::new(), FFMPEG_TIMEOUT).await?;
+    let mut envelope = Envelope::default();
+    envelope.extend(output.chunks_exact(2).map(|s| f32::from(i16::from_le_bytes([s[0], s[1]])) / f32::from(i16::MAX)));
+    Ok(envelope.finish())
+}
+
+/// The height of each of `bars` bars, from 0 to 1, relative to the loudest.
+fn bars(envelope: &[f32], bars: usize) -> Vec<f32> {
+    if envelope.is_empty() || bars == 0 {
+        return vec![0.0; bars];
+    }
+    let per_bar = envelope.len().div_ceil(bars).max(1);
+    let mut heights: Vec<f32> = (0..bars)
+        .map(|i| {
+            let from = (i * envelope.len() / bars).min(envelope.len() - 1);
+            envelope[from..(from + per_bar).min(envelope.len())].iter().copied().fold(0.0, f32::max)
+        })
+        .collect();
+    let loudest = heights.iter().copied().fold(0.0, f32::max);
+    if loudest > 0.0 {
+        for height in &mut heights {
+            *height /= loudest;
+        }
+    }
+    heights
+}
+
+fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let channel = |at: usize| hex.get(at..at + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!("invalid color {color:?}; use #rrggbb")),
+    }
+}
+
+/// Where each bar is, as `(x, y, height)`: centered vertically, at least a
+/// pixel high so silence shows as a line.
+fn layout(options: &WaveformOptions, heights: &[f32]) -> Vec<(u32, u32, u32)> {
+    let step = options.bar_width + options.gap;
+    heights
+        .iter()
+        .zip((0..).map(|i: u32| i * step))
+        .map(|(&height, x)| {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+            let bar = ((height * options.height as f32).round() as u32).clamp(1, options.height);
+            (x, (options.height - bar) / 2, bar)
+        })
+        .collect()
+}
+
+fn render_png(options: &WaveformOptions, heights: &[f32], [r, g, b]: [u8; 3]) -> Result<Vec<u8>, String> {
+    let mut image = RgbaImage::new(options.width, options.height);
+    for (x, y, height) in layout(options, heights) {
+        for px in x..(x + options.bar_width).min(options.width) {
+            for py in y..y + height {
+                image.put_pixel(px, py, Rgba([r, g, b, 255]));
+            }
+        }
+    }
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png).map_err(|e| format!("encode png: {e}"))?;
+    Ok(png)
+}
+
+fn render_svg(options: &WaveformOptions, heights: &[f32], [r, g, b]: [u8; 3]) -> String {
+    let (width, height) = (options.width, options.height);
+    let mut svg = format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"##
+    );
+    let _ = write!(svg, r##"<path fill="#{r:02x}{g:02x}{b:02x}" d=""##);
+    for (x, y, bar) in layout(options, heights) {
+        let _ = write!(svg, "M{x} {y}h{}v{bar}h-{}z", options.bar_width, options.bar_width);
+    }
+    svg.push_str("\"/></svg>");
+    svg
+}
+
+/// A waveform of the audio at `path`, as PNG or SVG data: bars from left
+/// to right across `width`, scaled to the loudest.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn audio_waveform(path: String, options: Option<WaveformOptions>) -> Result<Response, BackendError> {
+    log::info!("audio_waveform start: {path}");
+    let mut options = options.unwrap_or_default();
+    options.width = options.width.clamp(1, MAX_WIDTH);
+    options.height = options.height.clamp(1, MAX_HEIGHT);
+    options.bar_width = options.bar_width.max(1);
+    let color = parse_color(&options.color)?;
+    let file = Path::new(&path).to_owned();
+    let decoded = match workers::run(Priority::Interactive, move || decode(&file)).await {
+        Ok(result) => result.map_err(|e| format!("audio_waveform: {e}"))?,
+        Err(e) => return Err(format!("workers::run: {e}").into()),
+    };
+    let envelope = match decoded {
+        Some(envelope) => envelope,
+        None => decode_ffmpeg(Path::new(&path)).await.map_err(|e| format!("audio_waveform: {e}"))?,
+    };
+    let count = options.width.div_ceil(options.bar_width + options.gap) as usize;
+    let heights = bars(&envelope, count);
+    let data = match options.format {
+        WaveformFormat::Png => render_png(&options, &heights, color)?,
+        WaveformFormat::Svg => render_svg(&options, &heights, color).into_bytes(),
+    };
+    log::info!("audio_waveform done");
+    Ok(Response::new(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> WaveformOptions {
+        WaveformOptions { width: 10, height: 8, bar_width: 2, gap: 1, ..WaveformOptions::default() }
+    }
+
+    #[test]
+    fn the_envelope_is_scaled_to_bars() {
+        let mut envelope = Envelope::default();
+        envelope.extend((0..BLOCK).map(|i| if i == 10 { -0.9 } else { 0.5 }));
+        envelope.extend([0.2; 3]);
+        assert_eq!(envelope.finish(), [0.9, 0.2]);
+
+        assert_eq!(bars(&[0.1, 0.2, 0.4, 0.8], 2), [0.25, 1.0]);
+        assert_eq!(bars(&[0.5], 3), [1.0; 3]);
+        assert_eq!(bars(&[], 3), [0.0; 3]);
+        assert_eq!(bars(&[0.0, 0.0], 2), [0.0; 2]);
+    }
+
+    #[test]
+    fn colors_are_hex() {
+        assert_eq!(parse_color("#4A90e2"), Ok([0x4a, 0x90, 0xe2]));
+        assert_eq!(parse_color("4a90e2"), Ok([0x4a, 0x90, 0xe2]));
+        for invalid in ["#fff", "#zzzzzz", "#ééé", ""] {
+            assert!(parse_color(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn bars_are_centered_and_at_least_a_pixel_high() {
+        let heights = [1.0, 0.5, 0.0];
+        assert_eq!(layout(&options(), &heights), [(0, 0, 8), (3, 2, 4), (6, 3, 1)]);
+        assert_eq!(
+            render_svg(&options(), &heights, [0x4a, 0x90, 0xe2]),
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="8" viewBox="0 0 10 8">"##.to_owned()
+                + r##"<path fill="#4a90e2" d="M0 0h2v8h-2zM3 2h2v4h-2zM6 3h2v1h-2z"/></svg>"##
+        );
+
+        let png = render_png(&options(), &heights, [1, 2, 3]).expect("render a png");
+        let image = image::load_from_memory(&png).expect("decode the png").to_rgba8();
+        assert_eq!(image.dimensions(), (10, 8));
+        assert_eq!(image.get_pixel(0, 7), &Rgba([1, 2, 3, 255]));
+        assert_eq!(image.get_pixel(2, 0)[3], 0);
+        assert_eq!(image.get_pixel(7, 3), &Rgba([1, 2, 3, 255]));
+        assert_eq!(image.get_pixel(7, 2)[3], 0);
+    }
+
+    #[test]
+    fn unknown_formats_are_left_to_ffmpeg() {
+        let path = std::env::temp_dir().join(format!("emmm-waveform-test-{}.opus", std::process::id()));
+        std::fs::write(&path, b"not audio at all").expect("write the file");
+        assert_eq!(decode(&path), Ok(None));
+        std::fs::remove_file(&path).ok();
+        assert!(decode(&path).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! User-defined webhooks notified of editing activity, for automations
+//! like rebuilding a site after a publish.
+
+use std::{sync::RwLock, time::Duration};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tauri::Url;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    error::BackendError,
+    net::{self, reqwest},
+    secrets,
+};
+
+/// Delays before the second, third and fourth attempt.
+const RETRY_DELAYS: [Duration; 3] =
+    [Duration::from_secs(2), Duration::from_secs(10), Duration::from_secs(60)];
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookEvent {
+    Save,
+    Export,
+    Publish,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    url: String,
+    /// Events to send; all of them if empty.
+    #[serde(default)]
+    events: Vec<WebhookEvent>,
+    /// Keychain entry with a key to sign payloads with; the HMAC-SHA256 is
+    /// sent as `X-Emmm-Signature: sha256=<hex>`.
+    secret_name: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Payload<'a> {
+    event: WebhookEvent,
+    path: &'a str,
+    /// RFC 3339.
+    timestamp: String,
+}
+
+static WEBHOOKS: RwLock<Vec<Webhook>> = RwLock::new(Vec::new());
+
+impl Webhook {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let url = Url::parse(&self.url).map_err(|e| format!("invalid url {}: {e}", self.url))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("not an http(s) url: {}", self.url));
+        }
+        Ok(())
+    }
+}
+
+fn signature(key: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+        .expect("hmac takes any key size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn deliver(webhook: Webhook, body: Vec<u8>) {
+    let secret = match &webhook.secret_name {
+        Some(name) => match secrets::require_secret(name.clone()).await {
+            Ok(secret) => Some(secret),
+            Err(e) => {
+                log::warn!("webhook {}: {e}", webhook.url);
+                return;
+            }
+        },
+        None => None,
+    };
+    let client = match net::client() {
+        Ok(client) => client,
+        Err(e) => {
+            log::warn!("webhook {}: {e}", webhook.url);
+            return;
+        }
+    };
+    for attempt in 0..=RETRY_DELAYS.len() {
+        if attempt > 0 {
+            tokio::time::sleep(RETRY_DELAYS[attempt - 1]).await;
+        }
+        let mut request = client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(secret) = &secret {
+            request = request.header("X-Emmm-Signature", signature(secret, &body));
+        }
+        match request.send().await.and_then(reqwest::Response::error_for_status) {
+            Ok(_) => return,
+            Err(e) => log::warn!("webhook {} (attempt {}): {e}", webhook.url, attempt + 1),
+        }
+    }
+}
+
+/// Notifies the webhooks interested in `event` in the background.
+pub fn emit(event: WebhookEvent, path: &str) {
+    let webhooks: Vec<_> = WEBHOOKS
+        .read()
+        .expect("webhooks lock poisoned")
+        .iter()
+        .filter(|w| w.events.is_empty() || w.events.contains(&event))
+        .cloned()
+        .collect();
+    if webhooks.is_empty() {
+        return;
+    }
+    let payload = Payload {
+        event,
+        path,
+        timestamp: OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+    };
+    let body = serde_json::to_vec(&payload).expect("payload serializes");
+    for webhook in webhooks {
+        tauri::async_runtime::spawn(deliver(webhook, body.clone()));
+    }
+}
+
+/// Replaces the configured webhooks.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn configure_webhooks(webhooks: Vec<Webhook>) -> Result<(), BackendError> {
+    for webhook in &webhooks {
+        webhook.validate()?;
+    }
+    log::info!("configure_webhooks: {} webhooks", webhooks.len());
+    *WEBHOOKS.write().expect("webhooks lock poisoned") = webhooks;
+    Ok(())
+}
+
+/// For events the frontend sees first, like autosaves.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn emit_webhook_event(event: WebhookEvent, path: String) {
+    emit(event, &path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads_are_signed_with_hmac_sha256() {
+        assert_eq!(
+            signature("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8",
+        );
+        let webhook = |url: &str| Webhook { url: url.to_owned(), events: Vec::new(), secret_name: None };
+        assert!(webhook("https://example.com/hook").validate().is_ok());
+        assert!(webhook("ftp://example.com/hook").validate().is_err());
+        assert!(webhook("example.com/hook").validate().is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Requests to the Weixin official account API for the frontend, made from
+//! here so they go through the proxy and extra certificates of [`net`] like
+//! every other request.
+
+use percent_encoding::percent_decode_str;
+use tauri::ipc::{InvokeBody, Request, Response};
+
+use crate::{
+    error::BackendError,
+    jobs::{self, JobKind},
+    net::{self, reqwest},
+    workers::Priority,
+};
+
+const API: &str = "https://api.weixin.qq.com/cgi-bin/";
+/// Materials can be videos.
+const MAX_RESPONSE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Like `draft/batchget`: only the API's endpoints can be reached.
+fn check_endpoint(endpoint: &str) -> Result<(), String> {
+    let valid = !endpoint.is_empty()
+        && endpoint.split('/').all(|part| {
+            !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("invalid Weixin API endpoint {endpoint:?}"))
+    }
+}
+
+async fn send(endpoint: &str, token: Option<&str>, request: reqwest::RequestBuilder) -> Result<Vec<u8>, BackendError> {
+    let request = match token {
+        Some(token) => request.query(&[("access_token", token)]),
+        None => request,
+    };
+    let step = format!("weixin {endpoint}");
+    let response = request.send().await.map_err(|e| BackendError::network(step.clone(), e))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(BackendError::network(step, status));
+    }
+    net::read_capped(response, MAX_RESPONSE_SIZE).await.map_err(|e| BackendError::network(step, e))
+}
+
+/// POSTs the JSON `body` to `endpoint` of the API, with the access token
+/// if there's one, and gives back what it answered: JSON, or a material's
+/// data.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn weixin_request(
+    endpoint: String, access_token: Option<String>, body: String,
+) -> Result<Response, BackendError> {
+    log::info!("weixin_request start: {endpoint}");
+    check_endpoint(&endpoint).map_err(BackendError::invalid)?;
+    let client = net::client()?;
+    let request = client
+        .post(format!("{API}{endpoint}"))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body);
+    let data = send(&endpoint, access_token.as_deref(), request).await?;
+    log::info!("weixin_request done: {} bytes", data.len());
+    Ok(Response::new(data))
+}
+
+/// Uploads the file in the body of the request to the endpoint in its
+/// `endpoint` header, as the form field `media` named by its `name` header,
+/// percent-encoded, with the access token in its `access-token` header.
+/// Gives back the API's JSON answer.
+#[tauri::command]
+pub async fn weixin_upload(request: Request<'_>) -> Result<Response, BackendError> {
+    let header = |name: &str| {
+        let value = request.headers().get(name).and_then(|v| v.to_str().ok());
+        value.map(|v| percent_decode_str(v).decode_utf8_lossy().into_owned())
+    };
+    let endpoint = header("endpoint").ok_or_else(|| BackendError::invalid("weixin_upload: no endpoint header"))?;
+    let name = header("name").ok_or_else(|| BackendError::invalid("weixin_upload: no name header"))?;
+    let access_token = header("access-token");
+    let InvokeBody::Raw(data) = request.body() else {
+        return Err(BackendError::invalid("weixin_upload: the file must be the body"));
+    };
+    log::info!("weixin_upload start: {endpoint}, {} bytes", data.len());
+    check_endpoint(&endpoint).map_err(BackendError::invalid)?;
+
+    let client = net::client()?;
+    let part = reqwest::multipart::Part::bytes(data.clone()).file_name(name);
+    let form = reqwest::multipart::Form::new().part("media", part);
+    let request = client.post(format!("{API}{endpoint}")).multipart(form);
+    let job = jobs::start(JobKind::Upload, Priority::Interactive, &endpoint);
+    let result = send(&endpoint, access_token.as_deref(), request).await;
+    job.finish(&result.as_ref().map(|_| ()).map_err(ToString::to_string));
+    let data = result?;
+    log::info!("weixin_upload done");
+    Ok(Response::new(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_endpoints_of_the_api_are_allowed() {
+        for valid in ["stable_token", "draft/batchget", "material/get_material"] {
+            assert!(check_endpoint(valid).is_ok(), "{valid}");
+        }
+        for invalid in ["", "../sns/x", "draft//update", "draft/update?x=1", "https://example.com", "a/"] {
+            assert!(check_endpoint(invalid).is_err(), "{invalid}");
+        }
+    }
+}
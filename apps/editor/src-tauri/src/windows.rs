@@ -0,0 +1,164 @@
+//! Document windows besides the main one, e.g. to compare two notes side by
+//! side, and which window has which document open: a document belongs to
+//! the first window that asks for its [`crate::conflict::document_version`],
+//! and only that window saves it, so two autosaves can't overwrite each
+//! other. A window lets go of its document when it opens another one, and
+//! when it closes.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock, Mutex,
+    },
+};
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Serialize;
+use tauri::{AppHandle, Manager, WebviewUrl, WebviewWindowBuilder};
+
+use crate::{error::BackendError, print};
+
+/// Labels of document windows start with this, followed by a number.
+pub const PREFIX: &str = "document-";
+
+static NEXT_WINDOW: AtomicU64 = AtomicU64::new(1);
+/// The document each window has open, by label.
+static OWNERS: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(Mutex::default);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowInfo {
+    label: String,
+    document: Option<String>,
+}
+
+/// The window that has `path` open, if one has.
+pub fn owner(path: &str) -> Option<String> {
+    let owners = OWNERS.lock().expect("windows lock poisoned");
+    owners.iter().find(|(_, document)| *document == path).map(|(label, _)| label.clone())
+}
+
+/// Makes `path` the document of `window`, in place of the one it had
+/// open; fails with the label of another window that still has it open.
+fn try_claim(window: &str, path: &str) -> Result<(), String> {
+    let mut owners = OWNERS.lock().expect("windows lock poisoned");
+    match owners.iter().find(|(_, document)| *document == path) {
+        Some((label, _)) if label != window => Err(label.clone()),
+        _ => {
+            owners.insert(window.to_owned(), path.to_owned());
+            Ok(())
+        }
+    }
+}
+
+/// Makes `path` the document of `window`, in place of the one it had
+/// open; fails if another window still has it open.
+pub fn claim(window: &str, path: &str) -> Result<(), String> {
+    try_claim(window, path).map_err(|label| format!("{path} is already open in window {label}"))
+}
+
+/// Fails if a window other than `window` has `path` open.
+pub fn check_owner(window: &str, path: &str) -> Result<(), String> {
+    match owner(path) {
+        Some(label) if label != window => Err(format!("{path} is open in window {label}")),
+        _ => Ok(()),
+    }
+}
+
+/// Lets go of the document of the window `label`, once it's closed.
+pub fn forget(label: &str) {
+    if let Some(document) = OWNERS.lock().expect("windows lock poisoned").remove(label) {
+        log::info!("windows: {label} closed with {document}");
+    }
+}
+
+/// Opens a window showing the document at `path`, or focuses the one that
+/// already does; returns the window's label. The page gets the path as
+/// the `document` query parameter. Async, as creating a window from a
+/// synchronous command deadlocks on Windows.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn open_window(path: String, app: AppHandle) -> Result<String, BackendError> {
+    log::info!("open_window: {path}");
+    let label = format!("{PREFIX}{}", NEXT_WINDOW.fetch_add(1, Ordering::Relaxed));
+    // claimed first, so a second call for the path finds this window
+    if let Err(owner) = try_claim(&label, &path) {
+        // which may still be being opened
+        if let Some(window) = app.get_webview_window(&owner) {
+            window.set_focus().map_err(|e| format!("focus {owner}: {e}"))?;
+        }
+        return Ok(owner);
+    }
+    let query = utf8_percent_encode(&path, NON_ALPHANUMERIC);
+    let title = Path::new(&path).file_name().map_or_else(|| path.clone(), |name| name.to_string_lossy().into_owned());
+    let built = WebviewWindowBuilder::new(&app, &label, WebviewUrl::App(format!("index.html?document={query}").into()))
+        .title(title)
+        .inner_size(900.0, 800.0)
+        .build();
+    if let Err(e) = built {
+        forget(&label);
+        return Err(format!("create window {label}: {e}").into());
+    }
+    Ok(label)
+}
+
+fn release(window: &str, path: &str) {
+    let mut owners = OWNERS.lock().expect("windows lock poisoned");
+    if owners.get(window).is_some_and(|document| document == path) {
+        owners.remove(window);
+    }
+}
+
+/// Lets go of `path` in the calling window, like when it's closed there
+/// without another one opened, so another window can open it.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn release_document(path: String, window: tauri::Window) {
+    release(window.label(), &path);
+}
+
+/// The open windows, but those printing, with the documents each has open.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub fn list_windows(app: AppHandle) -> Vec<WindowInfo> {
+    let owners = OWNERS.lock().expect("windows lock poisoned");
+    let mut windows: Vec<WindowInfo> = app
+        .webview_windows()
+        .into_keys()
+        .filter(|label| !label.starts_with(print::PREFIX))
+        .map(|label| {
+            let document = owners.get(&label).cloned();
+            WindowInfo { label, document }
+        })
+        .collect();
+    windows.sort_by(|a, b| a.label.cmp(&b.label));
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_own_one_document() {
+        claim("test-a", "/notes/one.md").expect("claimed");
+        assert_eq!(owner("/notes/one.md").as_deref(), Some("test-a"));
+        assert_eq!(try_claim("test-b", "/notes/one.md"), Err("test-a".to_owned()));
+        check_owner("test-a", "/notes/one.md").expect("the owner");
+        assert!(check_owner("test-b", "/notes/one.md").is_err());
+
+        // opening another document lets go of the first
+        claim("test-a", "/notes/two.md").expect("claimed");
+        assert_eq!(owner("/notes/one.md"), None);
+        claim("test-b", "/notes/one.md").expect("claimed");
+
+        release("test-b", "/notes/other.md");
+        assert_eq!(owner("/notes/one.md").as_deref(), Some("test-b"));
+        release("test-b", "/notes/one.md");
+        assert_eq!(owner("/notes/one.md"), None);
+        forget("test-a");
+        assert_eq!(owner("/notes/two.md"), None);
+    }
+}
@@ -0,0 +1,231 @@
+//! The most frequent words and two-word phrases of a document or a whole
+//! workspace, to spot overused ones in a draft. Common words like "the"
+//! are left out with a stopword list for the language, and so are phrases
+//! starting or ending with one; phrases don't cross sentences.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::BackendError,
+    prose_lint, readability, spellcheck,
+    workers::{self, Priority},
+    workspace,
+};
+
+const DEFAULT_LIMIT: usize = 50;
+
+const ENGLISH: &[&str] = &[
+    "a", "about", "after", "all", "also", "an", "and", "any", "are", "as", "at", "be", "because",
+    "been", "but", "by", "can", "could", "did", "do", "does", "for", "from", "had", "has", "have",
+    "he", "her", "him", "his", "how", "i", "if", "in", "into", "is", "it", "its", "it's", "just",
+    "me", "more", "my", "no", "not", "of", "on", "one", "or", "our", "out", "she", "so", "some",
+    "than", "that", "the", "their", "them", "then", "there", "these", "they", "this", "to", "up",
+    "us", "was", "we", "were", "what", "when", "which", "who", "will", "with", "would", "you",
+    "your",
+];
+const GERMAN: &[&str] = &[
+    "aber", "als", "am", "an", "auch", "auf", "aus", "bei", "bin", "bis", "da", "das", "dass",
+    "dem", "den", "der", "des", "die", "doch", "du", "ein", "eine", "einem", "einen", "einer",
+    "es", "für", "hat", "hatte", "ich", "ihr", "im", "in", "ist", "ja", "kann", "man", "mit",
+    "nach", "nicht", "noch", "nur", "oder", "sich", "sie", "sind", "so", "um", "und", "uns",
+    "von", "vor", "war", "was", "wenn", "wie", "wir", "wird", "zu", "zum", "zur",
+];
+const FRENCH: &[&str] = &[
+    "a", "à", "au", "aux", "avec", "ce", "ces", "c'est", "dans", "de", "des", "du", "elle", "en",
+    "est", "et", "il", "ils", "je", "la", "le", "les", "leur", "l'", "mais", "me", "mon", "ne",
+    "nous", "on", "ou", "par", "pas", "plus", "pour", "qu'il", "que", "qui", "sa", "se", "ses",
+    "son", "sont", "sur", "ta", "te", "un", "une", "vous", "y",
+];
+const SPANISH: &[&str] = &[
+    "a", "al", "como", "con", "de", "del", "el", "ella", "en", "es", "esta", "este", "ha", "la",
+    "las", "le", "lo", "los", "más", "me", "mi", "no", "o", "para", "pero", "por", "que", "se",
+    "si", "sin", "son", "su", "sus", "te", "un", "una", "uno", "y", "ya",
+];
+const ITALIAN: &[&str] = &[
+    "a", "al", "alla", "anche", "che", "ci", "con", "da", "del", "della", "di", "e", "è", "gli",
+    "ha", "i", "il", "in", "la", "le", "lo", "ma", "mi", "non", "per", "più", "se", "si", "sono",
+    "su", "un", "una", "uno",
+];
+const PORTUGUESE: &[&str] = &[
+    "a", "ao", "as", "com", "como", "da", "das", "de", "do", "dos", "e", "é", "ela", "ele", "em",
+    "mais", "mas", "na", "no", "não", "nos", "o", "os", "ou", "para", "por", "que", "se", "sem",
+    "seu", "sua", "um", "uma",
+];
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FrequencyOptions {
+    /// Picks the stopwords, like `en` or `de-AT`; English by default.
+    language: Option<String>,
+    /// Counted along with the built-in ones.
+    extra_stopwords: Vec<String>,
+    /// Keeps the stopwords in.
+    include_stopwords: bool,
+    /// Of each list; 50 by default.
+    limit: Option<usize>,
+    /// Terms seen fewer times are left out; 2 by default.
+    min_count: Option<usize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Term {
+    /// Lowercase.
+    term: String,
+    count: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Frequencies {
+    /// The words counted, stopwords included.
+    total_words: usize,
+    /// The most frequent first.
+    words: Vec<Term>,
+    bigrams: Vec<Term>,
+}
+
+fn stopwords(language: Option<&str>) -> &'static [&'static str] {
+    let language = language.unwrap_or("en").to_lowercase().replace('_', "-");
+    match language.split('-').next().unwrap_or_default() {
+        "de" => GERMAN,
+        "fr" => FRENCH,
+        "es" => SPANISH,
+        "it" => ITALIAN,
+        "pt" => PORTUGUESE,
+        _ => ENGLISH,
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    words: HashMap<String, usize>,
+    bigrams: HashMap<String, usize>,
+}
+
+impl Counts {
+    fn add(&mut self, text: &str, is_stopword: &impl Fn(&str) -> bool) {
+        let prose = readability::prose(text);
+        let words = spellcheck::words(&prose);
+        let mut next = 0;
+        for (_, end) in prose_lint::sentences(&prose) {
+            let mut previous: Option<String> = None;
+            while let Some(&(_, word)) = words.get(next).filter(|&&(at, _)| at < end) {
+                next += 1;
+                let word = word.to_lowercase().replace('’', "'");
+                self.total += 1;
+                let stop = is_stopword(&word);
+                if !stop {
+                    *self.words.entry(word.clone()).or_default() += 1;
+                }
+                match previous.take() {
+                    Some(first) if !stop => *self.bigrams.entry(format!("{first} {word}")).or_default() += 1,
+                    _ => {}
+                }
+                // a phrase doesn't start with a stopword either
+                previous = (!stop).then_some(word);
+            }
+        }
+    }
+}
+
+fn top(counts: HashMap<String, usize>, limit: usize, min_count: usize) -> Vec<Term> {
+    let mut terms: Vec<Term> = counts
+        .into_iter()
+        .filter(|&(_, count)| count >= min_count)
+        .map(|(term, count)| Term { term, count })
+        .collect();
+    terms.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.term.cmp(&b.term)));
+    terms.truncate(limit);
+    terms
+}
+
+fn frequencies(texts: impl IntoIterator<Item = String>, options: &FrequencyOptions) -> Frequencies {
+    let builtin = stopwords(options.language.as_deref());
+    let extra: Vec<String> = options.extra_stopwords.iter().map(|w| w.to_lowercase()).collect();
+    let is_stopword =
+        |word: &str| !options.include_stopwords && (builtin.contains(&word) || extra.iter().any(|w| w == word));
+    let mut counts = Counts::default();
+    for text in texts {
+        counts.add(&text, &is_stopword);
+    }
+    let limit = options.limit.unwrap_or(DEFAULT_LIMIT);
+    let min_count = options.min_count.unwrap_or(2);
+    Frequencies {
+        total_words: counts.total,
+        words: top(counts.words, limit, min_count),
+        bigrams: top(counts.bigrams, limit, min_count),
+    }
+}
+
+fn workspace_frequencies(root: &Path, options: &FrequencyOptions) -> Result<Frequencies, String> {
+    let texts = workspace::documents(root)?.into_iter().filter_map(|path| match fs::read_to_string(&path) {
+        Ok(text) => Some(text),
+        Err(e) => {
+            log::warn!("word_frequency: {}: {e}", path.display());
+            None
+        }
+    });
+    Ok(frequencies(texts, options))
+}
+
+/// The most frequent words and phrases of the markdown `text`, or of
+/// every document of `workspace` if there's no text.
+#[tauri::command]
+#[allow(clippy::needless_pass_by_value)]
+pub async fn word_frequency(
+    text: Option<String>, workspace: Option<String>, options: FrequencyOptions,
+) -> Result<Frequencies, BackendError> {
+    let result = workers::run(Priority::Interactive, move || match (text, workspace) {
+        (Some(text), _) => Ok(frequencies([text], &options)),
+        (None, Some(workspace)) => workspace_frequencies(Path::new(&workspace), &options),
+        (None, None) => Err("a text or a workspace is needed".to_owned()),
+    }).await;
+    match result {
+        Ok(Ok(frequencies)) => Ok(frequencies),
+        Ok(Err(e)) => Err(format!("word_frequency task: {e}").into()),
+        Err(e) => Err(format!("workers::run: {e}").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(terms: &[Term]) -> Vec<(&str, usize)> {
+        terms.iter().map(|t| (t.term.as_str(), t.count)).collect()
+    }
+
+    #[test]
+    fn words_and_phrases_are_counted_without_stopwords() {
+        let text = "The red car is fast. The red car stopped. Red cars, red car!\n\n# Red heading\n";
+        let found = frequencies([text.to_owned()], &FrequencyOptions::default());
+        assert_eq!(found.total_words, 13);
+        assert_eq!(terms(&found.words), [("red", 4), ("car", 3)]);
+        assert_eq!(terms(&found.bigrams), [("red car", 3)]);
+
+        let options = FrequencyOptions { min_count: Some(1), limit: Some(3), ..FrequencyOptions::default() };
+        let found = frequencies([text.to_owned()], &options);
+        assert_eq!(terms(&found.words), [("red", 4), ("car", 3), ("cars", 1)]);
+        assert_eq!(terms(&found.bigrams), [("red car", 3), ("car stopped", 1), ("cars red", 1)]);
+    }
+
+    #[test]
+    fn stopwords_follow_the_options() {
+        let options = FrequencyOptions {
+            language: Some("de_AT".to_owned()),
+            extra_stopwords: vec!["Hund".to_owned()],
+            min_count: Some(1),
+            ..FrequencyOptions::default()
+        };
+        let found = frequencies(["Der Hund und die Katze.".to_owned()], &options);
+        assert_eq!(terms(&found.words), [("katze", 1)]);
+
+        let options = FrequencyOptions { include_stopwords: true, min_count: Some(1), ..FrequencyOptions::default() };
+        let found = frequencies(["Stop here. Here stop.".to_owned()], &options);
+        assert_eq!(terms(&found.bigrams), [("here stop", 1), ("stop here", 1)]);
+    }
+}
@@ -0,0 +1,169 @@
+//! A bounded pool for CPU-bound work, like compressing images and
+//! exporting. `spawn_blocking` alone starts a thread for every task, so
+//! dropping fifty images would take every core and make the UI stutter;
+//! [`run`] waits for one of at most [`configure_workers`] slots first.
+//! Blocking IO doesn't need a slot and still uses `spawn_blocking`.
+//!
+//! Interactive tasks, which someone is waiting for, go before background
+//! ones like bulk recompression and indexing: background tasks leave a
+//! slot free for them and don't start while one is waiting, or while
+//! [`crate::power::throttled`], like on battery.
+
+use std::{
+    sync::{LazyLock, Mutex},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Notify, task::JoinError};
+
+use crate::power;
+
+static POOL: LazyLock<Pool> = LazyLock::new(|| Pool {
+    state: Mutex::new(State { running: 0, background: 0, waiting: 0, limit: default_limit() }),
+    freed: Notify::new(),
+});
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Priority {
+    #[default]
+    Interactive,
+    Background,
+}
+
+struct State {
+    running: usize,
+    /// Of those running.
+    background: usize,
+    /// Interactive tasks waiting for a slot.
+    waiting: usize,
+    limit: usize,
+}
+
+impl State {
+    fn has_slot(&self, priority: Priority) -> bool {
+        match priority {
+            Priority::Interactive => self.running < self.limit,
+            // one slot is kept for interactive tasks, unless there's only one
+            Priority::Background =>
+                self.waiting == 0 && self.running < self.limit && self.background < self.limit.saturating_sub(1).max(1),
+        }
+    }
+}
+
+struct Pool {
+    state: Mutex<State>,
+    /// A slot was freed, the limit raised or background work let go.
+    freed: Notify,
+}
+
+/// Taken while a task runs.
+struct Slot(Priority);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut state = POOL.state.lock().expect("workers lock poisoned");
+        state.running -= 1;
+        if self.0 == Priority::Background {
+            state.background -= 1;
+        }
+        drop(state);
+        POOL.freed.notify_waiters();
+    }
+}
+
+/// Counts an interactive task as waiting until it gets a slot or gives up.
+struct Waiting;
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        POOL.state.lock().expect("workers lock poisoned").waiting -= 1;
+        // background tasks held back for it may go now
+        POOL.freed.notify_waiters();
+    }
+}
+
+/// Every core but one, which is left for the UI.
+fn default_limit() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1).max(1))
+}
+
+async fn acquire(priority: Priority) -> Slot {
+    let mut waiting = None;
+    loop {
+        // created before checking, so a slot freed in between isn't missed
+        let freed = POOL.freed.notified();
+        {
+            let mut state = POOL.state.lock().expect("workers lock poisoned");
+            if state.has_slot(priority) && (priority == Priority::Interactive || !power::throttled()) {
+                state.running += 1;
+                if priority == Priority::Background {
+                    state.background += 1;
+                }
+                drop(state);
+                drop(waiting);
+                return Slot(priority);
+            }
+            if priority == Priority::Interactive && waiting.is_none() {
+                state.waiting += 1;
+                waiting = Some(Waiting);
+            }
+        }
+        freed.await;
+    }
+}
+
+/// Has tasks waiting look for a slot again, like when background work
+/// isn't throttled anymore.
+pub fn wake() {
+    POOL.freed.notify_waiters();
+}
+
+/// Whether no interactive task is running or waiting for a slot.
+pub fn interactive_idle() -> bool {
+    let state = POOL.state.lock().expect("workers lock poisoned");
+    state.running == state.background && state.waiting == 0
+}
+
+/// `spawn_blocking` for CPU-bound `f`, once a slot is free for `priority`.
+pub async fn run<T, F>(priority: Priority, f: F) -> Result<T, JoinError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let slot = acquire(priority).await;
+    tokio::task::spawn_blocking(move || {
+        let _slot = slot;
+        f()
+    }).await
+}
+
+/// Runs at most `max_parallelism` CPU-bound tasks at once; every core but
+/// one if it's `None` or 0. Running tasks aren't stopped when it's lowered.
+#[tauri::command]
+pub fn configure_workers(max_parallelism: Option<usize>) {
+    let limit = max_parallelism.filter(|&n| n > 0).unwrap_or_else(default_limit);
+    log::info!("configure_workers: {limit}");
+    POOL.state.lock().expect("workers lock poisoned").limit = limit;
+    POOL.freed.notify_waiters();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn background_tasks_leave_a_slot_for_interactive_ones() {
+        let state = |running, background, waiting, limit| State { running, background, waiting, limit };
+        assert!(state(2, 2, 0, 4).has_slot(Priority::Background));
+        assert!(!state(3, 3, 0, 4).has_slot(Priority::Background));
+        assert!(state(3, 3, 0, 4).has_slot(Priority::Interactive));
+        assert!(!state(4, 3, 0, 4).has_slot(Priority::Interactive));
+        // nothing starts in the background while someone waits
+        assert!(!state(1, 0, 1, 4).has_slot(Priority::Background));
+        // with one slot, background work still gets to run
+        assert!(state(0, 0, 0, 1).has_slot(Priority::Background));
+        assert!(!state(1, 1, 0, 1).has_slot(Priority::Interactive));
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use ignore::WalkBuilder;
+
+/// Whether walking a workspace skips what `.gitignore` and `.ignore` files
+/// exclude, like `node_modules` or build output.
+static RESPECT_IGNORE_FILES: AtomicBool = AtomicBool::new(true);
+
+/// Files we treat as documents when working on a whole folder.
+pub const DOCUMENT_EXTENSIONS: &[&str] = &["md", "markdown", "emmm"];
+
+pub fn is_document(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| DOCUMENT_EXTENSIONS.iter().any(|d| d.eq_ignore_ascii_case(e)))
+}
+
+/// Lists every document under `root`, recursively and sorted, skipping
+/// hidden files and directories and, unless turned off, ignored ones.
+pub fn documents(root: &Path) -> Result<Vec<PathBuf>, String> {
+    walk(root, RESPECT_IGNORE_FILES.load(Ordering::Relaxed), is_document)
+}
+
+/// Like [`documents`], but lists every file, including ignored ones
+/// whatever the setting, for when a file seen before must not disappear
+/// just because it became ignored.
+pub fn all_files(root: &Path) -> Result<Vec<PathBuf>, String> {
+    walk(root, false, |_| true)
+}
+
+fn walk(root: &Path, respect: bool, filter: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>, String> {
+    let walker = WalkBuilder::new(root)
+        .hidden(true)
+        .parents(respect)
+        .ignore(respect)
+        .git_ignore(respect)
+        .git_exclude(respect)
+        .git_global(false)
+        // a workspace needn't be a repository for its .gitignore to count
+        .require_git(false)
+        .build();
+    let mut result = Vec::new();
+    for entry in walker {
+        let entry = entry.map_err(|e| format!("walk {}: {e}", root.display()))?;
+        if entry.file_type().is_some_and(|t| t.is_file()) && filter(entry.path()) {
+            result.push(entry.into_path());
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+/// `path` relative to `root` with forward slashes, for use in URLs.
+pub fn relative_url_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    Some(parts.join("/"))
+}
+
+/// Writes to a hidden temporary file next to `path` first, so readers
+/// never see a partial file.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), String> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("not a file path: {}", path.display()))?;
+    let temp = path.with_file_name(format!(".{}.part", name.to_string_lossy()));
+    fs::write(&temp, data).map_err(|e| format!("write {}: {e}", temp.display()))?;
+    fs::rename(&temp, path).map_err(|e| format!("rename {}: {e}", path.display()))
+}
+
+/// Sets whether walking a workspace, for previews, feeds, site exports and
+/// the like, respects `.gitignore` files; it does by default.
+#[tauri::command]
+pub fn configure_workspace(respect_gitignore: bool) {
+    log::info!("configure_workspace: respect_gitignore {respect_gitignore}");
+    RESPECT_IGNORE_FILES.store(respect_gitignore, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_skip_hidden_and_ignored_files() {
+        let root = std::env::temp_dir().join(format!("emmm-workspace-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["notes", ".hidden", "build"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["a.md", "notes/b.MARKDOWN", "notes/c.txt", ".hidden/d.md", "build/e.md"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(root.join(".gitignore"), "build/\n").unwrap();
+
+        let relative = |paths: Vec<PathBuf>| -> Vec<String> {
+            paths.iter().filter_map(|p| relative_url_path(&root, p)).collect()
+        };
+        assert_eq!(relative(documents(&root).unwrap()), ["a.md", "notes/b.MARKDOWN"]);
+        assert_eq!(relative(all_files(&root).unwrap()), ["a.md", "build/e.md", "notes/b.MARKDOWN", "notes/c.txt"]);
+        assert_eq!(relative_url_path(&root, Path::new("/elsewhere/a.md")), None);
+
+        write_atomic(&root.join("a.md"), b"saved").unwrap();
+        assert_eq!(fs::read(root.join("a.md")).unwrap(), b"saved");
+        assert!(!root.join(".a.md.part").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
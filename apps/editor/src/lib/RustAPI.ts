@@ -1,46 +1,104 @@
-import { Channel, invoke } from "@tauri-apps/api/core";
+import { Channel, type InvokeArgs, type InvokeOptions, invoke as tauriInvoke } from "@tauri-apps/api/core";
+
+export type ErrorCode = 'notFound' | 'permissionDenied' | 'alreadyExists' | 'invalid' | 'network'
+    | 'timeout' | 'cancelled' | 'io' | 'external' | 'internal';
+
+/** What every command rejects with, and `failed` events carry. */
+export type BackendErrorInfo = {
+    code: ErrorCode,
+    /** what was being done, like `read /notes/a.md` */
+    step: string | null,
+    /** the cause, or without a step, the whole chain */
+    message: string,
+    /** what the code means, in the user's language */
+    summary: string | null
+};
 
 type BackendEvent = {
     event: 'failed'
     data: {
-        msg: string
+        error: BackendErrorInfo
     }
 } | {
     event: 'inlined'
     data: {
         result: string
     }
+} | {
+    event: 'chunk'
+    data: {
+        text: string
+    }
+} | {
+    event: 'progress'
+    data: {
+        done: number,
+        total: number,
+        message: string
+    }
+} | {
+    event: 'word'
+    data: {
+        offset: number,
+        length: number
+    }
 } | {
     event: 'done',
     data: {}
 }
 
+/** Every event carries the ID of the call it belongs to, which is the
+ *  ID of its job for commands that run as one. */
+type TaggedBackendEvent = BackendEvent & {jobId: number};
+
 type BackendEventKey = BackendEvent['event'];
 type BackendEventData = {[E in BackendEvent as E['event']]: E['data']};
-type BackendEventHandler<key extends BackendEventKey> = (data: BackendEventData[key]) => void;
+type BackendEventHandler<key extends BackendEventKey> = (data: BackendEventData[key], jobId: number) => void;
+
+export class BackendError extends Error {
+    readonly code: ErrorCode;
+    readonly step: string | null;
+    readonly summary: string | null;
 
-class BackendError extends Error {
-    constructor(msg: string) {
-        super(msg);
+    constructor(info: BackendErrorInfo) {
+        super(info.step ? `${info.step}: ${info.message}` : info.message);
         this.name = 'BackendError';
+        this.code = info.code;
+        this.step = info.step;
+        this.summary = info.summary;
+    }
+
+    static from(e: unknown): unknown {
+        if (typeof e === 'object' && e !== null && 'code' in e && 'message' in e)
+            return new BackendError(e as BackendErrorInfo);
+        return e;
+    }
+}
+
+/** `invoke`, rejecting with a `BackendError` */
+async function invoke<T>(cmd: string, args?: InvokeArgs, options?: InvokeOptions): Promise<T> {
+    try {
+        return await tauriInvoke<T>(cmd, args, options);
+    } catch (e) {
+        throw BackendError.from(e);
     }
 }
 
 function createChannel(handler: {[key in BackendEventKey]?: BackendEventHandler<key>}) {
-    const channel = new Channel<BackendEvent>;
+    const channel = new Channel<TaggedBackendEvent>;
     channel.onmessage = (msg) => {
         let h = handler[msg.event];
         // 'as any' because a little quirk in TypeScript's inference system
         // a functor of type (A | B) => C obviously accepts A | B as the parameter
         // however, (A => C) | (B => C) will not accept it
         if (h) {
-            h(msg.data as any);
+            h(msg.data as any, msg.jobId);
             return;
         }
 
         switch (msg.event) {
         case 'failed':
-            throw new BackendError(msg.data.msg);
+            throw new BackendError(msg.data.error);
         default:
             throw new Error('unhandled event: ' + msg.event);
         }
@@ -48,9 +106,1909 @@ function createChannel(handler: {[key in BackendEventKey]?: BackendEventHandler<
     return channel;
 }
 
+export type HighlightOptions = {
+    languages: {
+        name: string,
+        extensions: string[]
+    }[],
+    themes: string[]
+};
+
+export type FormatterConfig = {
+    program: string,
+    args?: string[],
+    timeoutMs?: number
+};
+
+/** All lengths in millimeters. */
+export type PrintOptions = {
+    paper?: 'A3' | 'A4' | 'A5' | 'B4' | 'B5' | 'letter' | 'legal' | 'ledger' 
+        | {widthMm: number, heightMm: number},
+    orientation?: 'portrait' | 'landscape',
+    /** must leave at least 10mm of the page each way */
+    margins?: {top: number, right: number, bottom: number, left: number},
+    timeoutMs?: number
+};
+
+export type SlideOptions = {
+    /** headings at this level or above start a new slide */
+    splitLevel?: number,
+    title?: string,
+    imageMaxSize?: number,
+    codeTheme?: string,
+    /** link to this reveal.js instead of inlining the bundled one */
+    revealUrl?: string
+};
+
+export type SingleFileOptions = {
+    /** each image is compressed to under this many bytes */
+    imageMaxSize?: number,
+    /** also download and embed http(s) resources */
+    fetchRemote?: boolean
+};
+
+export type FeedOptions = {
+    format?: 'atom' | 'rss',
+    /** post URLs are this plus the path, with the extension replaced by .html */
+    baseUrl: string,
+    title: string,
+    description?: string,
+    author?: string,
+    limit?: number,
+    fullContent?: boolean
+};
+
+export type SiteOptions = {
+    title?: string,
+    /** enables the Atom feed */
+    baseUrl?: string,
+    imageMaxSize?: number,
+    codeTheme?: string
+};
+
+export type SiteSummary = {
+    pages: number,
+    assets: number,
+    tags: number
+};
+
+export type CachedImage = {
+    /** load with `convertFileSrc` */
+    path: string,
+    mimeType: string | null,
+    fromCache: boolean
+};
+
+export type NetworkConfig = {
+    /** `http://`, `https://`, `socks5://` or `socks5h://` URL */
+    proxy: string | null,
+    /** comma-separated hosts that bypass the proxy */
+    noProxy: string | null,
+    /** paths to PEM files */
+    caCertificates: string[]
+};
+
+export type WebDavConfig = {
+    /** the collection mirroring the workspace root */
+    url: string,
+    username?: string,
+    password?: string,
+    /** encrypts the files on the server if set */
+    encryption?: {
+        /** keychain entry with the passphrase; `sync-passphrase` by default */
+        secretName?: string
+    }
+};
+
+export type RotationSummary = {
+    rewrapped: number,
+    /** files uploaded before encryption was turned on */
+    encrypted: number,
+    errors: string[]
+};
+
+export type SyncSummary = {
+    uploaded: number,
+    downloaded: number,
+    deletedLocal: number,
+    deletedRemote: number,
+    /** workspace-relative paths of the conflict copies created */
+    conflicts: string[],
+    /** failed files, retried on the next sync */
+    errors: string[]
+};
+
+export type S3Config = {
+    /** e.g. `https://s3.eu-west-1.amazonaws.com` */
+    endpoint: string,
+    region?: string,
+    bucket: string,
+    accessKeyId: string,
+    /** keychain entry with the secret key; `s3:<accessKeyId>` by default */
+    secretName?: string,
+    /** `endpoint/bucket/key` instead of `bucket.endpoint/key` */
+    pathStyle?: boolean,
+    prefix?: string,
+    /** e.g. `public-read` */
+    acl?: string,
+    /** base of the returned links, e.g. a CDN domain */
+    publicUrl?: string
+};
+
+export type UploadTarget = {
+    kind: 'smms',
+    /** keychain entry with the API token; `smms` by default */
+    secretName?: string
+} | {
+    kind: 'imgur',
+    clientId: string
+} | {
+    kind: 'custom',
+    url: string,
+    /** form field holding the file; `file` by default */
+    field?: string,
+    form?: Record<string, string>,
+    /** `{secret}` in values is replaced by the keychain entry `secretName` */
+    headers?: Record<string, string>,
+    secretName?: string,
+    /** where the URL is in the JSON response, e.g. `$.data.links[0].url` */
+    urlPath: string
+} | ({kind: 's3'} & S3Config);
+
+export type QueuedOperation = {
+    kind: 'uploadImage',
+    path: string,
+    target: UploadTarget,
+    maxSize: number | null
+} | {
+    kind: 'publishGist',
+    path: string,
+    public: boolean,
+    /** keychain entry with the GitHub token; `github` by default */
+    secretName: string | null,
+    fileName: string | null
+};
+
+export type QueueEntry = {
+    id: number,
+    operation: QueuedOperation,
+    status: 'pending' | 'running' | 'done' | 'failed',
+    attempts: number,
+    /** seconds since the epoch */
+    createdAt: number,
+    nextAttemptAt: number,
+    lastError: string | null,
+    /** URL of the uploaded or published result */
+    result: string | null
+};
+
+export type LinkPreview = {
+    url: string,
+    title: string | null,
+    description: string | null,
+    siteName: string | null,
+    imageUrl: string | null,
+    /** the compressed image saved in the assets folder */
+    imagePath: string | null
+};
+
+export type PublishedGist = {
+    id: string,
+    url: string,
+    /** the document with the gist ID in its frontmatter */
+    document: string
+};
+
+export type WebhookEvent = 'save' | 'export' | 'publish';
+
+export type Webhook = {
+    url: string,
+    /** all events if empty */
+    events: WebhookEvent[],
+    /** keychain entry with the key to sign payloads with */
+    secretName?: string
+};
+
+export type PreviewOptions = {
+    /** any free port if not given */
+    port?: number,
+    /** listen on every interface, for other devices */
+    lan?: boolean,
+    /** `light`, `dark` or the path to a stylesheet */
+    theme?: string,
+    site?: SiteOptions
+};
+
+export type PreviewServer = {
+    url: string,
+    lanUrl: string | null
+};
+
+export type CheckOptions = {
+    /** the public LanguageTool server if not given */
+    server?: string,
+    /** e.g. `en-US`; `auto` by default */
+    language?: string,
+    motherTongue?: string,
+    /** premium accounts; the API key is read from the keychain */
+    username?: string,
+    secretName?: string,
+    disabledRules?: string[],
+    picky?: boolean
+};
+
+export type GrammarIssue = {
+    /** JavaScript string index into the checked text */
+    offset: number,
+    length: number,
+    message: string,
+    shortMessage: string | null,
+    replacements: string[],
+    ruleId: string,
+    issueType: string | null,
+    category: string | null
+};
+
+export type TranslationProvider =
+    | { kind: 'deepL', secretName?: string, formality?: string }
+    | { kind: 'libreTranslate', url: string, secretName?: string };
+
+export type AiProvider = {
+    /** e.g. `https://api.openai.com/v1` or `http://localhost:11434/v1` */
+    baseUrl: string,
+    model: string,
+    /** keychain entry with the API key */
+    secretName?: string
+};
+
+export type CompletionOptions = {
+    system?: string,
+    temperature?: number,
+    maxTokens?: number
+};
+
+/** Dictation uses the whisper.cpp model in the settings. */
+export type DictationOptions = {
+    /** detected if not given */
+    language?: string,
+    segmentSeconds?: number
+};
+
+export type DocumentVersion = {
+    hash: string,
+    /** modification time in ms */
+    modified: number
+};
+
+export type SaveResult =
+    | { kind: 'saved', version: DocumentVersion }
+    /** changed on disk meanwhile; the content went to `copy` instead */
+    | { kind: 'conflict', copy: string, baseHash: string, current: DocumentVersion };
+
+export type MergeResult = {
+    /** with conflict markers around overlapping changes */
+    text: string,
+    conflicts: number,
+    /** set if the merge was saved */
+    version: DocumentVersion | null
+};
+
+export type GitChange =
+    'added' | 'modified' | 'deleted' | 'renamed' | 'typeChanged' | 'untracked' | 'conflicted';
+
+export type GitFileStatus = {
+    /** relative to `root` */
+    path: string,
+    staged: GitChange | null,
+    unstaged: GitChange | null
+};
+
+export type GitStatus = {
+    /** the working tree of the repository */
+    root: string,
+    /** `null` on a detached HEAD */
+    branch: string | null,
+    files: GitFileStatus[]
+};
+
+export type GitLogEntry = {
+    id: string,
+    shortId: string,
+    summary: string,
+    message: string,
+    author: string,
+    email: string,
+    /** ms since the epoch */
+    time: number,
+    /** absolute; differs from the current path before a rename */
+    path: string,
+    additions: number,
+    deletions: number
+};
+
+export type AutoCommitOptions = {
+    enabled: boolean,
+    /** `{files}` and `{date}` are filled in; `Update {files}` by default */
+    message?: string,
+    /** saves this soon after an automatic commit are added to it */
+    coalesceSeconds: number
+};
+
+export type GitRemoteOptions = {
+    /** `origin` by default */
+    remote?: string,
+    /** the current branch by default */
+    branch?: string,
+    username?: string,
+    /** keychain entry with the HTTPS token, or the SSH key's passphrase */
+    secretName?: string,
+    /** uses the SSH agent if not given */
+    sshKeyPath?: string,
+    /** pulls fail when the branches have diverged unless set */
+    allowMerge?: boolean
+};
+
+export type PullSummary = {
+    outcome: 'upToDate' | 'fastForward' | 'merged' | 'conflicts',
+    head: string | null,
+    /** relative to the repository root */
+    conflicts: string[]
+};
+
+export type GitBlameCommit = {
+    id: string,
+    shortId: string,
+    summary: string,
+    author: string,
+    email: string,
+    /** ms since the epoch */
+    time: number
+};
+
+export type GitBlame = {
+    /** per line, an index into `commits`; `null` for uncommitted changes */
+    lines: (number | null)[],
+    commits: GitBlameCommit[]
+};
+
+export type GitBranch = {
+    name: string,
+    current: boolean,
+    /** e.g. `origin/main` */
+    upstream: string | null,
+    head: string,
+    summary: string,
+    /** ms since the epoch, of the last commit */
+    time: number
+};
+
+/** `stash` keeps uncommitted changes for the current branch until it is checked out again */
+export type OnDirty = 'refuse' | 'stash';
+
+export type CheckoutResult =
+    | {kind: 'checkedOut', stashed: boolean, restored: boolean}
+    | {kind: 'dirty', files: string[]};
+
+export type ConflictRegion = {
+    /** the whole region, markers included, in UTF-16 code units */
+    offset: number,
+    length: number,
+    /** of the `<<<<<<<` marker, from 0 */
+    line: number,
+    oursLabel: string,
+    ours: string,
+    /** only with diff3-style markers */
+    base: string | null,
+    theirsLabel: string,
+    theirs: string
+};
+
+export type ConflictResolution = {
+    /** of the region, in the order `findConflictMarkers` returns them */
+    index: number,
+    accept: 'ours' | 'theirs' | 'both'
+};
+
+export type ConflictedDocument = {
+    path: string,
+    conflicts: number
+};
+
+export type DictionaryInfo = {
+    /** e.g. `en_US` */
+    language: string,
+    bundled: boolean
+};
+
+export type Misspelling = {
+    /** in UTF-16 code units */
+    offset: number,
+    length: number,
+    word: string,
+    suggestions: string[]
+};
+
+export type ProseRule = 'passiveVoice' | 'weaselWord' | 'longSentence' | 'duplicateWord';
+
+export type ProseLintOptions = {
+    disabledRules?: ProseRule[],
+    /** 35 by default */
+    maxSentenceWords?: number,
+    extraWeaselWords?: string[]
+};
+
+export type ProseDiagnostic = {
+    /** in UTF-16 code units */
+    offset: number,
+    length: number,
+    rule: ProseRule,
+    message: string,
+    replacements: string[]
+};
+
+export type CompletionKind = 'note' | 'tag' | 'heading';
+
+export type Completion = {
+    label: string,
+    /** the workspace-relative path without extension for notes, the tag without `#`, or the heading text */
+    insert: string,
+    detail: string | null,
+    score: number
+};
+
+export type Snippet = {
+    /** unique */
+    trigger: string,
+    /** TextMate syntax: `$1`, `${1:default}`, `$0`, and `${date}`, `${time}`, `${clipboard}`, `${selection}`, `${file}` */
+    body: string,
+    description?: string | null
+};
+
+export type SnippetExpansion = {
+    text: string,
+    /** in visiting order; offsets in UTF-16 code units, and equal indices mirror each other */
+    placeholders: {index: number, offset: number, length: number}[],
+    cursor: number
+};
+
+export type EmojiSkinTone = 'default' | 'light' | 'mediumLight' | 'medium' | 'mediumDark' | 'dark';
+
+export type EmojiMatch = {
+    /** In the requested skin tone if it has them. */
+    emoji: string,
+    shortcode: string,
+    name: string,
+    hasSkinTones: boolean
+};
+
+export type Smartening = 'quotes' | 'dashes' | 'ellipses' | 'noBreakSpaces' | 'normalize';
+
+export type SmartenOptions = {
+    /** A language tag like `de-CH`; English by default. */
+    language?: string,
+    disabled?: Smartening[]
+};
+
+export type SlugOptions = {
+    /** Spells other scripts in ASCII, so "北京" becomes `bei-jing`. */
+    transliterate?: boolean,
+    /** In characters; the slug is cut at a dash if it's longer. */
+    maxLength?: number
+};
+
+export type TextCase = 'title' | 'sentence';
+
+export type CitationStyle = {
+    /** What to give as `style`, or as `csl` in the frontmatter. */
+    name: string,
+    title: string
+};
+
+export type FootnoteReport = {
+    text: string,
+    /** Labels referenced without a definition. */
+    missing: string[],
+    /** Labels defined but never referenced; kept at the end. */
+    unreferenced: string[],
+    /** Labels defined more than once; the first definition is used. */
+    duplicates: string[]
+};
+
+export type NumberingOptions = {
+    /** In UTF-16 code units; the whole document by default. Numbers carry on from the headings before. */
+    from?: number,
+    to?: number,
+    /** The level numbered "1.", "2."...; by default the highest, not counting a lone title. */
+    topLevel?: number,
+    maxLevel?: number
+};
+
+export type LintDiagnostic = {
+    /** In UTF-16 code units, like string indices. */
+    offset: number,
+    length: number,
+    /** Like `MD009`. */
+    rule: string,
+    /** Like `no-trailing-spaces`. */
+    name: string,
+    message: string,
+    /** Replaces the range to fix it. */
+    fix: string | null
+};
+
+export type WorkspaceTask = {
+    path: string,
+    /** Of the document. */
+    title: string,
+    /** Among the tasks of the document, for `toggleTask`. */
+    index: number,
+    /** 1-based. */
+    line: number,
+    kind: 'checkbox' | 'todo',
+    done: boolean,
+    text: string,
+    /** The heading the task is under. */
+    context: string | null,
+    /** Like `2025-03-01`. */
+    due: string | null
+};
+
+export type DailyNote = {
+    path: string,
+    /** Like `2025-03-01`. */
+    date: string,
+    /** Whether opening it created it. */
+    created: boolean
+};
+
+export type TemplateContext = {
+    /** The document the template is for. */
+    path?: string,
+    workspace?: string,
+    /** By default the file name without extension. */
+    title?: string,
+    selection?: string,
+    /** By question, for `{{prompt:Question|default}}`. */
+    answers?: Record<string, string>
+};
+
+export type RenderedTemplate = {
+    text: string,
+    /** Not answered in the context; ask them and render again. */
+    prompts: {question: string, default: string | null}[]
+};
+
+export type Readability = {
+    words: number,
+    sentences: number,
+    syllables: number,
+    /** Flesch reading ease, 0 to 100, higher is easier; null without sentences. */
+    readingEase: number | null,
+    /** Flesch-Kincaid grade. */
+    gradeLevel: number | null,
+    averageSentenceWords: number,
+    longestSentenceWords: number,
+    /** Sentences by length in words; the last bucket has no `max`. */
+    sentenceLengths: {min: number, max: number | null, count: number}[],
+    /** In UTF-16 code units. */
+    hardSentences: {offset: number, length: number, difficulty: 'hard' | 'veryHard', grade: number}[]
+};
+
+export type FrequencyOptions = {
+    /** Picks the stopwords, like `en` or `de`; English by default. */
+    language?: string,
+    extraStopwords?: string[],
+    includeStopwords?: boolean,
+    /** Of each list; 50 by default. */
+    limit?: number,
+    /** 2 by default. */
+    minCount?: number
+};
+
+export type Frequencies = {
+    /** Stopwords included. */
+    totalWords: number,
+    /** The most frequent first, lowercase. */
+    words: {term: string, count: number}[],
+    bigrams: {term: string, count: number}[]
+};
+
+export type RegexOptions = {
+    caseInsensitive?: boolean,
+    /** `^` and `$` match at line ends. */
+    multiLine?: boolean,
+    /** `.` matches line ends. */
+    dotAll?: boolean,
+    /** 2000 by default. */
+    timeoutMs?: number,
+    /** Matches listed at most; 1000 by default. */
+    previewLimit?: number
+};
+
+export type RegexTransform = {
+    text: string,
+    count: number,
+    /** In UTF-16 code units of the original text. */
+    matches: {offset: number, length: number, replacement: string}[]
+};
+
+export type LineOperation = 'sort' | 'naturalSort' | 'unique' | 'reverse' | 'shuffle';
+
+export type PasteClipboard = {
+    /** `text/plain`. */
+    text?: string,
+    html?: string,
+    rtf?: string,
+    /** Paths of copied files. */
+    files?: string[],
+    /** Of a copied image, like `image/png`. */
+    imageType?: string,
+    /** Where it's pasted, for relative paths. */
+    document?: string,
+    /** Fetches the title of a pasted URL. */
+    fetchTitle?: boolean
+};
+
+export type PasteSuggestion = {
+    kind: 'image' | 'files' | 'link' | 'table' | 'code' | 'text',
+    /** Null for an image, which has to be saved first, and for plain text. */
+    markdown: string | null,
+    language: string | null
+};
+
+/** Interactive jobs run before background ones. */
+export type JobPriority = 'interactive' | 'background';
+
+export type JobInfo = {
+    id: number,
+    kind: 'compression' | 'export' | 'sync' | 'indexing' | 'maintenance' | 'script' | 'upload' | 'fetch',
+    priority: JobPriority,
+    /** what it works on, like a path */
+    label: string,
+    done: number,
+    /** 0 until it's known */
+    total: number,
+    message: string,
+    startedAt: number,
+    cancelled: boolean
+};
+
+export type JobEvent = {
+    event: 'started',
+    data: {job: JobInfo}
+} | {
+    event: 'progress',
+    data: {id: number, done: number, total: number, message: string}
+} | {
+    event: 'finished',
+    /** `error` is null if it succeeded */
+    data: {id: number, error: BackendErrorInfo | null}
+};
+
+export type OperationMetrics = {
+    name: string,
+    count: number,
+    failures: number,
+    /** 0 to 1 */
+    failureRate: number,
+    averageMs: number,
+    maxMs: number,
+    /** of the last 200 runs */
+    medianMs: number,
+    p95Ms: number,
+    /** time by input size; `maxBytes` is null for the last bucket */
+    bySize: {maxBytes: number | null, count: number, averageMs: number}[]
+};
+
+export type Metrics = {
+    enabled: boolean,
+    /** seconds since the epoch */
+    since: number | null,
+    operations: OperationMetrics[]
+};
+
+export type CrashInfo = {
+    /** path of the report, a JSON file */
+    report: string,
+    /** path of the log lines before the crash */
+    breadcrumbs: string,
+    /** path of the minidump, for a crash in native code */
+    minidump: string | null,
+    message: string,
+    location: string | null,
+    /** seconds since the epoch */
+    time: number
+};
+
+export type LogLevel = 'error' | 'warn' | 'info' | 'debug' | 'trace';
+
+export type LogFilter = {
+    /** the least severe level sent; `info` by default */
+    level?: LogLevel,
+    /** prefixes of the targets sent, like `kfgui::sync`; all if empty */
+    targets?: string[],
+    /** only records with this in the message, ignoring case */
+    contains?: string
+};
+
+export type LogRecord = {
+    level: LogLevel,
+    target: string,
+    message: string,
+    /** milliseconds since the epoch */
+    time: number
+};
+
+export type DatabaseInfo = {
+    path: string,
+    /** of the schema */
+    version: number,
+    /** of the file, in bytes */
+    size: number,
+    tables: {name: string, rows: number}[]
+};
+
+export type MaintenanceTask = 'reindex' | 'pruneSnapshots' | 'evictImageCache' | 'backup';
+
+export type ScheduleOptions = {
+    /** minutes between runs, 0 to turn a task off; the task's default if missing */
+    intervals: Partial<Record<MaintenanceTask, number>>,
+    /** also run tasks on battery, unless PowerOptions.deferOnBattery holds them back */
+    onBattery: boolean
+};
+
+export type PowerOptions = {
+    /** hold background work like bulk recompression and reindexing back on battery */
+    deferOnBattery: boolean,
+    /** hold it back until there was no input in the app for idleSeconds */
+    deferWhileActive: boolean,
+    /** scheduled tasks wait for this too */
+    idleSeconds: number
+};
+
+export type PowerStatus = {
+    onBattery: boolean,
+    /** since the last input */
+    idleSeconds: number,
+    /** whether background work is held back */
+    throttled: boolean
+};
+
+export type ResourceUsage = {
+    /** null where it can't be told */
+    memory: {resident: number, reserved: number} | null,
+    /** bytes on disk */
+    disk: {imageCache: number, documentBases: number, database: number, logs: number},
+    caches: {indexedDocuments: number, dictionaries: string[]},
+    watchers: {previewServer: boolean, previewPages: number, logTails: number, jobListeners: number},
+    jobs: JobInfo[]
+};
+
+/** the `maxSize` of `compressImage` to try */
+export type CompressionProfile = {
+    name: string,
+    /** in bytes */
+    maxSize: number
+};
+
+export type ProfileResult = {
+    profile: string,
+    size: number,
+    /** in dB against the sample scaled like the result; null if lossless */
+    psnr: number | null,
+    millis: number,
+    width: number,
+    height: number,
+    error: string | null
+};
+
+export type BenchmarkReport = {
+    samples: {
+        path: string,
+        size: number,
+        width: number,
+        height: number,
+        /** why the sample couldn't be read */
+        error: string | null,
+        results: ProfileResult[]
+    }[],
+    profiles: {
+        profile: string,
+        originalSize: number,
+        size: number,
+        meanPsnr: number | null,
+        millis: number,
+        failures: number
+    }[]
+};
+
+export type CheckStatus = 'ok' | 'warning' | 'failed';
+
+export type DiagnosticsReport = {
+    /** the worst of the checks */
+    status: CheckStatus,
+    checks: {
+        category: 'storage' | 'tools' | 'network',
+        name: string,
+        status: CheckStatus,
+        /** what was found, or what went wrong */
+        detail: string,
+        millis: number
+    }[]
+};
+
+export type Flag = 'sync' | 'ai' | 'collaboration';
+
+export type FlagState = {
+    flag: Flag,
+    enabled: boolean,
+    default: boolean,
+    description: string
+};
+
+/**
+ * A WebAssembly module exporting `memory`, `alloc` and its hook's function;
+ * see the backend's plugins module for the interface.
+ */
+export type Plugin = {
+    name: string,
+    path: string,
+    /** transform gets text, paste the pasted text, export the document's Markdown */
+    hook: 'transform' | 'paste' | 'export'
+};
+
+/** A Rhai script; see the backend's scripting module for what it can call. */
+export type Script = {
+    name: string,
+    path: string,
+    /** the workspace it works on, which its paths are relative to */
+    workspace: string,
+    capabilities?: ('read' | 'write' | 'export' | 'compress')[],
+    /** run after every save of a document in the workspace */
+    onSave?: boolean
+};
+
+export type ScriptRun = {
+    /** what the script printed */
+    output: string[],
+    /** the value of its last expression */
+    result: string,
+    millis: number
+};
+
+/** What to do for an `emmm://` link from another app. */
+export type DeepLink =
+    | { action: 'open', path: string, line: number | null }
+    /** in `workspace`, with `createNote` once the user agreed, or in the
+     *  one that's open */
+    | { action: 'newNote', title: string, workspace: string | null };
+
+export type UpdateChannel = 'stable' | 'beta';
+
+export type UpdateInfo = {
+    version: string,
+    currentVersion: string,
+    notes: string | null,
+    /** RFC 3339 */
+    date: string | null
+};
+
+export type Messages = {
+    locale: string,
+    messages: Record<string, string>
+};
+
+export type WindowInfo = {
+    label: string,
+    /** the document it has open, which only it saves */
+    document: string | null
+};
+
+export type CaptureOptions = {
+    /** like 'CmdOrCtrl+Shift+Space'; none if empty */
+    shortcut: string,
+    /** the note captures are appended to */
+    inbox: string,
+    imageMaxSize: number
+};
+
+export type Captured = {
+    path: string,
+    created: boolean,
+    /** relative to the inbox's folder */
+    image: string | null
+};
+
+export type ClipKind = 'text' | 'url' | 'image';
+
+export type Clip = {
+    id: number,
+    kind: ClipKind,
+    /** seconds since the epoch */
+    captured: number,
+    /** null for images */
+    text: string | null,
+    /** of the compressed image */
+    path: string | null
+};
+
+export type VideoThumbnail = {
+    /** for convertFileSrc */
+    path: string,
+    width: number,
+    height: number,
+    fromCache: boolean
+};
+
+/** bytes for the whole file, or bits per second of the video */
+export type VideoTarget = {maxSize: number} | {bitrate: number};
+
+export type CompressedVideo = {
+    path: string,
+    size: number,
+    originalSize: number
+};
+
+export type GifTarget = 'mp4' | 'webm';
+
+export type ConvertedGif = {
+    path: string,
+    size: number,
+    originalSize: number,
+    /** embeds of the GIF in the document that now embed the video */
+    rewritten: number,
+    /** of the document, if it was changed */
+    saved: SaveResult | null,
+    /** whether the GIF was deleted */
+    removed: boolean
+};
+
+export type OcrWord = {
+    text: string,
+    /** 0 to 100 */
+    confidence: number,
+    x: number,
+    y: number,
+    width: number,
+    height: number
+};
+
+export type OcrResult = {
+    text: string,
+    /** 0 to 100 */
+    confidence: number,
+    words?: OcrWord[]
+};
+
+export type ScannedCode = {
+    text: string,
+    /** like `qrcode`, `ean 13` or `code 128` */
+    format: string,
+    isUrl: boolean,
+    /** in pixels of the image */
+    points: [number, number][]
+};
+
+/** the crop, in pixels of the original */
+export type SmartCrop = {
+    path: string,
+    x: number,
+    y: number,
+    width: number,
+    height: number
+};
+
+export type RemovedBackground = {
+    path: string,
+    width: number,
+    height: number
+};
+
+export type MemoFormat = 'opus' | 'm4a';
+
+/** levels are from 0 to 1 */
+export type MemoEvent = {event: 'level', data: {rms: number, peak: number, elapsedMs: number}};
+
+export type VoiceMemo = {
+    path: string,
+    durationMs: number,
+    size: number
+};
+
+export type WaveformOptions = {
+    /** in pixels; 600 x 64 by default */
+    width?: number,
+    height?: number,
+    format?: 'png' | 'svg',
+    /** like '#4a90e2' */
+    color?: string,
+    barWidth?: number,
+    gap?: number
+};
+
+export type ShutdownEvent = {event: 'flush'};
+
 export const RustAPI = {
-    async compressImage(path: string, maxSize: number) {
-        const buf = await invoke<ArrayBuffer>('compress_image', {path, maxSize});
+    /** Bulk recompression should be `background`, so images on screen go first. */
+    async compressImage(path: string, maxSize: number, priority?: JobPriority) {
+        const buf = await invoke<ArrayBuffer>('compress_image', {path, maxSize, priority});
         return new Blob([buf], {type: 'image/jpeg'});
+    },
+
+    async renderMath(tex: string, display: boolean) {
+        return await invoke<string>('render_math', {tex, display});
+    },
+
+    async highlightCode(source: string, language: string, theme: string) {
+        return await invoke<string>('highlight_code', {source, language, theme});
+    },
+
+    async listHighlightOptions() {
+        return await invoke<HighlightOptions>('list_highlight_options');
+    },
+
+    /** With the formatter for `language` in the settings, or the
+     *  built-in one. */
+    async formatCode(source: string, language: string) {
+        return await invoke<string>('format_code', {source, language});
+    },
+
+    /** Text output is passed to `onChunk` as it arrives. Binary formats 
+     *  (docx, epub...) require `outputPath` and produce no chunks. */
+    async pandocConvert(
+        input: string, from: string, to: string, args: string[],
+        onChunk: (text: string) => void, 
+        outputPath?: string, timeoutMs?: number
+    ) {
+        const channel = createChannel({
+            chunk: (data) => onChunk(data.text),
+            done: () => {}
+        });
+        await invoke('pandoc_convert', 
+            {input, from, to, args, outputPath, timeoutMs, channel});
+    },
+
+    /**
+     * The body of a markdown document as HTML, with its citations and bibliography if its
+     * frontmatter names a `bibliography` (and `csl` style); to print or export as a single file.
+     */
+    async renderMarkdown(source: string, path?: string) {
+        return await invoke<string>('render_markdown', {source, path});
+    },
+
+    /** writes a PDF to `outputPath`, without the print dialog */
+    async printToPDF(html: string, options: PrintOptions, outputPath: string) {
+        await invoke('print_to_pdf', {html, options, outputPath});
+    },
+
+    async exportSlides(markdown: string, baseDir: string | undefined, options: SlideOptions) {
+        return await invoke<string>('export_slides', {markdown, baseDir, options});
+    },
+
+    async exportSingleHTML(html: string, baseDir: string | undefined, options: SingleFileOptions) {
+        return await invoke<string>('export_single_html', {html, baseDir, options});
+    },
+
+    async generateFeed(folder: string, options: FeedOptions) {
+        return await invoke<string>('generate_feed', {folder, options});
+    },
+
+    /** `theme` is 'light', 'dark' or the path to a stylesheet */
+    async exportSite(workspace: string, outDir: string, theme: string, options: SiteOptions) {
+        return await invoke<SiteSummary>('export_site', {workspace, outDir, theme, options});
+    },
+
+    /** `path` is where the document lives, for resolving relative references */
+    async exportWithTemplate(templatePath: string, source: string, path?: string) {
+        return await invoke<string>('export_with_template', {templatePath, source, path});
+    },
+
+    async fetchRemoteImage(url: string) {
+        return await invoke<CachedImage>('fetch_remote_image', {url});
+    },
+
+    async configureNetwork(config: NetworkConfig) {
+        await invoke('configure_network', {config});
+    },
+
+    /**
+     * POSTs `body` (JSON) to an endpoint of the Weixin API, like `draft/batchget`, through the
+     * configured proxy; resolves with what it answered, JSON or a material's data.
+     */
+    async weixinRequest(endpoint: string, accessToken: string | undefined, body: string) {
+        return await invoke<ArrayBuffer>('weixin_request', {endpoint, accessToken, body});
+    },
+
+    /** Uploads `data` to an endpoint of the Weixin API as the form field `media`; resolves with its JSON answer. */
+    async weixinUpload(endpoint: string, accessToken: string, name: string, data: Uint8Array) {
+        const headers = {
+            'endpoint': endpoint,
+            'access-token': accessToken,
+            'name': encodeURIComponent(name)
+        };
+        return await invoke<ArrayBuffer>('weixin_upload', data, {headers});
+    },
+
+    async syncNow(
+        workspace: string, config: WebDavConfig,
+        onProgress: (done: number, total: number, path: string) => void
+    ) {
+        const channel = createChannel({
+            progress: (data) => onProgress(data.done, data.total, data.message),
+            done: () => {}
+        });
+        return await invoke<SyncSummary>('sync_now', {workspace, config, channel});
+    },
+
+    /**
+     * Re-encrypts the file keys on the server with a new workspace key, locked with `newPassphrase`,
+     * which replaces the one in the keychain; e.g. after a device was lost.
+     */
+    async rotateSyncKey(
+        workspace: string, config: WebDavConfig, newPassphrase: string,
+        onProgress: (done: number, total: number, path: string) => void
+    ) {
+        const channel = createChannel({
+            progress: (data) => onProgress(data.done, data.total, data.message),
+            done: () => {}
+        });
+        return await invoke<RotationSummary>('rotate_sync_key', {workspace, config, newPassphrase, channel});
+    },
+
+    /** Also replaces the passphrase stored in the keychain. */
+    async changeSyncPassphrase(config: WebDavConfig, passphrase: string) {
+        await invoke('change_sync_passphrase', {config, passphrase});
+    },
+
+    /** Returns the URL of the uploaded image. */
+    async uploadToS3(path: string, maxSize: number, config: S3Config) {
+        return await invoke<string>('upload_to_s3', {path, maxSize, config});
+    },
+
+    async publishGist(doc: string, isPublic: boolean, token: string, fileName?: string) {
+        return await invoke<PublishedGist>('publish_gist',
+            {doc, public: isPublic, token, fileName});
+    },
+
+    /** Returns the URL of the uploaded image. */
+    async uploadImage(path: string, target: UploadTarget, maxSize?: number) {
+        return await invoke<string>('upload_image', {path, target, maxSize});
+    },
+
+    /** Returns the queue entry ID. */
+    async enqueueUpload(path: string, target: UploadTarget, maxSize?: number) {
+        return await invoke<number>('enqueue_upload', {path, target, maxSize});
+    },
+
+    /** Returns the queue entry ID. */
+    async enqueueGist(path: string, isPublic: boolean, secretName?: string, fileName?: string) {
+        return await invoke<number>('enqueue_gist', 
+            {path, public: isPublic, secretName, fileName});
+    },
+
+    async listQueue() {
+        return await invoke<QueueEntry[]>('list_queue');
+    },
+
+    /** Retries one entry, or all unfinished ones, right away. */
+    async retryQueue(id?: number) {
+        await invoke('retry_queue', {id});
+    },
+
+    async removeFromQueue(id: number) {
+        await invoke('remove_from_queue', {id});
+    },
+
+    async fetchLinkPreview(url: string, assetsDir: string, imageMaxSize?: number) {
+        return await invoke<LinkPreview>('fetch_link_preview', {url, assetsDir, imageMaxSize});
+    },
+
+    /** `null` if the URL isn't a web page or it has no title. */
+    async fetchTitle(url: string) {
+        return await invoke<string | null>('fetch_title', {url});
+    },
+
+    async configureWebhooks(webhooks: Webhook[]) {
+        await invoke('configure_webhooks', {webhooks});
+    },
+
+    /** For events only the frontend knows about, like autosaves. */
+    async emitWebhookEvent(event: WebhookEvent, path: string) {
+        await invoke('emit_webhook_event', {event, path});
+    },
+
+    /** Replaces the preview server that's already running, if any. */
+    async startPreviewServer(workspace: string, options: PreviewOptions = {}) {
+        return await invoke<PreviewServer>('start_preview_server', {workspace, options});
+    },
+
+    async stopPreviewServer() {
+        await invoke('stop_preview_server');
+    },
+
+    /** Reloads open preview pages right away, e.g. after an autosave. */
+    async notifyPreviewChanged(path?: string) {
+        await invoke('notify_preview_changed', {path});
+    },
+
+    async checkGrammar(text: string, options: CheckOptions = {}) {
+        return await invoke<GrammarIssue[]>('check_grammar', {text, options});
+    },
+
+    /** `onChunk` receives the translation a few paragraphs at a time */
+    async translate(
+        text: string, targetLang: string, provider: TranslationProvider,
+        onChunk: (text: string) => void, sourceLang?: string
+    ) {
+        const channel = createChannel({
+            chunk: (data) => onChunk(data.text),
+            done: () => {}
+        });
+        await invoke('translate', {text, targetLang, provider, sourceLang, channel});
+    },
+
+    /** `onChunk` receives the completion as it is generated */
+    async complete(
+        prompt: string, provider: AiProvider, options: CompletionOptions,
+        onChunk: (text: string) => void
+    ) {
+        const channel = createChannel({
+            chunk: (data) => onChunk(data.text),
+            done: () => {}
+        });
+        await invoke('complete', {prompt, provider, options, channel});
+    },
+
+    /** Resolves after `stopDictation`, once the rest has been transcribed. */
+    async startDictation(options: DictationOptions, onText: (text: string) => void) {
+        const channel = createChannel({
+            chunk: (data) => onText(data.text),
+            done: () => {}
+        });
+        await invoke('start_dictation', {options, channel});
+    },
+
+    async stopDictation() {
+        await invoke('stop_dictation');
+    },
+
+    /**
+     * Reads `text` aloud; resolves when it's finished or stopped. `onWord`
+     * gets the position of each word in `text` as it's about to be spoken.
+     */
+    async speak(
+        text: string, onWord: (offset: number, length: number) => void,
+        voice?: string, rate?: number
+    ) {
+        const channel = createChannel({
+            word: (data) => onWord(data.offset, data.length),
+            done: () => {}
+        });
+        await invoke('speak', {text, voice, rate, channel});
+    },
+
+    async pauseSpeech() {
+        await invoke('pause_speech');
+    },
+
+    async resumeSpeech() {
+        await invoke('resume_speech');
+    },
+
+    async stopSpeech() {
+        await invoke('stop_speech');
+    },
+
+    /** Call when opening a document, and pass the result to `saveDocument`; the window lets go of the
+     *  document it had open. */
+    async documentVersion(path: string) {
+        return await invoke<DocumentVersion>('document_version', {path});
+    },
+
+    /** Doesn't overwrite changes made on disk since `expected`. */
+    async saveDocument(path: string, content: string, expected?: DocumentVersion) {
+        return await invoke<SaveResult>('save_document', {path, content, expected});
+    },
+
+    /** With `apply`, a merge without conflicts is saved and the copy deleted. */
+    async resolveConflict(path: string, copy: string, baseHash: string, apply = false) {
+        return await invoke<MergeResult>('resolve_conflict', {path, copy, baseHash, apply});
+    },
+
+    async gitStatus(workspace: string) {
+        return await invoke<GitStatus>('git_status', {workspace});
+    },
+
+    /**
+     * Stages `paths` (relative to the repository root, or absolute) and
+     * commits everything staged. Returns the commit ID.
+     */
+    async gitCommit(workspace: string, paths: string[], message: string) {
+        return await invoke<string>('git_commit', {workspace, paths, message});
+    },
+
+    /** Newest first, following renames; `path` is absolute, like in the other history calls. */
+    async gitLog(path: string, limit?: number) {
+        return await invoke<GitLogEntry[]>('git_log', {path, limit});
+    },
+
+    /** `rev` is a commit ID or e.g. `HEAD~2`. */
+    async gitShow(path: string, rev: string) {
+        return await invoke<string>('git_show', {path, rev});
+    },
+
+    /** What `rev` changed in the file, as a unified diff. */
+    async gitDiff(path: string, rev: string) {
+        return await invoke<string>('git_diff', {path, rev});
+    },
+
+    /** Commits on every `saveDocument` in a git repository if enabled. */
+    async configureAutoCommit(options: AutoCommitOptions) {
+        await invoke('configure_auto_commit', {options});
+    },
+
+    async gitPull(
+        workspace: string, options: GitRemoteOptions,
+        onProgress: (done: number, total: number, stage: string) => void
+    ) {
+        const channel = createChannel({
+            progress: (data) => onProgress(data.done, data.total, data.message),
+            done: () => {}
+        });
+        return await invoke<PullSummary>('git_pull', {workspace, options, channel});
+    },
+
+    /** Never forces; fails if the remote has commits that aren't pulled yet. */
+    async gitPush(
+        workspace: string, options: GitRemoteOptions,
+        onProgress: (done: number, total: number, stage: string) => void
+    ) {
+        const channel = createChannel({
+            progress: (data) => onProgress(data.done, data.total, data.message),
+            done: () => {}
+        });
+        await invoke('git_push', {workspace, options, channel});
+    },
+
+    /** Pass the editor's `content` to account for unsaved changes. */
+    async gitBlame(path: string, content?: string) {
+        return await invoke<GitBlame>('git_blame', {path, content});
+    },
+
+    async gitBranches(workspace: string) {
+        return await invoke<GitBranch[]>('git_branches', {workspace});
+    },
+
+    /** At `start`, or the current commit; doesn't check it out. */
+    async gitCreateBranch(workspace: string, name: string, start?: string) {
+        return await invoke<GitBranch>('git_create_branch', {workspace, name, start});
+    },
+
+    /** Save open documents first; by default returns `dirty` rather than touch uncommitted changes. */
+    async gitCheckout(workspace: string, name: string, onDirty?: OnDirty) {
+        return await invoke<CheckoutResult>('git_checkout', {workspace, name, onDirty});
+    },
+
+    /** Whether previews, feeds and exports skip files excluded by `.gitignore`; on by default. */
+    async configureWorkspace(respectGitignore: boolean) {
+        await invoke('configure_workspace', {respectGitignore});
+    },
+
+    async findConflictMarkers(text: string) {
+        return await invoke<ConflictRegion[]>('find_conflict_markers', {text});
+    },
+
+    /** Regions not in `resolutions` are left as they are. */
+    async resolveConflictMarkers(text: string, resolutions: ConflictResolution[]) {
+        return await invoke<string>('resolve_conflict_markers', {text, resolutions});
+    },
+
+    async scanConflictMarkers(workspace: string) {
+        return await invoke<ConflictedDocument[]>('scan_conflict_markers', {workspace});
+    },
+
+    async listDictionaries() {
+        return await invoke<DictionaryInfo[]>('list_dictionaries');
+    },
+
+    /** `language` is e.g. `en_US`, `en-US` or just `en`. */
+    async spellCheck(text: string, language: string) {
+        return await invoke<Misspelling[]>('spell_check', {text, language});
+    },
+
+    /** Accepts `word` from now on, in every language. */
+    async addToDictionary(word: string) {
+        await invoke('add_to_dictionary', {word});
+    },
+
+    /** `dicPath` needs its `.aff` file next to it; returns the language. */
+    async installDictionary(dicPath: string) {
+        return await invoke<string>('install_dictionary', {dicPath});
+    },
+
+    /** Style suggestions for English prose, computed locally. */
+    async lintProse(text: string, options: ProseLintOptions = {}) {
+        return await invoke<ProseDiagnostic[]>('lint_prose', {text, options});
+    },
+
+    /** Fuzzy-matched candidates, best first; `heading` completes the headings of `path`. */
+    async autocomplete(workspace: string, prefix: string, kind: CompletionKind, path?: string, limit?: number) {
+        return await invoke<Completion[]>('autocomplete', {workspace, prefix, kind, path, limit});
+    },
+
+    async listSnippets() {
+        return await invoke<Snippet[]>('list_snippets');
+    },
+
+    /** Replaces the snippet with the same trigger, if any. */
+    async saveSnippet(snippet: Snippet) {
+        await invoke('save_snippet', {snippet});
+    },
+
+    async deleteSnippet(trigger: string) {
+        await invoke('delete_snippet', {trigger});
+    },
+
+    async expandSnippet(trigger: string, context: {selection?: string, path?: string} = {}) {
+        return await invoke<SnippetExpansion>('expand_snippet', {trigger, context});
+    },
+
+    /** Best matches first; `query` may include the colons. */
+    async searchEmoji(query: string, skinTone?: EmojiSkinTone, limit?: number) {
+        return await invoke<EmojiMatch[]>('search_emoji', {query, skinTone, limit});
+    },
+
+    /** Curly quotes, dashes, ellipses, no-break spaces and NFC for the prose of `text`. */
+    async smartenText(text: string, options: SmartenOptions = {}) {
+        return await invoke<string>('smarten_text', {text, options});
+    },
+
+    /** Without transliteration, the same as the anchors given to headings. */
+    async slugify(text: string, options: SlugOptions = {}) {
+        return await invoke<string>('slugify', {text, options});
+    },
+
+    async changeCase(text: string, textCase: TextCase) {
+        return await invoke<string>('change_case', {text, case: textCase});
+    },
+
+    /** A `.bib` file or CSL-JSON; returns how many entries it has. */
+    async loadCitationLibrary(path: string) {
+        return await invoke<number>('load_citation_library', {path});
+    },
+
+    /** `insert` is the citation key. */
+    async citeComplete(query: string, limit?: number) {
+        return await invoke<Completion[]>('cite_complete', {query, limit});
+    },
+
+    /** HTML; `style` is a name from `listCitationStyles` or a `.csl` file. */
+    async formatBibliography(keys: string[], style?: string) {
+        return await invoke<string>('format_bibliography', {keys, style});
+    },
+
+    async listCitationStyles() {
+        return await invoke<CitationStyle[]>('list_citation_styles');
+    },
+
+    /** Definitions go to the end of the document, or before the next heading with `'section'`. */
+    async renumberFootnotes(text: string, placement: 'end' | 'section' = 'end') {
+        return await invoke<FootnoteReport>('renumber_footnotes', {text, options: {placement}});
+    },
+
+    /** `doc` with the table of contents between `<!-- toc -->` and `<!-- /toc -->` updated, or inserted. */
+    async generateToc(doc: string, depth?: number, style: 'bullets' | 'numbered' = 'bullets') {
+        return await invoke<string>('generate_toc', {doc, depth, style});
+    },
+
+    async numberHeadings(text: string, options: NumberingOptions = {}) {
+        return await invoke<string>('number_headings', {text, options});
+    },
+
+    async stripHeadingNumbers(text: string, options: NumberingOptions = {}) {
+        return await invoke<string>('strip_heading_numbers', {text, options});
+    },
+
+    /** With the `.markdownlint.json` of `workspace`, if it has one. */
+    async lintMarkdown(text: string, workspace?: string) {
+        return await invoke<LintDiagnostic[]>('lint_markdown', {text, workspace});
+    },
+
+    /** `text` with every fixable diagnostic fixed, to apply as one edit. */
+    async fixMarkdown(text: string, workspace?: string) {
+        return await invoke<string>('fix_markdown', {text, workspace});
+    },
+
+    /** Tasks with due dates first, soonest first; only those not done with `open`. */
+    async listTasks(workspace: string, open = false) {
+        return await invoke<WorkspaceTask[]>('list_tasks', {workspace, open});
+    },
+
+    /** Whether the task is done now. */
+    async toggleTask(path: string, index: number) {
+        return await invoke<boolean>('toggle_task', {path, index});
+    },
+
+    /**
+     * The note for `date` (`YYYY-MM-DD`, today by default), created from the `template` document if missing.
+     * `pattern` is a format description of the `time` crate, like `journal/[year]/[year]-[month]-[day].md`;
+     * moment-style tokens like `YYYY` aren't replaced.
+     */
+    async openDailyNote(workspace: string, date?: string, template?: string, pattern?: string) {
+        return await invoke<DailyNote>('open_daily_note', {workspace, date, template, pattern});
+    },
+
+    /** Oldest first; both ends are included and optional. */
+    async listDailyNotes(workspace: string, from?: string, to?: string, pattern?: string) {
+        return await invoke<DailyNote[]>('list_daily_notes', {workspace, from, to, pattern});
+    },
+
+    /** Fills in variables like `{{title}}`, `{{clipboard}}` or `{{date+7d:YYYY-MM-DD}}`. */
+    async renderTemplate(text: string, context: TemplateContext = {}) {
+        return await invoke<RenderedTemplate>('render_template', {text, context});
+    },
+
+    /** Prose only: headings, code and the frontmatter don't count. */
+    async readability(text: string) {
+        return await invoke<Readability>('readability', {text});
+    },
+
+    /** Of `text`, or of every document of `workspace` without one. */
+    async wordFrequency(text: string | null, workspace?: string, options: FrequencyOptions = {}) {
+        return await invoke<Frequencies>('word_frequency', {text, workspace, options});
+    },
+
+    /** `$1` or `${name}` in `replacement` are groups; rejects invalid patterns and gives up after the timeout. */
+    async regexTransform(text: string, pattern: string, replacement: string, options: RegexOptions = {}) {
+        return await invoke<RegexTransform>('regex_transform', {text, pattern, replacement, options});
+    },
+
+    /** `descending` is for sorting, `caseInsensitive` for sorting and `unique`. Line endings are kept. */
+    async transformLines(text: string, operation: LineOperation, options: {descending?: boolean, caseInsensitive?: boolean} = {}) {
+        return await invoke<string>('transform_lines', {text, operation, options});
+    },
+
+    /** What to insert, given every flavor of the paste event. */
+    async classifyPaste(clipboard: PasteClipboard) {
+        return await invoke<PasteSuggestion>('classify_paste', {clipboard});
+    },
+
+    /** Running jobs, the oldest first. */
+    async listJobs() {
+        return await invoke<JobInfo[]>('list_jobs');
+    },
+
+    /** The job stops at its next checkpoint and finishes with a `cancelled` error. */
+    async cancelJob(id: number) {
+        await invoke('cancel_job', {id});
+    },
+
+    /** Calls `handler` with the events of every job from now on. */
+    async watchJobs(handler: (event: JobEvent) => void) {
+        const channel = new Channel<JobEvent>;
+        channel.onmessage = handler;
+        await invoke('watch_jobs', {channel});
+    },
+
+    /** CPU-bound tasks like image compression and exports run at most this many at once; every core but one if 0. */
+    async configureWorkers(maxParallelism: number) {
+        await invoke('configure_workers', {maxParallelism});
+    },
+
+    /** Records how long operations take in a local file, never sent anywhere. */
+    async configureMetrics(enabled: boolean) {
+        await invoke('configure_metrics', {enabled});
+    },
+
+    async getMetrics() {
+        return await invoke<Metrics>('get_metrics');
+    },
+
+    async clearMetrics() {
+        await invoke('clear_metrics');
+    },
+
+    /** Zips the newest logs with system information for a bug report; returns how many logs it has. */
+    async exportLogs(targetZip: string) {
+        return await invoke<number>('export_logs', {targetZip});
+    },
+
+    /** The newest crash report, unless it was dismissed. */
+    async lastCrash() {
+        return await invoke<CrashInfo | null>('last_crash');
+    },
+
+    async dismissCrash() {
+        await invoke('dismiss_crash');
+    },
+
+    /** Calls `handler` with the backend log records matching `filter` from now on; returns an ID for `stopTailLogs`. */
+    async tailLogs(filter: LogFilter, handler: (record: LogRecord) => void) {
+        const channel = new Channel<LogRecord>;
+        channel.onmessage = handler;
+        return await invoke<number>('tail_logs', {filter, channel});
+    },
+
+    async stopTailLogs(id: number) {
+        await invoke('stop_tail_logs', {id});
+    },
+
+    /** The settings saved in the app's config directory, shared by every window and the backend. */
+    async getSettings<T>() {
+        return await invoke<T>('get_settings');
+    },
+
+    /** Saves `value` for `key` if it's valid; returns all the settings. */
+    async setSetting<T>(key: string, value: unknown) {
+        return await invoke<T>('set_setting', {key, value});
+    },
+
+    async resetSetting<T>(key: string) {
+        return await invoke<T>('reset_setting', {key});
+    },
+
+    /** Keeps `value` in the platform keychain as `name`; removes it if `value` is empty. */
+    async storeSecret(name: string, value: string) {
+        await invoke('store_secret', {name, value});
+    },
+
+    async getSecret(name: string) {
+        return await invoke<string | null>('get_secret', {name});
+    },
+
+    /** Where the app's database is and how much it holds, for diagnostics. */
+    async databaseInfo() {
+        return await invoke<DatabaseInfo>('database_info');
+    },
+
+    /** Sets when upkeep tasks run. */
+    async configureScheduler(options: ScheduleOptions) {
+        await invoke('configure_scheduler', {options});
+    },
+
+    /** Runs an upkeep task now, even if the user isn't idle. */
+    async runNow(task: MaintenanceTask) {
+        await invoke('run_now', {task});
+    },
+
+    /** Tells the backend the user is active, so upkeep tasks and, if set to, background work wait. */
+    async reportActivity() {
+        await invoke('report_activity');
+    },
+
+    /** The backend's memory, caches, watchers and jobs, for diagnostics. */
+    async resourceUsage() {
+        return await invoke<ResourceUsage>('resource_usage');
+    },
+
+    /** Runs each profile over the sample images as a job, to compare size, quality and time. */
+    async benchmarkCompression(samplePaths: string[], profiles: CompressionProfile[]) {
+        return await invoke<BenchmarkReport>('benchmark_compression', {samplePaths, profiles});
+    },
+
+    /** Checks storage, external tools and the configured servers, for Help → Diagnostics. */
+    async runDiagnostics(workspace?: string) {
+        return await invoke<DiagnosticsReport>('run_diagnostics', {workspace});
+    },
+
+    /** The experimental subsystems and whether each is turned on. */
+    async listFlags() {
+        return await invoke<FlagState[]>('list_flags');
+    },
+
+    /** Turns a flag on or off for this user, or back to its default if `enabled` is null. */
+    async setFlag(flag: Flag, enabled: boolean | null) {
+        return await invoke<FlagState[]>('set_flag', {flag, enabled});
+    },
+
+    /** The plugins in the settings, with why each can't run if it can't. */
+    async listPlugins() {
+        return await invoke<(Plugin & {error: string | null})[]>('list_plugins');
+    },
+
+    /** Runs the plugin `name` on `input` and returns its output. */
+    async runPlugin(name: string, input: string) {
+        return await invoke<string>('run_plugin', {name, input});
+    },
+
+    /** Runs the script `name` from the settings as a job. */
+    async runScript(name: string) {
+        return await invoke<ScriptRun>('run_script', {name});
+    },
+
+    /** Calls `handler` for the `emmm://` links that arrived so far and every later one. */
+    async watchDeepLinks(handler: (link: DeepLink) => void) {
+        const channel = new Channel<DeepLink>;
+        channel.onmessage = handler;
+        await invoke('watch_deep_links', {channel});
+    },
+
+    /** Creates the note `title` in `workspace`, for a `new-note` link the
+     *  user agreed to, and returns its path. */
+    async createNote(workspace: string, title: string) {
+        return await invoke<string>('create_note', {workspace, title});
+    },
+
+    /** A newer release on the channel in the settings, or null. */
+    async checkForUpdate() {
+        return await invoke<UpdateInfo | null>('check_for_update');
+    },
+
+    /** Downloads and verifies the update the last check found. */
+    async downloadUpdate(onProgress: (done: number, total: number) => void) {
+        const channel = createChannel({
+            progress: (data) => onProgress(data.done, data.total),
+            done: () => {}
+        });
+        await invoke('download_update', {channel});
+    },
+
+    /** Takes effect on restart; on Windows the app exits right away. */
+    async installUpdate() {
+        await invoke('install_update');
+    },
+
+    async restartApp() {
+        await invoke('restart_app');
+    },
+
+    /** The backend's messages in `locale`, or the one in the settings, falling back to English. */
+    async getMessages(locale?: string) {
+        return await invoke<Messages>('get_messages', {locale});
+    },
+
+    /** Opens `path` in a new window, or focuses the window that has it open; the page gets it as `?document=`. */
+    async openWindow(path: string) {
+        return await invoke<string>('open_window', {path});
+    },
+
+    /** Lets another window open `path`, once this one closed it. */
+    async releaseDocument(path: string) {
+        await invoke('release_document', {path});
+    },
+
+    async listWindows() {
+        return await invoke<WindowInfo[]>('list_windows');
+    },
+
+    /** Registers the quick-capture shortcut, replacing the previous one. */
+    async configureCapture(options: CaptureOptions) {
+        await invoke('configure_capture', {options});
+    },
+
+    /** Appends to the inbox note and hides the capture window; `image` is base64 or a data URL. */
+    async capture(text: string, image?: string) {
+        return await invoke<Captured>('capture', {text, image});
+    },
+
+    /** Pausing cancels running syncs and refuses new ones until resumed; the tray menu toggles it too. */
+    async setSyncPaused(paused: boolean) {
+        await invoke('set_sync_paused', {paused});
+    },
+
+    /** Logs everything copied from now on, in any app, until stopped. */
+    async startClipboardCapture() {
+        await invoke('start_clipboard_capture');
+    },
+
+    async stopClipboardCapture() {
+        await invoke('stop_clipboard_capture');
+    },
+
+    async clipboardCaptureRunning() {
+        return await invoke<boolean>('clipboard_capture_running');
+    },
+
+    /** Newest first; `since` is in seconds since the epoch. */
+    async clipboardCaptures(options: {kind?: ClipKind, since?: number, limit?: number} = {}) {
+        return await invoke<Clip[]>('clipboard_captures', options);
+    },
+
+    async configurePower(options: PowerOptions) {
+        await invoke('configure_power', {options});
+    },
+
+    async powerStatus() {
+        return await invoke<PowerStatus>('power_status');
+    },
+
+    /** `flush` runs before this window closes or the app quits, which wait a few seconds for it; save pending edits there. */
+    async watchShutdown(flush: () => Promise<void>) {
+        const channel = new Channel<ShutdownEvent>;
+        channel.onmessage = async () => {
+            try {
+                await flush();
+            } catch (e) {
+                console.error('error flushing before shutdown:', e);
+            } finally {
+                await invoke('flushed');
+            }
+        };
+        await invoke('watch_shutdown', {channel});
+    },
+
+    /** A poster frame of a video, through ffmpeg; `timestamp` is in seconds, 1 by default. */
+    async videoThumbnail(path: string, timestamp?: number, maxEdge?: number) {
+        return await invoke<VideoThumbnail>('video_thumbnail', {path, timestamp, maxEdge});
+    },
+
+    /** Re-encodes a video to `out` as H.264; progress is in milliseconds of the video, total 0 if unknown. */
+    async compressVideo(
+        path: string, out: string, target: VideoTarget,
+        onProgress?: (done: number, total: number) => void, priority?: JobPriority
+    ) {
+        const channel = createChannel({
+            progress: (data) => onProgress?.(data.done, data.total),
+            done: () => {}
+        });
+        return await invoke<CompressedVideo>('compress_video', {path, out, target, priority, channel});
+    },
+
+    /** Records the microphone until stopVoiceMemo, saving into the asset folder `dir`; `onLevel` drives a meter. */
+    async startVoiceMemo(dir: string, format: MemoFormat, onLevel: (rms: number, peak: number, elapsedMs: number) => void) {
+        const channel = new Channel<MemoEvent>;
+        channel.onmessage = (event) => onLevel(event.data.rms, event.data.peak, event.data.elapsedMs);
+        await invoke('start_voice_memo', {options: {dir, format}, channel});
+    },
+
+    async stopVoiceMemo() {
+        return await invoke<VoiceMemo>('stop_voice_memo');
+    },
+
+    /** Stops recording without saving. */
+    async cancelVoiceMemo() {
+        await invoke('cancel_voice_memo');
+    },
+
+    /** A waveform image of an audio file, scaled to its loudest moment. */
+    async audioWaveform(path: string, options: WaveformOptions = {}) {
+        const buf = await invoke<ArrayBuffer>('audio_waveform', {path, options});
+        return new Blob([buf], {type: options.format == 'svg' ? 'image/svg+xml' : 'image/png'});
+    },
+
+    /** Converts an animated GIF to a video next to it, and embeds that in `document` instead, saved from
+     *  the version `expected` as `saveDocument` does. With `removeOriginal`, the GIF is deleted once no
+     *  document in `workspace` embeds it. */
+    async convertGif(
+        path: string, target: GifTarget, document?: string, expected?: DocumentVersion, workspace?: string,
+        removeOriginal?: boolean, onProgress?: (done: number, total: number) => void
+    ) {
+        const channel = createChannel({
+            progress: (data) => onProgress?.(data.done, data.total),
+            done: () => {}
+        });
+        return await invoke<ConvertedGif>('convert_gif',
+            {path, target, document, expected, workspace, removeOriginal, channel});
+    },
+
+    /** Recognizes the text in an image with tesseract; `lang` like `eng+deu`. */
+    async ocrImage(path: string, lang?: string, boxes?: boolean) {
+        return await invoke<OcrResult>('ocr_image', {path, lang, boxes});
+    },
+
+    /** A QR code of `text`, `size` pixels wide, to embed in documents and exports. */
+    async generateQr(text: string, size?: number, format: 'png' | 'svg' = 'png') {
+        const buf = await invoke<ArrayBuffer>('generate_qr', {text, size, format});
+        return new Blob([buf], {type: format == 'svg' ? 'image/svg+xml' : 'image/png'});
+    },
+
+    /** What the QR codes and barcodes in an image say, like the link in a pasted screenshot. */
+    async scanCodes(path: string) {
+        return await invoke<ScannedCode[]>('scan_codes', {path});
+    },
+
+    /** Suggests alt text for an image with the local captioning model in the settings. */
+    async suggestAltText(path: string) {
+        return await invoke<string>('suggest_alt_text', {path});
+    },
+
+    /** Crops an image to `aspectRatio` (width / height) around what's interesting in it, saving to `out`. */
+    async smartCrop(path: string, aspectRatio: number, out: string) {
+        return await invoke<SmartCrop>('smart_crop', {path, aspectRatio, out});
+    },
+
+    /** Saves an image to `out` (.png or .webp) with its background made transparent by the model in the settings. */
+    async removeBackground(path: string, out: string) {
+        return await invoke<RemovedBackground>('remove_background', {path, out});
     }
-}
\ No newline at end of file
+}
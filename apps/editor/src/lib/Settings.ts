@@ -1,17 +1,72 @@
-import { path } from "@tauri-apps/api";
-import { BaseDirectory } from "@tauri-apps/plugin-fs";
-import * as fs from "@tauri-apps/plugin-fs"
 import { assert } from "./Debug";
+import { RustAPI, type AiProvider, type AutoCommitOptions, type CaptureOptions, type FormatterConfig, type Plugin, type PowerOptions, type ScheduleOptions, type Script, type UpdateChannel, type Webhook } from "./RustAPI";
 
-const configPath = 'config.json';
-
+// kept by the backend, which checks and saves them; these are the
+// defaults until it answers
 let settingsInitialized = false;
 let configData = {
     weixinAppId: '',
-    weixinAppSecret: '',
     weixinSmallImageCache: [] as [string, string][],
     weixinAssetCache: [] as [string, string][],
 
+    // applies to backend requests (image downloads, exports, uploads)
+    networkProxy: '',
+    networkNoProxy: '',
+    networkCaCertificates: [] as string[],
+
+    webhooks: [] as Webhook[],
+
+    // WebAssembly modules to run on text, pastes and exports
+    plugins: [] as Plugin[],
+    // Rhai scripts, run by hand or on saves
+    scripts: [] as Script[],
+
+    // LanguageTool server for grammar checking; the public one if empty
+    languageToolServer: '',
+    languageToolLanguage: 'auto',
+
+    aiProvider: null as AiProvider | null,
+
+    // formatters for code blocks by language, lowercase, instead of the
+    // built-in ones
+    formatters: {} as Record<string, FormatterConfig>,
+
+    // whisper.cpp model for dictation
+    dictationModelPath: '',
+
+    // captioning model folder for alt text suggestions, U²-Net model for
+    // background removal, and the ONNX Runtime library they run with;
+    // the library next to the app if empty
+    captionModelDir: '',
+    backgroundModelPath: '',
+    onnxRuntimePath: '',
+
+    // commit each save when the workspace is a git repository
+    autoCommit: {enabled: false, coalesceSeconds: 300} as AutoCommitOptions,
+
+    // skip files excluded by .gitignore when working on a whole folder
+    respectGitignore: true,
+
+    // CPU-bound tasks running at once, like compressing dropped images;
+    // every core but one if 0
+    maxParallelism: 0,
+    // how long operations take, kept locally for bug reports
+    collectMetrics: false,
+    // upkeep like reindexing and backups, run while idle
+    schedule: {intervals: {}, onBattery: false} as ScheduleOptions,
+    // when background work waits: on battery, or while the user is at it
+    power: {deferOnBattery: true, deferWhileActive: false, idleSeconds: 60} as PowerOptions,
+    // a global shortcut for a window that appends to an inbox note
+    capture: {shortcut: 'CmdOrCtrl+Shift+Space', inbox: '', imageMaxSize: 1024 * 1024} as CaptureOptions,
+    // experimental features the user turned on or off; see RustAPI.listFlags
+    flags: {} as Record<string, boolean>,
+    // of messages from the backend, like 'zh-CN'; the system's if empty
+    locale: '',
+    // where updates come from; the project's release feed if empty, with
+    // {{channel}} for the channel
+    updateChannel: 'stable' as UpdateChannel,
+    updateFeed: '',
+
     tempSource: '',
     tempLibrary: '',
     tempStylesheet: '',
@@ -27,32 +82,110 @@ let onInitCallbacks: (() => void)[] = [];
 type ConfigType = typeof configData;
 type ConfigKey = keyof ConfigType;
 
-async function saveSettings() {
-    const configDir = await path.appConfigDir();
-    if (!await fs.exists(configDir))
-        await fs.mkdir(configDir, {recursive: true});
+async function applyNetworkSettings() {
+    try {
+        await RustAPI.configureNetwork({
+            proxy: configData.networkProxy || null,
+            noProxy: configData.networkNoProxy || null,
+            caCertificates: configData.networkCaCertificates ?? []
+        });
+    } catch (e) {
+        console.error('error applying network settings:', e);
+    }
+}
+
+async function applyWebhookSettings() {
+    try {
+        await RustAPI.configureWebhooks(configData.webhooks ?? []);
+    } catch (e) {
+        console.error('error applying webhook settings:', e);
+    }
+}
+
+async function applyAutoCommitSettings() {
+    try {
+        await RustAPI.configureAutoCommit(configData.autoCommit ?? {enabled: false, coalesceSeconds: 0});
+    } catch (e) {
+        console.error('error applying auto-commit settings:', e);
+    }
+}
+
+async function applyWorkspaceSettings() {
+    try {
+        await RustAPI.configureWorkspace(configData.respectGitignore ?? true);
+    } catch (e) {
+        console.error('error applying workspace settings:', e);
+    }
+}
+
+async function applyWorkerSettings() {
+    try {
+        await RustAPI.configureWorkers(configData.maxParallelism ?? 0);
+    } catch (e) {
+        console.error('error applying worker settings:', e);
+    }
+}
+
+async function applyMetricsSettings() {
+    try {
+        await RustAPI.configureMetrics(configData.collectMetrics ?? false);
+    } catch (e) {
+        console.error('error applying metrics settings:', e);
+    }
+}
 
+async function applyScheduleSettings() {
     try {
-        await fs.writeTextFile(configPath, 
-            JSON.stringify(configData), {baseDir: BaseDirectory.AppConfig});
-        console.log('saved config');
+        await RustAPI.configureScheduler(configData.schedule ?? {intervals: {}, onBattery: false});
     } catch (e) {
-        // fail silently
-        console.error('error saving config:', e);
+        console.error('error applying schedule settings:', e);
     }
 }
 
+async function applyPowerSettings() {
+    try {
+        await RustAPI.configurePower(configData.power);
+    } catch (e) {
+        console.error('error applying power settings:', e);
+    }
+}
+
+async function applyCaptureSettings() {
+    try {
+        await RustAPI.configureCapture(configData.capture);
+    } catch (e) {
+        console.error('error applying capture settings:', e);
+    }
+}
+
+let lastActivityReport = 0;
+
+// postpones scheduled upkeep, and background work if set to, while the user is at it
+function reportActivity() {
+    const now = Date.now();
+    if (now - lastActivityReport < 10_000) return;
+    lastActivityReport = now;
+    RustAPI.reportActivity().catch((e) => console.error('error reporting activity:', e));
+}
+
 export const Settings = {
     async init() {
-        console.log('config path:', await path.appConfigDir(), configPath);
         try {
-            if (!await fs.exists(configPath, {baseDir: BaseDirectory.AppConfig}))
-                console.log('no config file found')
-            else configData = JSON.parse(await fs.readTextFile(
-                configPath, {baseDir: BaseDirectory.AppConfig}));
+            configData = {...configData, ...await RustAPI.getSettings<ConfigType>()};
         } catch (e) {
             console.error('error reading config file:', e);
         } finally {
+            await applyNetworkSettings();
+            await applyWebhookSettings();
+            await applyAutoCommitSettings();
+            await applyWorkspaceSettings();
+            await applyWorkerSettings();
+            await applyMetricsSettings();
+            await applyScheduleSettings();
+            await applyPowerSettings();
+            await applyCaptureSettings();
+            for (const event of ['keydown', 'pointerdown', 'wheel'])
+                window.addEventListener(event, reportActivity, {passive: true});
             settingsInitialized = true;
             for (const callback of onInitCallbacks)
                 callback();
@@ -64,10 +197,28 @@ export const Settings = {
         else callback();
     },
 
+    /** Throws if the backend finds `value` invalid, leaving the setting as it was. */
     async set<prop extends ConfigKey>(key: prop, value: ConfigType[prop]) {
         assert(settingsInitialized);
-        configData[key] = value;
-        await saveSettings();
+        configData = {...configData, ...await RustAPI.setSetting<ConfigType>(key, value)};
+        if (key.startsWith('network'))
+            await applyNetworkSettings();
+        if (key == 'webhooks')
+            await applyWebhookSettings();
+        if (key == 'autoCommit')
+            await applyAutoCommitSettings();
+        if (key == 'respectGitignore')
+            await applyWorkspaceSettings();
+        if (key == 'maxParallelism')
+            await applyWorkerSettings();
+        if (key == 'collectMetrics')
+            await applyMetricsSettings();
+        if (key == 'schedule')
+            await applyScheduleSettings();
+        if (key == 'power')
+            await applyPowerSettings();
+        if (key == 'capture')
+            await applyCaptureSettings();
     },
     get<prop extends ConfigKey>(key: prop): ConfigType[prop] {
         assert(settingsInitialized);
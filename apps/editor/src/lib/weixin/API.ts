@@ -1,7 +1,6 @@
 import { get, readonly, writable, type Readable } from "svelte/store";
 import { Settings } from "../Settings";
-import { fetch } from '@tauri-apps/plugin-http';
-import { RequestFailedError } from "../Util";
+import { RustAPI } from "../RustAPI";
 import { assert } from "../Debug";
 import { BaseDirectory, writeFile } from "@tauri-apps/plugin-fs";
 import { appLocalDataDir, join } from "@tauri-apps/api/path";
@@ -131,20 +130,44 @@ export class WeixinAPIError extends Error {
 
 let appid = writable<string>('');
 let secret = writable<string>('');
+// the keychain entry the backend moved it to from the settings
+const secretName = 'weixin-app-secret';
 let stableToken = writable<string>('');
 let expireTime = new Date();
 
+/** Through the backend, so the configured proxy is used. */
+async function post(endpoint: string, body: object, token?: string) {
+    return await RustAPI.weixinRequest(endpoint, token, JSON.stringify(body));
+}
+
+function parse(data: ArrayBuffer) {
+    const json = JSON.parse(new TextDecoder().decode(data));
+    if (json.errcode) throw new WeixinAPIError(json);
+    return json;
+}
+
 let smallImageCache = new Map<string, string>();
 let assetCache = new Map<string, string>();
 
-Settings.onInitialized(() => {
+Settings.onInitialized(async () => {
     appid.set(Settings.get('weixinAppId'));
-    secret.set(Settings.get('weixinAppSecret'));
+    let stored = '';
+    try {
+        stored = await RustAPI.getSecret(secretName) ?? '';
+    } catch (e) {
+        console.error('error reading the weixin secret:', e);
+    }
+    secret.set(stored);
     smallImageCache = new Map(Settings.get('weixinSmallImageCache'));
     assetCache = new Map(Settings.get('weixinAssetCache'));
 
     appid.subscribe((x) => Settings.set('weixinAppId', x));
-    secret.subscribe((x) => Settings.set('weixinAppSecret', x));
+    secret.subscribe((x) => {
+        if (x == stored) return;
+        stored = x;
+        RustAPI.storeSecret(secretName, x)
+            .catch((e) => console.error('error storing the weixin secret:', e));
+    });
 });
 
 export const Weixin = {
@@ -171,17 +194,11 @@ export const Weixin = {
             return get(stableToken);
         if (!get(appid) || !get(secret)) 
             throw new WeixinBadCredentialError();
-        const r = await fetch('https://api.weixin.qq.com/cgi-bin/stable_token', {
-            method: 'POST',
-            body: JSON.stringify({
-                "grant_type": "client_credential",
-                "appid": get(appid),
-                "secret": get(secret)
-            })
-        });
-        if (!r.ok) throw new RequestFailedError(r);
-        let json = await r.json();
-        if (json.errcode) throw new WeixinAPIError(json);
+        let json = parse(await post('stable_token', {
+            "grant_type": "client_credential",
+            "appid": get(appid),
+            "secret": get(secret)
+        }));
         const token = json.access_token as string;
         stableToken.set(token);
         // slightly reduce the lifetime to avoid invalid tokens
@@ -192,20 +209,11 @@ export const Weixin = {
     async getAssets(type: WeixinAssetType, from: number, count = 20) {
         if (!this.tokenOk && (!this.autoFetchToken || await this.fetchToken()))
             throw new WeixinInvalidTokenError();
-        const r = await fetch(
-            'https://api.weixin.qq.com/cgi-bin/material/batchget_material?'
-            + new URLSearchParams({access_token: get(stableToken)}),
-        {
-            method: 'POST',
-            body: JSON.stringify({
-                "type": type as string,
-                "offset": from,
-                "count": count
-            })
-        });
-        if (!r.ok) throw new RequestFailedError(r);
-        let json = await r.json();
-        if (json.errcode) throw new WeixinAPIError(json);
+        let json = parse(await post('material/batchget_material', {
+            "type": type as string,
+            "offset": from,
+            "count": count
+        }, get(stableToken)));
         const total = json.total_count as number;
         const assets: WeixinAsset[] = [...json.item].map((x) => ({
             type: type,
@@ -220,20 +228,11 @@ export const Weixin = {
     async getDrafts(from: number, count = 20) {
         if (!this.tokenOk && (!this.autoFetchToken || await this.fetchToken()))
             throw new WeixinInvalidTokenError();
-        const r = await fetch(
-            'https://api.weixin.qq.com/cgi-bin/draft/batchget?'
-            + new URLSearchParams({access_token: get(stableToken)}),
-        {
-            method: 'POST',
-            body: JSON.stringify({
-                "offset": from,
-                "count": count,
-                "no_content": 1
-            })
-        });
-        if (!r.ok) throw new RequestFailedError(r);
-        let json = await r.json();
-        if (json.errcode) throw new WeixinAPIError(json);
+        let json = parse(await post('draft/batchget', {
+            "offset": from,
+            "count": count,
+            "no_content": 1
+        }, get(stableToken)));
         const total = json.total_count as number;
         const drafts: WeixinDraft[] = [...json.item].map((x) => ({
             id: x.media_id as string,
@@ -247,20 +246,11 @@ export const Weixin = {
     async writeDraftArticle(id: string, index: number, article: WeixinDraftArticle) {
         if (!this.tokenOk && (!this.autoFetchToken || await this.fetchToken()))
             throw new WeixinInvalidTokenError();
-        const r = await fetch(
-            'https://api.weixin.qq.com/cgi-bin/draft/update?'
-            + new URLSearchParams({access_token: get(stableToken)}),
-        {
-            method: 'POST',
-            body: JSON.stringify({
-                "media_id": id,
-                "index": index,
-                "articles": makeArticle(article)
-            })
-        });
-        if (!r.ok) throw new RequestFailedError(r);
-        let json = await r.json();
-        if (json.errcode) throw new WeixinAPIError(json);
+        parse(await post('draft/update', {
+            "media_id": id,
+            "index": index,
+            "articles": makeArticle(article)
+        }, get(stableToken)));
         return true;
     },
 
@@ -273,19 +263,10 @@ export const Weixin = {
         console.log('downloadAsset', id, name, force);
         let path: string;
         try {
-            const r = await fetch(
-                'https://api.weixin.qq.com/cgi-bin/material/get_material?'
-                + new URLSearchParams({access_token: get(stableToken)}),
-            {
-                method: 'POST',
-                body: JSON.stringify({ "media_id": id })
-            });
-            console.log(r.status, r.statusText);
-            if (!r.ok)
-                throw new RequestFailedError(r);
+            const data = await post('material/get_material', { "media_id": id }, get(stableToken));
             const filename = `${id}-${[...name].filter((x) => /[a-zA-Z0-9.]/.test(x)).join('')}`;
             await writeFile(filename, 
-                new Uint8Array(await r.arrayBuffer()), { baseDir: BaseDirectory.AppLocalData });
+                new Uint8Array(data), { baseDir: BaseDirectory.AppLocalData });
             path = await join(await appLocalDataDir(), filename);
         } catch (_) {
             path = '';
@@ -310,18 +291,8 @@ export const Weixin = {
 
         if (!this.tokenOk && (!this.autoFetchToken || await this.fetchToken()))
             throw new WeixinInvalidTokenError();
-        const form = new FormData();
-        form.append('media', blob, name);
-        const r = await fetch(
-            'https://api.weixin.qq.com/cgi-bin/media/uploadimg?'
-            + new URLSearchParams({access_token: get(stableToken)}),
-        {
-            method: 'POST',
-            body: form
-        });
-        if (!r.ok) throw new RequestFailedError(r);
-        let json = await r.json();
-        if (json.errcode) throw new WeixinAPIError(json);
+        const data = new Uint8Array(await blob.arrayBuffer());
+        let json = parse(await RustAPI.weixinUpload('media/uploadimg', get(stableToken), name, data));
         const url = json.url as string;
         smallImageCache.set(name, url);
         Settings.set('weixinSmallImageCache', [...smallImageCache.entries()]);